@@ -11,11 +11,15 @@ fn main() {
         .compile(
             &[
                 "protos/auth.proto",
+                "protos/bundle.proto",
+                "protos/packet.proto",
+                "protos/searcher.proto",
                 "protos/shared.proto",
                 "protos/shredstream.proto",
                 "protos/trace_shred.proto",
+                "proxy_protos/shredstream_proxy.proto",
             ],
-            &["protos"],
+            &["protos", "proxy_protos"],
         )
         .unwrap();
 }
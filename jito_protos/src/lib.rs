@@ -6,10 +6,26 @@ pub mod auth {
     tonic::include_proto!("auth");
 }
 
+pub mod bundle {
+    tonic::include_proto!("bundle");
+}
+
+pub mod packet {
+    tonic::include_proto!("packet");
+}
+
+pub mod searcher {
+    tonic::include_proto!("searcher");
+}
+
 pub mod shredstream {
     tonic::include_proto!("shredstream");
 }
 
+pub mod shredstream_proxy {
+    tonic::include_proto!("shredstream_proxy");
+}
+
 pub mod trace_shred {
     tonic::include_proto!("trace_shred");
 }
@@ -0,0 +1,194 @@
+//! Forwards synthetic shreds through a `forward-only` proxy on localhost to several destinations,
+//! reporting allocations per forwarded packet and forward latency percentiles.
+//! Only uses the public API, so the same bench runs on two revisions to compare them:
+//! `cargo bench -p jito-shredstream-proxy --bench forward`
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use jito_shredstream_proxy::{CommonArgs, ShredstreamProxyBuilder};
+use solana_sdk::{clock::DEFAULT_MS_PER_SLOT, packet::PACKET_DATA_SIZE};
+
+const NUM_DESTS: usize = 8;
+/// Packets sent before measuring, so one-time allocations such as metrics entries aren't counted
+const WARMUP_PACKETS: u64 = 10_000;
+const MEASURED_PACKETS: u64 = 200_000;
+/// Low enough that neither the proxy nor the destinations drop packets on loopback
+const PACKETS_PER_SEC: u64 = 50_000;
+const SHREDS_PER_SLOT: u64 = PACKETS_PER_SEC * DEFAULT_MS_PER_SLOT / 1_000;
+const FIRST_SLOT: u64 = 300_000_000;
+const PACKET_SIZE: usize = 1_228;
+/// Destinations stop receiving once nothing arrives for this long
+const RECV_TIMEOUT: Duration = Duration::from_secs(2);
+
+// legacy data shred layout, as written by the proxy's own `bench` subcommand
+const OFFSET_OF_SHRED_VARIANT: usize = 64;
+const OFFSET_OF_SHRED_SLOT: usize = 65;
+const OFFSET_OF_SHRED_INDEX: usize = 73;
+const OFFSET_OF_DATA_SIZE: usize = 86;
+const SIZE_OF_DATA_SHRED_HEADERS: usize = 88;
+const LEGACY_DATA_VARIANT: u8 = 0xa5;
+/// Sequence number and send time, in nanoseconds after the bench started, follow the headers
+const OFFSET_OF_SEQ: usize = SIZE_OF_DATA_SHRED_HEADERS;
+const OFFSET_OF_SENT_NANOS: usize = OFFSET_OF_SEQ + 8;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts allocations by every thread, the proxy's included
+struct CountingAllocator;
+
+// SAFETY: forwards every call to the system allocator, only counting them
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Sends the packets numbered `seqs` to `target` at `PACKETS_PER_SEC`
+fn send(socket: &UdpSocket, target: (Ipv4Addr, u16), start: Instant, seqs: Range<u64>) {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[OFFSET_OF_SHRED_VARIANT] = LEGACY_DATA_VARIANT;
+    packet[OFFSET_OF_DATA_SIZE..SIZE_OF_DATA_SHRED_HEADERS]
+        .copy_from_slice(&(PACKET_SIZE as u16).to_le_bytes());
+    let (first_seq, send_start) = (seqs.start, Instant::now());
+    for seq in seqs {
+        let due = Duration::from_secs_f64((seq - first_seq) as f64 / PACKETS_PER_SEC as f64);
+        if let Some(wait) = due.checked_sub(send_start.elapsed()) {
+            thread::sleep(wait);
+        }
+        let (slot, index) = (FIRST_SLOT + seq / SHREDS_PER_SLOT, seq % SHREDS_PER_SLOT);
+        packet[OFFSET_OF_SHRED_SLOT..OFFSET_OF_SHRED_INDEX].copy_from_slice(&slot.to_le_bytes());
+        packet[OFFSET_OF_SHRED_INDEX..OFFSET_OF_SHRED_INDEX + 4]
+            .copy_from_slice(&(index as u32).to_le_bytes());
+        packet[OFFSET_OF_SEQ..OFFSET_OF_SENT_NANOS].copy_from_slice(&seq.to_le_bytes());
+        packet[OFFSET_OF_SENT_NANOS..OFFSET_OF_SENT_NANOS + 8]
+            .copy_from_slice(&(start.elapsed().as_nanos() as u64).to_le_bytes());
+        let _ = socket.send_to(&packet, target);
+    }
+}
+
+/// Receives until `RECV_TIMEOUT` passes without a packet, returning the latency of each measured packet in microseconds
+fn start_receive_thread(socket: UdpSocket, start: Instant) -> JoinHandle<Vec<u64>> {
+    socket.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+    thread::spawn(move || {
+        // reserved up front, so receiving doesn't allocate while measuring
+        let mut latencies_us = Vec::with_capacity(MEASURED_PACKETS as usize);
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        while let Ok(len) = socket.recv(&mut buf) {
+            let received_nanos = start.elapsed().as_nanos() as u64;
+            if len < OFFSET_OF_SENT_NANOS + 8 {
+                continue;
+            }
+            let seq =
+                u64::from_le_bytes(buf[OFFSET_OF_SEQ..OFFSET_OF_SENT_NANOS].try_into().unwrap());
+            let sent_nanos = u64::from_le_bytes(
+                buf[OFFSET_OF_SENT_NANOS..OFFSET_OF_SENT_NANOS + 8]
+                    .try_into()
+                    .unwrap(),
+            );
+            if seq >= WARMUP_PACKETS {
+                latencies_us.push(received_nanos.saturating_sub(sent_nanos) / 1_000);
+            }
+        }
+        latencies_us
+    })
+}
+
+fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+    sorted[((sorted.len() - 1) as f64 * percentile / 100.0) as usize]
+}
+
+fn main() {
+    let dests = (0..NUM_DESTS)
+        .map(|_| UdpSocket::bind("127.0.0.1:0").unwrap())
+        .collect::<Vec<_>>();
+    // reserve a port to listen on
+    let src_bind_port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+        src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        src_bind_port,
+        dest_ip_ports: dests
+            .iter()
+            .map(|dest| {
+                let addr = dest.local_addr().unwrap();
+                (addr, addr.to_string())
+            })
+            .collect(),
+        dest_resolve_interval_secs: 0,
+        num_threads: Some(1),
+        ..Default::default()
+    })
+    .build()
+    .expect("valid proxy args");
+    proxy.start().expect("proxy starts");
+
+    let start = Instant::now();
+    let receive_hdls = dests
+        .into_iter()
+        .map(|dest| start_receive_thread(dest, start))
+        .collect::<Vec<_>>();
+    let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let target = (Ipv4Addr::LOCALHOST, src_bind_port);
+    send(&sender, target, start, 0..WARMUP_PACKETS);
+    thread::sleep(Duration::from_millis(500));
+
+    let allocations_start = ALLOCATIONS.load(Ordering::Relaxed);
+    send(
+        &sender,
+        target,
+        start,
+        WARMUP_PACKETS..WARMUP_PACKETS + MEASURED_PACKETS,
+    );
+    let per_dest_latencies_us = receive_hdls
+        .into_iter()
+        .map(|hdl| hdl.join().unwrap())
+        .collect::<Vec<_>>();
+    // also counts the proxy's metrics reports while destinations time out, a few allocations over the whole run
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_start;
+    proxy.shutdown();
+
+    let mut latencies_us = per_dest_latencies_us.concat();
+    latencies_us.sort_unstable();
+    println!(
+        "Forwarded {} of {} packets to {NUM_DESTS} destinations at {PACKETS_PER_SEC} packets/sec.",
+        latencies_us.len(),
+        MEASURED_PACKETS * NUM_DESTS as u64,
+    );
+    println!(
+        "Allocations: {:.2} per packet received.",
+        allocations as f64 / MEASURED_PACKETS as f64
+    );
+    if latencies_us.is_empty() {
+        println!("Forward latency: no packets were forwarded.");
+        return;
+    }
+    println!(
+        "Forward latency: p50 {}us, p99 {}us, max {}us.",
+        percentile(&latencies_us, 50.0),
+        percentile(&latencies_us, 99.0),
+        latencies_us[latencies_us.len() - 1],
+    );
+}
@@ -0,0 +1,68 @@
+//! Compares forwarder threads counting forwards in metrics shared by every thread with each thread counting its own,
+//! as the forwarder does with `ShredMetrics::register_thread`.
+//! `cargo bench -p jito-shredstream-proxy --bench thread_metrics`
+
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+use jito_shredstream_proxy::forwarder::{ShredMetrics, Transport};
+
+const NUM_THREADS: usize = 4;
+const FORWARDS_PER_THREAD: u64 = 5_000_000;
+/// Each thread forwards to every destination in turn, as when fanning out to several
+const NUM_DESTS: u16 = 8;
+
+fn dest(i: u64) -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::LOCALHOST, 8_001 + (i % NUM_DESTS as u64) as u16))
+}
+
+/// Runs `count` on `NUM_THREADS` threads at once, returning how long they took
+fn run(metrics: &ShredMetrics, count: impl Fn(&ShredMetrics) + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..NUM_THREADS {
+            scope.spawn(|| count(metrics));
+        }
+    });
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration, metrics: &ShredMetrics) {
+    metrics.reset();
+    let forwards = NUM_THREADS as u64 * FORWARDS_PER_THREAD;
+    assert_eq!(
+        metrics
+            .agg_success_forward_cumulative
+            .load(Ordering::Relaxed),
+        forwards
+    );
+    println!(
+        "{name:<12} {elapsed:>12.2?} ({:.1}ns per forward per thread)",
+        elapsed.as_nanos() as f64 / FORWARDS_PER_THREAD as f64
+    );
+}
+
+fn main() {
+    println!("{NUM_THREADS} threads x {FORWARDS_PER_THREAD} forwards to {NUM_DESTS} destinations");
+
+    let metrics = ShredMetrics::new();
+    let shared = run(&metrics, |metrics| {
+        for i in 0..FORWARDS_PER_THREAD {
+            metrics.record_forward(dest(i), Transport::Udp, 1, 0);
+        }
+    });
+    report("shared", shared, &metrics);
+
+    let metrics = ShredMetrics::new();
+    let per_thread = run(&metrics, |metrics| {
+        let thread_metrics = metrics.register_thread();
+        for i in 0..FORWARDS_PER_THREAD {
+            thread_metrics.add_udp_forward(dest(i), 1, 0);
+        }
+    });
+    report("per thread", per_thread, &metrics);
+}
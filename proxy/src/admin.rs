@@ -0,0 +1,737 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind, Read},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use log::{info, warn, LevelFilter};
+use serde::{Deserialize, Serialize};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    build_info,
+    forwarder::{DestinationSources, ShredMetrics, TraceShredSampler},
+    heartbeat::{set_heartbeats_paused, HeartbeatState},
+    logging,
+    probes::Probes,
+};
+
+/// Max accepted request body size in bytes
+const MAX_BODY_LEN: u64 = 4096;
+
+#[derive(Debug, Deserialize)]
+struct AddDestinationRequest {
+    addr: String,
+    /// Removes the destination after this many seconds, unless added again
+    #[serde(default)]
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DestinationResponse {
+    pub(crate) addr: SocketAddr,
+    /// Where the destination came from: `static`, `shard_group`, `discovered`, `admin`, `library`, and/or `validator`
+    pub(crate) sources: Vec<&'static str>,
+    /// False while quarantined by health checks
+    pub(crate) healthy: bool,
+    /// True while paused via the admin API
+    pub(crate) paused: bool,
+    /// Seconds until removed, for admin destinations added with a TTL
+    pub(crate) ttl_remaining_secs: Option<u64>,
+    pub(crate) success_forward: u64,
+    pub(crate) fail_forward: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct LogLevelRequest {
+    /// Level for all targets: `error`, `warn`, `info`, `debug`, or `trace`
+    pub(crate) level: String,
+    /// Levels for specific modules, eg. `{"jito_shredstream_proxy::forwarder": "debug"}`
+    #[serde(default)]
+    pub(crate) targets: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+struct LogLevelResponse {
+    /// Active filters in `RUST_LOG` syntax
+    filters: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct DebugTraceShredRequest {
+    enabled: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct TraceShredSampleRateRequest {
+    /// Fraction of shreds to trace, from 0 to 1
+    rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct HeartbeatResponse {
+    /// True while heartbeats are paused for maintenance, forwarding continues
+    paused: bool,
+    /// State per desired region, empty without a block engine
+    regions: BTreeMap<String, HeartbeatState>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+type JsonResponse = Response<io::Cursor<Vec<u8>>>;
+
+/// Serves an HTTP API for adding and removing destinations at runtime.
+/// `GET /destinations`, `POST /destinations` with `{"addr": "ip:port"}`, `DELETE /destinations/{ip:port}`.
+/// Add `"ttl_seconds": 3600` to remove the destination once it expires, posting it again restarts the TTL.
+/// `PUT /destinations/{ip:port}/pause` stops forwarding to a destination until `PUT /destinations/{ip:port}/resume`, spilling its shreds if it has a `spill` option.
+/// Logging is adjusted without a restart with `GET /log-level`, `PUT /log-level` with `{"level": "debug", "targets": {...}}`,
+/// Shred tracing is adjusted with `GET /trace-shred-sample-rate`, `PUT /trace-shred-sample-rate` with `{"rate": 0.001}`,
+/// and `PUT /debug-trace-shred` with `{"enabled": true}`, same as a rate of 1 or 0.
+/// Heartbeats are paused for block engine maintenance with `PUT /heartbeat/pause` and `PUT /heartbeat/resume`, and reported by `GET /heartbeat`.
+/// Kubernetes probes are served by `GET /readyz` and `GET /healthz`, see [Probes].
+pub fn start_admin_thread(
+    bind_addr: SocketAddr,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    trace_shred_sampler: Arc<TraceShredSampler>,
+    metrics: Arc<ShredMetrics>,
+    probes: Arc<Probes>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let server = Server::http(bind_addr).map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+    info!("Serving admin API on http://{bind_addr}");
+
+    Builder::new()
+        .name("ssPxyAdmin".to_string())
+        .spawn(move || {
+            while !exit.load(Ordering::Relaxed) {
+                // use timeout so we periodically check for exit
+                let mut request = match server.recv_timeout(Duration::from_millis(500)) {
+                    Ok(Some(request)) => request,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Failed to receive admin request. Error: {e}");
+                        continue;
+                    }
+                };
+
+                let response = handle_request(
+                    &mut request,
+                    &dest_sources,
+                    &unioned_dest_sockets,
+                    &trace_shred_sampler,
+                    &metrics,
+                    &probes,
+                );
+                if let Err(e) = request.respond(response) {
+                    warn!("Failed to respond to admin request. Error: {e}");
+                }
+            }
+            info!("Exiting admin thread.");
+        })
+}
+
+fn handle_request(
+    request: &mut Request,
+    dest_sources: &Mutex<DestinationSources>,
+    unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>,
+    trace_shred_sampler: &TraceShredSampler,
+    metrics: &ShredMetrics,
+    probes: &Probes,
+) -> JsonResponse {
+    let url = request.url().to_string();
+    match (request.method(), url.as_str()) {
+        (Method::Get, "/destinations") => json_response(
+            200,
+            &list_destinations(&dest_sources.lock().unwrap(), metrics),
+        ),
+        (Method::Post, "/destinations") => {
+            let body = match read_body(request) {
+                Ok(body) => body,
+                Err(e) => return error_response(400, e),
+            };
+            let (addr, ttl) = match parse_add_destination(&body) {
+                Ok(req) => req,
+                Err(e) => return error_response(400, e),
+            };
+
+            let mut dest_sources = dest_sources.lock().unwrap();
+            add_destination(
+                &mut dest_sources,
+                unioned_dest_sockets,
+                addr,
+                ttl,
+                "admin API",
+            );
+            json_response(201, &list_destinations(&dest_sources, metrics))
+        }
+        (Method::Delete, path) if path.starts_with("/destinations/") => {
+            let addr = match parse_destination(&path["/destinations/".len()..]) {
+                Ok(addr) => addr,
+                Err(e) => return error_response(400, e),
+            };
+
+            let mut dest_sources = dest_sources.lock().unwrap();
+            if let Err(e) =
+                remove_destination(&mut dest_sources, unioned_dest_sockets, addr, "admin API")
+            {
+                return error_response(404, e);
+            }
+            json_response(200, &list_destinations(&dest_sources, metrics))
+        }
+        (Method::Put, path) if path.starts_with("/destinations/") => {
+            let Some((addr, paused)) =
+                path["/destinations/".len()..]
+                    .rsplit_once('/')
+                    .and_then(|(addr, action)| match action {
+                        "pause" => Some((addr, true)),
+                        "resume" => Some((addr, false)),
+                        _ => None,
+                    })
+            else {
+                return error_response(404, "Not Found".to_string());
+            };
+            let addr = match parse_destination(addr) {
+                Ok(addr) => addr,
+                Err(e) => return error_response(400, e),
+            };
+
+            let mut dest_sources = dest_sources.lock().unwrap();
+            if let Err(e) = set_destination_paused(
+                &mut dest_sources,
+                unioned_dest_sockets,
+                addr,
+                paused,
+                "admin API",
+            ) {
+                return error_response(404, e);
+            }
+            json_response(200, &list_destinations(&dest_sources, metrics))
+        }
+        (Method::Get, "/log-level") => match logging::log_filters() {
+            Some(filters) => json_response(200, &LogLevelResponse { filters }),
+            None => error_response(404, "Logger wasn't initialized by the proxy".to_string()),
+        },
+        (Method::Put, "/log-level") => {
+            let filters = match read_body(request).and_then(|body| {
+                serde_json::from_str::<LogLevelRequest>(&body)
+                    .map_err(|e| e.to_string())
+                    .and_then(|req| log_level_filters(&req))
+            }) {
+                Ok(filters) => filters,
+                Err(e) => return error_response(400, e),
+            };
+            if let Err(e) = logging::set_log_filters(filters.clone()) {
+                return error_response(404, e);
+            }
+            info!("Set log filters to {filters:?} via admin API.");
+            json_response(200, &LogLevelResponse { filters })
+        }
+        (Method::Put, "/debug-trace-shred") => {
+            let req = match read_body(request).and_then(|body| {
+                serde_json::from_str::<DebugTraceShredRequest>(&body).map_err(|e| e.to_string())
+            }) {
+                Ok(req) => req,
+                Err(e) => return error_response(400, e),
+            };
+            info!(
+                "Setting debug_trace_shred to {} via admin API.",
+                req.enabled
+            );
+            trace_shred_sampler.set_sample_rate(if req.enabled { 1.0 } else { 0.0 });
+            json_response(200, &req)
+        }
+        (Method::Get, "/trace-shred-sample-rate") => json_response(
+            200,
+            &TraceShredSampleRateRequest {
+                rate: trace_shred_sampler.sample_rate(),
+            },
+        ),
+        (Method::Put, "/trace-shred-sample-rate") => {
+            let req = match read_body(request).and_then(|body| {
+                serde_json::from_str::<TraceShredSampleRateRequest>(&body)
+                    .map_err(|e| e.to_string())
+                    .and_then(|req| {
+                        if (0.0..=1.0).contains(&req.rate) {
+                            Ok(req)
+                        } else {
+                            Err(format!("Invalid rate {}, expected 0 to 1", req.rate))
+                        }
+                    })
+            }) {
+                Ok(req) => req,
+                Err(e) => return error_response(400, e),
+            };
+            info!(
+                "Setting trace_shred_sample_rate to {} via admin API.",
+                req.rate
+            );
+            trace_shred_sampler.set_sample_rate(req.rate);
+            json_response(200, &req)
+        }
+        (Method::Get, "/heartbeat") => json_response(200, &heartbeat_status(metrics)),
+        (Method::Put, "/heartbeat/pause") => {
+            set_heartbeats_paused(metrics, true, "admin API");
+            json_response(200, &heartbeat_status(metrics))
+        }
+        (Method::Put, "/heartbeat/resume") => {
+            set_heartbeats_paused(metrics, false, "admin API");
+            json_response(200, &heartbeat_status(metrics))
+        }
+        (Method::Get, "/info") => json_response(200, &build_info::build_info()),
+        (Method::Get, url) => probes
+            .respond(url)
+            .unwrap_or_else(|| error_response(404, "Not Found".to_string())),
+        _ => error_response(404, "Not Found".to_string()),
+    }
+}
+
+fn heartbeat_status(metrics: &ShredMetrics) -> HeartbeatResponse {
+    HeartbeatResponse {
+        paused: metrics.heartbeat_paused.load(Ordering::Relaxed),
+        regions: metrics
+            .heartbeat_regions
+            .iter()
+            .map(|kv| (kv.key().clone(), kv.state))
+            .collect(),
+    }
+}
+
+fn read_body(request: &mut Request) -> Result<String, String> {
+    let mut body = String::new();
+    request
+        .as_reader()
+        .take(MAX_BODY_LEN)
+        .read_to_string(&mut body)
+        .map_err(|e| format!("Failed to read request body: {e}"))?;
+    Ok(body)
+}
+
+/// Forwards to `addr` alongside destinations from other sources, until `ttl` expires if set. `via` names the API for logging.
+/// Adding a destination again replaces its TTL
+pub(crate) fn add_destination(
+    dest_sources: &mut DestinationSources,
+    unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>,
+    addr: SocketAddr,
+    ttl: Option<Duration>,
+    via: &str,
+) {
+    let expires_in = ttl
+        .map(|ttl| format!(" for {}s", ttl.as_secs()))
+        .unwrap_or_default();
+    match ttl {
+        Some(ttl) => dest_sources
+            .admin_dest_expiries
+            .insert(addr, Instant::now() + ttl),
+        None => dest_sources.admin_dest_expiries.remove(&addr),
+    };
+    if !dest_sources.admin_dest_sockets.contains(&addr) {
+        info!("Adding destination {addr}{expires_in} via {via}.");
+        dest_sources.admin_dest_sockets.push(addr);
+        dest_sources.store_union(unioned_dest_sockets);
+    } else if ttl.is_some() {
+        info!("Renewing destination {addr}{expires_in} via {via}.");
+    }
+}
+
+/// Removes destinations added via the admin API whose TTL expired by `now`
+pub(crate) fn expire_destinations(
+    dest_sources: &mut DestinationSources,
+    unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>,
+    now: Instant,
+    metrics: &ShredMetrics,
+) {
+    let expired = dest_sources
+        .admin_dest_expiries
+        .iter()
+        .filter(|(_, expiry)| **expiry <= now)
+        .map(|(addr, _)| *addr)
+        .collect::<Vec<_>>();
+    if expired.is_empty() {
+        return;
+    }
+    for addr in &expired {
+        warn!(event = "destination_expired", addr:% = addr; "Destination {addr} added via the admin API expired, removing it.");
+        dest_sources.admin_dest_expiries.remove(addr);
+    }
+    dest_sources
+        .admin_dest_sockets
+        .retain(|addr| !expired.contains(addr));
+    metrics
+        .admin_dest_expired_cumulative
+        .fetch_add(expired.len() as u64, Ordering::Relaxed);
+    dest_sources.store_union(unioned_dest_sockets);
+}
+
+/// Stops forwarding to `addr`, if it was added by [add_destination]
+pub(crate) fn remove_destination(
+    dest_sources: &mut DestinationSources,
+    unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>,
+    addr: SocketAddr,
+    via: &str,
+) -> Result<(), String> {
+    let Some(index) = dest_sources
+        .admin_dest_sockets
+        .iter()
+        .position(|x| *x == addr)
+    else {
+        return Err(format!(
+            "Destination {addr} was not added via the admin API"
+        ));
+    };
+    info!("Removing destination {addr} via {via}.");
+    dest_sources.admin_dest_sockets.remove(index);
+    dest_sources.admin_dest_expiries.remove(&addr);
+    dest_sources.store_union(unioned_dest_sockets);
+    Ok(())
+}
+
+/// Pauses or resumes forwarding to `addr`, whatever its source
+pub(crate) fn set_destination_paused(
+    dest_sources: &mut DestinationSources,
+    unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>,
+    addr: SocketAddr,
+    paused: bool,
+    via: &str,
+) -> Result<(), String> {
+    if !dest_sources.union().contains(&addr) {
+        return Err(format!("Destination {addr} is not forwarded to"));
+    }
+    let changed = if paused {
+        dest_sources.paused_dest_sockets.insert(addr)
+    } else {
+        dest_sources.paused_dest_sockets.remove(&addr)
+    };
+    if changed {
+        info!(
+            "{} destination {addr} via {via}.",
+            if paused { "Pausing" } else { "Resuming" }
+        );
+        dest_sources.store_union(unioned_dest_sockets);
+    }
+    Ok(())
+}
+
+/// Returns the filters in `RUST_LOG` syntax, with the per-target levels after the global one
+pub(crate) fn log_level_filters(req: &LogLevelRequest) -> Result<String, String> {
+    let parse_level = |level: &str| {
+        LevelFilter::from_str(level.trim()).map_err(|_| {
+            format!("Invalid log level {level:?}, expected error, warn, info, debug, or trace")
+        })
+    };
+    let mut filters = vec![parse_level(&req.level)?.to_string().to_lowercase()];
+    for (target, level) in &req.targets {
+        let valid_target = !target.is_empty()
+            && target
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | ':' | '-'));
+        if !valid_target {
+            return Err(format!(
+                "Invalid log target {target:?}, expected a module path"
+            ));
+        }
+        filters.push(format!(
+            "{target}={}",
+            parse_level(level)?.to_string().to_lowercase()
+        ));
+    }
+    Ok(filters.join(","))
+}
+
+/// Returns the destination and TTL of a `POST /destinations` body
+fn parse_add_destination(body: &str) -> Result<(SocketAddr, Option<Duration>), String> {
+    let req = serde_json::from_str::<AddDestinationRequest>(body).map_err(|e| e.to_string())?;
+    if req.ttl_seconds == Some(0) {
+        return Err("Invalid ttl_seconds 0, expected at least 1".to_string());
+    }
+    Ok((
+        parse_destination(&req.addr)?,
+        req.ttl_seconds.map(Duration::from_secs),
+    ))
+}
+
+pub(crate) fn parse_destination(addr: &str) -> Result<SocketAddr, String> {
+    let addr = SocketAddr::from_str(addr.trim())
+        .map_err(|e| format!("Invalid destination {addr:?}, expected ip:port. Error: {e}"))?;
+    if addr.port() == 0 || addr.ip().is_unspecified() {
+        return Err(format!(
+            "Invalid destination {addr}, ip and port must be specified"
+        ));
+    }
+    Ok(addr)
+}
+
+pub(crate) fn list_destinations(
+    dest_sources: &DestinationSources,
+    metrics: &ShredMetrics,
+) -> Vec<DestinationResponse> {
+    // forwarder threads' per destination counts are otherwise only added each reporting interval
+    metrics.aggregate_threads();
+    let now = Instant::now();
+    dest_sources
+        .union()
+        .into_iter()
+        .map(|addr| {
+            let sources = dest_sources.sources(&addr);
+            let (success_forward, fail_forward) = [
+                metrics.dest_forwarded_cumulative.get(&addr),
+                metrics.dest_forwarded.get(&addr),
+            ]
+            .into_iter()
+            .flatten()
+            .fold((0, 0), |(success, fail), kv| {
+                (success + kv.value().0, fail + kv.value().1)
+            });
+            DestinationResponse {
+                addr,
+                sources,
+                healthy: !dest_sources.unhealthy_dest_sockets.contains(&addr),
+                paused: dest_sources.paused_dest_sockets.contains(&addr),
+                // rounded up, so a destination about to expire doesn't show 0
+                ttl_remaining_secs: dest_sources.admin_dest_expiries.get(&addr).map(|expiry| {
+                    expiry
+                        .saturating_duration_since(now)
+                        .as_millis()
+                        .div_ceil(1000) as u64
+                }),
+                success_forward,
+                fail_forward,
+            }
+        })
+        .collect()
+}
+
+fn json_response<T: Serialize>(status_code: u16, body: &T) -> JsonResponse {
+    Response::from_data(serde_json::to_vec(body).unwrap())
+        .with_status_code(status_code)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap())
+}
+
+fn error_response(status_code: u16, error: String) -> JsonResponse {
+    json_response(status_code, &ErrorResponse { error })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        str::FromStr,
+        sync::atomic::Ordering,
+        time::{Duration, Instant},
+    };
+
+    use arc_swap::ArcSwap;
+
+    use crate::{
+        admin::{
+            add_destination, expire_destinations, heartbeat_status, list_destinations,
+            log_level_filters, parse_destination, set_destination_paused, LogLevelRequest,
+        },
+        forwarder::{DestinationSources, ShredMetrics},
+        heartbeat::{set_heartbeats_paused, HeartbeatState, RegionHeartbeatStats},
+    };
+
+    #[test]
+    fn test_parse_destination() {
+        assert_eq!(
+            parse_destination(" 127.0.0.1:8001 ").unwrap(),
+            SocketAddr::from_str("127.0.0.1:8001").unwrap()
+        );
+        assert!(parse_destination("127.0.0.1").is_err());
+        assert!(parse_destination("127.0.0.1:0").is_err());
+        assert!(parse_destination("0.0.0.0:8001").is_err());
+        assert!(parse_destination("not-an-ip:8001").is_err());
+    }
+
+    #[test]
+    fn test_log_level_filters() {
+        let req = serde_json::from_str::<LogLevelRequest>(r#"{"level": "DEBUG"}"#).unwrap();
+        assert_eq!(log_level_filters(&req).unwrap(), "debug");
+
+        let req = serde_json::from_str::<LogLevelRequest>(
+            r#"{"level": "warn", "targets": {"jito_shredstream_proxy::heartbeat": "trace", "tonic": "error"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            log_level_filters(&req).unwrap(),
+            "warn,jito_shredstream_proxy::heartbeat=trace,tonic=error"
+        );
+
+        for invalid in [
+            r#"{"level": "verbose"}"#,
+            r#"{"level": "info", "targets": {"tonic": "loud"}}"#,
+            r#"{"level": "info", "targets": {"tonic=debug,h2": "info"}}"#,
+            r#"{"level": "info", "targets": {"": "info"}}"#,
+        ] {
+            let req = serde_json::from_str::<LogLevelRequest>(invalid).unwrap();
+            assert!(log_level_filters(&req).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_list_destinations_tracks_sources() {
+        let shared = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let admin_only = SocketAddr::from_str("10.0.0.2:8001").unwrap();
+        let dest_sources = DestinationSources {
+            static_dest_sockets: vec![(shared, shared.to_string())],
+            discovered_dest_sockets: vec![],
+            admin_dest_sockets: vec![shared, admin_only],
+            ..Default::default()
+        };
+        let metrics = ShredMetrics::new();
+        metrics.dest_forwarded_cumulative.insert(shared, (3, 1));
+        metrics.dest_forwarded.insert(shared, (2, 0));
+
+        let destinations = list_destinations(&dest_sources, &metrics);
+
+        assert_eq!(destinations.len(), 2);
+        assert_eq!(destinations[0].addr, shared);
+        assert_eq!(destinations[0].sources, vec!["static", "admin"]);
+        assert_eq!(
+            (
+                destinations[0].success_forward,
+                destinations[0].fail_forward
+            ),
+            (5, 1)
+        );
+        assert_eq!(destinations[1].addr, admin_only);
+        assert_eq!(destinations[1].sources, vec!["admin"]);
+        assert!(destinations[0].healthy);
+    }
+
+    #[test]
+    fn test_destination_ttl() {
+        let temporary = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let permanent = SocketAddr::from_str("10.0.0.2:8001").unwrap();
+        let mut dest_sources = DestinationSources::default();
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+        let metrics = ShredMetrics::new();
+        let ttl = Duration::from_secs(60);
+        add_destination(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            temporary,
+            Some(Duration::from_secs(1)),
+            "test",
+        );
+        add_destination(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            permanent,
+            None,
+            "test",
+        );
+        // adding again restarts the TTL
+        add_destination(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            temporary,
+            Some(ttl),
+            "test",
+        );
+        let destinations = list_destinations(&dest_sources, &metrics);
+        assert_eq!(destinations[0].ttl_remaining_secs, Some(60));
+        assert_eq!(destinations[1].ttl_remaining_secs, None);
+
+        let now = Instant::now();
+        expire_destinations(&mut dest_sources, &unioned_dest_sockets, now, &metrics);
+        assert_eq!(**unioned_dest_sockets.load(), vec![temporary, permanent]);
+        expire_destinations(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            now + ttl,
+            &metrics,
+        );
+        assert_eq!(**unioned_dest_sockets.load(), vec![permanent]);
+        assert!(dest_sources.admin_dest_expiries.is_empty());
+        assert_eq!(
+            metrics
+                .admin_dest_expired_cumulative
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn test_pause_destination() {
+        let plain = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let spilled = SocketAddr::from_str("10.0.0.2:8001").unwrap();
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![
+                (plain, plain.to_string()),
+                (spilled, format!("{spilled}?spill=true")),
+            ],
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(**dest_sources.dest_spill.load(), [(spilled, false)].into());
+
+        // paused destinations aren't forwarded to, unless they're spilled to instead
+        for addr in [plain, spilled] {
+            set_destination_paused(&mut dest_sources, &unioned_dest_sockets, addr, true, "test")
+                .unwrap();
+        }
+        assert_eq!(**unioned_dest_sockets.load(), vec![spilled]);
+        assert_eq!(**dest_sources.dest_spill.load(), [(spilled, true)].into());
+        assert!(list_destinations(&dest_sources, &ShredMetrics::new())[0].paused);
+
+        set_destination_paused(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            plain,
+            false,
+            "test",
+        )
+        .unwrap();
+        set_destination_paused(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            spilled,
+            false,
+            "test",
+        )
+        .unwrap();
+        assert_eq!(**unioned_dest_sockets.load(), vec![plain, spilled]);
+        assert_eq!(**dest_sources.dest_spill.load(), [(spilled, false)].into());
+
+        let unknown = SocketAddr::from_str("10.0.0.3:8001").unwrap();
+        assert!(set_destination_paused(
+            &mut dest_sources,
+            &unioned_dest_sockets,
+            unknown,
+            true,
+            "test"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_heartbeat_status() {
+        let metrics = ShredMetrics::new();
+        let mut stats = RegionHeartbeatStats::new(Instant::now());
+        stats.state = HeartbeatState::Active;
+        metrics.heartbeat_regions.insert("ny".to_string(), stats);
+        assert_eq!(
+            serde_json::to_value(heartbeat_status(&metrics)).unwrap(),
+            serde_json::json!({"paused": false, "regions": {"ny": "active"}})
+        );
+
+        assert!(set_heartbeats_paused(&metrics, true, "test"));
+        assert!(!set_heartbeats_paused(&metrics, true, "test"));
+        assert!(heartbeat_status(&metrics).paused);
+        assert!(set_heartbeats_paused(&metrics, false, "test"));
+        assert!(!heartbeat_status(&metrics).paused);
+    }
+}
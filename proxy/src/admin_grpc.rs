@@ -0,0 +1,824 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::Duration,
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use crossbeam_channel::Sender;
+use jito_protos::shredstream_proxy::{
+    shredstream_proxy_admin_server::{ShredstreamProxyAdmin, ShredstreamProxyAdminServer},
+    AddDestinationRequest, Destination, GetMetricsSnapshotRequest, ListDestinationsRequest,
+    ListDestinationsResponse, MetricsSnapshot, RemoveDestinationRequest, SetLogLevelRequest,
+    SetLogLevelResponse, TriggerDiscoveryRefreshRequest, TriggerDiscoveryRefreshResponse,
+};
+use log::{info, warn};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{
+    transport::{Identity, Server, ServerTlsConfig},
+    Request, Response, Status,
+};
+
+use crate::{
+    admin::{self, LogLevelRequest},
+    forwarder::{DestinationSources, EndpointDiscovery, ShredMetrics},
+    logging,
+};
+
+/// Certificate chain and private key files the admin gRPC service is served with
+#[derive(Clone, Debug)]
+pub struct AdminGrpcTls<'a> {
+    pub cert_file: &'a Path,
+    pub key_file: &'a Path,
+}
+
+/// Serves the `ShredstreamProxyAdmin` gRPC service on `bind_addr`, over TLS if `tls` is set.
+/// Shares destinations and metrics with the rest of the proxy, and exits along with it.
+/// `discovery_refresh_sender` wakes the destination refresh thread to fetch from the discovery service.
+/// Returns the address bound, which differs from `bind_addr` when its port is 0.
+#[allow(clippy::too_many_arguments)]
+pub fn start_admin_grpc_thread(
+    bind_addr: SocketAddr,
+    tls: Option<AdminGrpcTls>,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    endpoint_discovery: Arc<ArcSwapOption<EndpointDiscovery>>, /* can be changed by config reload */
+    discovery_refresh_sender: Sender<()>,
+    metrics: Arc<ShredMetrics>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(JoinHandle<()>, SocketAddr)> {
+    let mut server = Server::builder();
+    if let Some(tls) = &tls {
+        let identity = Identity::from_pem(
+            fs::read(tls.cert_file).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to read {:?}: {e}", tls.cert_file))
+            })?,
+            fs::read(tls.key_file).map_err(|e| {
+                io::Error::new(e.kind(), format!("Failed to read {:?}: {e}", tls.key_file))
+            })?,
+        );
+        server = server
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid admin gRPC TLS certificate or key: {e}"),
+                )
+            })?;
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("ssPxyAdmGrpcRt")
+        .enable_all()
+        .build()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(bind_addr))?;
+    let local_addr = listener.local_addr()?;
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    info!("Serving admin gRPC on {scheme}://{local_addr}");
+
+    let service = AdminService {
+        dest_sources,
+        unioned_dest_sockets,
+        endpoint_discovery,
+        discovery_refresh_sender,
+        metrics,
+    };
+    let hdl = Builder::new()
+        .name("ssPxyAdminGrpc".to_string())
+        .spawn(move || {
+            let shutdown = async move {
+                // periodically check for exit
+                while !exit.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            };
+            if let Err(e) = runtime.block_on(
+                server
+                    .add_service(ShredstreamProxyAdminServer::new(service))
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown),
+            ) {
+                warn!("Admin gRPC server exited with error: {e}");
+            }
+            info!("Exiting admin gRPC thread.");
+        })?;
+    Ok((hdl, local_addr))
+}
+
+struct AdminService {
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    endpoint_discovery: Arc<ArcSwapOption<EndpointDiscovery>>,
+    discovery_refresh_sender: Sender<()>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl AdminService {
+    fn list_destinations(&self, dest_sources: &DestinationSources) -> ListDestinationsResponse {
+        ListDestinationsResponse {
+            destinations: admin::list_destinations(dest_sources, &self.metrics)
+                .into_iter()
+                .map(|dest| Destination {
+                    addr: dest.addr.to_string(),
+                    sources: dest.sources.iter().map(|s| s.to_string()).collect(),
+                    healthy: dest.healthy,
+                    success_forward: dest.success_forward,
+                    fail_forward: dest.fail_forward,
+                    ttl_remaining_secs: dest.ttl_remaining_secs.unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl ShredstreamProxyAdmin for AdminService {
+    async fn list_destinations(
+        &self,
+        _request: Request<ListDestinationsRequest>,
+    ) -> Result<Response<ListDestinationsResponse>, Status> {
+        let dest_sources = self.dest_sources.lock().unwrap();
+        Ok(Response::new(self.list_destinations(&dest_sources)))
+    }
+
+    async fn add_destination(
+        &self,
+        request: Request<AddDestinationRequest>,
+    ) -> Result<Response<ListDestinationsResponse>, Status> {
+        let request = request.into_inner();
+        let addr = admin::parse_destination(&request.addr).map_err(Status::invalid_argument)?;
+        let ttl = (request.ttl_seconds > 0).then(|| Duration::from_secs(request.ttl_seconds));
+        let mut dest_sources = self.dest_sources.lock().unwrap();
+        admin::add_destination(
+            &mut dest_sources,
+            &self.unioned_dest_sockets,
+            addr,
+            ttl,
+            "admin gRPC",
+        );
+        Ok(Response::new(self.list_destinations(&dest_sources)))
+    }
+
+    async fn remove_destination(
+        &self,
+        request: Request<RemoveDestinationRequest>,
+    ) -> Result<Response<ListDestinationsResponse>, Status> {
+        let addr = admin::parse_destination(&request.into_inner().addr)
+            .map_err(Status::invalid_argument)?;
+        let mut dest_sources = self.dest_sources.lock().unwrap();
+        admin::remove_destination(
+            &mut dest_sources,
+            &self.unioned_dest_sockets,
+            addr,
+            "admin gRPC",
+        )
+        .map_err(Status::not_found)?;
+        Ok(Response::new(self.list_destinations(&dest_sources)))
+    }
+
+    async fn get_metrics_snapshot(
+        &self,
+        _request: Request<GetMetricsSnapshotRequest>,
+    ) -> Result<Response<MetricsSnapshot>, Status> {
+        Ok(Response::new(metrics_snapshot(&self.metrics)))
+    }
+
+    async fn set_log_level(
+        &self,
+        request: Request<SetLogLevelRequest>,
+    ) -> Result<Response<SetLogLevelResponse>, Status> {
+        let request = request.into_inner();
+        let filters = admin::log_level_filters(&LogLevelRequest {
+            level: request.level,
+            targets: request.targets.into_iter().collect(),
+        })
+        .map_err(Status::invalid_argument)?;
+        logging::set_log_filters(filters.clone()).map_err(Status::failed_precondition)?;
+        info!("Set log filters to {filters:?} via admin gRPC.");
+        Ok(Response::new(SetLogLevelResponse { filters }))
+    }
+
+    async fn trigger_discovery_refresh(
+        &self,
+        _request: Request<TriggerDiscoveryRefreshRequest>,
+    ) -> Result<Response<TriggerDiscoveryRefreshResponse>, Status> {
+        if self.endpoint_discovery.load().is_none() {
+            return Err(Status::failed_precondition(
+                "Endpoint discovery isn't configured",
+            ));
+        }
+        info!("Triggering discovery refresh via admin gRPC.");
+        // a refresh already pending covers this one
+        let _ = self.discovery_refresh_sender.try_send(());
+        Ok(Response::new(TriggerDiscoveryRefreshResponse {}))
+    }
+}
+
+/// Counters include the interval not yet folded into the `_cumulative` fields, so they're current
+fn metrics_snapshot(metrics: &ShredMetrics) -> MetricsSnapshot {
+    // forwarder threads' counts are otherwise only added each reporting interval
+    metrics.aggregate_threads();
+    let total = |interval: &AtomicU64, cumulative: &AtomicU64| {
+        interval.load(Ordering::Relaxed) + cumulative.load(Ordering::Relaxed)
+    };
+    let counters = HashMap::from([
+        (
+            "agg_received",
+            total(&metrics.agg_received, &metrics.agg_received_cumulative),
+        ),
+        (
+            "agg_success_forward",
+            total(
+                &metrics.agg_success_forward,
+                &metrics.agg_success_forward_cumulative,
+            ),
+        ),
+        (
+            "agg_fail_forward",
+            total(
+                &metrics.agg_fail_forward,
+                &metrics.agg_fail_forward_cumulative,
+            ),
+        ),
+        (
+            "udp_fail_forward",
+            total(
+                &metrics.udp_fail_forward,
+                &metrics.udp_fail_forward_cumulative,
+            ),
+        ),
+        (
+            "quic_fail_forward",
+            total(
+                &metrics.quic_fail_forward,
+                &metrics.quic_fail_forward_cumulative,
+            ),
+        ),
+        (
+            "tunnel_fail_forward",
+            total(
+                &metrics.tunnel_fail_forward,
+                &metrics.tunnel_fail_forward_cumulative,
+            ),
+        ),
+        (
+            "unix_fail_forward",
+            total(
+                &metrics.unix_fail_forward,
+                &metrics.unix_fail_forward_cumulative,
+            ),
+        ),
+        (
+            "duplicate",
+            total(&metrics.duplicate, &metrics.duplicate_cumulative),
+        ),
+        (
+            "stale_slot_dropped",
+            total(
+                &metrics.stale_slot_dropped,
+                &metrics.stale_slot_dropped_cumulative,
+            ),
+        ),
+        (
+            "received_invalid",
+            total(
+                &metrics.received_invalid,
+                &metrics.received_invalid_cumulative,
+            ),
+        ),
+        (
+            "non_shred_dropped",
+            total(
+                &metrics.non_shred_dropped,
+                &metrics.non_shred_dropped_cumulative,
+            ),
+        ),
+        (
+            "conflicting_shreds",
+            total(
+                &metrics.conflicting_shreds,
+                &metrics.conflicting_shreds_cumulative,
+            ),
+        ),
+        (
+            "source_not_allowed",
+            total(
+                &metrics.source_not_allowed,
+                &metrics.source_not_allowed_cumulative,
+            ),
+        ),
+        (
+            "data_shred_filtered",
+            total(
+                &metrics.data_shred_filtered,
+                &metrics.data_shred_filtered_cumulative,
+            ),
+        ),
+        (
+            "code_shred_filtered",
+            total(
+                &metrics.code_shred_filtered,
+                &metrics.code_shred_filtered_cumulative,
+            ),
+        ),
+        (
+            "recv_socket_dropped",
+            total(
+                &metrics.recv_socket_dropped,
+                &metrics.recv_socket_dropped_cumulative,
+            ),
+        ),
+        (
+            "pcap_recorded",
+            total(&metrics.pcap_recorded, &metrics.pcap_recorded_cumulative),
+        ),
+        (
+            "pcap_dropped",
+            total(&metrics.pcap_dropped, &metrics.pcap_dropped_cumulative),
+        ),
+        (
+            "kafka_published",
+            total(
+                &metrics.kafka_published,
+                &metrics.kafka_published_cumulative,
+            ),
+        ),
+        (
+            "kafka_dropped",
+            total(&metrics.kafka_dropped, &metrics.kafka_dropped_cumulative),
+        ),
+        (
+            "kafka_delivery_failed",
+            total(
+                &metrics.kafka_delivery_failed,
+                &metrics.kafka_delivery_failed_cumulative,
+            ),
+        ),
+        (
+            "archive_inserted",
+            total(
+                &metrics.archive_inserted,
+                &metrics.archive_inserted_cumulative,
+            ),
+        ),
+        (
+            "archive_dropped",
+            total(
+                &metrics.archive_dropped,
+                &metrics.archive_dropped_cumulative,
+            ),
+        ),
+        (
+            "archive_insert_failed",
+            total(
+                &metrics.archive_insert_failed,
+                &metrics.archive_insert_failed_cumulative,
+            ),
+        ),
+        (
+            "subscriber_delivered",
+            total(
+                &metrics.subscriber_delivered,
+                &metrics.subscriber_delivered_cumulative,
+            ),
+        ),
+        (
+            "subscriber_dropped",
+            total(
+                &metrics.subscriber_dropped,
+                &metrics.subscriber_dropped_cumulative,
+            ),
+        ),
+        (
+            "channel_dropped_batches",
+            total(
+                &metrics.channel_dropped_batches,
+                &metrics.channel_dropped_batches_cumulative,
+            ),
+        ),
+        (
+            "channel_dropped_packets",
+            total(
+                &metrics.channel_dropped_packets,
+                &metrics.channel_dropped_packets_cumulative,
+            ),
+        ),
+        (
+            "memory_guard_dropped",
+            total(
+                &metrics.memory_guard_dropped,
+                &metrics.memory_guard_dropped_cumulative,
+            ),
+        ),
+        (
+            "keepalive_sent",
+            total(&metrics.keepalive_sent, &metrics.keepalive_sent_cumulative),
+        ),
+        (
+            "keepalive_send_failed",
+            total(
+                &metrics.keepalive_send_failed,
+                &metrics.keepalive_send_failed_cumulative,
+            ),
+        ),
+        (
+            "keepalive_received",
+            total(
+                &metrics.keepalive_received,
+                &metrics.keepalive_received_cumulative,
+            ),
+        ),
+        (
+            "encrypt_dropped",
+            total(
+                &metrics.encrypt_dropped,
+                &metrics.encrypt_dropped_cumulative,
+            ),
+        ),
+        (
+            "decrypt_failed",
+            total(&metrics.decrypt_failed, &metrics.decrypt_failed_cumulative),
+        ),
+        (
+            "events_sent",
+            total(&metrics.events_sent, &metrics.events_sent_cumulative),
+        ),
+        (
+            "events_dropped",
+            total(&metrics.events_dropped, &metrics.events_dropped_cumulative),
+        ),
+        (
+            "priority_dropped_high",
+            total(
+                &metrics.priority_dropped_high,
+                &metrics.priority_dropped_high_cumulative,
+            ),
+        ),
+        (
+            "priority_dropped_normal",
+            total(
+                &metrics.priority_dropped_normal,
+                &metrics.priority_dropped_normal_cumulative,
+            ),
+        ),
+        (
+            "priority_dropped_low",
+            total(
+                &metrics.priority_dropped_low,
+                &metrics.priority_dropped_low_cumulative,
+            ),
+        ),
+        (
+            "spill_spilled",
+            total(&metrics.spill_spilled, &metrics.spill_spilled_cumulative),
+        ),
+        (
+            "spill_replayed",
+            total(&metrics.spill_replayed, &metrics.spill_replayed_cumulative),
+        ),
+        (
+            "spill_expired",
+            total(&metrics.spill_expired, &metrics.spill_expired_cumulative),
+        ),
+        (
+            "spill_dropped",
+            total(&metrics.spill_dropped, &metrics.spill_dropped_cumulative),
+        ),
+        (
+            "mirror_sent",
+            total(&metrics.mirror_sent, &metrics.mirror_sent_cumulative),
+        ),
+        (
+            "mirror_failed",
+            total(&metrics.mirror_failed, &metrics.mirror_failed_cumulative),
+        ),
+        (
+            "mirror_shed",
+            total(&metrics.mirror_shed, &metrics.mirror_shed_cumulative),
+        ),
+        (
+            "chaos_dropped",
+            total(&metrics.chaos_dropped, &metrics.chaos_dropped_cumulative),
+        ),
+        (
+            "chaos_duplicated",
+            total(
+                &metrics.chaos_duplicated,
+                &metrics.chaos_duplicated_cumulative,
+            ),
+        ),
+        (
+            "chaos_reordered",
+            total(
+                &metrics.chaos_reordered,
+                &metrics.chaos_reordered_cumulative,
+            ),
+        ),
+        (
+            "chaos_delayed",
+            total(&metrics.chaos_delayed, &metrics.chaos_delayed_cumulative),
+        ),
+        (
+            "dest_became_unhealthy",
+            total(
+                &metrics.dest_became_unhealthy,
+                &metrics.dest_became_unhealthy_cumulative,
+            ),
+        ),
+        (
+            "dest_became_healthy",
+            total(
+                &metrics.dest_became_healthy,
+                &metrics.dest_became_healthy_cumulative,
+            ),
+        ),
+        (
+            "thread_restarts",
+            total(
+                &metrics.thread_restarts,
+                &metrics.thread_restarts_cumulative,
+            ),
+        ),
+        (
+            "socket_rebinds",
+            total(&metrics.socket_rebinds, &metrics.socket_rebinds_cumulative),
+        ),
+        (
+            "deduper_early_resets",
+            total(
+                &metrics.deduper_early_resets,
+                &metrics.deduper_early_resets_cumulative,
+            ),
+        ),
+        // only tracked cumulatively
+        (
+            "slots_finalized",
+            metrics.slots_finalized_cumulative.load(Ordering::Relaxed),
+        ),
+        (
+            "slots_last_shred_seen",
+            metrics
+                .slots_last_shred_seen_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "successful_heartbeat",
+            metrics
+                .successful_heartbeat_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "failed_heartbeat",
+            metrics.failed_heartbeat_cumulative.load(Ordering::Relaxed),
+        ),
+        (
+            "block_engine_failovers",
+            metrics
+                .block_engine_failovers_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "stall_reregistrations",
+            metrics
+                .stall_reregistrations_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "admin_dest_expired",
+            metrics
+                .admin_dest_expired_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "public_ip_changes",
+            metrics.public_ip_changes_cumulative.load(Ordering::Relaxed),
+        ),
+        (
+            "token_refresh_attempts",
+            metrics
+                .token_refresh_attempts_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "token_refresh_failures",
+            metrics
+                .token_refresh_failures_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "discovery_auth_failures",
+            metrics
+                .discovery_auth_failures_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "discovery_failures",
+            metrics
+                .discovery_failures_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "discovery_not_modified",
+            metrics
+                .discovery_not_modified_cumulative
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "discovery_mass_removals_held",
+            metrics
+                .discovery_mass_removals_held_cumulative
+                .load(Ordering::Relaxed),
+        ),
+    ]);
+    let gauges = HashMap::from([
+        (
+            "healthy_destinations",
+            metrics.healthy_destinations.load(Ordering::Relaxed),
+        ),
+        (
+            "unhealthy_destinations",
+            metrics.unhealthy_destinations.load(Ordering::Relaxed),
+        ),
+        (
+            "blocked_destinations",
+            metrics.blocked_destinations.load(Ordering::Relaxed),
+        ),
+        (
+            "dest_cap_exceeded",
+            metrics.dest_cap_exceeded.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "validator_identities_unresolved",
+            metrics
+                .validator_identities_unresolved
+                .load(Ordering::Relaxed),
+        ),
+        (
+            "access_token_ttl_secs",
+            metrics.access_token_ttl_secs.load(Ordering::Relaxed),
+        ),
+        (
+            "heartbeat_paused",
+            metrics.heartbeat_paused.load(Ordering::Relaxed) as u64,
+        ),
+        (
+            "discovery_using_saved_state",
+            metrics.discovery_using_saved_state.load(Ordering::Relaxed) as u64,
+        ),
+        ("buffered_bytes", metrics.memory_guard.total()),
+        (
+            "last_interval_duplicate_ppm",
+            metrics.last_interval_duplicate_ppm.load(Ordering::Relaxed),
+        ),
+        (
+            "deduper_saturation_ppm",
+            metrics.deduper_saturation_ppm.load(Ordering::Relaxed),
+        ),
+        (
+            "deduper_age_ms",
+            metrics.deduper_age_ms.load(Ordering::Relaxed),
+        ),
+    ]);
+    MetricsSnapshot {
+        counters: counters
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+        gauges: gauges
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use arc_swap::{ArcSwap, ArcSwapOption};
+    use jito_protos::shredstream_proxy::{
+        shredstream_proxy_admin_client::ShredstreamProxyAdminClient, ListDestinationsRequest,
+        TriggerDiscoveryRefreshRequest,
+    };
+    use tonic::{
+        transport::{Certificate, ClientTlsConfig, Endpoint},
+        Code,
+    };
+
+    use crate::{
+        admin_grpc::{metrics_snapshot, start_admin_grpc_thread, AdminGrpcTls},
+        forwarder::{DestinationSources, ShredMetrics},
+        tunnel::tests::{TEST_CERT, TEST_KEY},
+    };
+
+    #[test]
+    fn test_metrics_snapshot_includes_interval() {
+        let metrics = ShredMetrics::new();
+        metrics.agg_received.store(2, Ordering::Relaxed);
+        metrics.agg_received_cumulative.store(5, Ordering::Relaxed);
+        metrics.heartbeat_paused.store(true, Ordering::Relaxed);
+
+        let snapshot = metrics_snapshot(&metrics);
+        assert_eq!(snapshot.counters["agg_received"], 7);
+        assert_eq!(snapshot.counters["duplicate"], 0);
+        assert_eq!(snapshot.gauges["heartbeat_paused"], 1);
+    }
+
+    #[test]
+    fn test_admin_grpc_tls() {
+        let dir = std::env::temp_dir().join(format!("test_admin_grpc_tls_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_file, key_file) = (dir.join("cert.pem"), dir.join("key.pem"));
+        std::fs::write(&cert_file, TEST_CERT).unwrap();
+        std::fs::write(&key_file, TEST_KEY).unwrap();
+        let dest = SocketAddr::from(([127, 0, 0, 1], 8001));
+        let exit = Arc::new(AtomicBool::new(false));
+        let (hdl, bind_addr) = start_admin_grpc_thread(
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            Some(AdminGrpcTls {
+                cert_file: &cert_file,
+                key_file: &key_file,
+            }),
+            Arc::new(Mutex::new(DestinationSources {
+                admin_dest_sockets: vec![dest],
+                ..Default::default()
+            })),
+            Arc::new(ArcSwap::from_pointee(vec![dest])),
+            Arc::new(ArcSwapOption::empty()),
+            crossbeam_channel::bounded(1).0,
+            Arc::new(ShredMetrics::new()),
+            exit.clone(),
+        )
+        .unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let channel = Endpoint::from_shared(format!("https://{bind_addr}"))
+                .unwrap()
+                .tls_config(
+                    ClientTlsConfig::new()
+                        .ca_certificate(Certificate::from_pem(TEST_CERT))
+                        .domain_name("localhost"),
+                )
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            let mut client = ShredstreamProxyAdminClient::new(channel);
+            let destinations = client
+                .list_destinations(ListDestinationsRequest {})
+                .await
+                .unwrap()
+                .into_inner()
+                .destinations;
+            assert_eq!(destinations.len(), 1);
+            assert_eq!(destinations[0].addr, "127.0.0.1:8001");
+            assert_eq!(destinations[0].sources, vec!["admin"]);
+
+            let status = client
+                .trigger_discovery_refresh(TriggerDiscoveryRefreshRequest {})
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+        });
+
+        exit.store(true, Ordering::Relaxed);
+        hdl.join().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_admin_grpc_rejects_missing_key() {
+        let missing = std::env::temp_dir().join("test_admin_grpc_missing_key.pem");
+        let err = start_admin_grpc_thread(
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            Some(AdminGrpcTls {
+                cert_file: &missing,
+                key_file: &missing,
+            }),
+            Arc::new(Mutex::new(DestinationSources::default())),
+            Arc::new(ArcSwap::from_pointee(vec![])),
+            Arc::new(ArcSwapOption::empty()),
+            crossbeam_channel::bounded(1).0,
+            Arc::new(ShredMetrics::new()),
+            Arc::new(AtomicBool::new(true)),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("test_admin_grpc_missing_key.pem"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::io;
+
+/// Pins the calling thread to a single core
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    if core >= libc::CPU_SETSIZE as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Core {core} exceeds the max of {}", libc::CPU_SETSIZE - 1),
+        ));
+    }
+    // SAFETY: cpu_set_t is a plain bitmask, zeroed is an empty set, and `core` is within its bounds
+    let result = unsafe {
+        let mut cpu_set = std::mem::zeroed::<libc::cpu_set_t>();
+        libc::CPU_SET(core, &mut cpu_set);
+        // pid 0 is the calling thread
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &cpu_set)
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_core: usize) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Thread affinity is only supported on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::affinity::pin_current_thread;
+
+    #[test]
+    fn test_pin_current_thread() {
+        assert!(pin_current_thread(usize::MAX).is_err());
+
+        #[cfg(not(target_os = "linux"))]
+        assert!(pin_current_thread(0).is_err());
+
+        // pin a separate thread so the test runner's threads stay floating
+        #[cfg(target_os = "linux")]
+        std::thread::spawn(|| {
+            // SAFETY: cpu_set_t is a plain bitmask filled in by sched_getaffinity
+            let allowed_core = unsafe {
+                let mut cpu_set = std::mem::zeroed::<libc::cpu_set_t>();
+                assert_eq!(
+                    libc::sched_getaffinity(
+                        0,
+                        std::mem::size_of::<libc::cpu_set_t>(),
+                        &mut cpu_set
+                    ),
+                    0
+                );
+                (0..libc::CPU_SETSIZE as usize)
+                    .find(|core| libc::CPU_ISSET(*core, &cpu_set))
+                    .unwrap()
+            };
+            pin_current_thread(allowed_core).unwrap();
+            assert_eq!(unsafe { libc::sched_getcpu() }, allowed_core as i32);
+        })
+        .join()
+        .unwrap();
+    }
+}
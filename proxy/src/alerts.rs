@@ -0,0 +1,589 @@
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{forwarder::ShredMetrics, validate::url_host};
+
+/// Alerts queued for the webhook thread before new ones are dropped
+const ALERT_CHANNEL_CAPACITY: usize = 64;
+/// Max time to wait on the webhook, so a hung endpoint only delays the alerts behind it
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Body posted to `--alert-webhook-url`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertWebhookFormat {
+    /// The alert's fields, `{"host_id":"proxy-1","condition":"no_shreds","status":"firing",...}`
+    #[default]
+    Json,
+    /// `{"text":"..."}`, for Slack incoming webhooks
+    Slack,
+    /// `{"content":"..."}`, for Discord webhooks
+    Discord,
+}
+
+#[derive(Clone, Debug)]
+pub struct AlertConfig {
+    // may carry the webhook's secret, so never logged
+    pub webhook_url: String,
+    pub format: AlertWebhookFormat,
+    /// Identifies this proxy in alerts
+    pub host_id: String,
+    pub no_shreds_after: Duration,
+    /// Fraction of packets failing to forward in a metrics interval
+    pub forward_failure_ratio: f64,
+    /// None when not heartbeating, in forward-only mode
+    pub heartbeat_down_after: Option<Duration>,
+    /// Estimated fraction of the deduper's bits set
+    pub deduper_saturation: f64,
+    /// Min time between alerts of the same condition
+    pub cooldown: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertCondition {
+    NoShreds,
+    ForwardFailures,
+    HeartbeatDown,
+    UnhealthyDestinations,
+    DeduperSaturated,
+}
+
+impl AlertCondition {
+    const ALL: [Self; 5] = [
+        Self::NoShreds,
+        Self::ForwardFailures,
+        Self::HeartbeatDown,
+        Self::UnhealthyDestinations,
+        Self::DeduperSaturated,
+    ];
+
+    fn recovered_message(self) -> &'static str {
+        match self {
+            Self::NoShreds => "Shreds are being received again.",
+            Self::ForwardFailures => "Forward failures are back under the threshold.",
+            Self::HeartbeatDown => "Heartbeats are succeeding again.",
+            Self::UnhealthyDestinations => "All destinations are healthy.",
+            Self::DeduperSaturated => "Deduper saturation is back under the threshold.",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// Counter values sent with every alert
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct AlertCounters {
+    pub received_total: u64,
+    pub forwarded_total: u64,
+    pub forward_failed_total: u64,
+    pub successful_heartbeats_total: u64,
+    pub unhealthy_destinations: u64,
+    pub deduper_saturation: f64,
+}
+
+impl AlertCounters {
+    /// Reads the counters as of the last metrics report
+    pub fn load(metrics: &ShredMetrics) -> Self {
+        Self {
+            received_total: metrics.agg_received_cumulative.load(Ordering::Relaxed),
+            forwarded_total: metrics
+                .agg_success_forward_cumulative
+                .load(Ordering::Relaxed),
+            forward_failed_total: metrics.agg_fail_forward_cumulative.load(Ordering::Relaxed),
+            successful_heartbeats_total: metrics
+                .successful_heartbeat_cumulative
+                .load(Ordering::Relaxed),
+            unhealthy_destinations: metrics.unhealthy_destinations.load(Ordering::Relaxed),
+            deduper_saturation: metrics.deduper_saturation_ppm.load(Ordering::Relaxed) as f64
+                / 1_000_000.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Alert {
+    pub host_id: String,
+    pub condition: AlertCondition,
+    pub status: AlertStatus,
+    pub message: String,
+    pub counters: AlertCounters,
+    pub timestamp_unix_secs: u64,
+}
+
+impl Alert {
+    fn text(&self) -> String {
+        let status = match self.status {
+            AlertStatus::Firing => "FIRING",
+            AlertStatus::Resolved => "RESOLVED",
+        };
+        let counters = &self.counters;
+        format!(
+            "[{}] {status} {:?}: {} received_total={} forwarded_total={} forward_failed_total={} successful_heartbeats_total={} unhealthy_destinations={} deduper_saturation={:.3}",
+            self.host_id,
+            self.condition,
+            self.message,
+            counters.received_total,
+            counters.forwarded_total,
+            counters.forward_failed_total,
+            counters.successful_heartbeats_total,
+            counters.unhealthy_destinations,
+            counters.deduper_saturation,
+        )
+    }
+
+    fn body(&self, format: AlertWebhookFormat) -> serde_json::Value {
+        match format {
+            AlertWebhookFormat::Json => json!(self),
+            AlertWebhookFormat::Slack => json!({ "text": self.text() }),
+            AlertWebhookFormat::Discord => json!({ "content": self.text() }),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ConditionState {
+    active: bool,
+    /// An alert was sent since the condition became active, so its recovery is sent too
+    notified: bool,
+    last_sent: Option<Instant>,
+}
+
+/// Checks alert conditions against the metrics after each report, queueing alerts for the webhook thread.
+/// Alerts repeat at most once per cooldown while a condition persists, and recoveries are sent once it clears
+pub struct AlertMonitor {
+    config: AlertConfig,
+    alert_sender: Sender<Alert>,
+    /// Counters at the previous check
+    last_counters: Option<AlertCounters>,
+    /// When packets last arrived or heartbeats last succeeded, or were paused. The monitor's start until then
+    last_received_at: Instant,
+    last_heartbeat_at: Instant,
+    states: HashMap<AlertCondition, ConditionState>,
+}
+
+impl AlertMonitor {
+    fn new(config: AlertConfig, alert_sender: Sender<Alert>, now: Instant) -> Self {
+        Self {
+            config,
+            alert_sender,
+            last_counters: None,
+            last_received_at: now,
+            last_heartbeat_at: now,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Called by the accessory thread after each metrics report, never blocking on the webhook
+    pub fn check_metrics(&mut self, metrics: &ShredMetrics, now: Instant) {
+        let heartbeat_paused = metrics.heartbeat_paused.load(Ordering::Relaxed);
+        let alerts = self.check(
+            AlertCounters::load(metrics),
+            heartbeat_paused,
+            now,
+            SystemTime::now(),
+        );
+        for alert in alerts {
+            warn!(
+                "Alert {:?} {:?}: {}",
+                alert.condition, alert.status, alert.message
+            );
+            if let Err(TrySendError::Full(alert)) = self.alert_sender.try_send(alert) {
+                warn!(
+                    "Alert webhook fell behind, dropped {:?} alert.",
+                    alert.condition
+                );
+            }
+        }
+    }
+
+    /// Returns alerts for conditions that became active or cleared, and reminders for ones active past the cooldown
+    fn check(
+        &mut self,
+        counters: AlertCounters,
+        heartbeat_paused: bool,
+        now: Instant,
+        now_system: SystemTime,
+    ) -> Vec<Alert> {
+        let last_counters = self
+            .last_counters
+            .replace(counters.clone())
+            .unwrap_or_else(|| counters.clone());
+        // nothing arrives while heartbeats are paused
+        if counters.received_total != last_counters.received_total || heartbeat_paused {
+            self.last_received_at = now;
+        }
+        if counters.successful_heartbeats_total != last_counters.successful_heartbeats_total
+            || heartbeat_paused
+        {
+            self.last_heartbeat_at = now;
+        }
+
+        let mut active = HashMap::new();
+        let no_shreds_for = now.duration_since(self.last_received_at);
+        if no_shreds_for >= self.config.no_shreds_after {
+            active.insert(
+                AlertCondition::NoShreds,
+                format!("No shreds received for {}s.", no_shreds_for.as_secs()),
+            );
+        }
+        let forwarded = counters
+            .forwarded_total
+            .saturating_sub(last_counters.forwarded_total);
+        let failed = counters
+            .forward_failed_total
+            .saturating_sub(last_counters.forward_failed_total);
+        if failed > 0
+            && failed as f64 / (forwarded + failed) as f64 > self.config.forward_failure_ratio
+        {
+            active.insert(
+                AlertCondition::ForwardFailures,
+                format!(
+                    "{failed} of {} packets failed to forward since the last check.",
+                    forwarded + failed
+                ),
+            );
+        }
+        if let Some(heartbeat_down_after) = self.config.heartbeat_down_after {
+            let down_for = now.duration_since(self.last_heartbeat_at);
+            if down_for >= heartbeat_down_after {
+                active.insert(
+                    AlertCondition::HeartbeatDown,
+                    format!("No heartbeat succeeded for {}s.", down_for.as_secs()),
+                );
+            }
+        }
+        if counters.unhealthy_destinations > 0 {
+            active.insert(
+                AlertCondition::UnhealthyDestinations,
+                format!(
+                    "{} destinations are failing health checks.",
+                    counters.unhealthy_destinations
+                ),
+            );
+        }
+        if counters.deduper_saturation > self.config.deduper_saturation {
+            active.insert(
+                AlertCondition::DeduperSaturated,
+                format!(
+                    "Deduper is {:.1}% saturated, letting duplicates through. Raise --deduper-num-bits.",
+                    counters.deduper_saturation * 100.0
+                ),
+            );
+        }
+
+        let timestamp_unix_secs = now_system
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut alerts = vec![];
+        for condition in AlertCondition::ALL {
+            let state = self.states.entry(condition).or_default();
+            let (status, message) = match active.remove(&condition) {
+                Some(message) => {
+                    state.active = true;
+                    let cooled_down = state.last_sent.map_or(true, |last_sent| {
+                        now.duration_since(last_sent) >= self.config.cooldown
+                    });
+                    if !cooled_down {
+                        continue;
+                    }
+                    state.notified = true;
+                    state.last_sent = Some(now);
+                    (AlertStatus::Firing, message)
+                }
+                None => {
+                    let was_notified = state.active && state.notified;
+                    state.active = false;
+                    state.notified = false;
+                    if !was_notified {
+                        continue;
+                    }
+                    (
+                        AlertStatus::Resolved,
+                        condition.recovered_message().to_string(),
+                    )
+                }
+            };
+            alerts.push(Alert {
+                host_id: self.config.host_id.clone(),
+                condition,
+                status,
+                message,
+                counters: counters.clone(),
+                timestamp_unix_secs,
+            });
+        }
+        alerts
+    }
+}
+
+/// Starts the thread posting alerts to the webhook. Returns the monitor for the accessory thread to check metrics with.
+/// Failed posts are logged and dropped, never retried, since the next check sends whatever is still firing after the cooldown
+pub fn start_alert_thread(
+    config: AlertConfig,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(AlertMonitor, JoinHandle<()>)> {
+    let webhook_host = url_host(&config.webhook_url).unwrap_or_default();
+    info!(
+        "Sending alerts as {} to {webhook_host} as {:?}.",
+        config.host_id, config.format
+    );
+    let (alert_sender, alert_receiver) = crossbeam_channel::bounded(ALERT_CHANNEL_CAPACITY);
+    let (webhook_url, format) = (config.webhook_url.clone(), config.format);
+    let hdl = Builder::new()
+        .name("ssPxyAlerts".to_string())
+        .spawn(move || {
+            let http_client = reqwest::blocking::Client::builder()
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()
+                .expect("to build alert webhook http client");
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(alert_receiver) -> alert => {
+                        let Ok(alert) = alert else {
+                            break;
+                        };
+                        let result = http_client
+                            .post(&webhook_url)
+                            .json(&alert.body(format))
+                            .send()
+                            .and_then(|response| response.error_for_status());
+                        if let Err(e) = result {
+                            // the error may include the url
+                            warn!(
+                                "Failed to post {:?} alert to {webhook_host}. Error: {}",
+                                alert.condition,
+                                e.without_url()
+                            );
+                        }
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            info!("Exiting alert thread.");
+        })?;
+    Ok((AlertMonitor::new(config, alert_sender, Instant::now()), hdl))
+}
+
+/// This host's name, for `--alert-host-id`'s default
+pub fn hostname() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "unknown".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant, SystemTime};
+
+    use crate::alerts::{
+        hostname, AlertCondition, AlertConfig, AlertCounters, AlertMonitor, AlertStatus,
+        AlertWebhookFormat,
+    };
+
+    fn new_monitor(now: Instant) -> AlertMonitor {
+        let (alert_sender, _alert_receiver) = crossbeam_channel::bounded(16);
+        AlertMonitor::new(
+            AlertConfig {
+                webhook_url: "https://hooks.example.com/secret".to_string(),
+                format: AlertWebhookFormat::Json,
+                host_id: "proxy-1".to_string(),
+                no_shreds_after: Duration::from_secs(60),
+                forward_failure_ratio: 0.05,
+                heartbeat_down_after: Some(Duration::from_secs(60)),
+                deduper_saturation: 0.1,
+                cooldown: Duration::from_secs(600),
+            },
+            alert_sender,
+            now,
+        )
+    }
+
+    /// Counters after `secs` seconds of steady traffic, heartbeating every second
+    fn steady_counters(secs: u64) -> AlertCounters {
+        AlertCounters {
+            received_total: secs * 1_000,
+            forwarded_total: secs * 1_000,
+            forward_failed_total: 0,
+            successful_heartbeats_total: secs,
+            unhealthy_destinations: 0,
+            deduper_saturation: 0.01,
+        }
+    }
+
+    fn statuses(alerts: &[crate::alerts::Alert]) -> Vec<(AlertCondition, AlertStatus)> {
+        alerts
+            .iter()
+            .map(|alert| (alert.condition, alert.status))
+            .collect()
+    }
+
+    #[test]
+    fn test_alert_monitor() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut monitor = new_monitor(start);
+        let now_system = SystemTime::now();
+        assert!(monitor
+            .check(steady_counters(0), false, start, now_system)
+            .is_empty());
+        assert!(monitor
+            .check(steady_counters(30), false, at(30), now_system)
+            .is_empty());
+
+        // stream and heartbeats stop
+        assert!(monitor
+            .check(steady_counters(30), false, at(60), now_system)
+            .is_empty());
+        let alerts = monitor.check(steady_counters(30), false, at(90), now_system);
+        assert_eq!(
+            statuses(&alerts),
+            vec![
+                (AlertCondition::NoShreds, AlertStatus::Firing),
+                (AlertCondition::HeartbeatDown, AlertStatus::Firing),
+            ]
+        );
+        assert_eq!(alerts[0].host_id, "proxy-1");
+        assert_eq!(alerts[0].message, "No shreds received for 60s.");
+        assert_eq!(alerts[0].counters.received_total, 30_000);
+        // no repeats within the cooldown
+        assert!(monitor
+            .check(steady_counters(30), false, at(120), now_system)
+            .is_empty());
+
+        // recovers, then a flap within the cooldown isn't sent, nor is its recovery
+        let alerts = monitor.check(steady_counters(150), false, at(150), now_system);
+        assert_eq!(
+            statuses(&alerts),
+            vec![
+                (AlertCondition::NoShreds, AlertStatus::Resolved),
+                (AlertCondition::HeartbeatDown, AlertStatus::Resolved),
+            ]
+        );
+        assert!(monitor
+            .check(steady_counters(150), false, at(210), now_system)
+            .is_empty());
+        assert!(monitor
+            .check(steady_counters(240), false, at(240), now_system)
+            .is_empty());
+
+        // nothing is expected while heartbeats are paused
+        assert!(monitor
+            .check(steady_counters(240), true, at(400), now_system)
+            .is_empty());
+        assert!(monitor
+            .check(steady_counters(240), false, at(420), now_system)
+            .is_empty());
+
+        // reminded once the cooldown elapses while still firing
+        let mut counters = steady_counters(500);
+        counters.unhealthy_destinations = 2;
+        counters.deduper_saturation = 0.2;
+        counters.forward_failed_total = 100_000;
+        let alerts = monitor.check(counters.clone(), false, at(500), now_system);
+        assert_eq!(
+            statuses(&alerts),
+            vec![
+                (AlertCondition::ForwardFailures, AlertStatus::Firing),
+                (AlertCondition::UnhealthyDestinations, AlertStatus::Firing),
+                (AlertCondition::DeduperSaturated, AlertStatus::Firing),
+            ]
+        );
+        let advance = |counters: &mut AlertCounters, failed: u64| {
+            counters.received_total += 1_000;
+            counters.forwarded_total += 1_000;
+            counters.forward_failed_total += failed;
+            counters.successful_heartbeats_total += 1;
+        };
+        advance(&mut counters, 1_000);
+        assert!(monitor
+            .check(counters.clone(), false, at(600), now_system)
+            .is_empty());
+        advance(&mut counters, 0);
+        assert_eq!(
+            statuses(&monitor.check(counters, false, at(1_100), now_system)),
+            vec![
+                (AlertCondition::ForwardFailures, AlertStatus::Resolved),
+                (AlertCondition::UnhealthyDestinations, AlertStatus::Firing),
+                (AlertCondition::DeduperSaturated, AlertStatus::Firing),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_forward_failure_ratio() {
+        let start = Instant::now();
+        let mut monitor = new_monitor(start);
+        let now_system = SystemTime::now();
+        monitor.check(steady_counters(0), false, start, now_system);
+        // 4% failed, under the threshold
+        let mut counters = steady_counters(1);
+        counters.forwarded_total = 960;
+        counters.forward_failed_total = 40;
+        assert!(monitor
+            .check(counters.clone(), false, start, now_system)
+            .is_empty());
+        // only failures since the last check count
+        counters.forwarded_total += 900;
+        counters.forward_failed_total += 100;
+        let alerts = monitor.check(counters, false, start, now_system);
+        assert_eq!(
+            statuses(&alerts),
+            vec![(AlertCondition::ForwardFailures, AlertStatus::Firing)]
+        );
+        assert_eq!(
+            alerts[0].message,
+            "100 of 1000 packets failed to forward since the last check."
+        );
+    }
+
+    #[test]
+    fn test_alert_body() {
+        let start = Instant::now();
+        let mut monitor = new_monitor(start);
+        monitor.check(steady_counters(0), false, start, SystemTime::now());
+        let mut counters = steady_counters(0);
+        counters.unhealthy_destinations = 1;
+        let alert = monitor
+            .check(counters, false, start, SystemTime::now())
+            .remove(0);
+
+        let json = alert.body(AlertWebhookFormat::Json);
+        assert_eq!(json["host_id"], "proxy-1");
+        assert_eq!(json["condition"], "unhealthy_destinations");
+        assert_eq!(json["status"], "firing");
+        assert_eq!(json["counters"]["unhealthy_destinations"], 1);
+        let slack = alert.body(AlertWebhookFormat::Slack);
+        let text = slack["text"].as_str().unwrap();
+        assert!(text.starts_with("[proxy-1] FIRING UnhealthyDestinations: 1 destinations"));
+        assert!(text.contains("unhealthy_destinations=1"));
+        assert_eq!(
+            alert.body(AlertWebhookFormat::Discord)["content"].as_str(),
+            Some(text)
+        );
+        assert!(!hostname().is_empty());
+    }
+}
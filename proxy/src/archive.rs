@@ -0,0 +1,350 @@
+use std::{
+    ops::RangeInclusive,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use crossbeam_channel::{Sender, TrySendError};
+use solana_sdk::clock::Slot;
+
+use crate::{
+    forwarder::ShredMetrics,
+    memory_guard::{BufferKind, HeldBytes},
+};
+
+/// Where to archive shreds and how many slots to keep, from the `archive-*` args
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveConfig {
+    pub path: PathBuf,
+    /// 0 keeps every slot
+    pub retention_slots: u64,
+    pub read_check: bool,
+}
+
+/// Copies deduped shreds from forwarders to the archiver thread, dropping them when it can't keep up so forwarding never waits on disk
+#[derive(Clone)]
+pub struct ArchiveTap {
+    shred_sender: Sender<(Vec<Vec<u8>>, HeldBytes)>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl ArchiveTap {
+    pub fn send(&self, packets: &[&[u8]]) {
+        if packets.is_empty() {
+            return;
+        }
+        let held = self.metrics.memory_guard.hold(
+            BufferKind::Archive,
+            packets.iter().map(|packet| packet.len()).sum(),
+        );
+        match self
+            .shred_sender
+            .try_send((packets.iter().map(|packet| packet.to_vec()).collect(), held))
+        {
+            Ok(()) => {}
+            Err(TrySendError::Full((shreds, _))) | Err(TrySendError::Disconnected((shreds, _))) => {
+                self.metrics
+                    .archive_dropped
+                    .fetch_add(shreds.len() as u64, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Decides which archived slots fall out of retention.
+/// Counts back from the newest slot with a completed data set, so stray shreds for far-off slots can't purge the archive
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+#[derive(Debug, Default)]
+struct RetentionPolicy {
+    retention_slots: u64,
+    /// Lowest slot that may still hold shreds
+    lowest_slot: Option<Slot>,
+    highest_completed_slot: Option<Slot>,
+}
+
+#[cfg_attr(not(feature = "archive"), allow(dead_code))]
+impl RetentionPolicy {
+    fn new(retention_slots: u64) -> Self {
+        Self {
+            retention_slots,
+            ..Self::default()
+        }
+    }
+
+    fn record_inserted(&mut self, slot: Slot) {
+        self.lowest_slot = Some(self.lowest_slot.map_or(slot, |lowest| lowest.min(slot)));
+    }
+
+    fn record_completed(&mut self, slot: Slot) {
+        self.highest_completed_slot = Some(
+            self.highest_completed_slot
+                .map_or(slot, |highest| highest.max(slot)),
+        );
+    }
+
+    /// Returns the slots to purge, if any fell out of retention since the last call
+    fn take_purge_range(&mut self) -> Option<RangeInclusive<Slot>> {
+        if self.retention_slots == 0 {
+            return None;
+        }
+        let lowest_slot = self.lowest_slot?;
+        let first_retained = self
+            .highest_completed_slot?
+            .saturating_sub(self.retention_slots - 1);
+        if lowest_slot >= first_retained {
+            return None;
+        }
+        self.lowest_slot = Some(first_retained);
+        Some(lowest_slot..=first_retained - 1)
+    }
+}
+
+#[cfg(feature = "archive")]
+pub use writer::{check_archive, start_archive_thread};
+
+/// Without the `archive` feature, `archive-path` is rejected when validating args
+#[cfg(not(feature = "archive"))]
+pub fn check_archive(_config: &ArchiveConfig) -> Result<(), crate::ShredstreamProxyError> {
+    Err(archive_feature_error())
+}
+
+/// Without the `archive` feature, `archive-path` is rejected when validating args
+#[cfg(not(feature = "archive"))]
+pub fn start_archive_thread(
+    _config: ArchiveConfig,
+    _metrics: Arc<ShredMetrics>,
+    _exit: Arc<std::sync::atomic::AtomicBool>,
+) -> Result<(ArchiveTap, std::thread::JoinHandle<()>), crate::ShredstreamProxyError> {
+    Err(archive_feature_error())
+}
+
+#[cfg(not(feature = "archive"))]
+fn archive_feature_error() -> crate::ShredstreamProxyError {
+    crate::ShredstreamProxyError::InvalidArguments(
+        "Invalid arguments provided, --archive-path requires building with the `archive` feature."
+            .to_string(),
+    )
+}
+
+#[cfg(feature = "archive")]
+mod writer {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::{Duration, Instant},
+    };
+
+    use crossbeam_channel::RecvTimeoutError;
+    use log::{info, warn};
+    use solana_ledger::{
+        blockstore::{Blockstore, PurgeType},
+        shred::Shred,
+    };
+
+    use crate::{
+        archive::{ArchiveConfig, ArchiveTap, RetentionPolicy},
+        forwarder::ShredMetrics,
+        memory_guard::HeldBytes,
+        ShredstreamProxyError,
+    };
+
+    /// Batches queued for the archiver thread before new ones are dropped
+    const ARCHIVE_CHANNEL_CAPACITY: usize = 1_024;
+    /// How often the archiver wakes to check for exit while idle
+    const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+    /// How often slots that fell out of retention are purged, keeping each purge small
+    const PURGE_INTERVAL: Duration = Duration::from_secs(10);
+
+    fn open_error(config: &ArchiveConfig, e: impl std::fmt::Display) -> ShredstreamProxyError {
+        ShredstreamProxyError::InvalidArguments(format!(
+            "Failed to open --archive-path {:?}: {e}",
+            config.path
+        ))
+    }
+
+    /// Opens the archive and reads every slot's metadata, plus the data shreds of its lowest and highest slots,
+    /// so a corrupt store fails startup instead of the archiver thread
+    pub fn check_archive(config: &ArchiveConfig) -> Result<(), ShredstreamProxyError> {
+        let blockstore = Blockstore::open(&config.path).map_err(|e| open_error(config, e))?;
+        let mut slots = 0u64;
+        let mut slot_range = None;
+        for (slot, _meta) in blockstore
+            .slot_meta_iterator(0)
+            .map_err(|e| open_error(config, e))?
+        {
+            slots += 1;
+            slot_range = Some(slot_range.map_or((slot, slot), |(lowest, _)| (lowest, slot)));
+        }
+        let Some((lowest_slot, highest_slot)) = slot_range else {
+            info!("Archive at {:?} is empty.", config.path);
+            return Ok(());
+        };
+        for slot in [lowest_slot, highest_slot] {
+            blockstore
+                .get_data_shreds_for_slot(slot, 0)
+                .map_err(|e| open_error(config, e))?;
+        }
+        info!(
+            "Archive at {:?} holds {slots} slots from {lowest_slot} to {highest_slot}.",
+            config.path
+        );
+        Ok(())
+    }
+
+    /// Inserts shreds sent through the returned tap into a blockstore at `config.path`, purging slots that fall out of retention.
+    /// Shreds may arrive out of order or for skipped slots, the blockstore keeps whatever it's given
+    pub fn start_archive_thread(
+        config: ArchiveConfig,
+        metrics: Arc<ShredMetrics>,
+        exit: Arc<AtomicBool>,
+    ) -> Result<(ArchiveTap, JoinHandle<()>), ShredstreamProxyError> {
+        let blockstore = Blockstore::open(&config.path).map_err(|e| open_error(&config, e))?;
+        let mut retention = RetentionPolicy::new(config.retention_slots);
+        // resume purging from whatever an earlier run left behind
+        if let Some(highest_slot) = blockstore
+            .highest_slot()
+            .map_err(|e| open_error(&config, e))?
+        {
+            retention.record_inserted(blockstore.lowest_slot());
+            retention.record_completed(highest_slot);
+        }
+        let (shred_sender, shred_receiver) =
+            crossbeam_channel::bounded::<(Vec<Vec<u8>>, HeldBytes)>(ARCHIVE_CHANNEL_CAPACITY);
+        info!(
+            "Archiving received shreds to {:?}, keeping {} slots.",
+            config.path,
+            match config.retention_slots {
+                0 => "all".to_string(),
+                retention_slots => retention_slots.to_string(),
+            }
+        );
+
+        let archiver_metrics = metrics.clone();
+        let hdl = Builder::new()
+            .name("ssPxyArchive".to_string())
+            .spawn(move || {
+                let metrics = archiver_metrics;
+                let mut last_purge = Instant::now();
+                // drain queued shreds before exiting
+                while !exit.load(Ordering::Relaxed) || !shred_receiver.is_empty() {
+                    match shred_receiver.recv_timeout(EXIT_CHECK_INTERVAL) {
+                        Ok(queued) => {
+                            // batch whatever else is queued into a single write, counted until written
+                            let (payloads, _held): (Vec<_>, Vec<_>) = std::iter::once(queued)
+                                .chain(shred_receiver.try_iter())
+                                .unzip();
+                            insert(
+                                &blockstore,
+                                payloads.into_iter().flatten(),
+                                &mut retention,
+                                &metrics,
+                            );
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_purge.elapsed() >= PURGE_INTERVAL {
+                        last_purge = Instant::now();
+                        if let Some(purge_range) = retention.take_purge_range() {
+                            blockstore.purge_slots(
+                                *purge_range.start(),
+                                *purge_range.end(),
+                                PurgeType::Exact,
+                            );
+                            info!("Purged archived slots {purge_range:?}.");
+                        }
+                    }
+                }
+                info!("Exiting archiver thread.");
+            })?;
+
+        Ok((
+            ArchiveTap {
+                shred_sender,
+                metrics,
+            },
+            hdl,
+        ))
+    }
+
+    fn insert(
+        blockstore: &Blockstore,
+        payloads: impl Iterator<Item = Vec<u8>>,
+        retention: &mut RetentionPolicy,
+        metrics: &ShredMetrics,
+    ) {
+        let mut invalid = 0u64;
+        let shreds = payloads
+            .filter_map(|payload| match Shred::new_from_serialized_shred(payload) {
+                Ok(shred) => Some(shred),
+                Err(_) => {
+                    invalid += 1;
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        metrics
+            .archive_insert_failed
+            .fetch_add(invalid, Ordering::Relaxed);
+        if shreds.is_empty() {
+            return;
+        }
+        let num_shreds = shreds.len() as u64;
+        shreds
+            .iter()
+            .for_each(|shred| retention.record_inserted(shred.slot()));
+        // untrusted, so shreds already archived or invalid for their slot are skipped
+        match blockstore.insert_shreds(shreds, None, false) {
+            Ok(completed_data_sets) => {
+                metrics
+                    .archive_inserted
+                    .fetch_add(num_shreds, Ordering::Relaxed);
+                completed_data_sets
+                    .iter()
+                    .for_each(|data_set| retention.record_completed(data_set.slot));
+            }
+            Err(e) => {
+                warn!("Failed to archive {num_shreds} shreds. Error: {e}");
+                metrics
+                    .archive_insert_failed
+                    .fetch_add(num_shreds, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::RetentionPolicy;
+
+    #[test]
+    fn test_retention_policy() {
+        let mut retention = RetentionPolicy::new(10);
+        assert_eq!(retention.take_purge_range(), None);
+
+        // nothing completed yet, so nothing is out of retention
+        retention.record_inserted(100);
+        assert_eq!(retention.take_purge_range(), None);
+        retention.record_completed(105);
+        assert_eq!(retention.take_purge_range(), None);
+
+        // 10 slots are kept, up to and including the newest completed
+        retention.record_completed(112);
+        assert_eq!(retention.take_purge_range(), Some(100..=102));
+        assert_eq!(retention.take_purge_range(), None);
+
+        // out of order shreds for an older slot are purged next time
+        retention.record_inserted(90);
+        retention.record_completed(108);
+        assert_eq!(retention.take_purge_range(), Some(90..=102));
+
+        // 0 keeps every slot
+        let mut retention = RetentionPolicy::new(0);
+        retention.record_inserted(1);
+        retention.record_completed(1_000);
+        assert_eq!(retention.take_purge_range(), None);
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{self, Builder},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use rand::Rng;
+use solana_sdk::{clock::Slot, packet::PACKET_DATA_SIZE};
+use solana_streamer::sendmmsg::{batch_send, SendPktsError};
+
+use crate::{
+    encryption::{self, EncryptionKeys},
+    shred::{self, SIZE_OF_DATA_SHRED_HEADERS},
+    socket, ShredstreamProxyError,
+};
+
+/// Synthetic shreds start at this slot, far enough from zero to look like mainnet
+const FIRST_SLOT: Slot = 300_000_000;
+/// Synthetic shreds per slot, roughly a busy mainnet slot
+const SHREDS_PER_SLOT: u64 = 1_000;
+/// Max packets per `sendmmsg` call
+const MAX_SEND_BATCH_SIZE: usize = 64;
+/// Longest pause between rate checks, bounds how late exit is noticed
+const MAX_PACING_SLEEP: Duration = Duration::from_millis(1);
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct BenchArgs {
+    /// IP:Port to send synthetic shreds to, usually a proxy's `src-bind-addr`:`src-bind-port`.
+    #[arg(long, env, default_value = "127.0.0.1:20000")]
+    pub target: SocketAddr,
+
+    /// Packets per second to send across all threads. 0 sends as fast as possible.
+    #[arg(long, env, default_value_t = 100_000)]
+    pub pps: u64,
+
+    /// How long to send for, eg. `60s`, `500ms` or `5m`. Bare numbers are seconds.
+    #[arg(long, env, default_value = "60s", value_parser = parse_duration)]
+    pub duration: Duration,
+
+    /// Size of each packet in bytes, including the shred headers.
+    #[arg(long, env, default_value_t = 1228)]
+    pub payload_size: usize,
+
+    /// Number of threads generating and sending packets.
+    #[arg(long, env, default_value_t = 4)]
+    pub num_threads: usize,
+
+    /// Fraction of packets that repeat the previous shred, to exercise the deduper. Eg. `0.5` sends every shred twice.
+    /// Otherwise every packet is a unique (slot, index).
+    #[arg(long, env, default_value_t = 0.0)]
+    pub dup_ratio: f64,
+
+    /// File holding a hex-encoded 32-byte key. Seals every packet as an `enc://` destination would, to measure the per-packet cost.
+    /// Point at a proxy started with `--decrypt-key-file` to measure unsealing.
+    #[arg(long, env)]
+    pub encrypt_key_file: Option<PathBuf>,
+}
+
+/// Parses durations such as `60s`, `500ms`, `5m` or `1h`. Bare numbers are seconds
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let duration = duration.trim();
+    let split = duration
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid duration {duration:?}, expected eg. `60s` or `500ms`."))?;
+    let secs = match unit {
+        "ms" => value / 1_000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3_600.0,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit {unit:?}, expected `ms`, `s`, `m` or `h`."
+            ))
+        }
+    };
+    Duration::try_from_secs_f64(secs).map_err(|e| format!("Invalid duration {duration:?}: {e}"))
+}
+
+/// Results of a bench run
+#[derive(Debug)]
+pub struct BenchReport {
+    pub target: SocketAddr,
+    pub target_pps: u64,
+    pub sent: u64,
+    /// Packets repeating an earlier shred, included in `sent`
+    pub duplicates: u64,
+    pub send_errors: u64,
+    pub elapsed: Duration,
+    /// User and system time of the whole process, None where unavailable
+    pub cpu_time: Option<Duration>,
+    /// Time spent sealing across all threads, None unless `--encrypt-key-file` is set
+    pub seal_time: Option<Duration>,
+}
+
+impl BenchReport {
+    pub fn achieved_pps(&self) -> f64 {
+        self.sent as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Mean time to seal one packet, including those that then failed to send
+    pub fn seal_time_per_packet(&self) -> Option<Duration> {
+        self.seal_time
+            .map(|seal_time| seal_time / (self.sent + self.send_errors).max(1) as u32)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target_pps = match self.target_pps {
+            0 => "unlimited".to_string(),
+            target_pps => target_pps.to_string(),
+        };
+        writeln!(
+            f,
+            "Sent {} packets ({} duplicates) to {} in {:.2?}.",
+            self.sent, self.duplicates, self.target, self.elapsed
+        )?;
+        writeln!(
+            f,
+            "Achieved {:.0} packets/sec, target {target_pps}.",
+            self.achieved_pps()
+        )?;
+        writeln!(f, "Send errors: {}.", self.send_errors)?;
+        if let Some(seal_time) = self.seal_time_per_packet() {
+            writeln!(f, "Sealing: {seal_time:.2?} per packet.")?;
+        }
+        match self.cpu_time {
+            Some(cpu_time) => write!(
+                f,
+                "CPU time: {cpu_time:.2?}, {:.0}% of one core.",
+                cpu_time.as_secs_f64() / self.elapsed.as_secs_f64().max(f64::EPSILON) * 100.0
+            ),
+            None => write!(f, "CPU time: unavailable."),
+        }
+    }
+}
+
+/// Picks the (slot, index) of each generated packet
+struct ShredSequence {
+    dup_ratio: f64,
+    /// Accumulates `dup_ratio` per packet, a duplicate is sent each time it reaches 1
+    dups_owed: f64,
+    last: Option<(Slot, u32)>,
+}
+
+impl ShredSequence {
+    fn new(dup_ratio: f64) -> Self {
+        Self {
+            dup_ratio,
+            dups_owed: 0.0,
+            last: None,
+        }
+    }
+
+    /// Returns the (slot, index) for the packet numbered `seq` across all threads and whether it repeats the previous one
+    fn next(&mut self, seq: u64) -> (Slot, u32, bool) {
+        self.dups_owed += self.dup_ratio;
+        if let Some((slot, index)) = self.last.filter(|_| self.dups_owed >= 1.0) {
+            self.dups_owed -= 1.0;
+            return (slot, index, true);
+        }
+        let (slot, index) = (
+            FIRST_SLOT + seq / SHREDS_PER_SLOT,
+            (seq % SHREDS_PER_SLOT) as u32,
+        );
+        self.last = Some((slot, index));
+        (slot, index, false)
+    }
+}
+
+fn validate(args: &BenchArgs) -> Result<(), ShredstreamProxyError> {
+    let invalid = |e: String| Err(ShredstreamProxyError::InvalidArguments(e));
+    if !(SIZE_OF_DATA_SHRED_HEADERS..=PACKET_DATA_SIZE).contains(&args.payload_size) {
+        return invalid(format!(
+            "Invalid arguments provided, --payload-size must be between {SIZE_OF_DATA_SHRED_HEADERS} and {PACKET_DATA_SIZE}."
+        ));
+    }
+    if args.num_threads == 0 {
+        return invalid(
+            "Invalid arguments provided, --num-threads must be greater than 0.".to_string(),
+        );
+    }
+    if !(0.0..=1.0).contains(&args.dup_ratio) {
+        return invalid(
+            "Invalid arguments provided, --dup-ratio must be between 0 and 1.".to_string(),
+        );
+    }
+    if let Some(path) = &args.encrypt_key_file {
+        if let Err(e) = encryption::read_key(path) {
+            return invalid(format!("Invalid arguments provided, {e}"));
+        }
+    }
+    Ok(())
+}
+
+/// Sends synthetic data shreds to `args.target` until `args.duration` elapses or exit is set, then reports throughput
+pub fn run_bench(
+    args: &BenchArgs,
+    exit: Arc<AtomicBool>,
+) -> Result<BenchReport, ShredstreamProxyError> {
+    validate(args)?;
+    let key_file = args
+        .encrypt_key_file
+        .as_ref()
+        .map(|path| EncryptionKeys::default().key_file(path));
+    info!(
+        "Sending {}-byte shreds to {} for {:?} from {} threads.",
+        args.payload_size, args.target, args.duration, args.num_threads
+    );
+
+    let sequence = Arc::new(AtomicU64::new(0));
+    let sent = Arc::new(AtomicU64::new(0));
+    let duplicates = Arc::new(AtomicU64::new(0));
+    let send_errors = Arc::new(AtomicU64::new(0));
+    let seal_nanos = Arc::new(AtomicU64::new(0));
+    // each thread sends an equal share of the rate
+    let thread_pps = args.pps as f64 / args.num_threads as f64;
+    let cpu_start = cpu_time();
+    let start = Instant::now();
+    let end = start + args.duration;
+
+    let hdls = (0..args.num_threads)
+        .map(|thread_id| {
+            let socket = socket::bind_send_socket()?;
+            let send_addr = socket::send_addr(
+                socket.local_addr().is_ok_and(|addr| addr.is_ipv6()),
+                args.target,
+            );
+            let (sequence, sent, duplicates, send_errors, seal_nanos, key_file, exit) = (
+                sequence.clone(),
+                sent.clone(),
+                duplicates.clone(),
+                send_errors.clone(),
+                seal_nanos.clone(),
+                key_file.clone(),
+                exit.clone(),
+            );
+            let payload_size = args.payload_size;
+            let mut shred_sequence = ShredSequence::new(args.dup_ratio);
+            // about a millisecond of packets per batch so low rates aren't bursty
+            let batch_size = match args.pps {
+                0 => MAX_SEND_BATCH_SIZE,
+                _ => ((thread_pps / 1_000.0) as usize).clamp(1, MAX_SEND_BATCH_SIZE),
+            };
+            Builder::new()
+                .name(format!("ssPxyBench{thread_id}"))
+                .spawn(move || {
+                    // random data once, only the headers change per packet
+                    let mut payload = vec![0u8; payload_size];
+                    rand::thread_rng().fill(&mut payload[..]);
+                    let mut batch = vec![payload; batch_size];
+                    let mut sealed_batch = vec![Vec::new(); batch_size];
+                    let mut thread_sent = 0u64;
+                    let mut warned = false;
+                    while !exit.load(Ordering::Relaxed) {
+                        let now = Instant::now();
+                        if now >= end {
+                            break;
+                        }
+                        if thread_pps > 0.0 {
+                            let due = start + Duration::from_secs_f64(thread_sent as f64 / thread_pps);
+                            if now < due {
+                                thread::sleep((due - now).min(MAX_PACING_SLEEP));
+                                continue;
+                            }
+                        }
+
+                        let first_seq = sequence.fetch_add(batch_size as u64, Ordering::Relaxed);
+                        let mut batch_duplicates = 0;
+                        for (seq, packet) in (first_seq..).zip(batch.iter_mut()) {
+                            let (slot, index, duplicate) = shred_sequence.next(seq);
+                            batch_duplicates += duplicate as u64;
+                            shred::write_data_shred_headers(packet, slot, index);
+                        }
+                        let packets = match &key_file {
+                            Some(key_file) => {
+                                let seal_start = Instant::now();
+                                for (packet, sealed) in batch.iter().zip(sealed_batch.iter_mut()) {
+                                    sealed.clear();
+                                    key_file.seal(packet, sealed);
+                                }
+                                seal_nanos.fetch_add(
+                                    seal_start.elapsed().as_nanos() as u64,
+                                    Ordering::Relaxed,
+                                );
+                                &sealed_batch
+                            }
+                            None => &batch,
+                        };
+                        let packets_with_dest = packets
+                            .iter()
+                            .map(|packet| (packet.as_slice(), &send_addr))
+                            .collect::<Vec<_>>();
+                        let num_failed = match batch_send(&socket, &packets_with_dest) {
+                            Ok(()) => 0,
+                            Err(SendPktsError::IoError(e, num_failed)) => {
+                                // only logged once per thread, a closed port fails every batch
+                                if !warned {
+                                    warned = true;
+                                    warn!("Failed to send to {send_addr}, counting further errors. Error: {e}");
+                                }
+                                num_failed.min(batch_size)
+                            }
+                        };
+                        thread_sent += batch_size as u64;
+                        sent.fetch_add((batch_size - num_failed) as u64, Ordering::Relaxed);
+                        send_errors.fetch_add(num_failed as u64, Ordering::Relaxed);
+                        duplicates.fetch_add(batch_duplicates, Ordering::Relaxed);
+                    }
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for hdl in hdls {
+        hdl.join().expect("bench thread panicked");
+    }
+
+    let elapsed = start.elapsed();
+    Ok(BenchReport {
+        target: args.target,
+        target_pps: args.pps,
+        sent: sent.load(Ordering::Relaxed),
+        duplicates: duplicates.load(Ordering::Relaxed),
+        send_errors: send_errors.load(Ordering::Relaxed),
+        elapsed,
+        cpu_time: cpu_time()
+            .zip(cpu_start)
+            .map(|(cpu_end, cpu_start)| cpu_end.saturating_sub(cpu_start)),
+        seal_time: key_file.map(|_| Duration::from_nanos(seal_nanos.load(Ordering::Relaxed))),
+    })
+}
+
+/// User and system CPU time used by the process so far
+#[cfg(unix)]
+fn cpu_time() -> Option<Duration> {
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::uninit();
+    // SAFETY: getrusage only writes to `usage`
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, usage.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: initialized by the successful call above
+    let usage = unsafe { usage.assume_init() };
+    let to_duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    Some(to_duration(usage.ru_utime) + to_duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_time() -> Option<Duration> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{SocketAddr, UdpSocket},
+        path::PathBuf,
+        sync::{atomic::AtomicBool, Arc},
+        time::Duration,
+    };
+
+    use solana_sdk::packet::PACKET_DATA_SIZE;
+
+    use crate::{
+        bench::{parse_duration, run_bench, BenchArgs, ShredSequence, FIRST_SLOT, SHREDS_PER_SLOT},
+        encryption::{EncryptionKeys, MAX_SEALED_PACKET_SIZE, NONCE_LEN, TAG_LEN},
+        shred,
+    };
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3_600)));
+        assert_eq!(parse_duration("1.5"), Ok(Duration::from_millis(1_500)));
+        assert!(parse_duration("60 seconds").is_err());
+        assert!(parse_duration("s").is_err());
+        assert!(parse_duration("-1s").is_err());
+    }
+
+    #[test]
+    fn test_shred_sequence() {
+        let mut unique = ShredSequence::new(0.0);
+        assert_eq!(unique.next(0), (FIRST_SLOT, 0, false));
+        assert_eq!(unique.next(1), (FIRST_SLOT, 1, false));
+        assert_eq!(unique.next(SHREDS_PER_SLOT), (FIRST_SLOT + 1, 0, false));
+
+        // half the packets repeat the shred before them
+        let mut half_dups = ShredSequence::new(0.5);
+        let keys = (0..6).map(|seq| half_dups.next(seq)).collect::<Vec<_>>();
+        assert_eq!(
+            keys,
+            vec![
+                (FIRST_SLOT, 0, false),
+                (FIRST_SLOT, 0, true),
+                (FIRST_SLOT, 2, false),
+                (FIRST_SLOT, 2, true),
+                (FIRST_SLOT, 4, false),
+                (FIRST_SLOT, 4, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_bench() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let args = BenchArgs {
+            target: receiver.local_addr().unwrap(),
+            pps: 200,
+            duration: Duration::from_millis(100),
+            payload_size: 1228,
+            num_threads: 2,
+            dup_ratio: 0.5,
+            encrypt_key_file: None,
+        };
+        let report = run_bench(&args, Arc::new(AtomicBool::new(false))).unwrap();
+        assert!(report.sent > 0 && report.sent <= 40, "{report:?}");
+        assert_eq!(report.send_errors, 0);
+        // each thread alternates unique and duplicate shreds
+        assert!(report.sent.abs_diff(report.duplicates * 2) <= args.num_threads as u64);
+        assert!(report.seal_time.is_none());
+
+        let mut buf = [0u8; 2048];
+        let mut received = 0;
+        while let Ok((len, _)) = receiver.recv_from(&mut buf) {
+            assert_eq!(len, 1228);
+            assert!(shred::get_data_shred(&buf[..len]).is_some());
+            received += 1;
+            if received == report.sent {
+                break;
+            }
+        }
+        assert_eq!(received, report.sent);
+
+        let invalid = BenchArgs {
+            target: SocketAddr::from(([127, 0, 0, 1], 0)),
+            dup_ratio: 1.5,
+            ..args.clone()
+        };
+        assert!(run_bench(&invalid, Arc::new(AtomicBool::new(false))).is_err());
+        let missing_key = BenchArgs {
+            encrypt_key_file: Some(PathBuf::from("/nonexistent/bench.key")),
+            ..args
+        };
+        assert!(run_bench(&missing_key, Arc::new(AtomicBool::new(false))).is_err());
+    }
+
+    #[test]
+    fn test_run_bench_sealed() {
+        let key_path =
+            std::env::temp_dir().join(format!("test_run_bench_sealed_{}.key", std::process::id()));
+        fs::write(&key_path, "22".repeat(32)).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let args = BenchArgs {
+            target: receiver.local_addr().unwrap(),
+            pps: 100,
+            duration: Duration::from_millis(100),
+            payload_size: 1228,
+            num_threads: 1,
+            dup_ratio: 0.0,
+            encrypt_key_file: Some(key_path.clone()),
+        };
+        let report = run_bench(&args, Arc::new(AtomicBool::new(false))).unwrap();
+        assert!(report.sent > 0, "{report:?}");
+        assert!(report.seal_time_per_packet().is_some());
+
+        let key_file = EncryptionKeys::default().key_file(&key_path);
+        let mut sealed = [0u8; MAX_SEALED_PACKET_SIZE];
+        let mut packet = [0u8; PACKET_DATA_SIZE];
+        let len = receiver.recv(&mut sealed).unwrap();
+        assert_eq!(len, 1228 + NONCE_LEN + TAG_LEN);
+        assert_eq!(key_file.open(&sealed[..len], &mut packet), Some(1228));
+        assert!(shred::get_data_shred(&packet[..1228]).is_some());
+        fs::remove_file(&key_path).unwrap();
+    }
+}
@@ -0,0 +1,82 @@
+//! Build details embedded by `build.rs`, to tell which build and features each proxy in a fleet runs
+
+use log::info;
+use serde::Serialize;
+
+use crate::metrics_backend::datapoint_info;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short commit hash, `unknown` if built outside a git checkout without `GIT_HASH` set
+pub const GIT_HASH: &str = env!("SHREDSTREAM_PROXY_GIT_HASH");
+/// UTC, from `SOURCE_DATE_EPOCH` if set
+pub const BUILD_TIMESTAMP: &str = env!("SHREDSTREAM_PROXY_BUILD_TIMESTAMP");
+pub const RUSTC_VERSION: &str = env!("SHREDSTREAM_PROXY_RUSTC_VERSION");
+/// Enabled cargo features, comma separated
+pub const FEATURES: &str = env!("SHREDSTREAM_PROXY_FEATURES");
+
+/// Printed by `--version --verbose`
+pub const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ngit hash: ",
+    env!("SHREDSTREAM_PROXY_GIT_HASH"),
+    "\nbuild timestamp: ",
+    env!("SHREDSTREAM_PROXY_BUILD_TIMESTAMP"),
+    "\nrustc: ",
+    env!("SHREDSTREAM_PROXY_RUSTC_VERSION"),
+    "\nfeatures: ",
+    env!("SHREDSTREAM_PROXY_FEATURES"),
+);
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_timestamp: &'static str,
+    pub rustc_version: &'static str,
+    pub features: Vec<&'static str>,
+}
+
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_hash: GIT_HASH,
+        build_timestamp: BUILD_TIMESTAMP,
+        rustc_version: RUSTC_VERSION,
+        features: FEATURES.split(',').filter(|f| !f.is_empty()).collect(),
+    }
+}
+
+/// Logs the build details and reports them once as the `build_info` datapoint, with the values as tags
+pub fn report_build_info() {
+    info!(
+        "Starting shredstream proxy {VERSION} ({GIT_HASH}), built {BUILD_TIMESTAMP} with {RUSTC_VERSION}, features: [{FEATURES}]."
+    );
+    datapoint_info!("shredstream_proxy-build_info",
+        "version" => VERSION,
+        "git_hash" => GIT_HASH,
+        "build_timestamp" => BUILD_TIMESTAMP,
+        "rustc_version" => RUSTC_VERSION,
+        "features" => FEATURES,
+        ("count", 1, i64),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build_info::{build_info, LONG_VERSION, VERSION};
+
+    #[test]
+    fn test_build_info() {
+        let info = build_info();
+        assert_eq!(info.version, VERSION);
+        assert!(!info.git_hash.is_empty());
+        assert!(info.rustc_version.starts_with("rustc ") || info.rustc_version == "unknown");
+        assert!(info.features.iter().all(|feature| !feature.is_empty()));
+        assert!(LONG_VERSION.starts_with(VERSION));
+        assert!(LONG_VERSION.contains(&format!("git hash: {}", info.git_hash)));
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["version"], VERSION);
+        assert!(json["features"].is_array());
+    }
+}
@@ -0,0 +1,2227 @@
+use std::{
+    collections::HashSet,
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{sleep, Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use crossbeam_channel::{Receiver, Sender};
+use log::*;
+use solana_sdk::signature::{Keypair, Signer};
+use solana_streamer::streamer::StreamerReceiveStats;
+
+use crate::{
+    admin,
+    admin_grpc::{self, AdminGrpcTls},
+    alert_config, alerts, archive, archive_config, broadcast_shutdown, build_info,
+    chaos::{self, ChaosInjector},
+    chaos_config, conflict_detector_config, conflicts, deshred, encryption, encryption_key_files,
+    endpoint_discovery, events, events_config,
+    forwarder::{
+        self, DestinationBlocklist, DestinationCap, DestinationSources, PacketFilter, PacketSource,
+        SendSocketOptions, ShredDeduper, ShredMetrics, SourceAllowlist, TraceShredSampler,
+    },
+    get_public_ip_with_retry, grpc_channel_config,
+    health::{self, HealthCheckConfig},
+    heartbeat::{self, BlockEngineFailover},
+    kafka, kafka_config, listener,
+    metrics_backend::{
+        new_metrics_backend, set_metrics_backend, MetricsBackend, MetricsBackendKind,
+    },
+    mirror::Mirror,
+    otel,
+    pcap::{self, PcapRotation},
+    privileges,
+    probes::{HeartbeatProbeConfig, Probes},
+    prometheus::{self, ReceiveStatsTotals},
+    quic::QuicSink,
+    rate_limit, read_auth_keypair, regions,
+    reload::{self, ReloadableState},
+    replay::{self, ReplayConfig},
+    rpc_discovery_config,
+    shard::ShardMembers,
+    slot_latency::{self, SlotLatencyTap},
+    slow_send_threshold,
+    socket::{self, RebindPolicy},
+    spill::{self, SpillStore},
+    spill_config, stake_discovery,
+    subscriber::{self, ShredSubscriber},
+    supervisor::{RestartPolicy, Supervisor},
+    systemd::{self, ThreadLiveness},
+    token_authenticator::TokenCache,
+    trace_shred_sample_rate,
+    tunnel::TunnelSink,
+    tunnel_config,
+    unix::{self, UnixSink},
+    validate::{self, ValidationReport},
+    validate_block_engine_args, validate_common_args, validate_core_affinity, validate_dscp,
+    validate_egress, validate_has_destinations, validate_region_ports, validator_resolver_config,
+    validators, watchdog, ws, AddressFamily, CommonArgs, ConfigFormat, ProxyConfig, ReplayArgs,
+    ShredstreamArgs, ShredstreamProxyError,
+};
+
+/// How often [ShredstreamProxy::join_with_deadline] checks whether threads have exited
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long receive threads get to start reading before startup fails, instead of heartbeating a port nobody reads
+const LISTEN_READY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the proxy gets packets to forward from, matching the CLI subcommands
+#[derive(Clone, Debug)]
+enum ProxyMode {
+    ForwardOnly(CommonArgs),
+    Shredstream(ShredstreamArgs),
+    Replay(ReplayArgs),
+}
+
+impl ProxyMode {
+    fn common_args(&self) -> &CommonArgs {
+        match self {
+            ProxyMode::ForwardOnly(args) => args,
+            ProxyMode::Shredstream(args) => &args.common_args,
+            ProxyMode::Replay(args) => &args.common_args,
+        }
+    }
+
+    /// Args as loaded from a config file, `None` for modes that can't be configured by file
+    fn proxy_config(&self) -> Option<ProxyConfig> {
+        match self {
+            ProxyMode::ForwardOnly(args) => Some(ProxyConfig::ForwardOnly(args.clone())),
+            ProxyMode::Shredstream(args) => Some(ProxyConfig::Shredstream(args.clone())),
+            ProxyMode::Replay(_) => None,
+        }
+    }
+}
+
+/// What [ShredstreamProxyBuilder::check_startup] read or derived from the args
+struct StartupState {
+    auth_keypair: Option<Arc<Keypair>>,
+    /// (port, region received on it) for each listen port
+    listen_ports: Vec<(u16, Option<String>)>,
+    /// Installed process wide once the proxy is built
+    metrics_backend: Option<Arc<dyn MetricsBackend>>,
+}
+
+struct ConfigReload {
+    path: PathBuf,
+    format: Option<ConfigFormat>,
+    reload_receiver: Receiver<()>,
+}
+
+/// Configures a [ShredstreamProxy] with the same arguments as the CLI subcommands.
+/// Unlike the binary, the proxy never installs signal handlers or a panic hook, so a panic in a forwarder thread doesn't shut it down.
+/// When `NOTIFY_SOCKET` is set, readiness, watchdog pings, and shutdown are reported to systemd.
+pub struct ShredstreamProxyBuilder {
+    mode: ProxyMode,
+    config_reload: Option<ConfigReload>,
+    key_reload: Option<Receiver<()>>,
+    shutdown: Option<(Arc<AtomicBool>, Sender<()>, Receiver<()>)>,
+    subscribers: Vec<ShredSubscriber>,
+}
+
+impl ShredstreamProxyBuilder {
+    /// Forwards anything received on `src_bind_addr`:`src_bind_port` without requesting shreds from Jito
+    pub fn forward_only(args: CommonArgs) -> Self {
+        Self::new(ProxyMode::ForwardOnly(args))
+    }
+
+    /// Requests shreds from Jito and forwards them
+    pub fn shredstream(args: ShredstreamArgs) -> Self {
+        Self::new(ProxyMode::Shredstream(args))
+    }
+
+    /// Replays a pcap capture through the forwarder, shutting down once it's exhausted
+    pub fn replay(args: ReplayArgs) -> Self {
+        Self::new(ProxyMode::Replay(args))
+    }
+
+    fn new(mode: ProxyMode) -> Self {
+        Self {
+            mode,
+            config_reload: None,
+            key_reload: None,
+            shutdown: None,
+            subscribers: vec![],
+        }
+    }
+
+    /// Re-reads the config file at `path` whenever `reload_receiver` is notified, applying fields that don't need a restart.
+    /// Only applies to [Self::shredstream] and [Self::forward_only], whose args should have been loaded from the same file.
+    pub fn config_reload(
+        mut self,
+        path: PathBuf,
+        format: Option<ConfigFormat>,
+        reload_receiver: Receiver<()>,
+    ) -> Self {
+        self.config_reload = Some(ConfigReload {
+            path,
+            format,
+            reload_receiver,
+        });
+        self
+    }
+
+    /// Re-reads the key files of `enc://` destinations and `decrypt-key-file` whenever `reload_receiver` is notified, rotating keys without a restart.
+    /// Config reload re-reads them too, so this is only needed without [Self::config_reload]
+    pub fn key_reload(mut self, reload_receiver: Receiver<()>) -> Self {
+        self.key_reload = Some(reload_receiver);
+        self
+    }
+
+    /// Shares the exit flag and shutdown channel with the embedding process, such as with its signal handler.
+    /// The channel needs room for a message per thread, see [crate::broadcast_shutdown]. Created by the proxy if not set.
+    pub fn shutdown_signal(
+        mut self,
+        exit: Arc<AtomicBool>,
+        shutdown_sender: Sender<()>,
+        shutdown_receiver: Receiver<()>,
+    ) -> Self {
+        self.shutdown = Some((exit, shutdown_sender, shutdown_receiver));
+        self
+    }
+
+    /// Hands every deduplicated shred to `subscriber`, in addition to the destinations.
+    /// Destinations aren't required once there's a subscriber
+    #[cfg(feature = "subscriber")]
+    pub fn subscribe(mut self, subscriber: ShredSubscriber) -> Self {
+        self.subscribers.push(subscriber);
+        self
+    }
+
+    /// Runs the checks [Self::build] does, then reports the destinations resolved and, with `probe_timeout`, whether
+    /// the block engine, auth, and discovery urls accept connections. Starts no threads.
+    pub fn validate(&self, probe_timeout: Option<Duration>) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        self.check_startup(&mut report);
+        let args = self.mode.common_args();
+        for (addr, hostname_port) in &args.dest_ip_ports {
+            report.record(
+                format!("destination {hostname_port}"),
+                Ok(format!("resolved to {addr}")),
+            );
+        }
+        let Some(probe_timeout) = probe_timeout else {
+            return report;
+        };
+        let mut probe_urls = vec![];
+        if let ProxyMode::Shredstream(shredstream_args) = &self.mode {
+            probe_urls.extend(
+                shredstream_args
+                    .block_engine_url
+                    .iter()
+                    .map(|url| ("block engine", url)),
+            );
+            probe_urls.extend(shredstream_args.auth_url.iter().map(|url| ("auth", url)));
+        }
+        probe_urls.extend(
+            args.endpoint_discovery_url
+                .iter()
+                .map(|url| ("endpoint discovery", url)),
+        );
+        for (kind, url) in probe_urls {
+            // the rest of the url may hold credentials
+            let name = match validate::url_host(url) {
+                Ok(host) => format!("{kind} {host}"),
+                Err(_) => format!("{kind} url"),
+            };
+            report.record(
+                name,
+                validate::probe_url(url, probe_timeout).map(|addr| format!("connected to {addr}")),
+            );
+        }
+        report
+    }
+
+    /// Files opened after dropping privileges, by the option setting them, so they must resolve inside `chroot`
+    fn paths_opened_after_chroot(&self) -> Vec<(&'static str, PathBuf)> {
+        let args = self.mode.common_args();
+        let mut paths = vec![];
+        if let Some(config_reload) = &self.config_reload {
+            paths.push(("config file", config_reload.path.clone()));
+        }
+        // re-read on reload to rotate keys
+        paths.extend(
+            encryption_key_files(args)
+                .into_iter()
+                .map(|key_file| ("key file", key_file)),
+        );
+        let token_cache_file = match &self.mode {
+            ProxyMode::Shredstream(shredstream_args) => &shredstream_args.token_cache_file,
+            _ => &None,
+        };
+        paths.extend(
+            [
+                ("--spill-dir", &args.spill_dir),
+                ("--record-pcap", &args.record_pcap),
+                ("--archive-path", &args.archive_path),
+                ("--allowed-source-ips-file", &args.allowed_source_ips_file),
+                ("--conflicting-shreds-dir", &args.conflicting_shreds_dir),
+                (
+                    "--endpoint-discovery-state-file",
+                    &args.endpoint_discovery_state_file,
+                ),
+                ("--crash-report-file", &args.crash_report_file),
+                // read on the first tls:// connection
+                ("--tunnel-tls-ca-cert", &args.tunnel_tls_ca_cert),
+                ("--tunnel-tls-client-cert", &args.tunnel_tls_client_cert),
+                ("--tunnel-tls-client-key", &args.tunnel_tls_client_key),
+                ("--token-cache-file", token_cache_file),
+            ]
+            .into_iter()
+            .filter_map(|(option, path)| Some((option, path.clone()?))),
+        );
+        paths
+    }
+
+    /// Whether hostnames are resolved after dropping privileges, when re-resolving destinations or reconnecting
+    /// to the block engine or endpoint discovery
+    fn resolves_hostnames(&self) -> bool {
+        let args = self.mode.common_args();
+        let is_domain_url =
+            |url: &String| reqwest::Url::parse(url).is_ok_and(|url| url.domain().is_some());
+        let dest_hostnames = args.dest_resolve_interval_secs > 0
+            && args.dest_ip_ports.iter().any(|(_, hostname_port)| {
+                let (address, _options) = rate_limit::split_dest_options(hostname_port);
+                let host_port = address
+                    .split_once("://")
+                    .map_or(address, |(_, host_port)| host_port);
+                !address.starts_with(unix::UNIX_SCHEME) && host_port.parse::<SocketAddr>().is_err()
+            });
+        let url_hostnames = match &self.mode {
+            ProxyMode::Shredstream(shredstream_args) => shredstream_args
+                .block_engine_url
+                .iter()
+                .chain(&shredstream_args.auth_url)
+                .any(is_domain_url),
+            _ => false,
+        };
+        dest_hostnames || url_hostnames || args.endpoint_discovery_url.iter().any(is_domain_url)
+    }
+
+    /// Validates the arguments and reads the auth keypair, recording each check in `report`.
+    /// Shared by [Self::build] and [Self::validate] so a dry run checks exactly what startup does
+    fn check_startup(&self, report: &mut ValidationReport) -> StartupState {
+        let args = self.mode.common_args();
+        if self.subscribers.is_empty() {
+            report.check("destinations", validate_has_destinations(args));
+        }
+        report.check("common args", validate_common_args(args));
+
+        let mut auth_keypair = None;
+        let mut listen_ports = vec![(args.src_bind_port, None)];
+        match &self.mode {
+            ProxyMode::Shredstream(shredstream_args) => {
+                report.check(
+                    "block engine args",
+                    validate_block_engine_args(shredstream_args),
+                );
+                if shredstream_args.region_ports {
+                    let result = validate_region_ports(
+                        args.src_bind_port,
+                        &shredstream_args.desired_regions,
+                    );
+                    if result.is_ok() {
+                        listen_ports = shredstream_args
+                            .desired_regions
+                            .iter()
+                            .zip(args.src_bind_port..)
+                            .map(|(region, port)| (port, Some(region.clone())))
+                            .collect();
+                    }
+                    report.check("region ports", result);
+                }
+                report.check(
+                    "grpc channel",
+                    grpc_channel_config(shredstream_args).map(|_| ()),
+                );
+                match read_auth_keypair(shredstream_args) {
+                    Ok(keypair) => {
+                        report.record("auth keypair", Ok(format!("pubkey {}", keypair.pubkey())));
+                        auth_keypair = Some(Arc::new(keypair));
+                    }
+                    Err(e) => report.check("auth keypair", Err(e)),
+                }
+            }
+            ProxyMode::Replay(replay_args) => {
+                report.check(
+                    "replay speed",
+                    match replay_args.speed >= 0.0 && replay_args.speed.is_finite() {
+                        true => Ok(()),
+                        false => {
+                            Err("Invalid arguments provided, --speed must be 0 or greater."
+                                .to_string())
+                        }
+                    },
+                );
+            }
+            ProxyMode::ForwardOnly(_) => {}
+        }
+        if !args.upstream_keepalives.is_empty() && !matches!(self.mode, ProxyMode::ForwardOnly(_)) {
+            report.check(
+                "upstream keepalive",
+                Err("Invalid arguments provided, --upstream-keepalive only applies to forward-only proxies.".to_string()),
+            );
+        }
+        if !args.listeners.is_empty() {
+            let result = match self.mode {
+                ProxyMode::Replay(_) => Err(
+                    "Invalid arguments provided, --listener doesn't apply to replay.".to_string(),
+                ),
+                _ => listener::validate_listeners(
+                    &args.listeners,
+                    &listen_ports
+                        .iter()
+                        .map(|(port, _region)| *port)
+                        .collect::<Vec<_>>(),
+                ),
+            };
+            report.check("listeners", result);
+        }
+        if args.decrypt_key_file.is_some() && !matches!(self.mode, ProxyMode::ForwardOnly(_)) {
+            report.check(
+                "decrypt key file",
+                Err("Invalid arguments provided, --decrypt-key-file only applies to forward-only proxies.".to_string()),
+            );
+        }
+        if self.config_reload.is_some() && self.mode.proxy_config().is_none() {
+            report.check(
+                "config reload",
+                Err(
+                    "Config reload requires the shredstream or forward-only subcommand."
+                        .to_string(),
+                ),
+            );
+        }
+        // privileges are dropped after the threads opening these start
+        if let Some(chroot) = &args.chroot {
+            report.check(
+                "chroot",
+                privileges::validate_chroot_paths(
+                    chroot,
+                    &self.paths_opened_after_chroot(),
+                    self.resolves_hostnames(),
+                ),
+            );
+        }
+
+        // replay feeds each send thread its own channel in place of listening
+        let num_forwarder_threads = match &self.mode {
+            ProxyMode::Replay(_) => args.forwarder_threads(1).send_per_port,
+            _ => args.forwarder_threads(listen_ports.len()).send_per_port * listen_ports.len(),
+        };
+        report.check(
+            "core affinity",
+            validate_core_affinity(&args.core_affinity, num_forwarder_threads),
+        );
+        report.check("dscp", validate_dscp(args));
+        report.check("egress", validate_egress(args));
+        if let Some(spill_dir) = &args.spill_dir {
+            report.check("spill dir", spill::validate_spill_dir(spill_dir));
+        }
+        let metrics_backend = match args.metrics_backend {
+            MetricsBackendKind::Statsd if args.statsd_addr.is_none() => None,
+            kind => {
+                let metrics_backend =
+                    new_metrics_backend(kind, args.statsd_addr.as_deref(), args.statsd_dialect)
+                        .map_err(|e| {
+                            format!(
+                                "Invalid arguments provided, --statsd-addr can't be sent to: {e}"
+                            )
+                        });
+                report.check(
+                    "metrics backend",
+                    metrics_backend.as_ref().map(|_| ()).map_err(Clone::clone),
+                );
+                metrics_backend.ok()
+            }
+        };
+
+        StartupState {
+            auth_keypair,
+            listen_ports,
+            metrics_backend,
+        }
+    }
+
+    /// Validates the arguments and reads the auth keypair, without starting any threads
+    pub fn build(self) -> Result<ShredstreamProxy, ShredstreamProxyError> {
+        let mut report = ValidationReport::default();
+        let StartupState {
+            auth_keypair,
+            listen_ports,
+            metrics_backend,
+        } = self.check_startup(&mut report);
+        if let Some(e) = report.first_failure() {
+            return Err(ShredstreamProxyError::InvalidArguments(e.to_string()));
+        }
+        let args = self.mode.common_args();
+        if let Some(metrics_backend) = metrics_backend {
+            match (args.metrics_backend, &args.statsd_addr) {
+                (MetricsBackendKind::Statsd, Some(statsd_addr)) => {
+                    info!("Reporting metrics to StatsD at {statsd_addr}.")
+                }
+                (MetricsBackendKind::None, _) => {
+                    info!("Not reporting metrics, --metrics-backend is none.")
+                }
+                _ => {}
+            }
+            set_metrics_backend(metrics_backend);
+        }
+        if args.num_threads.is_some() {
+            warn!("--num-threads is deprecated, use --num-recv-threads and --num-send-threads instead.");
+        }
+
+        let (exit, shutdown_sender, shutdown_receiver) = self.shutdown.unwrap_or_else(|| {
+            let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(256);
+            (
+                Arc::new(AtomicBool::new(false)),
+                shutdown_sender,
+                shutdown_receiver,
+            )
+        });
+        let mut static_dest_sockets = args.dest_ip_ports.clone();
+        let mut shard_group_specs = args.dest_shard_groups.clone();
+        if args.dest_address_family != AddressFamily::Any {
+            // CLI args are resolved to their first address while parsing, before the family is known
+            forwarder::resolve_static_destinations(
+                &mut static_dest_sockets,
+                args.dest_address_family,
+            );
+            for spec in shard_group_specs.iter_mut() {
+                if let ShardMembers::Static(members) = &mut spec.members {
+                    forwarder::resolve_static_destinations(members, args.dest_address_family);
+                }
+            }
+        }
+        let metrics = Arc::new(ShredMetrics::new());
+        metrics.memory_guard.set_max_bytes(args.max_buffered_bytes);
+        let self_ports = match args.allow_self_forward {
+            true => vec![],
+            false => listen_ports.iter().map(|(port, _region)| *port).collect(),
+        };
+        let mut dest_blocklist = DestinationBlocklist::new(
+            args.dest_blocklist.clone(),
+            args.src_bind_addr,
+            self_ports,
+            metrics.clone(),
+        );
+        if let Some(public_ip) = args.public_ip {
+            dest_blocklist.add_self_ip(public_ip);
+        }
+        if let (Some(advertise_addr), false) = (args.advertise_addr, args.allow_self_forward) {
+            dest_blocklist.add_self_addr(advertise_addr);
+        }
+        // share destination sources between refresh, admin, health check thread, and the handle
+        let dest_sources = Arc::new(Mutex::new(DestinationSources {
+            static_dest_sockets,
+            shard_group_specs,
+            dest_blocklist: Some(dest_blocklist),
+            dest_cap: Some(DestinationCap::new(args.max_destinations, metrics.clone())),
+            ..Default::default()
+        }));
+        // share sockets between refresh, admin, and forwarder thread
+        let unioned_dest_sockets = Arc::new(ArcSwap::from_pointee(vec![]));
+        dest_sources
+            .lock()
+            .unwrap()
+            .store_union(&unioned_dest_sockets);
+
+        let listener_ports = self
+            .mode
+            .common_args()
+            .listeners
+            .iter()
+            .map(|listener| listener.port)
+            .collect();
+        Ok(ShredstreamProxy {
+            mode: self.mode,
+            auth_keypair,
+            listen_ports,
+            listener_ports,
+            admin_grpc_addr: None,
+            config_reload: self.config_reload,
+            key_reload: self.key_reload,
+            subscribers: self.subscribers,
+            metrics,
+            dest_sources,
+            unioned_dest_sockets,
+            exit,
+            shutdown_sender,
+            shutdown_receiver,
+            thread_handles: vec![],
+            started: false,
+        })
+    }
+}
+
+/// Handle to a proxy built by [ShredstreamProxyBuilder]
+pub struct ShredstreamProxy {
+    mode: ProxyMode,
+    auth_keypair: Option<Arc<Keypair>>,
+    listen_ports: Vec<(u16, Option<String>)>,
+    /// Port of each `--listener`, in order
+    listener_ports: Vec<u16>,
+    admin_grpc_addr: Option<SocketAddr>,
+    config_reload: Option<ConfigReload>,
+    key_reload: Option<Receiver<()>>,
+    subscribers: Vec<ShredSubscriber>,
+    metrics: Arc<ShredMetrics>,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    exit: Arc<AtomicBool>,
+    shutdown_sender: Sender<()>,
+    shutdown_receiver: Receiver<()>,
+    thread_handles: Vec<JoinHandle<()>>,
+    started: bool,
+}
+
+impl ShredstreamProxy {
+    /// Starts listening and forwarding. Threads started before an error are shut down
+    pub fn start(&mut self) -> Result<(), ShredstreamProxyError> {
+        if self.started {
+            return Err(ShredstreamProxyError::AlreadyStarted);
+        }
+        self.started = true;
+        if let Err(e) = self.spawn_threads() {
+            self.shutdown();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Signals all threads to exit and waits for them
+    pub fn shutdown(&mut self) {
+        self.exit.store(true, Ordering::SeqCst);
+        broadcast_shutdown(&self.shutdown_sender);
+        self.join();
+    }
+
+    /// Waits for all threads to exit, such as after the shutdown signal or once a replay finishes
+    pub fn join(&mut self) {
+        for thread in self.thread_handles.drain(..) {
+            thread.join().expect("thread panicked");
+        }
+        // fold the last partial interval into the cumulative counters
+        self.metrics.reset();
+    }
+
+    /// Like [Self::join], but once the shutdown signal is set only waits `shutdown-grace-period-ms` for threads to exit.
+    /// Returns [ShredstreamProxyError::ShutdownTimeout] naming threads still running, which are left detached
+    pub fn join_with_deadline(&mut self) -> Result<(), ShredstreamProxyError> {
+        let all_finished = |threads: &[JoinHandle<()>]| threads.iter().all(JoinHandle::is_finished);
+        while !self.exit.load(Ordering::Relaxed) && !all_finished(&self.thread_handles) {
+            let _ = self.shutdown_receiver.recv_timeout(SHUTDOWN_POLL_INTERVAL);
+        }
+        let deadline = Instant::now()
+            + Duration::from_millis(self.mode.common_args().shutdown_grace_period_ms);
+        while Instant::now() < deadline && !all_finished(&self.thread_handles) {
+            sleep(SHUTDOWN_POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+        }
+
+        let (finished, running): (Vec<_>, Vec<_>) = self
+            .thread_handles
+            .drain(..)
+            .partition(JoinHandle::is_finished);
+        for thread in finished {
+            thread.join().expect("thread panicked");
+        }
+        // fold the last partial interval into the cumulative counters, even if threads are stuck
+        self.metrics.reset();
+        if running.is_empty() {
+            return Ok(());
+        }
+        Err(ShredstreamProxyError::ShutdownTimeout(
+            running
+                .iter()
+                .map(|thread| thread.thread().name().unwrap_or("unnamed").to_string())
+                .collect(),
+        ))
+    }
+
+    pub fn metrics(&self) -> Arc<ShredMetrics> {
+        self.metrics.clone()
+    }
+
+    /// Ports listened on, as bound once started, so `src-bind-port` 0 gives the ephemeral port picked
+    pub fn listen_ports(&self) -> Vec<u16> {
+        self.listen_ports
+            .iter()
+            .map(|(port, _region)| *port)
+            .collect()
+    }
+
+    /// Ports `--listener` pipelines listen on, in order, as bound once started, so port 0 gives the ephemeral port picked
+    pub fn listener_ports(&self) -> Vec<u16> {
+        self.listener_ports.clone()
+    }
+
+    /// Address the admin gRPC service is served on once started, so `admin-grpc-bind-addr` port 0 gives the ephemeral port picked
+    pub fn admin_grpc_addr(&self) -> Option<SocketAddr> {
+        self.admin_grpc_addr
+    }
+
+    /// Replaces the destinations set by the embedding process, alongside those from the arguments, discovery, and admin API.
+    /// Can be called before starting
+    pub fn update_destinations(&self, destinations: Vec<SocketAddr>) {
+        let mut dest_sources = self.dest_sources.lock().unwrap();
+        dest_sources.library_dest_sockets = destinations;
+        dest_sources.store_union(&self.unioned_dest_sockets);
+    }
+
+    fn spawn_threads(&mut self) -> Result<(), ShredstreamProxyError> {
+        let args = self.mode.common_args().clone();
+        let exit = &self.exit;
+        let shutdown_receiver = &self.shutdown_receiver;
+        let metrics = &self.metrics;
+        let thread_handles = &mut self.thread_handles;
+        build_info::report_build_info();
+
+        let supervisor = Supervisor::new(
+            RestartPolicy {
+                max_restarts: args.thread_max_restarts,
+                window: Duration::from_secs(args.thread_restart_window_secs),
+            },
+            metrics.clone(),
+            self.shutdown_sender.clone(),
+            shutdown_receiver.clone(),
+            exit.clone(),
+        );
+
+        // before the heartbeat thread, so its first authentication is exported
+        if let Some(otlp_endpoint) = &args.otlp_endpoint {
+            thread_handles.push(otel::start_otlp_exporter_thread(
+                otlp_endpoint,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            )?);
+        }
+
+        let (
+            quic_dest_sockets,
+            tunnel_dests,
+            unix_dests,
+            enc_dests,
+            encryption_keys,
+            dest_rate_limits,
+            dest_egress,
+            dest_priorities,
+            dest_spill,
+            shard_groups,
+        ) = {
+            let dest_sources = self.dest_sources.lock().unwrap();
+            (
+                dest_sources.quic_dest_sockets.clone(),
+                dest_sources.tunnel_dests.clone(),
+                dest_sources.unix_dests.clone(),
+                dest_sources.enc_dests.clone(),
+                dest_sources.encryption_keys.clone(),
+                dest_sources.dest_rate_limits.clone(),
+                dest_sources.dest_egress.clone(),
+                dest_sources.dest_priorities.clone(),
+                dest_sources.dest_spill.clone(),
+                dest_sources.shard_groups.clone(),
+            )
+        };
+
+        // share deduper + metrics between forwarder <-> accessory thread
+        // accessory thread swaps in a fresh deduper on reset so forwarders never block
+        let deduper = Arc::new(ArcSwap::from_pointee(ShredDeduper::new(
+            &mut rand::thread_rng(),
+            args.deduper_num_bits,
+            args.dedup_mode,
+        )));
+
+        let forward_stats = Arc::new(StreamerReceiveStats::new("shredstream_proxy-listen_thread"));
+        let endpoint_discovery = endpoint_discovery(&args);
+        let dest_resolve_interval = (args.dest_resolve_interval_secs > 0)
+            .then(|| Duration::from_secs(args.dest_resolve_interval_secs));
+        // destinations can change at runtime via admin API, config reload, or health checks
+        let runtime_dest_changes = args.admin_bind_addr.is_some()
+            || args.admin_grpc_bind_addr.is_some()
+            || self.config_reload.is_some()
+            || args.health_check_mode.is_some();
+        // forwarders pick up new destinations at least as often as the refresh thread produces them,
+        // and `update_destinations` can be called at any time
+        let dest_refresh_interval = Some(forwarder::RUNTIME_DEST_REFRESH_INTERVAL);
+        // shared with the config reload thread
+        let endpoint_discovery = Arc::new(ArcSwapOption::from_pointee(endpoint_discovery));
+        let source_allowlist = Arc::new(
+            SourceAllowlist::new(
+                args.allowed_source_ips.clone(),
+                args.allowed_source_ips_file.clone(),
+            )
+            .map_err(|e| {
+                ShredstreamProxyError::InvalidArguments(format!(
+                    "Failed to read --allowed-source-ips-file: {e}"
+                ))
+            })?,
+        );
+        let packet_filter = Arc::new(PacketFilter::new(
+            args.max_slot_age,
+            args.drop_unknown_packets(),
+            args.forward_shred_types,
+        ));
+        let trace_shred_sampler = Arc::new(TraceShredSampler::new(trace_shred_sample_rate(&args)));
+        let metrics_report_interval_ms = Arc::new(AtomicU64::new(args.metrics_report_interval_ms));
+        let deshred_tap = match args.grpc_service_bind_addr {
+            Some(grpc_service_bind_addr) => {
+                let (deshred_tap, deshred_hdls) = deshred::start_deshred_threads(
+                    grpc_service_bind_addr,
+                    Duration::from_millis(args.deshred_fec_set_timeout_ms),
+                    shutdown_receiver.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.extend(deshred_hdls);
+                Some(Arc::new(deshred_tap))
+            }
+            None => None,
+        };
+        let ws_tap = match args.ws_bind_addr {
+            Some(ws_bind_addr) => {
+                let (ws_tap, ws_hdls) = ws::start_ws_threads(
+                    ws_bind_addr,
+                    args.ws_max_clients,
+                    shutdown_receiver.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.extend(ws_hdls);
+                Some(Arc::new(ws_tap))
+            }
+            None => None,
+        };
+        let pcap_tap = match &args.record_pcap {
+            Some(record_pcap) => {
+                let (pcap_tap, pcap_hdl) = pcap::start_pcap_writer_thread(
+                    record_pcap.clone(),
+                    PcapRotation {
+                        max_file_bytes: (args.record_pcap_rotate_bytes > 0)
+                            .then_some(args.record_pcap_rotate_bytes),
+                        max_file_age: (args.record_pcap_rotate_secs > 0)
+                            .then(|| Duration::from_secs(args.record_pcap_rotate_secs)),
+                        max_files: (args.record_pcap_max_files > 0)
+                            .then_some(args.record_pcap_max_files),
+                    },
+                    metrics.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(pcap_hdl);
+                Some(pcap_tap)
+            }
+            None => None,
+        };
+        let subscriber_tap = if self.subscribers.is_empty() {
+            None
+        } else {
+            let (subscriber_tap, subscriber_hdl) = subscriber::start_subscriber_dispatch_thread(
+                std::mem::take(&mut self.subscribers),
+                metrics.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(subscriber_hdl);
+            Some(subscriber_tap)
+        };
+        let kafka_tap = match kafka_config(&args) {
+            Some(kafka_config) => {
+                let (kafka_tap, kafka_hdl) = kafka::start_kafka_publisher_thread(
+                    kafka_config,
+                    Duration::from_millis(args.shutdown_grace_period_ms),
+                    metrics.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(kafka_hdl);
+                Some(kafka_tap)
+            }
+            None => None,
+        };
+        let slot_latency_tap = args.slot_latency_rpc_url.as_ref().map(|rpc_url| {
+            let slot_latency_tap = Arc::new(SlotLatencyTap::default());
+            thread_handles.push(slot_latency::start_slot_latency_thread(
+                rpc_url.clone(),
+                slot_latency_tap.clone(),
+                &supervisor,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            ));
+            slot_latency_tap
+        });
+        let conflict_tap = match conflict_detector_config(&args) {
+            Some(config) => {
+                let (conflict_tap, conflict_hdl) = conflicts::start_conflict_detector_thread(
+                    config,
+                    metrics.clone(),
+                    shutdown_receiver.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(conflict_hdl);
+                Some(conflict_tap)
+            }
+            None => None,
+        };
+        let events_tap = match events_config(&args) {
+            Some(config) => {
+                let (events_tap, events_hdl) = events::start_events_thread(
+                    config,
+                    metrics.clone(),
+                    shutdown_receiver.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(events_hdl);
+                Some(events_tap)
+            }
+            None => None,
+        };
+        let alert_monitor = match alert_config(&args) {
+            Some(mut config) => {
+                // forward-only mode never heartbeats
+                if !matches!(self.mode, ProxyMode::Shredstream(_)) {
+                    config.heartbeat_down_after = None;
+                }
+                let (alert_monitor, alerts_hdl) =
+                    alerts::start_alert_thread(config, shutdown_receiver.clone(), exit.clone())?;
+                thread_handles.push(alerts_hdl);
+                Some(alert_monitor)
+            }
+            None => None,
+        };
+        let archive_tap = match archive_config(&args) {
+            Some(archive_config) => {
+                if archive_config.read_check {
+                    archive::check_archive(&archive_config)?;
+                }
+                let (archive_tap, archive_hdl) =
+                    archive::start_archive_thread(archive_config, metrics.clone(), exit.clone())?;
+                thread_handles.push(archive_hdl);
+                Some(archive_tap)
+            }
+            None => None,
+        };
+        let (listen_ready_sender, listen_ready_receiver) = crossbeam_channel::unbounded();
+        let packet_source = match &self.mode {
+            ProxyMode::Replay(replay_args) => {
+                let (packet_senders, packet_receivers) =
+                    (0..args.forwarder_threads(1).send_per_port)
+                        .map(|_| crossbeam_channel::bounded(replay::REPLAY_CHANNEL_CAPACITY))
+                        .unzip();
+                let replay_hdl = replay::start_replay_thread(
+                    ReplayConfig {
+                        input: replay_args.input.clone(),
+                        speed: replay_args.speed,
+                        loop_replay: replay_args.loop_replay,
+                        deduper_num_bits: args.deduper_num_bits,
+                    },
+                    packet_senders,
+                    deduper.clone(),
+                    // replay shuts down the proxy once the capture is exhausted
+                    self.shutdown_sender.clone(),
+                    shutdown_receiver.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(replay_hdl);
+                PacketSource::Channel(packet_receivers)
+            }
+            _ => PacketSource::Listen {
+                src_addr: args.src_bind_addr,
+                listen_ports: self.listen_ports.clone(),
+                threads: args.forwarder_threads(self.listen_ports.len()),
+                recv_socket_buffer_bytes: args.recv_socket_buffer_bytes,
+                dscp: args.listen_dscp,
+                channel_capacity: args.forwarder_channel_capacity,
+                drop_policy: args.forwarder_drop_policy,
+                rx_timestamps: args
+                    .measure_internal_latency
+                    .then_some(args.rx_timestamp_source),
+                recv_mmsg_batch_size: args.recv_mmsg_batch_size,
+                recv_poll_timeout: Duration::from_millis(args.recv_poll_timeout_ms),
+                upstream_keepalives: args.upstream_keepalives.clone(),
+                decrypt_key_file: args
+                    .decrypt_key_file
+                    .as_ref()
+                    .map(|path| encryption_keys.key_file(path)),
+                ready_sender: Some(listen_ready_sender),
+            },
+        };
+        let spill_store = match spill_config(&args) {
+            Some(spill_config) => {
+                let spill_store = Arc::new(SpillStore::new(
+                    spill_config,
+                    dest_spill.clone(),
+                    metrics.clone(),
+                ));
+                let spill_hdl = spill::start_spill_replay_thread(
+                    spill_store.clone(),
+                    SendSocketOptions {
+                        buffer_bytes: args.send_socket_buffer_bytes,
+                        dscp: args.dscp,
+                        egress: args.egress(),
+                    },
+                    args.send_batch_size,
+                    exit.clone(),
+                )?;
+                thread_handles.push(spill_hdl);
+                thread_handles.push(spill::start_spill_writer_thread(
+                    spill_store.clone(),
+                    exit.clone(),
+                )?);
+                Some(spill_store)
+            }
+            None => None,
+        };
+        let chaos = match chaos_config(&args) {
+            Some(chaos_config) => {
+                let chaos = Arc::new(ChaosInjector::new(chaos_config, metrics.clone()));
+                let chaos_hdl = chaos::start_chaos_thread(
+                    chaos.clone(),
+                    SendSocketOptions {
+                        buffer_bytes: args.send_socket_buffer_bytes,
+                        dscp: args.dscp,
+                        egress: args.egress(),
+                    },
+                    args.send_batch_size,
+                    exit.clone(),
+                )?;
+                thread_handles.push(chaos_hdl);
+                Some(chaos)
+            }
+            None => None,
+        };
+        let mut mirror_specs = args.mirror_dests.clone();
+        if args.dest_address_family != AddressFamily::Any {
+            for spec in mirror_specs.iter_mut() {
+                let mut dest = [(spec.dest, spec.hostname_port.clone())];
+                forwarder::resolve_static_destinations(&mut dest, args.dest_address_family);
+                spec.dest = dest[0].0;
+            }
+        }
+        let mirrors = mirror_specs.iter().map(Mirror::new).collect::<Vec<_>>();
+        let forwarder_liveness = Arc::new(ThreadLiveness::default());
+        let (forwarder_hdls, bound_ports, mut socket_drop_counter) =
+            forwarder::start_forwarder_threads(
+                self.unioned_dest_sockets.clone(),
+                packet_source,
+                None,
+                args.core_affinity.clone(),
+                args.send_batch_size,
+                Duration::from_micros(args.send_batch_linger_us),
+                args.send_socket_buffer_bytes,
+                args.dscp,
+                args.egress(),
+                Arc::new(RebindPolicy::new(
+                    args.socket_rebind_error_threshold,
+                    args.socket_max_rebinds_per_minute,
+                )),
+                slow_send_threshold(&args),
+                args.isolated_send_threads
+                    .then_some(args.isolated_send_queue_capacity),
+                quic_dest_sockets,
+                tunnel_dests.clone(),
+                unix_dests.clone(),
+                enc_dests,
+                dest_rate_limits,
+                dest_egress,
+                dest_priorities,
+                dest_spill,
+                shard_groups.clone(),
+                Arc::new(mirrors),
+                Arc::new(QuicSink::new(metrics.clone())),
+                Arc::new(TunnelSink::new(
+                    tunnel_config(&args),
+                    tunnel_dests,
+                    metrics.clone(),
+                )),
+                Arc::new(UnixSink::new(unix_dests, metrics.clone())),
+                spill_store,
+                chaos,
+                source_allowlist.clone(),
+                packet_filter.clone(),
+                deshred_tap,
+                pcap_tap,
+                subscriber_tap,
+                kafka_tap,
+                slot_latency_tap,
+                conflict_tap,
+                events_tap,
+                archive_tap,
+                ws_tap,
+                deduper.clone(),
+                metrics.clone(),
+                forward_stats.clone(),
+                dest_refresh_interval,
+                trace_shred_sampler.clone(),
+                forwarder_liveness.clone(),
+                shutdown_receiver.clone(),
+                Duration::from_millis(args.shutdown_grace_period_ms),
+                exit.clone(),
+            )?;
+        thread_handles.extend(forwarder_hdls);
+        // port 0 binds an ephemeral port, so track the ports as bound for advertising and probes
+        for ((port, _region), bound_port) in self.listen_ports.iter_mut().zip(bound_ports) {
+            *port = bound_port;
+        }
+        // each listener is its own pipeline, sharing only metrics, liveness, and shutdown with the main one
+        let mut dedupers = vec![deduper];
+        let mut listener_dest_sockets = vec![];
+        for (listener, listener_port) in args.listeners.iter().zip(&mut self.listener_ports) {
+            let mut dests = listener.dests.clone();
+            if args.dest_address_family != AddressFamily::Any {
+                forwarder::resolve_static_destinations(&mut dests, args.dest_address_family);
+            }
+            let dest_sockets = dests.into_iter().map(|(addr, _)| addr).collect::<Vec<_>>();
+            listener_dest_sockets.extend(dest_sockets.iter().copied());
+            let listener_deduper = Arc::new(ArcSwap::from_pointee(ShredDeduper::new(
+                &mut rand::thread_rng(),
+                args.deduper_num_bits,
+                args.dedup_mode,
+            )));
+            let (listener_hdls, bound_ports, listener_drop_counter) =
+                forwarder::start_forwarder_threads(
+                    Arc::new(ArcSwap::from_pointee(dest_sockets)),
+                    PacketSource::Listen {
+                        src_addr: args.src_bind_addr,
+                        listen_ports: vec![(listener.port, None)],
+                        threads: args.forwarder_threads(1),
+                        recv_socket_buffer_bytes: args.recv_socket_buffer_bytes,
+                        dscp: args.listen_dscp,
+                        channel_capacity: args.forwarder_channel_capacity,
+                        drop_policy: args.forwarder_drop_policy,
+                        rx_timestamps: args
+                            .measure_internal_latency
+                            .then_some(args.rx_timestamp_source),
+                        recv_mmsg_batch_size: args.recv_mmsg_batch_size,
+                        recv_poll_timeout: Duration::from_millis(args.recv_poll_timeout_ms),
+                        upstream_keepalives: vec![],
+                        decrypt_key_file: None,
+                        ready_sender: None,
+                    },
+                    Some(listener.name()),
+                    vec![],
+                    args.send_batch_size,
+                    Duration::from_micros(args.send_batch_linger_us),
+                    args.send_socket_buffer_bytes,
+                    args.dscp,
+                    args.egress(),
+                    Arc::new(RebindPolicy::new(
+                        args.socket_rebind_error_threshold,
+                        args.socket_max_rebinds_per_minute,
+                    )),
+                    slow_send_threshold(&args),
+                    None,
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Arc::new(QuicSink::new(metrics.clone())),
+                    Arc::new(TunnelSink::new(
+                        tunnel_config(&args),
+                        Default::default(),
+                        metrics.clone(),
+                    )),
+                    Arc::new(UnixSink::new(Default::default(), metrics.clone())),
+                    None,
+                    None,
+                    source_allowlist.clone(),
+                    packet_filter.clone(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                    listener_deduper.clone(),
+                    metrics.clone(),
+                    forward_stats.clone(),
+                    None,
+                    trace_shred_sampler.clone(),
+                    forwarder_liveness.clone(),
+                    shutdown_receiver.clone(),
+                    Duration::from_millis(args.shutdown_grace_period_ms),
+                    exit.clone(),
+                )?;
+            thread_handles.extend(listener_hdls);
+            *listener_port = bound_ports[0];
+            dedupers.push(listener_deduper);
+            if let (Some(counter), Some(listener_counter)) =
+                (socket_drop_counter.as_mut(), listener_drop_counter)
+            {
+                counter.merge(listener_counter);
+            }
+            info!(
+                "Listener {} started on {}:{}/udp, forwarding to {} destinations.",
+                listener.name(),
+                args.src_bind_addr,
+                listener_port,
+                listener.dests.len()
+            );
+        }
+        // best effort, destinations discovered later aren't checked
+        let dest_sources = self.dest_sources.lock().unwrap();
+        let non_udp_dests = dest_sources
+            .static_dest_sockets
+            .iter()
+            .filter(|(_, hostname_port)| hostname_port.contains("://"))
+            .map(|(socketaddr, _)| *socketaddr)
+            .collect::<HashSet<_>>();
+        let udp_dests = dest_sources
+            .union()
+            .into_iter()
+            .filter(|socketaddr| !non_udp_dests.contains(socketaddr))
+            .chain(listener_dest_sockets.iter().copied())
+            .collect::<Vec<_>>();
+        drop(dest_sources);
+        socket::check_path_mtus(&udp_dests);
+
+        // only heartbeat once every receive thread is reading, so the block engine never sends to a port nobody reads
+        let num_receive_threads = match self.mode {
+            ProxyMode::Replay(_) => 0,
+            _ => {
+                self.listen_ports.len()
+                    * args
+                        .forwarder_threads(self.listen_ports.len())
+                        .recv_per_port
+            }
+        };
+        let ready_deadline = Instant::now() + LISTEN_READY_TIMEOUT;
+        for _ in 0..num_receive_threads {
+            listen_ready_receiver
+                .recv_deadline(ready_deadline)
+                .map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "Receive threads didn't start listening in time.",
+                    )
+                })?;
+        }
+
+        // None unless heartbeating, in shredstream mode
+        let mut advertised_addr = None;
+        if let (ProxyMode::Shredstream(shredstream_args), Some(auth_keypair)) =
+            (&self.mode, &self.auth_keypair)
+        {
+            let shredstream_args = shredstream_args.clone();
+            // the first port, which region ports count up from
+            let listen_port = self.listen_ports[0].0;
+            // behind a NAT the advertised port may differ from the one bound
+            let advertise_addr = match (args.advertise_addr, args.public_ip) {
+                (Some(advertise_addr), _) => advertise_addr,
+                (None, Some(public_ip)) => SocketAddr::new(public_ip, listen_port),
+                (None, None) => {
+                    let public_ip = get_public_ip_with_retry(args.public_ip_family, exit)?;
+                    let mut dest_sources = self.dest_sources.lock().unwrap();
+                    if let Some(dest_blocklist) = &mut dest_sources.dest_blocklist {
+                        dest_blocklist.add_self_ip(public_ip);
+                    }
+                    dest_sources.store_union(&self.unioned_dest_sockets);
+                    SocketAddr::new(public_ip, listen_port)
+                }
+            };
+            advertised_addr = Some(advertise_addr);
+            // an explicit address is never rechecked
+            let public_ip_recheck_interval = (args.advertise_addr.is_none()
+                && args.public_ip.is_none()
+                && args.public_ip_recheck_interval_secs > 0)
+                .then(|| Duration::from_secs(args.public_ip_recheck_interval_secs));
+            let advertise_addr = Arc::new(ArcSwap::from_pointee(advertise_addr));
+            let reregister_receiver = if shredstream_args.stall_timeout_secs > 0
+                || public_ip_recheck_interval.is_some()
+            {
+                let (reregister_sender, reregister_receiver) = crossbeam_channel::bounded(1);
+                if shredstream_args.stall_timeout_secs > 0 {
+                    thread_handles.push(watchdog::start_stall_watchdog_thread(
+                        Duration::from_secs(shredstream_args.stall_timeout_secs),
+                        metrics.clone(),
+                        reregister_sender.clone(),
+                        &supervisor,
+                        shutdown_receiver.clone(),
+                        exit.clone(),
+                    ));
+                }
+                if let Some(recheck_interval) = public_ip_recheck_interval {
+                    thread_handles.push(watchdog::start_public_ip_recheck_thread(
+                        recheck_interval,
+                        args.public_ip_family,
+                        advertise_addr.clone(),
+                        self.dest_sources.clone(),
+                        self.unioned_dest_sockets.clone(),
+                        metrics.clone(),
+                        reregister_sender,
+                        &supervisor,
+                        shutdown_receiver.clone(),
+                        exit.clone(),
+                    ));
+                }
+                reregister_receiver
+            } else {
+                crossbeam_channel::never()
+            };
+            let grpc_channel_config = grpc_channel_config(&shredstream_args)
+                .map_err(ShredstreamProxyError::InvalidArguments)?;
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .thread_name("ssPxyHbeatRt")
+                .enable_all()
+                .build()?;
+            // fail fast on a typo, which would otherwise heartbeat successfully without receiving shreds
+            let desired_regions = regions::check_desired_regions(
+                &runtime,
+                &shredstream_args,
+                auth_keypair.clone(),
+                &grpc_channel_config,
+                metrics.clone(),
+            )?;
+            let mut token_cache = TokenCache::new(Duration::from_secs(
+                shredstream_args.token_refresh_margin_secs,
+            ))
+            .with_initial_auth_jitter(Duration::from_millis(
+                shredstream_args.initial_auth_jitter_ms,
+            ));
+            if let Some(token_cache_file) = shredstream_args.token_cache_file {
+                token_cache = token_cache.with_file(token_cache_file, &auth_keypair.pubkey());
+            }
+            let heartbeat_hdl = heartbeat::heartbeat_loop_thread(
+                BlockEngineFailover::new(
+                    shredstream_args.block_engine_url,
+                    shredstream_args.block_engine_failover_threshold,
+                    Duration::from_secs(shredstream_args.block_engine_primary_retry_secs),
+                ),
+                shredstream_args.auth_url,
+                auth_keypair.clone(),
+                grpc_channel_config,
+                desired_regions,
+                advertise_addr,
+                shredstream_args.region_ports,
+                runtime,
+                "shredstream_proxy".to_string(),
+                token_cache,
+                metrics.clone(),
+                reregister_receiver,
+                &supervisor,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(heartbeat_hdl);
+        }
+
+        // listen sockets are bound by now
+        if systemd::notify_enabled() {
+            thread_handles.push(systemd::start_systemd_notify_thread(
+                matches!(self.mode, ProxyMode::Shredstream(_)),
+                forwarder_liveness.clone(),
+                metrics.clone(),
+                shutdown_receiver.clone(),
+                exit.clone(),
+            )?);
+        }
+
+        let probes = Arc::new(Probes::new(
+            args.src_bind_addr,
+            match self.mode {
+                ProxyMode::Replay(_) => vec![],
+                _ => self
+                    .listen_ports
+                    .iter()
+                    .map(|(port, _region)| *port)
+                    .chain(self.listener_ports.iter().copied())
+                    .collect(),
+            },
+            self.unioned_dest_sockets.clone(),
+            forwarder_liveness.clone(),
+            match &self.mode {
+                ProxyMode::Shredstream(shredstream_args) => Some(HeartbeatProbeConfig {
+                    max_age: Duration::from_secs(shredstream_args.ready_heartbeat_max_age_secs),
+                    stall_timeout: (shredstream_args.stall_timeout_secs > 0)
+                        .then(|| Duration::from_secs(shredstream_args.stall_timeout_secs)),
+                }),
+                _ => None,
+            },
+            metrics.clone(),
+        ));
+        let receive_totals = Arc::new(ReceiveStatsTotals::default());
+        let report_metrics_thread = {
+            let exit = exit.clone();
+            let receive_totals = receive_totals.clone();
+            let shutdown_receiver = shutdown_receiver.clone();
+            Builder::new()
+                .name("ssPxyRecvStats".to_string())
+                .spawn(move || {
+                    while !exit.load(Ordering::Relaxed) {
+                        // wakes immediately on shutdown (avoid using sleep since it will hang under SIGINT)
+                        let _ = shutdown_receiver.recv_timeout(Duration::from_secs(1));
+                        receive_totals.report(&forward_stats);
+                    }
+                })?
+        };
+        thread_handles.push(report_metrics_thread);
+
+        if let Some(prometheus_bind_addr) = args.prometheus_bind_addr {
+            let prometheus_hdl = prometheus::start_prometheus_thread(
+                prometheus_bind_addr,
+                metrics.clone(),
+                receive_totals,
+                probes.clone(),
+                exit.clone(),
+            )?;
+            thread_handles.push(prometheus_hdl);
+        }
+
+        let metrics_hdl = forwarder::start_forwarder_accessory_thread(
+            dedupers,
+            args.deduper_false_positive_rate,
+            Duration::from_millis(args.deduper_reset_interval_ms),
+            socket_drop_counter,
+            metrics.clone(),
+            self.unioned_dest_sockets.clone(),
+            shard_groups,
+            listener_dest_sockets,
+            metrics_report_interval_ms.clone(),
+            alert_monitor,
+            &supervisor,
+            shutdown_receiver.clone(),
+            exit.clone(),
+        );
+        thread_handles.push(metrics_hdl);
+        if let Some(admin_bind_addr) = args.admin_bind_addr {
+            let admin_hdl = admin::start_admin_thread(
+                admin_bind_addr,
+                self.dest_sources.clone(),
+                self.unioned_dest_sockets.clone(),
+                trace_shred_sampler.clone(),
+                metrics.clone(),
+                probes,
+                exit.clone(),
+            )?;
+            thread_handles.push(admin_hdl);
+        }
+        let discovery_refresh_receiver = match args.admin_grpc_bind_addr {
+            Some(admin_grpc_bind_addr) => {
+                let (discovery_refresh_sender, discovery_refresh_receiver) =
+                    crossbeam_channel::bounded(1);
+                let tls = args
+                    .admin_grpc_tls_cert_file
+                    .as_deref()
+                    .zip(args.admin_grpc_tls_key_file.as_deref())
+                    .map(|(cert_file, key_file)| AdminGrpcTls {
+                        cert_file,
+                        key_file,
+                    });
+                let (admin_grpc_hdl, admin_grpc_addr) = admin_grpc::start_admin_grpc_thread(
+                    admin_grpc_bind_addr,
+                    tls,
+                    self.dest_sources.clone(),
+                    self.unioned_dest_sockets.clone(),
+                    endpoint_discovery.clone(),
+                    discovery_refresh_sender,
+                    metrics.clone(),
+                    exit.clone(),
+                )?;
+                thread_handles.push(admin_grpc_hdl);
+                self.admin_grpc_addr = Some(admin_grpc_addr);
+                discovery_refresh_receiver
+            }
+            None => crossbeam_channel::never(),
+        };
+        if let Some(validator_resolver_config) = validator_resolver_config(&args) {
+            thread_handles.push(validators::start_validator_resolver_thread(
+                validator_resolver_config,
+                self.dest_sources.clone(),
+                self.unioned_dest_sockets.clone(),
+                metrics.clone(),
+                &supervisor,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            ));
+        }
+        if let Some(rpc_discovery_config) = rpc_discovery_config(&args) {
+            thread_handles.push(stake_discovery::start_rpc_discovery_thread(
+                rpc_discovery_config,
+                self.dest_sources.clone(),
+                self.unioned_dest_sockets.clone(),
+                &supervisor,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            ));
+        }
+        if let Some(mode) = args.health_check_mode {
+            let health_hdl = health::start_health_check_thread(
+                HealthCheckConfig {
+                    mode,
+                    port: args.health_check_port,
+                    http_path: args.health_check_http_path.clone(),
+                    interval: Duration::from_millis(args.health_check_interval_ms),
+                    timeout: Duration::from_millis(args.health_check_timeout_ms),
+                    failure_threshold: args.health_check_failure_threshold,
+                },
+                self.dest_sources.clone(),
+                self.unioned_dest_sockets.clone(),
+                metrics.clone(),
+                shutdown_receiver.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(health_hdl);
+        }
+        if let (Some(config_reload), Some(config)) =
+            (self.config_reload.take(), self.mode.proxy_config())
+        {
+            let reload_hdl = reload::start_config_reload_thread(
+                config_reload.path,
+                config_reload.format,
+                config,
+                ReloadableState {
+                    dest_sources: self.dest_sources.clone(),
+                    unioned_dest_sockets: self.unioned_dest_sockets.clone(),
+                    endpoint_discovery: endpoint_discovery.clone(),
+                    metrics_report_interval_ms,
+                    trace_shred_sampler,
+                    packet_filter,
+                    source_allowlist: source_allowlist.clone(),
+                },
+                config_reload.reload_receiver,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(reload_hdl);
+        }
+        if let Some(key_reload_receiver) = self.key_reload.take() {
+            let key_reload_hdl = encryption::start_key_reload_thread(
+                encryption_keys,
+                key_reload_receiver,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(key_reload_hdl);
+        }
+        if endpoint_discovery.load().is_some()
+            || dest_resolve_interval.is_some()
+            || runtime_dest_changes
+            || args.allowed_source_ips_file.is_some()
+        {
+            let refresh_handle = forwarder::start_destination_refresh_thread(
+                endpoint_discovery,
+                discovery_refresh_receiver,
+                self.dest_sources.clone(),
+                dest_resolve_interval,
+                args.dest_address_family,
+                self.unioned_dest_sockets.clone(),
+                source_allowlist,
+                metrics.clone(),
+                &supervisor,
+                shutdown_receiver.clone(),
+                exit.clone(),
+            );
+            thread_handles.push(refresh_handle);
+        }
+
+        if args.run_as_user.is_some() || args.run_as_group.is_some() {
+            // every listener is bound and the keypair and public ip fetched by now, so nothing afterwards needs root
+            privileges::drop_privileges(
+                args.run_as_user.as_deref(),
+                args.run_as_group.as_deref(),
+                args.chroot.as_deref(),
+            )?;
+        }
+
+        if !matches!(self.mode, ProxyMode::Replay(_)) {
+            let threads = args.forwarder_threads(self.listen_ports.len());
+            let advertising = advertised_addr
+                .map(|addr| format!(", advertising {addr}/udp to the block engine,"))
+                .unwrap_or_default();
+            info!(
+                "Shredstream started, listening on {}:{}/udp{advertising} with {} receive threads per port feeding a queue shared by {} send threads per port.",
+                args.src_bind_addr, self.listen_ports[0].0, threads.recv_per_port, threads.send_per_port
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{sleep, Builder},
+        time::{Duration, Instant},
+    };
+
+    use jito_protos::shredstream_proxy::{
+        shredstream_proxy_admin_client::ShredstreamProxyAdminClient, AddDestinationRequest,
+        GetMetricsSnapshotRequest, ListDestinationsRequest, ListDestinationsResponse,
+        RemoveDestinationRequest, SetLogLevelRequest, TriggerDiscoveryRefreshRequest,
+    };
+    use solana_sdk::signature::{write_keypair_file, Keypair};
+    use tonic::Code;
+
+    use crate::{
+        broadcast_shutdown,
+        builder::ShredstreamProxyBuilder,
+        forwarder, listener,
+        mock_block_engine::{self, MockBlockEngineArgs},
+        shred::{self, tests::new_data_shred},
+        validate::CheckOutcome,
+        CommonArgs, ReplayArgs, ShredstreamArgs, ShredstreamProxyError,
+    };
+
+    fn dest_socket() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    fn recv_payload(socket: &UdpSocket) -> Vec<u8> {
+        let mut buf = [0u8; 1280];
+        let (len, _) = socket.recv_from(&mut buf).unwrap();
+        buf[..len].to_vec()
+    }
+
+    #[test]
+    fn test_forward_only_proxy() {
+        let (static_dest, static_addr) = dest_socket();
+        let (updated_dest, updated_addr) = dest_socket();
+        let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+            src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            src_bind_port: 0,
+            dest_ip_ports: vec![(static_addr, static_addr.to_string())],
+            dest_resolve_interval_secs: 0,
+            num_threads: Some(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+        proxy.update_destinations(vec![updated_addr]);
+        proxy.start().unwrap();
+        let src_bind_port = proxy.listen_ports()[0];
+        assert!(matches!(
+            proxy.start(),
+            Err(ShredstreamProxyError::AlreadyStarted)
+        ));
+
+        // wait for forwarders to pick up the updated destination
+        sleep(forwarder::RUNTIME_DEST_REFRESH_INTERVAL * 2);
+        // packets that aren't shreds are dropped by default, so only the shred arrives
+        let shred = new_data_shred(42, 0, 0, false, &[1, 2, 3]);
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(b"not a shred", (Ipv4Addr::LOCALHOST, src_bind_port))
+            .unwrap();
+        sender
+            .send_to(&shred, (Ipv4Addr::LOCALHOST, src_bind_port))
+            .unwrap();
+        assert_eq!(recv_payload(&static_dest), shred);
+        assert_eq!(recv_payload(&updated_dest), shred);
+
+        proxy.shutdown();
+        let metrics = proxy.metrics();
+        assert_eq!(metrics.agg_received_cumulative.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            metrics.received_invalid_cumulative.load(Ordering::Relaxed),
+            1
+        );
+        assert_eq!(
+            metrics
+                .agg_success_forward_cumulative
+                .load(Ordering::Relaxed),
+            2
+        );
+    }
+
+    #[test]
+    fn test_listeners() {
+        let (main_dest, main_addr) = dest_socket();
+        let (listener_dest, listener_addr) = dest_socket();
+        let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+            src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            src_bind_port: 0,
+            dest_ip_ports: vec![(main_addr, main_addr.to_string())],
+            listeners: vec![listener::parse_listener(&format!(
+                "port=0,dests={listener_addr},name=fleet-b"
+            ))
+            .unwrap()],
+            dest_resolve_interval_secs: 0,
+            num_threads: Some(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+        proxy.start().unwrap();
+        let (src_bind_port, listener_port) = (proxy.listen_ports()[0], proxy.listener_ports()[0]);
+
+        // the same shred on both ports reaches both fleets, as listeners don't share dedup state
+        let shred = new_data_shred(42, 0, 0, false, &[1, 2, 3]);
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for port in [src_bind_port, listener_port, listener_port] {
+            sender.send_to(&shred, (Ipv4Addr::LOCALHOST, port)).unwrap();
+        }
+        assert_eq!(recv_payload(&main_dest), shred);
+        assert_eq!(recv_payload(&listener_dest), shred);
+        // each fleet only gets its own port's shreds, once
+        for dest in [&main_dest, &listener_dest] {
+            dest.set_read_timeout(Some(Duration::from_millis(200)))
+                .unwrap();
+            assert!(dest.recv_from(&mut [0u8; 1280]).is_err());
+        }
+
+        proxy.shutdown();
+        let metrics = proxy.metrics();
+        assert_eq!(metrics.agg_received_cumulative.load(Ordering::Relaxed), 3);
+        assert_eq!(
+            *metrics
+                .listener_received_cumulative
+                .get("fleet-b")
+                .unwrap()
+                .value(),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn test_listener_port_conflict() {
+        let args = CommonArgs {
+            src_bind_port: 20000,
+            listeners: vec![listener::parse_listener("port=20000,dests=127.0.0.1:8001").unwrap()],
+            ..Default::default()
+        };
+        let Err(ShredstreamProxyError::InvalidArguments(e)) =
+            ShredstreamProxyBuilder::forward_only(args).build()
+        else {
+            panic!("expected the listener port to conflict");
+        };
+        assert!(e.contains("--listener port 20000"), "{e}");
+    }
+
+    #[test]
+    fn test_admin_grpc_service() {
+        let (fake_dest, fake_addr) = dest_socket();
+        let (_discovered_dest, discovered_addr) = dest_socket();
+        // discovery is only fetched when triggered, the interval is far off
+        let discovery_server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let endpoint_discovery_url =
+            format!("http://{}", discovery_server.server_addr().to_ip().unwrap());
+        let (discovery_request_sender, discovery_request_receiver) = crossbeam_channel::unbounded();
+        Builder::new()
+            .spawn(move || {
+                for request in discovery_server.incoming_requests() {
+                    discovery_request_sender.send(()).unwrap();
+                    request
+                        .respond(tiny_http::Response::from_string("[\"127.0.0.1\"]"))
+                        .unwrap();
+                }
+            })
+            .unwrap();
+        let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+            src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            src_bind_port: 0,
+            endpoint_discovery_url: Some(endpoint_discovery_url),
+            discovered_endpoints_port: Some(discovered_addr.port()),
+            endpoint_discovery_interval_ms: 3_600_000,
+            admin_grpc_bind_addr: Some(SocketAddr::from(([127, 0, 0, 1], 0))),
+            dest_resolve_interval_secs: 0,
+            num_threads: Some(1),
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+        proxy.start().unwrap();
+        let src_bind_port = proxy.listen_ports()[0];
+        let admin_grpc_bind_addr = proxy.admin_grpc_addr().unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let mut client =
+                ShredstreamProxyAdminClient::connect(format!("http://{admin_grpc_bind_addr}"))
+                    .await
+                    .unwrap();
+            let list = |response: tonic::Response<ListDestinationsResponse>| {
+                response
+                    .into_inner()
+                    .destinations
+                    .into_iter()
+                    .map(|dest| (dest.addr, dest.sources))
+                    .collect::<Vec<_>>()
+            };
+            assert!(list(
+                client
+                    .list_destinations(ListDestinationsRequest {})
+                    .await
+                    .unwrap()
+            )
+            .is_empty());
+
+            let status = client
+                .add_destination(AddDestinationRequest {
+                    addr: "127.0.0.1".to_string(),
+                    ttl_seconds: 0,
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            assert_eq!(
+                list(
+                    client
+                        .add_destination(AddDestinationRequest {
+                            addr: fake_addr.to_string(),
+                            ttl_seconds: 0,
+                        })
+                        .await
+                        .unwrap()
+                ),
+                vec![(fake_addr.to_string(), vec!["admin".to_string()])]
+            );
+
+            // wait for forwarders to pick up the added destination
+            sleep(forwarder::RUNTIME_DEST_REFRESH_INTERVAL * 2);
+            let shred = new_data_shred(42, 0, 0, false, &[1, 2, 3]);
+            UdpSocket::bind("127.0.0.1:0")
+                .unwrap()
+                .send_to(&shred, (Ipv4Addr::LOCALHOST, src_bind_port))
+                .unwrap();
+            assert_eq!(recv_payload(&fake_dest), shred);
+            let snapshot = client
+                .get_metrics_snapshot(GetMetricsSnapshotRequest {})
+                .await
+                .unwrap()
+                .into_inner();
+            assert_eq!(snapshot.counters["agg_received"], 1);
+            assert_eq!(snapshot.counters["agg_success_forward"], 1);
+            assert_eq!(snapshot.gauges["heartbeat_paused"], 0);
+
+            client
+                .trigger_discovery_refresh(TriggerDiscoveryRefreshRequest {})
+                .await
+                .unwrap();
+            discovery_request_receiver
+                .recv_timeout(Duration::from_secs(5))
+                .unwrap();
+            let deadline = Instant::now() + Duration::from_secs(5);
+            loop {
+                let destinations = list(
+                    client
+                        .list_destinations(ListDestinationsRequest {})
+                        .await
+                        .unwrap(),
+                );
+                if destinations.len() == 2 {
+                    assert!(destinations
+                        .contains(&(discovered_addr.to_string(), vec!["discovered".to_string()])));
+                    break;
+                }
+                assert!(Instant::now() < deadline, "{destinations:?}");
+                sleep(Duration::from_millis(50));
+            }
+
+            let status = client
+                .set_log_level(SetLogLevelRequest {
+                    level: "verbose".to_string(),
+                    targets: Default::default(),
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+            // the logger is only installed by the binary
+            let status = client
+                .set_log_level(SetLogLevelRequest {
+                    level: "debug".to_string(),
+                    targets: [("tonic".to_string(), "warn".to_string())].into(),
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::FailedPrecondition);
+
+            assert_eq!(
+                list(
+                    client
+                        .remove_destination(RemoveDestinationRequest {
+                            addr: fake_addr.to_string(),
+                        })
+                        .await
+                        .unwrap()
+                ),
+                vec![(discovered_addr.to_string(), vec!["discovered".to_string()])]
+            );
+            let status = client
+                .remove_destination(RemoveDestinationRequest {
+                    addr: fake_addr.to_string(),
+                })
+                .await
+                .unwrap_err();
+            assert_eq!(status.code(), Code::NotFound);
+        });
+
+        proxy.shutdown();
+    }
+
+    #[test]
+    fn test_join_with_deadline() {
+        let (_dest, dest_addr) = dest_socket();
+        let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+            src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            src_bind_port: 0,
+            dest_ip_ports: vec![(dest_addr, dest_addr.to_string())],
+            num_threads: Some(1),
+            // listen threads take up to their 1s read timeout to notice exit
+            shutdown_grace_period_ms: 2_000,
+            ..Default::default()
+        })
+        .build()
+        .unwrap();
+        proxy.start().unwrap();
+        // ignores shutdown
+        proxy.thread_handles.push(
+            Builder::new()
+                .name("ssPxyStuck".to_string())
+                .spawn(|| sleep(Duration::from_secs(30)))
+                .unwrap(),
+        );
+
+        proxy.exit.store(true, Ordering::SeqCst);
+        broadcast_shutdown(&proxy.shutdown_sender);
+        let start = Instant::now();
+        match proxy.join_with_deadline() {
+            Err(ShredstreamProxyError::ShutdownTimeout(running)) => {
+                assert_eq!(running, vec!["ssPxyStuck"])
+            }
+            res => panic!("expected shutdown timeout, got {res:?}"),
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(proxy.thread_handles.is_empty());
+    }
+
+    #[cfg(feature = "subscriber")]
+    #[test]
+    fn test_subscriber_without_destinations() {
+        let (shred_sender, shred_receiver) = crossbeam_channel::unbounded();
+        let mut proxy = ShredstreamProxyBuilder::forward_only(CommonArgs {
+            src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            src_bind_port: 0,
+            num_threads: Some(1),
+            ..Default::default()
+        })
+        .subscribe(crate::ShredSubscriber::Channel(shred_sender))
+        .build()
+        .unwrap();
+        proxy.start().unwrap();
+        let src_bind_port = proxy.listen_ports()[0];
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender
+            .send_to(b"not a shred", (Ipv4Addr::LOCALHOST, src_bind_port))
+            .unwrap();
+        assert_eq!(
+            shred_receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"not a shred"
+        );
+        proxy.shutdown();
+        assert_eq!(
+            proxy
+                .metrics()
+                .subscriber_delivered_cumulative
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn test_shredstream_proxy_with_mock_block_engine() {
+        let mock_exit = Arc::new(AtomicBool::new(false));
+        let (mock_block_engine, mock_hdls) = mock_block_engine::start_mock_block_engine(
+            &MockBlockEngineArgs {
+                bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                pps: 1_000,
+            },
+            mock_exit.clone(),
+        )
+        .unwrap();
+        let auth_keypair = std::env::temp_dir().join(format!(
+            "test_mock_block_engine_keypair_{}.json",
+            std::process::id()
+        ));
+        write_keypair_file(&Keypair::new(), &auth_keypair).unwrap();
+        let (dest, dest_addr) = dest_socket();
+        let block_engine_url = format!("http://{}", mock_block_engine.local_addr());
+        let mut proxy = ShredstreamProxyBuilder::shredstream(ShredstreamArgs {
+            block_engine_url: vec![block_engine_url],
+            block_engine_failover_threshold: 3,
+            block_engine_primary_retry_secs: 600,
+            auth_url: None,
+            auth_keypair: Some(auth_keypair.clone()),
+            auth_keypair_base58: None,
+            auth_keypair_stdin: false,
+            desired_regions: vec!["mock".to_string()],
+            list_regions: false,
+            region_ports: false,
+            stall_timeout_secs: 0,
+            ready_heartbeat_max_age_secs: 30,
+            token_refresh_margin_secs: 60,
+            token_cache_file: None,
+            initial_auth_jitter_ms: 0,
+            grpc_connect_timeout_ms: None,
+            grpc_keepalive_interval_ms: None,
+            grpc_keepalive_timeout_ms: None,
+            grpc_tls_ca_cert: None,
+            common_args: CommonArgs {
+                src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                src_bind_port: 0,
+                // skip fetching the public ip
+                public_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                dest_ip_ports: vec![(dest_addr, dest_addr.to_string())],
+                dest_resolve_interval_secs: 0,
+                num_threads: Some(1),
+                ..Default::default()
+            },
+        })
+        .build()
+        .unwrap();
+        proxy.start().unwrap();
+        let src_bind_port = proxy.listen_ports()[0];
+
+        // the proxy authenticates and registers, then receives synthetic shreds and forwards them
+        let payload = recv_payload(&dest);
+        assert_eq!(
+            shred::get_shred_type(&payload),
+            Some(shred::ShredType::Data)
+        );
+        assert_eq!(
+            mock_block_engine.registered(),
+            vec![SocketAddr::from(([127, 0, 0, 1], src_bind_port))]
+        );
+        assert!(mock_block_engine.heartbeats() > 0);
+        assert!(mock_block_engine.shreds_sent() > 0);
+        let metrics = proxy.metrics();
+        metrics.aggregate_threads();
+        assert!(
+            metrics
+                .successful_heartbeat_cumulative
+                .load(Ordering::Relaxed)
+                > 0
+        );
+        assert!(
+            metrics.agg_received.load(Ordering::Relaxed)
+                + metrics.agg_received_cumulative.load(Ordering::Relaxed)
+                > 0
+        );
+
+        proxy.shutdown();
+        mock_exit.store(true, Ordering::Relaxed);
+        for hdl in mock_hdls {
+            hdl.join().unwrap();
+        }
+        fs::remove_file(auth_keypair).unwrap();
+    }
+
+    #[test]
+    fn test_heartbeat_advertises_bound_port() {
+        let mock_exit = Arc::new(AtomicBool::new(false));
+        let (mock_block_engine, mock_hdls) = mock_block_engine::start_mock_block_engine(
+            &MockBlockEngineArgs {
+                bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                pps: 1_000,
+            },
+            mock_exit.clone(),
+        )
+        .unwrap();
+        let auth_keypair = std::env::temp_dir().join(format!(
+            "test_bound_port_keypair_{}.json",
+            std::process::id()
+        ));
+        write_keypair_file(&Keypair::new(), &auth_keypair).unwrap();
+        let (dest, dest_addr) = dest_socket();
+        let proxy_builder = |src_bind_port| {
+            ShredstreamProxyBuilder::shredstream(ShredstreamArgs {
+                block_engine_url: vec![format!("http://{}", mock_block_engine.local_addr())],
+                block_engine_failover_threshold: 3,
+                block_engine_primary_retry_secs: 600,
+                auth_url: None,
+                auth_keypair: Some(auth_keypair.clone()),
+                auth_keypair_base58: None,
+                auth_keypair_stdin: false,
+                desired_regions: vec!["mock".to_string()],
+                list_regions: false,
+                region_ports: false,
+                stall_timeout_secs: 0,
+                ready_heartbeat_max_age_secs: 30,
+                token_refresh_margin_secs: 60,
+                token_cache_file: None,
+                initial_auth_jitter_ms: 0,
+                grpc_connect_timeout_ms: None,
+                grpc_keepalive_interval_ms: None,
+                grpc_keepalive_timeout_ms: None,
+                grpc_tls_ca_cert: None,
+                common_args: CommonArgs {
+                    src_bind_addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                    src_bind_port,
+                    // skip fetching the public ip
+                    public_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                    dest_ip_ports: vec![(dest_addr, dest_addr.to_string())],
+                    dest_resolve_interval_secs: 0,
+                    num_threads: Some(1),
+                    ..Default::default()
+                },
+            })
+        };
+
+        // a port in use fails startup before anything is registered
+        let in_use = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut proxy = proxy_builder(in_use.local_addr().unwrap().port())
+            .build()
+            .unwrap();
+        assert!(proxy.start().is_err());
+        assert!(mock_block_engine.registered().is_empty());
+        assert_eq!(mock_block_engine.heartbeats(), 0);
+
+        // port 0 advertises the ephemeral port bound
+        let mut proxy = proxy_builder(0).build().unwrap();
+        proxy.start().unwrap();
+        let payload = recv_payload(&dest);
+        assert_eq!(
+            shred::get_shred_type(&payload),
+            Some(shred::ShredType::Data)
+        );
+        let bound_port = proxy.listen_ports()[0];
+        assert_ne!(bound_port, 0);
+        assert_eq!(
+            mock_block_engine.registered(),
+            vec![SocketAddr::from(([127, 0, 0, 1], bound_port))]
+        );
+
+        proxy.shutdown();
+        mock_exit.store(true, Ordering::Relaxed);
+        for hdl in mock_hdls {
+            hdl.join().unwrap();
+        }
+        fs::remove_file(auth_keypair).unwrap();
+    }
+
+    #[test]
+    fn test_build_rejects_invalid_args() {
+        let common_args = CommonArgs {
+            dest_ip_ports: vec![(
+                SocketAddr::from(([127, 0, 0, 1], 8001)),
+                "127.0.0.1:8001".to_string(),
+            )],
+            ..Default::default()
+        };
+        // no destinations
+        assert!(matches!(
+            ShredstreamProxyBuilder::forward_only(CommonArgs::default()).build(),
+            Err(ShredstreamProxyError::InvalidArguments(_))
+        ));
+        assert!(matches!(
+            ShredstreamProxyBuilder::replay(ReplayArgs {
+                input: PathBuf::from("capture.pcap"),
+                speed: -1.0,
+                loop_replay: false,
+                common_args: common_args.clone(),
+            })
+            .build(),
+            Err(ShredstreamProxyError::InvalidArguments(_))
+        ));
+        // files opened after chrooting must resolve inside it
+        assert!(matches!(
+            ShredstreamProxyBuilder::forward_only(CommonArgs {
+                run_as_user: Some("nobody".to_string()),
+                chroot: Some(std::env::temp_dir()),
+                record_pcap: Some(PathBuf::from("/nonexistent/capture.pcap")),
+                ..common_args.clone()
+            })
+            .build(),
+            Err(ShredstreamProxyError::InvalidArguments(e)) if e.contains("--record-pcap")
+        ));
+        // config reload only applies to modes loaded from a config file
+        let (_reload_sender, reload_receiver) = crossbeam_channel::bounded(1);
+        assert!(matches!(
+            ShredstreamProxyBuilder::replay(ReplayArgs {
+                input: PathBuf::from("capture.pcap"),
+                speed: 1.0,
+                loop_replay: false,
+                common_args,
+            })
+            .config_reload(PathBuf::from("config.toml"), None, reload_receiver)
+            .build(),
+            Err(ShredstreamProxyError::InvalidArguments(_))
+        ));
+    }
+
+    #[test]
+    fn test_validate() {
+        let block_engine = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dest_addr = SocketAddr::from(([127, 0, 0, 1], 8001));
+        let builder = ShredstreamProxyBuilder::shredstream(ShredstreamArgs {
+            block_engine_url: vec![format!("http://{}", block_engine.local_addr().unwrap())],
+            block_engine_failover_threshold: 3,
+            block_engine_primary_retry_secs: 600,
+            auth_url: None,
+            auth_keypair: Some(PathBuf::from("/nonexistent/keypair.json")),
+            auth_keypair_base58: None,
+            auth_keypair_stdin: false,
+            desired_regions: vec![],
+            list_regions: false,
+            region_ports: false,
+            stall_timeout_secs: 0,
+            ready_heartbeat_max_age_secs: 30,
+            token_refresh_margin_secs: 60,
+            token_cache_file: None,
+            initial_auth_jitter_ms: 0,
+            grpc_connect_timeout_ms: None,
+            grpc_keepalive_interval_ms: None,
+            grpc_keepalive_timeout_ms: None,
+            grpc_tls_ca_cert: None,
+            common_args: CommonArgs {
+                dest_ip_ports: vec![(dest_addr, "localhost:8001".to_string())],
+                ..Default::default()
+            },
+        });
+
+        let report = builder.validate(Some(Duration::from_secs(1)));
+        let outcomes = report
+            .checks
+            .iter()
+            .map(|check| {
+                (
+                    check.name.as_str(),
+                    matches!(check.outcome, CheckOutcome::Passed(_)),
+                )
+            })
+            .collect::<Vec<_>>();
+        let block_engine_check = format!("block engine {}", block_engine.local_addr().unwrap());
+        assert_eq!(
+            outcomes,
+            vec![
+                ("destinations", true),
+                ("common args", true),
+                ("block engine args", false),
+                ("grpc channel", true),
+                ("auth keypair", false),
+                ("core affinity", true),
+                ("dscp", true),
+                ("egress", true),
+                ("metrics backend", true),
+                ("destination localhost:8001", true),
+                (block_engine_check.as_str(), true),
+            ]
+        );
+        // build fails on the first check that failed
+        assert!(matches!(
+            builder.build(),
+            Err(ShredstreamProxyError::InvalidArguments(e)) if e.contains("--desired-regions")
+        ));
+    }
+}
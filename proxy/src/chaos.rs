@@ -0,0 +1,380 @@
+use std::{
+    collections::HashMap,
+    io, mem,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use rand::Rng;
+
+use crate::forwarder::{SendSocketOptions, ShredMetrics, ShredSink, UdpSink};
+
+/// Resolution of the delay wheel, and how often the chaos thread sends what's due
+const WHEEL_TICK: Duration = Duration::from_millis(1);
+/// Reorder buffers untouched for this long are flushed, so the tail of a burst isn't held until the next one
+const REORDER_IDLE_FLUSH: Duration = Duration::from_millis(50);
+
+/// Faults injected into sends to UDP destinations, for testing how consumers cope with a bad network
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChaosConfig {
+    /// Fraction of packets dropped
+    pub drop_rate: f64,
+    /// Fraction of packets sent twice
+    pub duplicate_rate: f64,
+    /// Packets held per destination and released in random order, 0 keeps them in order
+    pub reorder_window: usize,
+    /// Added to every packet's send time
+    pub delay: Duration,
+    /// Destinations faults are injected for, empty for every UDP destination
+    pub dests: Vec<SocketAddr>,
+}
+
+impl ChaosConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.drop_rate > 0.0
+            || self.duplicate_rate > 0.0
+            || self.reorder_window > 0
+            || !self.delay.is_zero()
+    }
+}
+
+/// Timing wheel of [WHEEL_TICK] slots spanning the delay, so scheduling and expiring a packet don't depend on how many are queued
+struct DelayWheel {
+    slots: Vec<Vec<(SocketAddr, Vec<u8>)>>,
+    start: Instant,
+    /// Next tick since `start` to send, earlier slots are already empty
+    cursor: u64,
+}
+
+impl DelayWheel {
+    fn new(delay: Duration, now: Instant) -> Self {
+        // one extra slot so a full delay never wraps onto the slot being sent
+        let num_slots = (delay.as_nanos() / WHEEL_TICK.as_nanos()) as usize + 2;
+        Self {
+            slots: (0..num_slots).map(|_| vec![]).collect(),
+            start: now,
+            cursor: 0,
+        }
+    }
+
+    fn tick_of(&self, instant: Instant) -> u64 {
+        (instant.saturating_duration_since(self.start).as_nanos() / WHEEL_TICK.as_nanos()) as u64
+    }
+
+    fn schedule(&mut self, due: Instant, dest: SocketAddr, packet: Vec<u8>) {
+        let num_slots = self.slots.len() as u64;
+        let tick = self
+            .tick_of(due)
+            .clamp(self.cursor, self.cursor + num_slots - 1);
+        self.slots[(tick % num_slots) as usize].push((dest, packet));
+    }
+
+    /// Removes the packets due by `now`, in the order they're due
+    fn advance(&mut self, now: Instant) -> Vec<(SocketAddr, Vec<u8>)> {
+        let now_tick = self.tick_of(now);
+        let num_slots = self.slots.len() as u64;
+        let mut due = vec![];
+        // after a stall, every slot is due at most once
+        let first_tick = self.cursor.max((now_tick + 1).saturating_sub(num_slots));
+        for tick in first_tick..=now_tick {
+            due.append(&mut self.slots[(tick % num_slots) as usize]);
+        }
+        self.cursor = self.cursor.max(now_tick + 1);
+        due
+    }
+}
+
+/// Packets held for reordering, and when one was last added
+type ReorderBuffer = (Vec<Vec<u8>>, Instant);
+
+/// Drops, duplicates, reorders, and delays packets on their way to UDP destinations, see `--chaos-*`
+pub struct ChaosInjector {
+    config: ChaosConfig,
+    reorder_buffers: Mutex<HashMap<SocketAddr, ReorderBuffer>>,
+    wheel: Mutex<DelayWheel>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl ChaosInjector {
+    pub fn new(config: ChaosConfig, metrics: Arc<ShredMetrics>) -> Self {
+        Self {
+            wheel: Mutex::new(DelayWheel::new(config.delay, Instant::now())),
+            config,
+            reorder_buffers: Mutex::default(),
+            metrics,
+        }
+    }
+
+    pub fn applies_to(&self, dest: &SocketAddr) -> bool {
+        self.config.dests.is_empty() || self.config.dests.contains(dest)
+    }
+
+    /// Injects faults into `packets`, sending the ones not held back through `sink`.
+    /// Held packets are sent by the chaos thread once due
+    pub fn send(&self, sink: &dyn ShredSink, dest: SocketAddr, packets: &[&[u8]], now: Instant) {
+        let mut rng = rand::thread_rng();
+        let mut survivors = Vec::with_capacity(packets.len());
+        for packet in packets {
+            if self.config.drop_rate > 0.0 && rng.gen_bool(self.config.drop_rate) {
+                self.metrics.chaos_dropped.fetch_add(1, Ordering::Relaxed);
+                continue;
+            }
+            survivors.push(*packet);
+            if self.config.duplicate_rate > 0.0 && rng.gen_bool(self.config.duplicate_rate) {
+                self.metrics
+                    .chaos_duplicated
+                    .fetch_add(1, Ordering::Relaxed);
+                survivors.push(*packet);
+            }
+        }
+        if self.config.reorder_window == 0 && self.config.delay.is_zero() {
+            sink.send(dest, &survivors);
+            return;
+        }
+        let released = match self.config.reorder_window {
+            0 => survivors.iter().map(|packet| packet.to_vec()).collect(),
+            window => {
+                let mut reorder_buffers = self.reorder_buffers.lock().unwrap();
+                let (held, last_added) = reorder_buffers.entry(dest).or_insert((vec![], now));
+                *last_added = now;
+                held.extend(survivors.iter().map(|packet| packet.to_vec()));
+                let mut released = vec![];
+                while held.len() > window {
+                    // anything but the oldest packet overtakes it
+                    let i = rng.gen_range(0..held.len());
+                    if i != 0 {
+                        self.metrics.chaos_reordered.fetch_add(1, Ordering::Relaxed);
+                    }
+                    released.push(held.remove(i));
+                }
+                released
+            }
+        };
+        self.release(sink, dest, released, now);
+    }
+
+    /// Sends `packets` now, or schedules them on the wheel when delaying
+    fn release(&self, sink: &dyn ShredSink, dest: SocketAddr, packets: Vec<Vec<u8>>, now: Instant) {
+        if packets.is_empty() {
+            return;
+        }
+        if self.config.delay.is_zero() {
+            let packets = packets.iter().map(Vec::as_slice).collect::<Vec<_>>();
+            sink.send(dest, &packets);
+            return;
+        }
+        self.metrics
+            .chaos_delayed
+            .fetch_add(packets.len() as u64, Ordering::Relaxed);
+        let mut wheel = self.wheel.lock().unwrap();
+        for packet in packets {
+            wheel.schedule(now + self.config.delay, dest, packet);
+        }
+    }
+
+    /// Flushes idle reorder buffers and sends the delayed packets due by `now`
+    fn tick(&self, sink: &dyn ShredSink, now: Instant) {
+        let idle = self
+            .reorder_buffers
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|(_, (held, last_added))| {
+                !held.is_empty() && now.duration_since(*last_added) >= REORDER_IDLE_FLUSH
+            })
+            .map(|(dest, (held, _))| (*dest, mem::take(held)))
+            .collect::<Vec<_>>();
+        for (dest, held) in idle {
+            self.release(sink, dest, held, now);
+        }
+
+        let due = self.wheel.lock().unwrap().advance(now);
+        // grouped per destination, keeping each destination's order
+        let mut due_per_dest = HashMap::<SocketAddr, Vec<&[u8]>>::new();
+        for (dest, packet) in &due {
+            due_per_dest.entry(*dest).or_default().push(packet);
+        }
+        for (dest, packets) in due_per_dest {
+            sink.send(dest, &packets);
+        }
+    }
+}
+
+/// Sends packets held back by `chaos` once due, from its own socket
+pub(crate) fn start_chaos_thread(
+    chaos: Arc<ChaosInjector>,
+    send_socket_options: SendSocketOptions,
+    send_batch_size: usize,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let socket = send_socket_options.bind()?;
+    warn!(
+        "Injecting faults into sends for testing: {:?}. Never use this in production.",
+        chaos.config
+    );
+    Builder::new()
+        .name("ssPxyChaos".to_string())
+        .spawn(move || {
+            let udp_sink = UdpSink::new(socket, send_batch_size, None, chaos.metrics.clone());
+            let tick = crossbeam_channel::tick(WHEEL_TICK);
+            while !exit.load(Ordering::Relaxed) {
+                if tick.recv().is_err() {
+                    break;
+                }
+                chaos.tick(&udp_sink, Instant::now());
+            }
+            info!("Exiting chaos thread.");
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        net::SocketAddr,
+        str::FromStr,
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        chaos::{ChaosConfig, ChaosInjector, DelayWheel, REORDER_IDLE_FLUSH},
+        forwarder::{ShredMetrics, ShredSink},
+    };
+
+    #[derive(Default)]
+    struct RecordingSink(RefCell<Vec<(SocketAddr, Vec<u8>)>>);
+
+    impl ShredSink for RecordingSink {
+        fn send(&self, dest: SocketAddr, packets: &[&[u8]]) {
+            self.0
+                .borrow_mut()
+                .extend(packets.iter().map(|packet| (dest, packet.to_vec())));
+        }
+    }
+
+    fn packets(n: u8) -> Vec<Vec<u8>> {
+        (0..n).map(|i| vec![i]).collect()
+    }
+
+    fn send_all(chaos: &ChaosInjector, sink: &RecordingSink, dest: SocketAddr, now: Instant) {
+        let packets = packets(100);
+        let packets = packets.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        chaos.send(sink, dest, &packets, now);
+    }
+
+    #[test]
+    fn test_drop_and_duplicate() {
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let metrics = Arc::new(ShredMetrics::new());
+        let chaos = ChaosInjector::new(
+            ChaosConfig {
+                drop_rate: 0.2,
+                duplicate_rate: 0.2,
+                ..Default::default()
+            },
+            metrics.clone(),
+        );
+        let sink = RecordingSink::default();
+        send_all(&chaos, &sink, dest, Instant::now());
+
+        let dropped = metrics.chaos_dropped.load(Ordering::Relaxed);
+        let duplicated = metrics.chaos_duplicated.load(Ordering::Relaxed);
+        assert!(dropped > 0 && duplicated > 0);
+        assert_eq!(sink.0.borrow().len() as u64, 100 - dropped + duplicated);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let metrics = Arc::new(ShredMetrics::new());
+        let chaos = ChaosInjector::new(
+            ChaosConfig {
+                reorder_window: 5,
+                ..Default::default()
+            },
+            metrics.clone(),
+        );
+        let sink = RecordingSink::default();
+        let now = Instant::now();
+        send_all(&chaos, &sink, dest, now);
+
+        // the window is held back until idle
+        assert_eq!(sink.0.borrow().len(), 95);
+        assert!(metrics.chaos_reordered.load(Ordering::Relaxed) > 0);
+        chaos.tick(&sink, now + REORDER_IDLE_FLUSH);
+        let mut sent = sink.0.take();
+        assert_ne!(
+            sent.iter()
+                .map(|(_, packet)| packet.clone())
+                .collect::<Vec<_>>(),
+            packets(100)
+        );
+        sent.sort();
+        assert_eq!(
+            sent,
+            packets(100)
+                .into_iter()
+                .map(|packet| (dest, packet))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_delay() {
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let metrics = Arc::new(ShredMetrics::new());
+        let chaos = ChaosInjector::new(
+            ChaosConfig {
+                delay: Duration::from_millis(3),
+                dests: vec![dest],
+                ..Default::default()
+            },
+            metrics.clone(),
+        );
+        assert!(!chaos.applies_to(&SocketAddr::from_str("10.0.0.2:8001").unwrap()));
+        let sink = RecordingSink::default();
+        let now = Instant::now();
+        send_all(&chaos, &sink, dest, now);
+
+        assert!(sink.0.borrow().is_empty());
+        assert_eq!(metrics.chaos_delayed.load(Ordering::Relaxed), 100);
+        chaos.tick(&sink, now + Duration::from_millis(1));
+        assert!(sink.0.borrow().is_empty());
+        chaos.tick(&sink, now + Duration::from_millis(4));
+        // delaying alone keeps the order
+        assert_eq!(
+            sink.0.take(),
+            packets(100)
+                .into_iter()
+                .map(|packet| (dest, packet))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_delay_wheel() {
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut wheel = DelayWheel::new(Duration::from_millis(3), start);
+        wheel.schedule(ms(3), dest, vec![1]);
+        wheel.schedule(ms(2), dest, vec![2]);
+        assert!(wheel.advance(ms(1)).is_empty());
+        assert_eq!(wheel.advance(ms(2)), vec![(dest, vec![2])]);
+        // scheduled in the past, so sent on the next advance
+        wheel.schedule(ms(0), dest, vec![3]);
+        // after a stall, everything left is sent once
+        assert_eq!(
+            wheel.advance(ms(100)),
+            vec![(dest, vec![1]), (dest, vec![3])]
+        );
+        assert!(wheel.advance(ms(200)).is_empty());
+    }
+}
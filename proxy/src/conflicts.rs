@@ -0,0 +1,292 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use log::{info, warn};
+use solana_sdk::clock::Slot;
+
+use crate::{
+    forwarder::ShredMetrics,
+    metrics_backend::datapoint_warn,
+    shred::{self, ShredType},
+};
+
+/// Batches queued for the detector thread before new ones are dropped
+const CONFLICT_CHANNEL_CAPACITY: usize = 1_024;
+/// Data and code shreds a slot can have, so a slot's map stays bounded even with bogus indices
+const MAX_SHREDS_PER_SLOT: usize = 2 * 32_768;
+
+/// How many slots to compare shreds over, and where to write conflicting payloads
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConflictDetectorConfig {
+    pub max_slots: usize,
+    pub payload_dir: Option<PathBuf>,
+}
+
+/// Copies deduped shreds from forwarders to the conflict detector thread
+#[derive(Clone)]
+pub struct ConflictTap {
+    shred_sender: Sender<Vec<Vec<u8>>>,
+}
+
+impl ConflictTap {
+    pub fn send(&self, packets: &[&[u8]]) {
+        if packets.is_empty() {
+            return;
+        }
+        // drop instead of blocking forwarding when the detector falls behind
+        let _ = self
+            .shred_sender
+            .try_send(packets.iter().map(|packet| packet.to_vec()).collect());
+    }
+
+    /// Like [Self::send] for copies already made, such as packets dropped as duplicates by shred id
+    pub fn send_owned(&self, packets: Vec<Vec<u8>>) {
+        if packets.is_empty() {
+            return;
+        }
+        let _ = self.shred_sender.try_send(packets);
+    }
+}
+
+struct SeenShred {
+    payload_hash: u64,
+    /// Kept only when writing conflicting payloads, taken once a conflict is reported
+    payload: Option<Vec<u8>>,
+    /// Already reported, so later payloads for the shred aren't reported again
+    conflicted: bool,
+}
+
+/// Two payloads received for the same shred
+#[derive(Debug, PartialEq, Eq)]
+struct Conflict {
+    slot: Slot,
+    index: u32,
+    shred_type: ShredType,
+    /// None unless payloads are kept
+    first_payload: Option<Vec<u8>>,
+}
+
+/// Remembers the first payload hash of each shred in the most recent slots
+struct ConflictDetector {
+    max_slots: usize,
+    keep_payloads: bool,
+    slots: BTreeMap<Slot, HashMap<(u32, ShredType), SeenShred>>,
+}
+
+impl ConflictDetector {
+    fn new(max_slots: usize, keep_payloads: bool) -> Self {
+        Self {
+            max_slots,
+            keep_payloads,
+            slots: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the conflict if `packet` is a shred already seen with a different payload, at most once per shred
+    fn check(&mut self, packet: &[u8]) -> Option<Conflict> {
+        let shred_type = shred::get_shred_type(packet)?;
+        let slot = shred::get_slot(packet)?;
+        let index = shred::get_index(packet)?;
+        let mut hasher = DefaultHasher::new();
+        packet.hash(&mut hasher);
+        let payload_hash = hasher.finish();
+
+        if !self.slots.contains_key(&slot) {
+            // too old to track once every tracked slot is newer
+            if self.slots.len() >= self.max_slots
+                && self
+                    .slots
+                    .first_key_value()
+                    .is_some_and(|(lowest, _)| slot < *lowest)
+            {
+                return None;
+            }
+            self.slots.insert(slot, HashMap::new());
+            while self.slots.len() > self.max_slots {
+                self.slots.pop_first();
+            }
+        }
+        let shreds = self.slots.get_mut(&slot)?;
+        match shreds.get_mut(&(index, shred_type)) {
+            Some(seen) if seen.payload_hash != payload_hash && !seen.conflicted => {
+                seen.conflicted = true;
+                Some(Conflict {
+                    slot,
+                    index,
+                    shred_type,
+                    first_payload: seen.payload.take(),
+                })
+            }
+            Some(_) => None,
+            None => {
+                if shreds.len() < MAX_SHREDS_PER_SLOT {
+                    shreds.insert(
+                        (index, shred_type),
+                        SeenShred {
+                            payload_hash,
+                            payload: self.keep_payloads.then(|| packet.to_vec()),
+                            conflicted: false,
+                        },
+                    );
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Writes both payloads of a conflict to `dir`, named `<slot>-<index>-<data|code>-<first|second>.bin`
+fn write_conflict(dir: &Path, conflict: &Conflict, second_payload: &[u8]) -> io::Result<()> {
+    let shred_type = match conflict.shred_type {
+        ShredType::Data => "data",
+        ShredType::Code => "code",
+    };
+    let prefix = format!("{}-{}-{shred_type}", conflict.slot, conflict.index);
+    if let Some(first_payload) = &conflict.first_payload {
+        fs::write(dir.join(format!("{prefix}-first.bin")), first_payload)?;
+    }
+    fs::write(dir.join(format!("{prefix}-second.bin")), second_payload)
+}
+
+/// Compares shreds sent through the returned tap against earlier shreds with the same (slot, index, type),
+/// logging and counting those with a different payload, a sign of duplicate block production.
+/// Batches are dropped when the detector falls behind, so conflicts may be missed under load
+pub fn start_conflict_detector_thread(
+    config: ConflictDetectorConfig,
+    metrics: Arc<ShredMetrics>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(ConflictTap, JoinHandle<()>)> {
+    if let Some(payload_dir) = &config.payload_dir {
+        fs::create_dir_all(payload_dir)?;
+    }
+    info!(
+        "Detecting conflicting shreds over the last {} slots.",
+        config.max_slots
+    );
+    let (shred_sender, shred_receiver) =
+        crossbeam_channel::bounded::<Vec<Vec<u8>>>(CONFLICT_CHANNEL_CAPACITY);
+
+    let hdl = Builder::new()
+        .name("ssPxyConflicts".to_string())
+        .spawn(move || {
+            let mut detector =
+                ConflictDetector::new(config.max_slots, config.payload_dir.is_some());
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(shred_receiver) -> maybe_shreds => {
+                        let Ok(shreds) = maybe_shreds else {
+                            break;
+                        };
+                        for shred in &shreds {
+                            let Some(conflict) = detector.check(shred) else {
+                                continue;
+                            };
+                            warn!(
+                                "Conflicting {:?} shred for slot {} index {}, possible duplicate block.",
+                                conflict.shred_type, conflict.slot, conflict.index
+                            );
+                            metrics.conflicting_shreds.fetch_add(1, Ordering::Relaxed);
+                            datapoint_warn!(
+                                "shredstream_proxy-conflicting_shred",
+                                ("slot", conflict.slot, i64),
+                                ("index", conflict.index, i64),
+                                ("is_data", conflict.shred_type == ShredType::Data, bool),
+                            );
+                            if let Some(payload_dir) = &config.payload_dir {
+                                if let Err(e) = write_conflict(payload_dir, &conflict, shred) {
+                                    warn!("Failed to write conflicting shred payloads to {payload_dir:?}. Error: {e}");
+                                }
+                            }
+                        }
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            info!("Exiting conflict detector thread.");
+        })?;
+    Ok((ConflictTap { shred_sender }, hdl))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        conflicts::{write_conflict, Conflict, ConflictDetector},
+        shred::{tests::new_data_shred, ShredType},
+    };
+
+    #[test]
+    fn test_conflict_detector() {
+        let mut detector = ConflictDetector::new(2, true);
+        let shred = new_data_shred(10, 3, 0, false, b"entries");
+        let conflicting = new_data_shred(10, 3, 0, false, b"other entries");
+
+        // retransmitted copies aren't conflicts, a different payload is reported once
+        assert_eq!(detector.check(&shred), None);
+        assert_eq!(detector.check(&shred), None);
+        assert_eq!(
+            detector.check(&conflicting),
+            Some(Conflict {
+                slot: 10,
+                index: 3,
+                shred_type: ShredType::Data,
+                first_payload: Some(shred.clone()),
+            })
+        );
+        assert_eq!(detector.check(&conflicting), None);
+        assert_eq!(detector.check(b"not a shred"), None);
+
+        // only the most recent slots are tracked, older ones are ignored
+        assert_eq!(detector.check(&new_data_shred(11, 3, 0, false, b"a")), None);
+        assert_eq!(detector.check(&new_data_shred(12, 3, 0, false, b"a")), None);
+        assert_eq!(detector.slots.keys().copied().collect::<Vec<_>>(), [11, 12]);
+        assert_eq!(detector.check(&new_data_shred(10, 4, 0, false, b"a")), None);
+        assert_eq!(detector.check(&new_data_shred(10, 4, 0, false, b"b")), None);
+        assert!(detector
+            .check(&new_data_shred(12, 3, 0, false, b"b"))
+            .is_some());
+    }
+
+    #[test]
+    fn test_write_conflict() {
+        let dir = std::env::temp_dir().join(format!(
+            "shredstream_proxy_conflicts_{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let conflict = Conflict {
+            slot: 10,
+            index: 3,
+            shred_type: ShredType::Code,
+            first_payload: Some(b"first".to_vec()),
+        };
+        write_conflict(&dir, &conflict, b"second").unwrap();
+        assert_eq!(fs::read(dir.join("10-3-code-first.bin")).unwrap(), b"first");
+        assert_eq!(
+            fs::read(dir.join("10-3-code-second.bin")).unwrap(),
+            b"second"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
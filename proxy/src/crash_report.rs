@@ -0,0 +1,141 @@
+use std::{
+    backtrace::Backtrace,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    panic::PanicInfo,
+    path::Path,
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, warn};
+
+use crate::{
+    metrics_backend::{self, datapoint_error},
+    supervisor::panic_message,
+};
+
+/// A panic that brings the proxy down, written to `--crash-report-file`
+#[derive(Debug)]
+pub struct CrashReport {
+    pub thread: String,
+    pub message: String,
+    pub location: Option<String>,
+    pub unix_secs: u64,
+    pub backtrace: String,
+}
+
+impl CrashReport {
+    /// Captures the current thread's name and backtrace, regardless of `RUST_BACKTRACE`
+    pub fn capture(panic_info: &PanicInfo) -> Self {
+        Self {
+            thread: thread::current().name().unwrap_or("<unnamed>").to_string(),
+            message: panic_message(panic_info.payload()),
+            location: panic_info.location().map(ToString::to_string),
+            unix_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            backtrace: Backtrace::force_capture().to_string(),
+        }
+    }
+
+    /// Appends the report to `path`, so reports of earlier crashes are kept
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        write!(file, "{self}")?;
+        file.sync_all()
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "panic at unix time {}", self.unix_secs)?;
+        writeln!(f, "thread: {}", self.thread)?;
+        writeln!(f, "message: {}", self.message)?;
+        if let Some(location) = &self.location {
+            writeln!(f, "location: {location}")?;
+        }
+        writeln!(f, "backtrace:\n{}", self.backtrace)
+    }
+}
+
+/// Records a panic that shuts down the proxy: logs it, writes it to `crash_report_file` if set,
+/// and reports a `shredstream_proxy-panic` datapoint, flushed before returning so it isn't lost when the process exits
+pub fn report_panic(panic_info: &PanicInfo, crash_report_file: Option<&Path>) {
+    let report = CrashReport::capture(panic_info);
+    error!(
+        "Thread {} panicked, exiting process. Error: {}",
+        report.thread, report.message
+    );
+    if let Some(path) = crash_report_file {
+        match report.write(path) {
+            Ok(()) => error!("Wrote crash report to {path:?}."),
+            Err(e) => warn!("Failed to write crash report to {path:?}. Error: {e}"),
+        }
+    }
+    datapoint_error!("shredstream_proxy-panic",
+        "thread" => report.thread,
+        ("error_str", report.message, String),
+        ("location", report.location.unwrap_or_default(), String),
+    );
+    metrics_backend::flush();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs, panic,
+        sync::{Arc, Mutex},
+        thread::Builder,
+    };
+
+    use crate::crash_report::CrashReport;
+
+    #[test]
+    fn test_crash_report() {
+        let crash_report_file =
+            std::env::temp_dir().join(format!("test_crash_report_{}", std::process::id()));
+        let path = crash_report_file.clone();
+        let captured = Arc::new(Mutex::new(None));
+        // the hook is process wide, so capture only from this test's thread and leave other panics to the default hook
+        let default_hook = Arc::new(panic::take_hook());
+        {
+            let captured = captured.clone();
+            let default_hook = default_hook.clone();
+            panic::set_hook(Box::new(move |panic_info| {
+                match std::thread::current().name() {
+                    Some("ssTestCrash") => {
+                        let report = CrashReport::capture(panic_info);
+                        report.write(&path).unwrap();
+                        *captured.lock().unwrap() = Some(report);
+                    }
+                    _ => default_hook(panic_info),
+                }
+            }));
+        }
+        let result = Builder::new()
+            .name("ssTestCrash".to_string())
+            .spawn(|| panic!("forwarder fell over"))
+            .unwrap()
+            .join();
+        panic::set_hook(Box::new(move |panic_info| default_hook(panic_info)));
+        assert!(result.is_err());
+
+        let report = captured.lock().unwrap().take().unwrap();
+        assert_eq!(report.thread, "ssTestCrash");
+        assert_eq!(report.message, "forwarder fell over");
+        assert!(report.location.unwrap().contains("crash_report.rs"));
+        assert!(report.backtrace.contains("test_crash_report"));
+
+        let written = fs::read_to_string(&crash_report_file).unwrap();
+        fs::remove_file(&crash_report_file).unwrap();
+        assert!(written.contains("thread: ssTestCrash\n"), "{written}");
+        assert!(
+            written.contains("message: forwarder fell over\n"),
+            "{written}"
+        );
+        assert!(written.contains("backtrace:\n"), "{written}");
+    }
+}
@@ -0,0 +1,380 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use jito_protos::shredstream_proxy::{
+    shredstream_proxy_server::{ShredstreamProxy, ShredstreamProxyServer},
+    Entry, SubscribeEntriesRequest,
+};
+use log::{info, warn};
+use solana_sdk::clock::Slot;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, TcpListenerStream},
+    Stream, StreamExt,
+};
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{metrics_backend::datapoint_info, shred};
+
+/// Max slots with pending shreds, the lowest slot is evicted when exceeded
+const MAX_IN_FLIGHT_SLOTS: usize = 32;
+/// Data shred indexes at or above this are ignored, bounds memory per slot
+const MAX_DATA_SHREDS_PER_SLOT: u32 = 32_768;
+/// Shred batches queued from forwarders. Batches are dropped when full, forwarding is never blocked
+const SHRED_CHANNEL_CAPACITY: usize = 1_024;
+/// Entries buffered per subscriber. Slow subscribers skip entries when full
+const ENTRY_CHANNEL_CAPACITY: usize = 1_024;
+
+/// Copies deduped shreds from forwarders to the deshred thread while any gRPC subscriber is connected
+pub struct DeshredTap {
+    shred_sender: Sender<Vec<Vec<u8>>>,
+    num_subscribers: Arc<AtomicUsize>,
+}
+
+impl DeshredTap {
+    pub fn send(&self, packets: &[&[u8]]) {
+        if packets.is_empty() || self.num_subscribers.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        // drop instead of blocking forwarding when deshredding falls behind
+        let _ = self
+            .shred_sender
+            .try_send(packets.iter().map(|packet| packet.to_vec()).collect());
+    }
+}
+
+/// Serves `SubscribeEntries` on `bind_addr` and reassembles entries from shreds sent through the returned tap.
+/// Data shreds between consecutive `DATA_COMPLETE_SHRED` flags hold a bincode serialized `Vec<Entry>`.
+/// Missing data shreds are not recovered from coding shreds, so their FEC set times out after `fec_set_timeout`.
+pub fn start_deshred_threads(
+    bind_addr: SocketAddr,
+    fec_set_timeout: Duration,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(DeshredTap, Vec<JoinHandle<()>>)> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("ssPxyGrpcRt")
+        .enable_all()
+        .build()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(bind_addr))?;
+    info!("Serving gRPC entries on {bind_addr}");
+
+    let (shred_sender, shred_receiver) =
+        crossbeam_channel::bounded::<Vec<Vec<u8>>>(SHRED_CHANNEL_CAPACITY);
+    let (entry_sender, _) = broadcast::channel(ENTRY_CHANNEL_CAPACITY);
+    let num_subscribers = Arc::new(AtomicUsize::new(0));
+    let service = ShredstreamProxyService {
+        entry_sender: entry_sender.clone(),
+        num_subscribers: num_subscribers.clone(),
+    };
+
+    let grpc_exit = exit.clone();
+    let grpc_hdl = Builder::new()
+        .name("ssPxyGrpc".to_string())
+        .spawn(move || {
+            let shutdown = async move {
+                // periodically check for exit
+                while !grpc_exit.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(500)).await;
+                }
+            };
+            if let Err(e) = runtime.block_on(
+                Server::builder()
+                    .add_service(ShredstreamProxyServer::new(service))
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown),
+            ) {
+                warn!("gRPC server exited with error: {e}");
+            }
+            info!("Exiting gRPC thread.");
+        })?;
+
+    let deshred_subscribers = num_subscribers.clone();
+    let deshred_hdl = Builder::new()
+        .name("ssPxyDeshred".to_string())
+        .spawn(move || {
+            let mut deshredder = Deshredder::new(fec_set_timeout);
+            let expire_tick = crossbeam_channel::tick(Duration::from_millis(100));
+            let metrics_tick = crossbeam_channel::tick(Duration::from_secs(30));
+            let mut num_entries_sent = 0u64;
+            let mut num_fec_sets_expired = 0u64;
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(shred_receiver) -> maybe_shreds => {
+                        let Ok(shreds) = maybe_shreds else {
+                            break;
+                        };
+                        shreds.iter().for_each(|shred| {
+                            if let Some((slot, entries)) = deshredder.insert(shred, Instant::now()) {
+                                num_entries_sent += 1;
+                                // only errors when every subscriber disconnected
+                                let _ = entry_sender.send(Entry { slot, entries });
+                            }
+                        });
+                    }
+                    recv(expire_tick) -> _ => {
+                        num_fec_sets_expired += deshredder.expire(Instant::now()) as u64;
+                    }
+                    recv(metrics_tick) -> _ => {
+                        datapoint_info!("shredstream_proxy-deshred_stats",
+                            ("subscribers", deshred_subscribers.load(Ordering::Relaxed), i64),
+                            ("entries_sent", std::mem::take(&mut num_entries_sent), i64),
+                            ("fec_sets_expired", std::mem::take(&mut num_fec_sets_expired), i64),
+                            ("in_flight_slots", deshredder.slots.len(), i64),
+                        );
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            info!("Exiting deshred thread.");
+        })?;
+
+    Ok((
+        DeshredTap {
+            shred_sender,
+            num_subscribers,
+        },
+        vec![grpc_hdl, deshred_hdl],
+    ))
+}
+
+struct ShredstreamProxyService {
+    entry_sender: broadcast::Sender<Entry>,
+    num_subscribers: Arc<AtomicUsize>,
+}
+
+/// Counts a subscriber while its stream is alive
+struct SubscriberGuard(Arc<AtomicUsize>);
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[tonic::async_trait]
+impl ShredstreamProxy for ShredstreamProxyService {
+    type SubscribeEntriesStream = Pin<Box<dyn Stream<Item = Result<Entry, Status>> + Send>>;
+
+    async fn subscribe_entries(
+        &self,
+        request: Request<SubscribeEntriesRequest>,
+    ) -> Result<Response<Self::SubscribeEntriesStream>, Status> {
+        let remote_addr = request.remote_addr();
+        info!("Entry subscriber connected from {remote_addr:?}.");
+        self.num_subscribers.fetch_add(1, Ordering::Relaxed);
+        let guard = SubscriberGuard(self.num_subscribers.clone());
+
+        let stream = BroadcastStream::new(self.entry_sender.subscribe()).filter_map(move |entry| {
+            // dropped along with the stream when the subscriber disconnects
+            let _guard = &guard;
+            match entry {
+                Ok(entry) => Some(Ok(entry)),
+                Err(BroadcastStreamRecvError::Lagged(num_skipped)) => {
+                    warn!("Entry subscriber {remote_addr:?} is lagging, skipped {num_skipped} entries.");
+                    None
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[derive(Default)]
+struct SlotShreds {
+    /// index -> (fec_set_index, data_complete, data)
+    data_shreds: BTreeMap<u32, (u32, bool, Vec<u8>)>,
+    /// end index -> start index of entry batches already sent
+    sent_batches: BTreeMap<u32, u32>,
+    /// When the first shred of each FEC set with pending shreds was received
+    fec_set_first_seen: HashMap<u32, Instant>,
+    last_updated: Option<Instant>,
+}
+
+/// Collects data shreds per slot until all shreds of an entry batch are received
+struct Deshredder {
+    slots: BTreeMap<Slot, SlotShreds>,
+    fec_set_timeout: Duration,
+}
+
+impl Deshredder {
+    fn new(fec_set_timeout: Duration) -> Self {
+        Self {
+            slots: BTreeMap::new(),
+            fec_set_timeout,
+        }
+    }
+
+    /// Returns the slot and serialized entries if `shred` completed an entry batch
+    fn insert(&mut self, shred: &[u8], now: Instant) -> Option<(Slot, Vec<u8>)> {
+        let shred = shred::get_data_shred(shred)?;
+        if shred.index >= MAX_DATA_SHREDS_PER_SLOT {
+            return None;
+        }
+        if !self.slots.contains_key(&shred.slot) && self.slots.len() >= MAX_IN_FLIGHT_SLOTS {
+            let (&lowest_slot, _) = self.slots.first_key_value()?;
+            if shred.slot < lowest_slot {
+                return None;
+            }
+            self.slots.remove(&lowest_slot);
+        }
+
+        let slot_shreds = self.slots.entry(shred.slot).or_default();
+        let already_sent = slot_shreds
+            .sent_batches
+            .range(shred.index..)
+            .next()
+            .is_some_and(|(_end, start)| *start <= shred.index);
+        if already_sent || slot_shreds.data_shreds.contains_key(&shred.index) {
+            return None;
+        }
+        slot_shreds.last_updated = Some(now);
+        slot_shreds
+            .fec_set_first_seen
+            .entry(shred.fec_set_index)
+            .or_insert(now);
+        slot_shreds.data_shreds.insert(
+            shred.index,
+            (
+                shred.fec_set_index,
+                shred.data_complete,
+                shred.data.to_vec(),
+            ),
+        );
+
+        // batch ends at the next data complete shred
+        let mut end = shred.index;
+        loop {
+            match slot_shreds.data_shreds.get(&end) {
+                Some((_, true, _)) => break,
+                Some(_) => end += 1,
+                None => return None,
+            }
+        }
+        // and starts after the previous one, or at the start of the slot
+        let mut start = shred.index;
+        while start > 0 && !slot_shreds.sent_batches.contains_key(&(start - 1)) {
+            match slot_shreds.data_shreds.get(&(start - 1)) {
+                Some((_, true, _)) => break,
+                Some(_) => start -= 1,
+                None => return None,
+            }
+        }
+
+        let mut entries = Vec::new();
+        (start..=end).for_each(|index| {
+            if let Some((_, _, data)) = slot_shreds.data_shreds.remove(&index) {
+                entries.extend_from_slice(&data);
+            }
+        });
+        slot_shreds.sent_batches.insert(end, start);
+        let data_shreds = &slot_shreds.data_shreds;
+        slot_shreds
+            .fec_set_first_seen
+            .retain(|fec_set_index, _| data_shreds.values().any(|(x, _, _)| x == fec_set_index));
+        Some((shred.slot, entries))
+    }
+
+    /// Drops pending shreds of FEC sets older than the timeout, and slots idle for longer than it.
+    /// Returns the number of FEC sets dropped.
+    fn expire(&mut self, now: Instant) -> usize {
+        let fec_set_timeout = self.fec_set_timeout;
+        let mut num_expired = 0;
+        self.slots.retain(|_slot, slot_shreds| {
+            let before = slot_shreds.fec_set_first_seen.len();
+            slot_shreds
+                .fec_set_first_seen
+                .retain(|_, first_seen| now.duration_since(*first_seen) < fec_set_timeout);
+            if slot_shreds.fec_set_first_seen.len() != before {
+                num_expired += before - slot_shreds.fec_set_first_seen.len();
+                let fec_set_first_seen = &slot_shreds.fec_set_first_seen;
+                slot_shreds.data_shreds.retain(|_, (fec_set_index, _, _)| {
+                    fec_set_first_seen.contains_key(fec_set_index)
+                });
+            }
+            slot_shreds
+                .last_updated
+                .is_some_and(|last_updated| now.duration_since(last_updated) < fec_set_timeout)
+        });
+        num_expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        deshred::{Deshredder, MAX_IN_FLIGHT_SLOTS},
+        shred::tests::new_data_shred,
+    };
+
+    #[test]
+    fn test_deshredder_reassembles_batches() {
+        let now = Instant::now();
+        let mut deshredder = Deshredder::new(Duration::from_secs(1));
+
+        // first batch is indexes 0..=2, second is 3..=3
+        assert_eq!(
+            deshredder.insert(&new_data_shred(10, 2, 0, true, &[3]), now),
+            None
+        );
+        assert_eq!(
+            deshredder.insert(&new_data_shred(10, 0, 0, false, &[1]), now),
+            None
+        );
+        assert_eq!(
+            deshredder.insert(&new_data_shred(10, 3, 3, true, &[4]), now),
+            Some((10, vec![4]))
+        );
+        assert_eq!(
+            deshredder.insert(&new_data_shred(10, 1, 0, false, &[2]), now),
+            Some((10, vec![1, 2, 3]))
+        );
+        // duplicates of sent batches are ignored
+        assert_eq!(
+            deshredder.insert(&new_data_shred(10, 3, 3, true, &[4]), now),
+            None
+        );
+        assert!(deshredder.slots[&10].data_shreds.is_empty());
+        assert!(deshredder.slots[&10].fec_set_first_seen.is_empty());
+    }
+
+    #[test]
+    fn test_deshredder_expires_and_evicts() {
+        let now = Instant::now();
+        let timeout = Duration::from_secs(1);
+        let mut deshredder = Deshredder::new(timeout);
+
+        deshredder.insert(&new_data_shred(10, 1, 0, true, &[2]), now);
+        deshredder.insert(&new_data_shred(10, 32, 32, false, &[2]), now + timeout / 2);
+        assert_eq!(deshredder.expire(now + timeout), 1);
+        assert_eq!(deshredder.slots[&10].data_shreds.len(), 1);
+        assert_eq!(deshredder.expire(now + timeout * 2), 1);
+        assert!(deshredder.slots.is_empty());
+
+        // lowest slot is evicted to bound memory
+        (0..MAX_IN_FLIGHT_SLOTS as u64 + 1).for_each(|slot| {
+            deshredder.insert(&new_data_shred(100 + slot, 1, 0, false, &[1]), now);
+        });
+        assert_eq!(deshredder.slots.len(), MAX_IN_FLIGHT_SLOTS);
+        assert!(!deshredder.slots.contains_key(&100));
+        assert_eq!(
+            deshredder.insert(&new_data_shred(99, 0, 0, true, &[1]), now),
+            None
+        );
+        assert!(!deshredder.slots.contains_key(&99));
+    }
+}
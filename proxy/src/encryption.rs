@@ -0,0 +1,439 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, ErrorKind},
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+};
+
+use arc_swap::ArcSwapOption;
+use chacha20poly1305::{AeadInPlace, Key, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+use rand::Rng;
+use solana_perf::packet::{Packet, PacketBatch};
+use solana_sdk::packet::PACKET_DATA_SIZE;
+
+use crate::{forwarder::ShredMetrics, rate_limit::split_dest_options};
+
+/// Prefix marking a destination in `dest-ip-ports` as sealed for a proxy running with `--decrypt-key-file`,
+/// eg. `enc://10.0.0.1:20000?key-file=/etc/shredstream/peer.key`. Otherwise sent over UDP like a plain destination
+pub const ENC_SCHEME: &str = "enc://";
+
+/// Random nonce starting each sealed packet
+pub const NONCE_LEN: usize = 24;
+/// Poly1305 tag ending each sealed packet
+pub const TAG_LEN: usize = 16;
+/// Largest packet a full-size shred is sealed into
+pub const MAX_SEALED_PACKET_SIZE: usize = PACKET_DATA_SIZE + NONCE_LEN + TAG_LEN;
+const KEY_LEN: usize = 32;
+
+/// Returns the key file of an `enc://` destination, or None if it isn't one or sets no key file
+pub fn parse_enc_dest(hostname_port: &str) -> Option<PathBuf> {
+    let (address, options) = split_dest_options(hostname_port);
+    address.strip_prefix(ENC_SCHEME)?;
+    options?
+        .split('&')
+        .find_map(|option| option.strip_prefix("key-file="))
+        .filter(|key_file| !key_file.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Reads a key file holding 32 bytes as 64 hex characters, eg. from `openssl rand -hex 32`.
+/// Errors never include the file's contents
+pub fn read_key(path: &Path) -> io::Result<Key> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| io::Error::new(e.kind(), format!("Failed to read key file {path:?}: {e}")))?;
+    let hex = contents.trim().as_bytes();
+    if hex.len() != KEY_LEN * 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Key file {path:?} must hold a 32 byte key as 64 hex characters."),
+        ));
+    }
+    let mut key = Key::default();
+    for (byte, digits) in key.iter_mut().zip(hex.chunks(2)) {
+        // both are hex digits, checked above
+        let digit = |c: u8| (c as char).to_digit(16).unwrap() as u8;
+        *byte = digit(digits[0]) << 4 | digit(digits[1]);
+    }
+    Ok(key)
+}
+
+/// The key read from a key file, and the one it replaced so packets sealed before a peer reloads still open
+struct KeyRing {
+    key: Key,
+    current: XChaCha20Poly1305,
+    previous: Option<XChaCha20Poly1305>,
+}
+
+/// A key file shared by the `enc://` destinations naming it, or `--decrypt-key-file`. Re-read on `SIGHUP` to rotate keys
+pub struct KeyFile {
+    path: PathBuf,
+    /// None until the file is read successfully, sealing and opening nothing meanwhile
+    keys: ArcSwapOption<KeyRing>,
+}
+
+/// There's one [KeyFile] per path, see [EncryptionKeys]
+impl PartialEq for KeyFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl KeyFile {
+    /// Reads the key at `path`, warning and holding no key if it can't be read
+    fn new(path: PathBuf) -> Self {
+        let key_file = Self {
+            path,
+            keys: ArcSwapOption::empty(),
+        };
+        if let Err(e) = key_file.reload() {
+            warn!(
+                "Dropping packets sealed with key file {:?} until it's reloaded. Error: {e}",
+                key_file.path
+            );
+        }
+        key_file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Re-reads the file, opening packets with the replaced key too until the next reload. Returns whether the key changed
+    pub fn reload(&self) -> io::Result<bool> {
+        let key = read_key(&self.path)?;
+        let keys = self.keys.load();
+        if keys.as_ref().is_some_and(|keys| keys.key == key) {
+            return Ok(false);
+        }
+        self.keys.store(Some(Arc::new(KeyRing {
+            current: XChaCha20Poly1305::new(&key),
+            previous: keys.as_ref().map(|keys| keys.current.clone()),
+            key,
+        })));
+        Ok(true)
+    }
+
+    /// Seals `packet`, appending a random nonce, the ciphertext, then the tag to `sealed`. Returns false if no key has been read
+    pub fn seal(&self, packet: &[u8], sealed: &mut Vec<u8>) -> bool {
+        let keys = self.keys.load();
+        let Some(keys) = keys.as_ref() else {
+            return false;
+        };
+        // random nonces are safe with XChaCha20's 192 bits, no counter to coordinate between threads
+        let mut nonce = XNonce::default();
+        rand::thread_rng().fill(&mut nonce[..]);
+        let start = sealed.len();
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(packet);
+        let tag = keys
+            .current
+            .encrypt_in_place_detached(&nonce, &[], &mut sealed[start + NONCE_LEN..])
+            .expect("packets to be far below the cipher's length limit");
+        sealed.extend_from_slice(&tag);
+        true
+    }
+
+    /// Opens a sealed packet into `packet`, returning the unsealed length.
+    /// None if it fails authentication with the current and previous keys, or wouldn't fit in `packet`
+    pub fn open(&self, sealed: &[u8], packet: &mut [u8]) -> Option<usize> {
+        let ciphertext_len = sealed.len().checked_sub(NONCE_LEN + TAG_LEN)?;
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (ciphertext, tag) = rest.split_at(ciphertext_len);
+        let packet = packet.get_mut(..ciphertext_len)?;
+        let keys = self.keys.load();
+        let keys = keys.as_ref()?;
+        let opened = [Some(&keys.current), keys.previous.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|cipher| {
+                packet.copy_from_slice(ciphertext);
+                cipher
+                    .decrypt_in_place_detached(
+                        XNonce::from_slice(nonce),
+                        &[],
+                        packet,
+                        Tag::from_slice(tag),
+                    )
+                    .is_ok()
+            });
+        opened.then_some(ciphertext_len)
+    }
+}
+
+/// Key files of `enc://` destinations and `--decrypt-key-file`, each read once and shared
+#[derive(Default)]
+pub struct EncryptionKeys {
+    key_files: Mutex<HashMap<PathBuf, Arc<KeyFile>>>,
+}
+
+impl EncryptionKeys {
+    /// Returns the key file at `path`, reading it on first use
+    pub fn key_file(&self, path: &Path) -> Arc<KeyFile> {
+        self.key_files
+            .lock()
+            .unwrap()
+            .entry(path.to_path_buf())
+            .or_insert_with(|| Arc::new(KeyFile::new(path.to_path_buf())))
+            .clone()
+    }
+
+    /// Re-reads every key file, keeping the current key of any that can't be read
+    pub fn reload(&self) {
+        let key_files = self.key_files.lock().unwrap();
+        for key_file in key_files.values() {
+            match key_file.reload() {
+                Ok(true) => info!("Loaded new key from {:?}.", key_file.path()),
+                Ok(false) => {}
+                Err(e) => warn!("Failed to reload key, keeping current key. Error: {e}"),
+            }
+        }
+    }
+}
+
+/// Re-reads key files whenever `reload_receiver` is notified, such as on `SIGHUP`, for proxies without a config file to reload
+pub fn start_key_reload_thread(
+    encryption_keys: Arc<EncryptionKeys>,
+    reload_receiver: Receiver<()>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    Builder::new()
+        .name("ssPxyKeyReload".to_string())
+        .spawn(move || {
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(reload_receiver) -> _ => {
+                        info!("Reloading key files.");
+                        encryption_keys.reload();
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+        })
+        .unwrap()
+}
+
+/// Reads sealed packets into `packet_batch`, up to `batch_size`, unsealing each with `key_file` and dropping those that fail authentication.
+/// Blocks until one arrives or the socket's read timeout elapses, then reads whatever else is ready. Returns how many were unsealed
+pub fn recv_sealed(
+    socket: &UdpSocket,
+    packet_batch: &mut PacketBatch,
+    batch_size: usize,
+    key_file: &KeyFile,
+    metrics: &ShredMetrics,
+) -> io::Result<usize> {
+    // a byte spare, so oversized packets are told apart from full-size ones and fail to open
+    let mut sealed = [0u8; MAX_SEALED_PACKET_SIZE + 1];
+    packet_batch.resize(batch_size, Packet::default());
+    let mut num_read = 0;
+    let mut num_unsealed = 0;
+    socket.set_nonblocking(false)?;
+    let res = loop {
+        if num_read >= batch_size {
+            break Ok(());
+        }
+        match socket.recv_from(&mut sealed) {
+            Ok((len, from)) => {
+                if num_read == 0 {
+                    if let Err(e) = socket.set_nonblocking(true) {
+                        break Err(e);
+                    }
+                }
+                num_read += 1;
+                let packet = &mut packet_batch[num_unsealed];
+                if let Some(size) = key_file.open(&sealed[..len], packet.buffer_mut()) {
+                    packet.meta_mut().size = size;
+                    packet.meta_mut().set_socket_addr(&from);
+                    num_unsealed += 1;
+                }
+            }
+            // nothing more ready
+            Err(_) if num_read > 0 => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    if num_read > 0 {
+        socket.set_nonblocking(false)?;
+    }
+    packet_batch.truncate(num_unsealed);
+    metrics
+        .decrypt_failed
+        .fetch_add((num_read - num_unsealed) as u64, Ordering::Relaxed);
+    res.map(|()| num_unsealed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::UdpSocket,
+        path::{Path, PathBuf},
+        sync::atomic::Ordering,
+        time::Duration,
+    };
+
+    use solana_perf::packet::PacketBatch;
+    use solana_sdk::packet::PACKET_DATA_SIZE;
+
+    use crate::{
+        encryption::{
+            parse_enc_dest, read_key, recv_sealed, EncryptionKeys, KeyFile, MAX_SEALED_PACKET_SIZE,
+            NONCE_LEN, TAG_LEN,
+        },
+        forwarder::ShredMetrics,
+    };
+
+    fn write_key(dir: &Path, name: &str, byte: u8) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("{}\n", format!("{byte:02x}").repeat(32))).unwrap();
+        path
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_parse_enc_dest_and_read_key() {
+        assert_eq!(
+            parse_enc_dest("enc://10.0.0.1:20000?key-file=/etc/peer.key"),
+            Some(PathBuf::from("/etc/peer.key"))
+        );
+        assert_eq!(
+            parse_enc_dest("enc://10.0.0.1:20000?rate=10pps&key-file=peer.key"),
+            Some(PathBuf::from("peer.key"))
+        );
+        assert_eq!(parse_enc_dest("enc://10.0.0.1:20000"), None);
+        assert_eq!(parse_enc_dest("10.0.0.1:20000?key-file=peer.key"), None);
+
+        let dir = temp_dir("test_read_key");
+        assert_eq!(
+            read_key(&write_key(&dir, "good.key", 0xab)).unwrap()[..],
+            [0xab; 32]
+        );
+        let short = dir.join("short.key");
+        fs::write(&short, "abcd").unwrap();
+        let not_hex = dir.join("not_hex.key");
+        fs::write(&not_hex, "zz".repeat(32)).unwrap();
+        for path in [short, not_hex, dir.join("missing.key")] {
+            let err = read_key(&path).unwrap_err().to_string();
+            // the contents never leak into errors, they may be a key with a typo
+            assert!(!err.contains("abcd") && !err.contains("zz"), "{err}");
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_seal_open_and_rotate() {
+        let dir = temp_dir("test_seal_open_and_rotate");
+        let path = write_key(&dir, "peer.key", 1);
+        let encryption_keys = EncryptionKeys::default();
+        let key_file = encryption_keys.key_file(&path);
+        assert!(std::sync::Arc::ptr_eq(
+            &key_file,
+            &encryption_keys.key_file(&path)
+        ));
+
+        let shred = [7u8; PACKET_DATA_SIZE];
+        let mut sealed = vec![];
+        assert!(key_file.seal(&shred, &mut sealed));
+        assert_eq!(sealed.len(), MAX_SEALED_PACKET_SIZE);
+        assert_ne!(&sealed[NONCE_LEN..NONCE_LEN + 16], &shred[..16]);
+        let mut resealed = vec![];
+        key_file.seal(&shred, &mut resealed);
+        assert_ne!(sealed, resealed, "nonces are random");
+
+        let mut packet = [0u8; PACKET_DATA_SIZE];
+        assert_eq!(key_file.open(&sealed, &mut packet), Some(PACKET_DATA_SIZE));
+        assert_eq!(packet, shred);
+        // tampered, truncated, or oversized packets fail
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert_eq!(key_file.open(&tampered, &mut packet), None);
+        assert_eq!(
+            key_file.open(&sealed[..NONCE_LEN + TAG_LEN - 1], &mut packet),
+            None
+        );
+        assert_eq!(key_file.open(&sealed, &mut packet[..100]), None);
+
+        // the replaced key still opens packets until the next reload
+        write_key(&dir, "peer.key", 2);
+        encryption_keys.reload();
+        let mut rotated = vec![];
+        key_file.seal(&shred, &mut rotated);
+        assert_eq!(key_file.open(&sealed, &mut packet), Some(PACKET_DATA_SIZE));
+        assert_eq!(key_file.open(&rotated, &mut packet), Some(PACKET_DATA_SIZE));
+        // an unchanged key keeps the previous one
+        assert!(!key_file.reload().unwrap());
+        assert_eq!(key_file.open(&sealed, &mut packet), Some(PACKET_DATA_SIZE));
+        write_key(&dir, "peer.key", 3);
+        encryption_keys.reload();
+        assert_eq!(key_file.open(&sealed, &mut packet), None);
+        assert_eq!(key_file.open(&rotated, &mut packet), Some(PACKET_DATA_SIZE));
+
+        // an unreadable file keeps the current key
+        fs::remove_file(&path).unwrap();
+        assert!(key_file.reload().is_err());
+        assert_eq!(key_file.open(&rotated, &mut packet), Some(PACKET_DATA_SIZE));
+
+        // a missing file seals nothing
+        let missing = encryption_keys.key_file(&dir.join("missing.key"));
+        assert!(!missing.seal(&shred, &mut sealed));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_recv_sealed() {
+        let dir = temp_dir("test_recv_sealed");
+        let encryption_keys = EncryptionKeys::default();
+        let key_file = encryption_keys.key_file(&write_key(&dir, "peer.key", 1));
+        let other_key_file = encryption_keys.key_file(&write_key(&dir, "other.key", 2));
+        let listen_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        listen_socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send = |key_file: &KeyFile, data: &[u8]| {
+            let mut sealed = vec![];
+            key_file.seal(data, &mut sealed);
+            sender
+                .send_to(&sealed, listen_socket.local_addr().unwrap())
+                .unwrap();
+        };
+        send(&key_file, &[1u8; PACKET_DATA_SIZE]);
+        send(&other_key_file, &[2u8; 100]);
+        send(&key_file, &[3u8; 100]);
+        sender
+            .send_to(&[4u8; 100], listen_socket.local_addr().unwrap())
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+
+        let metrics = ShredMetrics::new();
+        let mut packet_batch = PacketBatch::with_capacity(8);
+        assert_eq!(
+            recv_sealed(&listen_socket, &mut packet_batch, 8, &key_file, &metrics).unwrap(),
+            2
+        );
+        assert_eq!(packet_batch.len(), 2);
+        assert_eq!(packet_batch[0].data(..), Some(&[1u8; PACKET_DATA_SIZE][..]));
+        assert_eq!(packet_batch[1].data(..), Some(&[3u8; 100][..]));
+        assert_eq!(
+            packet_batch[1].meta().socket_addr(),
+            sender.local_addr().unwrap()
+        );
+        assert_eq!(metrics.decrypt_failed.load(Ordering::Relaxed), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
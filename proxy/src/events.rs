@@ -0,0 +1,437 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use solana_sdk::clock::Slot;
+
+use crate::{
+    forwarder::ShredMetrics,
+    rate_limit::RateLimiter,
+    shred,
+    socket::{self, send_addr},
+};
+
+/// Slots tracked at once, a few minutes of slots. Shreds for slots older than all of them are ignored
+const MAX_TRACKED_SLOTS: usize = 512;
+/// Events queued for the sender thread before new ones are dropped
+const EVENT_CHANNEL_CAPACITY: usize = 1_024;
+/// How often tracked slots are checked for abandonment, bounding how late `abandoned` events are
+const ABANDON_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Encoding of the datagrams sent to `--events-dest`, one event per datagram
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventsFormat {
+    /// `{"event":"first_shred","slot":1,"timestamp_us":2}`
+    #[default]
+    Json,
+    /// Bincode of the same fields: the event as a u32 (0 first_shred, 1 last_shred, 2 abandoned), then the slot and timestamp as u64s, little endian
+    Bincode,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotEventKind {
+    /// First shred of the slot received
+    FirstShred,
+    /// Data shred flagged last in slot received, the slot appears complete
+    LastShred,
+    /// No shreds for the slot within `--events-abandon-timeout-ms`, and its last shred never arrived
+    Abandoned,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SlotEvent {
+    pub event: SlotEventKind,
+    pub slot: Slot,
+    /// When the shred triggering the event was received, or the slot was found abandoned, in microseconds since the unix epoch
+    pub timestamp_us: u64,
+}
+
+impl SlotEvent {
+    fn new(event: SlotEventKind, slot: Slot, time: SystemTime) -> Self {
+        Self {
+            event,
+            slot,
+            timestamp_us: time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+        }
+    }
+
+    pub fn encode(&self, format: EventsFormat) -> Vec<u8> {
+        match format {
+            EventsFormat::Json => serde_json::to_vec(self).unwrap(),
+            EventsFormat::Bincode => bincode::serialize(self).unwrap(),
+        }
+    }
+}
+
+/// Where to send slot events and how many per second at most
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EventsConfig {
+    pub dest: SocketAddr,
+    pub format: EventsFormat,
+    pub abandon_timeout: Duration,
+    pub max_per_sec: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SlotState {
+    last_shred_time: Instant,
+    last_shred_seen: bool,
+    abandoned: bool,
+}
+
+/// Tracks the most recent slots to tell when each starts, completes, or is abandoned
+#[derive(Default)]
+struct SlotTracker {
+    slots: BTreeMap<Slot, SlotState>,
+}
+
+impl SlotTracker {
+    /// Records a shred for `slot`, pushing the events it triggers onto `events`
+    fn record(
+        &mut self,
+        slot: Slot,
+        last_in_slot: bool,
+        now: Instant,
+        events: &mut Vec<SlotEventKind>,
+    ) {
+        if !self.slots.contains_key(&slot) {
+            // too old to track once every tracked slot is newer, otherwise it would be reported as new again
+            if self.slots.len() >= MAX_TRACKED_SLOTS
+                && self
+                    .slots
+                    .first_key_value()
+                    .is_some_and(|(lowest, _)| slot < *lowest)
+            {
+                return;
+            }
+            self.slots.insert(
+                slot,
+                SlotState {
+                    last_shred_time: now,
+                    last_shred_seen: false,
+                    abandoned: false,
+                },
+            );
+            while self.slots.len() > MAX_TRACKED_SLOTS {
+                self.slots.pop_first();
+            }
+            events.push(SlotEventKind::FirstShred);
+        }
+        let state = self.slots.get_mut(&slot).unwrap();
+        state.last_shred_time = now;
+        if last_in_slot && !state.last_shred_seen {
+            state.last_shred_seen = true;
+            events.push(SlotEventKind::LastShred);
+        }
+    }
+
+    /// Returns slots without a shred for `timeout` whose last shred never arrived, each only once
+    fn abandoned(&mut self, timeout: Duration, now: Instant) -> Vec<Slot> {
+        self.slots
+            .iter_mut()
+            .filter(|(_, state)| {
+                !state.last_shred_seen
+                    && !state.abandoned
+                    && now.saturating_duration_since(state.last_shred_time) >= timeout
+            })
+            .map(|(slot, state)| {
+                state.abandoned = true;
+                *slot
+            })
+            .collect()
+    }
+}
+
+/// Turns deduped shreds from forwarders into slot events for the events thread
+pub struct EventsTap {
+    tracker: Mutex<SlotTracker>,
+    event_sender: Sender<SlotEvent>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl EventsTap {
+    pub fn record(&self, packets: &[&[u8]], received_time: SystemTime) {
+        let mut tracker = None;
+        let mut last_slot = None;
+        let mut events = vec![];
+        for packet in packets {
+            let Some(slot) = shred::get_slot(packet) else {
+                continue;
+            };
+            let last_in_slot = shred::is_last_in_slot(packet);
+            // shreds of a slot arrive together, so skip most lookups
+            if last_slot == Some(slot) && !last_in_slot {
+                continue;
+            }
+            last_slot = Some(slot);
+            let tracker = tracker.get_or_insert_with(|| self.tracker.lock().unwrap());
+            tracker.record(slot, last_in_slot, Instant::now(), &mut events);
+            for event in events.drain(..) {
+                self.send(SlotEvent::new(event, slot, received_time));
+            }
+        }
+    }
+
+    fn send(&self, event: SlotEvent) {
+        // drop instead of blocking forwarding when the events thread falls behind
+        if let Err(TrySendError::Full(_)) = self.event_sender.try_send(event) {
+            self.metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Sends slot events from the returned tap to `config.dest` as UDP datagrams: when a slot's first shred arrives,
+/// when its last shred arrives, and when it's abandoned without a last shred.
+/// Events over `config.max_per_sec`, or arriving faster than they can be sent, are dropped and counted
+pub fn start_events_thread(
+    config: EventsConfig,
+    metrics: Arc<ShredMetrics>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(Arc<EventsTap>, JoinHandle<()>)> {
+    let socket = socket::bind_send_socket()?;
+    let dest = send_addr(socket.local_addr()?.is_ipv6(), config.dest);
+    info!(
+        "Sending slot events to {} as {:?}.",
+        config.dest, config.format
+    );
+    let (event_sender, event_receiver) = crossbeam_channel::bounded(EVENT_CHANNEL_CAPACITY);
+    let events_tap = Arc::new(EventsTap {
+        tracker: Mutex::default(),
+        event_sender,
+        metrics: metrics.clone(),
+    });
+
+    let tap = events_tap.clone();
+    let hdl = Builder::new()
+        .name("ssPxyEvents".to_string())
+        .spawn(move || {
+            let rate_limiter = RateLimiter::new(config.max_per_sec, Instant::now());
+            let abandon_tick = crossbeam_channel::tick(ABANDON_CHECK_INTERVAL);
+            let mut send_failing = false;
+            let mut send = |event: SlotEvent| {
+                if rate_limiter.acquire(1, Instant::now()) == 0 {
+                    metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                match socket.send_to(&event.encode(config.format), dest) {
+                    Ok(_) => {
+                        send_failing = false;
+                        metrics.events_sent.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) => {
+                        // only logged once per outage since events are sent several times a slot
+                        if !send_failing {
+                            warn!("Failed to send slot event to {}. Error: {e}", config.dest);
+                            send_failing = true;
+                        }
+                        metrics.events_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            };
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(event_receiver) -> maybe_event => {
+                        let Ok(event) = maybe_event else {
+                            break;
+                        };
+                        send(event);
+                    }
+                    recv(abandon_tick) -> _ => {
+                        let abandoned = tap
+                            .tracker
+                            .lock()
+                            .unwrap()
+                            .abandoned(config.abandon_timeout, Instant::now());
+                        let now = SystemTime::now();
+                        for slot in abandoned {
+                            send(SlotEvent::new(SlotEventKind::Abandoned, slot, now));
+                        }
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            info!("Exiting slot events thread.");
+        })?;
+    Ok((events_tap, hdl))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::UdpSocket,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        events::{
+            start_events_thread, EventsConfig, EventsFormat, SlotEvent, SlotEventKind, SlotTracker,
+            MAX_TRACKED_SLOTS,
+        },
+        forwarder::ShredMetrics,
+        shred::tests::{new_data_shred, set_last_in_slot},
+    };
+
+    #[test]
+    fn test_slot_tracker() {
+        let mut tracker = SlotTracker::default();
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut events = vec![];
+
+        tracker.record(100, false, start, &mut events);
+        tracker.record(100, false, ms(10), &mut events);
+        assert_eq!(events, vec![SlotEventKind::FirstShred]);
+        events.clear();
+        tracker.record(101, true, ms(20), &mut events);
+        assert_eq!(
+            events,
+            vec![SlotEventKind::FirstShred, SlotEventKind::LastShred]
+        );
+        events.clear();
+        // the last shred is only reported once
+        tracker.record(101, true, ms(30), &mut events);
+        assert!(events.is_empty());
+
+        // completed slots are never abandoned, nor slots receiving shreds
+        assert!(tracker
+            .abandoned(Duration::from_millis(500), ms(500))
+            .is_empty());
+        assert_eq!(
+            tracker.abandoned(Duration::from_millis(500), ms(510)),
+            vec![100]
+        );
+        assert!(tracker
+            .abandoned(Duration::from_millis(500), ms(1_000))
+            .is_empty());
+
+        // bounded to the most recent slots, ignoring older ones
+        for slot in 1_000..1_000 + MAX_TRACKED_SLOTS as u64 {
+            tracker.record(slot, false, ms(1_000), &mut events);
+        }
+        assert_eq!(tracker.slots.len(), MAX_TRACKED_SLOTS);
+        events.clear();
+        tracker.record(100, false, ms(1_000), &mut events);
+        assert!(events.is_empty());
+        assert_eq!(tracker.slots.len(), MAX_TRACKED_SLOTS);
+    }
+
+    #[test]
+    fn test_encode_slot_event() {
+        let event = SlotEvent::new(
+            SlotEventKind::LastShred,
+            42,
+            UNIX_EPOCH + Duration::from_micros(7),
+        );
+        assert_eq!(
+            event.encode(EventsFormat::Json),
+            br#"{"event":"last_shred","slot":42,"timestamp_us":7}"#
+        );
+        let bincode = event.encode(EventsFormat::Bincode);
+        assert_eq!(
+            bincode,
+            [
+                1u32.to_le_bytes().as_slice(),
+                &42u64.to_le_bytes(),
+                &7u64.to_le_bytes()
+            ]
+            .concat()
+        );
+        assert_eq!(bincode::deserialize::<SlotEvent>(&bincode).unwrap(), event);
+    }
+
+    #[test]
+    fn test_events_thread() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let metrics = Arc::new(ShredMetrics::new());
+        let exit = Arc::new(AtomicBool::new(false));
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(1);
+        let (events_tap, hdl) = start_events_thread(
+            EventsConfig {
+                dest: receiver.local_addr().unwrap(),
+                format: EventsFormat::Json,
+                abandon_timeout: Duration::from_millis(100),
+                max_per_sec: 3,
+            },
+            metrics.clone(),
+            shutdown_receiver,
+            exit.clone(),
+        )
+        .unwrap();
+        let recv_event = || {
+            let mut buf = [0u8; 1_024];
+            let len = receiver.recv(&mut buf).unwrap();
+            serde_json::from_slice::<SlotEvent>(&buf[..len]).unwrap()
+        };
+
+        let mut last_shred = new_data_shred(10, 5, 0, true, &[]);
+        set_last_in_slot(&mut last_shred);
+        let shreds = [
+            new_data_shred(10, 0, 0, false, &[]),
+            last_shred,
+            new_data_shred(11, 0, 0, false, &[]),
+        ];
+        let packets = shreds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        events_tap.record(&packets, SystemTime::now());
+        let events = [recv_event(), recv_event(), recv_event()]
+            .map(|event| (event.event, event.slot))
+            .to_vec();
+        assert_eq!(
+            events,
+            vec![
+                (SlotEventKind::FirstShred, 10),
+                (SlotEventKind::LastShred, 10),
+                (SlotEventKind::FirstShred, 11),
+            ]
+        );
+        // slot 11 is abandoned, but the rate's used up
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while metrics.events_dropped.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(metrics.events_sent.load(Ordering::Relaxed), 3);
+        assert_eq!(metrics.events_dropped.load(Ordering::Relaxed), 1);
+
+        // and once the rate allows, later abandoned slots are sent
+        std::thread::sleep(Duration::from_secs(1));
+        events_tap.record(&[&new_data_shred(12, 0, 0, false, &[])], SystemTime::now());
+        let first_shred = recv_event();
+        assert_eq!(
+            (first_shred.event, first_shred.slot),
+            (SlotEventKind::FirstShred, 12)
+        );
+        let abandoned = recv_event();
+        assert_eq!(
+            (abandoned.event, abandoned.slot),
+            (SlotEventKind::Abandoned, 12)
+        );
+
+        shutdown_sender.send(()).unwrap();
+        hdl.join().unwrap();
+    }
+}
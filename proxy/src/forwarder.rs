@@ -1,113 +1,484 @@
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Read},
+    marker::PhantomData,
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
     panic,
+    path::PathBuf,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
-        Arc, RwLock,
+        atomic::{fence, AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
     },
     thread::{Builder, JoinHandle},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use arc_swap::ArcSwap;
-use crossbeam_channel::{Receiver, RecvError};
+use arc_swap::{ArcSwap, ArcSwapOption};
+use crossbeam_channel::{Receiver, RecvError, Sender};
 use dashmap::DashMap;
+use histogram::Histogram;
+use ipnet::IpNet;
 use itertools::Itertools;
 use jito_protos::trace_shred::TraceShred;
 use log::{debug, error, info, warn};
 use prost::Message;
-use solana_metrics::{datapoint_info, datapoint_warn};
+use reqwest::{
+    header::{HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
 use solana_perf::{
     deduper::Deduper,
-    packet::{PacketBatch, PacketBatchRecycler},
-    recycler::Recycler,
+    packet::{Packet, PacketBatch, PacketBatchRecycler, PACKETS_PER_BATCH},
 };
+use solana_sdk::clock::{Slot, DEFAULT_MS_PER_SLOT};
 use solana_streamer::{
+    packet,
     sendmmsg::{batch_send, SendPktsError},
-    streamer,
     streamer::StreamerReceiveStats,
 };
 
-use crate::{resolve_hostname_port, ShredstreamProxyError};
+use crate::{
+    admin, affinity,
+    alerts::AlertMonitor,
+    archive::ArchiveTap,
+    chaos::ChaosInjector,
+    conflicts::ConflictTap,
+    deshred::DeshredTap,
+    discovery_state::{self, SavedDiscovery},
+    encryption::{self, EncryptionKeys, KeyFile, MAX_SEALED_PACKET_SIZE},
+    events::EventsTap,
+    heartbeat::RegionHeartbeatStats,
+    isolated_send::IsolatedSendSink,
+    kafka::KafkaTap,
+    keepalive::{self, KeepaliveSender, UpstreamKeepalive},
+    memory_guard::{BufferKind, HeldBytes, MemoryGuard},
+    metrics_backend::{datapoint_info, datapoint_warn},
+    mirror::{Mirror, MirrorSink},
+    otel::{control_span, traced},
+    packet_channel::{self, DropPolicy, PacketBatchSender, ReceivedBatch},
+    parse_ip_net,
+    pcap::PcapTap,
+    priority::{self, DestPriority},
+    quic::{QuicSink, QUIC_SCHEME},
+    rate_limit::{parse_dest_options, parse_dest_rate_limit, RateLimiter},
+    resolve_hostname_port_with_family,
+    shard::{ShardGroup, ShardGroupSpec, ShardMembers},
+    shred::{self, ShredType},
+    slot_coverage::SlotCoverageTap,
+    slot_latency::SlotLatencyTap,
+    socket::{self, Egress, RebindPolicy, SocketBuffer, SocketDropCounter, SocketErrorTracker},
+    spill::SpillStore,
+    subscriber::SubscriberTap,
+    supervisor::Supervisor,
+    systemd::ThreadLiveness,
+    tunnel::{parse_tunnel_dest, TunnelDest, TunnelSink},
+    unix::{parse_unix_dest, UnixDest, UnixSink},
+    ws::WsTap,
+    AddressFamily, ShredstreamProxyError,
+};
 
-// values copied from https://github.com/solana-labs/solana/blob/33bde55bbdde13003acf45bb6afe6db4ab599ae4/core/src/sigverify_shreds.rs#L20
+// defaults copied from https://github.com/solana-labs/solana/blob/33bde55bbdde13003acf45bb6afe6db4ab599ae4/core/src/sigverify_shreds.rs#L20
 pub const DEDUPER_FALSE_POSITIVE_RATE: f64 = 0.001;
 pub const DEDUPER_NUM_BITS: u64 = 637_534_199; // 76MB
 pub const DEDUPER_RESET_CYCLE: Duration = Duration::from_secs(5 * 60);
+/// Number of hashes per packet, `K` in [Deduper]
+const DEDUPER_NUM_HASHES: usize = 2;
+/// How often the accessory thread checks whether the deduper needs a reset
+const DEDUPER_RESET_CHECK_INTERVAL: Duration = Duration::from_secs(2);
 
-/// Bind to ports and start forwarding shreds
-#[allow(clippy::too_many_arguments)]
+/// Max packets per `sendmmsg` call, kept well under the kernel's `UIO_MAXIOV` (1024)
+pub const DEFAULT_SEND_BATCH_SIZE: usize = 128;
+
+/// Default interval between discovery requests, see `--endpoint-discovery-interval-ms`
+pub const DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+/// How often `allowed-source-ips-file` is re-read
+const SOURCE_ALLOWLIST_RELOAD_INTERVAL: Duration = Duration::from_secs(30);
+/// How often sources of packets that aren't shreds are logged, so a scan doesn't flood the log
+const INVALID_SOURCE_LOG_INTERVAL: Duration = Duration::from_secs(10);
+/// Distinct sources of packets that aren't shreds included in each log line
+const MAX_LOGGED_INVALID_SOURCES: usize = 8;
+/// Slots a shred may be ahead of the slot clock and still advance the highest slot seen
+const MAX_SLOTS_AHEAD_OF_CLOCK: Slot = 32;
+/// The highest slot seen is reseeded from the next shreds if it hasn't advanced for this long
+const HIGHEST_SLOT_RESEED_AFTER: Duration = Duration::from_secs(10);
+/// Slots of received shreds sampled to seed the highest slot seen
+pub const HIGHEST_SLOT_SEED_SAMPLES: usize = 8;
+/// How often forwarders pick up destinations changed at runtime, via admin API, config reload, or the library handle
+pub const RUNTIME_DEST_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often destinations added via the admin API with a TTL are checked for expiry
+const ADMIN_DEST_EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Consecutive discovery responses that must confirm a removal beyond `endpoint-discovery-max-removal-percent` before it takes effect
+const MASS_REMOVAL_CONFIRMATIONS: usize = 2;
+
+/// Intervals in a row a destination's p99 send latency must exceed `slow-send-threshold-us` before warning
+const SLOW_SEND_WARN_INTERVALS: u64 = 3;
+/// Most receive threads started by default, reading is cheap next to fanning out to destinations
+const MAX_DEFAULT_RECV_THREADS: usize = 4;
+/// Fewest send threads started by default
+const MIN_DEFAULT_SEND_THREADS: usize = 4;
+
+/// Receive and send threads per listen port. Each port's receive threads feed one queue shared by its send threads
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ForwarderThreads {
+    pub recv_per_port: usize,
+    pub send_per_port: usize,
+}
+
+impl ForwarderThreads {
+    /// Splits threads between `num_ports` ports, at least one of each per port.
+    /// Receive threads default to a quarter of the cores, up to `MAX_DEFAULT_RECV_THREADS`.
+    /// Send threads default to one per destination known at startup within `MIN_DEFAULT_SEND_THREADS` and the core count,
+    /// or every core when `num_static_dests` is None because destinations are only known at runtime
+    pub fn new(
+        num_recv_threads: Option<usize>,
+        num_send_threads: Option<usize>,
+        num_static_dests: Option<usize>,
+        num_ports: usize,
+    ) -> Self {
+        Self::with_num_cores(
+            num_recv_threads,
+            num_send_threads,
+            num_static_dests,
+            num_ports,
+            std::thread::available_parallelism().map_or(1, usize::from),
+        )
+    }
+
+    fn with_num_cores(
+        num_recv_threads: Option<usize>,
+        num_send_threads: Option<usize>,
+        num_static_dests: Option<usize>,
+        num_ports: usize,
+        num_cores: usize,
+    ) -> Self {
+        let num_recv_threads =
+            num_recv_threads.unwrap_or_else(|| (num_cores / 4).clamp(1, MAX_DEFAULT_RECV_THREADS));
+        let max_send_threads = num_cores.max(MIN_DEFAULT_SEND_THREADS);
+        let num_send_threads = num_send_threads.unwrap_or_else(|| {
+            num_static_dests.map_or(max_send_threads, |num_dests| {
+                num_dests.clamp(MIN_DEFAULT_SEND_THREADS, max_send_threads)
+            })
+        });
+        Self {
+            recv_per_port: (num_recv_threads / num_ports).max(1),
+            send_per_port: (num_send_threads / num_ports).max(1),
+        }
+    }
+}
+
+/// Where forwarder threads receive packets from
+pub enum PacketSource {
+    /// Bind to each port, using one socket per receive thread
+    Listen {
+        src_addr: IpAddr,
+        listen_ports: Vec<(u16, Option<String>)>, /* (port, region received on it if any) */
+        threads: ForwarderThreads,
+        recv_socket_buffer_bytes: Option<usize>,
+        /// DSCP class to mark packets sent from the listen sockets with
+        dscp: Option<u8>,
+        /// Batches queued per receive thread before applying `drop_policy`, in a queue shared by the port's receive threads
+        channel_capacity: usize,
+        drop_policy: DropPolicy,
+        /// Read kernel receive timestamps to measure latency added by the proxy, Linux only
+        rx_timestamps: Option<RxTimestampSource>,
+        /// Read with `recvmmsg` directly, up to this many packets per call, instead of `solana_streamer`. Linux only
+        recv_mmsg_batch_size: Option<usize>,
+        /// How long a read waits for packets before checking for exit
+        recv_poll_timeout: Duration,
+        /// Upstreams sent keepalives from each port, to hold open the NAT mappings they forward to
+        upstream_keepalives: Vec<UpstreamKeepalive>,
+        /// Unseal every packet received with this key, dropping those that fail authentication
+        decrypt_key_file: Option<Arc<KeyFile>>,
+        /// Sent each receive thread's listen address once it's receiving
+        ready_sender: Option<Sender<SocketAddr>>,
+    },
+    /// Packets sent by another thread, such as replay. One forwarder thread per receiver
+    Channel(Vec<Receiver<ReceivedBatch>>),
+}
+
+/// Where the kernel takes receive timestamps with `--measure-internal-latency`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RxTimestampSource {
+    /// When the kernel network stack receives the packet
+    #[default]
+    Software,
+    /// When the NIC receives the packet, falling back to software where the NIC didn't stamp it
+    Hardware,
+}
+
+/// Bind to ports, or read from channels, and start forwarding shreds.
+/// Returns the ports as bound, which differ from `listen_ports` for port 0, and a counter of kernel drops on the listen sockets where supported.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn start_forwarder_threads(
     unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>, /* sockets shared between endpoint discovery thread and forwarders */
-    src_addr: IpAddr,
-    src_port: u16,
-    num_threads: Option<usize>,
-    deduper: Arc<RwLock<Deduper<2, [u8]>>>,
+    packet_source: PacketSource,
+    listener: Option<String>, /* `--listener` pipeline tagging metrics, `None` for the main pipeline */
+    core_affinity: Vec<usize>, /* core to pin each forwarder thread to, empty to leave them floating */
+    send_batch_size: usize,
+    send_batch_linger: Duration,
+    send_socket_buffer_bytes: Option<usize>,
+    send_dscp: Option<u8>, /* DSCP class to mark forwarded packets with */
+    send_egress: Egress,   /* interface and source address to send from */
+    rebind_policy: Arc<RebindPolicy>, /* when to rebind forwarding and listen sockets after persistent errors */
+    slow_send_threshold: Option<Duration>, /* time sends to each destination when set, counting slower ones */
+    isolated_send_queue_capacity: Option<usize>, /* send to each UDP destination from its own thread, queueing this many batches */
+    quic_dest_sockets: Arc<ArcSwap<HashSet<SocketAddr>>>, /* subset of unioned sockets sent to over QUIC */
+    tunnel_dests: Arc<ArcSwap<HashMap<SocketAddr, TunnelDest>>>, /* subset of unioned sockets sent to over TCP/TLS */
+    unix_dests: Arc<ArcSwap<HashMap<SocketAddr, UnixDest>>>, /* subset of unioned sockets sent to over unix sockets */
+    enc_dests: Arc<ArcSwap<HashMap<SocketAddr, Arc<KeyFile>>>>, /* subset of unioned sockets whose packets are sealed */
+    dest_rate_limits: Arc<ArcSwap<HashMap<SocketAddr, Arc<RateLimiter>>>>, /* unioned sockets with a `rate` option */
+    dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>, /* unioned sockets with an `iface` or `src` option */
+    dest_priorities: Arc<ArcSwap<HashMap<SocketAddr, DestPriority>>>, /* unioned sockets with a `priority` option */
+    dest_spill: Arc<ArcSwap<HashMap<SocketAddr, bool>>>, /* unioned sockets with a `spill` option, true while spilled to */
+    shard_groups: Arc<ArcSwap<Vec<ShardGroup>>>, /* sent their share of packets instead of every packet */
+    mirrors: Arc<Vec<Mirror>>, /* sent a sample of packets after every other destination */
+    quic_sink: Arc<QuicSink>,
+    tunnel_sink: Arc<TunnelSink>,
+    unix_sink: Arc<UnixSink>,
+    spill_store: Option<Arc<SpillStore>>,
+    chaos: Option<Arc<ChaosInjector>>,
+    source_allowlist: Arc<SourceAllowlist>,
+    packet_filter: Arc<PacketFilter>,
+    deshred_tap: Option<Arc<DeshredTap>>,
+    pcap_tap: Option<PcapTap>,
+    subscriber_tap: Option<SubscriberTap>,
+    kafka_tap: Option<KafkaTap>,
+    slot_latency_tap: Option<Arc<SlotLatencyTap>>,
+    conflict_tap: Option<ConflictTap>,
+    events_tap: Option<Arc<EventsTap>>,
+    archive_tap: Option<ArchiveTap>,
+    ws_tap: Option<Arc<WsTap>>,
+    deduper: Arc<ArcSwap<ShredDeduper>>,
     metrics: Arc<ShredMetrics>,
     forward_stats: Arc<StreamerReceiveStats>,
-    use_discovery_service: bool,
-    debug_trace_shred: bool,
+    dest_refresh_interval: Option<Duration>, /* how often to pick up changes from the refresh thread, if any */
+    trace_shred_sampler: Arc<TraceShredSampler>,
+    forwarder_liveness: Arc<ThreadLiveness>, /* beat on every loop iteration, for the systemd watchdog */
     shutdown_receiver: Receiver<()>,
+    shutdown_grace_period: Duration, /* time to flush queued packets after shutdown */
     exit: Arc<AtomicBool>,
-) -> Vec<JoinHandle<()>> {
-    let num_threads = num_threads
-        .unwrap_or_else(|| usize::from(std::thread::available_parallelism().unwrap()).max(4));
-
-    let recycler: PacketBatchRecycler = Recycler::warmed(100, 1024);
+) -> io::Result<(Vec<JoinHandle<()>>, Vec<u16>, Option<SocketDropCounter>)> {
+    let (listen_hdls, bound_ports, packet_receivers, socket_drop_counter) = match packet_source {
+        PacketSource::Listen {
+            src_addr,
+            listen_ports,
+            threads,
+            recv_socket_buffer_bytes,
+            dscp,
+            channel_capacity,
+            drop_policy,
+            rx_timestamps,
+            recv_mmsg_batch_size,
+            recv_poll_timeout,
+            upstream_keepalives,
+            decrypt_key_file,
+            ready_sender,
+        } => {
+            let (listen_hdls, bound_ports, port_receivers, socket_drop_counter) =
+                start_listen_threads(
+                    src_addr,
+                    listen_ports,
+                    threads.recv_per_port,
+                    ListenSocketOptions {
+                        recv_buffer_bytes: recv_socket_buffer_bytes,
+                        dscp,
+                        rx_timestamps,
+                        recv_mmsg_batch_size,
+                        recv_poll_timeout,
+                    },
+                    rebind_policy.clone(),
+                    channel_capacity,
+                    drop_policy,
+                    &upstream_keepalives,
+                    decrypt_key_file,
+                    ready_sender,
+                    pcap_tap,
+                    forward_stats,
+                    metrics.clone(),
+                    exit.clone(),
+                )?;
+            // a port's send threads all pull from its shared queue
+            let packet_receivers = port_receivers
+                .into_iter()
+                .flat_map(|port_receiver| {
+                    std::iter::repeat(port_receiver).take(threads.send_per_port)
+                })
+                .collect::<Vec<_>>();
+            (
+                listen_hdls,
+                bound_ports,
+                packet_receivers,
+                socket_drop_counter,
+            )
+        }
+        PacketSource::Channel(packet_receivers) => (
+            vec![],
+            vec![],
+            packet_receivers
+                .into_iter()
+                .map(|packet_receiver| (packet_receiver, None, pcap_tap.clone()))
+                .collect::<Vec<_>>(),
+            None,
+        ),
+    };
 
-    // spawn a thread for each listen socket. linux kernel will load balance amongst shared sockets
-    solana_net_utils::multi_bind_in_range(src_addr, (src_port, src_port + 1), num_threads)
-        .unwrap_or_else(|_| {
-            panic!("Failed to bind listener sockets. Check that port {src_port} is not in use.")
-        })
-        .1
+    let send_socket_options = SendSocketOptions {
+        buffer_bytes: send_socket_buffer_bytes,
+        dscp: send_dscp,
+        egress: send_egress,
+    };
+    let isolated_send_sink = isolated_send_queue_capacity.map(|queue_capacity| {
+        Arc::new(
+            IsolatedSendSink::new(
+                queue_capacity,
+                send_socket_options.clone(),
+                send_batch_size,
+                slow_send_threshold,
+                rebind_policy.clone(),
+                metrics.clone(),
+            )
+            .with_dest_egress(dest_egress.clone()),
+        )
+    });
+    let send_hdls = packet_receivers
         .into_iter()
         .enumerate()
-        .flat_map(|(thread_id, incoming_shred_socket)| {
-            let (packet_sender, packet_receiver) = crossbeam_channel::unbounded();
-            let listen_thread = streamer::receiver(
-                format!("ssListen{thread_id}"),
-                Arc::new(incoming_shred_socket),
-                exit.clone(),
-                packet_sender,
-                recycler.clone(),
-                forward_stats.clone(),
-                Duration::default(), // do not coalesce since batching consumes more cpu cycles and adds latency.
-                false,
-                None,
-                false,
-            );
-
+        .map(|(thread_id, (packet_receiver, region, pcap_tap))| {
             let deduper = deduper.clone();
+            let source_allowlist = source_allowlist.clone();
+            let packet_filter = packet_filter.clone();
+            let deshred_tap = deshred_tap.clone();
+            let subscriber_tap = subscriber_tap.clone();
+            let kafka_tap = kafka_tap.clone();
+            let slot_latency_tap = slot_latency_tap.clone();
+            let conflict_tap = conflict_tap.clone();
+            let events_tap = events_tap.clone();
+            let archive_tap = archive_tap.clone();
+            let ws_tap = ws_tap.clone();
+            let trace_shred_sampler = trace_shred_sampler.clone();
             let unioned_dest_sockets = unioned_dest_sockets.clone();
+            let quic_dest_sockets = quic_dest_sockets.clone();
+            let tunnel_dests = tunnel_dests.clone();
+            let unix_dests = unix_dests.clone();
+            let enc_dests = enc_dests.clone();
+            let dest_rate_limits = dest_rate_limits.clone();
+            let dest_egress = dest_egress.clone();
+            let dest_priorities = dest_priorities.clone();
+            let dest_spill = dest_spill.clone();
+            let send_socket_options = send_socket_options.clone();
+            let shard_groups = shard_groups.clone();
+            let mirrors = mirrors.clone();
+            let quic_sink = quic_sink.clone();
+            let tunnel_sink = tunnel_sink.clone();
+            let unix_sink = unix_sink.clone();
+            let isolated_send_sink = isolated_send_sink.clone();
+            let spill_store = spill_store.clone();
+            let chaos = chaos.clone();
             let metrics = metrics.clone();
             let shutdown_receiver = shutdown_receiver.clone();
             let exit = exit.clone();
+            let rebind_policy = rebind_policy.clone();
+            let listener = listener.clone();
+            let core = core_affinity.get(thread_id).copied();
+            let liveness_beat = forwarder_liveness.register();
 
-            let send_thread = Builder::new()
-                .name(format!("ssPxyTx_{thread_id}"))
+            Builder::new()
+                .name(match &listener {
+                    Some(listener) => format!("ssPxyTx_{listener}_{thread_id}"),
+                    None => format!("ssPxyTx_{thread_id}"),
+                })
                 .spawn(move || {
-                    let send_socket =
-                        UdpSocket::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
-                            .expect("to bind to udp port for forwarding");
-                    let mut local_dest_sockets = unioned_dest_sockets.load();
-
-                    let refresh_subscribers_tick = if use_discovery_service {
-                        crossbeam_channel::tick(Duration::from_secs(30))
-                    } else {
-                        crossbeam_channel::tick(Duration::MAX)
+                    if let Some(core) = core {
+                        match affinity::pin_current_thread(core) {
+                            Ok(()) => info!("Pinned forwarder thread {thread_id} to core {core}."),
+                            Err(e) => warn!("Failed to pin forwarder thread {thread_id} to core {core}, leaving it floating. Error: {e}"),
+                        }
+                    }
+                    // DSCP and egress are checked when building the proxy
+                    let send_socket = send_socket_options.bind().unwrap_or_else(|e| {
+                        panic!("Failed to bind forwarding socket. Error: {e}")
+                    });
+                    let mirror_sink = (!mirrors.is_empty()).then(|| {
+                        let mirror_socket = send_socket_options.bind().unwrap_or_else(|e| {
+                            panic!("Failed to bind mirror socket. Error: {e}")
+                        });
+                        MirrorSink::new(mirrors, mirror_socket, send_batch_size, metrics.clone())
+                    });
+                    let udp_sink = UdpSink::new(
+                        send_socket,
+                        send_batch_size,
+                        slow_send_threshold,
+                        metrics.clone(),
+                    )
+                    .with_rebind(send_socket_options.clone(), &rebind_policy)
+                    .with_dest_egress(send_socket_options, dest_egress);
+                    let context = ForwarderContext {
+                        deduper,
+                        source_allowlist,
+                        packet_filter,
+                        udp_sink,
+                        quic_sink,
+                        tunnel_sink,
+                        unix_sink,
+                        isolated_send_sink,
+                        spill_store,
+                        chaos,
+                        mirror_sink,
+                        deshred_tap,
+                        pcap_tap,
+                        subscriber_tap,
+                        kafka_tap,
+                        slot_latency_tap,
+                        conflict_tap,
+                        events_tap,
+                        archive_tap,
+                        ws_tap,
+                        region,
+                        listener,
+                        trace_shred_sampler,
+                        metrics,
+                    };
+                    let load_dests = || LocalDests {
+                        dest_sockets: unioned_dest_sockets.load_full(),
+                        quic_dest_sockets: quic_dest_sockets.load_full(),
+                        tunnel_dests: tunnel_dests.load_full(),
+                        unix_dests: unix_dests.load_full(),
+                        enc_dests: enc_dests.load_full(),
+                        dest_rate_limits: dest_rate_limits.load_full(),
+                        dest_priorities: dest_priorities.load_full(),
+                        dest_spill: dest_spill.load_full(),
+                        shard_groups: shard_groups.load_full(),
+                    };
+                    let mut local_dests = load_dests();
+
+                    let refresh_subscribers_tick = match dest_refresh_interval {
+                        Some(interval) => crossbeam_channel::tick(interval),
+                        None => crossbeam_channel::never(),
                     };
                     while !exit.load(Ordering::Relaxed) {
+                        liveness_beat.beat();
                         crossbeam_channel::select! {
                             // forward packets
                             recv(packet_receiver) -> maybe_packet_batch => {
+                               // measured before coalescing, which drains the queue
+                               let backlog = priority::backlog(&packet_receiver);
+                               let maybe_packet_batches = maybe_packet_batch.map(|packet_batch| {
+                                   coalesce_packet_batches(
+                                       packet_batch,
+                                       &packet_receiver,
+                                       send_batch_size,
+                                       send_batch_linger,
+                                   )
+                               });
                                let res = recv_from_channel_and_send_multiple_dest(
-                                   maybe_packet_batch,
-                                   &deduper,
-                                   &send_socket,
-                                   &local_dest_sockets,
-                                   debug_trace_shred,
-                                   &metrics,
+                                   maybe_packet_batches,
+                                   backlog,
+                                   &local_dests,
+                                   &context,
                                );
 
                                 // avoid unwrap to prevent log spam from panic handler in each thread
@@ -117,7 +488,13 @@ pub fn start_forwarder_threads(
                             }
                             // refresh thread-local subscribers
                             recv(refresh_subscribers_tick) -> _ => {
-                                local_dest_sockets = unioned_dest_sockets.load();
+                                local_dests = load_dests();
+                                context.quic_sink.retain_destinations(&local_dests.quic_dest_sockets);
+                                context.tunnel_sink.retain_destinations(&local_dests.tunnel_dests);
+                                context.unix_sink.retain_destinations(&local_dests.unix_dests);
+                                if let Some(isolated_send_sink) = &context.isolated_send_sink {
+                                    isolated_send_sink.retain_destinations(&local_dests.dest_sockets);
+                                }
                             }
                             // handle shutdown (avoid using sleep since it will hang under SIGINT)
                             recv(shutdown_receiver) -> _ => {
@@ -125,435 +502,6295 @@ pub fn start_forwarder_threads(
                             }
                         }
                     }
+                    // flush packets queued before shutdown, leaving the rest once the grace period is up.
+                    // nothing is shed by priority, the grace period bounds the flush instead
+                    let flush_deadline = Instant::now() + shutdown_grace_period;
+                    let mut num_flushed = 0;
+                    while Instant::now() < flush_deadline {
+                        let Ok(packet_batch) = packet_receiver.try_recv() else {
+                            break;
+                        };
+                        let packet_batches = coalesce_packet_batches(
+                            packet_batch,
+                            &packet_receiver,
+                            send_batch_size,
+                            Duration::ZERO,
+                        );
+                        num_flushed += packet_batches
+                            .iter()
+                            .map(|batch| batch.packets.len())
+                            .sum::<usize>();
+                        if recv_from_channel_and_send_multiple_dest(
+                            Ok(packet_batches),
+                            0.0,
+                            &local_dests,
+                            &context,
+                        )
+                        .is_err()
+                        {
+                            break;
+                        }
+                    }
+                    if num_flushed > 0 {
+                        info!("Flushed {num_flushed} queued packets from forwarder thread {thread_id}.");
+                    }
                     info!("Exiting forwarder thread {thread_id}.");
                 })
-                .unwrap();
-
-            [listen_thread, send_thread]
+                .unwrap()
         })
-        .collect::<Vec<JoinHandle<()>>>()
+        .collect::<Vec<JoinHandle<()>>>();
+    Ok((
+        listen_hdls.into_iter().chain(send_hdls).collect(),
+        bound_ports,
+        socket_drop_counter,
+    ))
 }
 
-/// Broadcasts same packet to multiple recipients
-/// Returns Err when unable to receive packets.
-fn recv_from_channel_and_send_multiple_dest(
-    maybe_packet_batch: Result<PacketBatch, RecvError>,
-    deduper: &RwLock<Deduper<2, [u8]>>,
-    send_socket: &UdpSocket,
-    local_dest_sockets: &[SocketAddr],
-    debug_trace_shred: bool,
-    metrics: &ShredMetrics,
-) -> Result<(), ShredstreamProxyError> {
-    let packet_batch = maybe_packet_batch.map_err(ShredstreamProxyError::RecvError)?;
-    let trace_shred_received_time = SystemTime::now();
-    metrics
-        .agg_received
-        .fetch_add(packet_batch.len() as u64, Ordering::Relaxed);
-    debug!(
-        "Got batch of {} packets, total size in bytes: {}",
-        packet_batch.len(),
-        packet_batch.iter().map(|x| x.meta().size).sum::<usize>()
-    );
+/// Binds `num_threads_per_port` listen sockets per port, spawning a receiver thread for each.
+/// Returns the receiver threads, the ports as bound, and, per port, the channel its sockets send to, the region received on, and its pcap tap
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+fn start_listen_threads(
+    src_addr: IpAddr,
+    listen_ports: Vec<(u16, Option<String>)>,
+    num_threads_per_port: usize,
+    listen_options: ListenSocketOptions,
+    rebind_policy: Arc<RebindPolicy>,
+    channel_capacity: usize,
+    drop_policy: DropPolicy,
+    upstream_keepalives: &[UpstreamKeepalive],
+    decrypt_key_file: Option<Arc<KeyFile>>,
+    ready_sender: Option<Sender<SocketAddr>>,
+    pcap_tap: Option<PcapTap>,
+    forward_stats: Arc<StreamerReceiveStats>,
+    metrics: Arc<ShredMetrics>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(
+    Vec<JoinHandle<()>>,
+    Vec<u16>,
+    Vec<(Receiver<ReceivedBatch>, Option<String>, Option<PcapTap>)>,
+    Option<SocketDropCounter>,
+)> {
+    // bind every port before spawning any thread, so a port in use fails startup cleanly
+    let port_sockets = listen_ports
+        .into_iter()
+        .map(|(src_port, region)| {
+            let sockets = socket::bind_reuseport(SocketAddr::new(src_addr, src_port), num_threads_per_port)
+                .map_err(|e| {
+                    io::Error::new(e.kind(), format!("Failed to bind listener sockets. Check that port {src_port} is not in use. Error: {e}"))
+                })?;
+            Ok((sockets, region))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let listen_sockets = || port_sockets.iter().flat_map(|(sockets, _region)| sockets);
+    for socket in listen_sockets() {
+        // DSCP is checked when building the proxy
+        listen_options.apply(socket).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to configure listen socket. Error: {e}"),
+            )
+        })?;
+    }
+    let bound_ports = port_sockets
+        .iter()
+        .map(|(sockets, _region)| sockets[0].local_addr().map(|addr| addr.port()))
+        .collect::<io::Result<Vec<_>>>()?;
+    // rebound sockets aren't counted
+    let socket_drop_counter = SocketDropCounter::new(listen_sockets());
 
-    let mut packet_batch_vec = vec![packet_batch];
+    // spawn a thread for each listen socket. linux kernel will load balance amongst shared sockets
+    let mut listen_hdls = vec![];
+    let port_receivers = port_sockets
+        .into_iter()
+        .map(|(sockets, region)| {
+            let pcap_tap = pcap_tap.as_ref().map(|pcap_tap| {
+                pcap_tap.with_listen_addr(
+                    sockets[0]
+                        .local_addr()
+                        .expect("listen socket to have local address"),
+                )
+            });
+            let (packet_sender, packet_receiver) = packet_channel::bounded(
+                channel_capacity * sockets.len(),
+                drop_policy,
+                metrics.clone(),
+            );
+            for (i, incoming_shred_socket) in sockets.into_iter().enumerate() {
+                // one keepalive per port is enough to hold its mapping
+                let keepalive_sender = (i == 0 && !upstream_keepalives.is_empty())
+                    .then(|| KeepaliveSender::new(upstream_keepalives));
+                listen_hdls.push(start_receive_thread(
+                    listen_hdls.len(),
+                    incoming_shred_socket,
+                    listen_options,
+                    rebind_policy.clone(),
+                    keepalive_sender,
+                    decrypt_key_file.clone(),
+                    ready_sender.clone(),
+                    packet_sender.clone(),
+                    forward_stats.clone(),
+                    metrics.clone(),
+                    exit.clone(),
+                ));
+            }
+            (packet_receiver, region, pcap_tap)
+        })
+        .collect();
+    Ok((
+        listen_hdls,
+        bound_ports,
+        port_receivers,
+        socket_drop_counter,
+    ))
+}
 
-    let num_deduped = solana_perf::deduper::dedup_packets_and_count_discards(
-        &deduper.read().unwrap(),
-        &mut packet_batch_vec,
-        |_received_packet, _is_already_marked_as_discard, _is_dup| {},
-    );
+/// Options applied to listen sockets when bound, and again when rebound after persistent errors
+#[derive(Clone, Copy, Debug)]
+struct ListenSocketOptions {
+    recv_buffer_bytes: Option<usize>,
+    /// DSCP class to mark packets sent from the listen sockets with
+    dscp: Option<u8>,
+    /// Read kernel receive timestamps to measure latency added by the proxy, Linux only
+    rx_timestamps: Option<RxTimestampSource>,
+    /// Read with `recvmmsg` directly, up to this many packets per call, Linux only
+    recv_mmsg_batch_size: Option<usize>,
+    /// How long a read waits for packets before checking for exit
+    recv_poll_timeout: Duration,
+}
 
-    packet_batch_vec.iter().for_each(|batch| {
-        batch.iter().for_each(|packet| {
-            metrics
-                .packets_received
-                .entry(packet.meta().addr)
-                .and_modify(|(discarded, not_discarded)| {
-                    *discarded += packet.meta().discard() as u64;
-                    *not_discarded += !packet.meta().discard() as u64;
-                })
-                .or_insert_with(|| {
-                    (
-                        packet.meta().discard() as u64,
-                        !packet.meta().discard() as u64,
-                    )
-                });
-        });
-    });
+impl ListenSocketOptions {
+    /// Packets per `recvmmsg` call when reading with [socket::RecvMmsg], `None` to read with `solana_streamer`.
+    /// Receive timestamps are only read through `recvmmsg`
+    fn recv_mmsg_batch_size(&self) -> Option<usize> {
+        if !cfg!(target_os = "linux") {
+            return None;
+        }
+        self.recv_mmsg_batch_size
+            .or(self.rx_timestamps.map(|_| PACKETS_PER_BATCH))
+    }
+
+    /// Binds another socket sharing `addr` with the existing listen sockets
+    fn bind(&self, addr: SocketAddr) -> io::Result<UdpSocket> {
+        let socket = socket::bind_reuseport(addr, 1)?.remove(0);
+        self.apply(&socket)?;
+        Ok(socket)
+    }
 
-    local_dest_sockets.iter().for_each(|outgoing_socketaddr| {
-        let packets_with_dest = packet_batch_vec[0].iter().filter_map(|pkt| {
-            let data = pkt.data(..)?;
-            let addr = outgoing_socketaddr;
-            Some((data, addr))
-        }).collect::<Vec<(&[u8], &SocketAddr)>>();
-
-        match batch_send(send_socket, &packets_with_dest) {
-            Ok(_) => {
-                metrics.agg_success_forward.fetch_add(packets_with_dest.len() as u64, Ordering::Relaxed);
-                metrics.duplicate.fetch_add(num_deduped, Ordering::Relaxed);
+    fn apply(&self, socket: &UdpSocket) -> io::Result<()> {
+        if let Some(recv_buffer_bytes) = self.recv_buffer_bytes {
+            if let Err(e) =
+                socket::set_socket_buffer_size(socket, SocketBuffer::Recv, recv_buffer_bytes)
+            {
+                warn!("Failed to set listen socket receive buffer size. Error: {e}");
             }
-            Err(SendPktsError::IoError(err, num_failed)) => {
-                metrics.agg_fail_forward.fetch_add(packets_with_dest.len() as u64, Ordering::Relaxed);
-                metrics.duplicate.fetch_add(num_failed as u64, Ordering::Relaxed);
-                error!("Failed to send batch of size {} to {outgoing_socketaddr:?}. {num_failed} packets failed. Error: {err}", packets_with_dest.len());
+        }
+        if let Some(dscp) = self.dscp {
+            socket::set_dscp(socket, dscp)
+                .map_err(|e| io::Error::new(e.kind(), format!("Failed to set DSCP {dscp}: {e}")))?;
+        }
+        // wake up periodically to check for exit
+        socket.set_read_timeout(Some(self.recv_poll_timeout))?;
+        if let Some(rx_timestamps) = self.rx_timestamps {
+            if let Err(e) =
+                socket::enable_rx_timestamps(socket, rx_timestamps == RxTimestampSource::Hardware)
+            {
+                warn!("Failed to enable receive timestamps on listen socket. Error: {e}");
             }
         }
-    });
+        Ok(())
+    }
+}
 
-    if debug_trace_shred {
-        packet_batch_vec[0]
-            .iter()
-            .filter_map(|p| TraceShred::decode(p.data(..)?).ok())
-            .filter(|t| t.created_at.is_some())
-            .for_each(|trace_shred| {
-                let elapsed = trace_shred_received_time
-                    .duration_since(SystemTime::try_from(trace_shred.created_at.unwrap()).unwrap())
-                    .unwrap_or_default();
+/// Options applied to forwarding sockets when bound, and again when rebound after persistent errors
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SendSocketOptions {
+    pub(crate) buffer_bytes: Option<usize>,
+    /// DSCP class to mark forwarded packets with
+    pub(crate) dscp: Option<u8>,
+    /// Interface and source address to send from, from `--egress-interface` and `--egress-source-ip`
+    pub(crate) egress: Egress,
+}
 
-                datapoint_info!(
-                    "shredstream_proxy-trace_shred_latency",
-                    "trace_region" => trace_shred.region,
-                    ("trace_seq_num", trace_shred.seq_num as i64, i64),
-                    ("elapsed_micros", elapsed.as_micros(), i64),
-                );
-            });
+impl SendSocketOptions {
+    /// These options with a destination's `iface` and `src` options taking precedence
+    pub(crate) fn with_dest_egress(&self, dest_egress: &Egress) -> Self {
+        Self {
+            egress: dest_egress.or(&self.egress),
+            ..self.clone()
+        }
     }
-    Ok(())
-}
 
-/// Starts a thread that updates our destinations used by the forwarder threads
-pub fn start_destination_refresh_thread(
-    endpoint_discovery_url: String,
-    discovered_endpoints_port: u16,
-    static_dest_sockets: Vec<(SocketAddr, String)>,
-    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
-    shutdown_receiver: Receiver<()>,
-    exit: Arc<AtomicBool>,
-) -> JoinHandle<()> {
-    Builder::new().name("ssPxyDstRefresh".to_string()).spawn(move || {
-        let fetch_socket_tick = crossbeam_channel::tick(Duration::from_secs(30));
-        let metrics_tick = crossbeam_channel::tick(Duration::from_secs(30));
-        let mut socket_count = static_dest_sockets.len();
-        while !exit.load(Ordering::Relaxed) {
-            crossbeam_channel::select! {
-                    recv(fetch_socket_tick) -> _ => {
-                        let fetched = fetch_unioned_destinations(
-                            &endpoint_discovery_url,
-                            discovered_endpoints_port,
-                            &static_dest_sockets,
-                        );
-                        let new_sockets = match fetched {
-                            Ok(s) => {
-                                info!("Sending shreds to {} destinations: {s:?}", s.len());
-                                s
-                            }
-                            Err(e) => {
-                                warn!("Failed to fetch from discovery service, retrying. Error: {e}");
-                                datapoint_warn!("shredstream_proxy-destination_refresh_error",
-                                                ("prev_unioned_dest_count", socket_count, i64),
-                                                ("errors", 1, i64),
-                                                ("error_str", e.to_string(), String),
-                                );
-                                continue;
-                            }
-                        };
-                        socket_count = new_sockets.len();
-                        unioned_dest_sockets.store(Arc::new(new_sockets));
-                    }
-                    recv(metrics_tick) -> _ => {
-                        datapoint_info!("shredstream_proxy-destination_refresh_stats",
-                                        ("destination_count", socket_count, i64),
-                        );
-                    }
-                    recv(shutdown_receiver) -> _ => {
-                        break;
-                    }
-                }
+    pub(crate) fn bind(&self) -> io::Result<UdpSocket> {
+        let socket = socket::bind_egress_send_socket(&self.egress)?;
+        if !self.egress.is_default() {
+            info!(
+                "Bound forwarding socket {} to {}.",
+                socket
+                    .local_addr()
+                    .map_or_else(|_| "?".to_string(), |addr| addr.to_string()),
+                self.egress
+            );
+        }
+        if let Some(buffer_bytes) = self.buffer_bytes {
+            if let Err(e) =
+                socket::set_socket_buffer_size(&socket, SocketBuffer::Send, buffer_bytes)
+            {
+                warn!("Failed to set forwarding socket send buffer size. Error: {e}");
+            }
+        }
+        if let Some(dscp) = self.dscp {
+            socket::set_dscp(&socket, dscp)
+                .map_err(|e| io::Error::new(e.kind(), format!("Failed to set DSCP {dscp}: {e}")))?;
         }
-    }).unwrap()
+        Ok(socket)
+    }
 }
 
-/// Returns dynamically discovered endpoints with CLI arg defined endpoints
-fn fetch_unioned_destinations(
-    endpoint_discovery_url: &str,
-    discovered_endpoints_port: u16,
-    static_dest_sockets: &[(SocketAddr, String)],
-) -> Result<Vec<SocketAddr>, ShredstreamProxyError> {
-    let bytes = reqwest::blocking::get(endpoint_discovery_url)?.bytes()?;
-
-    let sockets_json = match serde_json::from_slice::<Vec<IpAddr>>(&bytes) {
-        Ok(s) => s,
+/// Binds a replacement for a socket failing with `err`, returning `None` if binding fails so the caller retries after further errors.
+/// Panics, shutting down the proxy, once sockets are rebound more than `rebind_policy` allows rather than rebinding forever
+fn rebind_socket(
+    socket_kind: &str,
+    err: &io::Error,
+    rebind_policy: &RebindPolicy,
+    metrics: &ShredMetrics,
+    bind: impl FnOnce() -> io::Result<UdpSocket>,
+) -> Option<UdpSocket> {
+    if !rebind_policy.allow_rebind(Instant::now()) {
+        panic!(
+            "Rebound sockets more than {} times within a minute, giving up. Last {socket_kind} socket error: {err}",
+            rebind_policy.max_rebinds_per_minute()
+        );
+    }
+    match bind() {
+        Ok(socket) => {
+            warn!("Rebound {socket_kind} socket after persistent errors. Error: {err}");
+            metrics.socket_rebinds.fetch_add(1, Ordering::Relaxed);
+            Some(socket)
+        }
         Err(e) => {
             warn!(
-                "Failed to parse json from: {:?}",
-                std::str::from_utf8(&bytes)
+                "Failed to rebind {socket_kind} socket, retrying after further errors. Error: {e}"
             );
-            return Err(ShredstreamProxyError::from(e));
+            None
         }
-    };
-
-    // resolve again since ip address could change
-    let static_dest_sockets = static_dest_sockets
-        .iter()
-        .filter_map(|(_socketaddr, hostname_port)| {
-            Some(resolve_hostname_port(hostname_port).ok()?.0)
-        })
-        .collect::<Vec<_>>();
-
-    let unioned_dest_sockets = sockets_json
-        .into_iter()
-        .map(|ip| SocketAddr::new(ip, discovered_endpoints_port))
-        .chain(static_dest_sockets)
-        .unique()
-        .collect::<Vec<SocketAddr>>();
-    Ok(unioned_dest_sockets)
+    }
 }
 
-/// Reset dedup + send metrics to influx
-pub fn start_forwarder_accessory_thread(
-    deduper: Arc<RwLock<Deduper<2, [u8]>>>,
+/// Reads batches from the socket into the forwarder's channel.
+/// Same as `streamer::receiver`, but sends through [PacketBatchSender] so a slow forwarder can't grow the channel without bound.
+/// With `recv_mmsg_batch_size` or `rx_timestamps`, reads with `recvmmsg` instead, into batches pooled across reads,
+/// attaching kernel receive timestamps to each batch with `rx_timestamps`. Rebinds the socket once reads keep failing with persistent errors.
+/// Sends due upstream keepalives from the socket between reads. With `decrypt_key_file`, reads sealed packets and unseals them instead
+#[allow(clippy::too_many_arguments)]
+fn start_receive_thread(
+    thread_id: usize,
+    mut socket: UdpSocket,
+    listen_options: ListenSocketOptions,
+    rebind_policy: Arc<RebindPolicy>,
+    mut keepalive_sender: Option<KeepaliveSender>,
+    decrypt_key_file: Option<Arc<KeyFile>>,
+    ready_sender: Option<Sender<SocketAddr>>,
+    packet_sender: PacketBatchSender,
+    stats: Arc<StreamerReceiveStats>,
     metrics: Arc<ShredMetrics>,
-    metrics_update_interval_ms: u64,
-    shutdown_receiver: Receiver<()>,
     exit: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
+    let listen_addr = socket
+        .local_addr()
+        .expect("listen socket to have local address");
     Builder::new()
-        .name("ssPxyAccessory".to_string())
+        .name(format!("ssListen{}_{thread_id}", listen_addr.port()))
         .spawn(move || {
-            let metrics_tick =
-                crossbeam_channel::tick(Duration::from_millis(metrics_update_interval_ms));
-            let deduper_reset_tick = crossbeam_channel::tick(Duration::from_secs(2));
-            let mut rng = rand::thread_rng();
+            let recv_errors = rebind_policy.error_tracker();
+            let recv_mmsg_batch_size = listen_options.recv_mmsg_batch_size();
+            let mut recv_mmsg = recv_mmsg_batch_size.map(socket::RecvMmsg::new);
+            let batch_size = recv_mmsg_batch_size.unwrap_or(PACKETS_PER_BATCH);
+            // batches return to the pool once forwarded
+            let recycler = PacketBatchRecycler::default();
+            let new_batch =
+                || PacketBatch::new_unpinned_with_recycler(&recycler, batch_size, "ssListen");
+            let mut packet_batch = new_batch();
+            if let Some(ready_sender) = ready_sender {
+                // the receiver may have given up waiting
+                let _ = ready_sender.send(listen_addr);
+            }
             while !exit.load(Ordering::Relaxed) {
-                crossbeam_channel::select! {
-                    // reset deduper to avoid false positives
-                    recv(deduper_reset_tick) -> _ => {
-                        deduper
-                            .write()
-                            .unwrap()
-                            .maybe_reset(&mut rng, DEDUPER_FALSE_POSITIVE_RATE, DEDUPER_RESET_CYCLE);
+                if let Some(keepalive_sender) = keepalive_sender.as_mut() {
+                    keepalive_sender.send_due(&socket, &metrics);
+                }
+                // do not coalesce since batching consumes more cpu cycles and adds latency.
+                let received = match (&decrypt_key_file, recv_mmsg.as_mut()) {
+                    // recvmmsg is ruled out with a decrypt key when validating args
+                    (Some(key_file), _) => encryption::recv_sealed(
+                        &socket,
+                        &mut packet_batch,
+                        batch_size,
+                        key_file,
+                        &metrics,
+                    )
+                    .map(|len| (len, None)),
+                    (None, Some(recv_mmsg)) => recv_mmsg_batch(
+                        recv_mmsg,
+                        &mut packet_batch,
+                        batch_size,
+                        &socket,
+                        listen_options.rx_timestamps.is_some(),
+                    ),
+                    (None, None) => {
+                        packet::recv_from(&mut packet_batch, &socket, Duration::default())
+                            .map(|len| (len, None))
                     }
-
-                    // send metrics to influx
-                    recv(metrics_tick) -> _ => {
-                        metrics.report();
-                        metrics.reset();
+                };
+                let (len, rx_timestamps) = match received {
+                    Ok((len, rx_timestamps)) => {
+                        recv_errors.record_success();
+                        if len == 0 {
+                            continue;
+                        }
+                        (len, rx_timestamps)
                     }
-
-                    // handle SIGINT shutdown
-                    recv(shutdown_receiver) -> _ => {
-                        break;
+                    Err(e) => {
+                        if recv_errors.record_error(&e) {
+                            if let Some(rebound) =
+                                rebind_socket("listen", &e, &rebind_policy, &metrics, || {
+                                    listen_options.bind(listen_addr)
+                                })
+                            {
+                                socket = rebound;
+                            }
+                        }
+                        continue;
                     }
+                };
+                stats.packets_count.fetch_add(len, Ordering::Relaxed);
+                stats.packet_batches_count.fetch_add(1, Ordering::Relaxed);
+                stats
+                    .max_channel_len
+                    .fetch_max(packet_sender.num_queued(), Ordering::Relaxed);
+                if len >= batch_size {
+                    stats
+                        .full_packet_batches_count
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+                if metrics.memory_guard.should_drop() {
+                    metrics
+                        .memory_guard_dropped
+                        .fetch_add(len as u64, Ordering::Relaxed);
+                    // reuses the batch, so nothing more is allocated until buffers drain.
+                    // reads expect fresh packets past the batch's length
+                    packet_batch.truncate(0);
+                    continue;
+                }
+                let packets = std::mem::replace(&mut packet_batch, new_batch());
+                let held = metrics.memory_guard.hold(
+                    BufferKind::Receive,
+                    packets.capacity() * mem::size_of::<Packet>(),
+                );
+                let received_batch = ReceivedBatch {
+                    packets,
+                    rx_timestamps,
+                    held: Some(held),
+                };
+                if packet_sender.send(received_batch).is_err() {
+                    break;
                 }
             }
         })
         .unwrap()
 }
 
-pub struct ShredMetrics {
-    /// Total number of shreds received. Includes duplicates when receiving shreds from multiple regions
-    pub agg_received: AtomicU64,
-    /// Total number of shreds successfully forwarded, accounting for all destinations
-    pub agg_success_forward: AtomicU64,
-    /// Total number of shreds failed to forward, accounting for all destinations
-    pub agg_fail_forward: AtomicU64,
-    /// Number of duplicate shreds received
-    pub duplicate: AtomicU64,
-    /// (discarded, not discarded, from other shredstream instances)
-    pub packets_received: DashMap<IpAddr, (u64, u64)>,
+/// Reads whatever packets are ready, up to `batch_size`, with their kernel receive timestamps if `rx_timestamps`.
+/// Blocks until one arrives or the socket's read timeout elapses
+fn recv_mmsg_batch(
+    recv_mmsg: &mut socket::RecvMmsg,
+    packet_batch: &mut PacketBatch,
+    batch_size: usize,
+    socket: &UdpSocket,
+    rx_timestamps: bool,
+) -> io::Result<(usize, Option<Vec<Option<SystemTime>>>)> {
+    packet_batch.resize(batch_size, Packet::default());
+    let mut timestamps = rx_timestamps.then(|| vec![None; batch_size]);
+    let res = recv_mmsg.recv(socket, &mut packet_batch[..], timestamps.as_deref_mut());
+    let len = *res.as_ref().unwrap_or(&0);
+    packet_batch.truncate(len);
+    if let Some(timestamps) = timestamps.as_mut() {
+        timestamps.truncate(len);
+    }
+    res.map(|len| (len, timestamps))
+}
 
-    // cumulative metrics (persist after reset)
-    pub agg_received_cumulative: AtomicU64,
-    pub agg_success_forward_cumulative: AtomicU64,
-    pub agg_fail_forward_cumulative: AtomicU64,
-    pub duplicate_cumulative: AtomicU64,
+/// Transport used to forward to a destination
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    Udp,
+    Quic,
+    /// `tcp://` and `tls://` destinations
+    Tunnel,
+    /// `unix://` destinations
+    Unix,
 }
 
-impl ShredMetrics {
-    pub fn new() -> Self {
+/// Forwards packets to a single destination, recording results in [ShredMetrics]
+pub trait ShredSink {
+    fn send(&self, dest: SocketAddr, packets: &[&[u8]]);
+}
+
+/// Sends over a UDP socket owned by a single forwarder thread
+pub struct UdpSink {
+    /// Replaced when rebound after persistent send errors
+    socket: RefCell<UdpSocket>,
+    /// IPv4 destinations are sent to as IPv4-mapped addresses from dual-stack sockets
+    ipv6_socket: Cell<bool>,
+    /// Max packets per `sendmmsg` call
+    send_batch_size: usize,
+    /// Times each `sendmmsg` call when set, counting slower calls. Off by default since it reads the clock twice per call
+    slow_send_threshold: Option<Duration>,
+    /// Rebinds the socket with the same options after persistent send errors when set
+    rebind: Option<(SendSocketOptions, Arc<RebindPolicy>, SocketErrorTracker)>,
+    /// Destinations with their own `iface` or `src` options are sent to from a socket bound with them instead, when set
+    dest_egress: Option<DestEgressSinks>,
+    /// Counters of the thread owning this sink, also used by the forwarder for received and duplicate packets
+    thread_metrics: ThreadMetrics,
+    /// Allocation reused for the (packet, address) pairs passed to `sendmmsg`, so sending a batch to each destination doesn't allocate.
+    /// Always empty between sends, see [recycle_vec]
+    send_scratch: RefCell<Vec<(&'static [u8], SocketAddr)>>,
+    metrics: Arc<ShredMetrics>,
+}
+
+/// Sockets bound on first use for destinations whose egress differs from the thread's socket
+struct DestEgressSinks {
+    options: SendSocketOptions,
+    /// Shared with [DestinationSources], which updates it with the union
+    dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>,
+    /// None once binding failed, so a bad egress is reported once rather than per send
+    sinks: RefCell<HashMap<Egress, Option<UdpSink>>>,
+}
+
+impl UdpSink {
+    pub fn new(
+        socket: UdpSocket,
+        send_batch_size: usize,
+        slow_send_threshold: Option<Duration>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
         Self {
-            agg_received: Default::default(),
-            agg_success_forward: Default::default(),
-            agg_fail_forward: Default::default(),
-            duplicate: Default::default(),
-            packets_received: DashMap::with_capacity(10),
-            agg_received_cumulative: Default::default(),
-            agg_success_forward_cumulative: Default::default(),
-            agg_fail_forward_cumulative: Default::default(),
-            duplicate_cumulative: Default::default(),
+            ipv6_socket: Cell::new(socket.local_addr().is_ok_and(|addr| addr.is_ipv6())),
+            socket: RefCell::new(socket),
+            send_batch_size,
+            slow_send_threshold,
+            rebind: None,
+            dest_egress: None,
+            thread_metrics: metrics.register_thread(),
+            send_scratch: RefCell::default(),
+            metrics,
         }
     }
 
-    pub fn report(&self) {
-        datapoint_info!(
-            "shredstream_proxy-connection_metrics",
-            (
-                "agg_received",
-                self.agg_received.load(Ordering::Relaxed),
-                i64
-            ),
-            (
-                "agg_success_forward",
-                self.agg_success_forward.load(Ordering::Relaxed),
-                i64
-            ),
-            (
-                "agg_fail_forward",
-                self.agg_fail_forward.load(Ordering::Relaxed),
-                i64
-            ),
-            ("duplicate", self.duplicate.load(Ordering::Relaxed), i64),
-        );
-        self.packets_received.iter().for_each(|kv| {
-            let (addr, (discarded_packets, not_discarded_packets)) = kv.pair();
-            datapoint_info!("shredstream_proxy-receiver_stats",
-                "addr" => addr.to_string(),
-                ("discarded_packets", *discarded_packets, i64),
-                ("not_discarded_packets", *not_discarded_packets, i64),
+    pub fn thread_metrics(&self) -> &ThreadMetrics {
+        &self.thread_metrics
+    }
+
+    /// Sends to destinations in `dest_egress` from sockets bound with their egress overriding `options`
+    pub(crate) fn with_dest_egress(
+        mut self,
+        options: SendSocketOptions,
+        dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>,
+    ) -> Self {
+        self.dest_egress = Some(DestEgressSinks {
+            options,
+            dest_egress,
+            sinks: RefCell::default(),
+        });
+        self
+    }
+
+    /// Sends from the socket bound with `dest`'s own egress, returning false if it's sent to from this sink's socket
+    fn send_with_dest_egress(&self, dest: SocketAddr, packets: &[&[u8]]) -> bool {
+        let Some(dest_egress) = &self.dest_egress else {
+            return false;
+        };
+        let options = match dest_egress.dest_egress.load().get(&dest) {
+            Some(egress) => dest_egress.options.with_dest_egress(egress),
+            None => return false,
+        };
+        if options.egress == dest_egress.options.egress {
+            return false;
+        }
+        let mut sinks = dest_egress.sinks.borrow_mut();
+        let sink = sinks.entry(options.egress.clone()).or_insert_with(|| {
+            let socket = options
+                .bind()
+                .map_err(|e| {
+                    error!(
+                        "Failed to bind forwarding socket to {}, dropping packets for destinations using it. Error: {e}",
+                        options.egress
+                    )
+                })
+                .ok()?;
+            let mut sink = UdpSink::new(
+                socket,
+                self.send_batch_size,
+                self.slow_send_threshold,
+                self.metrics.clone(),
             );
+            if let Some((_, rebind_policy, _)) = &self.rebind {
+                sink = sink.with_rebind(options.clone(), rebind_policy);
+            }
+            Some(sink)
+        });
+        match sink {
+            Some(sink) => sink.send(dest, packets),
+            None => self
+                .metrics
+                .record_forward(dest, Transport::Udp, 0, packets.len() as u64),
+        }
+        true
+    }
+
+    pub(crate) fn with_rebind(
+        mut self,
+        options: SendSocketOptions,
+        rebind_policy: &Arc<RebindPolicy>,
+    ) -> Self {
+        self.rebind = Some((
+            options,
+            rebind_policy.clone(),
+            rebind_policy.error_tracker(),
+        ));
+        self
+    }
+
+    fn record_send_error(&self, err: &io::Error) {
+        let Some((options, rebind_policy, send_errors)) = &self.rebind else {
+            return;
+        };
+        if !send_errors.record_error(err) {
+            return;
+        }
+        if let Some(socket) = rebind_socket("forwarding", err, rebind_policy, &self.metrics, || {
+            options.bind()
+        }) {
+            self.ipv6_socket
+                .set(socket.local_addr().is_ok_and(|addr| addr.is_ipv6()));
+            *self.socket.borrow_mut() = socket;
+        }
+    }
+}
+
+impl ShredSink for UdpSink {
+    fn send(&self, dest: SocketAddr, packets: &[&[u8]]) {
+        if self.send_with_dest_egress(dest, packets) {
+            return;
+        }
+        let mut packets_with_dest: Vec<(&[u8], SocketAddr)> = recycle_vec(self.send_scratch.take());
+        // batch_send uses sendmmsg on linux, falling back to send_to per packet elsewhere
+        packets.chunks(self.send_batch_size).for_each(|chunk| {
+            // the socket may have been rebound by the previous chunk
+            let send_addr = socket::send_addr(self.ipv6_socket.get(), dest);
+            packets_with_dest.clear();
+            packets_with_dest.extend(chunk.iter().map(|data| (*data, send_addr)));
+
+            let send_start = self.slow_send_threshold.map(|_| Instant::now());
+            let result = batch_send(&self.socket.borrow(), &packets_with_dest);
+            if let (Some(send_start), Some(slow_send_threshold)) =
+                (send_start, self.slow_send_threshold)
+            {
+                self.metrics
+                    .record_send_latency(dest, send_start.elapsed(), slow_send_threshold);
+            }
+            let num_failed = match result {
+                Ok(_) => {
+                    if let Some((_, _, send_errors)) = &self.rebind {
+                        send_errors.record_success();
+                    }
+                    0
+                }
+                Err(SendPktsError::IoError(err, num_failed)) => {
+                    error!("Failed to send batch of size {} to {dest:?}. {num_failed} packets failed. Error: {err}", packets_with_dest.len());
+                    self.record_send_error(&err);
+                    let num_failed = num_failed.min(packets_with_dest.len());
+                    // sending stopped at the first packet that failed
+                    if socket::is_message_too_large(&err) && num_failed > 0 {
+                        self.metrics
+                            .record_emsgsize(dest, chunk[chunk.len() - num_failed].len());
+                    }
+                    num_failed
+                }
+            };
+            let num_success = (packets_with_dest.len() - num_failed) as u64;
+            self.thread_metrics
+                .add_udp_forward(dest, num_success, num_failed as u64);
+        });
+        *self.send_scratch.borrow_mut() = recycle_vec(packets_with_dest);
+    }
+}
+
+/// Empties `vec`, returning its allocation for another element type, usually the same type borrowing for a different lifetime.
+/// Collecting a vec's own iterator reuses its allocation when the layouts match, otherwise the result is allocated on first use
+pub(crate) fn recycle_vec<T, U>(mut vec: Vec<T>) -> Vec<U> {
+    vec.clear();
+    vec.into_iter().map(|_| unreachable!()).collect()
+}
+
+/// What the deduper hashes to recognize a packet it has seen, see `--dedup-mode`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupMode {
+    /// The whole packet, so copies of a shred with different payloads are all forwarded
+    #[default]
+    Payload,
+    /// The shred's variant, slot, index, and version, falling back to the whole packet for packets that aren't shreds.
+    /// Far cheaper, but only the first copy of a shred is forwarded even if a later one has a different payload
+    ShredId,
+}
+
+/// [Deduper] that tracks how many packets it has seen, to estimate its saturation since [Deduper] doesn't expose it.
+/// Replaced by the accessory thread once too saturated or too old, see [maybe_reset_deduper].
+pub struct ShredDeduper {
+    deduper: Deduper<DEDUPER_NUM_HASHES, [u8]>,
+    mode: DedupMode,
+    num_bits: u64,
+    /// Packets inserted, each setting up to [DEDUPER_NUM_HASHES] bits
+    num_inserted: AtomicU64,
+    created: Instant,
+}
+
+impl ShredDeduper {
+    pub fn new<R: rand::Rng>(rng: &mut R, num_bits: u64, mode: DedupMode) -> Self {
+        Self {
+            deduper: Deduper::new(rng, num_bits),
+            mode,
+            num_bits,
+            num_inserted: AtomicU64::new(0),
+            created: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> DedupMode {
+        self.mode
+    }
+
+    /// Marks duplicate packets as discarded. Returns number of discarded packets, including ones discarded before
+    pub fn dedup_packets(&self, packet_batches: &mut [PacketBatch]) -> u64 {
+        self.dedup_packets_with(packet_batches, |_duplicate| {})
+    }
+
+    /// Like [Self::dedup_packets], also passing each packet found to be a duplicate to `on_duplicate`
+    pub fn dedup_packets_with(
+        &self,
+        packet_batches: &mut [PacketBatch],
+        mut on_duplicate: impl FnMut(&Packet),
+    ) -> u64 {
+        let mut num_inserted = 0;
+        let num_discarded = packet_batches
+            .iter_mut()
+            .flat_map(PacketBatch::iter_mut)
+            .map(|packet| {
+                if packet.meta().discard() {
+                    return 1;
+                }
+                let is_dup = packet.data(..).map_or(true, |data| match self.mode {
+                    DedupMode::Payload => self.deduper.dedup(data),
+                    DedupMode::ShredId => self
+                        .deduper
+                        .dedup(shred::get_shred_id(data).unwrap_or(data)),
+                });
+                if !is_dup {
+                    num_inserted += 1;
+                    return 0;
+                }
+                // before discarding, since discarded packets have no data
+                on_duplicate(packet);
+                packet.meta_mut().set_discard(true);
+                1
+            })
+            .sum();
+        self.num_inserted.fetch_add(num_inserted, Ordering::Relaxed);
+        num_discarded
+    }
+
+    /// Estimated fraction of bits set, assuming uniform hashing
+    pub fn estimated_saturation(&self) -> f64 {
+        let num_inserted = self.num_inserted.load(Ordering::Relaxed) as f64;
+        1.0 - (-(DEDUPER_NUM_HASHES as f64) * num_inserted / self.num_bits as f64).exp()
+    }
+
+    /// Estimated chance a new packet is mistaken for a duplicate
+    pub fn estimated_false_positive_rate(&self) -> f64 {
+        self.estimated_saturation().powi(DEDUPER_NUM_HASHES as i32)
+    }
+
+    /// Time since this deduper replaced the previous one
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+
+    fn saturated(&self, false_positive_rate: f64) -> bool {
+        self.estimated_false_positive_rate() >= false_positive_rate
+    }
+
+    fn needs_reset(&self, false_positive_rate: f64, reset_interval: Duration) -> bool {
+        self.saturated(false_positive_rate) || self.age() >= reset_interval
+    }
+
+    /// Saturated before `reset_interval` elapsed, so a reset now comes ahead of schedule
+    fn needs_early_reset(&self, false_positive_rate: f64, reset_interval: Duration) -> bool {
+        self.saturated(false_positive_rate) && self.age() < reset_interval
+    }
+
+    fn report(&self, false_positive_rate: f64) {
+        let estimated_false_positive_rate = self.estimated_false_positive_rate();
+        datapoint_info!(
+            "shredstream_proxy-deduper_stats",
+            ("num_bits", self.num_bits, i64),
+            (
+                "num_inserted",
+                self.num_inserted.load(Ordering::Relaxed),
+                i64
+            ),
+            ("estimated_saturation", self.estimated_saturation(), f64),
+            (
+                "estimated_false_positive_rate",
+                estimated_false_positive_rate,
+                f64
+            ),
+            ("saturated", self.saturated(false_positive_rate), bool),
+            ("age_ms", self.age().as_millis() as u64, i64),
+        );
+    }
+}
+
+/// Swaps in a fresh deduper if the estimated false positive rate reaches `false_positive_rate` or `reset_interval` elapsed.
+/// Reports stats beforehand so operators can tell whether the deduper is sized adequately.
+/// Returns true if swapped.
+///
+/// Forwarder threads dedup against whichever deduper they loaded, so they never block on a reset.
+/// Packets deduped against the old deduper while the swap happens aren't inserted into the new one,
+/// so a packet can be considered new by both and forwarded twice.
+pub fn maybe_reset_deduper<R: rand::Rng>(
+    deduper: &ArcSwap<ShredDeduper>,
+    rng: &mut R,
+    false_positive_rate: f64,
+    reset_interval: Duration,
+) -> bool {
+    let current = deduper.load();
+    if !current.needs_reset(false_positive_rate, reset_interval) {
+        return false;
+    }
+    current.report(false_positive_rate);
+    // build before swapping so forwarders are never left without a deduper
+    let fresh = Arc::new(ShredDeduper::new(rng, current.num_bits, current.mode));
+    drop(current);
+    deduper.store(fresh);
+    true
+}
+
+/// Which shred types are forwarded, see `--forward-shred-types`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardShredTypes {
+    #[default]
+    All,
+    Data,
+    Code,
+}
+
+/// Sources packets are accepted from, from `allowed-source-ips` and `allowed-source-ips-file`.
+/// Checked before deduping so packets from unknown sources can't poison the deduper.
+#[derive(Default)]
+pub struct SourceAllowlist {
+    /// (`allowed-source-ips`, `allowed-source-ips-file`), kept to rebuild `allowed` when the file changes
+    sources: Mutex<(Vec<IpNet>, Option<PathBuf>)>,
+    /// `None` accepts packets from any source
+    allowed: ArcSwapOption<Vec<IpNet>>,
+}
+
+impl SourceAllowlist {
+    pub fn new(allowed_ips: Vec<IpNet>, allowed_ips_file: Option<PathBuf>) -> io::Result<Self> {
+        let source_allowlist = Self::default();
+        source_allowlist.set(allowed_ips, allowed_ips_file)?;
+        Ok(source_allowlist)
+    }
+
+    /// Replaces the allowlist, keeping the current one if the file can't be read
+    pub fn set(
+        &self,
+        allowed_ips: Vec<IpNet>,
+        allowed_ips_file: Option<PathBuf>,
+    ) -> io::Result<()> {
+        let mut sources = self.sources.lock().unwrap();
+        self.allowed
+            .store(Self::load(&allowed_ips, allowed_ips_file.as_ref())?.map(Arc::new));
+        *sources = (allowed_ips, allowed_ips_file);
+        Ok(())
+    }
+
+    /// Re-reads `allowed-source-ips-file`, if set, to pick up changed upstream addresses
+    pub fn reload_file(&self) -> io::Result<()> {
+        let sources = self.sources.lock().unwrap();
+        let (allowed_ips, Some(allowed_ips_file)) = &*sources else {
+            return Ok(());
+        };
+        self.allowed
+            .store(Self::load(allowed_ips, Some(allowed_ips_file))?.map(Arc::new));
+        Ok(())
+    }
+
+    /// Returns `None` if neither is set, allowing all sources
+    fn load(
+        allowed_ips: &[IpNet],
+        allowed_ips_file: Option<&PathBuf>,
+    ) -> io::Result<Option<Vec<IpNet>>> {
+        let Some(allowed_ips_file) = allowed_ips_file else {
+            return Ok((!allowed_ips.is_empty()).then(|| allowed_ips.to_vec()));
+        };
+        let mut allowed = allowed_ips.to_vec();
+        allowed.extend(parse_allowed_source_ips(&fs::read_to_string(
+            allowed_ips_file,
+        )?)?);
+        Ok(Some(allowed))
+    }
+
+    pub fn is_allowed(&self, src: IpAddr) -> bool {
+        self.allowed
+            .load()
+            .as_deref()
+            .map_or(true, |allowed| Self::contains(allowed, src))
+    }
+
+    fn contains(allowed: &[IpNet], src: IpAddr) -> bool {
+        // IPv4 sources arrive as IPv4-mapped addresses on dual-stack sockets
+        let src = src.to_canonical();
+        allowed.iter().any(|net| net.contains(&src))
+    }
+
+    /// Marks packets from sources not allowed as discarded, returning how many
+    fn apply(&self, packet_batches: &mut [PacketBatch]) -> u64 {
+        let Some(allowed) = self.allowed.load_full() else {
+            return 0;
+        };
+        let mut num_not_allowed = 0;
+        packet_batches
+            .iter_mut()
+            .flat_map(|batch| batch.iter_mut())
+            .filter(|packet| !packet.meta().discard())
+            .filter(|packet| !Self::contains(&allowed, packet.meta().addr))
+            .for_each(|packet| {
+                packet.meta_mut().set_discard(true);
+                num_not_allowed += 1;
+            });
+        num_not_allowed
+    }
+}
+
+/// Parses one IP or CIDR per line, ignoring blank lines and `#` comments
+pub fn parse_allowed_source_ips(contents: &str) -> io::Result<Vec<IpNet>> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| parse_ip_net(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        .collect()
+}
+
+/// Highest slot seen, which can be shared across threads.
+/// It's seeded from the first `HIGHEST_SLOT_SEED_SAMPLES` slots received, ignoring those far ahead of the rest, then a
+/// slot only advances it while within `MAX_SLOTS_AHEAD_OF_CLOCK` of the slot clock, estimated from the seed, so a
+/// single spoofed shred with a far future slot can't make every other shred look stale.
+/// If it stops advancing, because it was seeded from a bad slot or the cluster restarted, it's reseeded after
+/// `HIGHEST_SLOT_RESEED_AFTER`
+pub struct HighestSlot {
+    /// 0 while seeding
+    slot: AtomicU64,
+    /// Slot the clock estimate puts at `created_at`
+    clock_base_slot: AtomicU64,
+    /// Milliseconds after `created_at` when `slot` last advanced
+    advanced_ms: AtomicU64,
+    /// Slots received while seeding
+    seed_slots: Mutex<Vec<Slot>>,
+    created_at: Instant,
+}
+
+impl Default for HighestSlot {
+    fn default() -> Self {
+        Self {
+            slot: AtomicU64::new(0),
+            clock_base_slot: AtomicU64::new(0),
+            advanced_ms: AtomicU64::new(0),
+            seed_slots: Mutex::new(Vec::with_capacity(HIGHEST_SLOT_SEED_SAMPLES)),
+            created_at: Instant::now(),
+        }
+    }
+}
+
+impl HighestSlot {
+    /// Returns the highest slot seen, including `slot` received at `now` unless it's an outlier.
+    /// None while `slot` is sampled to seed it
+    pub fn observe(&self, slot: Slot, now: Instant) -> Option<Slot> {
+        let now_ms = now.saturating_duration_since(self.created_at).as_millis() as u64;
+        let highest_slot = self.slot.load(Ordering::Relaxed);
+        if highest_slot == 0 {
+            return self.sample_seed(slot, now);
+        }
+        let advanced_ms = self.advanced_ms.load(Ordering::Relaxed);
+        if now_ms.saturating_sub(advanced_ms) >= HIGHEST_SLOT_RESEED_AFTER.as_millis() as u64 {
+            // only the thread that sees it stalled first clears the samples
+            if self
+                .slot
+                .compare_exchange(highest_slot, 0, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                self.seed_slots.lock().unwrap().clear();
+            }
+            return self.sample_seed(slot, now);
+        }
+        if slot <= highest_slot {
+            return Some(highest_slot);
+        }
+        let clock_slot = self
+            .clock_base_slot
+            .load(Ordering::Relaxed)
+            .saturating_add(now_ms / DEFAULT_MS_PER_SLOT);
+        if slot > clock_slot.saturating_add(MAX_SLOTS_AHEAD_OF_CLOCK) {
+            return Some(highest_slot);
+        }
+        self.advanced_ms.store(now_ms, Ordering::Relaxed);
+        Some(self.slot.fetch_max(slot, Ordering::Relaxed).max(slot))
+    }
+
+    /// Samples `slot`, seeding from the samples once there are enough of them
+    fn sample_seed(&self, slot: Slot, now: Instant) -> Option<Slot> {
+        let mut seed_slots = self.seed_slots.lock().unwrap();
+        // seeded by another thread since
+        if self.slot.load(Ordering::Relaxed) != 0 {
+            drop(seed_slots);
+            return self.observe(slot, now);
+        }
+        seed_slots.push(slot);
+        if seed_slots.len() < HIGHEST_SLOT_SEED_SAMPLES {
+            return None;
+        }
+
+        // the highest sample that isn't far ahead of the median, so a few spoofed slots among the samples are ignored
+        seed_slots.sort_unstable();
+        let median = seed_slots[seed_slots.len() / 2];
+        let seed = seed_slots
+            .drain(..)
+            .filter(|slot| *slot <= median.saturating_add(MAX_SLOTS_AHEAD_OF_CLOCK))
+            .max()
+            .unwrap_or(median);
+        let now_ms = now.saturating_duration_since(self.created_at).as_millis() as u64;
+        self.clock_base_slot.store(
+            seed.saturating_sub(now_ms / DEFAULT_MS_PER_SLOT),
+            Ordering::Relaxed,
+        );
+        self.advanced_ms.store(now_ms, Ordering::Relaxed);
+        self.slot.store(seed, Ordering::Relaxed);
+        None
+    }
+}
+
+/// Filters applied to received packets after deduping, before forwarding.
+/// Settings are atomics so they can be updated while forwarding.
+pub struct PacketFilter {
+    /// Drop shreds more than this many slots behind the highest slot seen. `u64::MAX` disables
+    max_slot_age: AtomicU64,
+    /// Drop packets that don't parse as a shred. Otherwise they are forwarded as is
+    drop_non_shred_packets: AtomicBool,
+    drop_data_shreds: AtomicBool,
+    drop_code_shreds: AtomicBool,
+    /// Highest slot seen, shared across forwarder threads
+    highest_slot: HighestSlot,
+    created_at: Instant,
+    /// Milliseconds after `created_at` when sources of packets that aren't shreds may be logged again
+    next_invalid_source_log_ms: AtomicU64,
+}
+
+impl Default for PacketFilter {
+    fn default() -> Self {
+        Self::new(None, false, ForwardShredTypes::All)
+    }
+}
+
+impl PacketFilter {
+    pub fn new(
+        max_slot_age: Option<Slot>,
+        drop_non_shred_packets: bool,
+        forward_shred_types: ForwardShredTypes,
+    ) -> Self {
+        let packet_filter = Self {
+            max_slot_age: AtomicU64::new(u64::MAX),
+            drop_non_shred_packets: AtomicBool::new(false),
+            drop_data_shreds: AtomicBool::new(false),
+            drop_code_shreds: AtomicBool::new(false),
+            highest_slot: HighestSlot::default(),
+            created_at: Instant::now(),
+            next_invalid_source_log_ms: AtomicU64::new(0),
+        };
+        packet_filter.set(max_slot_age, drop_non_shred_packets, forward_shred_types);
+        packet_filter
+    }
+
+    pub fn set(
+        &self,
+        max_slot_age: Option<Slot>,
+        drop_non_shred_packets: bool,
+        forward_shred_types: ForwardShredTypes,
+    ) {
+        self.max_slot_age
+            .store(max_slot_age.unwrap_or(u64::MAX), Ordering::Relaxed);
+        self.drop_non_shred_packets
+            .store(drop_non_shred_packets, Ordering::Relaxed);
+        self.drop_data_shreds.store(
+            forward_shred_types == ForwardShredTypes::Code,
+            Ordering::Relaxed,
+        );
+        self.drop_code_shreds.store(
+            forward_shred_types == ForwardShredTypes::Data,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Marks filtered packets as discarded
+    fn apply(&self, packet_batches: &mut [PacketBatch], metrics: &ShredMetrics) {
+        let max_slot_age = self.max_slot_age.load(Ordering::Relaxed);
+        let drop_non_shred_packets = self.drop_non_shred_packets.load(Ordering::Relaxed);
+        let drop_data_shreds = self.drop_data_shreds.load(Ordering::Relaxed);
+        let drop_code_shreds = self.drop_code_shreds.load(Ordering::Relaxed);
+        let now = Instant::now();
+
+        let mut num_stale = 0u64;
+        let mut num_invalid = 0u64;
+        let mut invalid_sources = Vec::new();
+        let mut num_non_shred = 0u64;
+        let mut num_data_filtered = 0u64;
+        let mut num_code_filtered = 0u64;
+        packet_batches
+            .iter_mut()
+            .flat_map(|batch| batch.iter_mut())
+            .filter(|packet| !packet.meta().discard())
+            .for_each(|packet| {
+                // only reads the length and variant byte, so this is cheap enough to run on every packet
+                let Some(shred_type) = packet.data(..).and_then(shred::get_shred_type) else {
+                    num_invalid += 1;
+                    let source = packet.meta().addr;
+                    if invalid_sources.len() < MAX_LOGGED_INVALID_SOURCES
+                        && !invalid_sources.contains(&source)
+                    {
+                        invalid_sources.push(source);
+                    }
+                    if drop_non_shred_packets {
+                        packet.meta_mut().set_discard(true);
+                        num_non_shred += 1;
+                    }
+                    return;
+                };
+                match shred_type {
+                    ShredType::Data if drop_data_shreds => {
+                        packet.meta_mut().set_discard(true);
+                        num_data_filtered += 1;
+                        return;
+                    }
+                    ShredType::Code if drop_code_shreds => {
+                        packet.meta_mut().set_discard(true);
+                        num_code_filtered += 1;
+                        return;
+                    }
+                    _ => {}
+                }
+                if max_slot_age == u64::MAX {
+                    return;
+                }
+                let Some(slot) = packet.data(..).and_then(shred::get_slot) else {
+                    return;
+                };
+                let Some(highest_slot) = self.highest_slot.observe(slot, now) else {
+                    return;
+                };
+                if slot.saturating_add(max_slot_age) < highest_slot {
+                    packet.meta_mut().set_discard(true);
+                    num_stale += 1;
+                }
+            });
+        metrics
+            .stale_slot_dropped
+            .fetch_add(num_stale, Ordering::Relaxed);
+        metrics
+            .received_invalid
+            .fetch_add(num_invalid, Ordering::Relaxed);
+        if num_invalid > 0 {
+            self.log_invalid_sources(num_invalid, &invalid_sources, drop_non_shred_packets);
+        }
+        metrics
+            .non_shred_dropped
+            .fetch_add(num_non_shred, Ordering::Relaxed);
+        metrics
+            .data_shred_filtered
+            .fetch_add(num_data_filtered, Ordering::Relaxed);
+        metrics
+            .code_shred_filtered
+            .fetch_add(num_code_filtered, Ordering::Relaxed);
+    }
+
+    /// Logs a sample of the sources sending packets that aren't shreds, at most once per `INVALID_SOURCE_LOG_INTERVAL`
+    /// across all forwarder threads. Usually scanners hitting the listen port, so they can be firewalled
+    fn log_invalid_sources(&self, num_invalid: u64, sources: &[IpAddr], dropped: bool) {
+        let now_ms = self.created_at.elapsed().as_millis() as u64;
+        let next_log_ms = self.next_invalid_source_log_ms.load(Ordering::Relaxed);
+        if now_ms < next_log_ms
+            || self
+                .next_invalid_source_log_ms
+                .compare_exchange(
+                    next_log_ms,
+                    now_ms + INVALID_SOURCE_LOG_INTERVAL.as_millis() as u64,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        warn!(
+            "Received {num_invalid} packets that aren't shreds ({}), sources include: {}.",
+            if dropped { "dropped" } else { "forwarded" },
+            sources.iter().join(", ")
+        );
+    }
+}
+
+/// Drains queued batches after `first` until `max_packets` are collected or `max_linger` elapses.
+/// Lets a single `sendmmsg` call carry packets from several receive batches.
+fn coalesce_packet_batches(
+    first: ReceivedBatch,
+    packet_receiver: &Receiver<ReceivedBatch>,
+    max_packets: usize,
+    max_linger: Duration,
+) -> Vec<ReceivedBatch> {
+    let deadline = Instant::now() + max_linger;
+    let mut num_packets = first.packets.len();
+    let mut packet_batches = vec![first];
+    while num_packets < max_packets {
+        // returns queued batches immediately, even if the deadline has passed
+        match packet_receiver.recv_deadline(deadline) {
+            Ok(packet_batch) => {
+                num_packets += packet_batch.packets.len();
+                packet_batches.push(packet_batch);
+            }
+            // disconnects are surfaced on the next receive in the forwarder loop
+            Err(_) => break,
+        }
+    }
+    packet_batches
+}
+
+/// Seals packets for an `enc://` destination, then sends them like any UDP destination.
+/// Dropped and counted if its key file couldn't be read, never sent unsealed
+fn send_sealed(
+    udp_sink: &UdpSink,
+    dest: SocketAddr,
+    packets: &[&[u8]],
+    key_file: &KeyFile,
+    metrics: &ShredMetrics,
+) {
+    let mut sealed = Vec::with_capacity(packets.len() * MAX_SEALED_PACKET_SIZE);
+    let mut sealed_ends = Vec::with_capacity(packets.len());
+    for packet in packets {
+        if !key_file.seal(packet, &mut sealed) {
+            metrics
+                .encrypt_dropped
+                .fetch_add(packets.len() as u64, Ordering::Relaxed);
+            return;
+        }
+        sealed_ends.push(sealed.len());
+    }
+    let sealed_packets = sealed_ends
+        .iter()
+        .scan(0, |start, &end| {
+            let sealed_packet = &sealed[*start..end];
+            *start = end;
+            Some(sealed_packet)
+        })
+        .collect::<Vec<&[u8]>>();
+    udp_sink.send(dest, &sealed_packets);
+}
+
+/// Batches a forwarder received, after filtering, held in one allocation that every destination's send borrows from.
+/// Their buffers stay counted in [MemoryGuard] until the last holder drops them, which also returns them to the listener's recycler
+pub struct SharedBatches {
+    pub packet_batches: Vec<PacketBatch>,
+    _held: Vec<HeldBytes>,
+}
+
+impl SharedBatches {
+    pub fn new(packet_batches: Vec<PacketBatch>, held: Vec<HeldBytes>) -> Self {
+        Self {
+            packet_batches,
+            _held: held,
+        }
+    }
+}
+
+/// What a forwarder thread forwards through, built once per thread. The optional sinks and taps are None when disabled
+struct ForwarderContext {
+    deduper: Arc<ArcSwap<ShredDeduper>>,
+    source_allowlist: Arc<SourceAllowlist>,
+    packet_filter: Arc<PacketFilter>,
+    udp_sink: UdpSink,
+    quic_sink: Arc<QuicSink>,
+    tunnel_sink: Arc<TunnelSink>,
+    unix_sink: Arc<UnixSink>,
+    isolated_send_sink: Option<Arc<IsolatedSendSink>>,
+    spill_store: Option<Arc<SpillStore>>,
+    chaos: Option<Arc<ChaosInjector>>,
+    mirror_sink: Option<MirrorSink>,
+    deshred_tap: Option<Arc<DeshredTap>>,
+    pcap_tap: Option<PcapTap>,
+    subscriber_tap: Option<SubscriberTap>,
+    kafka_tap: Option<KafkaTap>,
+    slot_latency_tap: Option<Arc<SlotLatencyTap>>,
+    conflict_tap: Option<ConflictTap>,
+    events_tap: Option<Arc<EventsTap>>,
+    archive_tap: Option<ArchiveTap>,
+    ws_tap: Option<Arc<WsTap>>,
+    region: Option<String>, /* region the packets were received in, if tagged */
+    listener: Option<String>, /* `--listener` pipeline, `None` for the main pipeline */
+    trace_shred_sampler: Arc<TraceShredSampler>,
+    metrics: Arc<ShredMetrics>,
+}
+
+/// A forwarder thread's snapshot of its destinations, reloaded every `dest_refresh_interval`
+#[derive(Default)]
+struct LocalDests {
+    dest_sockets: Arc<Vec<SocketAddr>>,
+    quic_dest_sockets: Arc<HashSet<SocketAddr>>, /* subset of `dest_sockets` sent to over QUIC */
+    tunnel_dests: Arc<HashMap<SocketAddr, TunnelDest>>, /* subset sent to over TCP/TLS */
+    unix_dests: Arc<HashMap<SocketAddr, UnixDest>>, /* subset sent to over unix sockets */
+    enc_dests: Arc<HashMap<SocketAddr, Arc<KeyFile>>>, /* subset whose packets are sealed */
+    dest_rate_limits: Arc<HashMap<SocketAddr, Arc<RateLimiter>>>,
+    dest_priorities: Arc<HashMap<SocketAddr, DestPriority>>,
+    dest_spill: Arc<HashMap<SocketAddr, bool>>, /* true while spilled to */
+    shard_groups: Arc<Vec<ShardGroup>>,
+}
+
+/// Broadcasts same packet to multiple recipients
+/// Returns Err when unable to receive packets.
+fn recv_from_channel_and_send_multiple_dest(
+    maybe_packet_batches: Result<Vec<ReceivedBatch>, RecvError>,
+    backlog: f64, /* fraction of the forwarder's queue filled, destinations are dropped by priority as it grows */
+    dests: &LocalDests,
+    context: &ForwarderContext,
+) -> Result<(), ShredstreamProxyError> {
+    let ForwarderContext {
+        deduper,
+        source_allowlist,
+        packet_filter,
+        udp_sink,
+        quic_sink,
+        tunnel_sink,
+        unix_sink,
+        isolated_send_sink,
+        spill_store,
+        chaos,
+        mirror_sink,
+        deshred_tap,
+        pcap_tap,
+        subscriber_tap,
+        kafka_tap,
+        slot_latency_tap,
+        conflict_tap,
+        events_tap,
+        archive_tap,
+        ws_tap,
+        region,
+        listener,
+        trace_shred_sampler,
+        metrics,
+    } = context;
+    let LocalDests {
+        dest_sockets,
+        quic_dest_sockets,
+        tunnel_dests,
+        unix_dests,
+        enc_dests,
+        dest_rate_limits,
+        dest_priorities,
+        dest_spill,
+        shard_groups,
+    } = dests;
+    let mut received_batches = maybe_packet_batches.map_err(ShredstreamProxyError::RecvError)?;
+    // receive buffers stay counted until every sink is done with them, including isolated send queues
+    let held = received_batches
+        .iter_mut()
+        .filter_map(|batch| batch.held.take())
+        .collect::<Vec<_>>();
+    let (mut packet_batch_vec, rx_timestamps): (Vec<_>, Vec<_>) = received_batches
+        .into_iter()
+        .map(|batch| (batch.packets, batch.rx_timestamps))
+        .unzip();
+    let trace_shred_received_time = SystemTime::now();
+    let num_received = packet_batch_vec
+        .iter()
+        .map(|batch| batch.len())
+        .sum::<usize>();
+    let thread_metrics = udp_sink.thread_metrics();
+    thread_metrics.add_received(num_received as u64);
+    metrics.record_received_sizes(&packet_batch_vec);
+    debug!(
+        "Got {} batches of {num_received} packets, total size in bytes: {}",
+        packet_batch_vec.len(),
+        packet_batch_vec
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .map(|x| x.meta().size)
+            .sum::<usize>()
+    );
+
+    let num_keepalives = keepalive::discard_keepalives(&mut packet_batch_vec);
+    metrics
+        .keepalive_received
+        .fetch_add(num_keepalives, Ordering::Relaxed);
+    let num_not_allowed = source_allowlist.apply(&mut packet_batch_vec);
+    metrics
+        .source_not_allowed
+        .fetch_add(num_not_allowed, Ordering::Relaxed);
+    // dedup against a snapshot, accessory thread may swap in a fresh deduper meanwhile.
+    // keepalives and packets from sources not allowed are already discarded, so aren't duplicates
+    let current_deduper = deduper.load();
+    // a conflicting copy has the same shred id as the first, so copies dropped by id still go to the conflict detector
+    let mut id_duplicates = vec![];
+    let num_discarded = match conflict_tap {
+        Some(_) if current_deduper.mode() == DedupMode::ShredId => current_deduper
+            .dedup_packets_with(&mut packet_batch_vec, |duplicate| {
+                id_duplicates.extend(duplicate.data(..).map(<[u8]>::to_vec));
+            }),
+        _ => current_deduper.dedup_packets(&mut packet_batch_vec),
+    };
+    drop(current_deduper);
+    let num_deduped = num_discarded - num_keepalives - num_not_allowed;
+    thread_metrics.add_duplicate(num_deduped);
+    if let Some(region) = region {
+        metrics.record_region_received(region, num_received as u64, num_deduped);
+    }
+    if let Some(listener) = listener {
+        metrics.record_listener_received(listener, num_received as u64, num_deduped);
+    }
+
+    packet_batch_vec.iter().for_each(|batch| {
+        batch.iter().for_each(|packet| {
+            metrics
+                .packets_received
+                .entry(packet.meta().addr)
+                .and_modify(|(discarded, not_discarded)| {
+                    *discarded += packet.meta().discard() as u64;
+                    *not_discarded += !packet.meta().discard() as u64;
+                })
+                .or_insert_with(|| {
+                    (
+                        packet.meta().discard() as u64,
+                        !packet.meta().discard() as u64,
+                    )
+                });
         });
+    });
+
+    packet_filter.apply(&mut packet_batch_vec, metrics);
+
+    if let Some(pcap_tap) = pcap_tap {
+        pcap_tap.record(&packet_batch_vec, trace_shred_received_time);
+    }
+    // shared with isolated send threads, rather than copying packets into each destination's queue.
+    // every destination sends from these buffers, which return to the listener's recycler once the last send drops them
+    let shared_batches = Arc::new(SharedBatches::new(packet_batch_vec, held));
+    let packet_batch_vec = &shared_batches.packet_batches;
+
+    // discarded (duplicate or filtered) packets return None from `data()` and are skipped
+    let packets = packet_batch_vec
+        .iter()
+        .flat_map(|batch| batch.iter())
+        .filter_map(|pkt| pkt.data(..))
+        .collect::<Vec<&[u8]>>();
+
+    metrics.slot_coverage.send(&packets);
+    if rx_timestamps.iter().any(Option::is_some) {
+        metrics.record_internal_latency(packet_batch_vec, &rx_timestamps, SystemTime::now());
+    }
+    dest_sockets.iter().for_each(|outgoing_socketaddr| {
+        let priority = dest_priorities
+            .get(outgoing_socketaddr)
+            .copied()
+            .unwrap_or_default();
+        // falling behind, drop lower priority destinations' packets so higher priority ones keep up
+        if priority.is_shed(backlog) {
+            metrics.record_priority_dropped(priority, packets.len() as u64);
+            return;
+        }
+        // spilled while the destination is unavailable, then queued behind the replay once it's back
+        if let (Some(spill_store), Some(spilling)) =
+            (spill_store, dest_spill.get(outgoing_socketaddr))
+        {
+            if spill_store.divert(*outgoing_socketaddr, *spilling, &packets) {
+                return;
+            }
+        }
+        // None for UDP destinations
+        let sink: Option<&dyn ShredSink> = if quic_dest_sockets.contains(outgoing_socketaddr) {
+            Some(&**quic_sink)
+        } else if tunnel_dests.contains_key(outgoing_socketaddr) {
+            Some(&**tunnel_sink)
+        } else if unix_dests.contains_key(outgoing_socketaddr) {
+            Some(&**unix_sink)
+        } else {
+            None
+        };
+        // a lookup per batch, not per packet, so unlimited destinations are unaffected
+        let packets = match dest_rate_limits.get(outgoing_socketaddr) {
+            Some(rate_limiter) => {
+                let num_allowed = rate_limiter.acquire(packets.len(), Instant::now());
+                metrics.record_dest_throttled(
+                    *outgoing_socketaddr,
+                    (packets.len() - num_allowed) as u64,
+                );
+                &packets[..num_allowed]
+            }
+            None => &packets[..],
+        };
+        // sealed per destination, so sent from this thread rather than sharing batches with an isolated send queue
+        if let Some(key_file) = enc_dests.get(outgoing_socketaddr) {
+            send_sealed(udp_sink, *outgoing_socketaddr, packets, key_file, metrics);
+            return;
+        }
+        // faults are injected from this thread, so bypass the isolated send queue too
+        if let Some(chaos) = chaos
+            .as_deref()
+            .filter(|chaos| sink.is_none() && chaos.applies_to(outgoing_socketaddr))
+        {
+            chaos.send(udp_sink, *outgoing_socketaddr, packets, Instant::now());
+            return;
+        }
+        match (sink, isolated_send_sink) {
+            (Some(sink), _) => sink.send(*outgoing_socketaddr, packets),
+            (None, Some(isolated_send_sink)) => isolated_send_sink.send_shared(
+                *outgoing_socketaddr,
+                priority,
+                &shared_batches,
+                packets.len(),
+            ),
+            (None, None) => udp_sink.send(*outgoing_socketaddr, packets),
+        }
+    });
+    for shard_group in shard_groups.iter() {
+        for (member, member_packets) in shard_group.assign(&packets) {
+            metrics.record_shard_assigned(member, member_packets.len() as u64);
+            udp_sink.send(member, &member_packets);
+        }
+    }
+    if let Some(mirror_sink) = mirror_sink {
+        mirror_sink.send(&packets, backlog);
+    }
+
+    if let Some(deshred_tap) = deshred_tap {
+        deshred_tap.send(&packets);
+    }
+    if let Some(subscriber_tap) = subscriber_tap {
+        subscriber_tap.send(packet_batch_vec);
+    }
+    if let Some(kafka_tap) = kafka_tap {
+        kafka_tap.record(packet_batch_vec, trace_shred_received_time);
+    }
+    if let Some(slot_latency_tap) = slot_latency_tap {
+        slot_latency_tap.record(&packets, trace_shred_received_time);
+    }
+    if let Some(conflict_tap) = conflict_tap {
+        conflict_tap.send(&packets);
+        conflict_tap.send_owned(id_duplicates);
+    }
+    if let Some(events_tap) = events_tap {
+        events_tap.record(&packets, trace_shred_received_time);
+    }
+    if let Some(archive_tap) = archive_tap {
+        archive_tap.send(&packets);
+    }
+    if let Some(ws_tap) = ws_tap {
+        ws_tap.send(&packets, trace_shred_received_time);
+    }
+
+    let trace_shred_sample_rate = trace_shred_sampler.sample_rate();
+    if trace_shred_sample_rate > 0.0 {
+        trace_sampled_shreds(
+            packet_batch_vec,
+            trace_shred_sample_rate,
+            trace_shred_received_time,
+        );
     }
+    // trace shreds aren't shreds, so they're only decoded when tracing everything
+    if trace_shred_sample_rate >= 1.0 {
+        packets
+            .iter()
+            .filter_map(|data| TraceShred::decode(*data).ok())
+            .filter(|t| t.created_at.is_some())
+            .for_each(|trace_shred| {
+                let elapsed = trace_shred_received_time
+                    .duration_since(SystemTime::try_from(trace_shred.created_at.unwrap()).unwrap())
+                    .unwrap_or_default();
+
+                datapoint_info!(
+                    "shredstream_proxy-trace_shred_latency",
+                    "trace_region" => trace_shred.region,
+                    ("trace_seq_num", trace_shred.seq_num as i64, i64),
+                    ("elapsed_micros", elapsed.as_micros(), i64),
+                );
+            });
+    }
+    Ok(())
+}
+
+/// Fraction of shreds traced, adjustable at runtime by config reload and the admin API
+#[derive(Debug, Default)]
+pub struct TraceShredSampler {
+    /// f64 bits
+    sample_rate: AtomicU64,
+}
+
+impl TraceShredSampler {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate: AtomicU64::new(sample_rate.to_bits()),
+        }
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        f64::from_bits(self.sample_rate.load(Ordering::Relaxed))
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: f64) {
+        self.sample_rate
+            .store(sample_rate.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Whether the shred is traced at `sample_rate`. Depends only on (slot, index), so proxies sampling at the same rate
+/// trace the same shreds and their receive times can be compared across hosts
+pub fn is_trace_sampled(sample_rate: f64, slot: Slot, index: u32) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    if sample_rate <= 0.0 {
+        return false;
+    }
+    // a fixed mixer rather than std's hasher, whose output may change between releases
+    let hash = splitmix64(splitmix64(slot) ^ u64::from(index));
+    (hash as f64) < sample_rate * u64::MAX as f64
+}
+
+pub(crate) fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// [splitmix64] folded over `bytes` 8 at a time, starting from `seed`
+pub(crate) fn splitmix64_bytes(bytes: &[u8], seed: u64) -> u64 {
+    bytes.chunks(8).fold(splitmix64(seed), |hash, chunk| {
+        let mut word = [0u8; 8];
+        word[..chunk.len()].copy_from_slice(chunk);
+        splitmix64(hash ^ u64::from_le_bytes(word))
+    })
+}
+
+/// Logs and reports a datapoint for each sampled shred that wasn't discarded
+fn trace_sampled_shreds(packet_batches: &[PacketBatch], sample_rate: f64, received_at: SystemTime) {
+    let received_at_unix_micros = received_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i64;
+    packet_batches
+        .iter()
+        .flat_map(|batch| batch.iter())
+        .filter_map(|packet| {
+            let data = packet.data(..)?;
+            Some((
+                shred::get_slot(data)?,
+                shred::get_index(data)?,
+                packet.meta().socket_addr(),
+            ))
+        })
+        .filter(|(slot, index, _src)| is_trace_sampled(sample_rate, *slot, *index))
+        .for_each(|(slot, index, src)| {
+            datapoint_info!(
+                "shredstream_proxy-trace_shred_sample",
+                ("slot", slot, i64),
+                ("index", index, i64),
+                ("src_addr", src.to_string(), String),
+                ("received_at_unix_micros", received_at_unix_micros, i64),
+            );
+        });
+}
+
+/// Endpoints never forwarded to: `dest-blocklist`, and the proxy's own listen addresses unless `allow-self-forward`,
+/// since a discovery service returning the proxy itself would loop shreds back until the deduper saturates
+pub struct DestinationBlocklist {
+    blocked_ips: Vec<IpNet>,
+    /// IPs the proxy is reachable on, blocked together with `self_ports`
+    self_ips: Vec<IpAddr>,
+    /// Leading `self_ips` derived from the bind IP, which are never unblocked
+    num_bind_ips: usize,
+    self_ports: Vec<u16>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl DestinationBlocklist {
+    /// Blocks `blocked_ips`, and `bind_ip` on any of `self_ports` if not empty.
+    /// An unspecified `bind_ip` blocks loopback and unspecified addresses on those ports
+    pub fn new(
+        blocked_ips: Vec<IpNet>,
+        bind_ip: IpAddr,
+        self_ports: Vec<u16>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
+        let self_ips = if bind_ip.is_unspecified() {
+            vec![
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ]
+        } else {
+            vec![bind_ip.to_canonical()]
+        };
+        Self {
+            blocked_ips,
+            num_bind_ips: self_ips.len(),
+            self_ips,
+            self_ports,
+            metrics,
+        }
+    }
+
+    /// Blocks another IP the proxy is reachable on, eg. its public IP once fetched
+    pub fn add_self_ip(&mut self, ip: IpAddr) {
+        let ip = ip.to_canonical();
+        if !self.self_ips.contains(&ip) {
+            self.self_ips.push(ip);
+        }
+    }
+
+    /// Unblocks an IP added by [Self::add_self_ip], eg. a public IP that changed. IPs derived from the bind IP stay blocked
+    pub fn remove_self_ip(&mut self, ip: IpAddr) {
+        let ip = ip.to_canonical();
+        if let Some(index) = self.self_ips[self.num_bind_ips..]
+            .iter()
+            .position(|self_ip| *self_ip == ip)
+        {
+            self.self_ips.remove(self.num_bind_ips + index);
+        }
+    }
+
+    /// Blocks an address the proxy is reachable on with a port of its own, eg. its `advertise-addr` behind a NAT
+    pub fn add_self_addr(&mut self, socketaddr: SocketAddr) {
+        self.add_self_ip(socketaddr.ip());
+        if !self.self_ports.contains(&socketaddr.port()) {
+            self.self_ports.push(socketaddr.port());
+        }
+    }
+
+    fn blocks(&self, socketaddr: &SocketAddr) -> bool {
+        // discovered IPv4 endpoints may be IPv4-mapped
+        let ip = socketaddr.ip().to_canonical();
+        self.blocked_ips.iter().any(|net| net.contains(&ip))
+            || (self.self_ports.contains(&socketaddr.port()) && self.self_ips.contains(&ip))
+    }
+}
+
+/// `max-destinations`, so a misbehaving source can't fan shreds out to thousands of endpoints
+pub struct DestinationCap {
+    max_destinations: usize,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl DestinationCap {
+    pub fn new(max_destinations: usize, metrics: Arc<ShredMetrics>) -> Self {
+        Self {
+            max_destinations,
+            metrics,
+        }
+    }
+
+    /// Removes and returns endpoints beyond `max_destinations`, keeping those with the lowest `rank`, then in union order
+    fn apply(
+        &self,
+        sockets: &mut Vec<SocketAddr>,
+        rank: impl Fn(&SocketAddr) -> u8,
+    ) -> Vec<SocketAddr> {
+        let exceeded = sockets.len() > self.max_destinations;
+        self.metrics
+            .dest_cap_exceeded
+            .store(exceeded, Ordering::Relaxed);
+        if !exceeded {
+            return vec![];
+        }
+        // stable, so discovered endpoints stay in response order
+        let kept = sockets
+            .iter()
+            .copied()
+            .sorted_by_key(|socketaddr| rank(socketaddr))
+            .take(self.max_destinations)
+            .collect::<HashSet<_>>();
+        let (kept, dropped) = mem::take(sockets)
+            .into_iter()
+            .partition(|socketaddr| kept.contains(socketaddr));
+        *sockets = kept;
+        dropped
+    }
+}
+
+/// Destinations from each source, unioned into the set forwarded to.
+/// Shared between the refresh thread and admin API so neither clobbers the other's entries.
+#[derive(Default)]
+pub struct DestinationSources {
+    /// CLI arg defined endpoints, with the original hostname for re-resolving
+    pub static_dest_sockets: Vec<(SocketAddr, String)>,
+    /// Endpoints fetched from the discovery service
+    pub discovered_dest_sockets: Vec<SocketAddr>,
+    /// Endpoints added at runtime via the admin API
+    pub admin_dest_sockets: Vec<SocketAddr>,
+    /// When admin endpoints added with a TTL are removed
+    pub admin_dest_expiries: HashMap<SocketAddr, Instant>,
+    /// Endpoints set by the embedding process via [crate::ShredstreamProxy::update_destinations]
+    pub library_dest_sockets: Vec<SocketAddr>,
+    /// Last known TVU addresses of `dest-validator-identities`
+    pub validator_dest_sockets: Vec<SocketAddr>,
+    /// TVU addresses of the highest staked nodes from `rpc-discovery-url`
+    pub stake_dest_sockets: Vec<SocketAddr>,
+    /// Static endpoints declared with `quic://`, updated with the union. Shared with forwarders
+    pub quic_dest_sockets: Arc<ArcSwap<HashSet<SocketAddr>>>,
+    /// Static endpoints declared with `tcp://` or `tls://`, updated with the union. Shared with forwarders
+    pub tunnel_dests: Arc<ArcSwap<HashMap<SocketAddr, TunnelDest>>>,
+    /// Static endpoints declared with `unix://`, keyed by their stand-in address, updated with the union. Shared with forwarders
+    pub unix_dests: Arc<ArcSwap<HashMap<SocketAddr, UnixDest>>>,
+    /// Static endpoints declared with `enc://`, with the key file they're sealed with, updated with the union. Shared with forwarders
+    pub enc_dests: Arc<ArcSwap<HashMap<SocketAddr, Arc<KeyFile>>>>,
+    /// Key files of `enc://` destinations and `decrypt-key-file`, re-read on `SIGHUP`
+    pub encryption_keys: Arc<EncryptionKeys>,
+    /// Static endpoints declared with a `rate` option, updated with the union. Shared with forwarders
+    pub dest_rate_limits: Arc<ArcSwap<HashMap<SocketAddr, Arc<RateLimiter>>>>,
+    /// Static endpoints declared with an `iface` or `src` option, updated with the union. Shared with forwarders
+    pub dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>,
+    /// Static endpoints declared with a `priority` other than `normal`, updated with the union. Shared with forwarders
+    pub dest_priorities: Arc<ArcSwap<HashMap<SocketAddr, DestPriority>>>,
+    /// Endpoints failing health checks, not forwarded to until they recover
+    pub unhealthy_dest_sockets: HashSet<SocketAddr>,
+    /// Endpoints paused via the admin API, not forwarded to until resumed
+    pub paused_dest_sockets: HashSet<SocketAddr>,
+    /// Static endpoints declared with a `spill` option, true while they're unhealthy or paused and spilled to instead,
+    /// updated with the union. Shared with forwarders and the spill replay thread
+    pub dest_spill: Arc<ArcSwap<HashMap<SocketAddr, bool>>>,
+    /// `dest-shard-group` entries, with static members' original hostnames for re-resolving
+    pub shard_group_specs: Vec<ShardGroupSpec>,
+    /// Members of each shard group, minus unhealthy ones, updated with the union. Shared with forwarders
+    pub shard_groups: Arc<ArcSwap<Vec<ShardGroup>>>,
+    /// Endpoints left out of the union and shard groups whatever their source, None blocks nothing
+    pub dest_blocklist: Option<DestinationBlocklist>,
+    /// Max endpoints in the union, None forwards to any number
+    pub dest_cap: Option<DestinationCap>,
+}
+
+impl DestinationSources {
+    /// Returns dynamically discovered endpoints with CLI arg defined, shard group, admin added, library set, validator, and stake discovered endpoints, including unhealthy ones
+    pub fn union(&self) -> Vec<SocketAddr> {
+        self.discovered_dest_sockets
+            .iter()
+            .copied()
+            .chain(
+                self.static_dest_sockets
+                    .iter()
+                    .map(|(socketaddr, _)| *socketaddr),
+            )
+            .chain(
+                self.shard_group_specs
+                    .iter()
+                    .filter_map(|spec| match &spec.members {
+                        ShardMembers::Static(members) => Some(members),
+                        ShardMembers::Discovered => None,
+                    })
+                    .flatten()
+                    .map(|(socketaddr, _)| *socketaddr),
+            )
+            .chain(self.admin_dest_sockets.iter().copied())
+            .chain(self.library_dest_sockets.iter().copied())
+            .chain(self.validator_dest_sockets.iter().copied())
+            .chain(self.stake_dest_sockets.iter().copied())
+            .unique()
+            .collect()
+    }
+
+    /// Returns the sources an endpoint came from, eg. `static` and `admin`
+    pub fn sources(&self, socketaddr: &SocketAddr) -> Vec<&'static str> {
+        [
+            (
+                "static",
+                self.static_dest_sockets
+                    .iter()
+                    .any(|(x, _)| x == socketaddr),
+            ),
+            (
+                "shard_group",
+                self.shard_group_specs
+                    .iter()
+                    .any(|spec| match &spec.members {
+                        ShardMembers::Static(members) => {
+                            members.iter().any(|(x, _)| x == socketaddr)
+                        }
+                        ShardMembers::Discovered => false,
+                    }),
+            ),
+            (
+                "discovered",
+                self.discovered_dest_sockets.contains(socketaddr),
+            ),
+            ("admin", self.admin_dest_sockets.contains(socketaddr)),
+            ("library", self.library_dest_sockets.contains(socketaddr)),
+            (
+                "validator",
+                self.validator_dest_sockets.contains(socketaddr),
+            ),
+            ("stake", self.stake_dest_sockets.contains(socketaddr)),
+        ]
+        .into_iter()
+        .filter_map(|(source, is_source)| is_source.then_some(source))
+        .collect()
+    }
+
+    /// False while failing health checks or paused
+    fn is_available(&self, socketaddr: &SocketAddr) -> bool {
+        !self.unhealthy_dest_sockets.contains(socketaddr)
+            && !self.paused_dest_sockets.contains(socketaddr)
+    }
+
+    /// Endpoints are kept in this order when the union exceeds `max-destinations`: static, discovered, then other sources
+    fn cap_rank(&self, socketaddr: &SocketAddr) -> u8 {
+        if self
+            .static_dest_sockets
+            .iter()
+            .any(|(x, _)| x == socketaddr)
+        {
+            0
+        } else if self.discovered_dest_sockets.contains(socketaddr) {
+            1
+        } else {
+            2
+        }
+    }
+
+    fn is_blocked(&self, socketaddr: &SocketAddr) -> bool {
+        self.dest_blocklist
+            .as_ref()
+            .is_some_and(|dest_blocklist| dest_blocklist.blocks(socketaddr))
+    }
+
+    /// Swaps the union, minus unhealthy, paused, and blocked endpoints except spill destinations and capped to `max-destinations`,
+    /// into `unioned_dest_sockets` if it changed, along with the QUIC subset.
+    /// Shard group members are swapped into `shard_groups` instead, since they only get their share of shreds
+    pub fn store_union(&self, unioned_dest_sockets: &ArcSwap<Vec<SocketAddr>>) {
+        let new_quic_sockets = self
+            .static_dest_sockets
+            .iter()
+            .filter(|(_, hostname_port)| hostname_port.starts_with(QUIC_SCHEME))
+            .map(|(socketaddr, _)| *socketaddr)
+            .collect::<HashSet<SocketAddr>>();
+        if new_quic_sockets != **self.quic_dest_sockets.load() {
+            info!("Sending shreds over QUIC to {new_quic_sockets:?}");
+            self.quic_dest_sockets.store(Arc::new(new_quic_sockets));
+        }
+        let new_tunnel_dests = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                Some((*socketaddr, parse_tunnel_dest(hostname_port)?))
+            })
+            .collect::<HashMap<SocketAddr, TunnelDest>>();
+        if new_tunnel_dests != **self.tunnel_dests.load() {
+            info!("Sending shreds over TCP/TLS tunnels to {new_tunnel_dests:?}");
+            self.tunnel_dests.store(Arc::new(new_tunnel_dests));
+        }
+        let new_unix_dests = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                Some((*socketaddr, parse_unix_dest(hostname_port)?))
+            })
+            .collect::<HashMap<SocketAddr, UnixDest>>();
+        if new_unix_dests != **self.unix_dests.load() {
+            info!(
+                "Sending shreds over unix sockets to {}",
+                new_unix_dests
+                    .iter()
+                    .map(|(socketaddr, unix_dest)| format!("{unix_dest} (as {socketaddr})"))
+                    .join(", ")
+            );
+            self.unix_dests.store(Arc::new(new_unix_dests));
+        }
+        let new_enc_dests = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                let key_file = encryption::parse_enc_dest(hostname_port)?;
+                Some((*socketaddr, self.encryption_keys.key_file(&key_file)))
+            })
+            .collect::<HashMap<SocketAddr, Arc<KeyFile>>>();
+        if new_enc_dests != **self.enc_dests.load() {
+            info!(
+                "Sealing shreds sent to {}",
+                new_enc_dests
+                    .iter()
+                    .map(|(socketaddr, key_file)| format!(
+                        "{socketaddr} (with {:?})",
+                        key_file.path()
+                    ))
+                    .join(", ")
+            );
+            self.enc_dests.store(Arc::new(new_enc_dests));
+        }
+        // options were validated when parsing
+        let new_rate_limits = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                Some((*socketaddr, parse_dest_rate_limit(hostname_port).ok()??))
+            })
+            .collect::<HashMap<SocketAddr, u64>>();
+        let old_rate_limits = self.dest_rate_limits.load();
+        // keep existing buckets unless a limit changed
+        if new_rate_limits.len() != old_rate_limits.len()
+            || new_rate_limits.iter().any(|(socketaddr, rate_pps)| {
+                old_rate_limits
+                    .get(socketaddr)
+                    .map(|rate_limiter| rate_limiter.rate_pps())
+                    != Some(*rate_pps)
+            })
+        {
+            info!("Rate limiting destinations to packets per second: {new_rate_limits:?}");
+            let now = Instant::now();
+            self.dest_rate_limits.store(Arc::new(
+                new_rate_limits
+                    .into_iter()
+                    .map(|(socketaddr, rate_pps)| {
+                        (socketaddr, Arc::new(RateLimiter::new(rate_pps, now)))
+                    })
+                    .collect(),
+            ));
+        }
+        let new_dest_egress = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                let egress = parse_dest_options(hostname_port).ok()?.egress;
+                (!egress.is_default()).then_some((*socketaddr, egress))
+            })
+            .collect::<HashMap<SocketAddr, Egress>>();
+        if new_dest_egress != **self.dest_egress.load() {
+            info!(
+                "Sending to destinations from their own egress: {}",
+                new_dest_egress
+                    .iter()
+                    .map(|(socketaddr, egress)| format!("{socketaddr} via {egress}"))
+                    .join(", ")
+            );
+            self.dest_egress.store(Arc::new(new_dest_egress));
+        }
+        let new_dest_priorities = self
+            .static_dest_sockets
+            .iter()
+            .filter_map(|(socketaddr, hostname_port)| {
+                let priority = parse_dest_options(hostname_port).ok()?.priority;
+                (priority != DestPriority::Normal).then_some((*socketaddr, priority))
+            })
+            .collect::<HashMap<SocketAddr, DestPriority>>();
+        if new_dest_priorities != **self.dest_priorities.load() {
+            info!("Prioritizing destinations: {new_dest_priorities:?}");
+            self.dest_priorities.store(Arc::new(new_dest_priorities));
+        }
+        let new_dest_spill = self
+            .static_dest_sockets
+            .iter()
+            .filter(|(_, hostname_port)| {
+                parse_dest_options(hostname_port).is_ok_and(|dest_options| dest_options.spill)
+            })
+            .map(|(socketaddr, _)| (*socketaddr, !self.is_available(socketaddr)))
+            .collect::<HashMap<SocketAddr, bool>>();
+        if new_dest_spill != **self.dest_spill.load() {
+            info!(
+                "Spilling shreds to disk for {:?} while they're unavailable, currently spilling {:?}",
+                new_dest_spill.keys().collect::<Vec<_>>(),
+                new_dest_spill
+                    .iter()
+                    .filter_map(|(socketaddr, spilling)| spilling.then_some(socketaddr))
+                    .collect::<Vec<_>>()
+            );
+            self.dest_spill.store(Arc::new(new_dest_spill));
+        }
+
+        let new_shard_groups = self
+            .shard_group_specs
+            .iter()
+            .map(|spec| {
+                let members: Vec<SocketAddr> = match &spec.members {
+                    ShardMembers::Static(members) => {
+                        members.iter().map(|(socketaddr, _)| *socketaddr).collect()
+                    }
+                    // only one of the discovery modes can be configured
+                    ShardMembers::Discovered => self
+                        .discovered_dest_sockets
+                        .iter()
+                        .chain(&self.stake_dest_sockets)
+                        .copied()
+                        .collect(),
+                };
+                ShardGroup {
+                    members: members
+                        .into_iter()
+                        .filter(|socketaddr| {
+                            self.is_available(socketaddr) && !self.is_blocked(socketaddr)
+                        })
+                        .collect(),
+                    shard_by: spec.shard_by,
+                }
+            })
+            .collect::<Vec<_>>();
+        if new_shard_groups != **self.shard_groups.load() {
+            info!(
+                "Sharding shreds across groups: {:?}",
+                new_shard_groups
+                    .iter()
+                    .map(|group| &group.members)
+                    .collect::<Vec<_>>()
+            );
+            self.shard_groups.store(Arc::new(new_shard_groups));
+        }
+        let shard_group_members = self
+            .shard_groups
+            .load()
+            .iter()
+            .flat_map(|group| group.members.iter().copied())
+            .collect::<HashSet<_>>();
+
+        let mut new_sockets = self.union();
+        if let Some(dest_blocklist) = &self.dest_blocklist {
+            let blocked = new_sockets
+                .iter()
+                .filter(|socketaddr| dest_blocklist.blocks(socketaddr))
+                .collect::<Vec<_>>();
+            for addr in &blocked {
+                warn!(event = "destination_blocked", addr:% = addr; "Destination {addr} from {} blocked, not forwarding to it.", self.sources(addr).join(", "));
+            }
+            dest_blocklist
+                .metrics
+                .blocked_destinations
+                .store(blocked.len() as u64, Ordering::Relaxed);
+        }
+        // spill destinations stay in the union while unavailable, forwarders spill their shreds instead of sending
+        let dest_spill = self.dest_spill.load();
+        new_sockets.retain(|socketaddr| {
+            (self.is_available(socketaddr) || dest_spill.contains_key(socketaddr))
+                && !shard_group_members.contains(socketaddr)
+                && !self.is_blocked(socketaddr)
+        });
+        let capped = match &self.dest_cap {
+            Some(dest_cap) => {
+                dest_cap.apply(&mut new_sockets, |socketaddr| self.cap_rank(socketaddr))
+            }
+            None => vec![],
+        };
+        // forwarders send in union order, so higher priority destinations are sent to first
+        let dest_priorities = self.dest_priorities.load();
+        new_sockets
+            .sort_by_key(|socketaddr| dest_priorities.get(socketaddr).copied().unwrap_or_default());
+        let old_sockets = unioned_dest_sockets.load();
+        if new_sockets != **old_sockets {
+            for addr in new_sockets
+                .iter()
+                .filter(|addr| !old_sockets.contains(addr))
+            {
+                info!(event = "destination_added", addr:% = addr; "Destination {addr} added.");
+            }
+            for addr in old_sockets
+                .iter()
+                .filter(|addr| !new_sockets.contains(addr))
+            {
+                info!(event = "destination_removed", addr:% = addr; "Destination {addr} removed.");
+            }
+            if !capped.is_empty() {
+                warn!(
+                    "More than --max-destinations {} destinations, not forwarding to {}: {capped:?}",
+                    new_sockets.len(),
+                    capped.len()
+                );
+            }
+            info!(
+                "Sending shreds to {} destinations: {new_sockets:?}",
+                new_sockets.len()
+            );
+            unioned_dest_sockets.store(Arc::new(new_sockets));
+        }
+    }
+}
+
+/// Discovery service to fetch destinations from
+#[derive(Clone, PartialEq, Eq)]
+pub struct EndpointDiscovery {
+    pub url: String,
+    /// Port to send shreds to on discovered hosts
+    pub port: u16,
+    /// (name, value) headers sent with every request
+    pub headers: Vec<(String, String)>,
+    /// Sent as `Authorization: Bearer`. Read on every request so rotated tokens are picked up
+    pub bearer_token_file: Option<PathBuf>,
+    /// Time between requests
+    pub interval: Duration,
+    /// Where the last successful response is saved, to fall back to if the first request after startup fails
+    pub state_file: Option<PathBuf>,
+    /// Saved responses older than this are ignored
+    pub state_max_age: Duration,
+    /// Larger responses are rejected
+    pub max_response_bytes: u64,
+    /// Removals of more than this percentage of discovered endpoints wait for [MASS_REMOVAL_CONFIRMATIONS]
+    pub max_removal_percent: u8,
+}
+
+/// Validators from the last successful discovery response, sent back so an unchanged response isn't downloaded again
+#[derive(Default)]
+struct DiscoveryCache {
+    /// Validators only apply to the URL they came from
+    url: String,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+}
+
+/// Starts a thread that updates our destinations used by the forwarder threads.
+/// Periodically fetches from the discovery service (if configured) and re-resolves static destination hostnames.
+/// A message on `discovery_refresh_receiver` fetches from the discovery service immediately
+#[allow(clippy::too_many_arguments)]
+pub fn start_destination_refresh_thread(
+    endpoint_discovery: Arc<ArcSwapOption<EndpointDiscovery>>, /* can be changed by config reload */
+    discovery_refresh_receiver: Receiver<()>,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    dest_resolve_interval: Option<Duration>,
+    dest_address_family: AddressFamily,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    source_allowlist: Arc<SourceAllowlist>, /* re-reads `allowed-source-ips-file` */
+    metrics: Arc<ShredMetrics>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    supervisor.spawn_restartable("ssPxyDstRefresh", move || {
+        let http_client = reqwest::blocking::Client::new();
+        let mut discovery_cache = DiscoveryCache::default();
+        let mut discovery_refresh_receiver = discovery_refresh_receiver.clone();
+        // only fall back to the saved response before discovery first succeeds
+        let mut saved_state_checked = false;
+        // responses in a row held back for removing too many endpoints
+        let mut mass_removals_held = 0;
+        let discovery_interval = |endpoint_discovery: &ArcSwapOption<EndpointDiscovery>| {
+            endpoint_discovery
+                .load()
+                .as_ref()
+                .map_or(DISCOVERY_REFRESH_INTERVAL, |discovery| discovery.interval)
+        };
+        // interval can be changed by config reload
+        let mut fetch_interval = discovery_interval(&endpoint_discovery);
+        let mut fetch_socket_tick = crossbeam_channel::tick(fetch_interval);
+        // set when the next fetch was requested early, so the interval restarts after it
+        let mut fetch_requested = false;
+        let allowlist_tick = crossbeam_channel::tick(SOURCE_ALLOWLIST_RELOAD_INTERVAL);
+        let resolve_tick = match dest_resolve_interval {
+            Some(interval) => crossbeam_channel::tick(interval),
+            None => crossbeam_channel::never(),
+        };
+        let metrics_tick = crossbeam_channel::tick(Duration::from_secs(30));
+        let admin_dest_expiry_tick = crossbeam_channel::tick(ADMIN_DEST_EXPIRY_CHECK_INTERVAL);
+        while !exit.load(Ordering::Relaxed) {
+            crossbeam_channel::select! {
+                    recv(fetch_socket_tick) -> _ => {
+                        let interval_changed = discovery_interval(&endpoint_discovery) != fetch_interval;
+                        if std::mem::take(&mut fetch_requested) || interval_changed {
+                            if interval_changed {
+                                fetch_interval = discovery_interval(&endpoint_discovery);
+                                info!("Fetching from discovery service every {fetch_interval:?}.");
+                            }
+                            fetch_socket_tick = crossbeam_channel::tick(fetch_interval);
+                        }
+                        let Some(endpoint_discovery) = endpoint_discovery.load_full() else {
+                            // discovery may have been removed by config reload
+                            let mut dest_sources = dest_sources.lock().unwrap();
+                            dest_sources.discovered_dest_sockets.clear();
+                            dest_sources.store_union(&unioned_dest_sockets);
+                            continue;
+                        };
+                        let fetched = traced(control_span!("discovery_refresh", url = endpoint_discovery.url.as_str()), || {
+                            fetch_discovered_destinations(&http_client, &endpoint_discovery, &mut discovery_cache)
+                        });
+                        match fetched {
+                            Ok(discovered) => {
+                                saved_state_checked = true;
+                                metrics.discovery_last_success_unix_secs.store(unix_secs(SystemTime::now()), Ordering::Relaxed);
+                                metrics.discovery_using_saved_state.store(false, Ordering::Relaxed);
+                                let Some(discovered) = discovered else {
+                                    // unchanged, so leave destinations as they are
+                                    metrics.discovery_not_modified_cumulative.fetch_add(1, Ordering::Relaxed);
+                                    // still save so the file doesn't age out while responses are unchanged
+                                    let discovered = dest_sources.lock().unwrap().discovered_dest_sockets.clone();
+                                    save_discovery_state(&endpoint_discovery, discovered);
+                                    continue;
+                                };
+                                let previous = dest_sources.lock().unwrap().discovered_dest_sockets.clone();
+                                match mass_removal(&previous, &discovered, endpoint_discovery.max_removal_percent) {
+                                    Some(removed) if mass_removals_held < MASS_REMOVAL_CONFIRMATIONS => {
+                                        mass_removals_held += 1;
+                                        warn!("Discovery response removes {removed} of {} discovered destinations, more than --endpoint-discovery-max-removal-percent. Keeping them until {} more consecutive responses confirm the removal.",
+                                              previous.len(), MASS_REMOVAL_CONFIRMATIONS + 1 - mass_removals_held);
+                                        metrics.discovery_mass_removals_held_cumulative.fetch_add(1, Ordering::Relaxed);
+                                        // so the next response is downloaded in full rather than answered as unchanged
+                                        discovery_cache = DiscoveryCache::default();
+                                        continue;
+                                    }
+                                    Some(removed) => {
+                                        warn!("Removing {removed} of {} discovered destinations, confirmed by {MASS_REMOVAL_CONFIRMATIONS} consecutive responses.", previous.len());
+                                        mass_removals_held = 0;
+                                    }
+                                    None => mass_removals_held = 0,
+                                }
+                                save_discovery_state(&endpoint_discovery, discovered.clone());
+                                dest_sources.lock().unwrap().discovered_dest_sockets = discovered;
+                            }
+                            Err(e) => {
+                                let auth_error = is_auth_error(&e);
+                                if auth_error {
+                                    warn!("Discovery service rejected credentials, check --endpoint-discovery-header and --endpoint-discovery-bearer-token-file. Retrying. Error: {e}");
+                                    metrics.discovery_auth_failures_cumulative.fetch_add(1, Ordering::Relaxed);
+                                } else {
+                                    warn!("Failed to fetch from discovery service, retrying. Error: {e}");
+                                    metrics.discovery_failures_cumulative.fetch_add(1, Ordering::Relaxed);
+                                }
+                                datapoint_warn!("shredstream_proxy-destination_refresh_error",
+                                                ("prev_unioned_dest_count", unioned_dest_sockets.load().len(), i64),
+                                                ("errors", 1, i64),
+                                                ("auth_error", auth_error, bool),
+                                                ("error_str", e.to_string(), String),
+                                );
+                                if saved_state_checked {
+                                    continue;
+                                }
+                                saved_state_checked = true;
+                                let Some(saved) = load_discovery_state(&endpoint_discovery) else {
+                                    continue;
+                                };
+                                metrics.discovery_last_success_unix_secs.store(saved.saved_at_unix_secs, Ordering::Relaxed);
+                                metrics.discovery_using_saved_state.store(true, Ordering::Relaxed);
+                                dest_sources.lock().unwrap().discovered_dest_sockets = saved.destinations;
+                            }
+                        };
+                        // resolve again since ip address could change
+                        refresh_static_destinations(&dest_sources, dest_address_family);
+                    }
+                    recv(discovery_refresh_receiver) -> maybe_refresh => {
+                        if maybe_refresh.is_err() {
+                            // requester exited, stop selecting on the closed channel
+                            discovery_refresh_receiver = crossbeam_channel::never();
+                            continue;
+                        }
+                        // handled as a tick that fires now
+                        fetch_requested = true;
+                        fetch_socket_tick = crossbeam_channel::at(Instant::now());
+                        continue;
+                    }
+                    recv(resolve_tick) -> _ => {
+                        refresh_static_destinations(&dest_sources, dest_address_family);
+                    }
+                    recv(admin_dest_expiry_tick) -> _ => {
+                        let mut dest_sources = dest_sources.lock().unwrap();
+                        admin::expire_destinations(&mut dest_sources, &unioned_dest_sockets, Instant::now(), &metrics);
+                        continue;
+                    }
+                    recv(allowlist_tick) -> _ => {
+                        if let Err(e) = source_allowlist.reload_file() {
+                            warn!("Failed to reload allowed source IPs file, keeping current allowlist. Error: {e}");
+                        }
+                        continue;
+                    }
+                    recv(metrics_tick) -> _ => {
+                        datapoint_info!("shredstream_proxy-destination_refresh_stats",
+                                        ("destination_count", unioned_dest_sockets.load().len(), i64),
+                                        ("discovery_age_secs", metrics.discovery_age(SystemTime::now()).map(|age| age.as_secs() as i64), Option<i64>),
+                                        ("discovery_using_saved_state", metrics.discovery_using_saved_state.load(Ordering::Relaxed), bool),
+                                        ("dest_cap_exceeded", metrics.dest_cap_exceeded.load(Ordering::Relaxed), bool),
+                        );
+                        continue;
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+
+            dest_sources.lock().unwrap().store_union(&unioned_dest_sockets);
+        }
+    })
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Saves discovered destinations to the state file, if configured
+fn save_discovery_state(endpoint_discovery: &EndpointDiscovery, discovered: Vec<SocketAddr>) {
+    let Some(state_file) = &endpoint_discovery.state_file else {
+        return;
+    };
+    let saved = SavedDiscovery::new(&endpoint_discovery.url, SystemTime::now(), discovered);
+    if let Err(e) = discovery_state::save(state_file, &saved) {
+        warn!("Failed to save discovered destinations to {state_file:?}. Error: {e}");
+    }
+}
+
+/// Loads destinations saved to the state file, if configured and still usable
+fn load_discovery_state(endpoint_discovery: &EndpointDiscovery) -> Option<SavedDiscovery> {
+    let state_file = endpoint_discovery.state_file.as_ref()?;
+    let now = SystemTime::now();
+    match discovery_state::load(
+        state_file,
+        &endpoint_discovery.url,
+        endpoint_discovery.state_max_age,
+        now,
+    ) {
+        Ok(Some(saved)) => {
+            warn!(
+                "Using {} destinations saved to {state_file:?} {}s ago until discovery succeeds.",
+                saved.destinations.len(),
+                unix_secs(now).saturating_sub(saved.saved_at_unix_secs)
+            );
+            Some(saved)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Ignoring saved discovered destinations. Error: {e}");
+            None
+        }
+    }
+}
+
+/// Re-resolves static destinations without holding the lock during DNS lookups
+fn refresh_static_destinations(
+    dest_sources: &Mutex<DestinationSources>,
+    dest_address_family: AddressFamily,
+) {
+    let (mut static_dest_sockets, mut shard_group_specs) = {
+        let dest_sources = dest_sources.lock().unwrap();
+        (
+            dest_sources.static_dest_sockets.clone(),
+            dest_sources.shard_group_specs.clone(),
+        )
+    };
+    resolve_static_destinations(&mut static_dest_sockets, dest_address_family);
+    for spec in shard_group_specs.iter_mut() {
+        if let ShardMembers::Static(members) = &mut spec.members {
+            resolve_static_destinations(members, dest_address_family);
+        }
+    }
+    // only this thread modifies static destinations, so nothing was overwritten in the meantime
+    let mut dest_sources = dest_sources.lock().unwrap();
+    dest_sources.static_dest_sockets = static_dest_sockets;
+    dest_sources.shard_group_specs = shard_group_specs;
+}
+
+/// Returns endpoints fetched from the discovery service, or `None` if unchanged since the response cached in `discovery_cache`
+fn fetch_discovered_destinations(
+    http_client: &reqwest::blocking::Client,
+    endpoint_discovery: &EndpointDiscovery,
+    discovery_cache: &mut DiscoveryCache,
+) -> Result<Option<Vec<SocketAddr>>, ShredstreamProxyError> {
+    let mut request = endpoint_discovery.headers.iter().fold(
+        http_client.get(&endpoint_discovery.url),
+        |request, (name, value)| request.header(name, value),
+    );
+    if discovery_cache.url == endpoint_discovery.url {
+        if let Some(etag) = &discovery_cache.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &discovery_cache.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    if let Some(bearer_token_file) = &endpoint_discovery.bearer_token_file {
+        let token = fs::read_to_string(bearer_token_file).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to read bearer token file {bearer_token_file:?}: {e}"),
+            )
+        })?;
+        request = request.bearer_auth(token.trim());
+    }
+    let response = request.send()?.error_for_status()?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(None);
+    }
+    let (etag, last_modified) = (
+        response.headers().get(ETAG).cloned(),
+        response.headers().get(LAST_MODIFIED).cloned(),
+    );
+    let max_response_bytes = endpoint_discovery.max_response_bytes;
+    let too_large = || {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Discovery response larger than --endpoint-discovery-max-response-bytes {max_response_bytes}"),
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|content_length| content_length > max_response_bytes)
+    {
+        return Err(too_large().into());
+    }
+    // content length may be missing or wrong, so stop reading past the limit too
+    let mut bytes = vec![];
+    response
+        .take(max_response_bytes.saturating_add(1))
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > max_response_bytes {
+        return Err(too_large().into());
+    }
+
+    let discovered =
+        parse_discovered_destinations(&bytes, endpoint_discovery.port).map_err(|e| {
+            warn!(
+                "Failed to parse json from: {:?}",
+                std::str::from_utf8(&bytes)
+            );
+            ShredstreamProxyError::from(e)
+        })?;
+    // only cache validators for a response we could use
+    *discovery_cache = DiscoveryCache {
+        url: endpoint_discovery.url.clone(),
+        etag,
+        last_modified,
+    };
+    Ok(Some(discovered))
+}
+
+/// Returns how many `previous` endpoints are missing from `discovered`, if more than `max_removal_percent` of them
+fn mass_removal(
+    previous: &[SocketAddr],
+    discovered: &[SocketAddr],
+    max_removal_percent: u8,
+) -> Option<usize> {
+    let discovered = discovered.iter().collect::<HashSet<_>>();
+    let removed = previous
+        .iter()
+        .filter(|socketaddr| !discovered.contains(socketaddr))
+        .count();
+    (removed * 100 > previous.len() * max_removal_percent as usize).then_some(removed)
+}
+
+/// Entry in the discovery service's response
+#[derive(serde::Deserialize)]
+#[serde(untagged)]
+enum DiscoveredEndpoint {
+    Ip(IpAddr),
+    IpPort { ip: IpAddr, port: Option<u16> },
+}
+
+/// Parses a JSON array of IPs and/or `{"ip": "1.2.3.4", "port": 8001}` objects.
+/// Entries without a port use `default_port`
+fn parse_discovered_destinations(
+    bytes: &[u8],
+    default_port: u16,
+) -> serde_json::Result<Vec<SocketAddr>> {
+    Ok(serde_json::from_slice::<Vec<DiscoveredEndpoint>>(bytes)?
+        .into_iter()
+        .map(|endpoint| match endpoint {
+            DiscoveredEndpoint::Ip(ip) => SocketAddr::new(ip, default_port),
+            DiscoveredEndpoint::IpPort { ip, port } => {
+                SocketAddr::new(ip, port.unwrap_or(default_port))
+            }
+        })
+        .collect())
+}
+
+/// Returns true if the discovery service rejected our credentials
+fn is_auth_error(e: &ShredstreamProxyError) -> bool {
+    matches!(
+        e,
+        ShredstreamProxyError::ReqwestError(e)
+            if matches!(e.status(), Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN))
+    )
+}
+
+/// Re-resolves hostnames of CLI arg defined endpoints in place.
+/// Keeps the last known address when resolution fails.
+pub fn resolve_static_destinations(
+    static_dest_sockets: &mut [(SocketAddr, String)],
+    dest_address_family: AddressFamily,
+) {
+    static_dest_sockets
+        .iter_mut()
+        .for_each(|(socketaddr, hostname_port)| {
+            let resolved = traced(
+                control_span!("resolve_destination", hostname_port = hostname_port.as_str()),
+                || resolve_hostname_port_with_family(hostname_port, dest_address_family),
+            );
+            match resolved {
+                Ok((new_socketaddr, _)) if new_socketaddr != *socketaddr => {
+                    info!("Destination {hostname_port} changed address from {socketaddr} to {new_socketaddr}.");
+                    *socketaddr = new_socketaddr;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Failed to resolve destination {hostname_port}, keeping last known address {socketaddr}. Error: {e}");
+                    datapoint_warn!("shredstream_proxy-destination_resolve_error",
+                        "hostname_port" => hostname_port,
+                        ("errors", 1, i64),
+                        ("error_str", e.to_string(), String),
+                    );
+                }
+            }
+        });
+}
+
+/// Reset dedup + send metrics to influx
+#[allow(clippy::too_many_arguments)]
+pub fn start_forwarder_accessory_thread(
+    dedupers: Vec<Arc<ArcSwap<ShredDeduper>>>, /* the main pipeline's, then one per `--listener` */
+    deduper_false_positive_rate: f64,
+    deduper_reset_interval: Duration,
+    mut socket_drop_counter: Option<SocketDropCounter>,
+    metrics: Arc<ShredMetrics>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    shard_groups: Arc<ArcSwap<Vec<ShardGroup>>>,
+    listener_dest_sockets: Vec<SocketAddr>, /* forwarded to by `--listener` pipelines, so their metrics are kept */
+    metrics_update_interval_ms: Arc<AtomicU64>, /* can be changed by config reload */
+    mut alert_monitor: Option<AlertMonitor>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    supervisor.spawn_restartable("ssPxyAccessory", move || {
+            let mut current_metrics_update_interval_ms =
+                metrics_update_interval_ms.load(Ordering::Relaxed);
+            let mut metrics_tick =
+                crossbeam_channel::tick(Duration::from_millis(current_metrics_update_interval_ms));
+            let deduper_reset_tick =
+                crossbeam_channel::tick(DEDUPER_RESET_CHECK_INTERVAL.min(deduper_reset_interval));
+            let mut rng = rand::thread_rng();
+            while !exit.load(Ordering::Relaxed) {
+                // checked at least every deduper reset tick
+                let new_metrics_update_interval_ms = metrics_update_interval_ms.load(Ordering::Relaxed);
+                if new_metrics_update_interval_ms != current_metrics_update_interval_ms {
+                    current_metrics_update_interval_ms = new_metrics_update_interval_ms;
+                    metrics_tick = crossbeam_channel::tick(Duration::from_millis(current_metrics_update_interval_ms));
+                }
+                crossbeam_channel::select! {
+                    // reset deduper to avoid false positives
+                    recv(deduper_reset_tick) -> _ => {
+                        for deduper in &dedupers {
+                            let current = deduper.load();
+                            let early = current.needs_early_reset(deduper_false_positive_rate, deduper_reset_interval);
+                            let (saturation, age) = (current.estimated_saturation(), current.age());
+                            drop(current);
+                            if maybe_reset_deduper(deduper, &mut rng, deduper_false_positive_rate, deduper_reset_interval) && early {
+                                // a saturated deduper lets duplicates through, so resetting sooner beats waiting for the schedule
+                                info!("Reset deduper early at {:.1}% saturation, {}s after its last reset. Raise --deduper-num-bits if this is frequent.", saturation * 100.0, age.as_secs());
+                                metrics.deduper_early_resets.fetch_add(1, Ordering::Relaxed);
+                            }
+                        }
+                    }
+
+                    // report metrics to the metrics backend
+                    recv(metrics_tick) -> _ => {
+                        if let Some(counter) = socket_drop_counter.as_mut() {
+                            match counter.drops_since_last() {
+                                Ok(drops) => {
+                                    metrics.recv_socket_dropped.fetch_add(drops, Ordering::Relaxed);
+                                }
+                                Err(e) => warn!("Failed to read listen socket drops. Error: {e}"),
+                            }
+                        }
+                        let channel_dropped_packets = metrics.channel_dropped_packets.load(Ordering::Relaxed);
+                        if channel_dropped_packets > 0 {
+                            warn!("Forwarders fell behind, dropped {channel_dropped_packets} packets from full channels since the last report.");
+                        }
+                        let memory_guard_dropped = metrics.memory_guard_dropped.load(Ordering::Relaxed);
+                        if memory_guard_dropped > 0 {
+                            warn!("Dropped {memory_guard_dropped} received packets over --max-buffered-bytes since the last report, {} bytes are buffered.", metrics.memory_guard.total());
+                        }
+                        metrics.record_deduper(&dedupers[0].load());
+                        metrics.report();
+                        metrics.reset();
+                        if let Some(alert_monitor) = alert_monitor.as_mut() {
+                            alert_monitor.check_metrics(&metrics, Instant::now());
+                        }
+                        // drop destinations removed by the refresh thread so the maps don't grow forever
+                        let shard_groups = shard_groups.load();
+                        metrics.retain_destinations(
+                            &unioned_dest_sockets
+                                .load()
+                                .iter()
+                                .copied()
+                                .chain(shard_groups.iter().flat_map(|group| group.members.iter().copied()))
+                                .chain(listener_dest_sockets.iter().copied())
+                                .collect::<Vec<_>>(),
+                        );
+                    }
+
+                    // count forwarded shreds queued by forwarder threads
+                    recv(metrics.slot_coverage.receiver()) -> shreds => {
+                        if let Ok(shreds) = shreds {
+                            metrics.slot_coverage.record(&shreds, Instant::now());
+                        }
+                    }
+
+                    // handle SIGINT shutdown
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+    })
+}
+
+/// Histogram exact to the byte for any UDP packet size, where the default precision rounds sizes
+fn packet_bytes_histogram() -> Histogram {
+    Histogram::configure()
+        .max_value(u16::MAX as u64)
+        .precision(5)
+        .build()
+        .expect("valid histogram config")
+}
+
+pub struct ShredMetrics {
+    /// Total number of shreds received. Includes duplicates when receiving shreds from multiple regions
+    pub agg_received: AtomicU64,
+    /// Total number of shreds successfully forwarded, accounting for all destinations
+    pub agg_success_forward: AtomicU64,
+    /// Total number of shreds failed to forward, accounting for all destinations
+    pub agg_fail_forward: AtomicU64,
+    /// Shreds failed to forward to UDP destinations, included in `agg_fail_forward`
+    pub udp_fail_forward: AtomicU64,
+    /// Shreds failed to forward to QUIC destinations, included in `agg_fail_forward`
+    pub quic_fail_forward: AtomicU64,
+    /// Shreds failed to forward to `tcp://` and `tls://` destinations, included in `agg_fail_forward`
+    pub tunnel_fail_forward: AtomicU64,
+    /// Shreds failed to forward to `unix://` destinations, included in `agg_fail_forward`
+    pub unix_fail_forward: AtomicU64,
+    /// Number of duplicate shreds received
+    pub duplicate: AtomicU64,
+    /// Number of shreds dropped for being more than `max_slot_age` behind the highest slot seen
+    pub stale_slot_dropped: AtomicU64,
+    /// Number of packets failing the shred sanity check on length and variant byte, whether dropped or forwarded
+    pub received_invalid: AtomicU64,
+    /// Number of packets dropped for not parsing as a shred
+    pub non_shred_dropped: AtomicU64,
+    /// Shreds with the same (slot, index, type) as an earlier shred but a different payload, with `detect-conflicting-shreds`
+    pub conflicting_shreds: AtomicU64,
+    /// Number of packets dropped for coming from a source not in `allowed-source-ips`
+    pub source_not_allowed: AtomicU64,
+    /// Number of data shreds dropped by `forward_shred_types`
+    pub data_shred_filtered: AtomicU64,
+    /// Number of code shreds dropped by `forward_shred_types`
+    pub code_shred_filtered: AtomicU64,
+    /// Packets dropped by the kernel before reaching the listen sockets, usually from a full receive buffer
+    pub recv_socket_dropped: AtomicU64,
+    /// Packets queued for recording to pcap
+    pub pcap_recorded: AtomicU64,
+    /// Packets not recorded to pcap because the writer fell behind
+    pub pcap_dropped: AtomicU64,
+    /// Shreds acknowledged by the Kafka brokers
+    pub kafka_published: AtomicU64,
+    /// Shreds not published to Kafka because the publisher or producer queue was full
+    pub kafka_dropped: AtomicU64,
+    /// Shreds the Kafka producer failed to deliver
+    pub kafka_delivery_failed: AtomicU64,
+    /// Shreds written to the archive, including ones the blockstore already had
+    pub archive_inserted: AtomicU64,
+    /// Shreds not archived because the archiver fell behind
+    pub archive_dropped: AtomicU64,
+    /// Shreds that failed to parse or to write to the archive
+    pub archive_insert_failed: AtomicU64,
+    /// Shreds handed to in-process subscribers
+    pub subscriber_delivered: AtomicU64,
+    /// Shreds not handed to a subscriber because it or the dispatch thread fell behind
+    pub subscriber_dropped: AtomicU64,
+    /// Batches dropped because a forwarder's channel was full
+    pub channel_dropped_batches: AtomicU64,
+    /// Packets in `channel_dropped_batches`
+    pub channel_dropped_packets: AtomicU64,
+    /// Packets dropped at ingress while buffered bytes were over `--max-buffered-bytes`
+    pub memory_guard_dropped: AtomicU64,
+    /// Keepalives sent to `--upstream-keepalive` upstreams
+    pub keepalive_sent: AtomicU64,
+    /// Keepalives that failed to send
+    pub keepalive_send_failed: AtomicU64,
+    /// Keepalives received from downstream proxies, dropped before dedup
+    pub keepalive_received: AtomicU64,
+    /// Packets for `enc://` destinations dropped because their key file couldn't be read
+    pub encrypt_dropped: AtomicU64,
+    /// Packets received with `--decrypt-key-file` that failed authentication, dropped before dedup
+    pub decrypt_failed: AtomicU64,
+    /// Slot events sent to `--events-dest`
+    pub events_sent: AtomicU64,
+    /// Slot events dropped because the sender fell behind, `--events-max-per-sec` was exceeded, or sending failed
+    pub events_dropped: AtomicU64,
+    /// Packets not sent to destinations of each priority because the forwarder fell behind, or their isolated send queue was full
+    pub priority_dropped_high: AtomicU64,
+    pub priority_dropped_normal: AtomicU64,
+    pub priority_dropped_low: AtomicU64,
+    /// Packets written to disk for destinations with a `spill` option while they're unhealthy or paused
+    pub spill_spilled: AtomicU64,
+    /// Spilled packets sent once their destination was back
+    pub spill_replayed: AtomicU64,
+    /// Spilled or queued packets skipped when replaying because they were older than `spill-max-age-ms`
+    pub spill_expired: AtomicU64,
+    /// Spilled packets overwritten or discarded, and live packets dropped because the queue behind a replay was full
+    pub spill_dropped: AtomicU64,
+    /// Sampled packets sent to and failed to send to `--mirror-dest` mirrors
+    pub mirror_sent: AtomicU64,
+    pub mirror_failed: AtomicU64,
+    /// Sampled packets not mirrored because the forwarder fell behind
+    pub mirror_shed: AtomicU64,
+    /// Packets dropped, duplicated, sent out of order, and delayed by `--chaos-*` fault injection
+    pub chaos_dropped: AtomicU64,
+    pub chaos_duplicated: AtomicU64,
+    pub chaos_reordered: AtomicU64,
+    pub chaos_delayed: AtomicU64,
+    /// Number of times a destination was marked unhealthy by health checks
+    pub dest_became_unhealthy: AtomicU64,
+    /// Number of times an unhealthy destination recovered
+    pub dest_became_healthy: AtomicU64,
+    /// Number of times a restartable thread was restarted after panicking
+    pub thread_restarts: AtomicU64,
+    /// Number of forwarding or listen sockets rebound after persistent errors
+    pub socket_rebinds: AtomicU64,
+    /// Deduper resets because its estimated false positive rate reached `deduper-false-positive-rate`
+    /// before `deduper-reset-interval-ms` elapsed
+    pub deduper_early_resets: AtomicU64,
+    /// (discarded, not discarded, from other shredstream instances)
+    pub packets_received: DashMap<IpAddr, (u64, u64)>,
+    /// (successfully forwarded, failed to forward) per destination
+    pub dest_forwarded: DashMap<SocketAddr, (u64, u64)>,
+    /// Packets dropped by a destination's `rate` option
+    pub dest_throttled: DashMap<SocketAddr, u64>,
+    /// Packets dropped because a destination's send queue was full, with `--isolated-send-threads`
+    pub dest_send_queue_dropped: DashMap<SocketAddr, u64>,
+    /// Batches queued for each destination's send thread when it last sent, with `--isolated-send-threads`.
+    /// A gauge, removed once the destination's thread exits
+    pub dest_send_queue_depth: DashMap<SocketAddr, usize>,
+    /// Packets sent to each member of a `dest-shard-group`, to check the group is balanced
+    pub shard_assigned: DashMap<SocketAddr, u64>,
+    /// (received, duplicate) per region, when listening on a port per region.
+    /// Duplicates were first received from another region, or retransmitted
+    pub region_received: DashMap<String, (u64, u64)>,
+    /// (received, duplicate) per `--listener`, each deduped on its own
+    pub listener_received: DashMap<String, (u64, u64)>,
+    /// Heartbeat health per desired region. RTTs are reset each interval, the rest is kept
+    pub heartbeat_regions: DashMap<String, RegionHeartbeatStats>,
+    /// Microseconds from kernel receive to forwarding, with `--measure-internal-latency`
+    pub internal_latency_us: Mutex<Histogram>,
+    /// Bytes per received packet, so truncated shreds or garbage traffic stand out
+    pub received_packet_bytes: Mutex<Histogram>,
+    /// Bytes of each packet whose send failed with `EMSGSIZE`, too large for the path MTU, per destination
+    pub dest_emsgsize_bytes: DashMap<SocketAddr, Histogram>,
+    /// (microseconds per `sendmmsg` call, calls slower than `slow-send-threshold-us`) per destination,
+    /// with `--measure-send-latency`
+    pub dest_send_latency_us: DashMap<SocketAddr, (Histogram, u64)>,
+    /// Consecutive intervals each destination's p99 send latency exceeded the threshold, kept across resets
+    pub dest_slow_send_intervals: DashMap<SocketAddr, u64>,
+    /// Data shreds per recent slot, to tell whether whole slots are received
+    pub slot_coverage: SlotCoverageTap,
+
+    // cumulative metrics (persist after reset)
+    pub agg_received_cumulative: AtomicU64,
+    pub agg_success_forward_cumulative: AtomicU64,
+    pub agg_fail_forward_cumulative: AtomicU64,
+    pub udp_fail_forward_cumulative: AtomicU64,
+    pub quic_fail_forward_cumulative: AtomicU64,
+    pub tunnel_fail_forward_cumulative: AtomicU64,
+    pub unix_fail_forward_cumulative: AtomicU64,
+    pub duplicate_cumulative: AtomicU64,
+    pub stale_slot_dropped_cumulative: AtomicU64,
+    pub received_invalid_cumulative: AtomicU64,
+    pub non_shred_dropped_cumulative: AtomicU64,
+    pub conflicting_shreds_cumulative: AtomicU64,
+    pub source_not_allowed_cumulative: AtomicU64,
+    pub data_shred_filtered_cumulative: AtomicU64,
+    pub code_shred_filtered_cumulative: AtomicU64,
+    pub recv_socket_dropped_cumulative: AtomicU64,
+    pub pcap_recorded_cumulative: AtomicU64,
+    pub pcap_dropped_cumulative: AtomicU64,
+    pub kafka_published_cumulative: AtomicU64,
+    pub kafka_dropped_cumulative: AtomicU64,
+    pub kafka_delivery_failed_cumulative: AtomicU64,
+    pub archive_inserted_cumulative: AtomicU64,
+    pub archive_dropped_cumulative: AtomicU64,
+    pub archive_insert_failed_cumulative: AtomicU64,
+    pub subscriber_delivered_cumulative: AtomicU64,
+    pub subscriber_dropped_cumulative: AtomicU64,
+    pub channel_dropped_batches_cumulative: AtomicU64,
+    pub channel_dropped_packets_cumulative: AtomicU64,
+    pub memory_guard_dropped_cumulative: AtomicU64,
+    pub keepalive_sent_cumulative: AtomicU64,
+    pub keepalive_send_failed_cumulative: AtomicU64,
+    pub keepalive_received_cumulative: AtomicU64,
+    pub encrypt_dropped_cumulative: AtomicU64,
+    pub decrypt_failed_cumulative: AtomicU64,
+    pub events_sent_cumulative: AtomicU64,
+    pub events_dropped_cumulative: AtomicU64,
+    pub priority_dropped_high_cumulative: AtomicU64,
+    pub priority_dropped_normal_cumulative: AtomicU64,
+    pub priority_dropped_low_cumulative: AtomicU64,
+    pub spill_spilled_cumulative: AtomicU64,
+    pub spill_replayed_cumulative: AtomicU64,
+    pub spill_expired_cumulative: AtomicU64,
+    pub spill_dropped_cumulative: AtomicU64,
+    pub mirror_sent_cumulative: AtomicU64,
+    pub mirror_failed_cumulative: AtomicU64,
+    pub mirror_shed_cumulative: AtomicU64,
+    pub chaos_dropped_cumulative: AtomicU64,
+    pub chaos_duplicated_cumulative: AtomicU64,
+    pub chaos_reordered_cumulative: AtomicU64,
+    pub chaos_delayed_cumulative: AtomicU64,
+    pub dest_became_unhealthy_cumulative: AtomicU64,
+    pub dest_became_healthy_cumulative: AtomicU64,
+    pub thread_restarts_cumulative: AtomicU64,
+    pub socket_rebinds_cumulative: AtomicU64,
+    pub deduper_early_resets_cumulative: AtomicU64,
+    pub slots_finalized_cumulative: AtomicU64,
+    pub slots_last_shred_seen_cumulative: AtomicU64,
+    pub dest_forwarded_cumulative: DashMap<SocketAddr, (u64, u64)>,
+    pub dest_throttled_cumulative: DashMap<SocketAddr, u64>,
+    pub dest_send_queue_dropped_cumulative: DashMap<SocketAddr, u64>,
+    pub dest_slow_sends_cumulative: DashMap<SocketAddr, u64>,
+    pub dest_emsgsize_cumulative: DashMap<SocketAddr, u64>,
+    pub shard_assigned_cumulative: DashMap<SocketAddr, u64>,
+    pub region_received_cumulative: DashMap<String, (u64, u64)>,
+    pub listener_received_cumulative: DashMap<String, (u64, u64)>,
+
+    // destination health, updated live by the health check thread
+    pub healthy_destinations: AtomicU64,
+    pub unhealthy_destinations: AtomicU64,
+    /// Endpoints in the union blocked by `dest-blocklist` or as the proxy itself, updated with the union
+    pub blocked_destinations: AtomicU64,
+    /// Set while the union exceeds `max-destinations` and destinations are dropped, updated with the union
+    pub dest_cap_exceeded: AtomicBool,
+
+    /// Identities in `dest-validator-identities` unresolved for longer than `dest-validator-unresolved-alert-secs`,
+    /// updated live by the validator resolver thread
+    pub validator_identities_unresolved: AtomicU64,
+
+    // slot coverage of the last interval, updated on reset
+    pub last_interval_slots_observed: AtomicU64,
+    /// Min, median, and max data shreds per slot finalized in the last interval, `0` if none were
+    pub last_interval_slot_data_shreds_min: AtomicU64,
+    pub last_interval_slot_data_shreds_median: AtomicU64,
+    pub last_interval_slot_data_shreds_max: AtomicU64,
+    /// Duplicates per million packets received in the last interval
+    pub last_interval_duplicate_ppm: AtomicU64,
+
+    // main pipeline's deduper, updated by the accessory thread before each report
+    /// Estimated fraction of the deduper's bits set, in parts per million
+    pub deduper_saturation_ppm: AtomicU64,
+    /// Time since the deduper was last reset
+    pub deduper_age_ms: AtomicU64,
+
+    // heartbeat metrics, updated live by the heartbeat thread
+    pub successful_heartbeat_cumulative: AtomicU64,
+    pub failed_heartbeat_cumulative: AtomicU64,
+    /// Number of times the heartbeat failed over to the next block engine
+    pub block_engine_failovers_cumulative: AtomicU64,
+    /// Number of times the stall watchdog forced the heartbeat to re-register
+    pub stall_reregistrations_cumulative: AtomicU64,
+    /// Number of destinations added via the admin API removed once their TTL expired
+    pub admin_dest_expired_cumulative: AtomicU64,
+    /// Number of times the rechecked public IP differed from the registered one, forcing the heartbeat to re-register
+    pub public_ip_changes_cumulative: AtomicU64,
+    /// Block engine currently heartbeating to
+    pub active_block_engine_url: ArcSwapOption<String>,
+    /// Set while heartbeats are paused for maintenance, see [crate::set_heartbeats_paused]
+    pub heartbeat_paused: AtomicBool,
+
+    // auth metrics, updated live by the token refresh task
+    /// Access token refreshes and full re-auths attempted before the tokens expire
+    pub token_refresh_attempts_cumulative: AtomicU64,
+    /// Refresh attempts that failed, retried until the token expires
+    pub token_refresh_failures_cumulative: AtomicU64,
+    /// Seconds until the current access token expires
+    pub access_token_ttl_secs: AtomicU64,
+
+    // discovery metrics, updated live by the destination refresh thread
+    /// Discovery requests rejected with 401 or 403
+    pub discovery_auth_failures_cumulative: AtomicU64,
+    /// Discovery requests failed for any other reason
+    pub discovery_failures_cumulative: AtomicU64,
+    /// Discovery requests answered with 304, leaving destinations unchanged
+    pub discovery_not_modified_cumulative: AtomicU64,
+    /// When discovery last succeeded, including unchanged responses. `0` if it hasn't yet
+    pub discovery_last_success_unix_secs: AtomicU64,
+    /// Forwarding to destinations loaded from `endpoint-discovery-state-file` since discovery hasn't succeeded yet
+    pub discovery_using_saved_state: AtomicBool,
+    /// Discovery responses held back for removing more than `endpoint-discovery-max-removal-percent` of endpoints
+    pub discovery_mass_removals_held_cumulative: AtomicU64,
+
+    /// Bytes buffered in flight, updated live wherever buffers are queued and released
+    pub memory_guard: Arc<MemoryGuard>,
+
+    /// Counter blocks of threads from [ShredMetrics::register_thread], folded in by [ShredMetrics::aggregate_threads]
+    threads: Mutex<Vec<RegisteredThread>>,
+}
+
+/// Hottest counters, written only by the thread owning them, so increments never contend on a cache line shared with other threads.
+/// Aligned to 128 bytes since adjacent cache lines are prefetched in pairs
+#[derive(Default)]
+#[repr(align(128))]
+struct ThreadCounters {
+    received: AtomicU64,
+    duplicate: AtomicU64,
+    success_forward: AtomicU64,
+    fail_forward: AtomicU64,
+    udp_fail_forward: AtomicU64,
+    /// Per destination (success, fail) counts, taken by [ShredMetrics::aggregate_threads].
+    /// Only locked by the owning thread and the accessory thread every report, so never contended across forwarder threads
+    dest_forwarded: Mutex<HashMap<SocketAddr, (u64, u64)>>,
+}
+
+impl ThreadCounters {
+    /// Running totals since registering, in the order of [ShredMetrics::thread_totals]
+    fn values(&self) -> [u64; 5] {
+        [
+            self.received.load(Ordering::Relaxed),
+            self.duplicate.load(Ordering::Relaxed),
+            self.success_forward.load(Ordering::Relaxed),
+            self.fail_forward.load(Ordering::Relaxed),
+            self.udp_fail_forward.load(Ordering::Relaxed),
+        ]
+    }
+}
+
+struct RegisteredThread {
+    counters: Arc<ThreadCounters>,
+    /// Values already added to [ShredMetrics]
+    aggregated: [u64; 5],
+}
+
+/// Handle to a thread's own counters, see [ShredMetrics::register_thread].
+/// Not `Sync`, since counters are incremented with a plain load and store rather than a locked read-modify-write
+pub struct ThreadMetrics {
+    counters: Arc<ThreadCounters>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl ThreadMetrics {
+    fn add(counter: &AtomicU64, n: u64) {
+        // only this thread writes, the accessory thread just reads
+        counter.store(counter.load(Ordering::Relaxed) + n, Ordering::Relaxed);
+    }
+
+    pub fn add_received(&self, n: u64) {
+        Self::add(&self.counters.received, n);
+    }
+
+    pub fn add_duplicate(&self, n: u64) {
+        Self::add(&self.counters.duplicate, n);
+    }
+
+    /// Counts packets sent to a UDP destination, added to [ShredMetrics::dest_forwarded] each time metrics are reported
+    pub fn add_udp_forward(&self, dest: SocketAddr, num_success: u64, num_failed: u64) {
+        Self::add(&self.counters.success_forward, num_success);
+        Self::add(&self.counters.fail_forward, num_failed);
+        Self::add(&self.counters.udp_fail_forward, num_failed);
+        let mut dest_forwarded = self.counters.dest_forwarded.lock().unwrap();
+        let (success, fail) = dest_forwarded.entry(dest).or_default();
+        *success += num_success;
+        *fail += num_failed;
+    }
+}
+
+impl Default for ShredMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShredMetrics {
+    pub fn new() -> Self {
+        Self {
+            agg_received: Default::default(),
+            agg_success_forward: Default::default(),
+            agg_fail_forward: Default::default(),
+            udp_fail_forward: Default::default(),
+            quic_fail_forward: Default::default(),
+            tunnel_fail_forward: Default::default(),
+            unix_fail_forward: Default::default(),
+            duplicate: Default::default(),
+            stale_slot_dropped: Default::default(),
+            received_invalid: Default::default(),
+            non_shred_dropped: Default::default(),
+            conflicting_shreds: Default::default(),
+            source_not_allowed: Default::default(),
+            data_shred_filtered: Default::default(),
+            code_shred_filtered: Default::default(),
+            recv_socket_dropped: Default::default(),
+            pcap_recorded: Default::default(),
+            pcap_dropped: Default::default(),
+            kafka_published: Default::default(),
+            kafka_dropped: Default::default(),
+            kafka_delivery_failed: Default::default(),
+            archive_inserted: Default::default(),
+            archive_dropped: Default::default(),
+            archive_insert_failed: Default::default(),
+            subscriber_delivered: Default::default(),
+            subscriber_dropped: Default::default(),
+            channel_dropped_batches: Default::default(),
+            channel_dropped_packets: Default::default(),
+            memory_guard_dropped: Default::default(),
+            keepalive_sent: Default::default(),
+            keepalive_send_failed: Default::default(),
+            keepalive_received: Default::default(),
+            encrypt_dropped: Default::default(),
+            decrypt_failed: Default::default(),
+            events_sent: Default::default(),
+            events_dropped: Default::default(),
+            priority_dropped_high: Default::default(),
+            priority_dropped_normal: Default::default(),
+            priority_dropped_low: Default::default(),
+            spill_spilled: Default::default(),
+            spill_replayed: Default::default(),
+            spill_expired: Default::default(),
+            spill_dropped: Default::default(),
+            mirror_sent: Default::default(),
+            mirror_failed: Default::default(),
+            mirror_shed: Default::default(),
+            chaos_dropped: Default::default(),
+            chaos_duplicated: Default::default(),
+            chaos_reordered: Default::default(),
+            chaos_delayed: Default::default(),
+            dest_became_unhealthy: Default::default(),
+            dest_became_healthy: Default::default(),
+            thread_restarts: Default::default(),
+            socket_rebinds: Default::default(),
+            deduper_early_resets: Default::default(),
+            packets_received: DashMap::with_capacity(10),
+            dest_forwarded: DashMap::with_capacity(10),
+            dest_throttled: DashMap::default(),
+            dest_send_queue_dropped: DashMap::default(),
+            dest_send_queue_depth: DashMap::default(),
+            shard_assigned: DashMap::default(),
+            region_received: DashMap::with_capacity(10),
+            listener_received: DashMap::default(),
+            heartbeat_regions: DashMap::default(),
+            internal_latency_us: Mutex::new(Histogram::new()),
+            received_packet_bytes: Mutex::new(packet_bytes_histogram()),
+            dest_emsgsize_bytes: DashMap::default(),
+            dest_send_latency_us: DashMap::default(),
+            dest_slow_send_intervals: DashMap::default(),
+            slot_coverage: SlotCoverageTap::default(),
+            agg_received_cumulative: Default::default(),
+            agg_success_forward_cumulative: Default::default(),
+            agg_fail_forward_cumulative: Default::default(),
+            udp_fail_forward_cumulative: Default::default(),
+            quic_fail_forward_cumulative: Default::default(),
+            tunnel_fail_forward_cumulative: Default::default(),
+            unix_fail_forward_cumulative: Default::default(),
+            duplicate_cumulative: Default::default(),
+            stale_slot_dropped_cumulative: Default::default(),
+            received_invalid_cumulative: Default::default(),
+            non_shred_dropped_cumulative: Default::default(),
+            conflicting_shreds_cumulative: Default::default(),
+            source_not_allowed_cumulative: Default::default(),
+            data_shred_filtered_cumulative: Default::default(),
+            code_shred_filtered_cumulative: Default::default(),
+            recv_socket_dropped_cumulative: Default::default(),
+            pcap_recorded_cumulative: Default::default(),
+            pcap_dropped_cumulative: Default::default(),
+            kafka_published_cumulative: Default::default(),
+            kafka_dropped_cumulative: Default::default(),
+            kafka_delivery_failed_cumulative: Default::default(),
+            archive_inserted_cumulative: Default::default(),
+            archive_dropped_cumulative: Default::default(),
+            archive_insert_failed_cumulative: Default::default(),
+            subscriber_delivered_cumulative: Default::default(),
+            subscriber_dropped_cumulative: Default::default(),
+            channel_dropped_batches_cumulative: Default::default(),
+            channel_dropped_packets_cumulative: Default::default(),
+            memory_guard_dropped_cumulative: Default::default(),
+            keepalive_sent_cumulative: Default::default(),
+            keepalive_send_failed_cumulative: Default::default(),
+            keepalive_received_cumulative: Default::default(),
+            encrypt_dropped_cumulative: Default::default(),
+            decrypt_failed_cumulative: Default::default(),
+            events_sent_cumulative: Default::default(),
+            events_dropped_cumulative: Default::default(),
+            priority_dropped_high_cumulative: Default::default(),
+            priority_dropped_normal_cumulative: Default::default(),
+            priority_dropped_low_cumulative: Default::default(),
+            spill_spilled_cumulative: Default::default(),
+            spill_replayed_cumulative: Default::default(),
+            spill_expired_cumulative: Default::default(),
+            spill_dropped_cumulative: Default::default(),
+            mirror_sent_cumulative: Default::default(),
+            mirror_failed_cumulative: Default::default(),
+            mirror_shed_cumulative: Default::default(),
+            chaos_dropped_cumulative: Default::default(),
+            chaos_duplicated_cumulative: Default::default(),
+            chaos_reordered_cumulative: Default::default(),
+            chaos_delayed_cumulative: Default::default(),
+            dest_became_unhealthy_cumulative: Default::default(),
+            dest_became_healthy_cumulative: Default::default(),
+            thread_restarts_cumulative: Default::default(),
+            socket_rebinds_cumulative: Default::default(),
+            deduper_early_resets_cumulative: Default::default(),
+            slots_finalized_cumulative: Default::default(),
+            slots_last_shred_seen_cumulative: Default::default(),
+            healthy_destinations: Default::default(),
+            unhealthy_destinations: Default::default(),
+            blocked_destinations: Default::default(),
+            dest_cap_exceeded: Default::default(),
+            validator_identities_unresolved: Default::default(),
+            last_interval_slots_observed: Default::default(),
+            last_interval_slot_data_shreds_min: Default::default(),
+            last_interval_slot_data_shreds_median: Default::default(),
+            last_interval_slot_data_shreds_max: Default::default(),
+            last_interval_duplicate_ppm: Default::default(),
+            deduper_saturation_ppm: Default::default(),
+            deduper_age_ms: Default::default(),
+            dest_forwarded_cumulative: DashMap::with_capacity(10),
+            dest_throttled_cumulative: DashMap::default(),
+            dest_send_queue_dropped_cumulative: DashMap::default(),
+            dest_slow_sends_cumulative: DashMap::default(),
+            dest_emsgsize_cumulative: DashMap::default(),
+            shard_assigned_cumulative: DashMap::default(),
+            region_received_cumulative: DashMap::with_capacity(10),
+            listener_received_cumulative: DashMap::default(),
+            successful_heartbeat_cumulative: Default::default(),
+            failed_heartbeat_cumulative: Default::default(),
+            block_engine_failovers_cumulative: Default::default(),
+            stall_reregistrations_cumulative: Default::default(),
+            admin_dest_expired_cumulative: Default::default(),
+            public_ip_changes_cumulative: Default::default(),
+            active_block_engine_url: Default::default(),
+            heartbeat_paused: Default::default(),
+            token_refresh_attempts_cumulative: Default::default(),
+            token_refresh_failures_cumulative: Default::default(),
+            access_token_ttl_secs: Default::default(),
+            discovery_auth_failures_cumulative: Default::default(),
+            discovery_failures_cumulative: Default::default(),
+            discovery_not_modified_cumulative: Default::default(),
+            discovery_last_success_unix_secs: Default::default(),
+            discovery_using_saved_state: Default::default(),
+            discovery_mass_removals_held_cumulative: Default::default(),
+            memory_guard: Default::default(),
+            threads: Default::default(),
+        }
+    }
+
+    /// Registers counters owned by the calling thread, which are added to `agg_received`, `duplicate`,
+    /// `agg_success_forward`, `agg_fail_forward`, `udp_fail_forward`, and `dest_forwarded` each time metrics are reported
+    pub fn register_thread(&self) -> ThreadMetrics {
+        let counters = Arc::new(ThreadCounters::default());
+        self.threads.lock().unwrap().push(RegisteredThread {
+            counters: counters.clone(),
+            aggregated: [0; 5],
+        });
+        ThreadMetrics {
+            counters,
+            _not_sync: PhantomData,
+        }
+    }
+
+    fn thread_totals(&self) -> [&AtomicU64; 5] {
+        [
+            &self.agg_received,
+            &self.duplicate,
+            &self.agg_success_forward,
+            &self.agg_fail_forward,
+            &self.udp_fail_forward,
+        ]
+    }
+
+    /// Adds what registered threads counted since the last call. Called when reporting and resetting
+    pub fn aggregate_threads(&self) {
+        let mut threads = self.threads.lock().unwrap();
+        // the handle of an exited or restarted thread is dropped once its last counts are added, a restarted one registers anew.
+        // Orphaned handles are found before reading, with the fence pairing with the release of the dropped handle,
+        // so counts added just before dropping are read, and a handle dropped after the check is kept until the next call
+        threads.retain_mut(|thread| {
+            let orphaned = Arc::strong_count(&thread.counters) == 1;
+            fence(Ordering::Acquire);
+            let values = thread.counters.values();
+            for ((total, value), aggregated) in self
+                .thread_totals()
+                .into_iter()
+                .zip(values)
+                .zip(thread.aggregated.iter_mut())
+            {
+                total.fetch_add(value - *aggregated, Ordering::Relaxed);
+                *aggregated = value;
+            }
+            // drained rather than taken, keeping the map's allocation for the thread's next counts
+            let mut dest_forwarded = thread.counters.dest_forwarded.lock().unwrap();
+            for (dest, (num_success, num_failed)) in dest_forwarded.drain() {
+                self.record_dest_forwarded(dest, num_success, num_failed);
+            }
+            drop(dest_forwarded);
+            !orphaned
+        });
+    }
+
+    pub fn report(&self) {
+        self.aggregate_threads();
+        datapoint_info!(
+            "shredstream_proxy-connection_metrics",
+            (
+                "agg_received",
+                self.agg_received.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "agg_success_forward",
+                self.agg_success_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "agg_fail_forward",
+                self.agg_fail_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "udp_fail_forward",
+                self.udp_fail_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "quic_fail_forward",
+                self.quic_fail_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "tunnel_fail_forward",
+                self.tunnel_fail_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "unix_fail_forward",
+                self.unix_fail_forward.load(Ordering::Relaxed),
+                i64
+            ),
+            ("duplicate", self.duplicate.load(Ordering::Relaxed), i64),
+            (
+                "stale_slot_dropped",
+                self.stale_slot_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "received_invalid",
+                self.received_invalid.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "non_shred_dropped",
+                self.non_shred_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "conflicting_shreds",
+                self.conflicting_shreds.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "source_not_allowed",
+                self.source_not_allowed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "data_shred_filtered",
+                self.data_shred_filtered.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "code_shred_filtered",
+                self.code_shred_filtered.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "recv_socket_dropped",
+                self.recv_socket_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "pcap_recorded",
+                self.pcap_recorded.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "pcap_dropped",
+                self.pcap_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "kafka_published",
+                self.kafka_published.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "kafka_dropped",
+                self.kafka_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "kafka_delivery_failed",
+                self.kafka_delivery_failed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "archive_inserted",
+                self.archive_inserted.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "archive_dropped",
+                self.archive_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "archive_insert_failed",
+                self.archive_insert_failed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "subscriber_delivered",
+                self.subscriber_delivered.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "subscriber_dropped",
+                self.subscriber_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "channel_dropped_batches",
+                self.channel_dropped_batches.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "channel_dropped_packets",
+                self.channel_dropped_packets.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "memory_guard_dropped",
+                self.memory_guard_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "keepalive_sent",
+                self.keepalive_sent.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "keepalive_send_failed",
+                self.keepalive_send_failed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "keepalive_received",
+                self.keepalive_received.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "encrypt_dropped",
+                self.encrypt_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "decrypt_failed",
+                self.decrypt_failed.load(Ordering::Relaxed),
+                i64
+            ),
+            ("events_sent", self.events_sent.load(Ordering::Relaxed), i64),
+            (
+                "events_dropped",
+                self.events_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "priority_dropped_high",
+                self.priority_dropped_high.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "priority_dropped_normal",
+                self.priority_dropped_normal.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "priority_dropped_low",
+                self.priority_dropped_low.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "spill_spilled",
+                self.spill_spilled.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "spill_replayed",
+                self.spill_replayed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "spill_expired",
+                self.spill_expired.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "spill_dropped",
+                self.spill_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            ("mirror_sent", self.mirror_sent.load(Ordering::Relaxed), i64),
+            (
+                "mirror_failed",
+                self.mirror_failed.load(Ordering::Relaxed),
+                i64
+            ),
+            ("mirror_shed", self.mirror_shed.load(Ordering::Relaxed), i64),
+            (
+                "chaos_dropped",
+                self.chaos_dropped.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "chaos_duplicated",
+                self.chaos_duplicated.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "chaos_reordered",
+                self.chaos_reordered.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "chaos_delayed",
+                self.chaos_delayed.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "dest_became_unhealthy",
+                self.dest_became_unhealthy.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "dest_became_healthy",
+                self.dest_became_healthy.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "thread_restarts",
+                self.thread_restarts.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "socket_rebinds",
+                self.socket_rebinds.load(Ordering::Relaxed),
+                i64
+            ),
+        );
+        datapoint_info!(
+            "shredstream_proxy-dedup_stats",
+            (
+                "duplicate_percent",
+                self.duplicate_ppm() as f64 / 10_000.0,
+                f64
+            ),
+            (
+                "deduper_estimated_saturation",
+                self.deduper_saturation_ppm.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+                f64
+            ),
+            (
+                "deduper_age_ms",
+                self.deduper_age_ms.load(Ordering::Relaxed),
+                i64
+            ),
+            (
+                "deduper_early_resets",
+                self.deduper_early_resets.load(Ordering::Relaxed),
+                i64
+            ),
+        );
+        datapoint_info!(
+            "shredstream_proxy-buffered_bytes",
+            ("receive", self.memory_guard.held(BufferKind::Receive), i64),
+            ("tunnel", self.memory_guard.held(BufferKind::Tunnel), i64),
+            ("pcap", self.memory_guard.held(BufferKind::Pcap), i64),
+            ("kafka", self.memory_guard.held(BufferKind::Kafka), i64),
+            ("archive", self.memory_guard.held(BufferKind::Archive), i64),
+            ("spill", self.memory_guard.held(BufferKind::Spill), i64),
+            ("total", self.memory_guard.total(), i64),
+        );
+        self.packets_received.iter().for_each(|kv| {
+            let (addr, (discarded_packets, not_discarded_packets)) = kv.pair();
+            datapoint_info!("shredstream_proxy-receiver_stats",
+                "addr" => addr.to_string(),
+                ("discarded_packets", *discarded_packets, i64),
+                ("not_discarded_packets", *not_discarded_packets, i64),
+            );
+        });
+        self.dest_forwarded.iter().for_each(|kv| {
+            let (addr, (success_forward, fail_forward)) = kv.pair();
+            datapoint_info!("shredstream_proxy-destination_stats",
+                "addr" => addr.to_string(),
+                ("success_forward", *success_forward, i64),
+                ("fail_forward", *fail_forward, i64),
+            );
+        });
+        self.dest_throttled.iter().for_each(|kv| {
+            let (addr, throttled) = kv.pair();
+            datapoint_info!("shredstream_proxy-destination_throttle_stats",
+                "addr" => addr.to_string(),
+                ("throttled", *throttled, i64),
+            );
+        });
+        self.dest_send_queue_depth.iter().for_each(|kv| {
+            let (addr, queue_depth) = kv.pair();
+            let queue_dropped = self
+                .dest_send_queue_dropped
+                .get(addr)
+                .map(|dropped| *dropped)
+                .unwrap_or_default();
+            datapoint_info!("shredstream_proxy-destination_send_queue_stats",
+                "addr" => addr.to_string(),
+                ("queue_depth", *queue_depth, i64),
+                ("queue_dropped", queue_dropped, i64),
+            );
+        });
+        self.dest_send_latency_us.iter().for_each(|kv| {
+            let (addr, (send_latency_us, slow_sends)) = kv.pair();
+            let p50 = send_latency_us.percentile(50.0).unwrap_or_default();
+            let p99 = send_latency_us.percentile(99.0).unwrap_or_default();
+            let max = send_latency_us.maximum().unwrap_or_default();
+            datapoint_info!("shredstream_proxy-destination_send_latency",
+                "addr" => addr.to_string(),
+                ("sends", send_latency_us.entries(), i64),
+                ("p50_us", p50, i64),
+                ("p99_us", p99, i64),
+                ("max_us", max, i64),
+                ("slow_sends", *slow_sends, i64),
+            );
+            // p99 is over the threshold once more than 1% of sends were slow
+            if slow_sends * 100 > send_latency_us.entries() {
+                let mut slow_intervals = self.dest_slow_send_intervals.entry(*addr).or_default();
+                *slow_intervals += 1;
+                if *slow_intervals == SLOW_SEND_WARN_INTERVALS {
+                    warn!("Destination {addr} p99 send latency was {p99}us, over the slow send threshold for {SLOW_SEND_WARN_INTERVALS} intervals in a row. Sends to it may be delaying other destinations.");
+                }
+            } else {
+                self.dest_slow_send_intervals.remove(addr);
+            }
+        });
+        self.shard_assigned.iter().for_each(|kv| {
+            let (addr, assigned) = kv.pair();
+            datapoint_info!("shredstream_proxy-shard_member_stats",
+                "addr" => addr.to_string(),
+                ("assigned", *assigned, i64),
+            );
+        });
+        self.region_received.iter().for_each(|kv| {
+            let (region, (received, duplicate)) = kv.pair();
+            datapoint_info!("shredstream_proxy-region_stats",
+                "region" => region,
+                ("received", *received, i64),
+                ("duplicate", *duplicate, i64),
+            );
+        });
+        self.listener_received.iter().for_each(|kv| {
+            let (listener, (received, duplicate)) = kv.pair();
+            datapoint_info!("shredstream_proxy-listener_stats",
+                "listener" => listener,
+                ("received", *received, i64),
+                ("duplicate", *duplicate, i64),
+            );
+        });
+        self.heartbeat_regions.iter().for_each(|kv| {
+            let (region, stats) = kv.pair();
+            datapoint_info!("shredstream_proxy-heartbeat_region_stats",
+                "region" => region,
+                ("state", stats.state as i64, i64),
+                ("heartbeats", stats.rtt_us.entries(), i64),
+                ("rtt_p50_us", stats.rtt_us.percentile(50.0).unwrap_or_default(), i64),
+                ("rtt_p99_us", stats.rtt_us.percentile(99.0).unwrap_or_default(), i64),
+                ("rtt_max_us", stats.rtt_us.maximum().unwrap_or_default(), i64),
+                ("consecutive_failures", stats.consecutive_failures, i64),
+                ("last_success_age_ms", stats.last_success.elapsed().as_millis(), i64),
+                ("ttl_ms", stats.ttl_ms.unwrap_or_default(), i64),
+            );
+        });
+        let internal_latency_us = self.internal_latency_us.lock().unwrap();
+        if internal_latency_us.entries() > 0 {
+            let p50 = internal_latency_us.percentile(50.0).unwrap_or_default();
+            let p90 = internal_latency_us.percentile(90.0).unwrap_or_default();
+            let p99 = internal_latency_us.percentile(99.0).unwrap_or_default();
+            info!("Internal latency p50: {p50}us, p90: {p90}us, p99: {p99}us");
+            datapoint_info!(
+                "shredstream_proxy-internal_latency",
+                ("count", internal_latency_us.entries(), i64),
+                ("p50_us", p50, i64),
+                ("p90_us", p90, i64),
+                ("p99_us", p99, i64),
+            );
+        }
+        drop(internal_latency_us);
+        let received_packet_bytes = self.received_packet_bytes.lock().unwrap();
+        if received_packet_bytes.entries() > 0 {
+            datapoint_info!(
+                "shredstream_proxy-packet_sizes",
+                ("count", received_packet_bytes.entries(), i64),
+                (
+                    "min_bytes",
+                    received_packet_bytes.minimum().unwrap_or_default(),
+                    i64
+                ),
+                (
+                    "p1_bytes",
+                    received_packet_bytes.percentile(1.0).unwrap_or_default(),
+                    i64
+                ),
+                (
+                    "p50_bytes",
+                    received_packet_bytes.percentile(50.0).unwrap_or_default(),
+                    i64
+                ),
+                (
+                    "p99_bytes",
+                    received_packet_bytes.percentile(99.0).unwrap_or_default(),
+                    i64
+                ),
+                (
+                    "max_bytes",
+                    received_packet_bytes.maximum().unwrap_or_default(),
+                    i64
+                ),
+            );
+        }
+        drop(received_packet_bytes);
+        self.dest_emsgsize_bytes.iter().for_each(|kv| {
+            let (addr, emsgsize_bytes) = kv.pair();
+            let (min, max) = (
+                emsgsize_bytes.minimum().unwrap_or_default(),
+                emsgsize_bytes.maximum().unwrap_or_default(),
+            );
+            warn!("{} sends to {addr} failed with EMSGSIZE, packets of {min} to {max} bytes. The path MTU to it is likely too small.", emsgsize_bytes.entries());
+            datapoint_warn!("shredstream_proxy-destination_emsgsize",
+                "addr" => addr.to_string(),
+                ("count", emsgsize_bytes.entries(), i64),
+                ("min_bytes", min, i64),
+                ("max_bytes", max, i64),
+            );
+        });
+        let slot_coverage = self.slot_coverage.stats();
+        let (data_shreds_min, data_shreds_median, data_shreds_max) =
+            slot_coverage.data_shreds_per_slot.unwrap_or_default();
+        datapoint_info!(
+            "shredstream_proxy-slot_coverage",
+            ("slots_observed", slot_coverage.slots_observed, i64),
+            ("slots_finalized", slot_coverage.slots_finalized, i64),
+            (
+                "slots_last_shred_seen",
+                slot_coverage.slots_last_shred_seen,
+                i64
+            ),
+            ("data_shreds_per_slot_min", data_shreds_min, i64),
+            ("data_shreds_per_slot_median", data_shreds_median, i64),
+            ("data_shreds_per_slot_max", data_shreds_max, i64),
+        );
+    }
+
+    /// resets current values, increments cumulative values
+    pub fn reset(&self) {
+        self.aggregate_threads();
+        self.last_interval_duplicate_ppm
+            .store(self.duplicate_ppm(), Ordering::Relaxed);
+        self.agg_received_cumulative.fetch_add(
+            self.agg_received.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.agg_success_forward_cumulative.fetch_add(
+            self.agg_success_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.agg_fail_forward_cumulative.fetch_add(
+            self.agg_fail_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.udp_fail_forward_cumulative.fetch_add(
+            self.udp_fail_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.quic_fail_forward_cumulative.fetch_add(
+            self.quic_fail_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.tunnel_fail_forward_cumulative.fetch_add(
+            self.tunnel_fail_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.unix_fail_forward_cumulative.fetch_add(
+            self.unix_fail_forward.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.duplicate_cumulative
+            .fetch_add(self.duplicate.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        self.stale_slot_dropped_cumulative.fetch_add(
+            self.stale_slot_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.received_invalid_cumulative.fetch_add(
+            self.received_invalid.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.non_shred_dropped_cumulative.fetch_add(
+            self.non_shred_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.conflicting_shreds_cumulative.fetch_add(
+            self.conflicting_shreds.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.source_not_allowed_cumulative.fetch_add(
+            self.source_not_allowed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.data_shred_filtered_cumulative.fetch_add(
+            self.data_shred_filtered.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.code_shred_filtered_cumulative.fetch_add(
+            self.code_shred_filtered.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.recv_socket_dropped_cumulative.fetch_add(
+            self.recv_socket_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.pcap_recorded_cumulative.fetch_add(
+            self.pcap_recorded.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.pcap_dropped_cumulative.fetch_add(
+            self.pcap_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.kafka_published_cumulative.fetch_add(
+            self.kafka_published.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.kafka_dropped_cumulative.fetch_add(
+            self.kafka_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.kafka_delivery_failed_cumulative.fetch_add(
+            self.kafka_delivery_failed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.archive_inserted_cumulative.fetch_add(
+            self.archive_inserted.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.archive_dropped_cumulative.fetch_add(
+            self.archive_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.archive_insert_failed_cumulative.fetch_add(
+            self.archive_insert_failed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.subscriber_delivered_cumulative.fetch_add(
+            self.subscriber_delivered.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.subscriber_dropped_cumulative.fetch_add(
+            self.subscriber_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.channel_dropped_batches_cumulative.fetch_add(
+            self.channel_dropped_batches.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.channel_dropped_packets_cumulative.fetch_add(
+            self.channel_dropped_packets.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.memory_guard_dropped_cumulative.fetch_add(
+            self.memory_guard_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.keepalive_sent_cumulative.fetch_add(
+            self.keepalive_sent.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.keepalive_send_failed_cumulative.fetch_add(
+            self.keepalive_send_failed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.keepalive_received_cumulative.fetch_add(
+            self.keepalive_received.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.encrypt_dropped_cumulative.fetch_add(
+            self.encrypt_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.decrypt_failed_cumulative.fetch_add(
+            self.decrypt_failed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.events_sent_cumulative.fetch_add(
+            self.events_sent.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.events_dropped_cumulative.fetch_add(
+            self.events_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        for priority in DestPriority::ALL {
+            let (dropped, dropped_cumulative) = self.priority_dropped(priority);
+            dropped_cumulative.fetch_add(dropped.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        }
+        self.spill_spilled_cumulative.fetch_add(
+            self.spill_spilled.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.spill_replayed_cumulative.fetch_add(
+            self.spill_replayed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.spill_expired_cumulative.fetch_add(
+            self.spill_expired.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.spill_dropped_cumulative.fetch_add(
+            self.spill_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.mirror_sent_cumulative.fetch_add(
+            self.mirror_sent.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.mirror_failed_cumulative.fetch_add(
+            self.mirror_failed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.mirror_shed_cumulative.fetch_add(
+            self.mirror_shed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.chaos_dropped_cumulative.fetch_add(
+            self.chaos_dropped.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.chaos_duplicated_cumulative.fetch_add(
+            self.chaos_duplicated.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.chaos_reordered_cumulative.fetch_add(
+            self.chaos_reordered.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.chaos_delayed_cumulative.fetch_add(
+            self.chaos_delayed.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.dest_became_unhealthy_cumulative.fetch_add(
+            self.dest_became_unhealthy.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.dest_became_healthy_cumulative.fetch_add(
+            self.dest_became_healthy.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.thread_restarts_cumulative.fetch_add(
+            self.thread_restarts.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.socket_rebinds_cumulative.fetch_add(
+            self.socket_rebinds.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.deduper_early_resets_cumulative.fetch_add(
+            self.deduper_early_resets.swap(0, Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        self.packets_received.alter_all(|_ip, _metrics| (0, 0));
+        self.dest_forwarded.alter_all(|addr, (success, fail)| {
+            self.dest_forwarded_cumulative
+                .entry(*addr)
+                .and_modify(|(success_cumulative, fail_cumulative)| {
+                    *success_cumulative += success;
+                    *fail_cumulative += fail;
+                })
+                .or_insert((success, fail));
+            (0, 0)
+        });
+        self.dest_throttled.alter_all(|addr, throttled| {
+            *self.dest_throttled_cumulative.entry(*addr).or_default() += throttled;
+            0
+        });
+        self.dest_send_queue_dropped.alter_all(|addr, dropped| {
+            *self
+                .dest_send_queue_dropped_cumulative
+                .entry(*addr)
+                .or_default() += dropped;
+            0
+        });
+        // dropped so removed destinations don't linger, each is recreated on its next send
+        self.dest_send_latency_us
+            .retain(|addr, (_send_latency_us, slow_sends)| {
+                *self.dest_slow_sends_cumulative.entry(*addr).or_default() += *slow_sends;
+                false
+            });
+        self.shard_assigned.alter_all(|addr, assigned| {
+            *self.shard_assigned_cumulative.entry(*addr).or_default() += assigned;
+            0
+        });
+        self.heartbeat_regions
+            .iter_mut()
+            .for_each(|mut stats| stats.rtt_us = Histogram::new());
+        // few regions, so entries are kept
+        self.region_received
+            .alter_all(|region, (received, duplicate)| {
+                self.region_received_cumulative
+                    .entry(region.clone())
+                    .and_modify(|(received_cumulative, duplicate_cumulative)| {
+                        *received_cumulative += received;
+                        *duplicate_cumulative += duplicate;
+                    })
+                    .or_insert((received, duplicate));
+                (0, 0)
+            });
+        self.listener_received
+            .alter_all(|listener, (received, duplicate)| {
+                self.listener_received_cumulative
+                    .entry(listener.clone())
+                    .and_modify(|(received_cumulative, duplicate_cumulative)| {
+                        *received_cumulative += received;
+                        *duplicate_cumulative += duplicate;
+                    })
+                    .or_insert((received, duplicate));
+                (0, 0)
+            });
+        self.internal_latency_us.lock().unwrap().clear();
+        self.received_packet_bytes.lock().unwrap().clear();
+        // dropped so removed destinations don't linger, each is recreated on its next failure
+        self.dest_emsgsize_bytes.retain(|addr, emsgsize_bytes| {
+            *self.dest_emsgsize_cumulative.entry(*addr).or_default() += emsgsize_bytes.entries();
+            false
+        });
+        let stats = self.slot_coverage.take_stats();
+        self.slots_finalized_cumulative
+            .fetch_add(stats.slots_finalized, Ordering::Relaxed);
+        self.slots_last_shred_seen_cumulative
+            .fetch_add(stats.slots_last_shred_seen, Ordering::Relaxed);
+        self.last_interval_slots_observed
+            .store(stats.slots_observed, Ordering::Relaxed);
+        let (data_shreds_min, data_shreds_median, data_shreds_max) =
+            stats.data_shreds_per_slot.unwrap_or_default();
+        self.last_interval_slot_data_shreds_min
+            .store(data_shreds_min as u64, Ordering::Relaxed);
+        self.last_interval_slot_data_shreds_median
+            .store(data_shreds_median as u64, Ordering::Relaxed);
+        self.last_interval_slot_data_shreds_max
+            .store(data_shreds_max as u64, Ordering::Relaxed);
+    }
+
+    /// Duplicates per million packets received this interval, `0` if none were
+    fn duplicate_ppm(&self) -> u64 {
+        let received = self.agg_received.load(Ordering::Relaxed);
+        let duplicate = self.duplicate.load(Ordering::Relaxed);
+        (duplicate * 1_000_000).checked_div(received).unwrap_or(0)
+    }
+
+    /// Records the saturation and age of `deduper` for reporting
+    pub fn record_deduper(&self, deduper: &ShredDeduper) {
+        self.deduper_saturation_ppm.store(
+            (deduper.estimated_saturation() * 1_000_000.0) as u64,
+            Ordering::Relaxed,
+        );
+        self.deduper_age_ms
+            .store(deduper.age().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Time since discovery last succeeded, `None` if it hasn't yet
+    pub fn discovery_age(&self, now: SystemTime) -> Option<Duration> {
+        match self
+            .discovery_last_success_unix_secs
+            .load(Ordering::Relaxed)
+        {
+            0 => None,
+            last_success => Some(Duration::from_secs(
+                unix_secs(now).saturating_sub(last_success),
+            )),
+        }
+    }
+
+    /// Records packets received on a region's listen port, before forwarding
+    pub fn record_region_received(&self, region: &str, num_received: u64, num_duplicate: u64) {
+        // avoid allocating the key once the region is known
+        if let Some(mut entry) = self.region_received.get_mut(region) {
+            entry.0 += num_received;
+            entry.1 += num_duplicate;
+            return;
+        }
+        self.region_received
+            .entry(region.to_string())
+            .and_modify(|(received, duplicate)| {
+                *received += num_received;
+                *duplicate += num_duplicate;
+            })
+            .or_insert((num_received, num_duplicate));
+    }
+
+    /// (interval, cumulative) packets dropped for destinations of `priority`
+    pub fn priority_dropped(&self, priority: DestPriority) -> (&AtomicU64, &AtomicU64) {
+        match priority {
+            DestPriority::High => (
+                &self.priority_dropped_high,
+                &self.priority_dropped_high_cumulative,
+            ),
+            DestPriority::Normal => (
+                &self.priority_dropped_normal,
+                &self.priority_dropped_normal_cumulative,
+            ),
+            DestPriority::Low => (
+                &self.priority_dropped_low,
+                &self.priority_dropped_low_cumulative,
+            ),
+        }
+    }
+
+    pub fn record_priority_dropped(&self, priority: DestPriority, num_packets: u64) {
+        self.priority_dropped(priority)
+            .0
+            .fetch_add(num_packets, Ordering::Relaxed);
+    }
+
+    /// Records packets received on a `--listener` port, before forwarding
+    pub fn record_listener_received(&self, listener: &str, num_received: u64, num_duplicate: u64) {
+        if let Some(mut entry) = self.listener_received.get_mut(listener) {
+            entry.0 += num_received;
+            entry.1 += num_duplicate;
+            return;
+        }
+        self.listener_received
+            .entry(listener.to_string())
+            .and_modify(|(received, duplicate)| {
+                *received += num_received;
+                *duplicate += num_duplicate;
+            })
+            .or_insert((num_received, num_duplicate));
+    }
+
+    /// Records time from kernel receive until `forward_time` for each packet not discarded.
+    /// `rx_timestamps` are per batch, `None` for batches received without timestamps
+    pub fn record_internal_latency(
+        &self,
+        packet_batches: &[PacketBatch],
+        rx_timestamps: &[Option<Vec<Option<SystemTime>>>],
+        forward_time: SystemTime,
+    ) {
+        let mut internal_latency_us = self.internal_latency_us.lock().unwrap();
+        packet_batches
+            .iter()
+            .zip(rx_timestamps)
+            .filter_map(|(batch, rx_timestamps)| Some(batch.iter().zip(rx_timestamps.as_ref()?)))
+            .flatten()
+            .filter(|(packet, _)| !packet.meta().discard())
+            .filter_map(|(_, rx_timestamp)| forward_time.duration_since((*rx_timestamp)?).ok())
+            .for_each(|latency| {
+                // values past the histogram's max are dropped
+                let _ = internal_latency_us.increment(latency.as_micros() as u64);
+            });
+    }
+
+    /// Records the size of every received packet, including ones discarded later
+    pub fn record_received_sizes(&self, packet_batches: &[PacketBatch]) {
+        let mut received_packet_bytes = self.received_packet_bytes.lock().unwrap();
+        packet_batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .for_each(|packet| {
+                // values past the histogram's max are dropped
+                let _ = received_packet_bytes.increment(packet.meta().size as u64);
+            });
+    }
+
+    /// Records a packet of `packet_bytes` whose send to `dest` failed with `EMSGSIZE`
+    pub fn record_emsgsize(&self, dest: SocketAddr, packet_bytes: usize) {
+        // values past the histogram's max are dropped
+        let _ = self
+            .dest_emsgsize_bytes
+            .entry(dest)
+            .or_insert_with(packet_bytes_histogram)
+            .increment(packet_bytes as u64);
+    }
+
+    /// Records how long one `sendmmsg` call to `dest` took
+    pub fn record_send_latency(
+        &self,
+        dest: SocketAddr,
+        latency: Duration,
+        slow_send_threshold: Duration,
+    ) {
+        let mut send_latency = self.dest_send_latency_us.entry(dest).or_default();
+        let (send_latency_us, slow_sends) = send_latency.value_mut();
+        // values past the histogram's max are dropped
+        let _ = send_latency_us.increment(latency.as_micros() as u64);
+        *slow_sends += (latency > slow_send_threshold) as u64;
+    }
+
+    /// Records the result of sending packets to a single destination
+    pub fn record_forward(
+        &self,
+        dest: SocketAddr,
+        transport: Transport,
+        num_success: u64,
+        num_failed: u64,
+    ) {
+        self.agg_success_forward
+            .fetch_add(num_success, Ordering::Relaxed);
+        self.agg_fail_forward
+            .fetch_add(num_failed, Ordering::Relaxed);
+        match transport {
+            Transport::Udp => &self.udp_fail_forward,
+            Transport::Quic => &self.quic_fail_forward,
+            Transport::Tunnel => &self.tunnel_fail_forward,
+            Transport::Unix => &self.unix_fail_forward,
+        }
+        .fetch_add(num_failed, Ordering::Relaxed);
+        self.record_dest_forwarded(dest, num_success, num_failed);
+    }
+
+    /// Counts packets sent to `dest`. Forwarder threads count their own with [ThreadMetrics::add_udp_forward]
+    fn record_dest_forwarded(&self, dest: SocketAddr, num_success: u64, num_failed: u64) {
+        self.dest_forwarded
+            .entry(dest)
+            .and_modify(|(success, fail)| {
+                *success += num_success;
+                *fail += num_failed;
+            })
+            .or_insert((num_success, num_failed));
+    }
+
+    /// Counts packets over a destination's rate limit, dropped instead of sent
+    pub fn record_dest_throttled(&self, dest: SocketAddr, num_throttled: u64) {
+        if num_throttled > 0 {
+            *self.dest_throttled.entry(dest).or_default() += num_throttled;
+        }
+    }
+
+    /// Counts packets dropped from a full destination send queue, also as failed forwards
+    pub fn record_send_queue_dropped(&self, dest: SocketAddr, num_dropped: u64) {
+        *self.dest_send_queue_dropped.entry(dest).or_default() += num_dropped;
+        self.record_forward(dest, Transport::Udp, 0, num_dropped);
+    }
+
+    pub fn record_send_queue_depth(&self, dest: SocketAddr, queue_depth: usize) {
+        self.dest_send_queue_depth.insert(dest, queue_depth);
+    }
+
+    /// Counts packets sent to a shard group member
+    pub fn record_shard_assigned(&self, member: SocketAddr, num_assigned: u64) {
+        *self.shard_assigned.entry(member).or_default() += num_assigned;
+    }
+
+    /// Removes per-destination counters for destinations no longer forwarded to
+    pub fn retain_destinations(&self, dest_sockets: &[SocketAddr]) {
+        // so removed destinations' counts still held by forwarder threads aren't added back afterwards
+        self.aggregate_threads();
+        let dest_sockets = dest_sockets.iter().collect::<HashSet<_>>();
+        self.dest_forwarded
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.dest_forwarded_cumulative
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.dest_throttled
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.dest_throttled_cumulative
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.dest_send_queue_dropped
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.dest_send_queue_dropped_cumulative
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.shard_assigned
+            .retain(|addr, _| dest_sockets.contains(addr));
+        self.shard_assigned_cumulative
+            .retain(|addr, _| dest_sockets.contains(addr));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs, mem,
+        net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        thread::sleep,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use arc_swap::ArcSwap;
+    use ipnet::IpNet;
+    use solana_perf::packet::{Meta, Packet, PacketBatch, PACKETS_PER_BATCH};
+    use solana_sdk::{
+        clock::DEFAULT_MS_PER_SLOT,
+        packet::{PacketFlags, PACKET_DATA_SIZE},
+    };
+    use solana_streamer::streamer::StreamerReceiveStats;
+
+    use crate::{
+        conflicts::{self, ConflictDetectorConfig},
+        encryption::{self, MAX_SEALED_PACKET_SIZE},
+        forwarder::{
+            coalesce_packet_batches, fetch_discovered_destinations, is_auth_error,
+            is_trace_sampled, load_discovery_state, mass_removal, maybe_reset_deduper,
+            parse_discovered_destinations, recv_from_channel_and_send_multiple_dest, recycle_vec,
+            resolve_static_destinations, save_discovery_state, start_receive_thread, DedupMode,
+            DestinationBlocklist, DestinationCap, DestinationSources, DiscoveryCache,
+            EndpointDiscovery, ForwardShredTypes, ForwarderContext, ForwarderThreads, HighestSlot,
+            ListenSocketOptions, LocalDests, PacketFilter, SendSocketOptions, ShredDeduper,
+            ShredMetrics, ShredSink, SourceAllowlist, UdpSink, DISCOVERY_REFRESH_INTERVAL,
+            HIGHEST_SLOT_RESEED_AFTER, HIGHEST_SLOT_SEED_SAMPLES, INVALID_SOURCE_LOG_INTERVAL,
+            MAX_SLOTS_AHEAD_OF_CLOCK,
+        },
+        isolated_send::IsolatedSendSink,
+        memory_guard::BufferKind,
+        packet_channel::{self, DropPolicy, ReceivedBatch},
+        priority::DestPriority,
+        quic::QuicSink,
+        rate_limit::RateLimiter,
+        shard::{ShardBy, ShardGroup, ShardGroupSpec, ShardMembers},
+        shred::tests::new_data_shred,
+        slot_coverage::CoverageShred,
+        socket::{Egress, RebindPolicy},
+        tunnel::{TunnelConfig, TunnelDest, TunnelSink},
+        unix::{UnixDest, UnixSink},
+        AddressFamily,
+    };
+
+    fn new_tunnel_sink(metrics: Arc<ShredMetrics>) -> TunnelSink {
+        TunnelSink::new(
+            TunnelConfig {
+                buffer_packets: 1024,
+                tls_ca_cert: None,
+                tls_server_name: None,
+            },
+            Default::default(),
+            metrics,
+        )
+    }
+
+    /// Context of a forwarder thread without any optional sinks or taps
+    fn new_forwarder_context(
+        dedup_mode: DedupMode,
+        metrics: Arc<ShredMetrics>,
+    ) -> ForwarderContext {
+        ForwarderContext {
+            deduper: Arc::new(ArcSwap::from_pointee(ShredDeduper::new(
+                &mut rand::thread_rng(),
+                crate::forwarder::DEDUPER_NUM_BITS,
+                dedup_mode,
+            ))),
+            source_allowlist: Arc::default(),
+            packet_filter: Arc::default(),
+            udp_sink: UdpSink::new(
+                UdpSocket::bind("127.0.0.1:0").unwrap(),
+                crate::forwarder::DEFAULT_SEND_BATCH_SIZE,
+                None,
+                metrics.clone(),
+            ),
+            quic_sink: Arc::new(QuicSink::new(metrics.clone())),
+            tunnel_sink: Arc::new(new_tunnel_sink(metrics.clone())),
+            unix_sink: Arc::new(UnixSink::new(Default::default(), metrics.clone())),
+            isolated_send_sink: None,
+            spill_store: None,
+            chaos: None,
+            mirror_sink: None,
+            deshred_tap: None,
+            pcap_tap: None,
+            subscriber_tap: None,
+            kafka_tap: None,
+            slot_latency_tap: None,
+            conflict_tap: None,
+            events_tap: None,
+            archive_tap: None,
+            ws_tap: None,
+            region: None,
+            listener: None,
+            trace_shred_sampler: Arc::default(),
+            metrics,
+        }
+    }
+
+    fn listen_and_collect(listen_socket: UdpSocket, received_packets: Arc<Mutex<Vec<Vec<u8>>>>) {
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        loop {
+            listen_socket.recv(&mut buf).unwrap();
+            received_packets.lock().unwrap().push(Vec::from(buf));
+        }
+    }
+
+    #[test]
+    fn test_2shreds_3destinations() {
+        let packet_batch = PacketBatch::new(vec![
+            Packet::new(
+                [1; PACKET_DATA_SIZE],
+                Meta {
+                    size: PACKET_DATA_SIZE,
+                    addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    port: 48289, // received on random port
+                    flags: PacketFlags::empty(),
+                },
+            ),
+            Packet::new(
+                [2; PACKET_DATA_SIZE],
+                Meta {
+                    size: PACKET_DATA_SIZE,
+                    addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                    port: 9999,
+                    flags: PacketFlags::empty(),
+                },
+            ),
+        ]);
+        let (packet_sender, packet_receiver) = crossbeam_channel::unbounded::<ReceivedBatch>();
+        packet_sender.send(packet_batch.into()).unwrap();
+
+        let dest_socketaddrs = vec![
+            SocketAddr::from_str("0.0.0.0:32881").unwrap(),
+            SocketAddr::from_str("0.0.0.0:33881").unwrap(),
+            SocketAddr::from_str("0.0.0.0:34881").unwrap(),
+        ];
+
+        let test_listeners = dest_socketaddrs
+            .iter()
+            .map(|socketaddr| {
+                (
+                    UdpSocket::bind(socketaddr).unwrap(),
+                    *socketaddr,
+                    // store results in vec of packet, where packet is Vec<u8>
+                    Arc::new(Mutex::new(vec![])),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        let udp_sender = UdpSocket::bind("0.0.0.0:10000").unwrap();
+        let metrics = Arc::new(ShredMetrics::new());
+
+        // spawn listeners
+        test_listeners
+            .iter()
+            .for_each(|(listen_socket, _socketaddr, to_receive)| {
+                let socket = listen_socket.try_clone().unwrap();
+                let to_receive = to_receive.to_owned();
+                thread::spawn(move || listen_and_collect(socket, to_receive));
+            });
+
+        // send packets
+        recv_from_channel_and_send_multiple_dest(
+            packet_receiver
+                .recv()
+                .map(|packet_batch| vec![packet_batch]),
+            0.0,
+            &LocalDests {
+                dest_sockets: Arc::new(dest_socketaddrs.clone()),
+                ..Default::default()
+            },
+            &ForwarderContext {
+                udp_sink: UdpSink::new(
+                    udp_sender,
+                    crate::forwarder::DEFAULT_SEND_BATCH_SIZE,
+                    None,
+                    metrics.clone(),
+                ),
+                ..new_forwarder_context(DedupMode::Payload, metrics.clone())
+            },
+        )
+        .unwrap();
+
+        // allow packets to be received
+        sleep(Duration::from_millis(500));
+
+        let received = test_listeners
+            .iter()
+            .map(|(_, _, results)| results.clone())
+            .collect::<Vec<_>>();
+
+        // check results
+        for received in received.iter() {
+            let received = received.lock().unwrap();
+            assert_eq!(received.len(), 2);
+            assert!(received
+                .iter()
+                .all(|packet| packet.len() == PACKET_DATA_SIZE));
+            assert_eq!(received[0], [1; PACKET_DATA_SIZE]);
+            assert_eq!(received[1], [2; PACKET_DATA_SIZE]);
+        }
+
+        assert_eq!(
+            received
+                .iter()
+                .fold(0, |acc, elem| acc + elem.lock().unwrap().len()),
+            6
+        );
+
+        // per-destination counters survive reset and are pruned when destinations go away
+        metrics.reset();
+        assert!(dest_socketaddrs.iter().all(|addr| *metrics
+            .dest_forwarded_cumulative
+            .get(addr)
+            .unwrap()
+            == (2, 0)));
+        metrics.retain_destinations(&dest_socketaddrs[1..]);
+        assert!(!metrics.dest_forwarded.contains_key(&dest_socketaddrs[0]));
+        assert!(!metrics
+            .dest_forwarded_cumulative
+            .contains_key(&dest_socketaddrs[0]));
+        assert_eq!(metrics.dest_forwarded_cumulative.len(), 2);
+    }
+
+    #[test]
+    fn test_udp_sink_mixed_families() {
+        let receivers = ["127.0.0.1:0", "[::1]:0"].map(|addr| {
+            let socket = UdpSocket::bind(addr).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            socket
+        });
+        let metrics = Arc::new(ShredMetrics::new());
+        let udp_sink = UdpSink::new(
+            crate::socket::bind_send_socket().unwrap(),
+            crate::forwarder::DEFAULT_SEND_BATCH_SIZE,
+            None,
+            metrics.clone(),
+        );
+
+        for receiver in &receivers {
+            let dest = receiver.local_addr().unwrap();
+            udp_sink.send(dest, &[b"shred"]);
+            let mut buf = [0; 8];
+            assert_eq!(receiver.recv(&mut buf).unwrap(), 5, "{dest}");
+            // counted under the destination as configured, not the IPv4-mapped address
+            metrics.aggregate_threads();
+            assert_eq!(*metrics.dest_forwarded.get(&dest).unwrap(), (1, 0));
+        }
+    }
+
+    #[test]
+    fn test_udp_sink_dest_egress() {
+        let receivers = [(); 3].map(|_| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            socket
+        });
+        let [default_dest, egress_dest, unbindable_dest] = receivers
+            .each_ref()
+            .map(|socket| socket.local_addr().unwrap());
+        let dest_sources = DestinationSources {
+            static_dest_sockets: vec![
+                (default_dest, default_dest.to_string()),
+                (egress_dest, format!("{egress_dest}?src=127.0.0.2")),
+                // documentation prefix, never assigned to this host
+                (unbindable_dest, format!("{unbindable_dest}?src=192.0.2.1")),
+            ],
+            ..Default::default()
+        };
+        dest_sources.store_union(&ArcSwap::from_pointee(vec![]));
+        assert_eq!(
+            dest_sources
+                .dest_egress
+                .load()
+                .keys()
+                .collect::<HashSet<_>>(),
+            HashSet::from([&egress_dest, &unbindable_dest])
+        );
+
+        let metrics = Arc::new(ShredMetrics::new());
+        let send_socket_options = SendSocketOptions {
+            egress: Egress {
+                interface: None,
+                source_ip: Some(Ipv4Addr::LOCALHOST.into()),
+            },
+            ..Default::default()
+        };
+        let udp_sink = UdpSink::new(
+            send_socket_options.bind().unwrap(),
+            crate::forwarder::DEFAULT_SEND_BATCH_SIZE,
+            None,
+            metrics.clone(),
+        )
+        .with_dest_egress(send_socket_options, dest_sources.dest_egress.clone());
+
+        for (receiver, expected_source) in receivers[..2].iter().zip(["127.0.0.1", "127.0.0.2"]) {
+            let dest = receiver.local_addr().unwrap();
+            udp_sink.send(dest, &[b"shred"]);
+            let mut buf = [0; 8];
+            let (_, source) = receiver.recv_from(&mut buf).unwrap();
+            assert_eq!(source.ip().to_string(), expected_source, "{dest}");
+        }
+        // failing to bind drops the destination's packets, once for every send
+        udp_sink.send(unbindable_dest, &[b"shred", b"shred"]);
+        udp_sink.send(unbindable_dest, &[b"shred"]);
+        metrics.aggregate_threads();
+        assert_eq!(
+            *metrics.dest_forwarded.get(&unbindable_dest).unwrap(),
+            (0, 3)
+        );
+        assert_eq!(*metrics.dest_forwarded.get(&egress_dest).unwrap(), (1, 0));
+    }
+
+    #[test]
+    fn test_recycle_vec() {
+        let mut packets: Vec<&[u8]> = Vec::with_capacity(64);
+        let ptr = packets.as_ptr() as usize;
+        let shred = b"shred".to_vec();
+        packets.push(&shred);
+        let recycled: Vec<&'static [u8]> = recycle_vec(packets);
+        assert!(recycled.is_empty());
+        assert_eq!(recycled.capacity(), 64);
+        assert_eq!(recycled.as_ptr() as usize, ptr);
+    }
+
+    #[test]
+    fn test_forwarder_threads() {
+        let threads = |recv_per_port, send_per_port| ForwarderThreads {
+            recv_per_port,
+            send_per_port,
+        };
+        // send threads follow the destination count between 4 and the core count
+        assert_eq!(
+            ForwarderThreads::with_num_cores(None, None, Some(2), 1, 16),
+            threads(4, 4)
+        );
+        assert_eq!(
+            ForwarderThreads::with_num_cores(None, None, Some(10), 1, 16),
+            threads(4, 10)
+        );
+        assert_eq!(
+            ForwarderThreads::with_num_cores(None, None, Some(100), 1, 16),
+            threads(4, 16)
+        );
+        // runtime destinations use every core, small hosts still get one receive thread
+        assert_eq!(
+            ForwarderThreads::with_num_cores(None, None, None, 1, 2),
+            threads(1, 4)
+        );
+        // explicit counts are split between ports, at least one each
+        assert_eq!(
+            ForwarderThreads::with_num_cores(Some(2), Some(8), None, 4, 16),
+            threads(1, 2)
+        );
+    }
+
+    #[test]
+    fn test_max_buffered_bytes_with_stalled_forwarder() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let batch_bytes = (PACKETS_PER_BATCH * mem::size_of::<Packet>()) as u64;
+        let max_bytes = 4 * batch_bytes;
+        metrics.memory_guard.set_max_bytes(Some(max_bytes));
+        let listen_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let listen_addr = listen_socket.local_addr().unwrap();
+        listen_socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        // nothing receives from the channel, as if every sink stalled
+        let (packet_sender, packet_receiver) =
+            packet_channel::bounded(1024, DropPolicy::Block, metrics.clone());
+        let exit = Arc::new(AtomicBool::new(false));
+        let hdl = start_receive_thread(
+            0,
+            listen_socket,
+            ListenSocketOptions {
+                recv_buffer_bytes: None,
+                dscp: None,
+                rx_timestamps: None,
+                recv_mmsg_batch_size: None,
+                recv_poll_timeout: Duration::from_millis(10),
+            },
+            Arc::new(RebindPolicy::new(100, 10)),
+            None,
+            None,
+            None,
+            packet_sender,
+            Arc::new(StreamerReceiveStats::new("test")),
+            metrics.clone(),
+            exit.clone(),
+        );
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let send_until = |done: &dyn Fn() -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !done() {
+                assert!(Instant::now() < deadline);
+                sender.send_to(&[1u8; 100], listen_addr).unwrap();
+                assert!(metrics.memory_guard.total() <= max_bytes + batch_bytes);
+                sleep(Duration::from_micros(100));
+            }
+        };
+        send_until(&|| metrics.memory_guard_dropped.load(Ordering::Relaxed) >= 100);
+        // the batch that crossed the cap is kept, later ones are dropped
+        assert_eq!(packet_receiver.len(), 5);
+        assert_eq!(
+            metrics.memory_guard.held(BufferKind::Receive),
+            5 * batch_bytes
+        );
+
+        // once the forwarder catches up, buffers are released and packets accepted again
+        drop(packet_receiver.try_iter().collect::<Vec<_>>());
+        assert_eq!(metrics.memory_guard.total(), 0);
+        send_until(&|| !packet_receiver.is_empty());
+
+        exit.store(true, Ordering::Relaxed);
+        hdl.join().unwrap();
+    }
+
+    #[test]
+    fn test_is_trace_sampled() {
+        let shreds = || (0..100).flat_map(|slot| (0..1_000).map(move |index| (slot, index)));
+        let sampled = |rate: f64| {
+            shreds()
+                .filter(|(slot, index)| is_trace_sampled(rate, *slot, *index))
+                .collect::<HashSet<_>>()
+        };
+        assert_eq!(sampled(1.0).len(), 100_000);
+        assert!(sampled(0.0).is_empty());
+
+        // about the requested fraction, the same shreds every time, and a subset of those sampled at higher rates
+        let sampled_low = sampled(0.001);
+        let sampled_high = sampled(0.01);
+        assert!(
+            (50..=150).contains(&sampled_low.len()),
+            "{}",
+            sampled_low.len()
+        );
+        assert!(
+            (800..=1_200).contains(&sampled_high.len()),
+            "{}",
+            sampled_high.len()
+        );
+        assert_eq!(sampled(0.001), sampled_low);
+        assert!(sampled_low.is_subset(&sampled_high));
+    }
+
+    #[test]
+    fn test_coalesce_packet_batches() {
+        let new_batch = |num_packets: usize| {
+            PacketBatch::new(vec![
+                Packet::new([0; PACKET_DATA_SIZE], Meta::default());
+                num_packets
+            ])
+            .into()
+        };
+        let (packet_sender, packet_receiver) = crossbeam_channel::unbounded::<ReceivedBatch>();
+        for _ in 0..4 {
+            packet_sender.send(new_batch(3)).unwrap();
+        }
+
+        // stops once max packets is reached, leaving the rest queued
+        let coalesced = coalesce_packet_batches(new_batch(3), &packet_receiver, 7, Duration::ZERO);
+        assert_eq!(coalesced.len(), 3);
+        assert_eq!(packet_receiver.len(), 2);
+
+        // drains whatever is queued without waiting when linger is zero
+        let coalesced =
+            coalesce_packet_batches(new_batch(1), &packet_receiver, 128, Duration::ZERO);
+        assert_eq!(
+            coalesced
+                .iter()
+                .map(|batch| batch.packets.len())
+                .sum::<usize>(),
+            7
+        );
+        assert!(packet_receiver.is_empty());
+    }
+
+    #[test]
+    fn test_source_allowlist() {
+        let allowlist_file = std::env::temp_dir().join("test_source_allowlist.txt");
+        fs::write(
+            &allowlist_file,
+            "# upstream proxies\n10.1.0.0/16\n\n2001:db8::1 # v6 upstream\n",
+        )
+        .unwrap();
+        let source_allowlist = SourceAllowlist::new(
+            vec!["10.0.0.1/32".parse().unwrap()],
+            Some(allowlist_file.clone()),
+        )
+        .unwrap();
+        let src_ips: [IpAddr; 5] = [
+            [10, 0, 0, 1].into(),
+            [10, 1, 2, 3].into(),
+            [10, 2, 0, 1].into(),
+            "2001:db8::1".parse().unwrap(),
+            // IPv4 sources received on dual-stack sockets
+            "::ffff:10.1.2.3".parse().unwrap(),
+        ];
+        let mut packet_batches = vec![PacketBatch::new(
+            src_ips
+                .iter()
+                .map(|src_ip| {
+                    let mut packet = Packet::new([0; PACKET_DATA_SIZE], Meta::default());
+                    packet.meta_mut().size = 1;
+                    packet.meta_mut().addr = *src_ip;
+                    packet
+                })
+                .collect(),
+        )];
+        assert_eq!(source_allowlist.apply(&mut packet_batches), 1);
+        assert_eq!(
+            packet_batches[0]
+                .iter()
+                .map(|packet| packet.meta().discard())
+                .collect::<Vec<_>>(),
+            vec![false, false, true, false, false]
+        );
+
+        // file changes are picked up on reload, an unreadable file keeps the current allowlist
+        fs::write(&allowlist_file, "10.2.0.0/16\n").unwrap();
+        source_allowlist.reload_file().unwrap();
+        assert!(source_allowlist.is_allowed([10, 2, 0, 1].into()));
+        assert!(!source_allowlist.is_allowed([10, 1, 2, 3].into()));
+        fs::write(&allowlist_file, "not an ip\n").unwrap();
+        assert!(source_allowlist.reload_file().is_err());
+        assert!(source_allowlist.is_allowed([10, 2, 0, 1].into()));
+        fs::remove_file(&allowlist_file).unwrap();
+
+        // allows everything once unset
+        source_allowlist.set(vec![], None).unwrap();
+        assert!(source_allowlist.is_allowed([192, 168, 0, 1].into()));
+    }
+
+    #[test]
+    fn test_record_internal_latency() {
+        let metrics = ShredMetrics::new();
+        let forward_time = SystemTime::now();
+        let mut timestamped = PacketBatch::new(vec![Packet::default(); 3]);
+        timestamped[1].meta_mut().set_discard(true);
+        let rx_timestamps = vec![
+            Some(vec![
+                Some(forward_time - Duration::from_micros(100)),
+                Some(forward_time - Duration::from_micros(900)),
+                None,
+            ]),
+            None,
+        ];
+        metrics.record_internal_latency(
+            &[timestamped, PacketBatch::new(vec![Packet::default()])],
+            &rx_timestamps,
+            forward_time,
+        );
+
+        // discarded packets and packets without a timestamp are skipped
+        let internal_latency_us = metrics.internal_latency_us.lock().unwrap();
+        assert_eq!(internal_latency_us.entries(), 1);
+        assert_eq!(internal_latency_us.percentile(50.0).unwrap(), 100);
+        drop(internal_latency_us);
+        metrics.reset();
+        assert_eq!(metrics.internal_latency_us.lock().unwrap().entries(), 0);
+    }
+
+    #[test]
+    fn test_thread_metrics() {
+        let metrics = ShredMetrics::new();
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let first = metrics.register_thread();
+        let second = metrics.register_thread();
+        first.add_received(3);
+        first.add_udp_forward(dest, 2, 1);
+        second.add_received(5);
+        second.add_duplicate(1);
+        metrics.report();
+        assert_eq!(metrics.agg_received.load(Ordering::Relaxed), 8);
+        assert_eq!(metrics.duplicate.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.agg_success_forward.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.agg_fail_forward.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.udp_fail_forward.load(Ordering::Relaxed), 1);
+        assert_eq!(*metrics.dest_forwarded.get(&dest).unwrap(), (2, 1));
+
+        // counts of a thread that exited before the next report aren't lost, and its restart counts from zero
+        first.add_received(4);
+        first.add_udp_forward(dest, 1, 0);
+        drop(first);
+        let restarted = metrics.register_thread();
+        restarted.add_received(1);
+        metrics.reset();
+        assert_eq!(metrics.agg_received_cumulative.load(Ordering::Relaxed), 13);
+        assert_eq!(
+            *metrics.dest_forwarded_cumulative.get(&dest).unwrap(),
+            (3, 1)
+        );
+        assert_eq!(metrics.threads.lock().unwrap().len(), 2);
+        // nothing is added twice
+        metrics.reset();
+        assert_eq!(metrics.agg_received_cumulative.load(Ordering::Relaxed), 13);
+    }
+
+    #[test]
+    fn test_aggregate_threads_concurrently() {
+        const NUM_THREADS: u64 = 4;
+        const RESTARTS: u64 = 200;
+        let metrics = ShredMetrics::new();
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            // aggregates while threads count and drop their handles, as the accessory thread would
+            let aggregator = scope.spawn(|| {
+                while !done.load(Ordering::Relaxed) {
+                    metrics.aggregate_threads();
+                }
+            });
+            let threads = (0..NUM_THREADS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        for _ in 0..RESTARTS {
+                            let thread_metrics = metrics.register_thread();
+                            for _ in 0..10 {
+                                thread_metrics.add_received(1);
+                                thread_metrics.add_udp_forward(dest, 1, 0);
+                            }
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .for_each(|thread| thread.join().unwrap());
+            done.store(true, Ordering::Relaxed);
+            aggregator.join().unwrap();
+        });
+
+        // counts added right before a handle was dropped are never lost, nor added twice
+        metrics.aggregate_threads();
+        let expected = NUM_THREADS * RESTARTS * 10;
+        assert_eq!(metrics.agg_received.load(Ordering::Relaxed), expected);
+        assert_eq!(
+            metrics.agg_success_forward.load(Ordering::Relaxed),
+            expected
+        );
+        assert_eq!(*metrics.dest_forwarded.get(&dest).unwrap(), (expected, 0));
+        assert!(metrics.threads.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_record_packet_sizes() {
+        let metrics = ShredMetrics::new();
+        let mut batch = PacketBatch::new(vec![Packet::default(); 3]);
+        batch[0].meta_mut().size = 1203;
+        batch[1].meta_mut().size = 1228;
+        // discarded packets were still received
+        batch[2].meta_mut().size = 40;
+        batch[2].meta_mut().set_discard(true);
+        metrics.record_received_sizes(&[batch]);
+        let dest = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        metrics.record_emsgsize(dest, 1228);
+        metrics.record_emsgsize(dest, 1203);
+
+        let received_packet_bytes = metrics.received_packet_bytes.lock().unwrap();
+        assert_eq!(received_packet_bytes.entries(), 3);
+        assert_eq!(received_packet_bytes.minimum().unwrap(), 40);
+        assert_eq!(received_packet_bytes.maximum().unwrap(), 1228);
+        drop(received_packet_bytes);
+        assert_eq!(metrics.dest_emsgsize_bytes.get(&dest).unwrap().entries(), 2);
+
+        metrics.reset();
+        assert_eq!(metrics.received_packet_bytes.lock().unwrap().entries(), 0);
+        assert!(metrics.dest_emsgsize_bytes.is_empty());
+        assert_eq!(*metrics.dest_emsgsize_cumulative.get(&dest).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_record_send_latency() {
+        let metrics = ShredMetrics::new();
+        let dest = SocketAddr::from_str("127.0.0.1:8001").unwrap();
+        let threshold = Duration::from_micros(1_000);
+        for _ in 0..3 {
+            (0..99).for_each(|_| {
+                metrics.record_send_latency(dest, Duration::from_micros(10), threshold)
+            });
+            metrics.record_send_latency(dest, Duration::from_micros(5_000), threshold);
+            metrics.record_send_latency(dest, Duration::from_micros(5_000), threshold);
+            {
+                let send_latency = metrics.dest_send_latency_us.get(&dest).unwrap();
+                let (send_latency_us, slow_sends) = send_latency.value();
+                assert_eq!(send_latency_us.entries(), 101);
+                assert_eq!(send_latency_us.percentile(50.0).unwrap(), 10);
+                assert_eq!(*slow_sends, 2);
+            }
+            metrics.report();
+            metrics.reset();
+        }
+
+        // slow intervals are counted in a row, sends are cleared each interval
+        assert_eq!(*metrics.dest_slow_send_intervals.get(&dest).unwrap(), 3);
+        assert_eq!(*metrics.dest_slow_sends_cumulative.get(&dest).unwrap(), 6);
+        assert!(metrics.dest_send_latency_us.is_empty());
+        metrics.record_send_latency(dest, Duration::from_micros(10), threshold);
+        metrics.report();
+        assert!(metrics.dest_slow_send_intervals.is_empty());
+    }
+
+    #[test]
+    fn test_slot_coverage_reset() {
+        let metrics = ShredMetrics::new();
+        let seed_shreds = vec![new_data_shred(10, 0, 0, false, &[]); HIGHEST_SLOT_SEED_SAMPLES];
+        let shreds = [(10, 0), (10, 1), (11, 0)]
+            .map(|(slot, index)| new_data_shred(slot, index, 0, false, &[]));
+        metrics.slot_coverage.send(
+            &seed_shreds
+                .iter()
+                .chain(&shreds)
+                .map(Vec::as_slice)
+                .collect::<Vec<_>>(),
+        );
+        // shreds still queued for the accessory thread are counted before the stats are read,
+        // except those sampled to seed the highest slot seen
+        assert_eq!(metrics.slot_coverage.stats().slots_observed, 2);
+        metrics.slot_coverage.record(
+            &[CoverageShred::parse(&new_data_shred(100, 0, 0, false, &[])).unwrap()],
+            Instant::now() + Duration::from_millis(90 * DEFAULT_MS_PER_SLOT),
+        );
+
+        // slots 10 and 11 fell behind slot 100, so are finalized
+        metrics.reset();
+        assert_eq!(
+            metrics.slots_finalized_cumulative.load(Ordering::Relaxed),
+            2
+        );
+        assert_eq!(
+            metrics
+                .slots_last_shred_seen_cumulative
+                .load(Ordering::Relaxed),
+            0
+        );
+        assert_eq!(
+            metrics.last_interval_slots_observed.load(Ordering::Relaxed),
+            3
+        );
+        assert_eq!(
+            [
+                &metrics.last_interval_slot_data_shreds_min,
+                &metrics.last_interval_slot_data_shreds_median,
+                &metrics.last_interval_slot_data_shreds_max,
+            ]
+            .map(|gauge| gauge.load(Ordering::Relaxed)),
+            [1, 2, 2]
+        );
+
+        // an interval without shreds clears the gauges, keeping the totals
+        metrics.reset();
+        assert_eq!(
+            metrics.slots_finalized_cumulative.load(Ordering::Relaxed),
+            2
+        );
+        assert_eq!(
+            metrics.last_interval_slots_observed.load(Ordering::Relaxed),
+            0
+        );
+        assert_eq!(
+            metrics
+                .last_interval_slot_data_shreds_max
+                .load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_resolve_static_destinations_keeps_last_known() {
+        let stale = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let mut static_dest_sockets = vec![
+            (stale, "127.0.0.1:8001".to_string()),
+            (stale, "unresolvable.invalid:8001".to_string()),
+        ];
+
+        resolve_static_destinations(&mut static_dest_sockets, AddressFamily::Any);
+
+        assert_eq!(
+            static_dest_sockets[0].0,
+            SocketAddr::from_str("127.0.0.1:8001").unwrap()
+        );
+        assert_eq!(static_dest_sockets[1].0, stale);
+    }
+
+    #[test]
+    fn test_highest_slot() {
+        let highest_slot = HighestSlot::default();
+        let start = highest_slot.created_at;
+        // seeded from the first slots, ignoring a spoofed one far ahead of the rest
+        let mut seed_slots = vec![1_000; HIGHEST_SLOT_SEED_SAMPLES];
+        seed_slots[0] = u64::MAX - 1;
+        for slot in seed_slots {
+            assert_eq!(highest_slot.observe(slot, start), None);
+        }
+        assert_eq!(highest_slot.observe(990, start), Some(1_000));
+        assert_eq!(
+            highest_slot.observe(1_000 + MAX_SLOTS_AHEAD_OF_CLOCK, start),
+            Some(1_000 + MAX_SLOTS_AHEAD_OF_CLOCK)
+        );
+
+        // outliers are ignored until the slot clock catches up with them
+        let outlier = 1_000 + MAX_SLOTS_AHEAD_OF_CLOCK + 10;
+        assert_eq!(
+            highest_slot.observe(outlier, start),
+            Some(1_000 + MAX_SLOTS_AHEAD_OF_CLOCK)
+        );
+        assert_eq!(
+            highest_slot.observe(u64::MAX, start),
+            Some(1_000 + MAX_SLOTS_AHEAD_OF_CLOCK)
+        );
+        let caught_up = start + Duration::from_millis(10 * DEFAULT_MS_PER_SLOT);
+        assert_eq!(highest_slot.observe(outlier, caught_up), Some(outlier));
+
+        // reseeded once it stops advancing, recovering from a bad seed
+        let stalled = caught_up + HIGHEST_SLOT_RESEED_AFTER;
+        for slot in 500..500 + HIGHEST_SLOT_SEED_SAMPLES as u64 {
+            assert_eq!(highest_slot.observe(slot, stalled), None);
+        }
+        assert_eq!(highest_slot.observe(500, stalled), Some(507));
+    }
+
+    #[test]
+    fn test_packet_filter() {
+        let new_packet = |variant: u8, slot: u64| {
+            let mut data = [0u8; PACKET_DATA_SIZE];
+            data[64] = variant;
+            data[65..73].copy_from_slice(&slot.to_le_bytes());
+            Packet::new(
+                data,
+                Meta {
+                    size: PACKET_DATA_SIZE,
+                    ..Meta::default()
+                },
+            )
+        };
+        let metrics = ShredMetrics::new();
+        let packet_filter = PacketFilter::new(Some(10), true, ForwardShredTypes::All);
+        // nothing is stale while the highest slot seen is seeded
+        let mut packet_batches = vec![PacketBatch::new(vec![
+            new_packet(0xa5, 100);
+            HIGHEST_SLOT_SEED_SAMPLES
+        ])];
+        packet_filter.apply(&mut packet_batches, &metrics);
+        assert!(packet_batches[0]
+            .iter()
+            .all(|packet| !packet.meta().discard()));
+
+        let mut packet_batches = vec![PacketBatch::new(vec![
+            new_packet(0xa5, 100),
+            new_packet(0xa5, 95),
+            new_packet(0xa5, 89),
+            new_packet(0x00, 100), // not a shred
+        ])];
+        packet_filter.apply(&mut packet_batches, &metrics);
+
+        let discarded = packet_batches[0]
+            .iter()
+            .map(|packet| packet.meta().discard())
+            .collect::<Vec<_>>();
+        assert_eq!(discarded, vec![false, false, true, true]);
+        assert_eq!(metrics.stale_slot_dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.non_shred_dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(metrics.received_invalid.load(Ordering::Relaxed), 1);
+
+        // a spoofed slot far ahead doesn't advance the highest slot, even when it's the first one received,
+        // so it can't make everything else stale
+        let mut packets = vec![new_packet(0xa5, u64::MAX - 1)];
+        packets.extend(vec![new_packet(0xa5, 100); HIGHEST_SLOT_SEED_SAMPLES - 1]);
+        packets.extend([
+            new_packet(0xa5, u64::MAX - 1),
+            new_packet(0xa5, 95),
+            new_packet(0xa5, 89),
+        ]);
+        let mut packet_batches = vec![PacketBatch::new(packets)];
+        let spoofed_metrics = ShredMetrics::new();
+        PacketFilter::new(Some(10), false, ForwardShredTypes::All)
+            .apply(&mut packet_batches, &spoofed_metrics);
+        let discarded = packet_batches[0]
+            .iter()
+            .map(|packet| packet.meta().discard())
+            .collect::<Vec<_>>();
+        let mut expected_discarded = vec![false; HIGHEST_SLOT_SEED_SAMPLES + 2];
+        expected_discarded.push(true);
+        assert_eq!(discarded, expected_discarded);
+        assert_eq!(
+            spoofed_metrics.stale_slot_dropped.load(Ordering::Relaxed),
+            1
+        );
+
+        // forwarded non-shred packets are still counted as invalid, and their sources logged at most once per interval
+        let packet_filter = PacketFilter::new(Some(10), false, ForwardShredTypes::All);
+        let mut packet_batches = vec![PacketBatch::new(vec![new_packet(0x00, 0)])];
+        packet_filter.apply(&mut packet_batches, &metrics);
+        assert!(!packet_batches[0][0].meta().discard());
+        assert_eq!(metrics.received_invalid.load(Ordering::Relaxed), 2);
+        let next_log_ms = packet_filter
+            .next_invalid_source_log_ms
+            .load(Ordering::Relaxed);
+        assert!(next_log_ms >= INVALID_SOURCE_LOG_INTERVAL.as_millis() as u64);
+        packet_filter.apply(&mut packet_batches, &metrics);
+        assert_eq!(
+            packet_filter
+                .next_invalid_source_log_ms
+                .load(Ordering::Relaxed),
+            next_log_ms
+        );
+
+        // shred type filter counts each type separately, leaving non-shred packets to their own flag
+        for (forward_shred_types, expected_discarded, expected_data, expected_code) in [
+            (
+                ForwardShredTypes::Data,
+                vec![false, true, true, false],
+                0,
+                2,
+            ),
+            (
+                ForwardShredTypes::Code,
+                vec![true, false, false, false],
+                1,
+                0,
+            ),
+        ] {
+            let metrics = ShredMetrics::new();
+            let mut packet_batches = vec![PacketBatch::new(vec![
+                new_packet(0xa5, 100),
+                new_packet(0x5a, 100),
+                new_packet(0x46, 100), // merkle code
+                new_packet(0x00, 100),
+            ])];
+            PacketFilter::new(None, false, forward_shred_types)
+                .apply(&mut packet_batches, &metrics);
+            let discarded = packet_batches[0]
+                .iter()
+                .map(|packet| packet.meta().discard())
+                .collect::<Vec<_>>();
+            assert_eq!(discarded, expected_discarded, "{forward_shred_types:?}");
+            assert_eq!(
+                metrics.data_shred_filtered.load(Ordering::Relaxed),
+                expected_data
+            );
+            assert_eq!(
+                metrics.code_shred_filtered.load(Ordering::Relaxed),
+                expected_code
+            );
+            assert_eq!(metrics.non_shred_dropped.load(Ordering::Relaxed), 0);
+            assert_eq!(metrics.received_invalid.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    fn new_dedup_packet(i: u64) -> Packet {
+        let mut data = [0u8; PACKET_DATA_SIZE];
+        data[..8].copy_from_slice(&i.to_le_bytes());
+        Packet::new(
+            data,
+            Meta {
+                size: 8,
+                ..Meta::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_shred_deduper_reset() {
+        let mut rng = rand::thread_rng();
+        let deduper =
+            ArcSwap::from_pointee(ShredDeduper::new(&mut rng, 1 << 16, DedupMode::Payload));
+
+        let mut packet_batches = vec![PacketBatch::new((0..64).map(new_dedup_packet).collect())];
+        assert_eq!(deduper.load().dedup_packets(&mut packet_batches), 0);
+        let mut packet_batches = vec![PacketBatch::new((0..64).map(new_dedup_packet).collect())];
+        assert_eq!(deduper.load().dedup_packets(&mut packet_batches), 64);
+        // ~1 - e^(-2 * 64 / 2^16) of bits set
+        assert!((deduper.load().estimated_saturation() - 0.00195).abs() < 0.00001);
+
+        // not saturated or old enough
+        assert!(!maybe_reset_deduper(
+            &deduper,
+            &mut rng,
+            0.5,
+            Duration::from_secs(60)
+        ));
+        // saturated, ahead of schedule
+        assert!(deduper
+            .load()
+            .needs_early_reset(0.000001, Duration::from_secs(60)));
+        assert!(maybe_reset_deduper(
+            &deduper,
+            &mut rng,
+            0.000001,
+            Duration::from_secs(60)
+        ));
+        assert_eq!(deduper.load().estimated_false_positive_rate(), 0.0);
+        let mut packet_batches = vec![PacketBatch::new((0..64).map(new_dedup_packet).collect())];
+        assert_eq!(deduper.load().dedup_packets(&mut packet_batches), 0);
+        // old enough, on schedule
+        assert!(!deduper.load().needs_early_reset(0.5, Duration::ZERO));
+        assert!(maybe_reset_deduper(&deduper, &mut rng, 0.5, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_dedup_stats() {
+        let metrics = ShredMetrics::new();
+        let deduper = ShredDeduper::new(&mut rand::thread_rng(), 1 << 16, DedupMode::Payload);
+        let mut packet_batches = vec![PacketBatch::new((0..64).map(new_dedup_packet).collect())];
+        deduper.dedup_packets(&mut packet_batches);
+        metrics.record_deduper(&deduper);
+        assert_eq!(
+            metrics.deduper_saturation_ppm.load(Ordering::Relaxed),
+            1_951
+        );
+
+        metrics.agg_received.store(400, Ordering::Relaxed);
+        metrics.duplicate.store(100, Ordering::Relaxed);
+        metrics.deduper_early_resets.store(1, Ordering::Relaxed);
+        metrics.reset();
+        assert_eq!(
+            metrics.last_interval_duplicate_ppm.load(Ordering::Relaxed),
+            250_000
+        );
+        assert_eq!(
+            metrics
+                .deduper_early_resets_cumulative
+                .load(Ordering::Relaxed),
+            1
+        );
+        // an interval without packets has no duplicates
+        metrics.reset();
+        assert_eq!(
+            metrics.last_interval_duplicate_ppm.load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn test_dedup_by_shred_id() {
+        let mut rng = rand::thread_rng();
+        let deduper =
+            ArcSwap::from_pointee(ShredDeduper::new(&mut rng, 1 << 16, DedupMode::ShredId));
+        let packet = |data: &[u8]| {
+            let mut packet = Packet::default();
+            packet.buffer_mut()[..data.len()].copy_from_slice(data);
+            packet.meta_mut().size = data.len();
+            packet
+        };
+        let shred = new_data_shred(42, 7, 0, false, b"entries");
+        let conflicting = new_data_shred(42, 7, 0, false, b"other entries");
+        let mut packet_batches = vec![PacketBatch::new(vec![
+            packet(&shred),
+            packet(&conflicting),
+            packet(&new_data_shred(42, 8, 0, false, b"entries")),
+            packet(b"not a shred"),
+            packet(b"not a shred"),
+            packet(b"not a shred either"),
+        ])];
+
+        // the conflicting copy shares the shred id, packets that aren't shreds are deduped by payload
+        let mut duplicates = vec![];
+        assert_eq!(
+            deduper
+                .load()
+                .dedup_packets_with(&mut packet_batches, |duplicate| {
+                    duplicates.push(duplicate.data(..).unwrap().to_vec())
+                }),
+            2
+        );
+        assert_eq!(duplicates, vec![conflicting, b"not a shred".to_vec()]);
+        assert_eq!(
+            packet_batches[0]
+                .iter()
+                .map(|packet| packet.meta().discard())
+                .collect::<Vec<_>>(),
+            [false, true, false, false, true, false]
+        );
+
+        // a fresh deduper keeps the mode
+        assert!(maybe_reset_deduper(&deduper, &mut rng, 0.5, Duration::ZERO));
+        assert_eq!(deduper.load().mode(), DedupMode::ShredId);
+    }
+
+    #[test]
+    fn test_conflicts_detected_when_deduping_by_shred_id() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let exit = Arc::new(AtomicBool::new(false));
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(1);
+        let (conflict_tap, conflict_hdl) = conflicts::start_conflict_detector_thread(
+            ConflictDetectorConfig {
+                max_slots: 4,
+                payload_dir: None,
+            },
+            metrics.clone(),
+            shutdown_receiver,
+            exit.clone(),
+        )
+        .unwrap();
+        let packet = |data: Vec<u8>| {
+            let mut packet = Packet::default();
+            packet.buffer_mut()[..data.len()].copy_from_slice(&data);
+            packet.meta_mut().size = data.len();
+            packet
+        };
+        let packets = vec![
+            packet(new_data_shred(42, 7, 0, false, b"entries")),
+            packet(new_data_shred(42, 7, 0, false, b"other entries")),
+        ];
+
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests::default(),
+            &ForwarderContext {
+                conflict_tap: Some(conflict_tap.clone()),
+                ..new_forwarder_context(DedupMode::ShredId, metrics.clone())
+            },
+        )
+        .unwrap();
+
+        // only the first copy is forwarded, but the detector sees both
+        metrics.aggregate_threads();
+        assert_eq!(metrics.duplicate.load(Ordering::Relaxed), 1);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while metrics.conflicting_shreds.load(Ordering::Relaxed) == 0 && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(metrics.conflicting_shreds.load(Ordering::Relaxed), 1);
+        exit.store(true, Ordering::Relaxed);
+        shutdown_sender.send(()).unwrap();
+        conflict_hdl.join().unwrap();
+    }
+
+    #[test]
+    fn test_dedup_during_deduper_swap() {
+        const NUM_PACKETS: u64 = 2_000;
+        const NUM_THREADS: usize = 4;
+        const NUM_PASSES: usize = 20;
+        let deduper = Arc::new(ArcSwap::from_pointee(ShredDeduper::new(
+            &mut rand::thread_rng(),
+            1 << 24,
+            DedupMode::Payload,
+        )));
+        let swapping = Arc::new(AtomicBool::new(true));
+        let num_swaps = Arc::new(AtomicU64::new(0));
+
+        let swap_hdl = {
+            let deduper = deduper.clone();
+            let swapping = swapping.clone();
+            let num_swaps = num_swaps.clone();
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                while swapping.load(Ordering::Relaxed) {
+                    assert!(maybe_reset_deduper(&deduper, &mut rng, 0.5, Duration::ZERO));
+                    num_swaps.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        };
+
+        // each thread dedups every packet, counting how often each was considered new
+        let dedup_hdls = (0..NUM_THREADS)
+            .map(|_| {
+                let deduper = deduper.clone();
+                thread::spawn(move || {
+                    let mut num_new = vec![0usize; NUM_PACKETS as usize];
+                    for _ in 0..NUM_PASSES {
+                        for chunk in (0..NUM_PACKETS).collect::<Vec<_>>().chunks(64) {
+                            let mut packet_batches = vec![PacketBatch::new(
+                                chunk.iter().copied().map(new_dedup_packet).collect(),
+                            )];
+                            deduper.load().dedup_packets(&mut packet_batches);
+                            packet_batches[0]
+                                .iter()
+                                .zip(chunk)
+                                .filter(|(packet, _)| !packet.meta().discard())
+                                .for_each(|(_, i)| num_new[*i as usize] += 1);
+                        }
+                    }
+                    num_new
+                })
+            })
+            .collect::<Vec<_>>();
+        let num_new = dedup_hdls.into_iter().map(|hdl| hdl.join().unwrap()).fold(
+            vec![0usize; NUM_PACKETS as usize],
+            |mut total, num_new| {
+                total.iter_mut().zip(num_new).for_each(|(x, y)| *x += y);
+                total
+            },
+        );
+        swapping.store(false, Ordering::Relaxed);
+        swap_hdl.join().unwrap();
+
+        // a packet is new at most once per deduper, and never dropped without being seen
+        let num_dedupers = num_swaps.load(Ordering::Relaxed) as usize + 1;
+        assert!(num_swaps.load(Ordering::Relaxed) > 0);
+        assert!(num_new
+            .iter()
+            .all(|x| *x >= 1 && *x <= num_dedupers.min(NUM_THREADS * NUM_PASSES)));
+
+        // no swaps, so every packet is a duplicate after being seen once
+        let mut packet_batches = vec![PacketBatch::new(
+            (0..NUM_PACKETS).map(new_dedup_packet).collect(),
+        )];
+        deduper.load().dedup_packets(&mut packet_batches);
+        let mut packet_batches = vec![PacketBatch::new(
+            (0..NUM_PACKETS).map(new_dedup_packet).collect(),
+        )];
+        assert_eq!(
+            deduper.load().dedup_packets(&mut packet_batches),
+            NUM_PACKETS
+        );
+    }
+
+    #[test]
+    fn test_store_union_tracks_rate_limits() {
+        let limited_dest = SocketAddr::from_str("127.0.0.1:8001").unwrap();
+        let unlimited_dest = SocketAddr::from_str("127.0.0.1:8002").unwrap();
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![
+                (limited_dest, format!("{limited_dest}?rate=100pps")),
+                (unlimited_dest, unlimited_dest.to_string()),
+            ],
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![limited_dest, unlimited_dest]
+        );
+        let rate_limits = dest_sources.dest_rate_limits.load_full();
+        assert_eq!(rate_limits.len(), 1);
+        assert_eq!(rate_limits[&limited_dest].rate_pps(), 100);
+
+        // buckets are kept while the limit is unchanged
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert!(Arc::ptr_eq(
+            &rate_limits[&limited_dest],
+            &dest_sources.dest_rate_limits.load()[&limited_dest]
+        ));
+
+        dest_sources.static_dest_sockets[0].1 = format!("{limited_dest}?rate=200pps");
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            dest_sources.dest_rate_limits.load()[&limited_dest].rate_pps(),
+            200
+        );
+    }
+
+    #[test]
+    fn test_send_drops_packets_over_rate_limit() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dest_addr = dest.local_addr().unwrap();
+        let packets = (0..10u8)
+            .map(|i| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[0] = i;
+                packet.meta_mut().size = 1;
+                packet
+            })
+            .collect::<Vec<_>>();
 
-    /// resets current values, increments cumulative values
-    pub fn reset(&self) {
-        self.agg_received_cumulative.fetch_add(
-            self.agg_received.swap(0, Ordering::Relaxed),
-            Ordering::Relaxed,
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests {
+                dest_sockets: Arc::new(vec![dest_addr]),
+                dest_rate_limits: Arc::new(HashMap::from([(
+                    dest_addr,
+                    Arc::new(RateLimiter::new(4, Instant::now())),
+                )])),
+                ..Default::default()
+            },
+            &new_forwarder_context(DedupMode::Payload, metrics.clone()),
+        )
+        .unwrap();
+
+        assert_eq!(*metrics.dest_throttled.get(&dest_addr).unwrap(), 6);
+        metrics.reset();
+        assert_eq!(
+            *metrics.dest_throttled_cumulative.get(&dest_addr).unwrap(),
+            6
         );
-        self.agg_success_forward_cumulative.fetch_add(
-            self.agg_success_forward.swap(0, Ordering::Relaxed),
-            Ordering::Relaxed,
+    }
+
+    #[test]
+    fn test_store_union_orders_by_priority() {
+        let low_dest = SocketAddr::from_str("127.0.0.1:8001").unwrap();
+        let normal_dest = SocketAddr::from_str("127.0.0.1:8002").unwrap();
+        let high_dest = SocketAddr::from_str("127.0.0.1:8003").unwrap();
+        let dest_sources = DestinationSources {
+            static_dest_sockets: vec![
+                (low_dest, format!("{low_dest}?priority=low")),
+                (normal_dest, normal_dest.to_string()),
+                (high_dest, format!("{high_dest}?priority=high")),
+            ],
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![high_dest, normal_dest, low_dest]
         );
-        self.agg_fail_forward_cumulative.fetch_add(
-            self.agg_fail_forward.swap(0, Ordering::Relaxed),
-            Ordering::Relaxed,
+        // normal priority is the default, so isn't tracked
+        assert_eq!(
+            **dest_sources.dest_priorities.load(),
+            HashMap::from([
+                (low_dest, DestPriority::Low),
+                (high_dest, DestPriority::High)
+            ])
         );
-        self.duplicate_cumulative
-            .fetch_add(self.duplicate.swap(0, Ordering::Relaxed), Ordering::Relaxed);
-        self.packets_received.alter_all(|_ip, _metrics| (0, 0))
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
-        str::FromStr,
-        sync::{Arc, Mutex, RwLock},
-        thread,
-        thread::sleep,
-        time::Duration,
-    };
+    #[test]
+    fn test_send_sheds_low_priority_under_backlog() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let high_dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        high_dest
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let high_addr = high_dest.local_addr().unwrap();
+        let low_addr = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        let packets = (0..3u8)
+            .map(|i| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[0] = i;
+                packet.meta_mut().size = 1;
+                packet
+            })
+            .collect::<Vec<_>>();
 
-    use solana_perf::{
-        deduper::Deduper,
-        packet::{Meta, Packet, PacketBatch},
-    };
-    use solana_sdk::packet::{PacketFlags, PACKET_DATA_SIZE};
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.5,
+            &LocalDests {
+                dest_sockets: Arc::new(vec![high_addr, low_addr]),
+                dest_priorities: Arc::new(HashMap::from([
+                    (high_addr, DestPriority::High),
+                    (low_addr, DestPriority::Low),
+                ])),
+                ..Default::default()
+            },
+            &new_forwarder_context(DedupMode::Payload, metrics.clone()),
+        )
+        .unwrap();
+
+        let mut buf = [0u8; 16];
+        for expected in 0..3u8 {
+            assert_eq!(high_dest.recv(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], expected);
+        }
+        let dropped = |priority| metrics.priority_dropped(priority).0.load(Ordering::Relaxed);
+        assert_eq!(dropped(DestPriority::Low), 3);
+        assert_eq!(dropped(DestPriority::High), 0);
+        metrics.reset();
+        assert_eq!(
+            metrics
+                .priority_dropped(DestPriority::Low)
+                .1
+                .load(Ordering::Relaxed),
+            3
+        );
+    }
 
-    use crate::forwarder::{recv_from_channel_and_send_multiple_dest, ShredMetrics};
+    #[test]
+    fn test_send_from_isolated_send_threads() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        dest.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let dest_addr = dest.local_addr().unwrap();
+        let packets = (0..10u8)
+            .map(|i| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[0] = i;
+                packet.meta_mut().size = 1;
+                packet
+            })
+            .collect::<Vec<_>>();
+        let isolated_send_sink = Arc::new(IsolatedSendSink::new(
+            16,
+            SendSocketOptions::default(),
+            crate::forwarder::DEFAULT_SEND_BATCH_SIZE,
+            None,
+            Arc::new(RebindPolicy::new(100, 10)),
+            metrics.clone(),
+        ));
 
-    fn listen_and_collect(listen_socket: UdpSocket, received_packets: Arc<Mutex<Vec<Vec<u8>>>>) {
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests {
+                dest_sockets: Arc::new(vec![dest_addr]),
+                dest_rate_limits: Arc::new(HashMap::from([(
+                    dest_addr,
+                    Arc::new(RateLimiter::new(4, Instant::now())),
+                )])),
+                ..Default::default()
+            },
+            &ForwarderContext {
+                isolated_send_sink: Some(isolated_send_sink.clone()),
+                ..new_forwarder_context(DedupMode::Payload, metrics.clone())
+            },
+        )
+        .unwrap();
+
+        // the destination's thread sends the packets allowed by its rate limit
         let mut buf = [0u8; PACKET_DATA_SIZE];
-        loop {
-            listen_socket.recv(&mut buf).unwrap();
-            received_packets.lock().unwrap().push(Vec::from(buf));
+        for i in 0..4u8 {
+            assert_eq!(dest.recv(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], i);
         }
+        dest.set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(dest.recv(&mut buf).is_err());
+        metrics.aggregate_threads();
+        assert_eq!(*metrics.dest_forwarded.get(&dest_addr).unwrap(), (4, 0));
     }
 
+    #[cfg(unix)]
     #[test]
-    fn test_2shreds_3destinations() {
-        let packet_batch = PacketBatch::new(vec![
-            Packet::new(
-                [1; PACKET_DATA_SIZE],
-                Meta {
-                    size: PACKET_DATA_SIZE,
-                    addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                    port: 48289, // received on random port
-                    flags: PacketFlags::empty(),
-                },
-            ),
-            Packet::new(
-                [2; PACKET_DATA_SIZE],
-                Meta {
-                    size: PACKET_DATA_SIZE,
-                    addr: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-                    port: 9999,
-                    flags: PacketFlags::empty(),
-                },
-            ),
-        ]);
-        let (packet_sender, packet_receiver) = crossbeam_channel::unbounded::<PacketBatch>();
-        packet_sender.send(packet_batch).unwrap();
+    fn test_send_to_unix_and_udp_destinations() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let udp_dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp_dest
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let udp_dest_addr = udp_dest.local_addr().unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "test_send_to_unix_and_udp_destinations_{}.sock",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let unix_dest = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+        let unix_dest_addr = crate::unix::unix_dest_addr(&UnixDest::Path(path.clone()));
+        let unix_dests = HashMap::from([(unix_dest_addr, UnixDest::Path(path.clone()))]);
+        let packets = (0..3u8)
+            .map(|i| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[0] = i;
+                packet.meta_mut().size = 1;
+                packet
+            })
+            .collect::<Vec<_>>();
 
-        let dest_socketaddrs = vec![
-            SocketAddr::from_str("0.0.0.0:32881").unwrap(),
-            SocketAddr::from_str("0.0.0.0:33881").unwrap(),
-            SocketAddr::from_str("0.0.0.0:34881").unwrap(),
-        ];
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests {
+                dest_sockets: Arc::new(vec![udp_dest_addr, unix_dest_addr]),
+                unix_dests: Arc::new(unix_dests.clone()),
+                ..Default::default()
+            },
+            &ForwarderContext {
+                unix_sink: Arc::new(UnixSink::new(
+                    Arc::new(ArcSwap::from_pointee(unix_dests.clone())),
+                    metrics.clone(),
+                )),
+                ..new_forwarder_context(DedupMode::Payload, metrics.clone())
+            },
+        )
+        .unwrap();
 
-        let test_listeners = dest_socketaddrs
-            .iter()
-            .map(|socketaddr| {
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        for i in 0..3u8 {
+            assert_eq!(udp_dest.recv(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], i);
+            assert_eq!(unix_dest.recv(&mut buf).unwrap(), 1);
+            assert_eq!(buf[0], i);
+        }
+        metrics.aggregate_threads();
+        for dest_addr in [udp_dest_addr, unix_dest_addr] {
+            assert_eq!(*metrics.dest_forwarded.get(&dest_addr).unwrap(), (3, 0));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_send_to_enc_destinations() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let key_path = std::env::temp_dir().join(format!(
+            "test_send_to_enc_destinations_{}.key",
+            std::process::id()
+        ));
+        std::fs::write(&key_path, "11".repeat(32)).unwrap();
+        let enc_dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        enc_dest
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let enc_dest_addr = enc_dest.local_addr().unwrap();
+        let unkeyed_dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        unkeyed_dest
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        let unkeyed_dest_addr = unkeyed_dest.local_addr().unwrap();
+        let dest_sources = DestinationSources {
+            static_dest_sockets: vec![
                 (
-                    UdpSocket::bind(socketaddr).unwrap(),
-                    *socketaddr,
-                    // store results in vec of packet, where packet is Vec<u8>
-                    Arc::new(Mutex::new(vec![])),
-                )
+                    enc_dest_addr,
+                    format!("enc://{enc_dest_addr}?key-file={}", key_path.display()),
+                ),
+                (
+                    unkeyed_dest_addr,
+                    format!("enc://{unkeyed_dest_addr}?key-file=/nonexistent/peer.key"),
+                ),
+            ],
+            ..Default::default()
+        };
+        dest_sources.store_union(&ArcSwap::from_pointee(vec![]));
+        let enc_dests = dest_sources.enc_dests.load_full();
+        assert_eq!(enc_dests.len(), 2);
+        let packets = (0..3u8)
+            .map(|i| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[..100].fill(i);
+                packet.meta_mut().size = 100;
+                packet
             })
             .collect::<Vec<_>>();
 
-        let udp_sender = UdpSocket::bind("0.0.0.0:10000").unwrap();
-
-        // spawn listeners
-        test_listeners
-            .iter()
-            .for_each(|(listen_socket, _socketaddr, to_receive)| {
-                let socket = listen_socket.try_clone().unwrap();
-                let to_receive = to_receive.to_owned();
-                thread::spawn(move || listen_and_collect(socket, to_receive));
-            });
-
-        // send packets
         recv_from_channel_and_send_multiple_dest(
-            packet_receiver.recv(),
-            &Arc::new(RwLock::new(Deduper::<2, [u8]>::new(
-                &mut rand::thread_rng(),
-                crate::forwarder::DEDUPER_NUM_BITS,
-            ))),
-            &udp_sender,
-            &Arc::new(dest_socketaddrs),
-            false,
-            &Arc::new(ShredMetrics::new()),
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests {
+                dest_sockets: Arc::new(vec![enc_dest_addr, unkeyed_dest_addr]),
+                enc_dests: enc_dests.clone(),
+                ..Default::default()
+            },
+            &new_forwarder_context(DedupMode::Payload, metrics.clone()),
         )
         .unwrap();
 
-        // allow packets to be received
-        sleep(Duration::from_millis(500));
+        let key_file = dest_sources.encryption_keys.key_file(&key_path);
+        let mut sealed = [0u8; MAX_SEALED_PACKET_SIZE];
+        let mut packet = [0u8; PACKET_DATA_SIZE];
+        for i in 0..3u8 {
+            let len = enc_dest.recv(&mut sealed).unwrap();
+            assert_eq!(len, 100 + encryption::NONCE_LEN + encryption::TAG_LEN);
+            assert_eq!(key_file.open(&sealed[..len], &mut packet), Some(100));
+            assert_eq!(packet[..100], [i; 100]);
+        }
+        metrics.aggregate_threads();
+        assert_eq!(*metrics.dest_forwarded.get(&enc_dest_addr).unwrap(), (3, 0));
+        // never sent unsealed without a key
+        assert!(unkeyed_dest.recv(&mut sealed).is_err());
+        assert_eq!(metrics.encrypt_dropped.load(Ordering::Relaxed), 3);
+        std::fs::remove_file(&key_path).unwrap();
+    }
 
-        let received = test_listeners
-            .iter()
-            .map(|(_, _, results)| results.clone())
+    #[test]
+    fn test_store_union_tracks_shard_groups() {
+        let static_dest = SocketAddr::from_str("127.0.0.1:8001").unwrap();
+        let discovered = (9000..9003)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
             .collect::<Vec<_>>();
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![(static_dest, static_dest.to_string())],
+            discovered_dest_sockets: discovered.clone(),
+            shard_group_specs: vec![ShardGroupSpec {
+                members: ShardMembers::Discovered,
+                shard_by: ShardBy::Slot,
+            }],
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
 
-        // check results
-        for received in received.iter() {
-            let received = received.lock().unwrap();
-            assert_eq!(received.len(), 2);
-            assert!(received
+        // discovered endpoints get their share instead of every shred
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(**unioned_dest_sockets.load(), vec![static_dest]);
+        assert_eq!(
+            **dest_sources.shard_groups.load(),
+            vec![ShardGroup {
+                members: discovered.clone(),
+                shard_by: ShardBy::Slot,
+            }]
+        );
+        // still health checked and listed
+        assert_eq!(dest_sources.union().len(), 4);
+
+        dest_sources.unhealthy_dest_sockets.insert(discovered[0]);
+        dest_sources.discovered_dest_sockets.push(static_dest);
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert!(unioned_dest_sockets.load().is_empty());
+        assert_eq!(
+            dest_sources.shard_groups.load()[0].members,
+            vec![discovered[1], discovered[2], static_dest]
+        );
+    }
+
+    #[test]
+    fn test_store_union_blocks_destinations() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let allowed = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let blocked = SocketAddr::from_str("10.1.0.1:8001").unwrap();
+        let self_loopback = SocketAddr::from_str("127.0.0.1:20000").unwrap();
+        let self_public = SocketAddr::from_str("[::ffff:203.0.113.1]:20000").unwrap();
+        let mut dest_blocklist = DestinationBlocklist::new(
+            vec![IpNet::from_str("10.1.0.0/16").unwrap()],
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            vec![20000],
+            metrics.clone(),
+        );
+        dest_blocklist.add_self_ip(IpAddr::from_str("203.0.113.1").unwrap());
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![(blocked, blocked.to_string())],
+            // the proxy's own port is only blocked on its own addresses
+            discovered_dest_sockets: vec![
+                allowed,
+                self_loopback,
+                self_public,
+                SocketAddr::from_str("127.0.0.1:20001").unwrap(),
+            ],
+            dest_blocklist: Some(dest_blocklist),
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![allowed, SocketAddr::from_str("127.0.0.1:20001").unwrap()]
+        );
+        assert_eq!(metrics.blocked_destinations.load(Ordering::Relaxed), 3);
+        // still listed with their source
+        assert_eq!(dest_sources.sources(&blocked), vec!["static"]);
+
+        // a public IP that changed is unblocked, while the bind addresses stay blocked
+        let dest_blocklist = dest_sources.dest_blocklist.as_mut().unwrap();
+        dest_blocklist.remove_self_ip(IpAddr::from_str("203.0.113.1").unwrap());
+        dest_blocklist.remove_self_ip(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![
+                allowed,
+                self_public,
+                SocketAddr::from_str("127.0.0.1:20001").unwrap()
+            ]
+        );
+
+        dest_sources.dest_blocklist = None;
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(unioned_dest_sockets.load().len(), 5);
+    }
+
+    #[test]
+    fn test_store_union_caps_destinations() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let addr = |s| SocketAddr::from_str(s).unwrap();
+        let static_dest = addr("10.0.0.1:8001");
+        let discovered = vec![
+            addr("10.0.1.3:8001"),
+            addr("10.0.1.1:8001"),
+            addr("10.0.1.2:8001"),
+        ];
+        let admin_dest = addr("10.0.2.1:8001");
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![(static_dest, static_dest.to_string())],
+            discovered_dest_sockets: discovered.clone(),
+            admin_dest_sockets: vec![admin_dest],
+            dest_cap: Some(DestinationCap::new(3, metrics.clone())),
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+
+        // static first, then discovered in response order
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![discovered[0], discovered[1], static_dest]
+        );
+        assert!(metrics.dest_cap_exceeded.load(Ordering::Relaxed));
+
+        dest_sources.discovered_dest_sockets.truncate(1);
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![discovered[0], static_dest, admin_dest]
+        );
+        assert!(!metrics.dest_cap_exceeded.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_send_shards_across_group() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let members = [(); 2].map(|_| {
+            let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            socket
+        });
+        let shard_group = ShardGroup {
+            members: members
                 .iter()
-                .all(|packet| packet.len() == PACKET_DATA_SIZE));
-            assert_eq!(received[0], [1; PACKET_DATA_SIZE]);
-            assert_eq!(received[1], [2; PACKET_DATA_SIZE]);
-        }
+                .map(|socket| socket.local_addr().unwrap())
+                .collect(),
+            shard_by: ShardBy::Shred,
+        };
+        let packets = (0..20u32)
+            .map(|index| {
+                let shred = new_data_shred(42, index, 0, false, &[]);
+                let mut packet = Packet::default();
+                packet.buffer_mut()[..shred.len()].copy_from_slice(&shred);
+                packet.meta_mut().size = shred.len();
+                packet
+            })
+            .collect::<Vec<_>>();
+
+        recv_from_channel_and_send_multiple_dest(
+            Ok(vec![ReceivedBatch::from(PacketBatch::new(packets))]),
+            0.0,
+            &LocalDests {
+                shard_groups: Arc::new(vec![shard_group.clone()]),
+                ..Default::default()
+            },
+            &new_forwarder_context(DedupMode::Payload, metrics.clone()),
+        )
+        .unwrap();
 
+        // each shred arrives at exactly one member
+        let mut buf = [0u8; PACKET_DATA_SIZE];
+        for (member, socket) in shard_group.members.iter().zip(&members) {
+            let mut num_received = 0;
+            while socket.recv(&mut buf).is_ok() {
+                num_received += 1;
+            }
+            assert_eq!(
+                num_received,
+                metrics.shard_assigned.get(member).map_or(0, |n| *n)
+            );
+        }
         assert_eq!(
-            received
+            metrics
+                .shard_assigned
                 .iter()
-                .fold(0, |acc, elem| acc + elem.lock().unwrap().len()),
-            6
+                .map(|kv| *kv.value())
+                .sum::<u64>(),
+            20
+        );
+    }
+
+    #[test]
+    fn test_store_union_tracks_quic_tunnel_and_unix_destinations() {
+        let udp_dest = SocketAddr::from_str("127.0.0.1:8001").unwrap();
+        let quic_dest = SocketAddr::from_str("127.0.0.1:8002").unwrap();
+        let tls_dest = SocketAddr::from_str("127.0.0.1:8003").unwrap();
+        // parsed without resolving, which rejects unix destinations off unix
+        let unix_hostname_port = "unix://@consumer".to_string();
+        let unix_dest = crate::unix::unix_dest_addr(&UnixDest::Abstract("consumer".to_string()));
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![
+                (udp_dest, udp_dest.to_string()),
+                (quic_dest, format!("quic://{quic_dest}")),
+                (tls_dest, format!("tls://{tls_dest}")),
+                (unix_dest, unix_hostname_port),
+            ],
+            ..Default::default()
+        };
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![udp_dest, quic_dest, tls_dest, unix_dest]
+        );
+        assert_eq!(
+            **dest_sources.quic_dest_sockets.load(),
+            HashSet::from([quic_dest])
+        );
+
+        assert_eq!(
+            **dest_sources.tunnel_dests.load(),
+            HashMap::from([(
+                tls_dest,
+                TunnelDest::Tls {
+                    server_name: "127.0.0.1".to_string()
+                }
+            )])
+        );
+
+        assert_eq!(
+            **dest_sources.unix_dests.load(),
+            HashMap::from([(unix_dest, UnixDest::Abstract("consumer".to_string()))])
+        );
+
+        dest_sources.static_dest_sockets.truncate(1);
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert!(dest_sources.quic_dest_sockets.load().is_empty());
+        assert!(dest_sources.tunnel_dests.load().is_empty());
+        assert!(dest_sources.unix_dests.load().is_empty());
+
+        // unhealthy destinations stay configured but aren't forwarded to
+        dest_sources.unhealthy_dest_sockets.insert(udp_dest);
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert!(unioned_dest_sockets.load().is_empty());
+        assert_eq!(dest_sources.union(), vec![udp_dest]);
+    }
+
+    #[test]
+    fn test_fetch_discovered_destinations() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        // accepts requests with the expected headers, rejecting the rest with 401
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let header = |name: &'static str| {
+                    request
+                        .headers()
+                        .iter()
+                        .find(|header| header.field.equiv(name))
+                        .map(|header| header.value.to_string())
+                };
+                let authorized = header("Authorization").as_deref() == Some("Bearer rotated")
+                    && header("X-Env").as_deref() == Some("prod");
+                let response = if authorized {
+                    tiny_http::Response::from_string("[\"10.0.0.1\"]")
+                } else {
+                    tiny_http::Response::from_string("").with_status_code(401)
+                };
+                request.respond(response).unwrap();
+            }
+        });
+        let token_file = std::env::temp_dir().join(format!(
+            "shredstream_proxy_discovery_token_{}",
+            rand::random::<u64>()
+        ));
+        let endpoint_discovery = EndpointDiscovery {
+            url,
+            port: 8001,
+            headers: vec![("X-Env".to_string(), "prod".to_string())],
+            bearer_token_file: Some(token_file.clone()),
+            interval: DISCOVERY_REFRESH_INTERVAL,
+            state_file: None,
+            state_max_age: Duration::from_secs(86_400),
+            max_response_bytes: 1_048_576,
+            max_removal_percent: 50,
+        };
+        let http_client = reqwest::blocking::Client::new();
+        let mut discovery_cache = DiscoveryCache::default();
+
+        // missing token file isn't an auth error
+        let err =
+            fetch_discovered_destinations(&http_client, &endpoint_discovery, &mut discovery_cache)
+                .unwrap_err();
+        assert!(!is_auth_error(&err), "{err}");
+
+        fs::write(&token_file, "stale\n").unwrap();
+        let err =
+            fetch_discovered_destinations(&http_client, &endpoint_discovery, &mut discovery_cache)
+                .unwrap_err();
+        assert!(is_auth_error(&err), "{err}");
+
+        // rotated token is picked up on the next request
+        fs::write(&token_file, "rotated\n").unwrap();
+        assert_eq!(
+            fetch_discovered_destinations(&http_client, &endpoint_discovery, &mut discovery_cache)
+                .unwrap(),
+            Some(vec![SocketAddr::from_str("10.0.0.1:8001").unwrap()])
+        );
+        fs::remove_file(&token_file).unwrap();
+    }
+
+    #[test]
+    fn test_fetch_discovered_destinations_not_modified() {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        // answers 304 when the client already has the current etag
+        let current_etag = Arc::new(Mutex::new("\"v1\"".to_string()));
+        let server_etag = current_etag.clone();
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let etag = server_etag.lock().unwrap().clone();
+                let if_none_match = request
+                    .headers()
+                    .iter()
+                    .find(|header| header.field.equiv("If-None-Match"))
+                    .map(|header| header.value.to_string());
+                let response = if if_none_match.as_ref() == Some(&etag) {
+                    tiny_http::Response::from_string("").with_status_code(304)
+                } else {
+                    tiny_http::Response::from_string(format!("[\"10.0.0.{}\"]", etag.len()))
+                };
+                let response = response
+                    .with_header(tiny_http::Header::from_bytes("ETag", etag.as_bytes()).unwrap());
+                request.respond(response).unwrap();
+            }
+        });
+        let endpoint_discovery = EndpointDiscovery {
+            url,
+            port: 8001,
+            headers: vec![],
+            bearer_token_file: None,
+            interval: DISCOVERY_REFRESH_INTERVAL,
+            state_file: None,
+            state_max_age: Duration::from_secs(86_400),
+            max_response_bytes: 1_048_576,
+            max_removal_percent: 50,
+        };
+        let http_client = reqwest::blocking::Client::new();
+        let mut discovery_cache = DiscoveryCache::default();
+        let fetch = |discovery_cache: &mut DiscoveryCache| {
+            fetch_discovered_destinations(&http_client, &endpoint_discovery, discovery_cache)
+                .unwrap()
+        };
+
+        assert_eq!(
+            fetch(&mut discovery_cache),
+            Some(vec![SocketAddr::from_str("10.0.0.4:8001").unwrap()])
+        );
+        assert_eq!(fetch(&mut discovery_cache), None);
+
+        *current_etag.lock().unwrap() = "\"v10\"".to_string();
+        assert_eq!(
+            fetch(&mut discovery_cache),
+            Some(vec![SocketAddr::from_str("10.0.0.5:8001").unwrap()])
+        );
+        assert_eq!(fetch(&mut discovery_cache), None);
+
+        // validators from another url aren't sent
+        discovery_cache.url = "http://other".to_string();
+        assert!(fetch(&mut discovery_cache).is_some());
+    }
+
+    #[test]
+    fn test_discovery_state_fallback() {
+        let state_file = std::env::temp_dir().join(format!(
+            "shredstream_proxy_discovery_state_{}.json",
+            rand::random::<u64>()
+        ));
+        let mut endpoint_discovery = EndpointDiscovery {
+            url: "http://127.0.0.1:1/endpoints".to_string(),
+            port: 8001,
+            headers: vec![],
+            bearer_token_file: None,
+            interval: DISCOVERY_REFRESH_INTERVAL,
+            state_file: None,
+            state_max_age: Duration::from_secs(86_400),
+            max_response_bytes: 1_048_576,
+            max_removal_percent: 50,
+        };
+        let discovered = vec![SocketAddr::from_str("10.0.0.1:8001").unwrap()];
+
+        // not configured
+        save_discovery_state(&endpoint_discovery, discovered.clone());
+        assert!(load_discovery_state(&endpoint_discovery).is_none());
+
+        endpoint_discovery.state_file = Some(state_file.clone());
+        assert!(load_discovery_state(&endpoint_discovery).is_none());
+        save_discovery_state(&endpoint_discovery, discovered.clone());
+        assert_eq!(
+            load_discovery_state(&endpoint_discovery)
+                .unwrap()
+                .destinations,
+            discovered
+        );
+
+        // saved for another discovery service
+        endpoint_discovery.url = "http://127.0.0.2:1/endpoints".to_string();
+        assert!(load_discovery_state(&endpoint_discovery).is_none());
+        fs::remove_file(&state_file).unwrap();
+    }
+
+    #[test]
+    fn test_parse_discovered_destinations() {
+        let addr = |s| SocketAddr::from_str(s).unwrap();
+        // bare IPs, as before
+        assert_eq!(
+            parse_discovered_destinations(br#"["10.0.0.1", "10.0.0.2"]"#, 8001).unwrap(),
+            vec![addr("10.0.0.1:8001"), addr("10.0.0.2:8001")]
+        );
+        // mixed, with and without a port
+        assert_eq!(
+            parse_discovered_destinations(
+                br#"["10.0.0.1", {"ip": "10.0.0.2", "port": 9001}, {"ip": "10.0.0.3"}, {"ip": "::1", "port": 9002}]"#,
+                8001
+            )
+            .unwrap(),
+            vec![
+                addr("10.0.0.1:8001"),
+                addr("10.0.0.2:9001"),
+                addr("10.0.0.3:8001"),
+                addr("[::1]:9002"),
+            ]
+        );
+        assert!(parse_discovered_destinations(br#"[{"port": 9001}]"#, 8001).is_err());
+        assert!(
+            parse_discovered_destinations(br#"[{"ip": "10.0.0.1", "port": 70000}]"#, 8001).is_err()
+        );
+    }
+
+    #[test]
+    fn test_mass_removal() {
+        let addrs = (1..=4)
+            .map(|i| SocketAddr::from_str(&format!("10.0.0.{i}:8001")).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(mass_removal(&addrs, &addrs[2..], 50), None);
+        assert_eq!(mass_removal(&addrs, &addrs[3..], 50), Some(3));
+        // added endpoints don't offset removed ones
+        assert_eq!(mass_removal(&addrs[..2], &addrs[2..], 50), Some(2));
+        assert_eq!(mass_removal(&addrs, &[], 100), None);
+        assert_eq!(mass_removal(&[], &addrs, 0), None);
+    }
+
+    #[test]
+    fn test_discovered_destinations_switch_format() {
+        let addr = |s| SocketAddr::from_str(s).unwrap();
+        let unioned_dest_sockets = ArcSwap::from_pointee(vec![]);
+        let mut dest_sources = DestinationSources {
+            static_dest_sockets: vec![(addr("10.0.0.1:8001"), "10.0.0.1:8001".to_string())],
+            ..DestinationSources::default()
+        };
+
+        // same IP as a static destination on another port is a separate destination
+        dest_sources.discovered_dest_sockets = parse_discovered_destinations(
+            br#"["10.0.0.1", {"ip": "10.0.0.1", "port": 9001}]"#,
+            8001,
+        )
+        .unwrap();
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![addr("10.0.0.1:8001"), addr("10.0.0.1:9001")]
+        );
+
+        // next refresh switches to bare IPs, dropping the per-entry port
+        dest_sources.discovered_dest_sockets =
+            parse_discovered_destinations(br#"["10.0.0.1", "10.0.0.2"]"#, 8001).unwrap();
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![addr("10.0.0.1:8001"), addr("10.0.0.2:8001")]
+        );
+
+        // and back to objects
+        dest_sources.discovered_dest_sockets =
+            parse_discovered_destinations(br#"[{"ip": "10.0.0.2", "port": 9001}]"#, 8001).unwrap();
+        dest_sources.store_union(&unioned_dest_sockets);
+        assert_eq!(
+            **unioned_dest_sockets.load(),
+            vec![addr("10.0.0.2:9001"), addr("10.0.0.1:8001")]
         );
     }
 }
@@ -0,0 +1,335 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Builder, JoinHandle},
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+
+use crate::{
+    forwarder::{DestinationSources, ShredMetrics},
+    metrics_backend::{datapoint_info, datapoint_warn},
+};
+
+/// Payload sent by `udp-echo` probes, followed by a random nonce. Expected back unchanged
+const UDP_ECHO_PROBE_PREFIX: &[u8] = b"shredstream-proxy-probe";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HealthCheckMode {
+    /// Send a UDP datagram and wait for it to be echoed back
+    UdpEcho,
+    /// Open a TCP connection
+    Tcp,
+    /// Send an HTTP GET, expecting a 2xx response
+    Http,
+}
+
+#[derive(Clone, Debug)]
+pub struct HealthCheckConfig {
+    pub mode: HealthCheckMode,
+    /// Port to probe, defaults to each destination's port
+    pub port: Option<u16>,
+    /// Path requested by `http` probes
+    pub http_path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Consecutive failed probes before a destination is marked unhealthy
+    pub failure_threshold: u32,
+}
+
+/// Periodically probes all destinations. Destinations failing `failure_threshold` consecutive probes
+/// are quarantined: removed from the set forwarded to, but still probed so they're restored on the next success.
+pub fn start_health_check_thread(
+    config: HealthCheckConfig,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    metrics: Arc<ShredMetrics>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    Builder::new()
+        .name("ssPxyHealth".to_string())
+        .spawn(move || {
+            info!(
+                "Health checking destinations every {:?} with {:?} probes.",
+                config.interval, config.mode
+            );
+            let http_client = reqwest::blocking::Client::builder()
+                .timeout(config.timeout)
+                .build()
+                .expect("to build health check http client");
+            // consecutive failures per destination
+            let mut consecutive_failures = HashMap::new();
+            let probe_tick = crossbeam_channel::tick(config.interval);
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(probe_tick) -> _ => {
+                        // unix destinations have no address to probe, and reconnect on their own
+                        let destinations = {
+                            let dest_sources = dest_sources.lock().unwrap();
+                            let unix_dests = dest_sources.unix_dests.load();
+                            dest_sources
+                                .union()
+                                .into_iter()
+                                .filter(|dest| !unix_dests.contains_key(dest))
+                                .collect::<Vec<_>>()
+                        };
+                        // probe concurrently so slow destinations don't delay the rest
+                        let results = thread::scope(|scope| {
+                            destinations
+                                .iter()
+                                .map(|dest| {
+                                    let http_client = &http_client;
+                                    let config = &config;
+                                    scope.spawn(move || (*dest, probe(config, http_client, *dest)))
+                                })
+                                .collect::<Vec<_>>()
+                                .into_iter()
+                                .map(|hdl| hdl.join().unwrap())
+                                .collect::<Vec<_>>()
+                        });
+
+                        let mut dest_sources = dest_sources.lock().unwrap();
+                        // destinations removed while probing are dropped by update_health
+                        let current = dest_sources.union().into_iter().collect::<HashSet<_>>();
+                        let transitions = update_health(
+                            &mut consecutive_failures,
+                            &mut dest_sources.unhealthy_dest_sockets,
+                            &current,
+                            &results,
+                            config.failure_threshold,
+                        );
+                        for (dest, healthy) in transitions.iter() {
+                            if *healthy {
+                                info!("Destination {dest} recovered, resuming forwarding.");
+                                metrics.dest_became_healthy.fetch_add(1, Ordering::Relaxed);
+                            } else {
+                                warn!("Destination {dest} failed {} consecutive health checks, pausing forwarding.", config.failure_threshold);
+                                metrics.dest_became_unhealthy.fetch_add(1, Ordering::Relaxed);
+                            }
+                            datapoint_warn!(
+                                "shredstream_proxy-destination_health_transition",
+                                "addr" => dest.to_string(),
+                                ("healthy", *healthy, bool),
+                            );
+                        }
+                        if !transitions.is_empty() {
+                            dest_sources.store_union(&unioned_dest_sockets);
+                        }
+                        let num_unhealthy = dest_sources.unhealthy_dest_sockets.len();
+                        metrics.healthy_destinations.store((current.len() - num_unhealthy) as u64, Ordering::Relaxed);
+                        metrics.unhealthy_destinations.store(num_unhealthy as u64, Ordering::Relaxed);
+                        datapoint_info!(
+                            "shredstream_proxy-destination_health",
+                            ("healthy", current.len() - num_unhealthy, i64),
+                            ("unhealthy", num_unhealthy, i64),
+                        );
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+        })
+        .unwrap()
+}
+
+/// Probes a destination, returning an error if it's unhealthy
+fn probe(
+    config: &HealthCheckConfig,
+    http_client: &reqwest::blocking::Client,
+    dest: SocketAddr,
+) -> io::Result<()> {
+    let probe_addr = SocketAddr::new(dest.ip(), config.port.unwrap_or(dest.port()));
+    match config.mode {
+        HealthCheckMode::UdpEcho => probe_udp_echo(probe_addr, config.timeout),
+        HealthCheckMode::Tcp => TcpStream::connect_timeout(&probe_addr, config.timeout).map(drop),
+        HealthCheckMode::Http => http_client
+            .get(format!("http://{probe_addr}{}", config.http_path))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map(drop)
+            .map_err(|e| io::Error::new(ErrorKind::Other, e)),
+    }
+}
+
+fn probe_udp_echo(probe_addr: SocketAddr, timeout: Duration) -> io::Result<()> {
+    let unspecified = match probe_addr.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(unspecified, 0))?;
+    socket.connect(probe_addr)?;
+    socket.set_read_timeout(Some(timeout))?;
+
+    let mut payload = UDP_ECHO_PROBE_PREFIX.to_vec();
+    payload.extend_from_slice(&rand::random::<u64>().to_le_bytes());
+    socket.send(&payload)?;
+    let mut buf = [0u8; 64];
+    let len = socket.recv(&mut buf)?;
+    if buf[..len] != payload {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Unexpected udp echo probe response",
+        ));
+    }
+    Ok(())
+}
+
+/// Applies probe results, updating the unhealthy set.
+/// Returns destinations that changed state, with whether they're now healthy.
+fn update_health(
+    consecutive_failures: &mut HashMap<SocketAddr, u32>,
+    unhealthy: &mut HashSet<SocketAddr>,
+    current: &HashSet<SocketAddr>,
+    results: &[(SocketAddr, io::Result<()>)],
+    failure_threshold: u32,
+) -> Vec<(SocketAddr, bool)> {
+    // forget destinations no longer configured
+    consecutive_failures.retain(|dest, _| current.contains(dest));
+    unhealthy.retain(|dest| current.contains(dest));
+
+    results
+        .iter()
+        .filter(|(dest, _)| current.contains(dest))
+        .filter_map(|(dest, result)| {
+            let failures = consecutive_failures.entry(*dest).or_default();
+            match result {
+                Ok(()) => {
+                    *failures = 0;
+                    unhealthy.remove(dest).then_some((*dest, true))
+                }
+                Err(e) => {
+                    *failures += 1;
+                    if *failures == 1 {
+                        warn!("Health check failed for destination {dest}. Error: {e}");
+                    }
+                    (*failures >= failure_threshold && unhealthy.insert(*dest))
+                        .then_some((*dest, false))
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        io::{self, ErrorKind},
+        net::{SocketAddr, TcpListener, UdpSocket},
+        str::FromStr,
+        thread,
+        time::Duration,
+    };
+
+    use crate::health::{probe, update_health, HealthCheckConfig, HealthCheckMode};
+
+    #[test]
+    fn test_update_health() {
+        let flaky = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let healthy = SocketAddr::from_str("10.0.0.2:8001").unwrap();
+        let current = HashSet::from([flaky, healthy]);
+        let mut consecutive_failures = HashMap::new();
+        let mut unhealthy = HashSet::new();
+        let results = |flaky_ok: bool| {
+            vec![
+                (
+                    flaky,
+                    if flaky_ok {
+                        Ok(())
+                    } else {
+                        Err(io::Error::from(ErrorKind::TimedOut))
+                    },
+                ),
+                (healthy, Ok(())),
+            ]
+        };
+
+        // below threshold
+        for _ in 0..2 {
+            let transitions = update_health(
+                &mut consecutive_failures,
+                &mut unhealthy,
+                &current,
+                &results(false),
+                3,
+            );
+            assert!(transitions.is_empty());
+        }
+        // reaches threshold once
+        for expected in [vec![(flaky, false)], vec![]] {
+            let transitions = update_health(
+                &mut consecutive_failures,
+                &mut unhealthy,
+                &current,
+                &results(false),
+                3,
+            );
+            assert_eq!(transitions, expected);
+            assert_eq!(unhealthy, HashSet::from([flaky]));
+        }
+        // recovers on first success
+        let transitions = update_health(
+            &mut consecutive_failures,
+            &mut unhealthy,
+            &current,
+            &results(true),
+            3,
+        );
+        assert_eq!(transitions, vec![(flaky, true)]);
+        assert!(unhealthy.is_empty());
+
+        // removed destinations are forgotten
+        unhealthy.insert(flaky);
+        let transitions = update_health(
+            &mut consecutive_failures,
+            &mut unhealthy,
+            &HashSet::from([healthy]),
+            &results(false),
+            1,
+        );
+        assert!(transitions.is_empty());
+        assert!(unhealthy.is_empty());
+        assert!(!consecutive_failures.contains_key(&flaky));
+    }
+
+    #[test]
+    fn test_probe() {
+        let config = |mode| HealthCheckConfig {
+            mode,
+            port: None,
+            http_path: "/health".to_string(),
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_millis(500),
+            failure_threshold: 1,
+        };
+        let http_client = reqwest::blocking::Client::new();
+
+        let tcp_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp_addr = tcp_listener.local_addr().unwrap();
+        assert!(probe(&config(HealthCheckMode::Tcp), &http_client, tcp_addr).is_ok());
+        drop(tcp_listener);
+        assert!(probe(&config(HealthCheckMode::Tcp), &http_client, tcp_addr).is_err());
+
+        let echo_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let echo_addr = echo_socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (len, from) = echo_socket.recv_from(&mut buf).unwrap();
+            echo_socket.send_to(&buf[..len], from).unwrap();
+        });
+        assert!(probe(&config(HealthCheckMode::UdpEcho), &http_client, echo_addr).is_ok());
+        // echo thread only answers once
+        assert!(probe(&config(HealthCheckMode::UdpEcho), &http_client, echo_addr).is_err());
+    }
+}
@@ -4,24 +4,29 @@ use std::{
         atomic::{AtomicBool, Ordering},
         Arc,
     },
-    thread::{sleep, Builder, JoinHandle},
-    time::Duration,
+    thread::JoinHandle,
+    time::{Duration, Instant},
 };
 
+use arc_swap::ArcSwap;
 use crossbeam_channel::Receiver;
+use histogram::Histogram;
 use jito_protos::{
     auth::{auth_service_client::AuthServiceClient, Role},
     shredstream::{shredstream_client::ShredstreamClient, Heartbeat},
 };
 use log::{info, warn};
-use solana_metrics::{datapoint_info, datapoint_warn};
+use serde::Serialize;
 use solana_sdk::signature::Keypair;
 use tokio::runtime::Runtime;
 use tonic::{codegen::InterceptedService, transport::Channel, Code};
 
 use crate::{
     forwarder::ShredMetrics,
-    token_authenticator::{create_grpc_channel, ClientInterceptor},
+    metrics_backend::{datapoint_info, datapoint_warn},
+    otel::{control_span, traced},
+    supervisor::Supervisor,
+    token_authenticator::{create_grpc_channel, ClientInterceptor, GrpcChannelConfig, TokenCache},
     ShredstreamProxyError,
 };
 /*
@@ -52,46 +57,228 @@ impl Drop for ScopedAtomicBool {
     }
 }
 
+/// Heartbeat state of a region, exported as the `heartbeat_state` gauge
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeartbeatState {
+    Disconnected = 0,
+    /// Connecting to and authenticating with the block engine
+    Authenticating = 1,
+    /// The last heartbeat for the region succeeded
+    Active = 2,
+    /// Heartbeats are paused for maintenance, see [set_heartbeats_paused]
+    Paused = 3,
+}
+
+/// How often a paused heartbeat loop checks whether it was resumed
+const PAUSE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Pauses or resumes heartbeats, such as on `SIGUSR1` and `SIGUSR2` or via the admin API. Forwarding continues while paused,
+/// draining whatever still arrives. The heartbeat loop closes its stream once paused, and re-authenticates once resumed.
+/// Returns false if already in that state
+pub fn set_heartbeats_paused(metrics: &ShredMetrics, paused: bool, via: &str) -> bool {
+    if metrics.heartbeat_paused.swap(paused, Ordering::Relaxed) == paused {
+        return false;
+    }
+    match paused {
+        true => info!("Pausing heartbeats via {via}, forwarding continues."),
+        false => info!("Resuming heartbeats via {via}."),
+    }
+    true
+}
+
+/// Heartbeat health of a region. Without `region-ports` one heartbeat covers all regions, so each records its result
+#[derive(Debug)]
+pub struct RegionHeartbeatStats {
+    pub state: HeartbeatState,
+    /// Microseconds per heartbeat call this metrics interval
+    pub rtt_us: Histogram,
+    pub last_rtt: Option<Duration>,
+    pub consecutive_failures: u64,
+    /// When a heartbeat last succeeded, or when the region was first tracked if none has
+    pub last_success: Instant,
+    /// `ttl_ms` from the last successful heartbeat, heartbeats are sent every third of it
+    pub ttl_ms: Option<u32>,
+}
+
+impl RegionHeartbeatStats {
+    pub(crate) fn new(now: Instant) -> Self {
+        Self {
+            state: HeartbeatState::Disconnected,
+            rtt_us: Histogram::new(),
+            last_rtt: None,
+            consecutive_failures: 0,
+            last_success: now,
+            ttl_ms: None,
+        }
+    }
+}
+
+fn set_heartbeat_state(metrics: &ShredMetrics, regions: &[String], state: HeartbeatState) {
+    regions.iter().for_each(|region| {
+        metrics
+            .heartbeat_regions
+            .entry(region.clone())
+            .or_insert_with(|| RegionHeartbeatStats::new(Instant::now()))
+            .state = state;
+    });
+}
+
+/// Records a heartbeat for `regions` taking `rtt`, with the `ttl_ms` the block engine responded with if it succeeded
+fn record_heartbeat(
+    metrics: &ShredMetrics,
+    regions: &[String],
+    rtt: Duration,
+    ttl_ms: Option<u32>,
+    now: Instant,
+) {
+    regions.iter().for_each(|region| {
+        let mut stats = metrics
+            .heartbeat_regions
+            .entry(region.clone())
+            .or_insert_with(|| RegionHeartbeatStats::new(now));
+        let _ = stats.rtt_us.increment(rtt.as_micros() as u64);
+        stats.last_rtt = Some(rtt);
+        match ttl_ms {
+            Some(ttl_ms) => {
+                stats.state = HeartbeatState::Active;
+                stats.consecutive_failures = 0;
+                stats.last_success = now;
+                stats.ttl_ms = Some(ttl_ms);
+            }
+            None => {
+                stats.state = HeartbeatState::Disconnected;
+                stats.consecutive_failures += 1;
+            }
+        }
+    });
+}
+
+/// Ordered block engine URLs. Fails over to the next URL after consecutive connection or heartbeat failures,
+/// returning to the primary after `primary_retry_interval` on another URL.
+pub struct BlockEngineFailover {
+    urls: Vec<String>,
+    active: usize,
+    consecutive_failures: u32,
+    failure_threshold: u32,
+    primary_retry_interval: Duration,
+    /// When we last switched away from the primary
+    switched_at: Option<Instant>,
+}
+
+impl BlockEngineFailover {
+    pub fn new(
+        urls: Vec<String>,
+        failure_threshold: u32,
+        primary_retry_interval: Duration,
+    ) -> Self {
+        Self {
+            urls,
+            active: 0,
+            consecutive_failures: 0,
+            failure_threshold,
+            primary_retry_interval,
+            switched_at: None,
+        }
+    }
+
+    fn active_url(&self) -> &str {
+        &self.urls[self.active]
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    /// Returns true if this failure switched to the next URL
+    fn record_failure(&mut self, now: Instant) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < self.failure_threshold || self.urls.len() < 2 {
+            return false;
+        }
+        self.consecutive_failures = 0;
+        self.active = (self.active + 1) % self.urls.len();
+        self.switched_at = (self.active != 0).then_some(now);
+        true
+    }
+
+    /// Returns true if switched back to the primary after `primary_retry_interval` on another URL
+    fn maybe_retry_primary(&mut self, now: Instant) -> bool {
+        match self.switched_at {
+            Some(switched_at) if now.duration_since(switched_at) >= self.primary_retry_interval => {
+                self.active = 0;
+                self.consecutive_failures = 0;
+                self.switched_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub fn heartbeat_loop_thread(
-    block_engine_url: String,
-    auth_url: String,
+    mut block_engine_failover: BlockEngineFailover,
+    auth_url: Option<String>, /* defaults to the active block engine url */
     auth_keypair: Arc<Keypair>,
+    grpc_channel_config: GrpcChannelConfig,
     desired_regions: Vec<String>,
-    recv_socket: SocketAddr,
+    recv_socket: Arc<ArcSwap<SocketAddr>>, /* updated by the public ip recheck, read on each reconnect */
+    region_ports: bool,
     runtime: Runtime,
     service_name: String,
+    token_cache: TokenCache, /* kept across restarts of the loop, and saved to disk if configured */
     metrics: Arc<ShredMetrics>,
+    reregister_receiver: Receiver<()>, /* signaled by the stall watchdog and public ip recheck */
+    supervisor: &Supervisor,
     shutdown_receiver: Receiver<()>,
     exit: Arc<AtomicBool>,
 ) -> JoinHandle<()> {
-    Builder::new().name("ssPxyHbeatLoop".to_string()).spawn(move || {
-        let heartbeat_socket = jito_protos::shared::Socket {
-            ip: recv_socket.ip().to_string(),
-            port: recv_socket.port() as i64,
-        };
+    supervisor.spawn_restartable("ssPxyHbeatLoop", move || {
+        // tokens are cached per auth url so failover never reuses a token issued by another block engine
+        let token_cache = token_cache.clone();
         let mut heartbeat_interval = Duration::from_secs(1); //start with 1s, change based on server suggestion
         // use tick() since we want to avoid thread::sleep(), as it's not interruptible. want to be interruptible for exiting quickly
         let mut heartbeat_tick = crossbeam_channel::tick(heartbeat_interval);
         let metrics_tick = crossbeam_channel::tick(Duration::from_secs(30));
         let mut last_cumulative_received_shred_count = 0;
         let mut client_restart_count = 0u64;
-        let mut successful_heartbeat_count = 0u64;
-        let mut failed_heartbeat_count = 0u64;
+        // (successful, failed) per heartbeat
+        // the number of heartbeats depends only on the regions, not the advertised address
+        let mut heartbeat_counts = vec![(0u64, 0u64); region_heartbeats(&desired_regions, **recv_socket.load(), region_ports).len()];
         let mut client_restart_count_cumulative = 0u64;
         let mut successful_heartbeat_count_cumulative = 0u64;
         let mut failed_heartbeat_count_cumulative = 0u64;
 
         while !exit.load(Ordering::Relaxed) {
+            if metrics.heartbeat_paused.load(Ordering::Relaxed) {
+                info!("Heartbeats paused, the block engine stops sending shreds once they expire.");
+                set_heartbeat_state(&metrics, &desired_regions, HeartbeatState::Paused);
+                while metrics.heartbeat_paused.load(Ordering::Relaxed) && !exit.load(Ordering::Relaxed) {
+                    // wakes immediately on shutdown (avoid using sleep since it will hang under SIGINT)
+                    let _ = shutdown_receiver.recv_timeout(PAUSE_CHECK_INTERVAL);
+                }
+                // nothing is expected while paused, so don't restart the client for it
+                last_cumulative_received_shred_count = metrics.agg_received_cumulative.load(Ordering::Relaxed);
+                continue;
+            }
+            let recv_socket = **recv_socket.load();
+            let heartbeats = region_heartbeats(&desired_regions, recv_socket, region_ports);
+            let block_engine_url = block_engine_failover.active_url().to_string();
+            metrics.active_block_engine_url.store(Some(Arc::new(block_engine_url.clone())));
             // We want to scope the grpc shredstream client to the heartbeat loop. This way shredstream client exits when the heartbeat loop exits
             let per_con_exit = ScopedAtomicBool::default();
-            info!("Starting heartbeat client");
+            info!("Starting heartbeat client for {block_engine_url}");
+            set_heartbeat_state(&metrics, &desired_regions, HeartbeatState::Authenticating);
             let shredstream_client_res = runtime.block_on(
                 get_grpc_client(
                     block_engine_url.clone(),
-                    auth_url.clone(),
+                    auth_url.clone().unwrap_or_else(|| block_engine_url.clone()),
+                    token_cache.clone(),
                     auth_keypair.clone(),
+                    &grpc_channel_config,
                     service_name.clone(),
+                    metrics.clone(),
                     per_con_exit.get_inner_clone(),
                 )
             );
@@ -100,6 +287,7 @@ pub fn heartbeat_loop_thread(
                 Ok(c) => c,
                 Err(e) => {
                     warn!("Failed to connect to block engine, retrying. Error: {e}");
+                    set_heartbeat_state(&metrics, &desired_regions, HeartbeatState::Disconnected);
                     client_restart_count += 1;
                     datapoint_warn!(
                         "shredstream_proxy-heartbeat_client_error",
@@ -107,7 +295,9 @@ pub fn heartbeat_loop_thread(
                         ("errors", 1, i64),
                         ("error_str", e.to_string(), String),
                     );
-                    sleep(Duration::from_secs(5));
+                    record_failure(&mut block_engine_failover, &block_engine_url, &metrics);
+                    // wakes immediately on shutdown (avoid using sleep since it will hang under SIGINT)
+                    let _ = shutdown_receiver.recv_timeout(Duration::from_secs(5));
                     continue; // avoid sending heartbeat, try acquiring grpc client again
                 }
             };
@@ -115,48 +305,89 @@ pub fn heartbeat_loop_thread(
                 crossbeam_channel::select! {
                     // send heartbeat
                     recv(heartbeat_tick) -> _ => {
-                        let heartbeat_result = runtime.block_on(shredstream_client
-                            .send_heartbeat(Heartbeat {
-                                socket: Some(heartbeat_socket.clone()),
-                                regions: desired_regions.clone(),
-                            }));
-
-                        match heartbeat_result {
-                            Ok(hb) => {
-                                // retry sooner in case a heartbeat fails
-                                let new_interval = Duration::from_millis((hb.get_ref().ttl_ms / 3) as u64);
-                                if heartbeat_interval != new_interval {
-                                    info!("Sending heartbeat every {new_interval:?}.");
-                                    heartbeat_interval = new_interval;
-                                    heartbeat_tick = crossbeam_channel::tick(new_interval);
+                        // close the stream, and re-authenticate once resumed
+                        if metrics.heartbeat_paused.load(Ordering::Relaxed) {
+                            token_cache.remove(auth_url.as_deref().unwrap_or(&block_engine_url));
+                            refresh_thread_hdl.abort();
+                            break;
+                        }
+                        let mut new_interval = None;
+                        for ((region, heartbeat), (successful, failed)) in heartbeats.iter().zip(heartbeat_counts.iter_mut()) {
+                            let heartbeat_start = Instant::now();
+                            let heartbeat_result = traced(
+                                control_span!("heartbeat", region = region.as_str(), block_engine_url = block_engine_url.as_str()),
+                                || runtime.block_on(shredstream_client.send_heartbeat(heartbeat.clone())),
+                            );
+                            record_heartbeat(
+                                &metrics,
+                                &heartbeat.regions,
+                                heartbeat_start.elapsed(),
+                                heartbeat_result.as_ref().ok().map(|hb| hb.get_ref().ttl_ms),
+                                Instant::now(),
+                            );
+
+                            match heartbeat_result {
+                                Ok(hb) => {
+                                    // retry sooner in case a heartbeat fails
+                                    let interval = Duration::from_millis((hb.get_ref().ttl_ms / 3) as u64);
+                                    new_interval = Some(new_interval.map_or(interval, |x: Duration| x.min(interval)));
+                                    *successful += 1;
+                                    metrics.successful_heartbeat_cumulative.fetch_add(1, Ordering::Relaxed);
+                                }
+                                Err(err) => {
+                                    if err.code() == Code::InvalidArgument {
+                                        panic!("Invalid arguments: {err}.");
+                                    };
+                                    // don't reuse a rejected token when reconnecting
+                                    if err.code() == Code::Unauthenticated {
+                                        token_cache.remove(auth_url.as_deref().unwrap_or(&block_engine_url));
+                                    }
+                                    warn!(
+                                        event = "heartbeat_failed",
+                                        region = region.as_str(),
+                                        error_kind:? = err.code(),
+                                        block_engine_url = block_engine_url.as_str();
+                                        "Error sending heartbeat for region {region}: {err}"
+                                    );
+                                    datapoint_warn!(
+                                        "shredstream_proxy-heartbeat_send_error",
+                                        "block_engine_url" => block_engine_url,
+                                        "region" => region,
+                                        ("errors", 1, i64),
+                                        ("error_str", err.to_string(), String),
+                                    );
+                                    *failed += 1;
+                                    metrics.failed_heartbeat_cumulative.fetch_add(1, Ordering::Relaxed);
                                 }
-                                successful_heartbeat_count += 1;
-                            }
-                            Err(err) => {
-                                if err.code() == Code::InvalidArgument {
-                                    panic!("Invalid arguments: {err}.");
-                                };
-                                warn!("Error sending heartbeat: {err}");
-                                datapoint_warn!(
-                                    "shredstream_proxy-heartbeat_send_error",
-                                    "block_engine_url" => block_engine_url,
-                                    ("errors", 1, i64),
-                                    ("error_str", err.to_string(), String),
-                                );
-                                failed_heartbeat_count += 1;
                             }
                         }
+                        // a successful heartbeat means the block engine is up
+                        if new_interval.is_some() {
+                            block_engine_failover.record_success();
+                        } else if record_failure(&mut block_engine_failover, &block_engine_url, &metrics) {
+                            refresh_thread_hdl.abort();
+                            break;
+                        }
+                        if let Some(new_interval) = new_interval.filter(|x| *x != heartbeat_interval) {
+                            info!("Sending heartbeat every {new_interval:?}.");
+                            heartbeat_interval = new_interval;
+                            heartbeat_tick = crossbeam_channel::tick(new_interval);
+                        }
                     }
 
                     // send metrics and handle grpc connection failing
                     recv(metrics_tick) -> _ => {
-                        datapoint_info!(
-                            "shredstream_proxy-heartbeat_stats",
-                            "block_engine_url" => block_engine_url,
-                            ("successful_heartbeat_count", successful_heartbeat_count, i64),
-                            ("failed_heartbeat_count", failed_heartbeat_count, i64),
-                            ("client_restart_count", client_restart_count, i64),
-                        );
+                        for ((region, _heartbeat), (successful, failed)) in heartbeats.iter().zip(heartbeat_counts.iter()) {
+                            datapoint_info!(
+                                "shredstream_proxy-heartbeat_stats",
+                                "block_engine_url" => block_engine_url,
+                                "region" => region,
+                                "advertise_addr" => recv_socket.to_string(),
+                                ("successful_heartbeat_count", *successful, i64),
+                                ("failed_heartbeat_count", *failed, i64),
+                                ("client_restart_count", client_restart_count, i64),
+                            );
+                        }
 
                         // handle scenario when grpc connection is open, but backend doesn't receive heartbeat
                         // possibly due to envoy losing track of the pod when backend restarts.
@@ -175,15 +406,30 @@ pub fn heartbeat_loop_thread(
                         }
                         last_cumulative_received_shred_count = new_received_count;
 
+                        if block_engine_failover.maybe_retry_primary(Instant::now()) {
+                            info!("Retrying primary block engine {}.", block_engine_failover.active_url());
+                            refresh_thread_hdl.abort();
+                            break;
+                        }
 
-                        successful_heartbeat_count_cumulative += successful_heartbeat_count;
-                        failed_heartbeat_count_cumulative += failed_heartbeat_count;
+                        for (successful, failed) in heartbeat_counts.iter_mut() {
+                            successful_heartbeat_count_cumulative += *successful;
+                            failed_heartbeat_count_cumulative += *failed;
+                            (*successful, *failed) = (0, 0);
+                        }
                         client_restart_count_cumulative += client_restart_count;
-                        successful_heartbeat_count = 0;
-                        failed_heartbeat_count = 0;
                         client_restart_count = 0;
                     }
 
+                    // the block engine may have lost our registration while heartbeats still succeed, so start over from auth
+                    recv(reregister_receiver) -> _ => {
+                        let auth_url = auth_url.as_deref().unwrap_or(&block_engine_url);
+                        token_cache.remove(auth_url);
+                        client_restart_count += 1;
+                        refresh_thread_hdl.abort();
+                        break;
+                    }
+
                     // handle SIGINT shutdown
                     recv(shutdown_receiver) -> _ => {
                         // exit should be true
@@ -192,15 +438,75 @@ pub fn heartbeat_loop_thread(
                 }
             }
         }
+        set_heartbeat_state(&metrics, &desired_regions, HeartbeatState::Disconnected);
         info!("Exiting heartbeat thread, sent {successful_heartbeat_count_cumulative} successful, {failed_heartbeat_count_cumulative} failed heartbeats. Client restarted {client_restart_count_cumulative} times.");
-    }).unwrap()
+    })
+}
+
+/// Records a connection or heartbeat failure, returning true if it failed over to the next block engine
+fn record_failure(
+    block_engine_failover: &mut BlockEngineFailover,
+    block_engine_url: &str,
+    metrics: &ShredMetrics,
+) -> bool {
+    if !block_engine_failover.record_failure(Instant::now()) {
+        return false;
+    }
+    let next_url = block_engine_failover.active_url();
+    warn!(
+        "Block engine {block_engine_url} failed {} consecutive times, failing over to {next_url}.",
+        block_engine_failover.failure_threshold
+    );
+    metrics
+        .block_engine_failovers_cumulative
+        .fetch_add(1, Ordering::Relaxed);
+    datapoint_warn!(
+        "shredstream_proxy-block_engine_failover",
+        "from" => block_engine_url,
+        "to" => next_url,
+        ("failovers", 1, i64),
+    );
+    true
 }
 
+/// Heartbeats to send each tick, labeled by region.
+/// With `region_ports`, each region gets its own heartbeat advertising port `recv_socket` port + region index,
+/// otherwise a single heartbeat advertises `recv_socket` for all regions.
+fn region_heartbeats(
+    desired_regions: &[String],
+    recv_socket: SocketAddr,
+    region_ports: bool,
+) -> Vec<(String, Heartbeat)> {
+    let heartbeat = |port: u16, regions: Vec<String>| Heartbeat {
+        socket: Some(jito_protos::shared::Socket {
+            ip: recv_socket.ip().to_string(),
+            port: port as i64,
+        }),
+        regions,
+    };
+    if region_ports {
+        desired_regions
+            .iter()
+            .zip(recv_socket.port()..)
+            .map(|(region, port)| (region.clone(), heartbeat(port, vec![region.clone()])))
+            .collect()
+    } else {
+        vec![(
+            desired_regions.join(","),
+            heartbeat(recv_socket.port(), desired_regions.to_vec()),
+        )]
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn get_grpc_client(
     block_engine_url: String,
     auth_url: String,
+    token_cache: TokenCache,
     auth_keypair: Arc<Keypair>,
+    grpc_channel_config: &GrpcChannelConfig,
     service_name: String,
+    metrics: Arc<ShredMetrics>,
     exit: Arc<AtomicBool>,
 ) -> Result<
     (
@@ -209,16 +515,256 @@ pub async fn get_grpc_client(
     ),
     ShredstreamProxyError,
 > {
-    let auth_channel = create_grpc_channel(auth_url).await?;
-    let searcher_channel = create_grpc_channel(block_engine_url).await?;
+    let auth_channel = create_grpc_channel(auth_url.clone(), grpc_channel_config).await?;
+    let searcher_channel = create_grpc_channel(block_engine_url, grpc_channel_config).await?;
     let (client_interceptor, thread_handle) = ClientInterceptor::new(
         AuthServiceClient::new(auth_channel),
+        auth_url,
+        token_cache,
         auth_keypair,
         Role::ShredstreamSubscriber,
         service_name,
+        metrics,
         exit,
     )
     .await?;
     let searcher_client = ShredstreamClient::with_interceptor(searcher_channel, client_interceptor);
     Ok((searcher_client, thread_handle))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::{SocketAddr, TcpListener, TcpStream},
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            Arc,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use solana_sdk::signature::Keypair;
+    use tokio::runtime::Runtime;
+
+    use crate::{
+        forwarder::ShredMetrics,
+        heartbeat::{
+            get_grpc_client, record_heartbeat, region_heartbeats, set_heartbeat_state,
+            BlockEngineFailover, HeartbeatState,
+        },
+        mock_block_engine::{start_mock_block_engine, MockBlockEngineArgs},
+        token_authenticator::{GrpcChannelConfig, TokenCache},
+    };
+
+    const HTTP2_PREFACE_LEN: usize = 24;
+    const HTTP2_FRAME_HEADER_LEN: usize = 9;
+    const HTTP2_PING: u8 = 0x6;
+    const HTTP2_ACK: u8 = 0x1;
+
+    /// Relays connections to `upstream`, counting the HTTP/2 pings clients send, excluding acks
+    fn start_ping_counting_relay(upstream: SocketAddr) -> (SocketAddr, Arc<AtomicU64>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let local_addr = listener.local_addr().unwrap();
+        let pings = Arc::new(AtomicU64::new(0));
+        let relay_pings = pings.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let mut client = client.unwrap();
+                let mut server = TcpStream::connect(upstream).unwrap();
+                let (mut client_reader, mut server_writer) =
+                    (client.try_clone().unwrap(), server.try_clone().unwrap());
+                thread::spawn(move || io::copy(&mut server, &mut client));
+                let pings = relay_pings.clone();
+                thread::spawn(move || -> io::Result<()> {
+                    let mut preface = [0u8; HTTP2_PREFACE_LEN];
+                    client_reader.read_exact(&mut preface)?;
+                    server_writer.write_all(&preface)?;
+                    loop {
+                        let mut header = [0u8; HTTP2_FRAME_HEADER_LEN];
+                        client_reader.read_exact(&mut header)?;
+                        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+                        if header[3] == HTTP2_PING && header[4] & HTTP2_ACK == 0 {
+                            pings.fetch_add(1, Ordering::Relaxed);
+                        }
+                        let mut payload = vec![0u8; len as usize];
+                        client_reader.read_exact(&mut payload)?;
+                        server_writer.write_all(&header)?;
+                        server_writer.write_all(&payload)?;
+                    }
+                });
+            }
+        });
+        (local_addr, pings)
+    }
+
+    /// Returns the pings sent over an idle connection to the mock block engine within `idle`
+    fn count_keepalive_pings(grpc_channel_config: &GrpcChannelConfig, idle: Duration) -> u64 {
+        let mock_exit = Arc::new(AtomicBool::new(false));
+        let (mock_block_engine, mock_hdls) = start_mock_block_engine(
+            &MockBlockEngineArgs {
+                bind_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                pps: 0,
+            },
+            mock_exit.clone(),
+        )
+        .unwrap();
+        let (relay_addr, pings) = start_ping_counting_relay(mock_block_engine.local_addr());
+        let url = format!("http://{relay_addr}");
+        let runtime = Runtime::new().unwrap();
+        let client_exit = Arc::new(AtomicBool::new(false));
+        let (client, refresh_hdl) = runtime
+            .block_on(get_grpc_client(
+                url.clone(),
+                url,
+                TokenCache::default(),
+                Arc::new(Keypair::new()),
+                grpc_channel_config,
+                "shredstream_proxy".to_string(),
+                Arc::new(ShredMetrics::new()),
+                client_exit.clone(),
+            ))
+            .unwrap();
+        // authenticating sends no pings, so any counted are keepalives
+        let pings_before = pings.load(Ordering::Relaxed);
+        thread::sleep(idle);
+        let keepalive_pings = pings.load(Ordering::Relaxed) - pings_before;
+
+        drop(client);
+        client_exit.store(true, Ordering::Relaxed);
+        refresh_hdl.abort();
+        mock_exit.store(true, Ordering::Relaxed);
+        for hdl in mock_hdls {
+            hdl.join().unwrap();
+        }
+        keepalive_pings
+    }
+
+    #[test]
+    fn test_keepalive_pings() {
+        let keepalive = GrpcChannelConfig {
+            keepalive_interval: Some(Duration::from_millis(100)),
+            keepalive_timeout: Some(Duration::from_secs(1)),
+            ..GrpcChannelConfig::default()
+        };
+        // pings both the auth and block engine connections while idle
+        assert!(count_keepalive_pings(&keepalive, Duration::from_secs(1)) >= 4);
+        // none by default, as before keepalives were configurable
+        assert_eq!(
+            count_keepalive_pings(&GrpcChannelConfig::default(), Duration::from_secs(1)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_block_engine_failover() {
+        let urls = vec!["primary".to_string(), "secondary".to_string()];
+        let mut failover = BlockEngineFailover::new(urls, 2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        // successes reset the failure count
+        assert!(!failover.record_failure(start));
+        failover.record_success();
+        assert!(!failover.record_failure(start));
+        assert_eq!(failover.active_url(), "primary");
+        // fails over once the threshold is reached
+        assert!(failover.record_failure(start));
+        assert_eq!(failover.active_url(), "secondary");
+
+        // primary is retried after the interval
+        assert!(!failover.maybe_retry_primary(start + Duration::from_secs(59)));
+        assert!(failover.maybe_retry_primary(start + Duration::from_secs(60)));
+        assert_eq!(failover.active_url(), "primary");
+        assert!(!failover.maybe_retry_primary(start + Duration::from_secs(600)));
+
+        // cycles back to the primary after the last url
+        for _ in 0..4 {
+            failover.record_failure(start);
+        }
+        assert_eq!(failover.active_url(), "primary");
+        assert!(!failover.maybe_retry_primary(start + Duration::from_secs(600)));
+
+        // a single url never fails over
+        let mut failover =
+            BlockEngineFailover::new(vec!["primary".to_string()], 1, Duration::from_secs(60));
+        assert!(!failover.record_failure(start));
+        assert_eq!(failover.active_url(), "primary");
+    }
+
+    #[test]
+    fn test_region_heartbeats() {
+        let regions = vec!["ny".to_string(), "amsterdam".to_string()];
+        let recv_socket = SocketAddr::from_str("10.0.0.1:20000").unwrap();
+
+        let combined = region_heartbeats(&regions, recv_socket, false);
+        assert_eq!(combined.len(), 1);
+        assert_eq!(combined[0].0, "ny,amsterdam");
+        assert_eq!(combined[0].1.regions, regions);
+        assert_eq!(combined[0].1.socket.as_ref().unwrap().port, 20000);
+
+        let per_region = region_heartbeats(&regions, recv_socket, true);
+        let per_region = per_region
+            .iter()
+            .map(|(region, hb)| {
+                let socket = hb.socket.as_ref().unwrap();
+                (
+                    region.as_str(),
+                    hb.regions.clone(),
+                    socket.ip.as_str(),
+                    socket.port,
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            per_region,
+            vec![
+                ("ny", vec!["ny".to_string()], "10.0.0.1", 20000),
+                (
+                    "amsterdam",
+                    vec!["amsterdam".to_string()],
+                    "10.0.0.1",
+                    20001
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_record_heartbeat() {
+        let metrics = ShredMetrics::new();
+        let regions = vec!["ny".to_string(), "amsterdam".to_string()];
+        let start = Instant::now();
+
+        set_heartbeat_state(&metrics, &regions, HeartbeatState::Authenticating);
+        assert_eq!(
+            metrics.heartbeat_regions.get("ny").unwrap().state,
+            HeartbeatState::Authenticating
+        );
+
+        record_heartbeat(&metrics, &regions, Duration::from_millis(3), None, start);
+        record_heartbeat(&metrics, &regions, Duration::from_millis(4), None, start);
+        let ny = metrics.heartbeat_regions.get("ny").unwrap();
+        assert_eq!(ny.state, HeartbeatState::Disconnected);
+        assert_eq!(ny.consecutive_failures, 2);
+        assert_eq!(ny.ttl_ms, None);
+        drop(ny);
+
+        let later = start + Duration::from_secs(5);
+        record_heartbeat(
+            &metrics,
+            &regions,
+            Duration::from_millis(2),
+            Some(600),
+            later,
+        );
+        let amsterdam = metrics.heartbeat_regions.get("amsterdam").unwrap();
+        assert_eq!(amsterdam.state, HeartbeatState::Active);
+        assert_eq!(amsterdam.consecutive_failures, 0);
+        assert_eq!(amsterdam.last_success, later);
+        assert_eq!(amsterdam.last_rtt, Some(Duration::from_millis(2)));
+        assert_eq!(amsterdam.ttl_ms, Some(600));
+        assert_eq!(amsterdam.rtt_us.entries(), 3);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, thread::Builder, time::Duration};
+
+use crate::{
+    forwarder::{
+        recycle_vec, SendSocketOptions, SharedBatches, ShredMetrics, ShredSink, Transport, UdpSink,
+    },
+    priority::DestPriority,
+    socket::{Egress, RebindPolicy},
+};
+use arc_swap::ArcSwap;
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use dashmap::DashMap;
+use log::{error, info};
+
+/// Default batches queued per destination with `--isolated-send-threads`
+pub const DEFAULT_ISOLATED_SEND_QUEUE_CAPACITY: usize = 1_024;
+
+/// Filtered batches shared by every destination's queue, and how many of their packets to send.
+/// Discarded packets are skipped, so `max_packets` counts only packets that are sent
+struct QueuedBatches {
+    batches: Arc<SharedBatches>,
+    max_packets: usize,
+}
+
+impl QueuedBatches {
+    fn packets(&self) -> impl Iterator<Item = &[u8]> {
+        self.batches
+            .packet_batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .filter_map(|packet| packet.data(..))
+            .take(self.max_packets)
+    }
+}
+
+/// Sends to each UDP destination from its own thread and socket, so a destination whose sends block only delays and drops its own packets.
+/// Threads start on the first send to a destination and exit once it's removed and its queue drains
+pub struct IsolatedSendSink {
+    queue_capacity: usize,
+    socket_options: SendSocketOptions,
+    send_batch_size: usize,
+    slow_send_threshold: Option<Duration>,
+    rebind_policy: Arc<RebindPolicy>,
+    /// Destinations with their own `iface` or `src` options, applied when their thread starts
+    dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>,
+    /// Dropping a destination's sender disconnects its thread
+    queues: DashMap<SocketAddr, Sender<QueuedBatches>>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl IsolatedSendSink {
+    pub(crate) fn new(
+        queue_capacity: usize,
+        socket_options: SendSocketOptions,
+        send_batch_size: usize,
+        slow_send_threshold: Option<Duration>,
+        rebind_policy: Arc<RebindPolicy>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
+        Self {
+            queue_capacity,
+            socket_options,
+            send_batch_size,
+            slow_send_threshold,
+            rebind_policy,
+            dest_egress: Arc::default(),
+            queues: DashMap::new(),
+            metrics,
+        }
+    }
+
+    /// Binds the sockets of destinations in `dest_egress` with their egress overriding the socket options
+    pub(crate) fn with_dest_egress(
+        mut self,
+        dest_egress: Arc<ArcSwap<HashMap<SocketAddr, Egress>>>,
+    ) -> Self {
+        self.dest_egress = dest_egress;
+        self
+    }
+
+    /// Queues the first `max_packets` packets of `batches` for `dest`, dropping them if its queue is full
+    /// or backlogged past what `priority` tolerates.
+    /// The batches are kept while queued, so they stay counted in [crate::memory_guard::MemoryGuard]
+    pub fn send_shared(
+        &self,
+        dest: SocketAddr,
+        priority: DestPriority,
+        batches: &Arc<SharedBatches>,
+        max_packets: usize,
+    ) {
+        if max_packets == 0 {
+            return;
+        }
+        let queue = self
+            .queues
+            .entry(dest)
+            .or_try_insert_with(|| self.start_send_thread(dest).ok_or(()));
+        let Ok(queue) = queue else {
+            self.metrics
+                .record_forward(dest, Transport::Udp, 0, max_packets as u64);
+            return;
+        };
+        let queued = QueuedBatches {
+            batches: batches.clone(),
+            max_packets,
+        };
+        let backlog = queue.len() as f64 / self.queue_capacity.max(1) as f64;
+        let dropped = if priority.is_shed(backlog) {
+            queued
+        } else {
+            match queue.try_send(queued) {
+                Ok(()) => return,
+                Err(TrySendError::Full(queued) | TrySendError::Disconnected(queued)) => queued,
+            }
+        };
+        let num_dropped = dropped.packets().count() as u64;
+        self.metrics.record_send_queue_dropped(dest, num_dropped);
+        self.metrics.record_priority_dropped(priority, num_dropped);
+    }
+
+    /// Retires the threads of destinations no longer forwarded to over UDP, once they send what's queued
+    pub fn retain_destinations(&self, dest_sockets: &[SocketAddr]) {
+        self.queues.retain(|addr, _| dest_sockets.contains(addr));
+    }
+
+    /// Starts the send thread for `dest`, or None if its socket can't be bound
+    fn start_send_thread(&self, dest: SocketAddr) -> Option<Sender<QueuedBatches>> {
+        let socket_options = match self.dest_egress.load().get(&dest) {
+            Some(egress) => self.socket_options.with_dest_egress(egress),
+            None => self.socket_options.clone(),
+        };
+        let socket = socket_options
+            .bind()
+            .map_err(|e| error!("Failed to bind forwarding socket for {dest}. Error: {e}"))
+            .ok()?;
+        let udp_sink = UdpSink::new(
+            socket,
+            self.send_batch_size,
+            self.slow_send_threshold,
+            self.metrics.clone(),
+        )
+        .with_rebind(socket_options, &self.rebind_policy);
+        let (sender, receiver) = crossbeam_channel::bounded(self.queue_capacity);
+        let send_batch_size = self.send_batch_size;
+        let metrics = self.metrics.clone();
+        Builder::new()
+            .name("ssPxyDestTx".to_string())
+            .spawn(move || run_send_thread(dest, &receiver, &udp_sink, send_batch_size, &metrics))
+            .map_err(|e| error!("Failed to start send thread for {dest}. Error: {e}"))
+            .ok()?;
+        Some(sender)
+    }
+}
+
+/// Sends queued batches to `dest`, coalescing up to `send_batch_size` packets per send, until the queue disconnects
+fn run_send_thread(
+    dest: SocketAddr,
+    receiver: &Receiver<QueuedBatches>,
+    udp_sink: &UdpSink,
+    send_batch_size: usize,
+    metrics: &ShredMetrics,
+) {
+    info!("Started send thread for {dest}.");
+    // reused for every send, so only the first sends allocate
+    let mut queued = Vec::new();
+    let mut packets_scratch: Vec<&'static [u8]> = Vec::new();
+    while let Ok(first) = receiver.recv() {
+        let mut num_packets = first.max_packets;
+        queued.push(first);
+        while num_packets < send_batch_size {
+            let Ok(next) = receiver.try_recv() else {
+                break;
+            };
+            num_packets += next.max_packets;
+            queued.push(next);
+        }
+        metrics.record_send_queue_depth(dest, receiver.len());
+        let mut packets: Vec<&[u8]> = recycle_vec(std::mem::take(&mut packets_scratch));
+        packets.extend(queued.iter().flat_map(QueuedBatches::packets));
+        udp_sink.send(dest, &packets);
+        packets_scratch = recycle_vec(packets);
+        // drops this thread's references to the batches, the last one returns them to the recycler
+        queued.clear();
+    }
+    metrics.dest_send_queue_depth.remove(&dest);
+    info!("Exiting send thread for {dest}.");
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        sync::{atomic::Ordering, Arc},
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use solana_perf::packet::{Packet, PacketBatch};
+
+    use crate::{
+        forwarder::{SendSocketOptions, SharedBatches, ShredMetrics},
+        isolated_send::IsolatedSendSink,
+        priority::DestPriority,
+        socket::RebindPolicy,
+    };
+
+    fn new_sink(queue_capacity: usize, metrics: Arc<ShredMetrics>) -> IsolatedSendSink {
+        IsolatedSendSink::new(
+            queue_capacity,
+            SendSocketOptions::default(),
+            128,
+            None,
+            Arc::new(RebindPolicy::new(100, 10)),
+            metrics,
+        )
+    }
+
+    fn packet_batches(payloads: &[&[u8]]) -> Arc<SharedBatches> {
+        let packets = payloads
+            .iter()
+            .map(|payload| {
+                let mut packet = Packet::default();
+                packet.buffer_mut()[..payload.len()].copy_from_slice(payload);
+                packet.meta_mut().size = payload.len();
+                packet
+            })
+            .collect();
+        Arc::new(SharedBatches::new(vec![PacketBatch::new(packets)], vec![]))
+    }
+
+    fn wait_for(condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out");
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_isolated_send_sink() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let sink = new_sink(16, metrics.clone());
+        let dest = UdpSocket::bind("127.0.0.1:0").unwrap();
+        dest.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let dest_addr = dest.local_addr().unwrap();
+
+        // only the first `max_packets` are sent
+        sink.send_shared(
+            dest_addr,
+            DestPriority::Normal,
+            &packet_batches(&[b"one", b"two", b"three"]),
+            2,
+        );
+        let mut buf = [0u8; 16];
+        for expected in [&b"one"[..], b"two"] {
+            let len = dest.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], expected);
+        }
+        wait_for(|| {
+            metrics.aggregate_threads();
+            metrics
+                .dest_forwarded
+                .get(&dest_addr)
+                .is_some_and(|forwarded| *forwarded == (2, 0))
+        });
+        assert!(metrics.dest_send_queue_depth.contains_key(&dest_addr));
+
+        // removed destinations retire their thread
+        sink.retain_destinations(&[]);
+        assert!(sink.queues.is_empty());
+        wait_for(|| !metrics.dest_send_queue_depth.contains_key(&dest_addr));
+    }
+
+    #[test]
+    fn test_isolated_send_sink_full_queue() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let sink = new_sink(1, metrics.clone());
+        let dest_addr = SocketAddr::from(([127, 0, 0, 1], 9));
+
+        // queue a batch behind one the thread may be sending, without a receiver to drain it
+        let (sender, _receiver) = crossbeam_channel::bounded(1);
+        sink.queues.insert(dest_addr, sender);
+        let batches = packet_batches(&[b"one", b"two"]);
+        sink.send_shared(dest_addr, DestPriority::High, &batches, 2);
+        sink.send_shared(dest_addr, DestPriority::High, &batches, 1);
+        assert_eq!(*metrics.dest_send_queue_dropped.get(&dest_addr).unwrap(), 1);
+        // batches are shared with the queue, not copied
+        assert_eq!(Arc::strong_count(&batches), 2);
+        assert_eq!(
+            metrics
+                .priority_dropped(DestPriority::High)
+                .0
+                .load(Ordering::Relaxed),
+            1
+        );
+    }
+
+    #[test]
+    fn test_isolated_send_sink_sheds_by_priority() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let sink = new_sink(4, metrics.clone());
+        let dest_addr = SocketAddr::from(([127, 0, 0, 1], 9));
+
+        // half full: low priority is shed while normal and high still queue
+        let (sender, _receiver) = crossbeam_channel::bounded(4);
+        sink.queues.insert(dest_addr, sender);
+        let batches = packet_batches(&[b"one"]);
+        sink.send_shared(dest_addr, DestPriority::Normal, &batches, 1);
+        sink.send_shared(dest_addr, DestPriority::Normal, &batches, 1);
+        sink.send_shared(dest_addr, DestPriority::Low, &batches, 1);
+        sink.send_shared(dest_addr, DestPriority::Normal, &batches, 1);
+        // three quarters full: normal is shed too
+        sink.send_shared(dest_addr, DestPriority::Normal, &batches, 1);
+        sink.send_shared(dest_addr, DestPriority::High, &batches, 1);
+
+        let dropped = |priority| metrics.priority_dropped(priority).0.load(Ordering::Relaxed);
+        assert_eq!(dropped(DestPriority::Low), 1);
+        assert_eq!(dropped(DestPriority::Normal), 1);
+        assert_eq!(dropped(DestPriority::High), 0);
+        assert_eq!(sink.queues.get(&dest_addr).unwrap().len(), 4);
+    }
+}
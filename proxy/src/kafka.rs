@@ -0,0 +1,347 @@
+use std::{
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Sender, TrySendError};
+use solana_perf::packet::PacketBatch;
+
+use crate::{
+    forwarder::ShredMetrics,
+    memory_guard::{BufferKind, HeldBytes},
+    shred,
+};
+
+/// Header holding when the shred was received, in nanoseconds since the unix epoch
+pub const RECEIVED_AT_HEADER: &str = "received_at_unix_nanos";
+/// Header holding the address the shred was received from
+pub const SOURCE_ADDR_HEADER: &str = "source_addr";
+
+/// `security.protocol` used to connect to the brokers
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KafkaSecurityProtocol {
+    #[default]
+    Plaintext,
+    Ssl,
+    SaslPlaintext,
+    SaslSsl,
+}
+
+impl KafkaSecurityProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            KafkaSecurityProtocol::Plaintext => "plaintext",
+            KafkaSecurityProtocol::Ssl => "ssl",
+            KafkaSecurityProtocol::SaslPlaintext => "sasl_plaintext",
+            KafkaSecurityProtocol::SaslSsl => "sasl_ssl",
+        }
+    }
+}
+
+/// Where and how to publish shreds, from the `kafka-*` args
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KafkaConfig {
+    pub brokers: String,
+    pub topic: String,
+    pub security_protocol: KafkaSecurityProtocol,
+    pub sasl_mechanism: Option<String>,
+    pub sasl_username: Option<String>,
+    /// Read when the producer is created, the password is never logged
+    pub sasl_password_file: Option<PathBuf>,
+    pub ssl_ca_location: Option<PathBuf>,
+    pub queue_capacity: usize,
+}
+
+/// Shred queued for publishing
+pub struct KafkaMessage {
+    pub received_at: SystemTime,
+    pub src: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+impl KafkaMessage {
+    /// Slot, so every shred of a slot lands on the same partition. None for packets that don't parse as shreds
+    pub fn key(&self) -> Option<String> {
+        shred::get_slot(&self.payload).map(|slot| slot.to_string())
+    }
+
+    pub fn received_at_unix_nanos(&self) -> u128 {
+        self.received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    }
+}
+
+/// Queues shreds for the Kafka publisher thread, dropping them when it can't keep up so forwarding never waits on the brokers
+#[derive(Clone)]
+pub struct KafkaTap {
+    message_sender: Sender<(KafkaMessage, HeldBytes)>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl KafkaTap {
+    /// Queues packets that weren't discarded
+    pub fn record(&self, packet_batches: &[PacketBatch], received_at: SystemTime) {
+        packet_batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .filter_map(|packet| Some((packet.meta().socket_addr(), packet.data(..)?)))
+            .for_each(|(src, payload)| {
+                let message = KafkaMessage {
+                    received_at,
+                    src,
+                    payload: payload.to_vec(),
+                };
+                let held = self
+                    .metrics
+                    .memory_guard
+                    .hold(BufferKind::Kafka, message.payload.len());
+                match self.message_sender.try_send((message, held)) {
+                    Ok(()) => {}
+                    Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                        self.metrics.kafka_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use publisher::start_kafka_publisher_thread;
+
+/// Without the `kafka` feature, `kafka-brokers` is rejected when validating args
+#[cfg(not(feature = "kafka"))]
+pub fn start_kafka_publisher_thread(
+    _config: KafkaConfig,
+    _flush_timeout: std::time::Duration,
+    _metrics: Arc<ShredMetrics>,
+    _exit: Arc<std::sync::atomic::AtomicBool>,
+) -> Result<(KafkaTap, std::thread::JoinHandle<()>), crate::ShredstreamProxyError> {
+    Err(crate::ShredstreamProxyError::InvalidArguments(
+        "Invalid arguments provided, --kafka-brokers requires building with the `kafka` feature."
+            .to_string(),
+    ))
+}
+
+#[cfg(feature = "kafka")]
+mod publisher {
+    use std::{
+        fs,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    };
+
+    use crossbeam_channel::RecvTimeoutError;
+    use log::{info, warn};
+    use rdkafka::{
+        config::ClientConfig,
+        error::{KafkaError, RDKafkaErrorCode},
+        message::{Header, OwnedHeaders},
+        producer::{BaseRecord, DeliveryResult, Producer, ProducerContext, ThreadedProducer},
+        ClientContext,
+    };
+
+    use crate::{
+        forwarder::ShredMetrics,
+        kafka::{KafkaConfig, KafkaMessage, KafkaTap, RECEIVED_AT_HEADER, SOURCE_ADDR_HEADER},
+        memory_guard::HeldBytes,
+        ShredstreamProxyError,
+    };
+
+    /// How often the publisher wakes to check for exit while idle
+    const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Counts broker acknowledgements and delivery failures
+    struct PublisherContext {
+        metrics: Arc<ShredMetrics>,
+    }
+
+    impl ClientContext for PublisherContext {}
+
+    impl ProducerContext for PublisherContext {
+        type DeliveryOpaque = ();
+
+        fn delivery(&self, delivery_result: &DeliveryResult<'_>, _delivery_opaque: ()) {
+            match delivery_result {
+                Ok(_) => {
+                    self.metrics.kafka_published.fetch_add(1, Ordering::Relaxed);
+                }
+                // not logged, a broker outage fails every queued shred
+                Err(_) => {
+                    self.metrics
+                        .kafka_delivery_failed
+                        .fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// Publishes shreds sent through the returned tap to `config.topic`.
+    /// On exit, queued shreds are handed to the producer and flushed for up to `flush_timeout`
+    pub fn start_kafka_publisher_thread(
+        config: KafkaConfig,
+        flush_timeout: Duration,
+        metrics: Arc<ShredMetrics>,
+        exit: Arc<AtomicBool>,
+    ) -> Result<(KafkaTap, JoinHandle<()>), ShredstreamProxyError> {
+        let producer = create_producer(&config, metrics.clone())?;
+        let (message_sender, message_receiver) =
+            crossbeam_channel::bounded::<(KafkaMessage, HeldBytes)>(config.queue_capacity);
+        info!(
+            "Publishing received shreds to Kafka topic {} on {}.",
+            config.topic, config.brokers
+        );
+
+        let publisher_metrics = metrics.clone();
+        let hdl = Builder::new()
+            .name("ssPxyKafka".to_string())
+            .spawn(move || {
+                let metrics = publisher_metrics;
+                // drain queued shreds before exiting
+                while !exit.load(Ordering::Relaxed) || !message_receiver.is_empty() {
+                    match message_receiver.recv_timeout(EXIT_CHECK_INTERVAL) {
+                        Ok((message, _held)) => {
+                            publish(&producer, &config.topic, &message, &metrics)
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                if let Err(e) = producer.flush(flush_timeout) {
+                    let in_flight = producer.in_flight_count();
+                    warn!("Failed to flush {in_flight} shreds to Kafka before exiting. Error: {e}");
+                    metrics
+                        .kafka_delivery_failed
+                        .fetch_add(in_flight.max(0) as u64, Ordering::Relaxed);
+                }
+            })?;
+
+        Ok((
+            KafkaTap {
+                message_sender,
+                metrics,
+            },
+            hdl,
+        ))
+    }
+
+    fn create_producer(
+        config: &KafkaConfig,
+        metrics: Arc<ShredMetrics>,
+    ) -> Result<ThreadedProducer<PublisherContext>, ShredstreamProxyError> {
+        let mut client_config = ClientConfig::new();
+        client_config
+            .set("bootstrap.servers", &config.brokers)
+            .set("security.protocol", config.security_protocol.as_str())
+            .set(
+                "queue.buffering.max.messages",
+                config.queue_capacity.to_string(),
+            );
+        if let Some(sasl_mechanism) = &config.sasl_mechanism {
+            client_config.set("sasl.mechanism", sasl_mechanism);
+        }
+        if let Some(sasl_username) = &config.sasl_username {
+            client_config.set("sasl.username", sasl_username);
+        }
+        let mut sasl_password = None;
+        if let Some(sasl_password_file) = &config.sasl_password_file {
+            let password = fs::read_to_string(sasl_password_file).map_err(|e| {
+                ShredstreamProxyError::InvalidArguments(format!(
+                    "Failed to read --kafka-sasl-password-file {sasl_password_file:?}: {e}"
+                ))
+            })?;
+            let password = password.trim().to_string();
+            client_config.set("sasl.password", &password);
+            sasl_password = Some(password);
+        }
+        if let Some(ssl_ca_location) = &config.ssl_ca_location {
+            client_config.set("ssl.ca.location", ssl_ca_location.to_string_lossy());
+        }
+        client_config
+            .create_with_context(PublisherContext { metrics })
+            .map_err(|e| {
+                let mut e = e.to_string();
+                // librdkafka may echo invalid values back
+                if let Some(password) = sasl_password.filter(|password| !password.is_empty()) {
+                    e = e.replace(&password, "<redacted>");
+                }
+                ShredstreamProxyError::InvalidArguments(format!(
+                    "Failed to create Kafka producer: {e}"
+                ))
+            })
+    }
+
+    /// Hands `message` to the producer without blocking, dropping it if the producer's queue is full
+    fn publish(
+        producer: &ThreadedProducer<PublisherContext>,
+        topic: &str,
+        message: &KafkaMessage,
+        metrics: &ShredMetrics,
+    ) {
+        let received_at = message.received_at_unix_nanos().to_string();
+        let src = message.src.to_string();
+        let headers = OwnedHeaders::new()
+            .insert(Header {
+                key: RECEIVED_AT_HEADER,
+                value: Some(&received_at),
+            })
+            .insert(Header {
+                key: SOURCE_ADDR_HEADER,
+                value: Some(&src),
+            });
+        let key = message.key();
+        let mut record = BaseRecord::to(topic)
+            .payload(&message.payload)
+            .headers(headers);
+        if let Some(key) = &key {
+            record = record.key(key);
+        }
+        match producer.send(record) {
+            Ok(()) => {}
+            Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                metrics.kafka_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                metrics
+                    .kafka_delivery_failed
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        str::FromStr,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use crate::{kafka::KafkaMessage, shred::tests::new_data_shred};
+
+    #[test]
+    fn test_kafka_message() {
+        let message = KafkaMessage {
+            received_at: UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789),
+            src: SocketAddr::from_str("10.0.0.1:8001").unwrap(),
+            payload: new_data_shred(42, 0, 0, false, &[]),
+        };
+        assert_eq!(message.key().as_deref(), Some("42"));
+        assert_eq!(message.received_at_unix_nanos(), 1_700_000_000_123_456_789);
+
+        let message = KafkaMessage {
+            payload: vec![1, 2, 3],
+            ..message
+        };
+        assert_eq!(message.key(), None);
+    }
+}
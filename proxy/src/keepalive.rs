@@ -0,0 +1,211 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+use solana_perf::packet::PacketBatch;
+
+use crate::{forwarder::ShredMetrics, socket};
+
+/// Starts every keepalive datagram, followed by a sequence number. Far shorter than any shred, so never mistaken for one
+pub const KEEPALIVE_MAGIC: &[u8; 8] = b"SSPXYKA1";
+const KEEPALIVE_LEN: usize = KEEPALIVE_MAGIC.len() + 8;
+
+/// An `--upstream-keepalive` entry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpstreamKeepalive {
+    pub addr: SocketAddr,
+    pub interval: Duration,
+}
+
+/// Parses `<ip:port>,<interval_ms>`, eg. `10.0.0.1:20000,5000`
+pub fn parse_upstream_keepalive(arg: &str) -> Result<UpstreamKeepalive, String> {
+    let (addr, interval_ms) = arg.trim().split_once(',').ok_or_else(|| {
+        format!("Invalid upstream keepalive {arg:?}, expected <ip:port>,<interval_ms>.")
+    })?;
+    let addr = addr
+        .trim()
+        .parse::<SocketAddr>()
+        .map_err(|_| format!("Invalid upstream keepalive address {addr:?}, expected an ip:port such as `10.0.0.1:20000`."))?;
+    let interval_ms = interval_ms
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|interval_ms| *interval_ms > 0)
+        .ok_or_else(|| {
+            format!("Invalid upstream keepalive interval {interval_ms:?}, expected milliseconds greater than 0.")
+        })?;
+    Ok(UpstreamKeepalive {
+        addr,
+        interval: Duration::from_millis(interval_ms),
+    })
+}
+
+fn keepalive_payload(sequence: u64) -> [u8; KEEPALIVE_LEN] {
+    let mut payload = [0u8; KEEPALIVE_LEN];
+    payload[..KEEPALIVE_MAGIC.len()].copy_from_slice(KEEPALIVE_MAGIC);
+    payload[KEEPALIVE_MAGIC.len()..].copy_from_slice(&sequence.to_be_bytes());
+    payload
+}
+
+pub fn is_keepalive(data: &[u8]) -> bool {
+    data.len() == KEEPALIVE_LEN && data.starts_with(KEEPALIVE_MAGIC)
+}
+
+/// Marks keepalives from downstream proxies as discarded, so they're never deduped or forwarded. Returns how many were found
+pub fn discard_keepalives(packet_batches: &mut [PacketBatch]) -> u64 {
+    let mut num_keepalives = 0;
+    packet_batches
+        .iter_mut()
+        .flat_map(|batch| batch.iter_mut())
+        .filter(|packet| packet.data(..).is_some_and(is_keepalive))
+        .for_each(|packet| {
+            packet.meta_mut().set_discard(true);
+            num_keepalives += 1;
+        });
+    num_keepalives
+}
+
+struct Upstream {
+    keepalive: UpstreamKeepalive,
+    next_send: Instant,
+    failing: bool,
+}
+
+/// Sends keepalives from a listen socket to each upstream, so a NAT in front of this proxy keeps the mapping upstreams forward to.
+/// Driven by the receive thread, which wakes at least every `recv-poll-timeout-ms`, so keepalives are late by at most that much
+pub struct KeepaliveSender {
+    upstreams: Vec<Upstream>,
+    sequence: u64,
+}
+
+impl KeepaliveSender {
+    pub fn new(keepalives: &[UpstreamKeepalive]) -> Self {
+        let now = Instant::now();
+        Self {
+            upstreams: keepalives
+                .iter()
+                .map(|keepalive| Upstream {
+                    keepalive: *keepalive,
+                    next_send: now,
+                    failing: false,
+                })
+                .collect(),
+            sequence: 0,
+        }
+    }
+
+    /// Sends to upstreams whose interval has elapsed
+    pub fn send_due(&mut self, socket: &UdpSocket, metrics: &ShredMetrics) {
+        let now = Instant::now();
+        let ipv6_socket = socket.local_addr().is_ok_and(|addr| addr.is_ipv6());
+        for upstream in self.upstreams.iter_mut() {
+            if now < upstream.next_send {
+                continue;
+            }
+            upstream.next_send = now + upstream.keepalive.interval;
+            let addr = upstream.keepalive.addr;
+            self.sequence += 1;
+            match socket.send_to(
+                &keepalive_payload(self.sequence),
+                socket::send_addr(ipv6_socket, addr),
+            ) {
+                Ok(_) => {
+                    metrics.keepalive_sent.fetch_add(1, Ordering::Relaxed);
+                    if upstream.failing {
+                        info!("Sending keepalives to upstream {addr} again.");
+                        upstream.failing = false;
+                    }
+                }
+                Err(e) => {
+                    metrics
+                        .keepalive_send_failed
+                        .fetch_add(1, Ordering::Relaxed);
+                    if !upstream.failing {
+                        warn!("Failed to send keepalive to upstream {addr}, the NAT mapping it forwards to may expire. Error: {e}");
+                        upstream.failing = true;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, sync::atomic::Ordering, time::Duration};
+
+    use solana_perf::packet::{Packet, PacketBatch};
+
+    use crate::{
+        forwarder::ShredMetrics,
+        keepalive::{
+            discard_keepalives, is_keepalive, parse_upstream_keepalive, KeepaliveSender,
+            UpstreamKeepalive,
+        },
+    };
+
+    #[test]
+    fn test_parse_upstream_keepalive() {
+        assert_eq!(
+            parse_upstream_keepalive("10.0.0.1:20000,5000"),
+            Ok(UpstreamKeepalive {
+                addr: "10.0.0.1:20000".parse().unwrap(),
+                interval: Duration::from_secs(5),
+            })
+        );
+        assert_eq!(
+            parse_upstream_keepalive(" [::1]:20000 , 250 ")
+                .unwrap()
+                .interval,
+            Duration::from_millis(250)
+        );
+        for invalid in [
+            "10.0.0.1:20000",
+            "10.0.0.1,5000",
+            "10.0.0.1:20000,0",
+            "10.0.0.1:20000,5s",
+        ] {
+            assert!(parse_upstream_keepalive(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_keepalives_sent_and_discarded() {
+        let upstream = UdpSocket::bind("127.0.0.1:0").unwrap();
+        upstream
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let listen_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let metrics = ShredMetrics::new();
+        let mut keepalive_sender = KeepaliveSender::new(&[UpstreamKeepalive {
+            addr: upstream.local_addr().unwrap(),
+            interval: Duration::from_secs(60),
+        }]);
+
+        // sent on start, then not again until the interval elapses
+        keepalive_sender.send_due(&listen_socket, &metrics);
+        keepalive_sender.send_due(&listen_socket, &metrics);
+        assert_eq!(metrics.keepalive_sent.load(Ordering::Relaxed), 1);
+        let mut buf = [0u8; 64];
+        let (len, from) = upstream.recv_from(&mut buf).unwrap();
+        assert_eq!(from, listen_socket.local_addr().unwrap());
+        assert!(is_keepalive(&buf[..len]));
+
+        let packet = |data: &[u8]| {
+            let mut packet = Packet::default();
+            packet.buffer_mut()[..data.len()].copy_from_slice(data);
+            packet.meta_mut().size = data.len();
+            packet
+        };
+        let mut packet_batches = vec![PacketBatch::new(vec![
+            packet(&buf[..len]),
+            packet(&[1u8; 1_000]),
+        ])];
+        assert_eq!(discard_keepalives(&mut packet_batches), 1);
+        assert!(packet_batches[0][0].meta().discard());
+        assert!(!packet_batches[0][1].meta().discard());
+    }
+}
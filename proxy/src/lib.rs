@@ -0,0 +1,3983 @@
+//! Receives shreds from Jito's block engine, or any UDP source, and forwards them to local consumers.
+//!
+//! [ShredstreamProxyBuilder] embeds the proxy in another process. The `jito-shredstream-proxy` binary is a thin wrapper around it,
+//! adding the CLI, config file, and process-wide signal and panic handling.
+
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{self, Error, ErrorKind, Read},
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::Duration,
+};
+
+use clap::arg;
+use crossbeam_channel::{RecvError, Sender};
+use ipnet::IpNet;
+use itertools::Itertools;
+use log::*;
+use rustls::pki_types::{pem::PemObject, CertificateDer, ServerName};
+use solana_client::client_error::{
+    reqwest::{
+        self,
+        header::{HeaderName, HeaderValue},
+    },
+    ClientError,
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+};
+use thiserror::Error;
+use tonic::{transport::Certificate, Status};
+
+pub use crate::builder::{ShredstreamProxy, ShredstreamProxyBuilder};
+pub use crate::heartbeat::set_heartbeats_paused;
+#[cfg(feature = "subscriber")]
+pub use crate::subscriber::{ShredCallback, ShredSubscriber};
+use crate::{
+    alerts::{AlertConfig, AlertWebhookFormat},
+    archive::ArchiveConfig,
+    chaos::ChaosConfig,
+    conflicts::ConflictDetectorConfig,
+    events::{EventsConfig, EventsFormat},
+    forwarder::{
+        DedupMode, EndpointDiscovery, ForwardShredTypes, ForwarderThreads, RxTimestampSource,
+    },
+    health::HealthCheckMode,
+    kafka::{KafkaConfig, KafkaSecurityProtocol},
+    keepalive::UpstreamKeepalive,
+    listener::{ListenerConfig, ListenerSpec},
+    metrics_backend::{MetricsBackendKind, StatsdDialect},
+    mirror::{MirrorSampleUnit, MirrorSpec},
+    packet_channel::DropPolicy,
+    priority::DestPriority,
+    shard::{ShardGroupSpec, ShardMembers},
+    spill::SpillConfig,
+    stake_discovery::{GeoTable, RpcDiscoveryConfig, RpcDiscoveryFilter},
+    token_authenticator::{BlockEngineConnectionError, GrpcChannelConfig},
+    tunnel::{TunnelConfig, TunnelDest},
+    validators::ValidatorResolverConfig,
+};
+
+mod admin;
+mod admin_grpc;
+mod affinity;
+mod alerts;
+pub mod archive;
+pub mod bench;
+pub mod build_info;
+mod builder;
+pub mod chaos;
+mod conflicts;
+pub mod crash_report;
+mod deshred;
+mod discovery_state;
+pub mod encryption;
+mod events;
+pub mod forwarder;
+pub mod health;
+mod heartbeat;
+mod isolated_send;
+pub mod kafka;
+pub mod keepalive;
+pub mod listener;
+pub mod logging;
+pub mod memory_guard;
+pub mod metrics_backend;
+pub mod mirror;
+pub mod mock_block_engine;
+pub mod otel;
+pub mod packet_channel;
+mod pcap;
+pub mod priority;
+mod privileges;
+mod probes;
+mod prometheus;
+pub mod quic;
+pub mod rate_limit;
+pub mod regions;
+mod reload;
+mod replay;
+pub mod shard;
+mod shred;
+pub mod signals;
+mod slot_coverage;
+mod slot_latency;
+mod socket;
+pub mod spill;
+mod stake_discovery;
+// only reachable through the builder with the `subscriber` feature
+#[cfg_attr(not(feature = "subscriber"), allow(dead_code))]
+mod subscriber;
+pub mod supervisor;
+mod systemd;
+mod token_authenticator;
+mod token_cache_file;
+pub mod tunnel;
+pub mod unix;
+pub mod validate;
+mod validators;
+mod watchdog;
+mod ws;
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct ReplayArgs {
+    /// Pcap capture to replay, such as one written by `--record-pcap` or `tcpdump -w`.
+    #[arg(long, env)]
+    pub input: PathBuf,
+
+    /// Multiplier applied to the captured inter-packet timing. Eg. `2.0` replays twice as fast. 0 replays as fast as possible.
+    #[arg(long, env, default_value_t = 1.0)]
+    pub speed: f64,
+
+    /// Replay the capture repeatedly until shutdown, for sustained load generation.
+    /// Each pass starts with a fresh deduper so packets aren't dropped as duplicates, lower `--deduper-num-bits` for short captures.
+    #[arg(long = "loop", env)]
+    pub loop_replay: bool,
+
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(clap::Args, Clone, Debug)]
+#[command(group(
+    clap::ArgGroup::new("auth_keypair_source")
+        .required(true)
+        .args(["auth_keypair", "auth_keypair_base58", "auth_keypair_stdin"])
+))]
+pub struct ShredstreamArgs {
+    /// Address for Jito Block Engine.
+    /// See https://jito-labs.gitbook.io/mev/searcher-resources/block-engine#connection-details
+    /// Accepts a comma separated list in order of preference, failing over to the next after `block-engine-failover-threshold` consecutive failures.
+    #[arg(long, env, value_delimiter = ',', required(true))]
+    pub block_engine_url: Vec<String>,
+
+    /// Consecutive connection or heartbeat failures before failing over to the next block engine url.
+    #[arg(long, env, default_value_t = 3)]
+    pub block_engine_failover_threshold: u32,
+
+    /// Seconds after failing over before retrying the primary block engine url.
+    #[arg(long, env, default_value_t = 600)]
+    pub block_engine_primary_retry_secs: u64,
+
+    /// Manual override for auth service address. For internal use.
+    #[arg(long, env)]
+    pub auth_url: Option<String>,
+
+    /// Path to keypair file used to authenticate with the backend.
+    #[arg(long, env)]
+    pub auth_keypair: Option<PathBuf>,
+
+    /// Name of an environment variable holding the auth keypair, instead of `--auth-keypair`.
+    /// Either base58 encoded or the JSON byte array written by `solana-keygen`.
+    #[arg(long, env, value_name = "ENV_VAR")]
+    pub auth_keypair_base58: Option<String>,
+
+    /// Read the auth keypair from stdin, instead of `--auth-keypair`.
+    /// Either base58 encoded or the JSON byte array written by `solana-keygen`.
+    #[arg(long)]
+    pub auth_keypair_stdin: bool,
+
+    /// Desired regions to receive heartbeats from.
+    /// Receives `n` different streams. Requires at least 1 region, comma separated.
+    /// Checked against the regions the block engine offers at startup, see `--list-regions`.
+    #[arg(
+        long,
+        env,
+        value_delimiter = ',',
+        required_unless_present = "list_regions"
+    )]
+    pub desired_regions: Vec<String>,
+
+    /// Print the regions offered by the first `block-engine-url` and exit.
+    #[arg(long)]
+    pub list_regions: bool,
+
+    /// Listen on a separate port per desired region, `src-bind-port` + region index, to tag received shreds and heartbeats by region.
+    /// Shreds are still deduped across regions before forwarding.
+    #[arg(long, env, default_value_t = false)]
+    pub region_ports: bool,
+
+    /// Re-register with the block engine, re-authenticating from scratch, after no shreds arrive for this many seconds while heartbeats succeed.
+    /// Only armed once a heartbeat succeeds. Use `0` to disable, such as when the stream is expected to go quiet.
+    #[arg(long, env, default_value_t = 120)]
+    pub stall_timeout_secs: u64,
+
+    /// `GET /readyz` fails unless a heartbeat succeeded within this many seconds.
+    #[arg(long, env, default_value_t = 30)]
+    pub ready_heartbeat_max_age_secs: u64,
+
+    /// Refresh the block engine access token this many seconds before it expires, without interrupting the stream.
+    /// Failed refreshes are retried until the token expires.
+    #[arg(long, env, default_value_t = token_authenticator::DEFAULT_TOKEN_REFRESH_MARGIN.as_secs())]
+    pub token_refresh_margin_secs: u64,
+
+    /// File to save block engine access and refresh tokens to, readable only by the owner, so restarts reuse them instead of authenticating again.
+    /// Saved tokens are keyed by auth url and keypair, and reused only while they expire in more than `token-refresh-margin-secs`.
+    /// Written after dropping privileges, so it must be writable by `run-as-user` and resolve inside `chroot`.
+    #[arg(long, env)]
+    pub token_cache_file: Option<PathBuf>,
+
+    /// Wait a random time up to this many milliseconds before authenticating at startup without cached tokens,
+    /// so a fleet of proxies restarted together doesn't authenticate all at once. Use `0` to disable.
+    #[arg(long, env, default_value_t = 2_000)]
+    pub initial_auth_jitter_ms: u64,
+
+    /// Fail connecting to the block engine or auth service after this many milliseconds, instead of waiting on the OS's TCP connect timeout.
+    #[arg(long, env)]
+    pub grpc_connect_timeout_ms: Option<u64>,
+
+    /// Send HTTP/2 keepalive pings to the block engine and auth service at this interval, even while idle.
+    /// Detects connections dropped silently, such as by a firewall expiring idle flows, which otherwise go unnoticed for minutes. Disabled by default.
+    #[arg(long, env)]
+    pub grpc_keepalive_interval_ms: Option<u64>,
+
+    /// Close the connection if a keepalive ping isn't acknowledged within this many milliseconds, 20s by default.
+    /// Requires `--grpc-keepalive-interval-ms`.
+    #[arg(long, env)]
+    pub grpc_keepalive_timeout_ms: Option<u64>,
+
+    /// PEM file of CA certificates to trust for `https` block engine and auth urls, in addition to the system's, such as an internal mirror's self-signed CA.
+    #[arg(long, env)]
+    pub grpc_tls_ca_cert: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub common_args: CommonArgs,
+}
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct CommonArgs {
+    /// Address where Shredstream proxy listens. `::` listens on both IPv4 and IPv6.
+    #[arg(long, env, default_value_t = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)))]
+    pub src_bind_addr: IpAddr,
+
+    /// Port where Shredstream proxy listens. Use `0` for random ephemeral port.
+    #[arg(long, env, default_value_t = 20_000)]
+    pub src_bind_port: u16,
+
+    /// Static set of IP:Port where Shredstream proxy forwards shreds to, comma separated.
+    /// Eg. `127.0.0.1:8001,10.0.0.1:8001`.
+    /// Prefix with `quic://` to forward over QUIC to a proxy running `quic-receive`, eg. `quic://10.0.0.1:20001`.
+    /// Prefix with `tcp://` or `tls://` to forward over a persistent connection to a proxy running `tcp-receive`, for networks that block or police UDP, eg. `tls://relay.example.com:20002`.
+    /// Prefix with `unix://` to send to a unix datagram socket of a consumer on the same host, skipping the loopback UDP stack, eg. `unix:///run/consumer.sock`, or `unix://@consumer` for the abstract namespace. Packets beyond the consumer's backlog, capped by `net.unix.max_dgram_qlen`, are dropped rather than blocking.
+    /// Prefix with `enc://` and append `?key-file=<path>` to seal each packet with XChaCha20-Poly1305 for a `forward-only` proxy running with the same `decrypt-key-file`, eg. `enc://10.0.0.1:20000?key-file=/etc/shredstream/peer.key`.
+    /// Append `?rate=<n>pps` to send at most `n` packets per second to that destination, dropping the rest, eg. `10.0.0.1:8001?rate=5000pps`.
+    /// Append `?priority=high|normal|low` to send to it before or after other destinations. Once a forwarder's queue backs up,
+    /// sends to `low` destinations are dropped from half full and to `normal` ones from three quarters full, while `high` ones are only dropped with everything else.
+    /// Append `?spill=true` to a UDP destination to spill its shreds to `spill-dir` while it fails health checks or is paused via the admin API,
+    /// and replay them once it's back, eg. `10.0.0.1:8001?spill=true`.
+    /// Hosts without a port are sent to on `default-dest-port`, eg. `10.0.0.1` or `quic://relay.example.com`. Entries resolving to the same address are only sent to once.
+    // Note: store the original string, so we can do hostname resolution when refreshing destinations
+    #[arg(long, env, value_delimiter = ',', value_parser = resolve_hostname_port)]
+    pub dest_ip_ports: Vec<(SocketAddr, String)>,
+
+    /// Groups of destinations that split shreds between their members instead of each getting every shred, semicolon separated.
+    /// Eg. `shard(10.0.0.1:9000,10.0.0.2:9000);shard(10.0.0.3:9000,10.0.0.4:9000)`.
+    /// Each shred is sent to one member, chosen by hashing its slot and index. Append `?shard-by=slot` to send every shred of a slot to the same member.
+    /// Use `shard(discovered)` to shard across the endpoints from `endpoint-discovery-url` instead of sending them every shred.
+    /// Members leaving or joining, such as on a discovery refresh or failed health check, only move the shreds they owned.
+    #[arg(long = "dest-shard-group", env, value_name = "shard(HOST:PORT,...)", value_delimiter = ';', value_parser = shard::parse_shard_group)]
+    pub dest_shard_groups: Vec<ShardGroupSpec>,
+
+    /// Mirrors sent a sample of forwarded shreds, such as a staging environment, as `HOST:PORT@FRACTION` with the fraction in (0, 1].
+    /// Eg. `10.0.0.1:8001@0.1` mirrors a tenth of shreds. Shreds are sampled by hashing their slot and index, so every proxy mirrors the same ones,
+    /// and a smaller fraction's sample is part of a larger one's. Append `?sample-unit=fecset` to sample whole FEC sets by hashing their slot and FEC set index.
+    /// Mirrors are sent to after every other destination, from their own socket, and are dropped first once a forwarder's queue is a quarter full.
+    #[arg(long = "mirror-dest", env, value_name = "HOST:PORT@FRACTION", value_delimiter = ',', value_parser = mirror::parse_mirror_dest)]
+    pub mirror_dests: Vec<MirrorSpec>,
+
+    /// Upstream proxy to send a small keepalive datagram from each listen port every `INTERVAL_MS`, repeatable or semicolon separated.
+    /// Eg. `10.0.0.1:20000,15000`. Holds open the NAT mapping an upstream forwards to when this `forward-only` proxy is behind NAT,
+    /// as long as the NAT filters inbound packets by the upstream's address rather than its port, since the upstream forwards from other ports.
+    /// Upstreams drop keepalives before dedup, counting them. Sent between reads, so up to `recv-poll-timeout-ms` late.
+    #[arg(long = "upstream-keepalive", env, value_name = "IP:PORT,INTERVAL_MS", value_delimiter = ';', value_parser = keepalive::parse_upstream_keepalive)]
+    pub upstream_keepalives: Vec<UpstreamKeepalive>,
+
+    /// Additional port to listen on with its own destinations, repeatable. Eg. `port=20001,dests=10.0.0.1:8001;10.0.0.2:8001,name=fleet-b`.
+    /// Each listener has its own receive and send threads and deduper, so shreds received on it are only forwarded to its destinations,
+    /// and never deduped against other ports. Destinations are plain UDP `host:port`, resolved at startup. Metrics are tagged by `name`, defaulting to the port.
+    /// Port 0 binds an ephemeral port, and needs a `name`.
+    #[arg(long = "listener", env, value_name = "port=PORT,dests=HOST:PORT;...", value_parser = listener::parse_listener)]
+    pub listeners: Vec<ListenerSpec>,
+
+    /// File with a 32 byte key as 64 hex characters, eg. from `openssl rand -hex 32`, to unseal packets from upstreams forwarding to `enc://` destinations.
+    /// Every packet received must be sealed with it, others are dropped before dedup and counted. `forward-only` only.
+    /// Re-read on `SIGHUP` along with `enc://` key files, opening packets with the replaced key too until the next reload, so reload receivers before senders when rotating keys.
+    #[arg(long, env)]
+    pub decrypt_key_file: Option<PathBuf>,
+
+    /// Packets buffered per `tcp://` or `tls://` destination while it reconnects or can't keep up. The oldest are dropped beyond this.
+    #[arg(long, env, default_value_t = 65_536)]
+    pub tunnel_buffer_packets: usize,
+
+    /// PEM file of CA certificates to verify `tls://` destinations with. Defaults to the system's.
+    #[arg(long, env)]
+    pub tunnel_tls_ca_cert: Option<PathBuf>,
+
+    /// Server name sent and verified for `tls://` destinations. Defaults to each destination's hostname.
+    #[arg(long, env)]
+    pub tunnel_tls_server_name: Option<String>,
+
+    /// PEM certificate chain presented to `tls://` destinations whose `tcp-receive` requires client certificates. Requires `tunnel-tls-client-key`.
+    #[arg(long, env, requires = "tunnel_tls_client_key")]
+    pub tunnel_tls_client_cert: Option<PathBuf>,
+
+    /// PEM private key for `tunnel-tls-client-cert`.
+    #[arg(long, env, requires = "tunnel_tls_client_cert")]
+    pub tunnel_tls_client_key: Option<PathBuf>,
+
+    /// Http JSON endpoint to dynamically get IPs for Shredstream proxy to forward shreds.
+    /// Returns an array of IPs and/or `{"ip": "1.2.3.4", "port": 8001}` objects, with the port defaulting to `discovered-endpoints-port`.
+    /// Endpoints are then set-union with `dest-ip-ports`.
+    #[arg(long, env)]
+    pub endpoint_discovery_url: Option<String>,
+
+    /// Port to send shreds to for hosts fetched via `endpoint-discovery-url` without their own port.
+    /// Port can be found using `scripts/get_tvu_port.sh`.
+    /// See https://jito-labs.gitbook.io/mev/searcher-services/shredstream#running-shredstream
+    #[arg(long, env)]
+    pub discovered_endpoints_port: Option<u16>,
+
+    /// Port to send to for `dest-ip-ports` entries without one. Defaults to `discovered-endpoints-port` when set, otherwise 8001.
+    #[arg(long, env)]
+    pub default_dest_port: Option<u16>,
+
+    /// Header sent with every `endpoint-discovery-url` request, as `Name: Value`. Can be repeated.
+    #[arg(long = "endpoint-discovery-header", env, value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub endpoint_discovery_headers: Vec<(String, String)>,
+
+    /// File holding a token sent as `Authorization: Bearer` with every `endpoint-discovery-url` request.
+    /// Read on every request, so rotated tokens are picked up without a restart.
+    #[arg(long, env)]
+    pub endpoint_discovery_bearer_token_file: Option<PathBuf>,
+
+    /// Interval between `endpoint-discovery-url` requests, in milliseconds.
+    /// Requests send `If-None-Match`/`If-Modified-Since` from the last response, so an unchanged response is cheap.
+    #[arg(long, env, default_value_t = 30_000)]
+    pub endpoint_discovery_interval_ms: u64,
+
+    /// File to save the last successful `endpoint-discovery-url` response to, written atomically after every success.
+    /// Loaded at startup if the first request fails, so a restart during a discovery outage still forwards to the last known endpoints.
+    #[arg(long, env)]
+    pub endpoint_discovery_state_file: Option<PathBuf>,
+
+    /// Max age of `endpoint-discovery-state-file`, in seconds. Older files are ignored with a warning.
+    #[arg(long, env, default_value_t = 86_400)]
+    pub endpoint_discovery_state_max_age_secs: u64,
+
+    /// Max size of an `endpoint-discovery-url` response, in bytes. Larger responses are rejected, keeping the current endpoints.
+    #[arg(long, env, default_value_t = 1_048_576)]
+    pub endpoint_discovery_max_response_bytes: u64,
+
+    /// Max percentage of previously discovered endpoints a single `endpoint-discovery-url` response may remove.
+    /// Larger removals only take effect once two consecutive responses confirm them.
+    #[arg(long, env, default_value_t = 50)]
+    pub endpoint_discovery_max_removal_percent: u8,
+
+    /// Interval between re-resolving hostnames in `dest-ip-ports`, in seconds.
+    /// Use `0` to only resolve once at startup.
+    #[arg(long, env, default_value_t = 30)]
+    pub dest_resolve_interval_secs: u64,
+
+    /// Address family preferred when a `dest-ip-ports` hostname resolves to both IPv4 and IPv6 addresses.
+    /// Falls back to the other family if the hostname only has addresses of that family.
+    #[arg(long, env, value_enum, default_value_t = AddressFamily::Any)]
+    pub dest_address_family: AddressFamily,
+
+    /// Validator identity pubkeys to forward shreds to, comma separated. Each is resolved to its current TVU address
+    /// with `getClusterNodes` on `dest-validator-rpc-url`, so destinations follow operators migrating hosts.
+    /// An identity missing from the cluster keeps its last known address.
+    #[arg(long, env, value_delimiter = ',', value_parser = parse_pubkey)]
+    pub dest_validator_identities: Vec<Pubkey>,
+
+    /// RPC to resolve `dest-validator-identities` with.
+    // may carry an API key, so not shown in help
+    #[arg(long, env, hide_env_values = true)]
+    pub dest_validator_rpc_url: Option<String>,
+
+    /// Interval between resolving `dest-validator-identities`, in seconds.
+    #[arg(long, env, default_value_t = 60)]
+    pub dest_validator_refresh_secs: u64,
+
+    /// Identities missing from the cluster for longer than this many seconds are logged and counted in metrics.
+    #[arg(long, env, default_value_t = 600)]
+    pub dest_validator_unresolved_alert_secs: u64,
+
+    /// RPC to discover destinations from by stake, as an alternative to `endpoint-discovery-url`.
+    /// Forwards to the TVU address of the highest staked nodes from `getClusterNodes` and `getVoteAccounts` that pass `rpc-discovery-filter`.
+    /// A failed refresh keeps the last discovered destinations.
+    // may carry an API key, so not shown in help
+    #[arg(long, env, hide_env_values = true)]
+    pub rpc_discovery_url: Option<String>,
+
+    /// Which nodes `rpc-discovery-url` forwards to, as comma separated `key=value` pairs.
+    /// `min_stake` is a share of the total activated stake like `0.1%`, or SOL like `50000`. `max_nodes` keeps the highest staked, defaulting to 25.
+    /// `country` and `asn` are `|` separated allowlists looked up in `rpc-discovery-geo-file`, eg. `min_stake=0.1%,max_nodes=25,country=DE|NL`.
+    #[arg(long, env, value_parser = stake_discovery::parse_rpc_discovery_filter)]
+    pub rpc_discovery_filter: Option<RpcDiscoveryFilter>,
+
+    /// Interval between `rpc-discovery-url` refreshes, in seconds.
+    #[arg(long, env, default_value_t = 300)]
+    pub rpc_discovery_interval_secs: u64,
+
+    /// CSV of `cidr,country,asn` lines, such as an export of a GeoIP database, to look up the `country` and `asn` of `rpc-discovery-filter` in.
+    /// Re-read on every refresh.
+    #[arg(long, env)]
+    pub rpc_discovery_geo_file: Option<PathBuf>,
+
+    /// Never forward to these IPs or CIDRs, comma separated, eg. `10.0.0.1,10.1.0.0/16`, whichever source adds them.
+    /// Blocked destinations are logged on each refresh and counted in metrics.
+    #[arg(long, env, value_delimiter = ',', value_parser = parse_ip_net)]
+    pub dest_blocklist: Vec<IpNet>,
+
+    /// Allow forwarding to the proxy's own bind address or public IP on `src-bind-port`, which is blocked by default
+    /// to avoid forwarding loops.
+    #[arg(long, env)]
+    pub allow_self_forward: bool,
+
+    /// Max destinations forwarded to, so a misbehaving source can't fan shreds out to thousands of endpoints.
+    /// Beyond this, `dest-ip-ports` are kept first, then discovered endpoints in response order, then other sources. Dropped destinations are logged.
+    #[arg(long, env, default_value_t = 200)]
+    pub max_destinations: usize,
+
+    /// Only accept packets from these source IPs or CIDRs, comma separated, eg. `10.0.0.1,10.1.0.0/16`.
+    /// Other packets are dropped before deduping and counted in metrics. Accepts packets from any source if not set.
+    #[arg(long, env, value_delimiter = ',', value_parser = parse_ip_net)]
+    pub allowed_source_ips: Vec<IpNet>,
+
+    /// File with one source IP or CIDR per line, allowed in addition to `allowed-source-ips`. `#` starts a comment.
+    /// Re-read every 30 seconds, so upstream changes are picked up without a restart. An empty file drops all packets.
+    #[arg(long, env)]
+    pub allowed_source_ips_file: Option<PathBuf>,
+
+    /// Drop shreds more than this many slots behind the highest slot seen. Disabled if not set.
+    #[arg(long, env)]
+    pub max_slot_age: Option<u64>,
+
+    /// Drop packets that don't parse as a shred, even with `forward-unknown-packets`.
+    /// Note: trace shreds used by `debug-trace-shred` are also dropped.
+    #[arg(long, env, default_value_t = false)]
+    pub drop_non_shred_packets: bool,
+
+    /// Shred types to forward, read from the shred variant byte. Filtered shreds are counted per type in metrics.
+    #[arg(long, env, value_enum, default_value_t = ForwardShredTypes::All)]
+    pub forward_shred_types: ForwardShredTypes,
+
+    /// Forward packets that don't parse as a shred, regardless of `forward-shred-types`. Dropped by default, since an
+    /// exposed listen port also receives scanner traffic. Either way they are counted in the `received_invalid` metric.
+    /// Set to true when using `debug-trace-shred`, trace shreds don't parse as a shred.
+    #[arg(long, env, default_value_t = false, action = clap::ArgAction::Set)]
+    pub forward_unknown_packets: bool,
+
+    /// Interval between logging stats to stdout and reporting them to `metrics-backend`
+    #[arg(long, env, default_value_t = 15_000)]
+    pub metrics_report_interval_ms: u64,
+
+    /// Where to report metrics. `influx` reads its server from `SOLANA_METRICS_CONFIG`, `statsd` sends to `statsd-addr`,
+    /// and `none` stops reporting, eg. when no Influx is set up. Prometheus is served either way when `prometheus-bind-addr` is set.
+    #[arg(long, env, value_enum, default_value_t = MetricsBackendKind::Influx)]
+    pub metrics_backend: MetricsBackendKind,
+
+    /// StatsD server to send metrics to over UDP with `--metrics-backend statsd`, eg. `127.0.0.1:8125`.
+    /// Every datapoint field is sent as a gauge named `<datapoint>.<field>`.
+    #[arg(long, env)]
+    pub statsd_addr: Option<String>,
+
+    /// How StatsD tags, eg. the address of per-destination metrics, are sent. `dogstatsd` sends them as `|#tag:value`,
+    /// `statsd` has no tags so folds their values into the metric name.
+    #[arg(long, env, value_enum, default_value_t = StatsdDialect::Statsd)]
+    pub statsd_dialect: StatsdDialect,
+
+    /// Address to serve Prometheus metrics on at `/metrics`, eg. `127.0.0.1:9090`. Disabled if not set.
+    /// Also serves Kubernetes readiness and liveness probes at `/readyz` and `/healthz`, as does `admin-bind-addr`.
+    #[arg(long, env)]
+    pub prometheus_bind_addr: Option<SocketAddr>,
+
+    /// Address to serve the admin API on for adding and removing destinations and adjusting logging at runtime, eg. `127.0.0.1:9091`.
+    /// `GET /info` returns the build details. Disabled if not set. Do not expose publicly, it is unauthenticated.
+    #[arg(long, env)]
+    pub admin_bind_addr: Option<SocketAddr>,
+
+    /// Address to serve the `ShredstreamProxyAdmin` gRPC service on, eg. `127.0.0.1:9092`. Disabled if not set.
+    /// Mirrors the admin API for orchestration tooling, and can also trigger an immediate `endpoint-discovery-url` fetch.
+    /// Unauthenticated, so only expose it to trusted networks, over TLS with `admin-grpc-tls-cert-file` if it leaves the host.
+    #[arg(long, env)]
+    pub admin_grpc_bind_addr: Option<SocketAddr>,
+
+    /// PEM certificate chain to serve `admin-grpc-bind-addr` over TLS with. Requires `admin-grpc-tls-key-file`.
+    #[arg(long, env)]
+    pub admin_grpc_tls_cert_file: Option<PathBuf>,
+
+    /// PEM private key for `admin-grpc-tls-cert-file`.
+    #[arg(long, env)]
+    pub admin_grpc_tls_key_file: Option<PathBuf>,
+
+    /// Address to serve the gRPC `SubscribeEntries` stream on, eg. `127.0.0.1:9999`. Disabled if not set.
+    /// Entries are reassembled from received data shreds only while a subscriber is connected.
+    #[arg(long, env)]
+    pub grpc_service_bind_addr: Option<SocketAddr>,
+
+    /// Time to wait for the rest of a FEC set's data shreds before dropping it, in milliseconds.
+    #[arg(long, env, default_value_t = 2_000)]
+    pub deshred_fec_set_timeout_ms: u64,
+
+    /// Address to serve a WebSocket stream of received shreds on, eg. `127.0.0.1:9998`. Disabled if not set.
+    /// Each shred is a binary message, or with `?format=json` a JSON summary: slot, index, type, size, and receive time.
+    /// Shreds are only copied while a client is connected, and clients that fall behind are disconnected.
+    #[arg(long, env)]
+    pub ws_bind_addr: Option<SocketAddr>,
+
+    /// Max WebSocket clients connected at once, more are rejected with HTTP 503.
+    #[arg(long, env, default_value_t = 16)]
+    pub ws_max_clients: usize,
+
+    /// Webhook to POST alerts to when forwarding degrades, eg. a Slack or Discord incoming webhook. Disabled if not set.
+    /// Checked every metrics interval: no shreds received, forward failures, heartbeats failing, unhealthy destinations, and deduper saturation.
+    /// Each alert repeats at most once per `alert-cooldown-secs` while it persists, and a recovery is sent once it clears. Webhook failures are only logged.
+    // may carry the webhook's secret, so not shown in help
+    #[arg(long, env, hide_env_values = true)]
+    pub alert_webhook_url: Option<String>,
+
+    /// Body of each alert POST.
+    #[arg(long, env, value_enum, default_value_t = AlertWebhookFormat::Json)]
+    pub alert_webhook_format: AlertWebhookFormat,
+
+    /// Identifies this proxy in alerts. Defaults to the hostname.
+    #[arg(long, env)]
+    pub alert_host_id: Option<String>,
+
+    /// Alert once no shreds are received for this many seconds.
+    #[arg(long, env, default_value_t = 60)]
+    pub alert_no_shreds_secs: u64,
+
+    /// Alert once more than this percent of packets fail to forward in a metrics interval.
+    #[arg(long, env, default_value_t = 5.0)]
+    pub alert_forward_failure_percent: f64,
+
+    /// Alert once no heartbeat succeeds for this many seconds. Not checked in forward-only mode or while heartbeats are paused.
+    #[arg(long, env, default_value_t = 60)]
+    pub alert_heartbeat_down_secs: u64,
+
+    /// Alert once the deduper is estimated to be more than this percent saturated, when it starts letting duplicates through.
+    #[arg(long, env, default_value_t = 10.0)]
+    pub alert_deduper_saturation_percent: f64,
+
+    /// Min seconds between alerts for the same condition.
+    #[arg(long, env, default_value_t = 900)]
+    pub alert_cooldown_secs: u64,
+
+    /// Solana RPC to measure how early each slot's first shred arrives against, eg. `http://127.0.0.1:8899`.
+    /// The RPC's processed slot is polled every 50ms, reporting per slot latency and rolling p50/p99 to influx. Positive latencies mean the proxy saw the slot first.
+    /// Disabled when unset.
+    // may carry an API key, so not shown in help
+    #[arg(long, env, hide_env_values = true)]
+    pub slot_latency_rpc_url: Option<String>,
+
+    /// OpenTelemetry collector to export control-plane spans to over OTLP gRPC, eg. `http://127.0.0.1:4317`.
+    /// Covers authentication, heartbeats, discovery refreshes, and destination resolution, never the packet path. Spans are also logged at debug level.
+    /// Requires building with the `otel` feature.
+    #[arg(long, env)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Same as `--trace-shred-sample-rate 1.0`, which also logs trace shreds to stdout and influx. Trace shreds require `--forward-unknown-packets true`.
+    #[arg(long, env, default_value_t = false)]
+    pub debug_trace_shred: bool,
+
+    /// Fraction of shreds to log and report a datapoint for, with their slot, index, source, and receive time. Eg. `0.001` for one in a thousand.
+    /// Shreds are picked by hashing their slot and index, so proxies sampling at the same rate trace the same shreds. Adjustable through the admin API.
+    #[arg(long, env, default_value_t = 0.0)]
+    pub trace_shred_sample_rate: f64,
+
+    /// For testing consumers only: fraction of packets to UDP destinations to drop, eg. `0.02`.
+    #[arg(long, env, default_value_t = 0.0)]
+    pub chaos_drop_rate: f64,
+
+    /// For testing consumers only: fraction of packets to UDP destinations to send twice, eg. `0.01`.
+    #[arg(long, env, default_value_t = 0.0)]
+    pub chaos_duplicate_rate: f64,
+
+    /// For testing consumers only: number of packets held per UDP destination and released in random order, eg. `5`.
+    /// Packets still held after 50ms without new ones are sent.
+    #[arg(long, env, default_value_t = 0)]
+    pub chaos_reorder_window: usize,
+
+    /// For testing consumers only: milliseconds to delay every packet to UDP destinations by, eg. `3`.
+    #[arg(long, env, default_value_t = 0)]
+    pub chaos_delay_ms: u64,
+
+    /// Destinations the `chaos-*` faults apply to, comma separated. Defaults to every UDP destination.
+    #[arg(long, env, value_delimiter = ',')]
+    pub chaos_dests: Vec<SocketAddr>,
+
+    /// Public IP address to use.
+    /// Overrides value fetched from ifconfig.me, api.ipify.org or icanhazip.com, and skips that detection.
+    #[arg(long, env)]
+    pub public_ip: Option<IpAddr>,
+
+    /// Address family of the public IP fetched when `public-ip` is not set.
+    /// `any` uses whichever family the host connects over.
+    #[arg(long, env, value_enum, default_value_t = AddressFamily::V4)]
+    pub public_ip_family: AddressFamily,
+
+    /// Interval between refetches of the public IP, in seconds, 0 to disable.
+    /// When it changed since registering, heartbeats re-register with the new IP. Skipped when `public-ip` or `advertise-addr` is set.
+    #[arg(long, env, default_value_t = 300)]
+    pub public_ip_recheck_interval_secs: u64,
+
+    /// Address advertised to the block engine in heartbeats, used verbatim in place of `public-ip`:`src-bind-port`.
+    /// For running behind a NAT whose external port differs from `src-bind-port`. Still binds `src-bind-addr`:`src-bind-port`.
+    #[arg(long, env)]
+    pub advertise_addr: Option<SocketAddr>,
+
+    /// Deprecated, sets both `num-recv-threads` and `num-send-threads` unless they're set.
+    #[arg(long, env)]
+    pub num_threads: Option<usize>,
+
+    /// Number of threads receiving from the listen sockets, split between ports.
+    /// Defaults to a quarter of the cores, up to 4.
+    #[arg(long, env)]
+    pub num_recv_threads: Option<usize>,
+
+    /// Number of threads sending to destinations, split between ports. Each port's send threads pull from a queue
+    /// shared by its receive threads. Defaults to one per destination given at startup, at least 4 and at most the
+    /// core count, or the core count when destinations are discovered at runtime.
+    #[arg(long, env)]
+    pub num_send_threads: Option<usize>,
+
+    /// Cores to pin forwarder threads to, one per thread in order, comma separated. Eg. `2,3,4,5`.
+    /// Needs at least as many cores as forwarder threads. Other threads are left floating.
+    #[arg(long, env, value_delimiter = ',')]
+    pub core_affinity: Vec<usize>,
+
+    /// Max number of packets sent per destination in a single `sendmmsg` call.
+    #[arg(long, env, default_value_t = forwarder::DEFAULT_SEND_BATCH_SIZE)]
+    pub send_batch_size: usize,
+
+    /// Max time in microseconds to wait for more packets before flushing a send batch.
+    /// `0` only coalesces packets that are already queued, adding no latency.
+    #[arg(long, env, default_value_t = 0)]
+    pub send_batch_linger_us: u64,
+
+    /// Send to each UDP destination from its own thread, socket, and queue, so a destination whose sends block
+    /// only delays and drops its own packets. Queued packets are shared between destinations, not copied.
+    /// Shard group members are still sent to from the forwarder threads.
+    #[arg(long, env, default_value_t = false)]
+    pub isolated_send_threads: bool,
+
+    /// Max packet batches queued per destination with `isolated-send-threads`. Once full, new batches for that destination are dropped.
+    #[arg(long, env, default_value_t = isolated_send::DEFAULT_ISOLATED_SEND_QUEUE_CAPACITY)]
+    pub isolated_send_queue_capacity: usize,
+
+    /// Max packet batches queued per receive thread in the queue a port's receive threads share with its send threads.
+    /// Once full, `forwarder-drop-policy` decides what happens to new batches.
+    #[arg(long, env, default_value_t = packet_channel::DEFAULT_FORWARDER_CHANNEL_CAPACITY)]
+    pub forwarder_channel_capacity: usize,
+
+    /// What to do when a forwarder falls behind and its channel is full. Drops are counted in metrics.
+    #[arg(long, env, value_enum, default_value_t = DropPolicy::DropOldest)]
+    pub forwarder_drop_policy: DropPolicy,
+
+    /// Max bytes buffered in flight across receive batches, tunnel queues, and pcap, Kafka, and archive queues, eg. on small hosts
+    /// where a slow destination could otherwise grow memory until the proxy is OOM killed. Once over, received packets are dropped
+    /// and counted until buffered bytes fall below 80% of this. Buffered bytes are reported whether or not this is set.
+    #[arg(long, env)]
+    pub max_buffered_bytes: Option<u64>,
+
+    /// Read listen sockets with `recvmmsg` directly, up to this many packets per call, into batches reused once forwarded.
+    /// Saves syscalls at high packet rates compared to the default receive path. Max 1024. Linux only, other platforms use the default path.
+    #[arg(long, env)]
+    pub recv_mmsg_batch_size: Option<usize>,
+
+    /// Time in milliseconds a listen socket read waits for packets before checking for shutdown.
+    #[arg(long, env, default_value_t = 1_000)]
+    pub recv_poll_timeout_ms: u64,
+
+    /// Report latency added by the proxy, from kernel receive timestamp to forwarding, as p50/p90/p99 on the metrics interval.
+    /// Linux only. Listen sockets are read with `recvmmsg` instead of the default receive path.
+    #[arg(long, env, default_value_t = false)]
+    pub measure_internal_latency: bool,
+
+    /// Receive timestamps used by `measure-internal-latency`. `hardware` requires timestamping enabled on the NIC, eg. with `hwstamp_ctl`,
+    /// and its clock synced to the system clock, eg. with `phc2sys`. Packets the NIC didn't stamp fall back to software timestamps.
+    #[arg(long, env, value_enum, default_value_t = RxTimestampSource::Software)]
+    pub rx_timestamp_source: RxTimestampSource,
+
+    /// Time each send to a destination and report p50/p99/max per destination on the metrics interval, to find slow peers.
+    /// Off by default since it reads the clock twice per `sendmmsg` call.
+    #[arg(long, env, default_value_t = false)]
+    pub measure_send_latency: bool,
+
+    /// Sends slower than this many microseconds are counted per destination with `measure-send-latency`.
+    /// A warning names destinations whose p99 exceeds it for several intervals in a row.
+    #[arg(long, env, default_value_t = 1_000)]
+    pub slow_send_threshold_us: u64,
+
+    /// Size of the deduper's bit vector. Memory used is `deduper-num-bits` / 8 bytes, 76MB by default.
+    /// Fewer bits saturate sooner, so the deduper resets more often.
+    #[arg(long, env, default_value_t = forwarder::DEDUPER_NUM_BITS)]
+    pub deduper_num_bits: u64,
+
+    /// Estimated false positive rate at which the deduper resets, between 0 and 1 exclusive.
+    /// False positives are new shreds dropped as duplicates. Resets reaching it before `deduper-reset-interval-ms` are counted as early resets.
+    #[arg(long, env, default_value_t = forwarder::DEDUPER_FALSE_POSITIVE_RATE)]
+    pub deduper_false_positive_rate: f64,
+
+    /// What duplicates are recognized by. `payload` hashes each whole packet.
+    /// `shred-id` hashes only the shred's variant, slot, index, and version, much cheaper when subscribed to several regions,
+    /// but forwards only the first copy of a shred even if a later one has a different payload. Packets that aren't shreds are deduped by payload.
+    /// Copies dropped by shred id still reach `detect-conflicting-shreds`.
+    #[arg(long, env, value_enum, default_value_t = DedupMode::Payload)]
+    pub dedup_mode: DedupMode,
+
+    /// Record received packets after deduping and filtering, before forwarding, to a pcap file at this path.
+    #[arg(long, env)]
+    pub record_pcap: Option<PathBuf>,
+
+    /// Start a new pcap file once the current one reaches this many bytes. `0` disables size based rotation.
+    #[arg(long, env, default_value_t = 1 << 30)]
+    pub record_pcap_rotate_bytes: u64,
+
+    /// Start a new pcap file once the current one is this many seconds old. `0` disables time based rotation.
+    #[arg(long, env, default_value_t = 0)]
+    pub record_pcap_rotate_secs: u64,
+
+    /// Delete the oldest rotated pcap files beyond this many. `0` keeps all files.
+    #[arg(long, env, default_value_t = 10)]
+    pub record_pcap_max_files: usize,
+
+    /// Compare each shred against earlier shreds with the same (slot, index, type), logging and counting those with a different payload.
+    /// A conflict is a sign of duplicate block production. Runs off the forwarding path, so conflicts may be missed under load.
+    #[arg(long, env, default_value_t = false)]
+    pub detect_conflicting_shreds: bool,
+
+    /// Most recent slots whose shreds are compared by `detect-conflicting-shreds`, bounding its memory.
+    #[arg(long, env, default_value_t = 32)]
+    pub conflicting_shreds_max_slots: usize,
+
+    /// Directory to write both payloads of each conflicting shred to, as `<slot>-<index>-<data|code>-<first|second>.bin`.
+    /// Keeps a copy of every tracked shred in memory. Requires `detect-conflicting-shreds`.
+    #[arg(long, env)]
+    pub conflicting_shreds_dir: Option<PathBuf>,
+
+    /// Address to send slot events to as UDP datagrams, eg. `127.0.0.1:9500`: when a slot's first shred arrives, when its last shred arrives,
+    /// and when it's abandoned without one. A lightweight signal for schedulers that don't need the shreds themselves. Disabled if not set.
+    #[arg(long, env)]
+    pub events_dest: Option<SocketAddr>,
+
+    /// Encoding of each slot event datagram.
+    #[arg(long, env, value_enum, default_value_t = EventsFormat::Json)]
+    pub events_format: EventsFormat,
+
+    /// Report a slot abandoned once no shreds arrive for it for this many milliseconds without its last shred.
+    #[arg(long, env, default_value_t = 1_000)]
+    pub events_abandon_timeout_ms: u64,
+
+    /// Most slot events to send per second, dropping and counting the rest.
+    #[arg(long, env, default_value_t = 100)]
+    pub events_max_per_sec: u64,
+
+    /// Kafka brokers to publish received shreds to after deduping and filtering, comma separated. Eg. `10.0.0.1:9092,10.0.0.2:9092`.
+    /// Each shred is a message keyed by slot, with `received_at_unix_nanos` and `source_addr` headers.
+    /// Requires building with the `kafka` feature.
+    #[arg(long, env)]
+    pub kafka_brokers: Option<String>,
+
+    /// Kafka topic to publish shreds to. Required with `kafka-brokers`.
+    #[arg(long, env)]
+    pub kafka_topic: Option<String>,
+
+    /// Protocol used to connect to the Kafka brokers.
+    #[arg(long, env, value_enum, default_value_t = KafkaSecurityProtocol::Plaintext)]
+    pub kafka_security_protocol: KafkaSecurityProtocol,
+
+    /// Kafka SASL mechanism, eg. `PLAIN`, `SCRAM-SHA-256`, or `SCRAM-SHA-512`.
+    #[arg(long, env)]
+    pub kafka_sasl_mechanism: Option<String>,
+
+    /// Kafka SASL username.
+    #[arg(long, env)]
+    pub kafka_sasl_username: Option<String>,
+
+    /// File containing the Kafka SASL password, read at startup.
+    #[arg(long, env)]
+    pub kafka_sasl_password_file: Option<PathBuf>,
+
+    /// PEM file of CA certificates to verify the Kafka brokers with. Defaults to the system's.
+    #[arg(long, env)]
+    pub kafka_ssl_ca_location: Option<PathBuf>,
+
+    /// Shreds queued for publishing to Kafka before new ones are dropped, so a slow broker never stalls forwarding.
+    #[arg(long, env, default_value_t = 65_536)]
+    pub kafka_queue_capacity: usize,
+
+    /// Directory of a RocksDB blockstore to archive received shreds to after deduping and filtering, for reconstructing blocks offline.
+    /// Shreds are dropped when the archiver can't keep up. Requires building with the `archive` feature.
+    #[arg(long, env)]
+    pub archive_path: Option<PathBuf>,
+
+    /// Slots to keep in the archive, counting back from the newest slot with a completed data set. Older slots are purged. 0 keeps every slot.
+    #[arg(long, env, default_value_t = 10_000)]
+    pub archive_retention_slots: u64,
+
+    /// Read the existing archive at startup, failing if it can't be opened or read. Requires `archive-path`.
+    #[arg(long, env, default_value_t = false)]
+    pub archive_read_check: bool,
+
+    /// Probe destinations, pausing forwarding to ones failing `health-check-failure-threshold` consecutive probes until a probe succeeds.
+    #[arg(long, env, value_enum)]
+    pub health_check_mode: Option<HealthCheckMode>,
+
+    /// Port to probe. Defaults to each destination's port.
+    #[arg(long, env)]
+    pub health_check_port: Option<u16>,
+
+    /// Path requested by `http` health checks.
+    #[arg(long, env, default_value = "/health")]
+    pub health_check_http_path: String,
+
+    /// Interval between health checks in milliseconds.
+    #[arg(long, env, default_value_t = 5_000)]
+    pub health_check_interval_ms: u64,
+
+    /// Time to wait for a health check response in milliseconds.
+    #[arg(long, env, default_value_t = 1_000)]
+    pub health_check_timeout_ms: u64,
+
+    /// Consecutive failed health checks before a destination is considered unhealthy.
+    #[arg(long, env, default_value_t = 3)]
+    pub health_check_failure_threshold: u32,
+
+    /// Directory to spill shreds to for destinations with `?spill=true` while they're unhealthy or paused, one subdirectory per destination.
+    /// Spills are replayed once the destination is back, and aren't kept across restarts. Disabled if not set.
+    /// Shreds are written from a dedicated thread, and dropped if it falls behind the disk.
+    #[arg(long, env)]
+    pub spill_dir: Option<PathBuf>,
+
+    /// Max bytes spilled per destination. Beyond it the oldest spilled shreds are overwritten.
+    #[arg(long, env, default_value_t = spill::DEFAULT_SPILL_MAX_BYTES)]
+    pub spill_max_bytes: u64,
+
+    /// Packets per second spilled shreds are replayed at once their destination is back, ahead of live shreds.
+    /// Must exceed the live rate for the replay to catch up.
+    #[arg(long, env, default_value_t = 50_000)]
+    pub spill_replay_rate_pps: u64,
+
+    /// Spilled shreds older than this many milliseconds are skipped when replaying.
+    #[arg(long, env, default_value_t = 60_000)]
+    pub spill_max_age_ms: u64,
+
+    /// Max live packets queued per destination behind its replay. Once full, new packets for that destination are dropped.
+    #[arg(long, env, default_value_t = 100_000)]
+    pub spill_live_queue_packets: usize,
+
+    /// Listen socket receive buffer size in bytes (`SO_RCVBUF`). Defaults to the kernel's default.
+    /// Kernel clamps this to `net.core.rmem_max`, raise it to avoid drops during bursts.
+    #[arg(long, env)]
+    pub recv_socket_buffer_bytes: Option<usize>,
+
+    /// Forwarding socket send buffer size in bytes (`SO_SNDBUF`). Defaults to the kernel's default.
+    /// Kernel clamps this to `net.core.wmem_max`.
+    #[arg(long, env)]
+    pub send_socket_buffer_bytes: Option<usize>,
+
+    /// DSCP class to mark forwarded packets with, eg. 46 for expedited forwarding. Set with `IP_TOS` and `IPV6_TCLASS` on the UDP forwarding sockets.
+    /// Startup fails if the platform rejects it. QUIC and tunnel destinations aren't marked.
+    #[arg(long, env, value_parser = clap::value_parser!(u8).range(0..=socket::MAX_DSCP as i64))]
+    pub dscp: Option<u8>,
+
+    /// DSCP class to mark packets sent from the listen sockets with. Startup fails if the platform rejects it.
+    #[arg(long, env, value_parser = clap::value_parser!(u8).range(0..=socket::MAX_DSCP as i64))]
+    pub listen_dscp: Option<u8>,
+
+    /// Network interface to send forwarded packets out of, eg. `eth1`, instead of the one the routing table picks. Set with `SO_BINDTODEVICE`,
+    /// which needs CAP_NET_RAW or root, startup fails if it can't be applied. A destination's `iface` option overrides it, eg. `1.2.3.4:8001?iface=eth2`.
+    /// QUIC, tunnel, and unix destinations aren't affected.
+    #[arg(long, env)]
+    pub egress_interface: Option<String>,
+
+    /// Local address to send forwarded packets from, which must be assigned to this host. A destination's `src` option overrides it, eg. `1.2.3.4:8001?src=10.0.0.6`.
+    /// Destinations of the other address family can't be sent to. QUIC, tunnel, and unix destinations aren't affected.
+    #[arg(long, env)]
+    pub egress_source_ip: Option<IpAddr>,
+
+    /// Rebind a forwarding or listen socket after this many consecutive errors that mean the socket itself is broken, eg. its interface went down.
+    /// Buffer sizes and DSCP are applied again to the new socket. 0 never rebinds.
+    #[arg(long, env, default_value_t = 100)]
+    pub socket_rebind_error_threshold: usize,
+
+    /// Shut down once sockets are rebound more than this many times within a minute, counted across all sockets.
+    #[arg(long, env, default_value_t = 10)]
+    pub socket_max_rebinds_per_minute: usize,
+
+    /// Max time in milliseconds between deduper resets, regardless of saturation.
+    #[arg(long, env, default_value_t = forwarder::DEDUPER_RESET_CYCLE.as_millis() as u64)]
+    pub deduper_reset_interval_ms: u64,
+
+    /// Restartable threads (heartbeat, destination refresh, metrics) are restarted when they panic.
+    /// Shut down once one panics more than this many times within `thread-restart-window-secs`.
+    #[arg(long, env, default_value_t = 5)]
+    pub thread_max_restarts: usize,
+
+    /// Window in seconds over which thread restarts are counted.
+    #[arg(long, env, default_value_t = 600)]
+    pub thread_restart_window_secs: u64,
+
+    /// File to append a crash report to when a thread that isn't restarted panics, with the thread's name, the panic message, and a backtrace.
+    /// Opened at the time of the panic, so it must be writable by `run-as-user` and resolves inside `chroot`.
+    #[arg(long, env)]
+    pub crash_report_file: Option<PathBuf>,
+
+    /// Time in milliseconds threads get to flush queued packets and exit after a shutdown signal.
+    /// Threads still running after this are abandoned and the process exits with a non-zero status.
+    #[arg(long, env, default_value_t = 5_000)]
+    pub shutdown_grace_period_ms: u64,
+
+    /// User to switch to, by name or uid, once sockets and the admin and metrics listeners are bound and the keypair is read.
+    /// Fails to start if the switch fails. Files opened afterwards, such as `record-pcap`, `allowed-source-ips-file`, and the config file on reload, must be accessible to this user.
+    #[arg(long, env)]
+    pub run_as_user: Option<String>,
+
+    /// Group to switch to, by name or gid. Defaults to the primary group of `run-as-user`.
+    #[arg(long, env)]
+    pub run_as_group: Option<String>,
+
+    /// Directory to chroot into before switching user, requires `run-as-user`.
+    /// Files opened afterwards resolve inside it, so it needs its own `/etc/resolv.conf` for hostname destinations, and `/proc` for socket drop counts.
+    /// Threads start before chrooting, so startup fails unless the config file, key files, `spill-dir`, `record-pcap`, `archive-path`,
+    /// `allowed-source-ips-file`, and the other files opened later are absolute paths that exist inside it, or whose parent does.
+    #[arg(long, env)]
+    pub chroot: Option<PathBuf>,
+}
+
+#[derive(Debug, Error)]
+pub enum ShredstreamProxyError {
+    #[error("TonicError {0}")]
+    TonicError(#[from] tonic::transport::Error),
+    #[error("GrpcError {0}")]
+    GrpcError(#[from] Status),
+    #[error("ReqwestError {0}")]
+    ReqwestError(#[from] reqwest::Error),
+    #[error("SerdeJsonError {0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+    #[error("RpcError {0}")]
+    RpcError(#[from] ClientError),
+    #[error("BlockEngineConnectionError {0}")]
+    BlockEngineConnectionError(#[from] BlockEngineConnectionError),
+    #[error("RecvError {0}")]
+    RecvError(#[from] RecvError),
+    #[error("IoError {0}")]
+    IoError(#[from] io::Error),
+    #[error("Failed to detect public IP, pass --public-ip to skip detection. {0}")]
+    PublicIpError(String),
+    #[error("Shutdown")]
+    Shutdown,
+    /// Invalid combination of arguments, describing the first one found
+    #[error("{0}")]
+    InvalidArguments(String),
+    #[error("AlreadyStarted")]
+    AlreadyStarted,
+    /// Threads still running once the shutdown grace period elapsed
+    #[error("Threads failed to stop within the shutdown grace period: {0:?}")]
+    ShutdownTimeout(Vec<String>),
+}
+
+/// Parses a `Name: Value` header. Errors don't include the value, which may be a secret
+pub fn parse_header(header: &str) -> Result<(String, String), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| "Expected a header formatted as `Name: Value`.".to_string())?;
+    let (name, value) = (name.trim(), value.trim());
+    HeaderName::from_str(name).map_err(|e| format!("Invalid header name {name:?}: {e}"))?;
+    HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header {name}."))?;
+    Ok((name.to_string(), value.to_string()))
+}
+
+/// Parses a base58 pubkey, such as a validator identity
+pub fn parse_pubkey(pubkey: &str) -> Result<Pubkey, String> {
+    let pubkey = pubkey.trim();
+    Pubkey::from_str(pubkey).map_err(|_| format!("Invalid pubkey {pubkey:?}."))
+}
+
+/// Parses an IP or CIDR, treating a bare IP as a single address
+pub fn parse_ip_net(ip_net: &str) -> Result<IpNet, String> {
+    let ip_net = ip_net.trim();
+    IpNet::from_str(ip_net)
+        .or_else(|_| IpAddr::from_str(ip_net).map(IpNet::from))
+        .map_err(|_| format!("Invalid IP or CIDR {ip_net:?}."))
+}
+
+/// Returns the threshold sends are counted as slow over, or None when not measuring send latency
+pub fn slow_send_threshold(args: &CommonArgs) -> Option<Duration> {
+    args.measure_send_latency
+        .then(|| Duration::from_micros(args.slow_send_threshold_us))
+}
+
+/// Returns the key files of `enc://` destinations and `decrypt-key-file`
+pub fn encryption_key_files(args: &CommonArgs) -> Vec<PathBuf> {
+    args.dest_ip_ports
+        .iter()
+        .filter_map(|(_, hostname_port)| encryption::parse_enc_dest(hostname_port))
+        .chain(args.decrypt_key_file.clone())
+        .unique()
+        .collect()
+}
+
+/// Returns the settings shared by `tcp://` and `tls://` destinations
+pub fn tunnel_config(args: &CommonArgs) -> TunnelConfig {
+    TunnelConfig {
+        buffer_packets: args.tunnel_buffer_packets,
+        tls_ca_cert: args.tunnel_tls_ca_cert.clone(),
+        tls_server_name: args.tunnel_tls_server_name.clone(),
+        tls_client_cert: args.tunnel_tls_client_cert.clone(),
+        tls_client_key: args.tunnel_tls_client_key.clone(),
+    }
+}
+
+/// Returns where to publish shreds to Kafka, if configured
+pub fn kafka_config(args: &CommonArgs) -> Option<KafkaConfig> {
+    Some(KafkaConfig {
+        brokers: args.kafka_brokers.clone()?,
+        topic: args.kafka_topic.clone()?,
+        security_protocol: args.kafka_security_protocol,
+        sasl_mechanism: args.kafka_sasl_mechanism.clone(),
+        sasl_username: args.kafka_sasl_username.clone(),
+        sasl_password_file: args.kafka_sasl_password_file.clone(),
+        ssl_ca_location: args.kafka_ssl_ca_location.clone(),
+        queue_capacity: args.kafka_queue_capacity,
+    })
+}
+
+/// Returns where to archive shreds, if configured
+pub fn archive_config(args: &CommonArgs) -> Option<ArchiveConfig> {
+    Some(ArchiveConfig {
+        path: args.archive_path.clone()?,
+        retention_slots: args.archive_retention_slots,
+        read_check: args.archive_read_check,
+    })
+}
+
+/// Returns the fraction of shreds to trace, all of them with `debug-trace-shred`
+pub fn trace_shred_sample_rate(args: &CommonArgs) -> f64 {
+    if args.debug_trace_shred {
+        1.0
+    } else {
+        args.trace_shred_sample_rate
+    }
+}
+
+/// Returns how to detect conflicting shreds, if enabled
+pub fn conflict_detector_config(args: &CommonArgs) -> Option<ConflictDetectorConfig> {
+    args.detect_conflicting_shreds
+        .then(|| ConflictDetectorConfig {
+            max_slots: args.conflicting_shreds_max_slots,
+            payload_dir: args.conflicting_shreds_dir.clone(),
+        })
+}
+
+/// Returns where to send slot events, if enabled
+pub fn events_config(args: &CommonArgs) -> Option<EventsConfig> {
+    Some(EventsConfig {
+        dest: args.events_dest?,
+        format: args.events_format,
+        abandon_timeout: Duration::from_millis(args.events_abandon_timeout_ms),
+        max_per_sec: args.events_max_per_sec,
+    })
+}
+
+/// Returns where to send alerts and when to raise them, if enabled
+pub fn alert_config(args: &CommonArgs) -> Option<AlertConfig> {
+    Some(AlertConfig {
+        webhook_url: args.alert_webhook_url.clone()?,
+        format: args.alert_webhook_format,
+        host_id: args.alert_host_id.clone().unwrap_or_else(alerts::hostname),
+        no_shreds_after: Duration::from_secs(args.alert_no_shreds_secs),
+        forward_failure_ratio: args.alert_forward_failure_percent / 100.0,
+        heartbeat_down_after: Some(Duration::from_secs(args.alert_heartbeat_down_secs)),
+        deduper_saturation: args.alert_deduper_saturation_percent / 100.0,
+        cooldown: Duration::from_secs(args.alert_cooldown_secs),
+    })
+}
+
+/// Returns where and how to spill shreds for unavailable destinations, if enabled
+pub fn spill_config(args: &CommonArgs) -> Option<SpillConfig> {
+    Some(SpillConfig {
+        dir: args.spill_dir.clone()?,
+        max_bytes: args.spill_max_bytes,
+        replay_rate_pps: args.spill_replay_rate_pps,
+        max_age: Duration::from_millis(args.spill_max_age_ms),
+        live_queue_packets: args.spill_live_queue_packets,
+    })
+}
+
+/// Returns the faults to inject into sends to UDP destinations, if any
+pub fn chaos_config(args: &CommonArgs) -> Option<ChaosConfig> {
+    let config = ChaosConfig {
+        drop_rate: args.chaos_drop_rate,
+        duplicate_rate: args.chaos_duplicate_rate,
+        reorder_window: args.chaos_reorder_window,
+        delay: Duration::from_millis(args.chaos_delay_ms),
+        dests: args.chaos_dests.clone(),
+    };
+    config.is_enabled().then_some(config)
+}
+
+/// Returns the validator identities to resolve into destinations, if configured
+pub fn validator_resolver_config(args: &CommonArgs) -> Option<ValidatorResolverConfig> {
+    if args.dest_validator_identities.is_empty() {
+        return None;
+    }
+    Some(ValidatorResolverConfig {
+        identities: args.dest_validator_identities.clone(),
+        rpc_url: args.dest_validator_rpc_url.clone()?,
+        refresh_interval: Duration::from_secs(args.dest_validator_refresh_secs),
+        unresolved_alert: Duration::from_secs(args.dest_validator_unresolved_alert_secs),
+    })
+}
+
+/// Returns the RPC to discover destinations from by stake, if configured
+pub fn rpc_discovery_config(args: &CommonArgs) -> Option<RpcDiscoveryConfig> {
+    Some(RpcDiscoveryConfig {
+        rpc_url: args.rpc_discovery_url.clone()?,
+        filter: args.rpc_discovery_filter.clone().unwrap_or_default(),
+        refresh_interval: Duration::from_secs(args.rpc_discovery_interval_secs),
+        geo_file: args.rpc_discovery_geo_file.clone(),
+    })
+}
+
+/// Returns the discovery service to fetch destinations from, if configured
+pub fn endpoint_discovery(args: &CommonArgs) -> Option<EndpointDiscovery> {
+    Some(EndpointDiscovery {
+        url: args.endpoint_discovery_url.clone()?,
+        port: args.discovered_endpoints_port?,
+        headers: args.endpoint_discovery_headers.clone(),
+        bearer_token_file: args.endpoint_discovery_bearer_token_file.clone(),
+        interval: Duration::from_millis(args.endpoint_discovery_interval_ms),
+        state_file: args.endpoint_discovery_state_file.clone(),
+        state_max_age: Duration::from_secs(args.endpoint_discovery_state_max_age_secs),
+        max_response_bytes: args.endpoint_discovery_max_response_bytes,
+        max_removal_percent: args.endpoint_discovery_max_removal_percent,
+    })
+}
+
+/// IP address family to prefer or require
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// Whichever comes first
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            AddressFamily::Any => true,
+            AddressFamily::V4 => ip.is_ipv4(),
+            AddressFamily::V6 => ip.is_ipv6(),
+        }
+    }
+
+    /// Returns the first address of this family, otherwise the first address
+    pub fn select(&self, addrs: impl IntoIterator<Item = SocketAddr>) -> Option<SocketAddr> {
+        let mut addrs = addrs.into_iter().peekable();
+        let first = *addrs.peek()?;
+        Some(addrs.find(|addr| self.matches(addr.ip())).unwrap_or(first))
+    }
+}
+
+/// Port `dest-ip-ports` entries without one are sent to, unless `--default-dest-port` or `--discovered-endpoints-port` is set
+pub const DEFAULT_DEST_PORT: u16 = 8001;
+
+/// Resolves to the first address, used to parse `--dest-ip-ports` before the preferred family is known.
+/// Hosts without a port are resolved on [DEFAULT_DEST_PORT] and returned as given, since `--default-dest-port` isn't known yet either.
+/// [apply_default_dest_port] adds the port once all args are parsed
+pub fn resolve_hostname_port(hostname_port: &str) -> io::Result<(SocketAddr, String)> {
+    let (socketaddr, _) = resolve_hostname_port_with_family(
+        &with_default_port(hostname_port, DEFAULT_DEST_PORT),
+        AddressFamily::Any,
+    )?;
+    Ok((socketaddr, hostname_port.to_string()))
+}
+
+/// Returns the port `dest-ip-ports` entries without one are sent to
+pub fn default_dest_port(args: &CommonArgs) -> u16 {
+    args.default_dest_port
+        .or(args.discovered_endpoints_port)
+        .unwrap_or(DEFAULT_DEST_PORT)
+}
+
+/// Adds the default port to `--dest-ip-ports` entries parsed without one, then drops entries resolving to an address already listed.
+/// Called once CLI args are parsed, config files get the same treatment when converted to [CommonArgs]
+pub fn apply_default_dest_port(args: &mut CommonArgs) -> io::Result<()> {
+    let default_port = default_dest_port(args);
+    let dest_ip_ports = mem::take(&mut args.dest_ip_ports)
+        .into_iter()
+        .map(|(socketaddr, hostname_port)| {
+            let with_port = with_default_port(&hostname_port, default_port);
+            if with_port == hostname_port {
+                return Ok((socketaddr, hostname_port));
+            }
+            // the host was already resolved while parsing, only the port changes
+            let socketaddr = SocketAddr::new(socketaddr.ip(), default_port);
+            check_dest_port(socketaddr, &with_port)?;
+            Ok((socketaddr, with_port))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    args.dest_ip_ports = dedup_dest_ip_ports(dest_ip_ports);
+    Ok(())
+}
+
+/// Returns `hostname_port` with `default_port` added to its address if it has no port, keeping its scheme and options.
+/// Eg. `10.0.0.1?rate=100pps` becomes `10.0.0.1:8001?rate=100pps`, and `::1` becomes `[::1]:8001`. `unix://` destinations have no port
+fn with_default_port(hostname_port: &str, default_port: u16) -> String {
+    let (address, options) = rate_limit::split_dest_options(hostname_port);
+    let (scheme, host) = address
+        .find("://")
+        .map_or(("", address), |i| address.split_at(i + "://".len()));
+    let is_ipv6 = host.parse::<Ipv6Addr>().is_ok();
+    let has_port = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.contains("]:"),
+        None => host.contains(':') && !is_ipv6,
+    };
+    if has_port || scheme == unix::UNIX_SCHEME {
+        return hostname_port.to_string();
+    }
+    let host = if is_ipv6 {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    let options = options
+        .map(|options| format!("?{options}"))
+        .unwrap_or_default();
+    format!("{scheme}{host}:{default_port}{options}")
+}
+
+/// Port 0 can't be sent to, and is more likely a typo than intended
+fn check_dest_port(socketaddr: SocketAddr, hostname_port: &str) -> io::Result<()> {
+    if socketaddr.port() == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Destination {hostname_port} has port 0, set a port to send to, eg. 10.0.0.1:8001"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Drops entries resolving to an address already in `dest_ip_ports`, warning once about all of them
+fn dedup_dest_ip_ports(dest_ip_ports: Vec<(SocketAddr, String)>) -> Vec<(SocketAddr, String)> {
+    let mut seen = HashSet::new();
+    let mut duplicates = vec![];
+    let mut deduped = Vec::with_capacity(dest_ip_ports.len());
+    for (socketaddr, hostname_port) in dest_ip_ports {
+        if seen.insert(socketaddr) {
+            deduped.push((socketaddr, hostname_port));
+        } else {
+            duplicates.push(hostname_port);
+        }
+    }
+    if !duplicates.is_empty() {
+        warn!("Ignoring destinations {duplicates:?}, they resolve to the same address as an earlier destination.");
+    }
+    deduped
+}
+
+/// Resolves to the first address of `family`, falling back to the first address of any family
+pub fn resolve_hostname_port_with_family(
+    hostname_port: &str,
+    family: AddressFamily,
+) -> io::Result<(SocketAddr, String)> {
+    let dest_options = rate_limit::parse_dest_options(hostname_port)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    let (address, _options) = rate_limit::split_dest_options(hostname_port);
+    let is_enc = address.starts_with(encryption::ENC_SCHEME);
+    if !dest_options.egress.is_default() && address.contains("://") && !is_enc {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Destination {hostname_port} can't set iface or src, they only apply to UDP destinations"),
+        ));
+    }
+    if dest_options.spill && address.contains("://") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Destination {hostname_port} can't set spill, it only applies to UDP destinations"
+            ),
+        ));
+    }
+    if is_enc != dest_options.key_file.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Destination {hostname_port} must set key-file if and only if it's an enc:// destination, eg. enc://10.0.0.1:20000?key-file=/etc/shredstream/peer.key"),
+        ));
+    }
+    if address.starts_with(unix::UNIX_SCHEME) {
+        if !cfg!(unix) {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "Destination {hostname_port} is a unix socket, which is only supported on unix"
+                ),
+            ));
+        }
+        let unix_dest = unix::parse_unix_dest(hostname_port).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("Destination {hostname_port} names no socket, eg. unix:///run/consumer.sock or unix://@consumer"),
+            )
+        })?;
+        return Ok((unix::unix_dest_addr(&unix_dest), hostname_port.to_string()));
+    }
+    let socketaddr = family
+        .select(
+            [
+                quic::QUIC_SCHEME,
+                tunnel::TCP_SCHEME,
+                tunnel::TLS_SCHEME,
+                encryption::ENC_SCHEME,
+            ]
+            .iter()
+            .find_map(|scheme| address.strip_prefix(scheme))
+            .unwrap_or(address)
+            .to_socket_addrs()
+            .map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Could not resolve destination {hostname_port}: {e}"),
+                )
+            })?,
+        )
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("Could not find destination {hostname_port}"),
+            )
+        })?;
+    check_dest_port(socketaddr, hostname_port)?;
+
+    Ok((socketaddr, hostname_port.to_string()))
+}
+
+/// Services asked for the public IP in order, each answering with just the address
+const PUBLIC_IP_PROVIDERS: [&str; 3] = [
+    "https://ifconfig.me/ip",
+    "https://api.ipify.org",
+    "https://icanhazip.com",
+];
+/// Time each provider has to answer before the next one is asked
+const PUBLIC_IP_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Rounds of asking every provider before giving up at startup, with the delay between rounds doubling from `PUBLIC_IP_INITIAL_BACKOFF`
+const PUBLIC_IP_MAX_ATTEMPTS: u32 = 5;
+const PUBLIC_IP_INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Returns the public-facing address of `family`, asking each of `PUBLIC_IP_PROVIDERS` until one answers with one.
+/// Binding the family's unspecified address makes dual-stack providers answer over that family
+pub fn get_public_ip(family: AddressFamily) -> Result<IpAddr, ShredstreamProxyError> {
+    let local_address = match family {
+        AddressFamily::Any => None,
+        AddressFamily::V4 => Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        AddressFamily::V6 => Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+    };
+    let client = reqwest::blocking::Client::builder()
+        .local_address(local_address)
+        .timeout(PUBLIC_IP_REQUEST_TIMEOUT)
+        .build()?;
+    let mut errors = vec![];
+    for provider in PUBLIC_IP_PROVIDERS {
+        info!("Requesting public ip ({family:?}) from {provider}...");
+        let response = client
+            .get(provider)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text());
+        match response.map(|body| (parse_public_ip(&body, family), body)) {
+            Ok((Some(public_ip), _)) => {
+                info!("Retrieved public ip: {public_ip:?}");
+                return Ok(public_ip);
+            }
+            Ok((None, body)) => {
+                warn!("Public ip provider {provider} returned {body:?}, which isn't a {family:?} address.");
+                errors.push(format!("{provider} returned an invalid address"));
+            }
+            Err(e) => {
+                warn!("Failed to request public ip from {provider}. Error: {e}");
+                errors.push(format!("{provider}: {e}"));
+            }
+        }
+    }
+    Err(ShredstreamProxyError::PublicIpError(errors.join(", ")))
+}
+
+/// Retries [get_public_ip] with backoff, so a brief outage at boot doesn't fail startup
+pub fn get_public_ip_with_retry(
+    family: AddressFamily,
+    exit: &AtomicBool,
+) -> Result<IpAddr, ShredstreamProxyError> {
+    let mut attempt = 1;
+    let mut backoff = PUBLIC_IP_INITIAL_BACKOFF;
+    loop {
+        match get_public_ip(family) {
+            Ok(public_ip) => return Ok(public_ip),
+            Err(e) if attempt >= PUBLIC_IP_MAX_ATTEMPTS || exit.load(Ordering::Relaxed) => {
+                return Err(e)
+            }
+            Err(e) => {
+                warn!("Attempt {attempt}/{PUBLIC_IP_MAX_ATTEMPTS} to detect public ip failed, retrying in {backoff:?}. Error: {e}");
+                sleep(backoff);
+                attempt += 1;
+                backoff *= 2;
+            }
+        }
+    }
+}
+
+/// Providers answer with the address and a trailing newline, anything else such as an HTML error page is rejected
+fn parse_public_ip(body: &str, family: AddressFamily) -> Option<IpAddr> {
+    IpAddr::from_str(body.trim())
+        .ok()
+        .filter(|ip| family.matches(*ip))
+}
+
+/// Sends the shutdown signal multiple times since crossbeam doesn't have broadcast channels.
+/// Each thread will consume a shutdown signal. Stops once the channel is full, since shutdown may be broadcast more than once
+pub fn broadcast_shutdown(shutdown_sender: &Sender<()>) {
+    for _ in 0..256 {
+        if shutdown_sender.try_send(()).is_err() {
+            break;
+        }
+    }
+}
+
+/// Returns an error describing the first invalid combination of arguments
+pub fn validate_common_args(args: &CommonArgs) -> Result<(), String> {
+    if (args.metrics_backend == MetricsBackendKind::Statsd) != args.statsd_addr.is_some() {
+        return Err("Invalid arguments provided, --statsd-addr is required with --metrics-backend statsd, and only used with it.".to_string());
+    }
+    if (args.endpoint_discovery_url.is_none() && args.discovered_endpoints_port.is_some())
+        || (args.endpoint_discovery_url.is_some() && args.discovered_endpoints_port.is_none())
+    {
+        return Err("Invalid arguments provided, dynamic endpoints requires both --endpoint-discovery-url and --discovered-endpoints-port.".to_string());
+    }
+    if args.default_dest_port == Some(0) {
+        return Err(
+            "Invalid arguments provided, --default-dest-port must be greater than 0.".to_string(),
+        );
+    }
+    if args.endpoint_discovery_url.is_none()
+        && (!args.endpoint_discovery_headers.is_empty()
+            || args.endpoint_discovery_bearer_token_file.is_some()
+            || args.endpoint_discovery_state_file.is_some())
+    {
+        return Err("Invalid arguments provided, --endpoint-discovery-header, --endpoint-discovery-bearer-token-file, and --endpoint-discovery-state-file require --endpoint-discovery-url.".to_string());
+    }
+    if args.endpoint_discovery_url.is_none()
+        && args.rpc_discovery_url.is_none()
+        && args
+            .dest_shard_groups
+            .iter()
+            .any(|group| group.members == ShardMembers::Discovered)
+    {
+        return Err("Invalid arguments provided, --dest-shard-group shard(discovered) requires --endpoint-discovery-url or --rpc-discovery-url.".to_string());
+    }
+    if args.endpoint_discovery_url.is_some() && args.rpc_discovery_url.is_some() {
+        return Err("Invalid arguments provided, --endpoint-discovery-url and --rpc-discovery-url can't be used together.".to_string());
+    }
+    if args.rpc_discovery_url.is_none()
+        && (args.rpc_discovery_filter.is_some() || args.rpc_discovery_geo_file.is_some())
+    {
+        return Err("Invalid arguments provided, --rpc-discovery-filter and --rpc-discovery-geo-file require --rpc-discovery-url.".to_string());
+    }
+    if args.rpc_discovery_interval_secs == 0 {
+        return Err(
+            "Invalid arguments provided, --rpc-discovery-interval-secs must be greater than 0."
+                .to_string(),
+        );
+    }
+    if let Some(filter) = &args.rpc_discovery_filter {
+        if (!filter.countries.is_empty() || !filter.asns.is_empty())
+            && args.rpc_discovery_geo_file.is_none()
+        {
+            return Err("Invalid arguments provided, --rpc-discovery-filter country and asn require --rpc-discovery-geo-file.".to_string());
+        }
+    }
+    if let Some(geo_file) = &args.rpc_discovery_geo_file {
+        GeoTable::load(geo_file).map_err(|e| format!("Invalid arguments provided, {e}"))?;
+    }
+    if args.dest_validator_identities.is_empty() != args.dest_validator_rpc_url.is_none() {
+        return Err("Invalid arguments provided, --dest-validator-identities and --dest-validator-rpc-url must be set together.".to_string());
+    }
+    if args.dest_validator_refresh_secs == 0 {
+        return Err(
+            "Invalid arguments provided, --dest-validator-refresh-secs must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.tunnel_buffer_packets == 0 {
+        return Err(
+            "Invalid arguments provided, --tunnel-buffer-packets must be greater than 0."
+                .to_string(),
+        );
+    }
+    let tls_server_names = match &args.tunnel_tls_server_name {
+        Some(server_name) => vec![server_name.clone()],
+        None => args
+            .dest_ip_ports
+            .iter()
+            .filter_map(
+                |(_, hostname_port)| match tunnel::parse_tunnel_dest(hostname_port)? {
+                    TunnelDest::Tls { server_name } => Some(server_name),
+                    TunnelDest::Tcp => None,
+                },
+            )
+            .collect(),
+    };
+    if let Some(server_name) = tls_server_names
+        .into_iter()
+        .find(|server_name| ServerName::try_from(server_name.as_str()).is_err())
+    {
+        return Err(format!("Invalid arguments provided, {server_name:?} is not a valid TLS server name for --tunnel-tls-server-name or a tls:// destination."));
+    }
+    if args.tunnel_tls_client_cert.is_some() != args.tunnel_tls_client_key.is_some() {
+        return Err("Invalid arguments provided, --tunnel-tls-client-cert and --tunnel-tls-client-key must be set together.".to_string());
+    }
+    if args.kafka_brokers.is_some() != args.kafka_topic.is_some() {
+        return Err(
+            "Invalid arguments provided, --kafka-brokers and --kafka-topic must be set together."
+                .to_string(),
+        );
+    }
+    if args.kafka_brokers.is_none()
+        && (args.kafka_sasl_mechanism.is_some()
+            || args.kafka_sasl_username.is_some()
+            || args.kafka_sasl_password_file.is_some()
+            || args.kafka_ssl_ca_location.is_some())
+    {
+        return Err("Invalid arguments provided, --kafka-sasl-* and --kafka-ssl-ca-location require --kafka-brokers.".to_string());
+    }
+    if args.kafka_brokers.is_some() && !cfg!(feature = "kafka") {
+        return Err("Invalid arguments provided, --kafka-brokers requires building with the `kafka` feature.".to_string());
+    }
+    if args.otlp_endpoint.is_some() && !cfg!(feature = "otel") {
+        return Err("Invalid arguments provided, --otlp-endpoint requires building with the `otel` feature.".to_string());
+    }
+    if !(0.0..=1.0).contains(&args.chaos_drop_rate)
+        || !(0.0..=1.0).contains(&args.chaos_duplicate_rate)
+    {
+        return Err("Invalid arguments provided, --chaos-drop-rate and --chaos-duplicate-rate must be between 0 and 1.".to_string());
+    }
+    if !args.chaos_dests.is_empty() && chaos_config(args).is_none() {
+        return Err(
+            "Invalid arguments provided, --chaos-dests requires a --chaos-* fault to inject."
+                .to_string(),
+        );
+    }
+    if !(0.0..=1.0).contains(&args.trace_shred_sample_rate) {
+        return Err(
+            "Invalid arguments provided, --trace-shred-sample-rate must be between 0 and 1."
+                .to_string(),
+        );
+    }
+    if args.archive_read_check && args.archive_path.is_none() {
+        return Err(
+            "Invalid arguments provided, --archive-read-check requires --archive-path.".to_string(),
+        );
+    }
+    if args.archive_path.is_some() && !cfg!(feature = "archive") {
+        return Err("Invalid arguments provided, --archive-path requires building with the `archive` feature.".to_string());
+    }
+    if args.measure_send_latency && args.slow_send_threshold_us == 0 {
+        return Err(
+            "Invalid arguments provided, --slow-send-threshold-us must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.kafka_queue_capacity == 0 {
+        return Err(
+            "Invalid arguments provided, --kafka-queue-capacity must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.conflicting_shreds_dir.is_some() && !args.detect_conflicting_shreds {
+        return Err("Invalid arguments provided, --conflicting-shreds-dir requires --detect-conflicting-shreds.".to_string());
+    }
+    if args.conflicting_shreds_max_slots == 0 {
+        return Err(
+            "Invalid arguments provided, --conflicting-shreds-max-slots must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.events_abandon_timeout_ms == 0 || args.events_max_per_sec == 0 {
+        return Err("Invalid arguments provided, --events-abandon-timeout-ms and --events-max-per-sec must be greater than 0.".to_string());
+    }
+    if args.endpoint_discovery_interval_ms == 0 {
+        return Err(
+            "Invalid arguments provided, --endpoint-discovery-interval-ms must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.admin_grpc_tls_cert_file.is_some() != args.admin_grpc_tls_key_file.is_some() {
+        return Err("Invalid arguments provided, --admin-grpc-tls-cert-file and --admin-grpc-tls-key-file must be set together.".to_string());
+    }
+    if args.admin_grpc_bind_addr.is_none() && args.admin_grpc_tls_cert_file.is_some() {
+        return Err("Invalid arguments provided, --admin-grpc-tls-cert-file requires --admin-grpc-bind-addr.".to_string());
+    }
+    if args.endpoint_discovery_state_max_age_secs == 0 {
+        return Err(
+            "Invalid arguments provided, --endpoint-discovery-state-max-age-secs must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.max_destinations == 0 {
+        return Err(
+            "Invalid arguments provided, --max-destinations must be greater than 0.".to_string(),
+        );
+    }
+    if args.endpoint_discovery_max_response_bytes == 0 {
+        return Err("Invalid arguments provided, --endpoint-discovery-max-response-bytes must be greater than 0.".to_string());
+    }
+    if args.endpoint_discovery_max_removal_percent > 100 {
+        return Err("Invalid arguments provided, --endpoint-discovery-max-removal-percent must be at most 100.".to_string());
+    }
+    if args.send_batch_size == 0 {
+        return Err(
+            "Invalid arguments provided, --send-batch-size must be greater than 0.".to_string(),
+        );
+    }
+    if args.ws_max_clients == 0 {
+        return Err(
+            "Invalid arguments provided, --ws-max-clients must be greater than 0.".to_string(),
+        );
+    }
+    if let Some(url) = &args.alert_webhook_url {
+        if !matches!(reqwest::Url::parse(url), Ok(url) if ["http", "https"].contains(&url.scheme()))
+        {
+            return Err(
+                "Invalid arguments provided, --alert-webhook-url must be an http(s) url."
+                    .to_string(),
+            );
+        }
+    }
+    if [
+        args.alert_no_shreds_secs,
+        args.alert_heartbeat_down_secs,
+        args.alert_cooldown_secs,
+    ]
+    .contains(&0)
+    {
+        return Err("Invalid arguments provided, --alert-no-shreds-secs, --alert-heartbeat-down-secs, and --alert-cooldown-secs must be greater than 0.".to_string());
+    }
+    if [
+        args.alert_forward_failure_percent,
+        args.alert_deduper_saturation_percent,
+    ]
+    .iter()
+    .any(|&percent| percent.is_nan() || percent <= 0.0 || percent > 100.0)
+    {
+        return Err("Invalid arguments provided, --alert-forward-failure-percent and --alert-deduper-saturation-percent must be greater than 0 and at most 100.".to_string());
+    }
+    if [
+        args.num_threads,
+        args.num_recv_threads,
+        args.num_send_threads,
+    ]
+    .contains(&Some(0))
+    {
+        return Err("Invalid arguments provided, --num-threads, --num-recv-threads, and --num-send-threads must be greater than 0.".to_string());
+    }
+    if args.forwarder_channel_capacity == 0 {
+        return Err(
+            "Invalid arguments provided, --forwarder-channel-capacity must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.max_buffered_bytes == Some(0) {
+        return Err(
+            "Invalid arguments provided, --max-buffered-bytes must be greater than 0.".to_string(),
+        );
+    }
+    if args.isolated_send_queue_capacity == 0 {
+        return Err(
+            "Invalid arguments provided, --isolated-send-queue-capacity must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.deduper_num_bits == 0 {
+        return Err(
+            "Invalid arguments provided, --deduper-num-bits must be greater than 0.".to_string(),
+        );
+    }
+    if !(args.deduper_false_positive_rate > 0.0 && args.deduper_false_positive_rate < 1.0) {
+        return Err("Invalid arguments provided, --deduper-false-positive-rate must be between 0 and 1 exclusive.".to_string());
+    }
+    if args.health_check_mode.is_some()
+        && (args.health_check_interval_ms == 0
+            || args.health_check_timeout_ms == 0
+            || args.health_check_failure_threshold == 0)
+    {
+        return Err("Invalid arguments provided, --health-check-interval-ms, --health-check-timeout-ms, and --health-check-failure-threshold must be greater than 0.".to_string());
+    }
+    if args.spill_max_bytes == 0
+        || args.spill_replay_rate_pps == 0
+        || args.spill_max_age_ms == 0
+        || args.spill_live_queue_packets == 0
+    {
+        return Err("Invalid arguments provided, --spill-max-bytes, --spill-replay-rate-pps, --spill-max-age-ms, and --spill-live-queue-packets must be greater than 0.".to_string());
+    }
+    if args.spill_dir.is_none() {
+        if let Some((_, hostname_port)) = args.dest_ip_ports.iter().find(|(_, hostname_port)| {
+            rate_limit::parse_dest_options(hostname_port).is_ok_and(|options| options.spill)
+        }) {
+            return Err(format!(
+                "Invalid arguments provided, destination {hostname_port} sets spill, which requires --spill-dir."
+            ));
+        }
+    }
+    if args.health_check_mode == Some(HealthCheckMode::Http)
+        && !args.health_check_http_path.starts_with('/')
+    {
+        return Err(
+            "Invalid arguments provided, --health-check-http-path must start with `/`.".to_string(),
+        );
+    }
+    if args.recv_socket_buffer_bytes == Some(0) || args.send_socket_buffer_bytes == Some(0) {
+        return Err("Invalid arguments provided, --recv-socket-buffer-bytes and --send-socket-buffer-bytes must be greater than 0.".to_string());
+    }
+    if args.deduper_reset_interval_ms == 0 {
+        return Err(
+            "Invalid arguments provided, --deduper-reset-interval-ms must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.thread_restart_window_secs == 0 {
+        return Err(
+            "Invalid arguments provided, --thread-restart-window-secs must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args
+        .recv_mmsg_batch_size
+        .is_some_and(|batch_size| !(1..=socket::MAX_RECV_MMSG_BATCH_SIZE).contains(&batch_size))
+    {
+        return Err(format!(
+            "Invalid arguments provided, --recv-mmsg-batch-size must be between 1 and {}.",
+            socket::MAX_RECV_MMSG_BATCH_SIZE
+        ));
+    }
+    if args.recv_poll_timeout_ms == 0 {
+        return Err(
+            "Invalid arguments provided, --recv-poll-timeout-ms must be greater than 0."
+                .to_string(),
+        );
+    }
+    if args.measure_internal_latency && !cfg!(target_os = "linux") {
+        return Err(
+            "Invalid arguments provided, --measure-internal-latency is only supported on Linux."
+                .to_string(),
+        );
+    }
+    if args.public_ip.is_some() && args.advertise_addr.is_some() {
+        return Err(
+            "Invalid arguments provided, only one of --public-ip and --advertise-addr can be given."
+                .to_string(),
+        );
+    }
+    // root can break out of a chroot, so it's only useful alongside switching user
+    if args.chroot.is_some() && args.run_as_user.is_none() {
+        return Err("Invalid arguments provided, --chroot requires --run-as-user.".to_string());
+    }
+    // sealed packets are read into larger buffers than recvmmsg's, which can't hold a full-size shred plus the seal
+    if args.decrypt_key_file.is_some()
+        && (args.recv_mmsg_batch_size.is_some() || args.measure_internal_latency)
+    {
+        return Err("Invalid arguments provided, --decrypt-key-file can't be combined with --recv-mmsg-batch-size or --measure-internal-latency.".to_string());
+    }
+    for key_file in encryption_key_files(args) {
+        encryption::read_key(&key_file).map_err(|e| format!("Invalid arguments provided, {e}"))?;
+    }
+    Ok(())
+}
+
+/// Returns an error if there's no way to get destinations, unless shreds are consumed in process by subscribers
+pub fn validate_has_destinations(args: &CommonArgs) -> Result<(), String> {
+    if args.endpoint_discovery_url.is_none()
+        && args.discovered_endpoints_port.is_none()
+        && args.rpc_discovery_url.is_none()
+        && args.dest_ip_ports.is_empty()
+        && args.dest_shard_groups.is_empty()
+        && args.dest_validator_identities.is_empty()
+        && args.listeners.is_empty()
+        && args.admin_bind_addr.is_none()
+        && args.admin_grpc_bind_addr.is_none()
+    {
+        return Err("No destinations found. You must provide values for --dest-ip-ports, --dest-shard-group, --dest-validator-identities, --listener, --endpoint-discovery-url, --rpc-discovery-url, --admin-bind-addr, or --admin-grpc-bind-addr.".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_block_engine_args(args: &ShredstreamArgs) -> Result<(), String> {
+    if args
+        .block_engine_url
+        .iter()
+        .any(|url| url.trim().is_empty())
+    {
+        return Err(
+            "Invalid arguments provided, --block-engine-url must not contain empty urls."
+                .to_string(),
+        );
+    }
+    if args.desired_regions.is_empty() {
+        return Err(
+            "Invalid arguments provided, --desired-regions requires at least 1 region.".to_string(),
+        );
+    }
+    if args.block_engine_failover_threshold == 0 || args.block_engine_primary_retry_secs == 0 {
+        return Err("Invalid arguments provided, --block-engine-failover-threshold and --block-engine-primary-retry-secs must be greater than 0.".to_string());
+    }
+    if args.token_refresh_margin_secs == 0 {
+        return Err(
+            "Invalid arguments provided, --token-refresh-margin-secs must be greater than 0."
+                .to_string(),
+        );
+    }
+    if [
+        args.grpc_connect_timeout_ms,
+        args.grpc_keepalive_interval_ms,
+        args.grpc_keepalive_timeout_ms,
+    ]
+    .contains(&Some(0))
+    {
+        return Err("Invalid arguments provided, --grpc-connect-timeout-ms, --grpc-keepalive-interval-ms, and --grpc-keepalive-timeout-ms must be greater than 0.".to_string());
+    }
+    if args.grpc_keepalive_timeout_ms.is_some() && args.grpc_keepalive_interval_ms.is_none() {
+        return Err("Invalid arguments provided, --grpc-keepalive-timeout-ms requires --grpc-keepalive-interval-ms.".to_string());
+    }
+    Ok(())
+}
+
+/// Returns an error if there's no core for each forwarder thread when pinning them
+pub fn validate_core_affinity(
+    core_affinity: &[usize],
+    num_forwarder_threads: usize,
+) -> Result<(), String> {
+    if !core_affinity.is_empty() && core_affinity.len() < num_forwarder_threads {
+        return Err(format!("Invalid arguments provided, --core-affinity lists {} cores but {num_forwarder_threads} send threads are started. List more cores or lower --num-send-threads.", core_affinity.len()));
+    }
+    Ok(())
+}
+
+/// Returns an error if the platform rejects `--dscp` or `--listen-dscp`, by marking throwaway sockets like the ones they apply to
+pub fn validate_dscp(args: &CommonArgs) -> Result<(), String> {
+    if let Some(dscp) = args.dscp {
+        socket::bind_send_socket()
+            .and_then(|socket| socket::set_dscp(&socket, dscp))
+            .map_err(|e| format!("Invalid arguments provided, --dscp {dscp} can't be applied to forwarding sockets: {e}"))?;
+        info!("Marking forwarded packets with DSCP {dscp}.");
+    }
+    if let Some(dscp) = args.listen_dscp {
+        UdpSocket::bind(SocketAddr::new(args.src_bind_addr, 0))
+            .and_then(|socket| socket::set_dscp(&socket, dscp))
+            .map_err(|e| format!("Invalid arguments provided, --listen-dscp {dscp} can't be applied to listen sockets: {e}"))?;
+        info!("Marking packets sent from listen sockets with DSCP {dscp}.");
+    }
+    Ok(())
+}
+
+/// Returns an error if forwarding sockets can't be bound with `--egress-interface` and `--egress-source-ip`,
+/// or with any destination's `iface` and `src` options, by binding throwaway sockets with each
+pub fn validate_egress(args: &CommonArgs) -> Result<(), String> {
+    let egress = args.egress();
+    if !egress.is_default() {
+        socket::bind_egress_send_socket(&egress).map_err(|e| {
+            let flags = [
+                egress
+                    .interface
+                    .as_ref()
+                    .map(|interface| format!("--egress-interface {interface}")),
+                egress
+                    .source_ip
+                    .map(|source_ip| format!("--egress-source-ip {source_ip}")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+            format!(
+                "Invalid arguments provided, {flags} can't be applied to forwarding sockets: {e}"
+            )
+        })?;
+        info!("Sending forwarded packets from {egress}.");
+    }
+    let mut checked = HashSet::from([egress.clone()]);
+    for (_, hostname_port) in &args.dest_ip_ports {
+        let Ok(dest_options) = rate_limit::parse_dest_options(hostname_port) else {
+            continue;
+        };
+        let dest_egress = dest_options.egress.or(&egress);
+        if !checked.insert(dest_egress.clone()) {
+            continue;
+        }
+        socket::bind_egress_send_socket(&dest_egress).map_err(|e| {
+            format!("Invalid arguments provided, destination {hostname_port} can't be sent to from {dest_egress}: {e}")
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns an error if a port per region can't be assigned starting from `src_bind_port`
+pub fn validate_region_ports(src_bind_port: u16, desired_regions: &[String]) -> Result<(), String> {
+    if src_bind_port == 0 {
+        return Err(
+            "Invalid arguments provided, --region-ports requires a fixed --src-bind-port."
+                .to_string(),
+        );
+    }
+    if src_bind_port as usize + desired_regions.len() - 1 > u16::MAX as usize {
+        return Err(format!("Invalid arguments provided, --region-ports needs {} ports starting from --src-bind-port {src_bind_port}.", desired_regions.len()));
+    }
+    Ok(())
+}
+
+/// Reads the auth keypair from the file, environment variable, or stdin given in `args`.
+/// Errors never include the secret.
+fn read_auth_keypair(args: &ShredstreamArgs) -> Result<Keypair, String> {
+    if let Some(auth_keypair) = &args.auth_keypair {
+        return read_keypair_file(auth_keypair).map_err(|e| {
+            format!("Unable to parse keypair file. Ensure that file {auth_keypair:?} is readable. Error: {e}")
+        });
+    }
+    let (encoded, source) = if let Some(env_var) = &args.auth_keypair_base58 {
+        let encoded = std::env::var(env_var).map_err(|e| {
+            format!("Unable to read keypair from environment variable {env_var}. Error: {e}")
+        })?;
+        (encoded, format!("environment variable {env_var}"))
+    } else if args.auth_keypair_stdin {
+        let mut encoded = String::new();
+        io::stdin()
+            .read_to_string(&mut encoded)
+            .map_err(|e| format!("Unable to read keypair from stdin. Error: {e}"))?;
+        (encoded, "stdin".to_string())
+    } else {
+        return Err(
+            "No auth keypair provided. You must provide --auth-keypair, --auth-keypair-base58, or --auth-keypair-stdin."
+                .to_string(),
+        );
+    };
+    parse_keypair(&encoded).map_err(|e| format!("Unable to parse keypair from {source}. {e}"))
+}
+
+/// Returns how to connect to the block engine and auth service, reading `--grpc-tls-ca-cert`
+fn grpc_channel_config(args: &ShredstreamArgs) -> Result<GrpcChannelConfig, String> {
+    let tls_ca_cert = match &args.grpc_tls_ca_cert {
+        Some(path) => {
+            let pem = fs::read(path).map_err(|e| {
+                format!("Unable to read --grpc-tls-ca-cert. Ensure that file {path:?} is readable. Error: {e}")
+            })?;
+            // tonic only parses it when connecting, so check it here to fail at startup instead of on every reconnect
+            let num_certs = CertificateDer::pem_slice_iter(&pem)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Invalid --grpc-tls-ca-cert {path:?}: {e}"))?
+                .len();
+            if num_certs == 0 {
+                return Err(format!(
+                    "Invalid --grpc-tls-ca-cert {path:?}: no PEM certificates found"
+                ));
+            }
+            Some(Certificate::from_pem(pem))
+        }
+        None => None,
+    };
+    Ok(GrpcChannelConfig {
+        connect_timeout: args.grpc_connect_timeout_ms.map(Duration::from_millis),
+        keepalive_interval: args.grpc_keepalive_interval_ms.map(Duration::from_millis),
+        keepalive_timeout: args.grpc_keepalive_timeout_ms.map(Duration::from_millis),
+        tls_ca_cert,
+    })
+}
+
+/// Parses a base58 encoded keypair, or the JSON byte array written by `solana-keygen`.
+/// Errors describe the expected format rather than the parse error, which may quote the secret.
+fn parse_keypair(encoded: &str) -> Result<Keypair, String> {
+    let encoded = encoded.trim();
+    let bytes = if encoded.starts_with('[') {
+        serde_json::from_str::<Vec<u8>>(encoded)
+            .map_err(|_| "Expected a JSON array of 64 bytes.".to_string())?
+    } else {
+        solana_sdk::bs58::decode(encoded)
+            .into_vec()
+            .map_err(|_| "Expected a base58 encoded keypair.".to_string())?
+    };
+    Keypair::from_bytes(&bytes).map_err(|_| {
+        format!(
+            "Expected a 64 byte keypair, got {} bytes or an invalid public key.",
+            bytes.len()
+        )
+    })
+}
+
+/// Subcommand a config file runs, from its `mode` field
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ConfigMode {
+    #[default]
+    Shredstream,
+    ForwardOnly,
+}
+
+/// Args loaded from a config file, for the subcommand selected by its `mode`
+// parsed once per reload, not worth boxing
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum ProxyConfig {
+    Shredstream(ShredstreamArgs),
+    ForwardOnly(CommonArgs),
+}
+
+impl ProxyConfig {
+    pub fn common_args(&self) -> &CommonArgs {
+        match self {
+            ProxyConfig::Shredstream(args) => &args.common_args,
+            ProxyConfig::ForwardOnly(args) => args,
+        }
+    }
+}
+
+/// Block engine fields are optional so `forward-only` configs can omit them, and are checked against the mode when converting
+#[derive(Clone, Debug, serde::Deserialize)]
+struct ShredstreamConfig {
+    #[serde(default)]
+    mode: ConfigMode,
+    /// Either a list or a comma separated string, like `--block-engine-url`
+    #[serde(default, deserialize_with = "deserialize_optional_comma_separated")]
+    block_engine_url: Option<Vec<String>>,
+    #[serde(default)]
+    block_engine_failover_threshold: Option<u32>,
+    #[serde(default)]
+    block_engine_primary_retry_secs: Option<u64>,
+    #[serde(default)]
+    auth_url: Option<String>,
+    #[serde(default)]
+    auth_keypair: Option<PathBuf>,
+    /// Name of an environment variable holding the keypair, instead of `auth_keypair`
+    #[serde(default)]
+    auth_keypair_env: Option<String>,
+    #[serde(default)]
+    desired_regions: Option<Vec<String>>,
+    #[serde(default)]
+    region_ports: Option<bool>,
+    #[serde(default)]
+    stall_timeout_secs: Option<u64>,
+    #[serde(default)]
+    ready_heartbeat_max_age_secs: Option<u64>,
+    #[serde(default)]
+    token_refresh_margin_secs: Option<u64>,
+    #[serde(default)]
+    token_cache_file: Option<PathBuf>,
+    #[serde(default)]
+    initial_auth_jitter_ms: Option<u64>,
+    #[serde(default)]
+    grpc_connect_timeout_ms: Option<u64>,
+    #[serde(default)]
+    grpc_keepalive_interval_ms: Option<u64>,
+    #[serde(default)]
+    grpc_keepalive_timeout_ms: Option<u64>,
+    #[serde(default)]
+    grpc_tls_ca_cert: Option<PathBuf>,
+    common: CommonConfig,
+}
+
+impl ShredstreamConfig {
+    /// Names of block engine fields set in the file
+    fn shredstream_fields(&self) -> Vec<&'static str> {
+        [
+            ("block_engine_url", self.block_engine_url.is_some()),
+            (
+                "block_engine_failover_threshold",
+                self.block_engine_failover_threshold.is_some(),
+            ),
+            (
+                "block_engine_primary_retry_secs",
+                self.block_engine_primary_retry_secs.is_some(),
+            ),
+            ("auth_url", self.auth_url.is_some()),
+            ("auth_keypair", self.auth_keypair.is_some()),
+            ("auth_keypair_env", self.auth_keypair_env.is_some()),
+            ("desired_regions", self.desired_regions.is_some()),
+            ("region_ports", self.region_ports.is_some()),
+            ("stall_timeout_secs", self.stall_timeout_secs.is_some()),
+            (
+                "ready_heartbeat_max_age_secs",
+                self.ready_heartbeat_max_age_secs.is_some(),
+            ),
+            (
+                "token_refresh_margin_secs",
+                self.token_refresh_margin_secs.is_some(),
+            ),
+            ("token_cache_file", self.token_cache_file.is_some()),
+            (
+                "initial_auth_jitter_ms",
+                self.initial_auth_jitter_ms.is_some(),
+            ),
+            (
+                "grpc_connect_timeout_ms",
+                self.grpc_connect_timeout_ms.is_some(),
+            ),
+            (
+                "grpc_keepalive_interval_ms",
+                self.grpc_keepalive_interval_ms.is_some(),
+            ),
+            (
+                "grpc_keepalive_timeout_ms",
+                self.grpc_keepalive_timeout_ms.is_some(),
+            ),
+            ("grpc_tls_ca_cert", self.grpc_tls_ca_cert.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(field, set)| set.then_some(field))
+        .collect()
+    }
+}
+
+/// Destination with per-destination options, equivalent to a `dest-ip-ports` entry with a query string
+#[derive(Clone, Debug, serde::Deserialize)]
+struct DestinationConfig {
+    addr: String,
+    #[serde(default)]
+    rate_pps: Option<u64>,
+    #[serde(default)]
+    egress_interface: Option<String>,
+    #[serde(default)]
+    egress_source_ip: Option<IpAddr>,
+    #[serde(default)]
+    priority: Option<DestPriority>,
+    #[serde(default)]
+    spill: bool,
+}
+
+/// `[[common.mirror]]` table, equivalent to a `mirror-dest` entry
+#[derive(Clone, Debug, serde::Deserialize)]
+struct MirrorConfig {
+    addr: String,
+    fraction: f64,
+    #[serde(default)]
+    sample_unit: MirrorSampleUnit,
+}
+
+impl MirrorConfig {
+    fn mirror_dest(&self) -> String {
+        format!(
+            "{}@{}?sample-unit={}",
+            self.addr, self.fraction, self.sample_unit
+        )
+    }
+}
+
+impl DestinationConfig {
+    fn hostname_port(&self) -> String {
+        let options = [
+            self.rate_pps.map(|rate_pps| format!("rate={rate_pps}pps")),
+            self.egress_interface
+                .as_ref()
+                .map(|interface| format!("iface={interface}")),
+            self.egress_source_ip
+                .map(|source_ip| format!("src={source_ip}")),
+            self.priority.map(|priority| format!("priority={priority}")),
+            self.spill.then(|| "spill=true".to_string()),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        if options.is_empty() {
+            self.addr.clone()
+        } else {
+            format!("{}?{}", self.addr, options.join("&"))
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+struct CommonConfig {
+    #[serde(default = "default_src_bind_addr")]
+    src_bind_addr: IpAddr,
+    #[serde(default = "default_src_bind_port")]
+    src_bind_port: u16,
+    #[serde(default)]
+    dest_ip_ports: Vec<String>,
+    /// `[[common.destination]]` tables, forwarded to alongside `dest_ip_ports`
+    #[serde(default, rename = "destination")]
+    destinations: Vec<DestinationConfig>,
+    /// `shard(<host:port>,...)` groups
+    #[serde(default)]
+    dest_shard_groups: Vec<String>,
+    /// `<host:port>@<fraction>` entries
+    #[serde(default)]
+    mirror_dests: Vec<String>,
+    /// `[[common.mirror]]` tables, mirrored to alongside `mirror_dests`
+    #[serde(default, rename = "mirror")]
+    mirrors: Vec<MirrorConfig>,
+    /// `<ip:port>,<interval_ms>` entries
+    #[serde(default)]
+    upstream_keepalive: Vec<String>,
+    /// `[[common.listener]]` tables
+    #[serde(default, rename = "listener")]
+    listeners: Vec<ListenerConfig>,
+    #[serde(default)]
+    decrypt_key_file: Option<PathBuf>,
+    #[serde(default = "default_tunnel_buffer_packets")]
+    tunnel_buffer_packets: usize,
+    #[serde(default)]
+    tunnel_tls_ca_cert: Option<PathBuf>,
+    #[serde(default)]
+    tunnel_tls_server_name: Option<String>,
+    #[serde(default)]
+    tunnel_tls_client_cert: Option<PathBuf>,
+    #[serde(default)]
+    tunnel_tls_client_key: Option<PathBuf>,
+    #[serde(default)]
+    endpoint_discovery_url: Option<String>,
+    #[serde(default)]
+    discovered_endpoints_port: Option<u16>,
+    #[serde(default)]
+    default_dest_port: Option<u16>,
+    /// `Name: Value` headers
+    #[serde(default)]
+    endpoint_discovery_headers: Vec<String>,
+    #[serde(default)]
+    endpoint_discovery_bearer_token_file: Option<PathBuf>,
+    #[serde(default = "default_endpoint_discovery_interval")]
+    endpoint_discovery_interval_ms: u64,
+    #[serde(default)]
+    endpoint_discovery_state_file: Option<PathBuf>,
+    #[serde(default = "default_endpoint_discovery_state_max_age")]
+    endpoint_discovery_state_max_age_secs: u64,
+    #[serde(default = "default_endpoint_discovery_max_response_bytes")]
+    endpoint_discovery_max_response_bytes: u64,
+    #[serde(default = "default_endpoint_discovery_max_removal_percent")]
+    endpoint_discovery_max_removal_percent: u8,
+    #[serde(default = "default_dest_resolve_interval")]
+    dest_resolve_interval_secs: u64,
+    #[serde(default)]
+    dest_address_family: AddressFamily,
+    /// Base58 pubkeys
+    #[serde(default)]
+    dest_validator_identities: Vec<String>,
+    #[serde(default)]
+    dest_validator_rpc_url: Option<String>,
+    #[serde(default = "default_dest_validator_refresh")]
+    dest_validator_refresh_secs: u64,
+    #[serde(default = "default_dest_validator_unresolved_alert")]
+    dest_validator_unresolved_alert_secs: u64,
+    #[serde(default)]
+    rpc_discovery_url: Option<String>,
+    #[serde(default)]
+    rpc_discovery_filter: Option<String>,
+    #[serde(default = "default_rpc_discovery_interval")]
+    rpc_discovery_interval_secs: u64,
+    #[serde(default)]
+    rpc_discovery_geo_file: Option<PathBuf>,
+    #[serde(default)]
+    max_slot_age: Option<u64>,
+    #[serde(default)]
+    drop_non_shred_packets: bool,
+    #[serde(default)]
+    forward_shred_types: ForwardShredTypes,
+    #[serde(default)]
+    forward_unknown_packets: bool,
+    #[serde(default)]
+    dest_blocklist: Vec<String>,
+    #[serde(default)]
+    allow_self_forward: bool,
+    #[serde(default = "default_max_destinations")]
+    max_destinations: usize,
+    #[serde(default)]
+    allowed_source_ips: Vec<String>,
+    #[serde(default)]
+    allowed_source_ips_file: Option<PathBuf>,
+    #[serde(default = "default_metrics_report_interval")]
+    metrics_report_interval_ms: u64,
+    #[serde(default)]
+    metrics_backend: MetricsBackendKind,
+    #[serde(default)]
+    statsd_addr: Option<String>,
+    #[serde(default)]
+    statsd_dialect: StatsdDialect,
+    #[serde(default)]
+    prometheus_bind_addr: Option<SocketAddr>,
+    #[serde(default)]
+    admin_bind_addr: Option<SocketAddr>,
+    #[serde(default)]
+    admin_grpc_bind_addr: Option<SocketAddr>,
+    #[serde(default)]
+    admin_grpc_tls_cert_file: Option<PathBuf>,
+    #[serde(default)]
+    admin_grpc_tls_key_file: Option<PathBuf>,
+    #[serde(default)]
+    grpc_service_bind_addr: Option<SocketAddr>,
+    #[serde(default = "default_deshred_fec_set_timeout")]
+    deshred_fec_set_timeout_ms: u64,
+    #[serde(default)]
+    ws_bind_addr: Option<SocketAddr>,
+    #[serde(default = "default_ws_max_clients")]
+    ws_max_clients: usize,
+    #[serde(default)]
+    alert_webhook_url: Option<String>,
+    #[serde(default)]
+    alert_webhook_format: AlertWebhookFormat,
+    #[serde(default)]
+    alert_host_id: Option<String>,
+    #[serde(default = "default_alert_no_shreds")]
+    alert_no_shreds_secs: u64,
+    #[serde(default = "default_alert_forward_failure_percent")]
+    alert_forward_failure_percent: f64,
+    #[serde(default = "default_alert_heartbeat_down")]
+    alert_heartbeat_down_secs: u64,
+    #[serde(default = "default_alert_deduper_saturation_percent")]
+    alert_deduper_saturation_percent: f64,
+    #[serde(default = "default_alert_cooldown")]
+    alert_cooldown_secs: u64,
+    #[serde(default)]
+    slot_latency_rpc_url: Option<String>,
+    #[serde(default)]
+    otlp_endpoint: Option<String>,
+    #[serde(default)]
+    debug_trace_shred: bool,
+    #[serde(default)]
+    trace_shred_sample_rate: f64,
+    #[serde(default)]
+    chaos_drop_rate: f64,
+    #[serde(default)]
+    chaos_duplicate_rate: f64,
+    #[serde(default)]
+    chaos_reorder_window: usize,
+    #[serde(default)]
+    chaos_delay_ms: u64,
+    #[serde(default)]
+    chaos_dests: Vec<SocketAddr>,
+    #[serde(default)]
+    public_ip: Option<IpAddr>,
+    #[serde(default = "default_public_ip_family")]
+    public_ip_family: AddressFamily,
+    #[serde(default = "default_public_ip_recheck_interval")]
+    public_ip_recheck_interval_secs: u64,
+    #[serde(default)]
+    advertise_addr: Option<SocketAddr>,
+    #[serde(default)]
+    num_threads: Option<usize>,
+    #[serde(default)]
+    num_recv_threads: Option<usize>,
+    #[serde(default)]
+    num_send_threads: Option<usize>,
+    #[serde(default)]
+    core_affinity: Vec<usize>,
+    #[serde(default = "default_send_batch_size")]
+    send_batch_size: usize,
+    #[serde(default)]
+    send_batch_linger_us: u64,
+    #[serde(default)]
+    isolated_send_threads: bool,
+    #[serde(default = "default_isolated_send_queue_capacity")]
+    isolated_send_queue_capacity: usize,
+    #[serde(default = "default_forwarder_channel_capacity")]
+    forwarder_channel_capacity: usize,
+    #[serde(default)]
+    forwarder_drop_policy: DropPolicy,
+    #[serde(default)]
+    max_buffered_bytes: Option<u64>,
+    #[serde(default)]
+    recv_mmsg_batch_size: Option<usize>,
+    #[serde(default = "default_recv_poll_timeout")]
+    recv_poll_timeout_ms: u64,
+    #[serde(default)]
+    measure_internal_latency: bool,
+    #[serde(default)]
+    rx_timestamp_source: RxTimestampSource,
+    #[serde(default)]
+    measure_send_latency: bool,
+    #[serde(default = "default_slow_send_threshold_us")]
+    slow_send_threshold_us: u64,
+    #[serde(default = "default_deduper_num_bits")]
+    deduper_num_bits: u64,
+    #[serde(default = "default_deduper_false_positive_rate")]
+    deduper_false_positive_rate: f64,
+    #[serde(default)]
+    dedup_mode: DedupMode,
+    #[serde(default)]
+    record_pcap: Option<PathBuf>,
+    #[serde(default = "default_record_pcap_rotate_bytes")]
+    record_pcap_rotate_bytes: u64,
+    #[serde(default)]
+    record_pcap_rotate_secs: u64,
+    #[serde(default = "default_record_pcap_max_files")]
+    record_pcap_max_files: usize,
+    #[serde(default)]
+    detect_conflicting_shreds: bool,
+    #[serde(default = "default_conflicting_shreds_max_slots")]
+    conflicting_shreds_max_slots: usize,
+    #[serde(default)]
+    conflicting_shreds_dir: Option<PathBuf>,
+    #[serde(default)]
+    events_dest: Option<SocketAddr>,
+    #[serde(default)]
+    events_format: EventsFormat,
+    #[serde(default = "default_events_abandon_timeout")]
+    events_abandon_timeout_ms: u64,
+    #[serde(default = "default_events_max_per_sec")]
+    events_max_per_sec: u64,
+    #[serde(default)]
+    kafka_brokers: Option<String>,
+    #[serde(default)]
+    kafka_topic: Option<String>,
+    #[serde(default)]
+    kafka_security_protocol: KafkaSecurityProtocol,
+    #[serde(default)]
+    kafka_sasl_mechanism: Option<String>,
+    #[serde(default)]
+    kafka_sasl_username: Option<String>,
+    #[serde(default)]
+    kafka_sasl_password_file: Option<PathBuf>,
+    #[serde(default)]
+    kafka_ssl_ca_location: Option<PathBuf>,
+    #[serde(default = "default_kafka_queue_capacity")]
+    kafka_queue_capacity: usize,
+    #[serde(default)]
+    archive_path: Option<PathBuf>,
+    #[serde(default = "default_archive_retention_slots")]
+    archive_retention_slots: u64,
+    #[serde(default)]
+    archive_read_check: bool,
+    #[serde(default)]
+    health_check_mode: Option<HealthCheckMode>,
+    #[serde(default)]
+    health_check_port: Option<u16>,
+    #[serde(default = "default_health_check_http_path")]
+    health_check_http_path: String,
+    #[serde(default = "default_health_check_interval")]
+    health_check_interval_ms: u64,
+    #[serde(default = "default_health_check_timeout")]
+    health_check_timeout_ms: u64,
+    #[serde(default = "default_health_check_failure_threshold")]
+    health_check_failure_threshold: u32,
+    #[serde(default)]
+    spill_dir: Option<PathBuf>,
+    #[serde(default = "default_spill_max_bytes")]
+    spill_max_bytes: u64,
+    #[serde(default = "default_spill_replay_rate_pps")]
+    spill_replay_rate_pps: u64,
+    #[serde(default = "default_spill_max_age_ms")]
+    spill_max_age_ms: u64,
+    #[serde(default = "default_spill_live_queue_packets")]
+    spill_live_queue_packets: usize,
+    #[serde(default)]
+    recv_socket_buffer_bytes: Option<usize>,
+    #[serde(default)]
+    send_socket_buffer_bytes: Option<usize>,
+    #[serde(default)]
+    dscp: Option<u8>,
+    #[serde(default)]
+    listen_dscp: Option<u8>,
+    #[serde(default)]
+    egress_interface: Option<String>,
+    #[serde(default)]
+    egress_source_ip: Option<IpAddr>,
+    #[serde(default = "default_socket_rebind_error_threshold")]
+    socket_rebind_error_threshold: usize,
+    #[serde(default = "default_socket_max_rebinds_per_minute")]
+    socket_max_rebinds_per_minute: usize,
+    #[serde(default = "default_deduper_reset_interval")]
+    deduper_reset_interval_ms: u64,
+    #[serde(default = "default_thread_max_restarts")]
+    thread_max_restarts: usize,
+    #[serde(default = "default_thread_restart_window")]
+    thread_restart_window_secs: u64,
+    #[serde(default)]
+    crash_report_file: Option<PathBuf>,
+    #[serde(default = "default_shutdown_grace_period")]
+    shutdown_grace_period_ms: u64,
+    #[serde(default)]
+    run_as_user: Option<String>,
+    #[serde(default)]
+    run_as_group: Option<String>,
+    #[serde(default)]
+    chroot: Option<PathBuf>,
+}
+
+// Default value functions for CommonConfig
+fn deserialize_comma_separated<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    // a visitor rather than an untagged enum, so parse errors keep their location
+    struct CommaSeparatedVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for CommaSeparatedVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+            formatter.write_str("a comma separated string or a list of strings")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(value.split(',').map(str::to_string).collect())
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut values = Vec::new();
+            while let Some(value) = seq.next_element()? {
+                values.push(value);
+            }
+            Ok(values)
+        }
+    }
+
+    deserializer.deserialize_any(CommaSeparatedVisitor)
+}
+
+fn deserialize_optional_comma_separated<'de, D>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    deserialize_comma_separated(deserializer).map(Some)
+}
+
+fn default_block_engine_failover_threshold() -> u32 {
+    3
+}
+
+fn default_block_engine_primary_retry_secs() -> u64 {
+    600
+}
+
+fn default_stall_timeout() -> u64 {
+    120
+}
+
+fn default_ready_heartbeat_max_age() -> u64 {
+    30
+}
+
+fn default_token_refresh_margin() -> u64 {
+    token_authenticator::DEFAULT_TOKEN_REFRESH_MARGIN.as_secs()
+}
+
+fn default_initial_auth_jitter() -> u64 {
+    2_000
+}
+
+fn default_src_bind_addr() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+}
+
+fn default_src_bind_port() -> u16 {
+    20_000
+}
+
+fn default_endpoint_discovery_interval() -> u64 {
+    30_000
+}
+
+fn default_endpoint_discovery_state_max_age() -> u64 {
+    86_400
+}
+
+fn default_endpoint_discovery_max_response_bytes() -> u64 {
+    1_048_576
+}
+
+fn default_endpoint_discovery_max_removal_percent() -> u8 {
+    50
+}
+
+fn default_max_destinations() -> usize {
+    200
+}
+
+fn default_dest_resolve_interval() -> u64 {
+    30
+}
+
+fn default_public_ip_recheck_interval() -> u64 {
+    300
+}
+
+fn default_public_ip_family() -> AddressFamily {
+    AddressFamily::V4
+}
+
+fn default_deshred_fec_set_timeout() -> u64 {
+    2_000
+}
+
+fn default_ws_max_clients() -> usize {
+    16
+}
+
+fn default_alert_no_shreds() -> u64 {
+    60
+}
+
+fn default_alert_forward_failure_percent() -> f64 {
+    5.0
+}
+
+fn default_alert_heartbeat_down() -> u64 {
+    60
+}
+
+fn default_alert_deduper_saturation_percent() -> f64 {
+    10.0
+}
+
+fn default_alert_cooldown() -> u64 {
+    900
+}
+
+fn default_metrics_report_interval() -> u64 {
+    15_000
+}
+
+fn default_send_batch_size() -> usize {
+    forwarder::DEFAULT_SEND_BATCH_SIZE
+}
+
+fn default_slow_send_threshold_us() -> u64 {
+    1_000
+}
+
+fn default_isolated_send_queue_capacity() -> usize {
+    isolated_send::DEFAULT_ISOLATED_SEND_QUEUE_CAPACITY
+}
+
+fn default_forwarder_channel_capacity() -> usize {
+    packet_channel::DEFAULT_FORWARDER_CHANNEL_CAPACITY
+}
+
+fn default_recv_poll_timeout() -> u64 {
+    1_000
+}
+
+fn default_record_pcap_rotate_bytes() -> u64 {
+    1 << 30
+}
+
+fn default_record_pcap_max_files() -> usize {
+    10
+}
+
+fn default_conflicting_shreds_max_slots() -> usize {
+    32
+}
+
+fn default_events_abandon_timeout() -> u64 {
+    1_000
+}
+
+fn default_events_max_per_sec() -> u64 {
+    100
+}
+
+fn default_dest_validator_refresh() -> u64 {
+    60
+}
+
+fn default_dest_validator_unresolved_alert() -> u64 {
+    600
+}
+
+fn default_rpc_discovery_interval() -> u64 {
+    300
+}
+
+fn default_tunnel_buffer_packets() -> usize {
+    65_536
+}
+
+fn default_kafka_queue_capacity() -> usize {
+    65_536
+}
+
+fn default_archive_retention_slots() -> u64 {
+    10_000
+}
+
+fn default_health_check_http_path() -> String {
+    "/health".to_string()
+}
+
+fn default_health_check_interval() -> u64 {
+    5_000
+}
+
+fn default_health_check_timeout() -> u64 {
+    1_000
+}
+
+fn default_health_check_failure_threshold() -> u32 {
+    3
+}
+
+fn default_spill_max_bytes() -> u64 {
+    spill::DEFAULT_SPILL_MAX_BYTES
+}
+
+fn default_spill_replay_rate_pps() -> u64 {
+    50_000
+}
+
+fn default_spill_max_age_ms() -> u64 {
+    60_000
+}
+
+fn default_spill_live_queue_packets() -> usize {
+    100_000
+}
+
+fn default_deduper_num_bits() -> u64 {
+    forwarder::DEDUPER_NUM_BITS
+}
+
+fn default_deduper_false_positive_rate() -> f64 {
+    forwarder::DEDUPER_FALSE_POSITIVE_RATE
+}
+
+fn default_socket_rebind_error_threshold() -> usize {
+    100
+}
+
+fn default_socket_max_rebinds_per_minute() -> usize {
+    10
+}
+
+fn default_deduper_reset_interval() -> u64 {
+    forwarder::DEDUPER_RESET_CYCLE.as_millis() as u64
+}
+
+fn default_thread_max_restarts() -> usize {
+    5
+}
+
+fn default_thread_restart_window() -> u64 {
+    600
+}
+
+fn default_shutdown_grace_period() -> u64 {
+    5_000
+}
+
+impl TryFrom<ShredstreamConfig> for ShredstreamArgs {
+    type Error = io::Error;
+
+    fn try_from(config: ShredstreamConfig) -> Result<Self, Self::Error> {
+        if config.mode != ConfigMode::Shredstream {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Config has mode = \"forward-only\", expected a shredstream config.",
+            ));
+        }
+        let required = |field: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{field} is required with mode = \"shredstream\"."),
+            )
+        };
+        if config.auth_keypair.is_some() == config.auth_keypair_env.is_some() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "Exactly one of auth_keypair or auth_keypair_env must be set.",
+            ));
+        }
+        Ok(ShredstreamArgs {
+            block_engine_url: config
+                .block_engine_url
+                .ok_or_else(|| required("block_engine_url"))?,
+            block_engine_failover_threshold: config
+                .block_engine_failover_threshold
+                .unwrap_or_else(default_block_engine_failover_threshold),
+            block_engine_primary_retry_secs: config
+                .block_engine_primary_retry_secs
+                .unwrap_or_else(default_block_engine_primary_retry_secs),
+            auth_url: config.auth_url,
+            auth_keypair: config.auth_keypair,
+            auth_keypair_base58: config.auth_keypair_env,
+            auth_keypair_stdin: false,
+            desired_regions: config
+                .desired_regions
+                .ok_or_else(|| required("desired_regions"))?,
+            list_regions: false,
+            region_ports: config.region_ports.unwrap_or_default(),
+            stall_timeout_secs: config
+                .stall_timeout_secs
+                .unwrap_or_else(default_stall_timeout),
+            ready_heartbeat_max_age_secs: config
+                .ready_heartbeat_max_age_secs
+                .unwrap_or_else(default_ready_heartbeat_max_age),
+            token_refresh_margin_secs: config
+                .token_refresh_margin_secs
+                .unwrap_or_else(default_token_refresh_margin),
+            token_cache_file: config.token_cache_file,
+            initial_auth_jitter_ms: config
+                .initial_auth_jitter_ms
+                .unwrap_or_else(default_initial_auth_jitter),
+            grpc_connect_timeout_ms: config.grpc_connect_timeout_ms,
+            grpc_keepalive_interval_ms: config.grpc_keepalive_interval_ms,
+            grpc_keepalive_timeout_ms: config.grpc_keepalive_timeout_ms,
+            grpc_tls_ca_cert: config.grpc_tls_ca_cert,
+            common_args: config.common.try_into()?,
+        })
+    }
+}
+
+/// Forward-only configs never read a keypair, and reject block engine fields rather than silently ignoring them
+impl TryFrom<ShredstreamConfig> for ProxyConfig {
+    type Error = io::Error;
+
+    fn try_from(config: ShredstreamConfig) -> Result<Self, Self::Error> {
+        match config.mode {
+            ConfigMode::Shredstream => Ok(ProxyConfig::Shredstream(config.try_into()?)),
+            ConfigMode::ForwardOnly => {
+                let shredstream_fields = config.shredstream_fields();
+                if !shredstream_fields.is_empty() {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("Config fields {shredstream_fields:?} require mode = \"shredstream\", remove them with mode = \"forward-only\"."),
+                    ));
+                }
+                Ok(ProxyConfig::ForwardOnly(config.common.try_into()?))
+            }
+        }
+    }
+}
+
+impl TryFrom<CommonConfig> for CommonArgs {
+    type Error = io::Error;
+
+    fn try_from(config: CommonConfig) -> Result<Self, Self::Error> {
+        let default_dest_port = config
+            .default_dest_port
+            .or(config.discovered_endpoints_port)
+            .unwrap_or(DEFAULT_DEST_PORT);
+        Ok(CommonArgs {
+            src_bind_addr: config.src_bind_addr,
+            src_bind_port: config.src_bind_port,
+            dest_ip_ports: dedup_dest_ip_ports(
+                config
+                    .dest_ip_ports
+                    .into_iter()
+                    .chain(
+                        config
+                            .destinations
+                            .iter()
+                            .map(DestinationConfig::hostname_port),
+                    )
+                    .map(|addr| {
+                        resolve_hostname_port_with_family(
+                            &with_default_port(&addr, default_dest_port),
+                            config.dest_address_family,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            dest_shard_groups: config
+                .dest_shard_groups
+                .iter()
+                .map(|spec| shard::parse_shard_group_with_family(spec, config.dest_address_family))
+                .collect::<Result<Vec<_>, _>>()?,
+            mirror_dests: config
+                .mirror_dests
+                .into_iter()
+                .chain(config.mirrors.iter().map(MirrorConfig::mirror_dest))
+                .map(|spec| {
+                    mirror::parse_mirror_dest_with_family(&spec, config.dest_address_family)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            upstream_keepalives: config
+                .upstream_keepalive
+                .iter()
+                .map(|keepalive| {
+                    keepalive::parse_upstream_keepalive(keepalive)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            listeners: config
+                .listeners
+                .iter()
+                .map(|listener| listener.resolve(config.dest_address_family))
+                .collect::<Result<Vec<_>, _>>()?,
+            decrypt_key_file: config.decrypt_key_file,
+            tunnel_buffer_packets: config.tunnel_buffer_packets,
+            tunnel_tls_ca_cert: config.tunnel_tls_ca_cert,
+            tunnel_tls_server_name: config.tunnel_tls_server_name,
+            tunnel_tls_client_cert: config.tunnel_tls_client_cert,
+            tunnel_tls_client_key: config.tunnel_tls_client_key,
+            endpoint_discovery_url: config.endpoint_discovery_url,
+            discovered_endpoints_port: config.discovered_endpoints_port,
+            default_dest_port: config.default_dest_port,
+            endpoint_discovery_headers: config
+                .endpoint_discovery_headers
+                .iter()
+                .map(|header| parse_header(header))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            endpoint_discovery_bearer_token_file: config.endpoint_discovery_bearer_token_file,
+            endpoint_discovery_interval_ms: config.endpoint_discovery_interval_ms,
+            endpoint_discovery_state_file: config.endpoint_discovery_state_file,
+            endpoint_discovery_state_max_age_secs: config.endpoint_discovery_state_max_age_secs,
+            endpoint_discovery_max_response_bytes: config.endpoint_discovery_max_response_bytes,
+            endpoint_discovery_max_removal_percent: config.endpoint_discovery_max_removal_percent,
+            dest_resolve_interval_secs: config.dest_resolve_interval_secs,
+            dest_address_family: config.dest_address_family,
+            dest_validator_identities: config
+                .dest_validator_identities
+                .iter()
+                .map(|identity| parse_pubkey(identity))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            dest_validator_rpc_url: config.dest_validator_rpc_url,
+            dest_validator_refresh_secs: config.dest_validator_refresh_secs,
+            dest_validator_unresolved_alert_secs: config.dest_validator_unresolved_alert_secs,
+            rpc_discovery_url: config.rpc_discovery_url,
+            rpc_discovery_filter: config
+                .rpc_discovery_filter
+                .as_deref()
+                .map(stake_discovery::parse_rpc_discovery_filter)
+                .transpose()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            rpc_discovery_interval_secs: config.rpc_discovery_interval_secs,
+            rpc_discovery_geo_file: config.rpc_discovery_geo_file,
+            max_slot_age: config.max_slot_age,
+            drop_non_shred_packets: config.drop_non_shred_packets,
+            forward_shred_types: config.forward_shred_types,
+            forward_unknown_packets: config.forward_unknown_packets,
+            dest_blocklist: config
+                .dest_blocklist
+                .iter()
+                .map(|ip_net| parse_ip_net(ip_net))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            allow_self_forward: config.allow_self_forward,
+            max_destinations: config.max_destinations,
+            allowed_source_ips: config
+                .allowed_source_ips
+                .iter()
+                .map(|ip_net| parse_ip_net(ip_net))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?,
+            allowed_source_ips_file: config.allowed_source_ips_file,
+            metrics_report_interval_ms: config.metrics_report_interval_ms,
+            metrics_backend: config.metrics_backend,
+            statsd_addr: config.statsd_addr,
+            statsd_dialect: config.statsd_dialect,
+            prometheus_bind_addr: config.prometheus_bind_addr,
+            admin_bind_addr: config.admin_bind_addr,
+            admin_grpc_bind_addr: config.admin_grpc_bind_addr,
+            admin_grpc_tls_cert_file: config.admin_grpc_tls_cert_file,
+            admin_grpc_tls_key_file: config.admin_grpc_tls_key_file,
+            grpc_service_bind_addr: config.grpc_service_bind_addr,
+            deshred_fec_set_timeout_ms: config.deshred_fec_set_timeout_ms,
+            ws_bind_addr: config.ws_bind_addr,
+            ws_max_clients: config.ws_max_clients,
+            alert_webhook_url: config.alert_webhook_url,
+            alert_webhook_format: config.alert_webhook_format,
+            alert_host_id: config.alert_host_id,
+            alert_no_shreds_secs: config.alert_no_shreds_secs,
+            alert_forward_failure_percent: config.alert_forward_failure_percent,
+            alert_heartbeat_down_secs: config.alert_heartbeat_down_secs,
+            alert_deduper_saturation_percent: config.alert_deduper_saturation_percent,
+            alert_cooldown_secs: config.alert_cooldown_secs,
+            slot_latency_rpc_url: config.slot_latency_rpc_url,
+            otlp_endpoint: config.otlp_endpoint,
+            debug_trace_shred: config.debug_trace_shred,
+            trace_shred_sample_rate: config.trace_shred_sample_rate,
+            chaos_drop_rate: config.chaos_drop_rate,
+            chaos_duplicate_rate: config.chaos_duplicate_rate,
+            chaos_reorder_window: config.chaos_reorder_window,
+            chaos_delay_ms: config.chaos_delay_ms,
+            chaos_dests: config.chaos_dests,
+            public_ip: config.public_ip,
+            public_ip_family: config.public_ip_family,
+            public_ip_recheck_interval_secs: config.public_ip_recheck_interval_secs,
+            advertise_addr: config.advertise_addr,
+            num_threads: config.num_threads,
+            num_recv_threads: config.num_recv_threads,
+            num_send_threads: config.num_send_threads,
+            core_affinity: config.core_affinity,
+            send_batch_size: config.send_batch_size,
+            send_batch_linger_us: config.send_batch_linger_us,
+            isolated_send_threads: config.isolated_send_threads,
+            isolated_send_queue_capacity: config.isolated_send_queue_capacity,
+            forwarder_channel_capacity: config.forwarder_channel_capacity,
+            forwarder_drop_policy: config.forwarder_drop_policy,
+            max_buffered_bytes: config.max_buffered_bytes,
+            recv_mmsg_batch_size: config.recv_mmsg_batch_size,
+            recv_poll_timeout_ms: config.recv_poll_timeout_ms,
+            measure_internal_latency: config.measure_internal_latency,
+            rx_timestamp_source: config.rx_timestamp_source,
+            measure_send_latency: config.measure_send_latency,
+            slow_send_threshold_us: config.slow_send_threshold_us,
+            deduper_num_bits: config.deduper_num_bits,
+            deduper_false_positive_rate: config.deduper_false_positive_rate,
+            dedup_mode: config.dedup_mode,
+            record_pcap: config.record_pcap,
+            record_pcap_rotate_bytes: config.record_pcap_rotate_bytes,
+            record_pcap_rotate_secs: config.record_pcap_rotate_secs,
+            record_pcap_max_files: config.record_pcap_max_files,
+            detect_conflicting_shreds: config.detect_conflicting_shreds,
+            conflicting_shreds_max_slots: config.conflicting_shreds_max_slots,
+            conflicting_shreds_dir: config.conflicting_shreds_dir,
+            events_dest: config.events_dest,
+            events_format: config.events_format,
+            events_abandon_timeout_ms: config.events_abandon_timeout_ms,
+            events_max_per_sec: config.events_max_per_sec,
+            kafka_brokers: config.kafka_brokers,
+            kafka_topic: config.kafka_topic,
+            kafka_security_protocol: config.kafka_security_protocol,
+            kafka_sasl_mechanism: config.kafka_sasl_mechanism,
+            kafka_sasl_username: config.kafka_sasl_username,
+            kafka_sasl_password_file: config.kafka_sasl_password_file,
+            kafka_ssl_ca_location: config.kafka_ssl_ca_location,
+            kafka_queue_capacity: config.kafka_queue_capacity,
+            archive_path: config.archive_path,
+            archive_retention_slots: config.archive_retention_slots,
+            archive_read_check: config.archive_read_check,
+            health_check_mode: config.health_check_mode,
+            health_check_port: config.health_check_port,
+            health_check_http_path: config.health_check_http_path,
+            health_check_interval_ms: config.health_check_interval_ms,
+            health_check_timeout_ms: config.health_check_timeout_ms,
+            health_check_failure_threshold: config.health_check_failure_threshold,
+            spill_dir: config.spill_dir,
+            spill_max_bytes: config.spill_max_bytes,
+            spill_replay_rate_pps: config.spill_replay_rate_pps,
+            spill_max_age_ms: config.spill_max_age_ms,
+            spill_live_queue_packets: config.spill_live_queue_packets,
+            recv_socket_buffer_bytes: config.recv_socket_buffer_bytes,
+            send_socket_buffer_bytes: config.send_socket_buffer_bytes,
+            dscp: config.dscp,
+            listen_dscp: config.listen_dscp,
+            egress_interface: config.egress_interface,
+            egress_source_ip: config.egress_source_ip,
+            socket_rebind_error_threshold: config.socket_rebind_error_threshold,
+            socket_max_rebinds_per_minute: config.socket_max_rebinds_per_minute,
+            deduper_reset_interval_ms: config.deduper_reset_interval_ms,
+            thread_max_restarts: config.thread_max_restarts,
+            thread_restart_window_secs: config.thread_restart_window_secs,
+            crash_report_file: config.crash_report_file,
+            shutdown_grace_period_ms: config.shutdown_grace_period_ms,
+            run_as_user: config.run_as_user,
+            run_as_group: config.run_as_group,
+            chroot: config.chroot,
+        })
+    }
+}
+
+impl CommonArgs {
+    /// Interface and source address forwarding sockets send from, unless overridden per destination
+    pub fn egress(&self) -> socket::Egress {
+        socket::Egress {
+            interface: self.egress_interface.clone(),
+            source_ip: self.egress_source_ip,
+        }
+    }
+
+    /// Packets that don't parse as a shred are dropped by either `drop-non-shred-packets` or `forward-unknown-packets`
+    pub fn drop_unknown_packets(&self) -> bool {
+        self.drop_non_shred_packets || !self.forward_unknown_packets
+    }
+
+    /// Receive and send threads per port when listening on `num_ports` ports, falling back to the deprecated `num-threads`
+    pub fn forwarder_threads(&self, num_ports: usize) -> ForwarderThreads {
+        // destinations added at runtime can't be counted up front
+        let num_static_dests = (self.endpoint_discovery_url.is_none()
+            && self.discovered_endpoints_port.is_none()
+            && self.rpc_discovery_url.is_none()
+            && self.admin_bind_addr.is_none()
+            && self.admin_grpc_bind_addr.is_none())
+        .then(|| {
+            self.dest_ip_ports.len()
+                + self.dest_shard_groups.len()
+                + self.dest_validator_identities.len()
+        });
+        ForwarderThreads::new(
+            self.num_recv_threads.or(self.num_threads),
+            self.num_send_threads.or(self.num_threads),
+            num_static_dests,
+            num_ports,
+        )
+    }
+}
+
+/// Same defaults as the CLI and config file, with no destinations
+impl Default for CommonArgs {
+    fn default() -> Self {
+        // every config field has a default, and there are no destinations or headers to fail parsing
+        serde_json::from_value::<CommonConfig>(serde_json::json!({}))
+            .expect("config fields have defaults")
+            .try_into()
+            .expect("default config is valid")
+    }
+}
+
+/// Loads a `shredstream` mode config, failing for `forward-only` configs. See [load_proxy_config]
+pub fn load_shredstream_config(
+    path: &Path,
+    format: Option<ConfigFormat>,
+) -> io::Result<ShredstreamArgs> {
+    read_shredstream_config(path, format)?.try_into()
+}
+
+/// Loads a config for whichever subcommand its `mode` selects
+pub fn load_proxy_config(path: &Path, format: Option<ConfigFormat>) -> io::Result<ProxyConfig> {
+    read_shredstream_config(path, format)?.try_into()
+}
+
+fn read_shredstream_config(
+    path: &Path,
+    format: Option<ConfigFormat>,
+) -> io::Result<ShredstreamConfig> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+    let format = format
+        .or_else(|| ConfigFormat::from_path(path))
+        .unwrap_or(ConfigFormat::Toml);
+    parse_shredstream_config(&contents, format)
+}
+
+/// Parse errors include the line and column reported by each format's parser
+fn parse_shredstream_config(contents: &str, format: ConfigFormat) -> io::Result<ShredstreamConfig> {
+    let result = match format {
+        ConfigFormat::Toml => toml::from_str(contents).map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::from_str(contents).map_err(|e| e.to_string()),
+    };
+    result.map_err(|e| {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Failed to parse {format:?} config file: {e}"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        path::Path,
+        str::FromStr,
+        time::Duration,
+    };
+
+    use solana_sdk::signature::{Keypair, Signer};
+
+    use crate::{
+        alerts::AlertWebhookFormat,
+        chaos_config,
+        events::EventsFormat,
+        events_config,
+        forwarder::{DedupMode, ForwardShredTypes, RxTimestampSource},
+        grpc_channel_config,
+        kafka::KafkaSecurityProtocol,
+        keepalive::UpstreamKeepalive,
+        listener,
+        metrics_backend::{MetricsBackendKind, StatsdDialect},
+        mirror::{MirrorSampleUnit, MirrorSpec},
+        parse_header, parse_ip_net, parse_keypair, parse_public_ip, parse_shredstream_config,
+        resolve_hostname_port, resolve_hostname_port_with_family,
+        shard::{ShardBy, ShardGroupSpec, ShardMembers},
+        trace_shred_sample_rate,
+        tunnel::tests::TEST_CERT,
+        validate_block_engine_args, validate_common_args, validate_core_affinity,
+        validate_region_ports, with_default_port, AddressFamily, CommonArgs, ConfigFormat,
+        ProxyConfig, ShredstreamArgs,
+    };
+
+    fn assert_parsed_with_defaults(contents: &str, format: ConfigFormat) {
+        let args: ShredstreamArgs = parse_shredstream_config(contents, format)
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        assert_eq!(
+            args.block_engine_url,
+            vec!["https://mainnet.block-engine.jito.wtf"]
+        );
+        assert_eq!(args.auth_url, None);
+        assert_eq!(args.desired_regions, vec!["amsterdam", "ny"]);
+        assert_eq!(
+            args.common_args.dest_ip_ports[0].0,
+            SocketAddr::from_str("127.0.0.1:8001").unwrap()
+        );
+        // defaulted fields
+        assert_eq!(
+            args.common_args.src_bind_addr,
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        );
+        assert_eq!(args.common_args.src_bind_port, 20_000);
+        assert_eq!(args.common_args.dest_address_family, AddressFamily::Any);
+        assert_eq!(args.common_args.endpoint_discovery_interval_ms, 30_000);
+        assert_eq!(args.common_args.endpoint_discovery_state_file, None);
+        assert_eq!(
+            args.common_args.endpoint_discovery_state_max_age_secs,
+            86_400
+        );
+        assert_eq!(args.common_args.shutdown_grace_period_ms, 5_000);
+        assert_eq!(args.common_args.run_as_user, None);
+        assert!(!args.common_args.detect_conflicting_shreds);
+        assert_eq!(args.common_args.conflicting_shreds_max_slots, 32);
+        assert_eq!(events_config(&args.common_args), None);
+        assert_eq!(args.common_args.events_format, EventsFormat::Json);
+        assert_eq!(args.common_args.events_abandon_timeout_ms, 1_000);
+        assert_eq!(args.common_args.events_max_per_sec, 100);
+        assert_eq!(args.common_args.run_as_group, None);
+        assert_eq!(args.common_args.chroot, None);
+        assert!(args.common_args.dest_shard_groups.is_empty());
+        assert!(args.common_args.mirror_dests.is_empty());
+        assert!(args.common_args.upstream_keepalives.is_empty());
+        assert!(args.common_args.listeners.is_empty());
+        assert!(args.common_args.crash_report_file.is_none());
+        assert!(args.common_args.spill_dir.is_none());
+        assert_eq!(args.common_args.spill_max_bytes, 1 << 30);
+        assert_eq!(args.common_args.spill_replay_rate_pps, 50_000);
+        assert_eq!(args.common_args.spill_max_age_ms, 60_000);
+        assert_eq!(args.common_args.spill_live_queue_packets, 100_000);
+        assert_eq!(args.common_args.decrypt_key_file, None);
+        assert_eq!(args.common_args.tunnel_buffer_packets, 65_536);
+        assert_eq!(args.common_args.tunnel_tls_server_name, None);
+        assert_eq!(args.common_args.tunnel_tls_client_cert, None);
+        assert_eq!(args.common_args.ws_bind_addr, None);
+        assert_eq!(args.common_args.ws_max_clients, 16);
+        assert_eq!(args.common_args.alert_webhook_url, None);
+        assert_eq!(
+            args.common_args.alert_webhook_format,
+            AlertWebhookFormat::Json
+        );
+        assert_eq!(args.common_args.alert_host_id, None);
+        assert_eq!(args.common_args.alert_no_shreds_secs, 60);
+        assert_eq!(args.common_args.alert_forward_failure_percent, 5.0);
+        assert_eq!(args.common_args.alert_heartbeat_down_secs, 60);
+        assert_eq!(args.common_args.alert_deduper_saturation_percent, 10.0);
+        assert_eq!(args.common_args.alert_cooldown_secs, 900);
+        assert_eq!(args.common_args.slot_latency_rpc_url, None);
+        assert_eq!(args.common_args.default_dest_port, None);
+        assert_eq!(args.common_args.otlp_endpoint, None);
+        assert!(args.common_args.dest_validator_identities.is_empty());
+        assert_eq!(args.common_args.dest_validator_refresh_secs, 60);
+        assert_eq!(args.common_args.dest_validator_unresolved_alert_secs, 600);
+        assert!(args.common_args.rpc_discovery_url.is_none());
+        assert_eq!(args.common_args.rpc_discovery_interval_secs, 300);
+        assert!(args.common_args.dest_blocklist.is_empty());
+        assert!(!args.common_args.allow_self_forward);
+        assert_eq!(args.common_args.max_destinations, 200);
+        assert_eq!(
+            args.common_args.endpoint_discovery_max_response_bytes,
+            1_048_576
+        );
+        assert_eq!(args.common_args.endpoint_discovery_max_removal_percent, 50);
+        assert_eq!(args.common_args.kafka_brokers, None);
+        assert_eq!(
+            args.common_args.kafka_security_protocol,
+            KafkaSecurityProtocol::Plaintext
+        );
+        assert_eq!(args.common_args.kafka_queue_capacity, 65_536);
+        assert_eq!(args.common_args.dscp, None);
+        assert_eq!(args.common_args.listen_dscp, None);
+        assert_eq!(args.common_args.egress_interface, None);
+        assert_eq!(args.common_args.egress_source_ip, None);
+        assert_eq!(args.common_args.socket_rebind_error_threshold, 100);
+        assert_eq!(args.common_args.socket_max_rebinds_per_minute, 10);
+        assert_eq!(args.common_args.archive_path, None);
+        assert_eq!(args.common_args.archive_retention_slots, 10_000);
+        assert!(!args.common_args.archive_read_check);
+        assert_eq!(args.common_args.public_ip_family, AddressFamily::V4);
+        assert_eq!(args.common_args.public_ip_recheck_interval_secs, 300);
+        assert_eq!(args.common_args.advertise_addr, None);
+        assert_eq!(args.common_args.metrics_report_interval_ms, 15_000);
+        assert_eq!(args.common_args.metrics_backend, MetricsBackendKind::Influx);
+        assert_eq!(args.common_args.statsd_addr, None);
+        assert_eq!(args.common_args.statsd_dialect, StatsdDialect::Statsd);
+        assert!(!args.common_args.debug_trace_shred);
+        assert_eq!(trace_shred_sample_rate(&args.common_args), 0.0);
+        assert_eq!(chaos_config(&args.common_args), None);
+        assert_eq!(args.common_args.forward_shred_types, ForwardShredTypes::All);
+        assert_eq!(args.common_args.dedup_mode, DedupMode::Payload);
+        assert!(args.common_args.drop_unknown_packets());
+        assert!(!args.common_args.measure_internal_latency);
+        assert_eq!(args.common_args.recv_mmsg_batch_size, None);
+        assert_eq!(args.common_args.max_buffered_bytes, None);
+        assert_eq!(args.common_args.recv_poll_timeout_ms, 1_000);
+        assert_eq!(
+            args.common_args.rx_timestamp_source,
+            RxTimestampSource::Software
+        );
+        assert!(!args.common_args.measure_send_latency);
+        assert_eq!(args.common_args.slow_send_threshold_us, 1_000);
+        assert!(!args.common_args.isolated_send_threads);
+        assert_eq!(args.common_args.isolated_send_queue_capacity, 1_024);
+        assert_eq!(args.block_engine_failover_threshold, 3);
+        assert_eq!(args.block_engine_primary_retry_secs, 600);
+        assert_eq!(args.stall_timeout_secs, 120);
+        assert_eq!(args.ready_heartbeat_max_age_secs, 30);
+        assert_eq!(args.token_refresh_margin_secs, 300);
+        assert_eq!(args.token_cache_file, None);
+        assert_eq!(args.initial_auth_jitter_ms, 2_000);
+        assert_eq!(args.grpc_connect_timeout_ms, None);
+        assert_eq!(args.grpc_keepalive_interval_ms, None);
+        assert_eq!(args.grpc_keepalive_timeout_ms, None);
+        assert_eq!(args.grpc_tls_ca_cert, None);
+    }
+
+    #[test]
+    fn test_parse_toml_config() {
+        let contents = r#"
+block_engine_url = "https://mainnet.block-engine.jito.wtf"
+auth_keypair = "keypair.json"
+desired_regions = ["amsterdam", "ny"]
+
+[common]
+dest_ip_ports = ["127.0.0.1:8001"]
+"#;
+        assert_parsed_with_defaults(contents, ConfigFormat::Toml);
+    }
+
+    #[test]
+    fn test_parse_config_mode() {
+        let forward_only = r#"
+mode = "forward-only"
+
+[common]
+dest_ip_ports = ["127.0.0.1:8001"]
+upstream_keepalive = ["10.0.0.1:20000,15000"]
+dedup_mode = "shred-id"
+
+[[common.listener]]
+port = 20001
+dests = ["127.0.0.1:8002", "127.0.0.1:8003"]
+name = "fleet-b"
+"#;
+        let config: ProxyConfig = parse_shredstream_config(forward_only, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let ProxyConfig::ForwardOnly(args) = config else {
+            panic!("expected forward-only config, got {config:?}");
+        };
+        assert_eq!(args.dedup_mode, DedupMode::ShredId);
+        assert_eq!(
+            args.upstream_keepalives,
+            vec![UpstreamKeepalive {
+                addr: SocketAddr::from_str("10.0.0.1:20000").unwrap(),
+                interval: Duration::from_secs(15),
+            }]
+        );
+        assert_eq!(
+            args.dest_ip_ports,
+            vec![(
+                SocketAddr::from_str("127.0.0.1:8001").unwrap(),
+                "127.0.0.1:8001".to_string()
+            )]
+        );
+        assert_eq!(
+            args.listeners,
+            vec![listener::parse_listener(
+                "port=20001,dests=127.0.0.1:8002;127.0.0.1:8003,name=fleet-b"
+            )
+            .unwrap()]
+        );
+        // only shredstream configs convert to shredstream args
+        let err = ShredstreamArgs::try_from(
+            parse_shredstream_config(forward_only, ConfigFormat::Toml).unwrap(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("forward-only"), "{err}");
+
+        // block engine fields are rejected rather than ignored
+        let with_shredstream_fields = forward_only.replace(
+            "[common]",
+            "desired_regions = [\"ny\"]\nregion_ports = false\n[common]",
+        );
+        let err = ProxyConfig::try_from(
+            parse_shredstream_config(&with_shredstream_fields, ConfigFormat::Toml).unwrap(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains(r#"["desired_regions", "region_ports"]"#),
+            "{err}"
+        );
+
+        // mode defaults to shredstream, which requires the block engine fields
+        let err = ProxyConfig::try_from(
+            parse_shredstream_config(
+                &forward_only.replace("mode = \"forward-only\"", "auth_keypair = \"keypair.json\""),
+                ConfigFormat::Toml,
+            )
+            .unwrap(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("block_engine_url"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_destination_tables() {
+        let contents = r#"
+block_engine_url = "a"
+auth_keypair = "keypair.json"
+desired_regions = ["ny"]
+
+[common]
+dest_ip_ports = ["127.0.0.1:8001?rate=100pps"]
+dest_shard_groups = ["shard(127.0.0.1:9000,127.0.0.1:9001)?shard-by=slot"]
+
+[[common.destination]]
+addr = "127.0.0.1:8002"
+rate_pps = 5000
+egress_interface = "lo"
+egress_source_ip = "127.0.0.1"
+spill = true
+
+[[common.destination]]
+addr = "quic://127.0.0.1:20001"
+priority = "high"
+"#;
+        let args: ShredstreamArgs = parse_shredstream_config(contents, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            args.common_args.dest_ip_ports,
+            vec![
+                (
+                    SocketAddr::from_str("127.0.0.1:8001").unwrap(),
+                    "127.0.0.1:8001?rate=100pps".to_string()
+                ),
+                (
+                    SocketAddr::from_str("127.0.0.1:8002").unwrap(),
+                    "127.0.0.1:8002?rate=5000pps&iface=lo&src=127.0.0.1&spill=true".to_string()
+                ),
+                (
+                    SocketAddr::from_str("127.0.0.1:20001").unwrap(),
+                    "quic://127.0.0.1:20001?priority=high".to_string()
+                ),
+            ]
+        );
+
+        assert_eq!(
+            args.common_args.dest_shard_groups,
+            vec![ShardGroupSpec {
+                members: ShardMembers::Static(
+                    ["127.0.0.1:9000", "127.0.0.1:9001"]
+                        .map(|addr| (SocketAddr::from_str(addr).unwrap(), addr.to_string()))
+                        .to_vec()
+                ),
+                shard_by: ShardBy::Slot,
+            }]
+        );
+
+        let invalid = contents.replace("100pps", "100");
+        assert!(ShredstreamArgs::try_from(
+            parse_shredstream_config(&invalid, ConfigFormat::Toml).unwrap()
+        )
+        .is_err());
+
+        // egress only applies to UDP destinations
+        let err = resolve_hostname_port("quic://127.0.0.1:20001?iface=lo").unwrap_err();
+        assert!(err.to_string().contains("only apply to UDP"), "{err}");
+
+        // sealed destinations need a key file, and only they take one
+        assert!(
+            resolve_hostname_port("enc://127.0.0.1:20001?key-file=peer.key&src=127.0.0.1").is_ok()
+        );
+        assert!(resolve_hostname_port("enc://127.0.0.1:20001").is_err());
+        assert!(resolve_hostname_port("127.0.0.1:20001?key-file=peer.key").is_err());
+    }
+
+    #[test]
+    fn test_parse_mirror_tables() {
+        let contents = r#"
+block_engine_url = "a"
+auth_keypair = "keypair.json"
+desired_regions = ["ny"]
+
+[common]
+dest_ip_ports = ["127.0.0.1:8001"]
+mirror_dests = ["127.0.0.1:9000@0.1"]
+
+[[common.mirror]]
+addr = "127.0.0.1:9001"
+fraction = 0.5
+sample_unit = "fecset"
+"#;
+        let args: ShredstreamArgs = parse_shredstream_config(contents, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            args.common_args.mirror_dests,
+            vec![
+                MirrorSpec {
+                    dest: SocketAddr::from_str("127.0.0.1:9000").unwrap(),
+                    hostname_port: "127.0.0.1:9000".to_string(),
+                    fraction: 0.1,
+                    sample_unit: MirrorSampleUnit::Shred,
+                },
+                MirrorSpec {
+                    dest: SocketAddr::from_str("127.0.0.1:9001").unwrap(),
+                    hostname_port: "127.0.0.1:9001".to_string(),
+                    fraction: 0.5,
+                    sample_unit: MirrorSampleUnit::FecSet,
+                },
+            ]
+        );
+
+        let invalid = contents.replace("0.5", "2");
+        assert!(ShredstreamArgs::try_from(
+            parse_shredstream_config(&invalid, ConfigFormat::Toml).unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_yaml_config() {
+        let contents = r#"
+block_engine_url: https://mainnet.block-engine.jito.wtf
+auth_keypair: keypair.json
+desired_regions:
+  - amsterdam
+  - ny
+common:
+  dest_ip_ports:
+    - 127.0.0.1:8001
+"#;
+        assert_parsed_with_defaults(contents, ConfigFormat::Yaml);
+    }
+
+    #[test]
+    fn test_parse_json_config() {
+        let contents = r#"{
+  "block_engine_url": "https://mainnet.block-engine.jito.wtf",
+  "auth_keypair": "keypair.json",
+  "desired_regions": ["amsterdam", "ny"],
+  "common": { "dest_ip_ports": ["127.0.0.1:8001"] }
+}"#;
+        assert_parsed_with_defaults(contents, ConfigFormat::Json);
+    }
+
+    #[test]
+    fn test_parse_block_engine_url_list() {
+        let expected = vec![
+            "https://ny.mainnet.block-engine.jito.wtf",
+            "https://amsterdam.mainnet.block-engine.jito.wtf",
+        ];
+        for block_engine_url in [
+            r#""https://ny.mainnet.block-engine.jito.wtf,https://amsterdam.mainnet.block-engine.jito.wtf""#,
+            r#"["https://ny.mainnet.block-engine.jito.wtf", "https://amsterdam.mainnet.block-engine.jito.wtf"]"#,
+        ] {
+            let contents = format!(
+                "block_engine_url = {block_engine_url}\nauth_keypair = \"keypair.json\"\ndesired_regions = [\"ny\"]\n[common]\n"
+            );
+            let args: ShredstreamArgs = parse_shredstream_config(&contents, ConfigFormat::Toml)
+                .unwrap()
+                .try_into()
+                .unwrap();
+            assert_eq!(args.block_engine_url, expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_public_ip() {
+        assert_eq!(
+            parse_public_ip("203.0.113.7\n", AddressFamily::Any),
+            Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)))
+        );
+        assert_eq!(
+            parse_public_ip(" 2001:db8::1 ", AddressFamily::V6),
+            Some(IpAddr::from_str("2001:db8::1").unwrap())
+        );
+        assert_eq!(parse_public_ip("203.0.113.7", AddressFamily::V6), None);
+        assert_eq!(
+            parse_public_ip("<html>502 Bad Gateway</html>", AddressFamily::Any),
+            None
+        );
+        assert_eq!(parse_public_ip("", AddressFamily::Any), None);
+    }
+
+    #[test]
+    fn test_parse_config_error_has_location() {
+        let contents = "block_engine_url: a\n  auth_keypair: [\n";
+        let err = parse_shredstream_config(contents, ConfigFormat::Yaml).unwrap_err();
+        assert!(err.to_string().contains("line"), "{err}");
+
+        let err = parse_shredstream_config("{\n  \"block_engine_url\": 1\n}", ConfigFormat::Json)
+            .unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{err}");
+    }
+
+    #[test]
+    fn test_config_format_from_path() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.toml")),
+            Some(ConfigFormat::Toml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.YML")),
+            Some(ConfigFormat::Yaml)
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.json")),
+            Some(ConfigFormat::Json)
+        );
+        assert_eq!(ConfigFormat::from_path(Path::new("config")), None);
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("Authorization: Bearer abc:def").unwrap(),
+            ("Authorization".to_string(), "Bearer abc:def".to_string())
+        );
+        assert_eq!(
+            parse_header("X-Env:prod").unwrap(),
+            ("X-Env".to_string(), "prod".to_string())
+        );
+        assert!(parse_header("X-Env").is_err());
+        assert!(parse_header("Bad Name: prod").is_err());
+        // invalid values aren't echoed back
+        let err = parse_header("X-Token: secret\x01").unwrap_err();
+        assert!(!err.contains("secret"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_ip_net() {
+        assert_eq!(
+            parse_ip_net("10.0.0.0/8").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            parse_ip_net(" 10.0.0.1 ").unwrap().to_string(),
+            "10.0.0.1/32"
+        );
+        assert_eq!(
+            parse_ip_net("2001:db8::1").unwrap().to_string(),
+            "2001:db8::1/128"
+        );
+        assert!(parse_ip_net("10.0.0.0/33").is_err());
+        assert!(parse_ip_net("upstream.example.com").is_err());
+    }
+
+    #[test]
+    fn test_address_family_select() {
+        let v4 = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let v6 = SocketAddr::from_str("[2001:db8::1]:8001").unwrap();
+        assert_eq!(AddressFamily::Any.select([v4, v6]), Some(v4));
+        assert_eq!(AddressFamily::V4.select([v6, v4]), Some(v4));
+        assert_eq!(AddressFamily::V6.select([v4, v6]), Some(v6));
+        // falls back to the other family
+        assert_eq!(AddressFamily::V6.select([v4]), Some(v4));
+        assert_eq!(AddressFamily::V4.select([]), None);
+    }
+
+    #[test]
+    fn test_resolve_mixed_families() {
+        for (hostname_port, expected) in [
+            ("127.0.0.1:8001", "127.0.0.1:8001"),
+            ("[::1]:8001", "[::1]:8001"),
+            ("quic://[::1]:20001", "[::1]:20001"),
+        ] {
+            let (addr, original) =
+                resolve_hostname_port_with_family(hostname_port, AddressFamily::V6).unwrap();
+            assert_eq!(addr, SocketAddr::from_str(expected).unwrap());
+            assert_eq!(original, hostname_port);
+        }
+
+        let contents = r#"
+block_engine_url = "a"
+auth_keypair = "keypair.json"
+desired_regions = ["ny"]
+
+[common]
+dest_ip_ports = ["127.0.0.1:8001", "[::1]:8001"]
+dest_address_family = "v6"
+public_ip_family = "any"
+"#;
+        let args: ShredstreamArgs = parse_shredstream_config(contents, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert_eq!(
+            args.common_args
+                .dest_ip_ports
+                .iter()
+                .map(|(addr, _)| *addr)
+                .collect::<Vec<_>>(),
+            vec![
+                SocketAddr::from_str("127.0.0.1:8001").unwrap(),
+                SocketAddr::from_str("[::1]:8001").unwrap()
+            ]
+        );
+        assert_eq!(args.common_args.dest_address_family, AddressFamily::V6);
+        assert_eq!(args.common_args.public_ip_family, AddressFamily::Any);
+    }
+
+    #[test]
+    fn test_with_default_port() {
+        for (hostname_port, expected) in [
+            ("10.0.0.1", "10.0.0.1:8001"),
+            ("10.0.0.1:9000", "10.0.0.1:9000"),
+            ("10.0.0.1?rate=100pps", "10.0.0.1:8001?rate=100pps"),
+            ("relay.example.com", "relay.example.com:8001"),
+            ("tls://relay.example.com", "tls://relay.example.com:8001"),
+            ("::1", "[::1]:8001"),
+            ("[::1]", "[::1]:8001"),
+            ("[::1]:9000", "[::1]:9000"),
+            ("quic://[::1]:20001", "quic://[::1]:20001"),
+            ("unix:///run/consumer.sock", "unix:///run/consumer.sock"),
+        ] {
+            assert_eq!(with_default_port(hostname_port, 8001), expected);
+        }
+
+        let (addr, original) = resolve_hostname_port("127.0.0.1?rate=100pps").unwrap();
+        assert_eq!(addr, SocketAddr::from_str("127.0.0.1:8001").unwrap());
+        // the port is added once the default is known
+        assert_eq!(original, "127.0.0.1?rate=100pps");
+        let err = resolve_hostname_port("127.0.0.1:0").unwrap_err();
+        assert!(err.to_string().contains("has port 0"), "{err}");
+    }
+
+    #[test]
+    fn test_parse_config_default_dest_port() {
+        let parse = |common: &str| {
+            let contents = format!(
+                "block_engine_url = \"a\"\nauth_keypair = \"keypair.json\"\ndesired_regions = [\"ny\"]\n[common]\n{common}"
+            );
+            ShredstreamArgs::try_from(
+                parse_shredstream_config(&contents, ConfigFormat::Toml).unwrap(),
+            )
+            .map(|args| args.common_args.dest_ip_ports)
+        };
+        let dest = |addr: &str, hostname_port: &str| {
+            (
+                SocketAddr::from_str(addr).unwrap(),
+                hostname_port.to_string(),
+            )
+        };
+
+        assert_eq!(
+            parse("dest_ip_ports = [\"127.0.0.1\", \"127.0.0.1:8002\"]").unwrap(),
+            vec![
+                dest("127.0.0.1:8001", "127.0.0.1:8001"),
+                dest("127.0.0.1:8002", "127.0.0.1:8002"),
+            ]
+        );
+        assert_eq!(
+            parse(
+                "dest_ip_ports = [\"127.0.0.1\", \"localhost:9000\"]\n\
+                 default_dest_port = 9000\n\
+                 endpoint_discovery_url = \"http://127.0.0.1:9999\"\n\
+                 discovered_endpoints_port = 9001"
+            )
+            .unwrap()[0],
+            dest("127.0.0.1:9000", "127.0.0.1:9000")
+        );
+        // falls back to the discovered endpoints' port
+        assert_eq!(
+            parse(
+                "dest_ip_ports = [\"127.0.0.1\"]\n\
+                 endpoint_discovery_url = \"http://127.0.0.1:9999\"\n\
+                 discovered_endpoints_port = 9001"
+            )
+            .unwrap(),
+            vec![dest("127.0.0.1:9001", "127.0.0.1:9001")]
+        );
+        // duplicates, including from destination tables, are dropped
+        assert_eq!(
+            parse(
+                "dest_ip_ports = [\"127.0.0.1:8001\", \"127.0.0.1\"]\n\
+                 [[common.destination]]\n\
+                 addr = \"127.0.0.1:8001\""
+            )
+            .unwrap(),
+            vec![dest("127.0.0.1:8001", "127.0.0.1:8001")]
+        );
+        assert!(parse("dest_ip_ports = [\"127.0.0.1:0\"]").is_err());
+    }
+
+    #[test]
+    fn test_validate_core_affinity() {
+        assert!(validate_core_affinity(&[], 4).is_ok());
+        assert!(validate_core_affinity(&[2, 3, 4, 5], 4).is_ok());
+        assert!(validate_core_affinity(&[2, 3, 4, 5, 6], 4).is_ok());
+        assert!(validate_core_affinity(&[2, 3], 4).is_err());
+    }
+
+    #[test]
+    fn test_validate_region_ports() {
+        let regions = vec!["ny".to_string(), "amsterdam".to_string()];
+        assert!(validate_region_ports(20_000, &regions).is_ok());
+        assert!(validate_region_ports(u16::MAX - 1, &regions).is_ok());
+        assert!(validate_region_ports(u16::MAX, &regions).is_err());
+        assert!(validate_region_ports(0, &regions).is_err());
+    }
+
+    #[test]
+    fn test_validate_archive_args() {
+        let args = CommonArgs {
+            archive_read_check: true,
+            ..CommonArgs::default()
+        };
+        assert!(validate_common_args(&args).is_err());
+
+        let args = CommonArgs {
+            archive_path: Some("/tmp/shredstream-archive".into()),
+            ..args
+        };
+        assert_eq!(
+            validate_common_args(&args).is_ok(),
+            cfg!(feature = "archive")
+        );
+    }
+
+    #[test]
+    fn test_validate_recv_args() {
+        for (recv_mmsg_batch_size, valid) in [
+            (None, true),
+            (Some(0), false),
+            (Some(256), true),
+            (Some(1_024), true),
+            (Some(1_025), false),
+        ] {
+            let args = CommonArgs {
+                recv_mmsg_batch_size,
+                ..CommonArgs::default()
+            };
+            assert_eq!(
+                validate_common_args(&args).is_ok(),
+                valid,
+                "{recv_mmsg_batch_size:?}"
+            );
+        }
+        let args = CommonArgs {
+            recv_poll_timeout_ms: 0,
+            ..CommonArgs::default()
+        };
+        assert!(validate_common_args(&args).is_err());
+    }
+
+    #[test]
+    fn test_grpc_channel_args() {
+        let ca_cert =
+            std::env::temp_dir().join(format!("test_grpc_channel_args_{}.pem", std::process::id()));
+        std::fs::write(&ca_cert, TEST_CERT).unwrap();
+        let contents = format!(
+            r#"
+block_engine_url = "https://mainnet.block-engine.jito.wtf"
+auth_keypair = "keypair.json"
+desired_regions = ["ny"]
+grpc_connect_timeout_ms = 5000
+grpc_keepalive_interval_ms = 10000
+grpc_keepalive_timeout_ms = 3000
+grpc_tls_ca_cert = {ca_cert:?}
+
+[common]
+"#
+        );
+        let args: ShredstreamArgs = parse_shredstream_config(&contents, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        assert!(validate_block_engine_args(&args).is_ok());
+        let config = grpc_channel_config(&args).unwrap();
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.keepalive_interval, Some(Duration::from_secs(10)));
+        assert_eq!(config.keepalive_timeout, Some(Duration::from_secs(3)));
+        assert!(config.tls_ca_cert.is_some());
+
+        // a keepalive timeout does nothing without pings to time out
+        let invalid = ShredstreamArgs {
+            grpc_keepalive_interval_ms: None,
+            ..args.clone()
+        };
+        assert!(validate_block_engine_args(&invalid).is_err());
+        let invalid = ShredstreamArgs {
+            grpc_connect_timeout_ms: Some(0),
+            ..args.clone()
+        };
+        assert!(validate_block_engine_args(&invalid).is_err());
+
+        std::fs::write(&ca_cert, "not a certificate").unwrap();
+        assert!(grpc_channel_config(&args).is_err());
+        std::fs::remove_file(&ca_cert).unwrap();
+        assert!(grpc_channel_config(&args).is_err());
+    }
+
+    #[test]
+    fn test_parse_keypair() {
+        let keypair = Keypair::new();
+        let base58 = keypair.to_base58_string();
+        let json = serde_json::to_string(&keypair.to_bytes().to_vec()).unwrap();
+        for encoded in [base58.clone(), format!("{json}\n")] {
+            assert_eq!(parse_keypair(&encoded).unwrap().pubkey(), keypair.pubkey());
+        }
+
+        // errors don't quote the input
+        for invalid in [&base58[..40], "[1, 2, 3]", "not base58 0OIl"] {
+            let err = parse_keypair(invalid).unwrap_err();
+            assert!(!err.contains(invalid), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_auth_keypair_sources() {
+        let config = |auth: &str| {
+            parse_shredstream_config(
+                &format!(
+                    "block_engine_url = \"a\"\n{auth}\ndesired_regions = [\"ny\"]\n[common]\n"
+                ),
+                ConfigFormat::Toml,
+            )
+            .unwrap()
+        };
+        let args: ShredstreamArgs = config("auth_keypair_env = \"AUTH_KEYPAIR_SECRET\"")
+            .try_into()
+            .unwrap();
+        assert_eq!(args.auth_keypair, None);
+        assert_eq!(
+            args.auth_keypair_base58.as_deref(),
+            Some("AUTH_KEYPAIR_SECRET")
+        );
+        assert!(ShredstreamArgs::try_from(config("")).is_err());
+        assert!(ShredstreamArgs::try_from(config(
+            "auth_keypair = \"keypair.json\"\nauth_keypair_env = \"AUTH_KEYPAIR_SECRET\""
+        ))
+        .is_err());
+    }
+}
@@ -0,0 +1,217 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    net::SocketAddr,
+};
+
+use crate::{resolve_hostname_port_with_family, AddressFamily};
+
+/// A `--listener` pipeline: shreds received on `port` are deduped on their own and forwarded only to `dests`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ListenerSpec {
+    /// 0 binds an ephemeral port, see [crate::ShredstreamProxy::listener_ports]
+    pub port: u16,
+    pub dests: Vec<(SocketAddr, String)>,
+    /// Tags the listener's metrics, defaults to the port
+    pub name: Option<String>,
+}
+
+impl ListenerSpec {
+    pub fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.port.to_string())
+    }
+}
+
+/// `[[common.listener]]` config table
+#[derive(Clone, Debug, serde::Deserialize)]
+pub struct ListenerConfig {
+    port: u16,
+    dests: Vec<String>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+impl ListenerConfig {
+    pub fn resolve(&self, family: AddressFamily) -> io::Result<ListenerSpec> {
+        let dests = self.dests.iter().map(String::as_str).collect::<Vec<_>>();
+        new_listener_spec(self.port, &dests, self.name.clone(), family)
+    }
+}
+
+/// Parses a `--listener` entry, eg. `port=20001,dests=10.0.0.1:8001;10.0.0.2:8001,name=fleet-b`
+pub fn parse_listener(spec: &str) -> io::Result<ListenerSpec> {
+    parse_listener_with_family(spec, AddressFamily::Any)
+}
+
+/// Parses a `--listener` entry, resolving destinations to the first address of `family`
+pub fn parse_listener_with_family(spec: &str, family: AddressFamily) -> io::Result<ListenerSpec> {
+    let invalid = |reason: String| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid listener {spec:?}, {reason}."),
+        )
+    };
+    let (mut port, mut dests, mut name) = (None, None, None);
+    for field in spec.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+        match field.split_once('=') {
+            Some(("port", value)) => {
+                port = Some(value.trim().parse::<u16>().map_err(|_| {
+                    invalid(format!("port {value:?} is not a valid port"))
+                })?)
+            }
+            Some(("dests", value)) => dests = Some(value.split(';').collect::<Vec<_>>()),
+            Some(("name", value)) => name = Some(value.trim().to_string()),
+            _ => {
+                return Err(invalid(format!(
+                    "unknown field {field:?}, expected `port=<port>,dests=<host:port>;...[,name=<name>]`"
+                )))
+            }
+        }
+    }
+    let port = port.ok_or_else(|| invalid("missing `port=<port>`".to_string()))?;
+    let dests = dests.ok_or_else(|| invalid("missing `dests=<host:port>;...`".to_string()))?;
+    new_listener_spec(port, &dests, name, family)
+        .map_err(|e| invalid(e.to_string().trim_end_matches('.').to_string()))
+}
+
+fn new_listener_spec(
+    port: u16,
+    dests: &[&str],
+    name: Option<String>,
+    family: AddressFamily,
+) -> io::Result<ListenerSpec> {
+    let invalid = |reason: String| Error::new(ErrorKind::InvalidInput, reason);
+    // the port is the default name, which wouldn't tell ephemeral ports apart
+    if port == 0 && name.is_none() {
+        return Err(invalid("port 0 needs a name".to_string()));
+    }
+    if let Some(name) = &name {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(invalid(format!(
+                "name {name:?} must be letters, digits, `-` or `_`"
+            )));
+        }
+    }
+    let mut resolved: Vec<(SocketAddr, String)> = Vec::with_capacity(dests.len());
+    for dest in dests
+        .iter()
+        .map(|dest| dest.trim())
+        .filter(|d| !d.is_empty())
+    {
+        // listeners only send plain UDP, the other transports and options are set up for the main destinations
+        if dest.contains("://") || dest.contains('?') {
+            return Err(invalid(format!(
+                "destination {dest:?} must be a plain UDP host:port"
+            )));
+        }
+        let (socketaddr, hostname_port) = resolve_hostname_port_with_family(dest, family)?;
+        if !resolved.iter().any(|(addr, _)| *addr == socketaddr) {
+            resolved.push((socketaddr, hostname_port));
+        }
+    }
+    if resolved.is_empty() {
+        return Err(invalid("no destinations".to_string()));
+    }
+    Ok(ListenerSpec {
+        port,
+        dests: resolved,
+        name,
+    })
+}
+
+/// Returns an error if listeners share a port or name, or use a port of the main pipeline.
+/// Port 0 binds a different ephemeral port each time, so never conflicts
+pub fn validate_listeners(listeners: &[ListenerSpec], main_ports: &[u16]) -> Result<(), String> {
+    for (i, listener) in listeners.iter().enumerate() {
+        if listener.port != 0 && main_ports.contains(&listener.port) {
+            return Err(format!("Invalid arguments provided, --listener port {} is already listened on by --src-bind-port or --region-ports.", listener.port));
+        }
+        let earlier = &listeners[..i];
+        if listener.port != 0 && earlier.iter().any(|other| other.port == listener.port) {
+            return Err(format!(
+                "Invalid arguments provided, --listener port {} is used by more than one listener.",
+                listener.port
+            ));
+        }
+        if earlier.iter().any(|other| other.name() == listener.name()) {
+            return Err(format!(
+                "Invalid arguments provided, --listener name {:?} is used by more than one listener.",
+                listener.name()
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use crate::listener::{parse_listener, validate_listeners, ListenerSpec};
+
+    #[test]
+    fn test_parse_listener() {
+        let listener =
+            parse_listener("port=20001, dests=127.0.0.1:8001;127.0.0.1:8002;127.0.0.1:8001")
+                .unwrap();
+        assert_eq!(
+            listener,
+            ListenerSpec {
+                port: 20001,
+                dests: vec![
+                    (
+                        SocketAddr::from_str("127.0.0.1:8001").unwrap(),
+                        "127.0.0.1:8001".to_string()
+                    ),
+                    (
+                        SocketAddr::from_str("127.0.0.1:8002").unwrap(),
+                        "127.0.0.1:8002".to_string()
+                    ),
+                ],
+                name: None,
+            }
+        );
+        assert_eq!(listener.name(), "20001");
+        assert_eq!(
+            parse_listener("port=20001,dests=127.0.0.1:8001,name=fleet-b")
+                .unwrap()
+                .name(),
+            "fleet-b"
+        );
+
+        for invalid in [
+            "",
+            "port=20001",
+            "dests=127.0.0.1:8001",
+            "port=0,dests=127.0.0.1:8001",
+            "port=70000,dests=127.0.0.1:8001",
+            "port=20001,dests=",
+            "port=20001,dests=quic://127.0.0.1:8001",
+            "port=20001,dests=127.0.0.1:8001?rate=10pps",
+            "port=20001,dests=127.0.0.1:8001,name=fleet b",
+            "port=20001,dests=127.0.0.1:8001,region=ny",
+        ] {
+            assert!(parse_listener(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_validate_listeners() {
+        let listener = |spec: &str| parse_listener(spec).unwrap();
+        let fleet_a = listener("port=20001,dests=127.0.0.1:8001");
+        let fleet_b = listener("port=20002,dests=127.0.0.1:8002");
+        assert!(validate_listeners(&[fleet_a.clone(), fleet_b.clone()], &[20000]).is_ok());
+        assert!(validate_listeners(&[fleet_a.clone()], &[20000, 20001]).is_err());
+        assert!(validate_listeners(&[fleet_a.clone(), fleet_a.clone()], &[20000]).is_err());
+        // a name can collide with another listener's default name
+        let named = listener("port=20003,dests=127.0.0.1:8003,name=20001");
+        assert!(validate_listeners(&[fleet_a, named], &[20000]).is_err());
+        // ephemeral ports don't conflict with each other or the main pipeline's
+        let ephemeral_a = listener("port=0,dests=127.0.0.1:8001,name=a");
+        let ephemeral_b = listener("port=0,dests=127.0.0.1:8002,name=b");
+        assert!(validate_listeners(&[ephemeral_a, ephemeral_b], &[0]).is_ok());
+    }
+}
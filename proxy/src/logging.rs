@@ -0,0 +1,178 @@
+use std::{io::Write, sync::OnceLock};
+
+use arc_swap::ArcSwap;
+use env_logger::{Env, Logger, DEFAULT_FILTER_ENV, DEFAULT_WRITE_STYLE_ENV};
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    Log, Metadata, Record,
+};
+use serde_json::{Map, Value as JsonValue};
+
+/// Installed by [init_logger], so its filters can be replaced at runtime
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines from `env_logger`
+    #[default]
+    Text,
+    /// One JSON object per line with `ts`, `level`, `target`, `msg`, and any structured fields of the event
+    Json,
+}
+
+/// Logger built from filter directives in `RUST_LOG` syntax
+struct FilteredLogger {
+    filters: String,
+    logger: Logger,
+}
+
+impl FilteredLogger {
+    fn new(log_format: LogFormat, filters: String) -> Self {
+        // filters only come from `filters`, so a reload replaces `RUST_LOG` rather than adding to it
+        let mut builder =
+            env_logger::Builder::from_env(Env::new().write_style(DEFAULT_WRITE_STYLE_ENV));
+        if log_format == LogFormat::Json {
+            builder.format(|buf, record| {
+                let line = json_line(buf.timestamp_micros().to_string(), record);
+                writeln!(buf, "{line}")
+            });
+        }
+        let logger = builder.parse_filters(&filters).build();
+        Self { filters, logger }
+    }
+}
+
+/// Forwards to an `env_logger` that's swapped out when the filters change
+struct ReloadableLogger {
+    log_format: LogFormat,
+    current: ArcSwap<FilteredLogger>,
+}
+
+impl Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.current.load().logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.current.load().logger.log(record)
+    }
+
+    fn flush(&self) {
+        self.current.load().logger.flush()
+    }
+}
+
+/// Initializes the global logger, filtered by `RUST_LOG` as before
+pub fn init_logger(log_format: LogFormat) {
+    let filters = std::env::var(DEFAULT_FILTER_ENV).unwrap_or_default();
+    let logger = LOGGER.get_or_init(|| ReloadableLogger {
+        log_format,
+        current: ArcSwap::from_pointee(FilteredLogger::new(log_format, filters)),
+    });
+    log::set_logger(logger).expect("logger to be initialized once");
+    log::set_max_level(logger.current.load().logger.filter());
+}
+
+/// Replaces the filters of the logger installed by [init_logger], taking effect immediately.
+/// `filters` uses `RUST_LOG` syntax, eg. `info,jito_shredstream_proxy::forwarder=debug`
+pub fn set_log_filters(filters: String) -> Result<(), String> {
+    let logger = LOGGER
+        .get()
+        .ok_or_else(|| "Logger wasn't initialized by the proxy".to_string())?;
+    let filtered_logger = FilteredLogger::new(logger.log_format, filters);
+    log::set_max_level(filtered_logger.logger.filter());
+    logger.current.store(filtered_logger.into());
+    Ok(())
+}
+
+/// Returns the filters of the logger installed by [init_logger], if any
+pub fn log_filters() -> Option<String> {
+    LOGGER
+        .get()
+        .map(|logger| logger.current.load().filters.clone())
+}
+
+/// Structured fields are added alongside the standard ones, which they can't overwrite
+fn json_line(ts: String, record: &Record) -> JsonValue {
+    let mut line = Map::new();
+    line.insert("ts".to_string(), ts.into());
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("target".to_string(), record.target().into());
+    line.insert("msg".to_string(), record.args().to_string().into());
+    // visiting only fails if the visitor does, which this one never does
+    let _ = record.key_values().visit(&mut JsonFields(&mut line));
+    JsonValue::Object(line)
+}
+
+struct JsonFields<'a>(&'a mut Map<String, JsonValue>);
+
+impl<'kvs> VisitSource<'kvs> for JsonFields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0
+            .entry(key.as_str())
+            .or_insert_with(|| to_json_value(&value));
+        Ok(())
+    }
+}
+
+/// Keeps numbers and bools typed, everything else is formatted as a string
+fn to_json_value(value: &Value) -> JsonValue {
+    if let Some(value) = value.to_bool() {
+        value.into()
+    } else if let Some(value) = value.to_u64() {
+        value.into()
+    } else if let Some(value) = value.to_i64() {
+        value.into()
+    } else if let Some(value) = value.to_f64() {
+        value.into()
+    } else {
+        value.to_string().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{kv::Value, Level, Record};
+    use serde_json::json;
+
+    use crate::logging::json_line;
+
+    #[test]
+    fn test_json_line() {
+        let addr = "127.0.0.1:8001".parse::<std::net::SocketAddr>().unwrap();
+        let key_values = [
+            ("addr", Value::from_display(&addr)),
+            ("count", Value::from(3u64)),
+            ("delta", Value::from(-1i64)),
+            ("rate", Value::from(0.5f64)),
+            ("healthy", Value::from(true)),
+            // standard fields can't be overwritten
+            ("level", Value::from("ERROR")),
+        ];
+        // built in the same statement so the formatted args outlive the record
+        let line = json_line(
+            "2026-01-01T00:00:00.000000Z".to_string(),
+            &Record::builder()
+                .level(Level::Info)
+                .target("jito_shredstream_proxy::forwarder")
+                .args(format_args!("Destination {addr} added."))
+                .key_values(&key_values)
+                .build(),
+        );
+
+        assert_eq!(
+            line,
+            json!({
+                "ts": "2026-01-01T00:00:00.000000Z",
+                "level": "INFO",
+                "target": "jito_shredstream_proxy::forwarder",
+                "msg": "Destination 127.0.0.1:8001 added.",
+                "addr": "127.0.0.1:8001",
+                "count": 3,
+                "delta": -1,
+                "rate": 0.5,
+                "healthy": true,
+            })
+        );
+    }
+}
@@ -1,44 +1,69 @@
 use std::{
-    fs::File,
-    io::{self, Error, ErrorKind, Read},
-    net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs},
+    net::{IpAddr, SocketAddr},
     panic,
-    path::{Path, PathBuf},
-    str::FromStr,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc,
     },
-    thread::{self, sleep, spawn, JoinHandle},
+    thread::sleep,
     time::Duration,
 };
 
-use arc_swap::ArcSwap;
-use clap::{arg, Parser};
-use crossbeam_channel::{Receiver, RecvError, Sender};
+use clap::{arg, error::ErrorKind, CommandFactory, Parser};
+use jito_shredstream_proxy::{
+    apply_default_dest_port,
+    bench::{self, BenchArgs},
+    build_info, crash_report, encryption_key_files,
+    forwarder::ShredMetrics,
+    load_proxy_config,
+    logging::{self, LogFormat},
+    metrics_backend::MetricsBackendKind,
+    mock_block_engine::{self, MockBlockEngineArgs},
+    quic, regions,
+    signals::{heartbeat_pause_notifier, reload_notifier, shutdown_notifier},
+    supervisor, tunnel,
+    validate::ValidationReport,
+    CommonArgs, ConfigFormat, ProxyConfig, ReplayArgs, ShredstreamArgs, ShredstreamProxyBuilder,
+    ShredstreamProxyError,
+};
 use log::*;
-use signal_hook::consts::{SIGINT, SIGTERM};
-use solana_client::client_error::{reqwest, ClientError};
 use solana_metrics::set_host_id;
-use solana_perf::deduper::Deduper;
-use solana_sdk::signature::read_keypair_file;
-use solana_streamer::streamer::StreamerReceiveStats;
-use thiserror::Error;
-use tokio::runtime::Runtime;
-use tonic::Status;
-
-use crate::{forwarder::ShredMetrics, token_authenticator::BlockEngineConnectionError};
-
-mod forwarder;
-mod heartbeat;
-mod token_authenticator;
 
 #[derive(Clone, Debug, Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, long_version = build_info::LONG_VERSION, about, long_about = None)]
+// `--version` is our own flag so `--verbose` can be checked, clap's would print and exit on sight
+#[command(disable_version_flag = true, arg_required_else_help = true)]
 // https://docs.rs/clap/latest/clap/_derive/_cookbook/git_derive/index.html
 struct Args {
+    // only None with `--version`
     #[command(subcommand)]
-    shredstream_args: ProxySubcommands,
+    shredstream_args: Option<ProxySubcommands>,
+
+    /// Print version.
+    #[arg(short = 'V', long)]
+    version: bool,
+
+    /// With `--version`, also print the git commit, build timestamp, rustc version, and enabled cargo features.
+    #[arg(long, requires = "version")]
+    verbose: bool,
+
+    /// Log output format. `json` writes one object per line for log aggregators.
+    #[arg(long, env, global = true, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Runs the startup checks and prints a report without starting the proxy, exiting non-zero if any fail.
+    /// Applies to `shredstream`, `forward-only`, `replay`, and `shredstream-file-config`.
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// With `validate` or `--dry-run`, also check that the block engine, auth, and endpoint discovery urls accept connections.
+    #[arg(long, global = true)]
+    probe: bool,
+
+    /// Connect timeout for each url checked by `--probe`.
+    #[arg(long, global = true, default_value_t = 3_000)]
+    probe_timeout_ms: u64,
 }
 
 #[derive(Clone, Debug, clap::Subcommand)]
@@ -46,427 +71,573 @@ enum ProxySubcommands {
     /// Requests shreds from Jito and sends to all destinations.
     Shredstream(ShredstreamArgs),
 
+    /// Runs the shredstream or forward-only subcommand with args from a config file, selected by its `mode`.
     ShredstreamFileConfig(ShredstreamFileConfigArgs),
 
+    /// Checks a config file the way `shredstream-file-config` would at startup, printing a report without starting the proxy.
+    /// Exits non-zero if any check fails. Same as `shredstream-file-config --dry-run`.
+    Validate(ShredstreamFileConfigArgs),
+
     /// Does not request shreds from Jito. Sends anything received on `src-bind-addr`:`src-bind-port` to all destinations.
     ForwardOnly(CommonArgs),
-}
 
-#[derive(clap::Args, Clone, Debug)]
-struct ShredstreamFileConfigArgs {
-    #[arg(long, env)]
-    config: PathBuf,
-}
+    /// Receives shreds from a proxy forwarding to a `quic://` destination, re-emitting them over UDP to all destinations.
+    QuicReceive(QuicReceiveArgs),
 
-#[derive(clap::Args, Clone, Debug)]
-struct ShredstreamArgs {
-    /// Address for Jito Block Engine.
-    /// See https://jito-labs.gitbook.io/mev/searcher-resources/block-engine#connection-details
-    #[arg(long, env)]
-    block_engine_url: String,
-
-    /// Manual override for auth service address. For internal use.
-    #[arg(long, env)]
-    auth_url: Option<String>,
+    /// Receives shreds from a proxy forwarding to a `tcp://` or `tls://` destination, re-emitting them over UDP to all destinations.
+    TcpReceive(TcpReceiveArgs),
 
-    /// Path to keypair file used to authenticate with the backend.
-    #[arg(long, env)]
-    auth_keypair: PathBuf,
+    /// Replays packets from a pcap capture through the forwarder to all destinations, then exits.
+    Replay(ReplayArgs),
 
-    /// Desired regions to receive heartbeats from.
-    /// Receives `n` different streams. Requires at least 1 region, comma separated.
-    #[arg(long, env, value_delimiter = ',', required(true))]
-    desired_regions: Vec<String>,
+    /// Sends synthetic shreds to `target` for load testing a proxy, then prints throughput, send errors and CPU usage.
+    Bench(BenchArgs),
 
-    #[clap(flatten)]
-    common_args: CommonArgs,
+    /// Dev tool: serves the auth and heartbeat services of a block engine locally, accepting any keypair,
+    /// and sends synthetic shreds to proxies that register. For testing the shredstream flow without Jito access.
+    MockBlockEngine(MockBlockEngineArgs),
 }
 
 #[derive(clap::Args, Clone, Debug)]
-struct CommonArgs {
-    /// Address where Shredstream proxy listens.
+struct QuicReceiveArgs {
+    /// Address to accept QUIC connections on.
+    /// Connections are not authenticated, restrict access with a firewall.
     #[arg(long, env, default_value_t = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)))]
-    src_bind_addr: IpAddr,
+    quic_bind_addr: IpAddr,
 
-    /// Port where Shredstream proxy listens. Use `0` for random ephemeral port.
-    #[arg(long, env, default_value_t = 20_000)]
-    src_bind_port: u16,
+    /// Port to accept QUIC connections on.
+    #[arg(long, env, default_value_t = 20_001)]
+    quic_bind_port: u16,
 
-    /// Static set of IP:Port where Shredstream proxy forwards shreds to, comma separated.
+    /// IP:Port to forward received shreds to over UDP, comma separated.
     /// Eg. `127.0.0.1:8001,10.0.0.1:8001`.
-    // Note: store the original string, so we can do hostname resolution when refreshing destinations
-    #[arg(long, env, value_delimiter = ',', value_parser = resolve_hostname_port)]
-    dest_ip_ports: Vec<(SocketAddr, String)>,
-
-    /// Http JSON endpoint to dynamically get IPs for Shredstream proxy to forward shreds.
-    /// Endpoints are then set-union with `dest-ip-ports`.
-    #[arg(long, env)]
-    endpoint_discovery_url: Option<String>,
-
-    /// Port to send shreds to for hosts fetched via `endpoint-discovery-url`.
-    /// Port can be found using `scripts/get_tvu_port.sh`.
-    /// See https://jito-labs.gitbook.io/mev/searcher-services/shredstream#running-shredstream
-    #[arg(long, env)]
-    discovered_endpoints_port: Option<u16>,
-
-    /// Interval between logging stats to stdout and influx
-    #[arg(long, env, default_value_t = 15_000)]
-    metrics_report_interval_ms: u64,
+    #[arg(long, env, value_delimiter = ',', required(true))]
+    dest_ip_ports: Vec<SocketAddr>,
+}
 
-    /// Logs trace shreds to stdout and influx
-    #[arg(long, env, default_value_t = false)]
-    debug_trace_shred: bool,
+#[derive(clap::Args, Clone, Debug)]
+struct TcpReceiveArgs {
+    /// Address to accept TCP connections on.
+    /// Connections are only authenticated with `tls-client-ca-cert`, otherwise restrict access with a firewall.
+    #[arg(long, env, default_value_t = IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)))]
+    tcp_bind_addr: IpAddr,
 
-    /// Public IP address to use.
-    /// Overrides value fetched from `ifconfig.me`.
-    #[arg(long, env)]
-    public_ip: Option<IpAddr>,
+    /// Port to accept TCP connections on.
+    #[arg(long, env, default_value_t = 20_002)]
+    tcp_bind_port: u16,
 
-    /// Number of threads to use. Defaults to use up to 4.
-    #[arg(long, env)]
-    num_threads: Option<usize>,
-}
+    /// PEM certificate chain to terminate TLS with, for proxies forwarding to `tls://` destinations. Requires `tls-key-file`.
+    #[arg(long, env, requires = "tls_key_file")]
+    tls_cert_file: Option<PathBuf>,
 
-#[derive(Debug, Error)]
-pub enum ShredstreamProxyError {
-    #[error("TonicError {0}")]
-    TonicError(#[from] tonic::transport::Error),
-    #[error("GrpcError {0}")]
-    GrpcError(#[from] Status),
-    #[error("ReqwestError {0}")]
-    ReqwestError(#[from] reqwest::Error),
-    #[error("SerdeJsonError {0}")]
-    SerdeJsonError(#[from] serde_json::Error),
-    #[error("RpcError {0}")]
-    RpcError(#[from] ClientError),
-    #[error("BlockEngineConnectionError {0}")]
-    BlockEngineConnectionError(#[from] BlockEngineConnectionError),
-    #[error("RecvError {0}")]
-    RecvError(#[from] RecvError),
-    #[error("IoError {0}")]
-    IoError(#[from] io::Error),
-    #[error("Shutdown")]
-    Shutdown,
-}
+    /// PEM private key for `tls-cert-file`.
+    #[arg(long, env, requires = "tls_cert_file")]
+    tls_key_file: Option<PathBuf>,
 
-fn resolve_hostname_port(hostname_port: &str) -> io::Result<(SocketAddr, String)> {
-    let socketaddr = hostname_port.to_socket_addrs()?.next().ok_or_else(|| {
-        Error::new(
-            ErrorKind::AddrNotAvailable,
-            format!("Could not find destination {hostname_port}"),
-        )
-    })?;
+    /// PEM file of CA certificates to verify client certificates with, rejecting TLS connections without one it signed.
+    /// Senders present theirs with `tunnel-tls-client-cert`. Requires `tls-cert-file`.
+    #[arg(long, env, requires = "tls_cert_file")]
+    tls_client_ca_cert: Option<PathBuf>,
 
-    Ok((socketaddr, hostname_port.to_string()))
-}
+    /// Max connections handled at once, further connections are closed as soon as they're accepted and counted.
+    #[arg(long, env, default_value_t = 64)]
+    max_connections: usize,
 
-/// Returns public-facing IPV4 address
-pub fn get_public_ip() -> reqwest::Result<IpAddr> {
-    info!("Requesting public ip from ifconfig.me...");
-    let client = reqwest::blocking::Client::builder()
-        .local_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
-        .build()?;
-    let response = client.get("https://ifconfig.me/ip").send()?.text()?;
-    let public_ip = IpAddr::from_str(&response).unwrap();
-    info!("Retrieved public ip: {public_ip:?}");
-
-    Ok(public_ip)
+    /// IP:Port to forward received shreds to over UDP, comma separated.
+    /// Eg. `127.0.0.1:8001,10.0.0.1:8001`.
+    #[arg(long, env, value_delimiter = ',', required(true))]
+    dest_ip_ports: Vec<SocketAddr>,
 }
 
-// Creates a channel that gets a message every time `SIGINT` is signalled.
-fn shutdown_notifier(exit: Arc<AtomicBool>) -> io::Result<(Sender<()>, Receiver<()>)> {
-    let (s, r) = crossbeam_channel::bounded(256);
-    let mut signals = signal_hook::iterator::Signals::new([SIGINT, SIGTERM])?;
-
-    let s_thread = s.clone();
-    thread::spawn(move || {
-        for _ in signals.forever() {
-            exit.store(true, Ordering::SeqCst);
-            // send shutdown signal multiple times since crossbeam doesn't have broadcast channels
-            // each thread will consume a shutdown signal
-            for _ in 0..256 {
-                if s_thread.send(()).is_err() {
-                    break;
-                }
-            }
-        }
-    });
+#[derive(clap::Args, Clone, Debug)]
+struct ShredstreamFileConfigArgs {
+    /// Path to config file. Format is detected from the extension: `.toml`, `.yaml`/`.yml`, or `.json`.
+    #[arg(long, env)]
+    config: PathBuf,
 
-    Ok((s, r))
+    /// Overrides the config file format detected from the extension. Defaults to TOML if undetected.
+    #[arg(long, env, value_enum)]
+    config_format: Option<ConfigFormat>,
 }
 
 fn main() -> Result<(), ShredstreamProxyError> {
-    env_logger::builder().init();
-    let all_args: Args = Args::parse();
+    let mut all_args: Args = Args::parse();
+    if all_args.version {
+        let command = Args::command();
+        let version = if all_args.verbose {
+            command.render_long_version()
+        } else {
+            command.render_version()
+        };
+        println!("{}", version.trim_end());
+        return Ok(());
+    }
+    let Some(subcommand) = all_args.shredstream_args.take() else {
+        Args::command()
+            .error(ErrorKind::MissingSubcommand, "a subcommand is required")
+            .exit();
+    };
+    logging::init_logger(all_args.log_format);
+
+    let dry_run = all_args.dry_run || matches!(subcommand, ProxySubcommands::Validate(_));
+    let mut report = ValidationReport::default();
 
     // Potentially override *ALL* CLI args with config file
-    let all_args = match all_args.shredstream_args {
-        ProxySubcommands::ShredstreamFileConfig(args) => {
-            let config = load_shredstream_config(&args.config)?;
-            Args {
-                shredstream_args: ProxySubcommands::Shredstream(config),
+    let mut reload_config = None;
+    let mut subcommand = match subcommand {
+        ProxySubcommands::ShredstreamFileConfig(args) | ProxySubcommands::Validate(args) => {
+            let config = match load_proxy_config(&args.config, args.config_format) {
+                Ok(config) => config,
+                Err(e) if dry_run => {
+                    report.check("config", Err(e.to_string()));
+                    return print_validation_report(&report, all_args.log_format);
+                }
+                Err(e) => return Err(e.into()),
+            };
+            report.record("config", Ok(format!("loaded {:?}", args.config)));
+            reload_config = Some((args.config, args.config_format));
+            match config {
+                ProxyConfig::Shredstream(args) => ProxySubcommands::Shredstream(args),
+                ProxyConfig::ForwardOnly(args) => ProxySubcommands::ForwardOnly(args),
             }
         }
-        other => Args {
-            shredstream_args: other,
-        },
+        other => other,
     };
+    let common_args = match &mut subcommand {
+        ProxySubcommands::Shredstream(x) => Some(&mut x.common_args),
+        ProxySubcommands::ForwardOnly(x) => Some(x),
+        ProxySubcommands::Replay(x) => Some(&mut x.common_args),
+        _ => None,
+    };
+    if let Some(common_args) = common_args {
+        // needs `--default-dest-port`, so can't happen while parsing each destination
+        if let Err(e) = apply_default_dest_port(common_args) {
+            Args::command()
+                .error(ErrorKind::ValueValidation, format!("--dest-ip-ports: {e}"))
+                .exit();
+        }
+    }
 
-    let shredstream_args = all_args.shredstream_args.clone();
-    // common args
-    let args = match all_args.shredstream_args {
-        ProxySubcommands::Shredstream(x) => x.common_args,
-        ProxySubcommands::ForwardOnly(x) => x,
-        ProxySubcommands::ShredstreamFileConfig(_) => unreachable!(),
+    let (builder, args) = match subcommand {
+        ProxySubcommands::Shredstream(x) if x.list_regions => {
+            for region in regions::list_available_regions(&x)? {
+                println!("{region}");
+            }
+            return Ok(());
+        }
+        ProxySubcommands::Shredstream(x) => {
+            let args = x.common_args.clone();
+            (ShredstreamProxyBuilder::shredstream(x), args)
+        }
+        ProxySubcommands::ForwardOnly(x) => (ShredstreamProxyBuilder::forward_only(x.clone()), x),
+        ProxySubcommands::Replay(x) => {
+            let args = x.common_args.clone();
+            (ShredstreamProxyBuilder::replay(x), args)
+        }
+        ProxySubcommands::QuicReceive(args) => return run_quic_receive(args),
+        ProxySubcommands::TcpReceive(args) => return run_tcp_receive(args),
+        ProxySubcommands::Bench(args) => return run_bench(args),
+        ProxySubcommands::MockBlockEngine(args) => return run_mock_block_engine(args),
+        ProxySubcommands::ShredstreamFileConfig(_) | ProxySubcommands::Validate(_) => {
+            unreachable!()
+        }
     };
-    set_host_id(hostname::get()?.into_string().unwrap());
-    if (args.endpoint_discovery_url.is_none() && args.discovered_endpoints_port.is_some())
-        || (args.endpoint_discovery_url.is_some() && args.discovered_endpoints_port.is_none())
-    {
-        panic!("Invalid arguments provided, dynamic endpoints requires both --endpoint-discovery-url and --discovered-endpoints-port.")
+    if dry_run {
+        let probe_timeout = all_args
+            .probe
+            .then(|| Duration::from_millis(all_args.probe_timeout_ms));
+        report.checks.extend(builder.validate(probe_timeout).checks);
+        return print_validation_report(&report, all_args.log_format);
     }
-    if args.endpoint_discovery_url.is_none()
-        && args.discovered_endpoints_port.is_none()
-        && args.dest_ip_ports.is_empty()
-    {
-        panic!("No destinations found. You must provide values for --dest-ip-ports or --endpoint-discovery-url.")
+    if args.metrics_backend == MetricsBackendKind::Influx {
+        set_host_id(hostname::get()?.into_string().unwrap());
     }
 
     let exit = Arc::new(AtomicBool::new(false));
     let (shutdown_sender, shutdown_receiver) =
         shutdown_notifier(exit.clone()).expect("Failed to set up signal handler");
+    let mut builder =
+        builder.shutdown_signal(exit.clone(), shutdown_sender.clone(), shutdown_receiver);
+    if let Some((config_path, config_format)) = reload_config {
+        builder = builder.config_reload(config_path, config_format, reload_notifier()?);
+    } else if !encryption_key_files(&args).is_empty() {
+        // otherwise SIGHUP keeps its default of terminating the process
+        builder = builder.key_reload(reload_notifier()?);
+    }
+    let mut proxy = builder.build().unwrap_or_else(|e| panic!("{e}"));
+    heartbeat_pause_notifier(proxy.metrics())?;
+
     let panic_hook = panic::take_hook();
-    {
-        let exit = exit.clone();
-        panic::set_hook(Box::new(move |panic_info| {
-            exit.store(true, Ordering::SeqCst);
-            let _ = shutdown_sender.send(());
-            error!("exiting process");
-            sleep(Duration::from_secs(1));
-            // invoke the default handler and exit the process
+    let crash_report_file = args.crash_report_file.clone();
+    panic::set_hook(Box::new(move |panic_info| {
+        // supervisor decides whether to restart or shut down
+        if supervisor::is_supervised_thread() {
             panic_hook(panic_info);
-        }));
+            return;
+        }
+        crash_report::report_panic(panic_info, crash_report_file.as_deref());
+        exit.store(true, Ordering::SeqCst);
+        let _ = shutdown_sender.send(());
+        sleep(Duration::from_secs(1));
+        // invoke the default handler and exit the process
+        panic_hook(panic_info);
+    }));
+
+    proxy.start()?;
+    let join_res = proxy.join_with_deadline();
+    log_exit_summary(&proxy.metrics(), &args);
+    if let Err(e) = join_res {
+        // stuck threads would otherwise keep the process alive
+        error!("{e}, forcing exit.");
+        std::process::exit(1);
     }
+    Ok(())
+}
 
-    let metrics = Arc::new(ShredMetrics::new());
-
-    let runtime = Runtime::new()?;
-    let mut thread_handles = vec![];
-    if let ProxySubcommands::Shredstream(args) = shredstream_args {
-        let heartbeat_hdl =
-            start_heartbeat(args, &exit, &shutdown_receiver, runtime, metrics.clone());
-        thread_handles.push(heartbeat_hdl);
+/// Prints `report` to stdout, as a JSON object with `--log-format json`, exiting non-zero if any check failed
+fn print_validation_report(
+    report: &ValidationReport,
+    log_format: LogFormat,
+) -> Result<(), ShredstreamProxyError> {
+    match log_format {
+        LogFormat::Text => println!("{report}"),
+        LogFormat::Json => println!(
+            "{}",
+            serde_json::to_string(report).expect("report serializes to JSON")
+        ),
     }
-
-    // share sockets between refresh and forwarder thread
-    let unioned_dest_sockets = Arc::new(ArcSwap::from_pointee(
-        args.dest_ip_ports
-            .iter()
-            .map(|x| x.0)
-            .collect::<Vec<SocketAddr>>(),
-    ));
-
-    // share deduper + metrics between forwarder <-> accessory thread
-    // use mutex since metrics are write heavy. cheaper than rwlock
-    let deduper = Arc::new(RwLock::new(Deduper::<2, [u8]>::new(
-        &mut rand::thread_rng(),
-        forwarder::DEDUPER_NUM_BITS,
-    )));
-
-    let forward_stats = Arc::new(StreamerReceiveStats::new("shredstream_proxy-listen_thread"));
-    let use_discovery_service =
-        args.endpoint_discovery_url.is_some() && args.discovered_endpoints_port.is_some();
-    let forwarder_hdls = forwarder::start_forwarder_threads(
-        unioned_dest_sockets.clone(),
-        args.src_bind_addr,
-        args.src_bind_port,
-        args.num_threads,
-        deduper.clone(),
-        metrics.clone(),
-        forward_stats.clone(),
-        use_discovery_service,
-        args.debug_trace_shred,
-        shutdown_receiver.clone(),
-        exit.clone(),
-    );
-    thread_handles.extend(forwarder_hdls);
-
-    let report_metrics_thread = {
-        let exit = exit.clone();
-        spawn(move || {
-            while !exit.load(Ordering::Relaxed) {
-                sleep(Duration::from_secs(1));
-                forward_stats.report();
-            }
-        })
-    };
-    thread_handles.push(report_metrics_thread);
-
-    let metrics_hdl = forwarder::start_forwarder_accessory_thread(
-        deduper,
-        metrics.clone(),
-        args.metrics_report_interval_ms,
-        shutdown_receiver.clone(),
-        exit.clone(),
-    );
-    thread_handles.push(metrics_hdl);
-    if use_discovery_service {
-        let refresh_handle = forwarder::start_destination_refresh_thread(
-            args.endpoint_discovery_url.unwrap(),
-            args.discovered_endpoints_port.unwrap(),
-            args.dest_ip_ports,
-            unioned_dest_sockets,
-            shutdown_receiver,
-            exit,
-        );
-        thread_handles.push(refresh_handle);
+    if !report.passed() {
+        std::process::exit(1);
     }
+    Ok(())
+}
 
-    info!(
-        "Shredstream started, listening on {}:{}/udp.",
-        args.src_bind_addr, args.src_bind_port
-    );
+fn run_quic_receive(args: QuicReceiveArgs) -> Result<(), ShredstreamProxyError> {
+    let exit = Arc::new(AtomicBool::new(false));
+    // receiver only checks exit, so the shutdown channel is unused
+    let _shutdown = shutdown_notifier(exit.clone()).expect("Failed to set up signal handler");
+
+    let (receiver_hdl, _) = quic::start_quic_receiver_thread(
+        SocketAddr::new(args.quic_bind_addr, args.quic_bind_port),
+        args.dest_ip_ports,
+        exit,
+    )?;
+    receiver_hdl.join().expect("thread panicked");
+    Ok(())
+}
 
-    for thread in thread_handles {
-        thread.join().expect("thread panicked");
+fn run_tcp_receive(args: TcpReceiveArgs) -> Result<(), ShredstreamProxyError> {
+    if args.max_connections == 0 {
+        return Err(ShredstreamProxyError::InvalidArguments(
+            "Invalid arguments provided, --max-connections must be greater than 0.".to_string(),
+        ));
     }
-
-    info!(
-        "Exiting Shredstream, {} received , {} sent successfully, {} failed, {} duplicate shreds.",
-        metrics.agg_received_cumulative.load(Ordering::Relaxed),
-        metrics
-            .agg_success_forward_cumulative
-            .load(Ordering::Relaxed),
-        metrics.agg_fail_forward_cumulative.load(Ordering::Relaxed),
-        metrics.duplicate_cumulative.load(Ordering::Relaxed),
-    );
+    let tls_config = match (&args.tls_cert_file, &args.tls_key_file) {
+        (Some(cert_file), Some(key_file)) => Some(tunnel::new_tls_server_config(
+            cert_file,
+            key_file,
+            args.tls_client_ca_cert.as_deref(),
+        )?),
+        _ => None,
+    };
+    let exit = Arc::new(AtomicBool::new(false));
+    // receiver only checks exit, so the shutdown channel is unused
+    let _shutdown = shutdown_notifier(exit.clone()).expect("Failed to set up signal handler");
+
+    let (receiver_hdl, _) = tunnel::start_tunnel_receiver_thread(
+        SocketAddr::new(args.tcp_bind_addr, args.tcp_bind_port),
+        tls_config,
+        args.dest_ip_ports,
+        args.max_connections,
+        exit,
+    )?;
+    receiver_hdl.join().expect("thread panicked");
     Ok(())
 }
 
-fn start_heartbeat(
-    args: ShredstreamArgs,
-    exit: &Arc<AtomicBool>,
-    shutdown_receiver: &Receiver<()>,
-    runtime: Runtime,
-    metrics: Arc<ShredMetrics>,
-) -> JoinHandle<()> {
-    let auth_keypair = Arc::new(
-        read_keypair_file(Path::new(&args.auth_keypair)).unwrap_or_else(|e| {
-            panic!(
-                "Unable to parse keypair file. Ensure that file {:?} is readable. Error: {e}",
-                args.auth_keypair
-            )
-        }),
-    );
+fn run_bench(args: BenchArgs) -> Result<(), ShredstreamProxyError> {
+    let exit = Arc::new(AtomicBool::new(false));
+    // bench threads only check exit, so the shutdown channel is unused
+    let _shutdown = shutdown_notifier(exit.clone()).expect("Failed to set up signal handler");
 
-    heartbeat::heartbeat_loop_thread(
-        args.block_engine_url.clone(),
-        args.auth_url.unwrap_or(args.block_engine_url),
-        auth_keypair,
-        args.desired_regions,
-        SocketAddr::new(
-            args.common_args
-                .public_ip
-                .unwrap_or_else(|| get_public_ip().unwrap()),
-            args.common_args.src_bind_port,
-        ),
-        runtime,
-        "shredstream_proxy".to_string(),
-        metrics,
-        shutdown_receiver.clone(),
-        exit.clone(),
-    )
+    let report = bench::run_bench(&args, exit)?;
+    println!("{report}");
+    Ok(())
 }
 
-#[derive(Clone, Debug, serde::Deserialize)]
-struct ShredstreamConfig {
-    block_engine_url: String,
-    #[serde(default)]
-    auth_url: Option<String>,
-    auth_keypair: PathBuf,
-    desired_regions: Vec<String>,
-    common: CommonConfig,
-}
+fn run_mock_block_engine(args: MockBlockEngineArgs) -> Result<(), ShredstreamProxyError> {
+    let exit = Arc::new(AtomicBool::new(false));
+    // mock threads only check exit, so the shutdown channel is unused
+    let _shutdown = shutdown_notifier(exit.clone()).expect("Failed to set up signal handler");
 
-#[derive(Clone, Debug, serde::Deserialize)]
-struct CommonConfig {
-    #[serde(default = "default_src_bind_addr")]
-    src_bind_addr: IpAddr,
-    #[serde(default = "default_src_bind_port")]
-    src_bind_port: u16,
-    #[serde(default)]
-    dest_ip_ports: Vec<String>,
-    #[serde(default)]
-    endpoint_discovery_url: Option<String>,
-    #[serde(default)]
-    discovered_endpoints_port: Option<u16>,
-    #[serde(default = "default_metrics_report_interval")]
-    metrics_report_interval_ms: u64,
-    #[serde(default)]
-    debug_trace_shred: bool,
-    #[serde(default)]
-    public_ip: Option<IpAddr>,
-    #[serde(default)]
-    num_threads: Option<usize>,
+    let (_mock_block_engine, hdls) = mock_block_engine::start_mock_block_engine(&args, exit)?;
+    for hdl in hdls {
+        hdl.join().expect("thread panicked");
+    }
+    Ok(())
 }
 
-// Default value functions for CommonConfig
-fn default_src_bind_addr() -> IpAddr {
-    IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))
+fn log_exit_summary(metrics: &ShredMetrics, args: &CommonArgs) {
+    let received = metrics.agg_received_cumulative.load(Ordering::Relaxed);
+    let success_forward = metrics
+        .agg_success_forward_cumulative
+        .load(Ordering::Relaxed);
+    let fail_forward = metrics.agg_fail_forward_cumulative.load(Ordering::Relaxed);
+    let udp_fail_forward = metrics.udp_fail_forward_cumulative.load(Ordering::Relaxed);
+    let quic_fail_forward = metrics.quic_fail_forward_cumulative.load(Ordering::Relaxed);
+    let tunnel_fail_forward = metrics
+        .tunnel_fail_forward_cumulative
+        .load(Ordering::Relaxed);
+    let duplicate = metrics.duplicate_cumulative.load(Ordering::Relaxed);
+    let stale_slot_dropped = metrics
+        .stale_slot_dropped_cumulative
+        .load(Ordering::Relaxed);
+    let received_invalid = metrics.received_invalid_cumulative.load(Ordering::Relaxed);
+    let non_shred_dropped = metrics.non_shred_dropped_cumulative.load(Ordering::Relaxed);
+    let recv_socket_dropped = metrics
+        .recv_socket_dropped_cumulative
+        .load(Ordering::Relaxed);
+    info!(
+        event = "metrics_report",
+        received,
+        success_forward,
+        fail_forward,
+        udp_fail_forward,
+        quic_fail_forward,
+        tunnel_fail_forward,
+        duplicate,
+        stale_slot_dropped,
+        received_invalid,
+        non_shred_dropped,
+        recv_socket_dropped;
+        "Exiting Shredstream, {received} received , {success_forward} sent successfully, {fail_forward} failed ({udp_fail_forward} udp, {quic_fail_forward} quic, {tunnel_fail_forward} tunnel), {duplicate} duplicate shreds, {stale_slot_dropped} stale shreds dropped, {received_invalid} non-shred packets received ({non_shred_dropped} dropped), {recv_socket_dropped} dropped by the kernel.",
+    );
+    let channel_dropped_packets = metrics
+        .channel_dropped_packets_cumulative
+        .load(Ordering::Relaxed);
+    if channel_dropped_packets > 0 {
+        warn!(
+            "Dropped {channel_dropped_packets} packets in {} batches from full forwarder channels.",
+            metrics
+                .channel_dropped_batches_cumulative
+                .load(Ordering::Relaxed),
+        );
+    }
+    let memory_guard_dropped = metrics
+        .memory_guard_dropped_cumulative
+        .load(Ordering::Relaxed);
+    if memory_guard_dropped > 0 {
+        warn!("Dropped {memory_guard_dropped} received packets while buffered bytes were over --max-buffered-bytes.");
+    }
+    if args.record_pcap.is_some() {
+        info!(
+            "Recorded {} packets to pcap, dropped {} when the writer fell behind.",
+            metrics.pcap_recorded_cumulative.load(Ordering::Relaxed),
+            metrics.pcap_dropped_cumulative.load(Ordering::Relaxed),
+        );
+    }
+    if args.kafka_brokers.is_some() {
+        info!(
+            "Published {} shreds to Kafka, dropped {} when the publisher fell behind, {} failed delivery.",
+            metrics.kafka_published_cumulative.load(Ordering::Relaxed),
+            metrics.kafka_dropped_cumulative.load(Ordering::Relaxed),
+            metrics.kafka_delivery_failed_cumulative.load(Ordering::Relaxed),
+        );
+    }
+    let thread_restarts = metrics.thread_restarts_cumulative.load(Ordering::Relaxed);
+    if thread_restarts > 0 {
+        warn!("Restarted threads {thread_restarts} times after panics.");
+    }
+    let socket_rebinds = metrics.socket_rebinds_cumulative.load(Ordering::Relaxed);
+    if socket_rebinds > 0 {
+        warn!("Rebound sockets {socket_rebinds} times after persistent errors.");
+    }
+    if args.health_check_mode.is_some() {
+        info!(
+            "Destination health checks marked destinations unhealthy {} times, {} recovered, {} unhealthy at exit.",
+            metrics
+                .dest_became_unhealthy_cumulative
+                .load(Ordering::Relaxed),
+            metrics
+                .dest_became_healthy_cumulative
+                .load(Ordering::Relaxed),
+            metrics.unhealthy_destinations.load(Ordering::Relaxed),
+        );
+    }
+    let mut dest_forwarded = metrics
+        .dest_forwarded_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_forwarded.sort_unstable();
+    for (addr, (success_forward, fail_forward)) in dest_forwarded {
+        info!("Destination {addr}: {success_forward} sent successfully, {fail_forward} failed.");
+    }
 }
 
-fn default_src_bind_port() -> u16 {
-    20_000
-}
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use clap::Parser;
+    use jito_shredstream_proxy::{apply_default_dest_port, CommonArgs};
+
+    use crate::{Args, ProxySubcommands};
+
+    #[test]
+    fn test_parse_block_engine_url_arg() {
+        let expected = vec![
+            "https://ny.mainnet.block-engine.jito.wtf",
+            "https://amsterdam.mainnet.block-engine.jito.wtf",
+        ];
+        let args = Args::try_parse_from([
+            "proxy",
+            "shredstream",
+            "--block-engine-url",
+            &expected.join(","),
+            "--auth-keypair",
+            "keypair.json",
+            "--desired-regions",
+            "ny",
+        ])
+        .unwrap();
+        let Some(ProxySubcommands::Shredstream(args)) = args.shredstream_args else {
+            panic!("expected shredstream subcommand");
+        };
+        assert_eq!(args.block_engine_url, expected);
+    }
 
-fn default_metrics_report_interval() -> u64 {
-    15_000
-}
+    #[test]
+    fn test_auth_keypair_args() {
+        let parse = |auth_args: &[&str]| {
+            let args = [
+                "proxy",
+                "shredstream",
+                "--block-engine-url",
+                "a",
+                "--desired-regions",
+                "ny",
+            ];
+            Args::try_parse_from(args.iter().chain(auth_args))
+        };
+        assert!(parse(&["--auth-keypair", "keypair.json"]).is_ok());
+        assert!(parse(&["--auth-keypair-base58", "AUTH_KEYPAIR_SECRET"]).is_ok());
+        assert!(parse(&["--auth-keypair-stdin"]).is_ok());
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["--auth-keypair", "keypair.json", "--auth-keypair-stdin"]).is_err());
+    }
 
-impl TryFrom<ShredstreamConfig> for ShredstreamArgs {
-    type Error = io::Error;
-
-    fn try_from(config: ShredstreamConfig) -> Result<Self, Self::Error> {
-        Ok(ShredstreamArgs {
-            block_engine_url: config.block_engine_url,
-            auth_url: config.auth_url,
-            auth_keypair: config.auth_keypair,
-            desired_regions: config.desired_regions,
-            common_args: config.common.try_into()?,
-        })
+    #[test]
+    fn test_list_regions_without_desired_regions() {
+        let parse = |args: &[&str]| {
+            Args::try_parse_from(
+                [
+                    "proxy",
+                    "shredstream",
+                    "--block-engine-url",
+                    "a",
+                    "--auth-keypair",
+                    "keypair.json",
+                ]
+                .iter()
+                .chain(args),
+            )
+        };
+        assert!(parse(&[]).is_err());
+        let args = parse(&["--list-regions"]).unwrap();
+        let Some(ProxySubcommands::Shredstream(args)) = args.shredstream_args else {
+            panic!("expected shredstream subcommand");
+        };
+        assert!(args.list_regions);
+        assert!(args.desired_regions.is_empty());
     }
-}
 
-impl TryFrom<CommonConfig> for CommonArgs {
-    type Error = io::Error;
-
-    fn try_from(config: CommonConfig) -> Result<Self, Self::Error> {
-        Ok(CommonArgs {
-            src_bind_addr: config.src_bind_addr,
-            src_bind_port: config.src_bind_port,
-            dest_ip_ports: config
-                .dest_ip_ports
-                .into_iter()
-                .map(|addr| resolve_hostname_port(&addr))
-                .collect::<Result<Vec<_>, _>>()?,
-            endpoint_discovery_url: config.endpoint_discovery_url,
-            discovered_endpoints_port: config.discovered_endpoints_port,
-            metrics_report_interval_ms: config.metrics_report_interval_ms,
-            debug_trace_shred: config.debug_trace_shred,
-            public_ip: config.public_ip,
-            num_threads: config.num_threads,
-        })
+    #[test]
+    fn test_library_defaults_match_cli() {
+        let args = Args::try_parse_from(["proxy", "forward-only"]).unwrap();
+        let Some(ProxySubcommands::ForwardOnly(args)) = args.shredstream_args else {
+            panic!("expected forward-only subcommand");
+        };
+        assert_eq!(format!("{args:?}"), format!("{:?}", CommonArgs::default()));
     }
-}
 
-fn load_shredstream_config(path: &Path) -> io::Result<ShredstreamArgs> {
-    let mut contents = String::new();
-    File::open(path)?.read_to_string(&mut contents)?;
-    let config: ShredstreamConfig = toml::from_str(&contents).map_err(|e| {
-        Error::new(
-            ErrorKind::InvalidData,
-            format!("Failed to parse config file: {}", e),
-        )
-    })?;
-    config.try_into()
+    #[test]
+    fn test_default_dest_port_args() {
+        let parse = |args: &[&str]| {
+            let args = Args::try_parse_from(["proxy", "forward-only"].iter().chain(args)).unwrap();
+            let Some(ProxySubcommands::ForwardOnly(mut args)) = args.shredstream_args else {
+                panic!("expected forward-only subcommand");
+            };
+            apply_default_dest_port(&mut args).map(|()| args.dest_ip_ports)
+        };
+        let dest = |addr: &str, hostname_port: &str| {
+            (
+                SocketAddr::from_str(addr).unwrap(),
+                hostname_port.to_string(),
+            )
+        };
+
+        assert_eq!(
+            parse(&["--dest-ip-ports", "127.0.0.1,127.0.0.1:8002?rate=100pps"]).unwrap(),
+            vec![
+                dest("127.0.0.1:8001", "127.0.0.1:8001"),
+                dest("127.0.0.1:8002", "127.0.0.1:8002?rate=100pps"),
+            ]
+        );
+        // later entries resolving to the same address are dropped
+        assert_eq!(
+            parse(&[
+                "--dest-ip-ports",
+                "127.0.0.1,127.0.0.1:9000",
+                "--default-dest-port",
+                "9000",
+            ])
+            .unwrap(),
+            vec![dest("127.0.0.1:9000", "127.0.0.1:9000")]
+        );
+        assert_eq!(
+            parse(&[
+                "--dest-ip-ports",
+                "quic://::1",
+                "--endpoint-discovery-url",
+                "http://127.0.0.1:9999",
+                "--discovered-endpoints-port",
+                "9001",
+            ])
+            .unwrap(),
+            vec![dest("[::1]:9001", "quic://[::1]:9001")]
+        );
+
+        let err = parse(&["--dest-ip-ports", "127.0.0.1", "--default-dest-port", "0"]).unwrap_err();
+        assert!(err.to_string().contains("port 0"), "{err}");
+        assert!(
+            Args::try_parse_from(["proxy", "forward-only", "--dest-ip-ports", "127.0.0.1:0"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_validate_args() {
+        let args = Args::try_parse_from(["proxy", "validate", "--config", "config.toml"]).unwrap();
+        assert!(matches!(
+            args.shredstream_args,
+            Some(ProxySubcommands::Validate(_))
+        ));
+        assert!(!args.dry_run);
+
+        let args = Args::try_parse_from(["proxy", "forward-only", "--dry-run", "--probe"]).unwrap();
+        assert!(args.dry_run);
+        assert!(args.probe);
+        assert_eq!(args.probe_timeout_ms, 3_000);
+    }
+
+    #[test]
+    fn test_version_args() {
+        let args = Args::try_parse_from(["proxy", "--version", "--verbose"]).unwrap();
+        assert!(args.version && args.verbose);
+        assert!(args.shredstream_args.is_none());
+        assert!(Args::try_parse_from(["proxy", "-V"]).unwrap().version);
+        // only meaningful with --version
+        assert!(Args::try_parse_from(["proxy", "forward-only", "--verbose"]).is_err());
+    }
 }
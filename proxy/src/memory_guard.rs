@@ -0,0 +1,176 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
+
+use log::{info, warn};
+
+/// Once over `--max-buffered-bytes`, ingress drops packets until usage falls to this percent of it, so it doesn't flap at the limit
+pub const LOW_WATER_PERCENT: u64 = 80;
+
+/// Where buffered bytes are held
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferKind {
+    /// Received batches, from the receive threads until every sink is done with them, including isolated send queues
+    Receive,
+    /// Packets buffered for `tcp://` and `tls://` destinations, eg. while they reconnect
+    Tunnel,
+    /// Packets queued for the pcap writer
+    Pcap,
+    /// Shreds queued for the Kafka publisher
+    Kafka,
+    /// Shreds queued for the archiver
+    Archive,
+    /// Packets queued for the spill writer, and live packets queued behind a spill replay
+    Spill,
+}
+
+impl BufferKind {
+    pub const ALL: [BufferKind; 6] = [
+        BufferKind::Receive,
+        BufferKind::Tunnel,
+        BufferKind::Pcap,
+        BufferKind::Kafka,
+        BufferKind::Archive,
+        BufferKind::Spill,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BufferKind::Receive => "receive",
+            BufferKind::Tunnel => "tunnel",
+            BufferKind::Pcap => "pcap",
+            BufferKind::Kafka => "kafka",
+            BufferKind::Archive => "archive",
+            BufferKind::Spill => "spill",
+        }
+    }
+}
+
+/// Bytes held in flight by the proxy's queues, and the `--max-buffered-bytes` guard that drops packets at ingress while they're over it.
+/// Updated with relaxed atomics where buffers are queued and released, so the guard may let a few batches past the limit under contention
+#[derive(Debug, Default)]
+pub struct MemoryGuard {
+    held: [AtomicU64; BufferKind::ALL.len()],
+    /// 0 for no limit
+    max_bytes: AtomicU64,
+    dropping: AtomicBool,
+}
+
+impl MemoryGuard {
+    pub fn set_max_bytes(&self, max_bytes: Option<u64>) {
+        self.max_bytes
+            .store(max_bytes.unwrap_or_default(), Ordering::Relaxed);
+    }
+
+    pub fn max_bytes(&self) -> Option<u64> {
+        Some(self.max_bytes.load(Ordering::Relaxed)).filter(|max_bytes| *max_bytes > 0)
+    }
+
+    pub fn held(&self, kind: BufferKind) -> u64 {
+        self.held[kind as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        BufferKind::ALL.iter().map(|kind| self.held(*kind)).sum()
+    }
+
+    /// Counts `bytes` as held in `kind`, until [MemoryGuard::release]d. Prefer [MemoryGuard::hold] unless buffers are freed piecemeal
+    pub fn add(&self, kind: BufferKind, bytes: usize) {
+        self.held[kind as usize].fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn release(&self, kind: BufferKind, bytes: usize) {
+        self.held[kind as usize].fetch_sub(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Counts `bytes` as held in `kind` until the returned [HeldBytes] is dropped
+    pub fn hold(self: &Arc<Self>, kind: BufferKind, bytes: usize) -> HeldBytes {
+        self.add(kind, bytes);
+        HeldBytes {
+            guard: self.clone(),
+            kind,
+            bytes,
+        }
+    }
+
+    /// Returns true while new packets should be dropped at ingress,
+    /// from when usage exceeds the limit until it falls below [LOW_WATER_PERCENT] of it
+    pub fn should_drop(&self) -> bool {
+        let Some(max_bytes) = self.max_bytes() else {
+            return false;
+        };
+        let total = self.total();
+        if self.dropping.load(Ordering::Relaxed) {
+            if total >= max_bytes * LOW_WATER_PERCENT / 100 {
+                return true;
+            }
+            if self
+                .dropping
+                .compare_exchange(true, false, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                info!("Buffered bytes fell to {total}, accepting packets again.");
+            }
+            false
+        } else if total > max_bytes {
+            if self
+                .dropping
+                .compare_exchange(false, true, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+            {
+                warn!("Buffered bytes reached {total}, over --max-buffered-bytes {max_bytes}. Dropping received packets until they fall below {LOW_WATER_PERCENT}% of it.");
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Bytes counted in a [MemoryGuard] until dropped. Travels with the buffer it accounts for
+#[derive(Debug)]
+pub struct HeldBytes {
+    guard: Arc<MemoryGuard>,
+    kind: BufferKind,
+    bytes: usize,
+}
+
+impl Drop for HeldBytes {
+    fn drop(&mut self) {
+        self.guard.release(self.kind, self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::memory_guard::{BufferKind, MemoryGuard};
+
+    #[test]
+    fn test_memory_guard_hysteresis() {
+        let guard = Arc::new(MemoryGuard::default());
+        let unlimited = guard.hold(BufferKind::Receive, 1_000_000);
+        assert!(!guard.should_drop());
+        drop(unlimited);
+
+        guard.set_max_bytes(Some(1_000));
+        let receive = guard.hold(BufferKind::Receive, 600);
+        let pcap = guard.hold(BufferKind::Pcap, 300);
+        assert_eq!(guard.held(BufferKind::Receive), 600);
+        assert_eq!(guard.total(), 900);
+        assert!(!guard.should_drop());
+
+        let kafka = guard.hold(BufferKind::Kafka, 200);
+        assert!(guard.should_drop());
+        // still over the low-water mark
+        drop(kafka);
+        assert_eq!(guard.total(), 900);
+        assert!(guard.should_drop());
+        drop(pcap);
+        assert!(!guard.should_drop());
+        drop(receive);
+        assert_eq!(guard.total(), 0);
+    }
+}
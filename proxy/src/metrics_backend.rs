@@ -0,0 +1,344 @@
+use std::{
+    io::{self, Error, ErrorKind},
+    iter,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::{Arc, RwLock},
+};
+
+use log::{debug, Level};
+use solana_metrics::datapoint::DataPoint;
+
+/// Where datapoints are sent, see `--metrics-backend`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricsBackendKind {
+    /// Influx, configured with `SOLANA_METRICS_CONFIG`
+    #[default]
+    Influx,
+    /// StatsD over UDP to `--statsd-addr`
+    Statsd,
+    /// Datapoints are dropped
+    None,
+}
+
+/// How StatsD metric lines are formatted, see `--statsd-dialect`
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsdDialect {
+    /// Plain StatsD has no tags, so tag values are folded into the metric name
+    #[default]
+    Statsd,
+    /// DogStatsD `|#tag:value` tags
+    Dogstatsd,
+}
+
+/// Receives every datapoint reported with this crate's `datapoint_*` macros
+pub trait MetricsBackend: Send + Sync {
+    fn submit(&self, point: DataPoint, level: Level);
+
+    /// Blocks until datapoints submitted so far are sent, for backends that batch them
+    fn flush(&self) {}
+}
+
+/// Submits to Influx through [solana_metrics], the default
+pub struct InfluxMetrics;
+
+impl MetricsBackend for InfluxMetrics {
+    fn submit(&self, point: DataPoint, level: Level) {
+        solana_metrics::submit(point, level);
+    }
+
+    fn flush(&self) {
+        solana_metrics::flush();
+    }
+}
+
+/// Drops datapoints, so nothing is queued for an Influx that isn't configured
+pub struct NoMetrics;
+
+impl MetricsBackend for NoMetrics {
+    fn submit(&self, _point: DataPoint, _level: Level) {}
+}
+
+/// Kept under typical path MTU so datagrams aren't fragmented
+const MAX_STATSD_DATAGRAM_BYTES: usize = 1_432;
+
+/// Sends each datapoint field as a StatsD gauge named `<datapoint>.<field>`, eg. `shredstream_proxy.destination_stats.success_forward`.
+/// Gauges hold the value Influx would get, so per-interval counters keep their meaning. String fields are skipped
+pub struct StatsdMetrics {
+    socket: UdpSocket,
+    addr: SocketAddr,
+    dialect: StatsdDialect,
+}
+
+impl StatsdMetrics {
+    /// Resolves `hostname_port`, sending to its first address
+    pub fn new(hostname_port: &str, dialect: StatsdDialect) -> io::Result<Self> {
+        let addr = hostname_port.to_socket_addrs()?.next().ok_or_else(|| {
+            Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("{hostname_port} has no addresses"),
+            )
+        })?;
+        let socket = match addr {
+            SocketAddr::V4(_) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            SocketAddr::V6(_) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+        };
+        // a slow or missing collector never blocks the thread reporting
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            addr,
+            dialect,
+        })
+    }
+
+    fn send(&self, datagram: &str) {
+        if let Err(e) = self.socket.send_to(datagram.as_bytes(), self.addr) {
+            debug!(
+                "Failed to send metrics to StatsD at {}. Error: {e}",
+                self.addr
+            );
+        }
+    }
+}
+
+impl MetricsBackend for StatsdMetrics {
+    fn submit(&self, point: DataPoint, _level: Level) {
+        let mut datagram = String::new();
+        for line in statsd_lines(&point, self.dialect) {
+            if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_STATSD_DATAGRAM_BYTES {
+                self.send(&datagram);
+                datagram.clear();
+            }
+            if !datagram.is_empty() {
+                datagram.push('\n');
+            }
+            datagram.push_str(&line);
+        }
+        if !datagram.is_empty() {
+            self.send(&datagram);
+        }
+    }
+}
+
+/// Formats `point`'s numeric and bool fields as StatsD gauges
+fn statsd_lines(point: &DataPoint, dialect: StatsdDialect) -> Vec<String> {
+    let prefix = point.name.replace('-', ".");
+    point
+        .fields
+        .iter()
+        .filter_map(|(field, value)| {
+            let value = statsd_value(value)?;
+            Some(match dialect {
+                StatsdDialect::Statsd => {
+                    let name = iter::once(prefix.clone())
+                        .chain(
+                            point
+                                .tags
+                                .iter()
+                                .map(|(_, tag_value)| statsd_name_part(tag_value)),
+                        )
+                        .chain(iter::once(field.to_string()))
+                        .collect::<Vec<_>>()
+                        .join(".");
+                    format!("{name}:{value}|g")
+                }
+                StatsdDialect::Dogstatsd if point.tags.is_empty() => {
+                    format!("{prefix}.{field}:{value}|g")
+                }
+                StatsdDialect::Dogstatsd => {
+                    let tags = point
+                        .tags
+                        .iter()
+                        .map(|(tag, tag_value)| {
+                            format!("{tag}:{}", tag_value.replace([',', '|', '#'], "_"))
+                        })
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    format!("{prefix}.{field}:{value}|g|#{tags}")
+                }
+            })
+        })
+        .collect()
+}
+
+/// Converts an Influx line protocol field value to a StatsD value, None for strings and non-finite floats
+fn statsd_value(value: &str) -> Option<String> {
+    match value {
+        "true" => Some("1".to_string()),
+        "false" => Some("0".to_string()),
+        _ => match value.strip_suffix('i') {
+            Some(int) => int.parse::<i64>().ok().map(|int| int.to_string()),
+            None => value
+                .parse::<f64>()
+                .ok()
+                .filter(|float| float.is_finite())
+                .map(|float| float.to_string()),
+        },
+    }
+}
+
+/// StatsD names are dot separated, so dots and colons in tag values, eg. addresses, can't be kept
+fn statsd_name_part(tag_value: &str) -> String {
+    tag_value
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+/// Backend for `--metrics-backend`, None sends to Influx
+static METRICS_BACKEND: RwLock<Option<Arc<dyn MetricsBackend>>> = RwLock::new(None);
+
+/// Returns the backend for `--metrics-backend`. Errors if `--statsd-addr` can't be resolved
+pub fn new_metrics_backend(
+    kind: MetricsBackendKind,
+    statsd_addr: Option<&str>,
+    statsd_dialect: StatsdDialect,
+) -> io::Result<Arc<dyn MetricsBackend>> {
+    Ok(match (kind, statsd_addr) {
+        (MetricsBackendKind::Influx, _) => Arc::new(InfluxMetrics),
+        (MetricsBackendKind::Statsd, Some(statsd_addr)) => {
+            Arc::new(StatsdMetrics::new(statsd_addr, statsd_dialect)?)
+        }
+        (MetricsBackendKind::Statsd, None) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the statsd backend requires a StatsD address",
+            ))
+        }
+        (MetricsBackendKind::None, _) => Arc::new(NoMetrics),
+    })
+}
+
+/// Sends datapoints reported from now on to `backend`, process wide
+pub fn set_metrics_backend(backend: Arc<dyn MetricsBackend>) {
+    *METRICS_BACKEND.write().unwrap() = Some(backend);
+}
+
+/// Submits `point` to the backend set with [set_metrics_backend]
+pub fn submit(point: DataPoint, level: Level) {
+    let backend = METRICS_BACKEND.read().unwrap().clone();
+    match backend {
+        Some(backend) => backend.submit(point, level),
+        None => solana_metrics::submit(point, level),
+    }
+}
+
+/// Flushes the backend set with [set_metrics_backend], such as before the process exits
+pub fn flush() {
+    let backend = METRICS_BACKEND.read().unwrap().clone();
+    match backend {
+        Some(backend) => backend.flush(),
+        None => solana_metrics::flush(),
+    }
+}
+
+/// Like [solana_metrics::datapoint], but submitted to the backend set with [set_metrics_backend]
+macro_rules! datapoint {
+    ($level:expr, $name:expr, $($fields:tt)+) => {
+        if log::log_enabled!($level) {
+            $crate::metrics_backend::submit(
+                solana_metrics::create_datapoint!(@point $name, $($fields)+),
+                $level,
+            );
+        }
+    };
+}
+
+macro_rules! datapoint_error {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics_backend::datapoint!(log::Level::Error, $name, $($fields)+);
+    };
+}
+
+macro_rules! datapoint_warn {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics_backend::datapoint!(log::Level::Warn, $name, $($fields)+);
+    };
+}
+
+macro_rules! datapoint_info {
+    ($name:expr, $($fields:tt)+) => {
+        $crate::metrics_backend::datapoint!(log::Level::Info, $name, $($fields)+);
+    };
+}
+
+pub(crate) use {datapoint, datapoint_error, datapoint_info, datapoint_warn};
+
+#[cfg(test)]
+mod tests {
+    use std::{net::UdpSocket, time::Duration};
+
+    use log::Level;
+    use solana_metrics::datapoint::DataPoint;
+
+    use crate::metrics_backend::{statsd_lines, MetricsBackend, StatsdDialect, StatsdMetrics};
+
+    fn destination_point() -> DataPoint {
+        let mut point = DataPoint::new("shredstream_proxy-destination_stats");
+        point
+            .add_tag("addr", "1.2.3.4:8001")
+            .add_field_i64("success_forward", 10)
+            .add_field_f64("ratio", 0.5)
+            .add_field_bool("healthy", true)
+            .add_field_str("region", "ny");
+        point
+    }
+
+    #[test]
+    fn test_statsd_lines() {
+        assert_eq!(
+            statsd_lines(&destination_point(), StatsdDialect::Statsd),
+            vec![
+                "shredstream_proxy.destination_stats.1_2_3_4_8001.success_forward:10|g",
+                "shredstream_proxy.destination_stats.1_2_3_4_8001.ratio:0.5|g",
+                "shredstream_proxy.destination_stats.1_2_3_4_8001.healthy:1|g",
+            ]
+        );
+        assert_eq!(
+            statsd_lines(&destination_point(), StatsdDialect::Dogstatsd),
+            vec![
+                "shredstream_proxy.destination_stats.success_forward:10|g|#addr:1.2.3.4:8001",
+                "shredstream_proxy.destination_stats.ratio:0.5|g|#addr:1.2.3.4:8001",
+                "shredstream_proxy.destination_stats.healthy:1|g|#addr:1.2.3.4:8001",
+            ]
+        );
+        let mut untagged = DataPoint::new("shredstream_proxy-connection_metrics");
+        untagged.add_field_i64("agg_received", 3);
+        assert_eq!(
+            statsd_lines(&untagged, StatsdDialect::Dogstatsd),
+            vec!["shredstream_proxy.connection_metrics.agg_received:3|g"]
+        );
+    }
+
+    #[test]
+    fn test_statsd_metrics_splits_datagrams() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        collector
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let statsd = StatsdMetrics::new(
+            &collector.local_addr().unwrap().to_string(),
+            StatsdDialect::Statsd,
+        )
+        .unwrap();
+
+        let mut point = DataPoint::new("shredstream_proxy-connection_metrics");
+        for _ in 0..100 {
+            point.add_field_i64("agg_received", 1_000);
+        }
+        statsd.submit(point, Level::Info);
+        let mut buf = [0u8; 2048];
+        let mut num_lines = 0;
+        while num_lines < 100 {
+            let len = collector.recv(&mut buf).unwrap();
+            assert!(len <= super::MAX_STATSD_DATAGRAM_BYTES);
+            num_lines += std::str::from_utf8(&buf[..len]).unwrap().lines().count();
+        }
+        assert_eq!(num_lines, 100);
+    }
+}
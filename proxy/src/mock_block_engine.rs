@@ -0,0 +1,336 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use jito_protos::{
+    auth::{
+        auth_service_server::{AuthService, AuthServiceServer},
+        GenerateAuthChallengeRequest, GenerateAuthChallengeResponse, GenerateAuthTokensRequest,
+        GenerateAuthTokensResponse, RefreshAccessTokenRequest, RefreshAccessTokenResponse, Token,
+    },
+    shredstream::{
+        shredstream_server::{Shredstream, ShredstreamServer},
+        Heartbeat, HeartbeatResponse,
+    },
+};
+use log::{info, warn};
+use rand::Rng;
+use solana_sdk::{clock::Slot, pubkey::Pubkey, signature::Signature};
+use solana_streamer::sendmmsg::batch_send;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::{transport::Server, Request, Response, Status};
+
+use crate::{shred, socket};
+
+/// Heartbeats keep an address registered this long, the proxy heartbeats every third of it
+const HEARTBEAT_TTL: Duration = Duration::from_secs(3);
+/// Access tokens expire after this long
+const ACCESS_TOKEN_TTL: Duration = Duration::from_secs(30 * 60);
+/// Refresh tokens expire after this long, outliving any local session
+const REFRESH_TOKEN_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// Size of each synthetic shred, including the headers
+const SHRED_PAYLOAD_SIZE: usize = 1_228;
+/// Synthetic shreds start at this slot, far enough from zero to look like mainnet
+const FIRST_SLOT: Slot = 300_000_000;
+/// Synthetic shreds per slot
+const SHREDS_PER_SLOT: u64 = 1_000;
+/// How often shreds are sent, each time catching up to the rate
+const SEND_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(clap::Args, Clone, Debug)]
+pub struct MockBlockEngineArgs {
+    /// Address to serve the auth and shredstream gRPC services on. Point the proxy's `block-engine-url` at it, eg. `http://127.0.0.1:1005`.
+    #[arg(long, env, default_value = "127.0.0.1:1005")]
+    pub bind_addr: SocketAddr,
+
+    /// Synthetic shreds per second sent to each address registered by a heartbeat.
+    #[arg(long, env, default_value_t = 1_000)]
+    pub pps: u64,
+}
+
+/// Tokens issued and addresses registered, shared by the gRPC services and the shred sender
+#[derive(Default)]
+struct MockState {
+    access_tokens: Mutex<HashSet<String>>,
+    refresh_tokens: Mutex<HashSet<String>>,
+    /// Addresses from heartbeats, with when they expire
+    registered: Mutex<HashMap<SocketAddr, Instant>>,
+    heartbeats: AtomicU64,
+    shreds_sent: AtomicU64,
+}
+
+impl MockState {
+    fn new_token(tokens: &Mutex<HashSet<String>>, kind: &str, ttl: Duration) -> Token {
+        let value = format!("mock-{kind}-{:016x}", rand::thread_rng().gen::<u64>());
+        tokens.lock().unwrap().insert(value.clone());
+        Token {
+            value,
+            expires_at_utc: Some((SystemTime::now() + ttl).into()),
+        }
+    }
+
+    /// Returns the addresses registered by unexpired heartbeats, forgetting expired ones
+    fn live_registrations(&self, now: Instant) -> Vec<SocketAddr> {
+        let mut registered = self.registered.lock().unwrap();
+        registered.retain(|_addr, expires_at| *expires_at > now);
+        registered.keys().copied().collect()
+    }
+}
+
+/// Handle to a running mock block engine, for checking what the proxy did
+pub struct MockBlockEngine {
+    local_addr: SocketAddr,
+    state: Arc<MockState>,
+}
+
+impl MockBlockEngine {
+    /// Address the gRPC services listen on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Addresses registered by unexpired heartbeats
+    pub fn registered(&self) -> Vec<SocketAddr> {
+        self.state.live_registrations(Instant::now())
+    }
+
+    /// Authenticated heartbeats received
+    pub fn heartbeats(&self) -> u64 {
+        self.state.heartbeats.load(Ordering::Relaxed)
+    }
+
+    /// Synthetic shreds sent across all registered addresses
+    pub fn shreds_sent(&self) -> u64 {
+        self.state.shreds_sent.load(Ordering::Relaxed)
+    }
+}
+
+struct MockAuthService {
+    state: Arc<MockState>,
+}
+
+#[tonic::async_trait]
+impl AuthService for MockAuthService {
+    async fn generate_auth_challenge(
+        &self,
+        _request: Request<GenerateAuthChallengeRequest>,
+    ) -> Result<Response<GenerateAuthChallengeResponse>, Status> {
+        Ok(Response::new(GenerateAuthChallengeResponse {
+            challenge: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+        }))
+    }
+
+    /// Accepts any keypair, as long as it signed `<pubkey>-<challenge>` like the real block engine expects
+    async fn generate_auth_tokens(
+        &self,
+        request: Request<GenerateAuthTokensRequest>,
+    ) -> Result<Response<GenerateAuthTokensResponse>, Status> {
+        let request = request.into_inner();
+        let pubkey = Pubkey::try_from(request.client_pubkey.as_slice())
+            .map_err(|_| Status::invalid_argument("Invalid client pubkey."))?;
+        let signature = Signature::try_from(request.signed_challenge.as_slice())
+            .map_err(|_| Status::invalid_argument("Invalid signed challenge."))?;
+        if !request.challenge.starts_with(&format!("{pubkey}-"))
+            || !signature.verify(pubkey.as_ref(), request.challenge.as_bytes())
+        {
+            return Err(Status::unauthenticated(
+                "Challenge signature doesn't match.",
+            ));
+        }
+        info!("Issued tokens to {pubkey}.");
+        Ok(Response::new(GenerateAuthTokensResponse {
+            access_token: Some(MockState::new_token(
+                &self.state.access_tokens,
+                "access",
+                ACCESS_TOKEN_TTL,
+            )),
+            refresh_token: Some(MockState::new_token(
+                &self.state.refresh_tokens,
+                "refresh",
+                REFRESH_TOKEN_TTL,
+            )),
+        }))
+    }
+
+    async fn refresh_access_token(
+        &self,
+        request: Request<RefreshAccessTokenRequest>,
+    ) -> Result<Response<RefreshAccessTokenResponse>, Status> {
+        if !self
+            .state
+            .refresh_tokens
+            .lock()
+            .unwrap()
+            .contains(&request.get_ref().refresh_token)
+        {
+            return Err(Status::unauthenticated("Unknown refresh token."));
+        }
+        Ok(Response::new(RefreshAccessTokenResponse {
+            access_token: Some(MockState::new_token(
+                &self.state.access_tokens,
+                "access",
+                ACCESS_TOKEN_TTL,
+            )),
+        }))
+    }
+}
+
+struct MockShredstreamService {
+    state: Arc<MockState>,
+}
+
+#[tonic::async_trait]
+impl Shredstream for MockShredstreamService {
+    /// Registers the heartbeat's socket for synthetic shreds, requiring an access token issued by [MockAuthService]
+    async fn send_heartbeat(
+        &self,
+        request: Request<Heartbeat>,
+    ) -> Result<Response<HeartbeatResponse>, Status> {
+        let authorized = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| self.state.access_tokens.lock().unwrap().contains(token));
+        if !authorized {
+            return Err(Status::unauthenticated("Missing or unknown access token."));
+        }
+        let heartbeat = request.into_inner();
+        let addr = heartbeat
+            .socket
+            .as_ref()
+            .and_then(|socket| {
+                Some(SocketAddr::new(
+                    IpAddr::from_str(&socket.ip).ok()?,
+                    u16::try_from(socket.port).ok()?,
+                ))
+            })
+            .ok_or_else(|| Status::invalid_argument("Invalid heartbeat socket."))?;
+        let expires_at = Instant::now() + HEARTBEAT_TTL;
+        if self
+            .state
+            .registered
+            .lock()
+            .unwrap()
+            .insert(addr, expires_at)
+            .is_none()
+        {
+            info!(
+                "Registered {addr} for regions {:?}, sending synthetic shreds.",
+                heartbeat.regions
+            );
+        }
+        self.state.heartbeats.fetch_add(1, Ordering::Relaxed);
+        Ok(Response::new(HeartbeatResponse {
+            ttl_ms: HEARTBEAT_TTL.as_millis() as u32,
+        }))
+    }
+}
+
+/// Serves the auth and heartbeat services of a block engine on `args.bind_addr`, accepting any keypair,
+/// and sends synthetic data shreds at `args.pps` to every address registered by a heartbeat. For local development and tests only
+pub fn start_mock_block_engine(
+    args: &MockBlockEngineArgs,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(MockBlockEngine, Vec<JoinHandle<()>>)> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("ssPxyMockRt")
+        .enable_all()
+        .build()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(args.bind_addr))?;
+    let local_addr = listener.local_addr()?;
+    info!("Serving mock block engine on http://{local_addr}");
+
+    let state = Arc::new(MockState::default());
+    let auth_service = MockAuthService {
+        state: state.clone(),
+    };
+    let shredstream_service = MockShredstreamService {
+        state: state.clone(),
+    };
+    let grpc_exit = exit.clone();
+    let grpc_hdl = Builder::new()
+        .name("ssPxyMockGrpc".to_string())
+        .spawn(move || {
+            let shutdown = async move {
+                // periodically check for exit
+                while !grpc_exit.load(Ordering::Relaxed) {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            };
+            // other services, eg. listing regions, answer unimplemented which the proxy tolerates
+            if let Err(e) = runtime.block_on(
+                Server::builder()
+                    .add_service(AuthServiceServer::new(auth_service))
+                    .add_service(ShredstreamServer::new(shredstream_service))
+                    .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown),
+            ) {
+                warn!("Mock block engine gRPC server exited with error: {e}");
+            }
+            info!("Exiting mock block engine gRPC thread.");
+        })?;
+
+    let send_socket = socket::bind_send_socket()?;
+    let ipv6_socket = send_socket.local_addr()?.is_ipv6();
+    let sender_state = state.clone();
+    let pps = args.pps;
+    let sender_hdl = Builder::new()
+        .name("ssPxyMockShreds".to_string())
+        .spawn(move || {
+            let mut packet = vec![0u8; SHRED_PAYLOAD_SIZE];
+            let mut seq = 0u64;
+            let start = Instant::now();
+            while !exit.load(Ordering::Relaxed) {
+                thread::sleep(SEND_INTERVAL);
+                let due = (start.elapsed().as_secs_f64() * pps as f64) as u64;
+                let registered = sender_state.live_registrations(Instant::now());
+                // shreds owed while nothing was registered are skipped rather than sent in a burst
+                if registered.is_empty() {
+                    seq = due;
+                    continue;
+                }
+                let packets = (seq..due)
+                    .map(|seq| {
+                        shred::write_data_shred_headers(
+                            &mut packet,
+                            FIRST_SLOT + seq / SHREDS_PER_SLOT,
+                            (seq % SHREDS_PER_SLOT) as u32,
+                        );
+                        packet.clone()
+                    })
+                    .collect::<Vec<_>>();
+                seq = due;
+                for addr in registered {
+                    let send_addr = socket::send_addr(ipv6_socket, addr);
+                    let packets_with_dest = packets
+                        .iter()
+                        .map(|packet| (packet.as_slice(), &send_addr))
+                        .collect::<Vec<_>>();
+                    match batch_send(&send_socket, &packets_with_dest) {
+                        Ok(()) => {
+                            sender_state
+                                .shreds_sent
+                                .fetch_add(packets.len() as u64, Ordering::Relaxed);
+                        }
+                        Err(e) => warn!("Failed to send synthetic shreds to {addr}. Error: {e:?}"),
+                    }
+                }
+            }
+            info!("Exiting mock block engine shred sender thread.");
+        })?;
+
+    Ok((
+        MockBlockEngine { local_addr, state },
+        vec![grpc_hdl, sender_hdl],
+    ))
+}
@@ -0,0 +1,172 @@
+//! Spans for slow-path control-plane operations: authentication, heartbeats, discovery, and destination resolution.
+//! Logged at debug level, and exported to an OpenTelemetry collector with `--otlp-endpoint` when built with the `otel` feature.
+//! Nothing on the packet path creates spans.
+
+use std::{fmt::Display, future::Future, time::Instant};
+
+use tracing::{Instrument, Span};
+
+/// `debug_span!` for a control-plane operation, also declaring the `result` and `duration_ms` fields [traced] records
+macro_rules! control_span {
+    ($name:literal $(, $($fields:tt)+)?) => {
+        tracing::debug_span!(
+            $name,
+            $($($fields)+,)?
+            result = tracing::field::Empty,
+            duration_ms = tracing::field::Empty
+        )
+    };
+}
+
+pub(crate) use control_span;
+
+/// Runs `f` inside `span`, then records its result and duration
+pub fn traced<T, E: Display>(span: Span, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let start = Instant::now();
+    let result = span.in_scope(f);
+    record_result(&span, &result, start);
+    result
+}
+
+/// Async [traced]
+pub async fn traced_async<T, E: Display>(
+    span: Span,
+    future: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let start = Instant::now();
+    let result = future.instrument(span.clone()).await;
+    record_result(&span, &result, start);
+    result
+}
+
+fn record_result<T, E: Display>(span: &Span, result: &Result<T, E>, start: Instant) {
+    match result {
+        Ok(_) => span.record("result", "ok"),
+        Err(e) => span.record("result", e.to_string().as_str()),
+    };
+    span.record("duration_ms", start.elapsed().as_secs_f64() * 1_000.0);
+}
+
+#[cfg(feature = "otel")]
+pub use exporter::start_otlp_exporter_thread;
+
+/// Without the `otel` feature, `otlp-endpoint` is rejected when validating args
+#[cfg(not(feature = "otel"))]
+pub fn start_otlp_exporter_thread(
+    _endpoint: &str,
+    _shutdown_receiver: crossbeam_channel::Receiver<()>,
+    _exit: std::sync::Arc<std::sync::atomic::AtomicBool>,
+) -> Result<std::thread::JoinHandle<()>, crate::ShredstreamProxyError> {
+    Err(crate::ShredstreamProxyError::InvalidArguments(
+        "Invalid arguments provided, --otlp-endpoint requires building with the `otel` feature."
+            .to_string(),
+    ))
+}
+
+#[cfg(feature = "otel")]
+mod exporter {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    };
+
+    use crossbeam_channel::Receiver;
+    use log::{info, warn};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{runtime, trace, Resource};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::ShredstreamProxyError;
+
+    /// How often the exporter thread wakes to check for exit
+    const EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// Installs a global `tracing` subscriber exporting spans in batches over OTLP gRPC to `endpoint`.
+    /// Queued spans are flushed on exit
+    pub fn start_otlp_exporter_thread(
+        endpoint: &str,
+        shutdown_receiver: Receiver<()>,
+        exit: Arc<AtomicBool>,
+    ) -> Result<JoinHandle<()>, ShredstreamProxyError> {
+        // the batch processor and its gRPC client run on this runtime, off the forwarding threads
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("ssPxyOtlpRt")
+            .enable_all()
+            .build()?;
+        let tracer = {
+            let _guard = runtime.enter();
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(
+                    opentelemetry_otlp::new_exporter()
+                        .tonic()
+                        .with_endpoint(endpoint),
+                )
+                .with_trace_config(trace::config().with_resource(Resource::new(vec![
+                    KeyValue::new("service.name", "shredstream_proxy"),
+                ])))
+                .install_batch(runtime::Tokio)
+                .map_err(|e| {
+                    ShredstreamProxyError::InvalidArguments(format!(
+                        "Failed to create OTLP exporter for --otlp-endpoint {endpoint}: {e}"
+                    ))
+                })?
+        };
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+        match tracing::subscriber::set_global_default(subscriber) {
+            Ok(()) => info!("Exporting control-plane spans to {endpoint}."),
+            // embedding processes may have installed their own
+            Err(e) => warn!("Not exporting spans to {endpoint}, a tracing subscriber is already installed. Error: {e}"),
+        }
+
+        Ok(Builder::new()
+            .name("ssPxyOtlp".to_string())
+            .spawn(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    let _ = shutdown_receiver.recv_timeout(EXIT_CHECK_INTERVAL);
+                }
+                // flushes queued spans, needs the runtime still running
+                opentelemetry::global::shutdown_tracer_provider();
+                drop(runtime);
+            })?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::otel::{control_span, traced, traced_async};
+
+    #[test]
+    fn test_traced() {
+        assert_eq!(
+            traced(control_span!("test_ok", region = "ny"), || Ok::<_, String>(
+                1
+            )),
+            Ok(1)
+        );
+        assert_eq!(
+            traced(control_span!("test_err"), || Err::<(), _>(
+                "failed".to_string()
+            )),
+            Err("failed".to_string())
+        );
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(
+            runtime.block_on(traced_async(
+                control_span!("test_async", url = "http://127.0.0.1"),
+                async { Ok::<_, String>(2) }
+            )),
+            Ok(2)
+        );
+    }
+}
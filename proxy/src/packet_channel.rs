@@ -0,0 +1,181 @@
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::SystemTime,
+};
+
+use crossbeam_channel::{Receiver, SendError, Sender, TrySendError};
+use solana_perf::packet::PacketBatch;
+
+use crate::{forwarder::ShredMetrics, memory_guard::HeldBytes};
+
+/// Default batches queued per receive thread. Shreds are a few KB, so this caps each queue in the tens of MB
+pub const DEFAULT_FORWARDER_CHANNEL_CAPACITY: usize = 1024;
+
+/// Packets read from a socket together, passed from a receive thread to a forwarder thread
+pub struct ReceivedBatch {
+    pub packets: PacketBatch,
+    /// Kernel receive timestamp per packet, only read with `--measure-internal-latency`
+    pub rx_timestamps: Option<Vec<Option<SystemTime>>>,
+    /// Counts the batch's buffer in [crate::memory_guard::MemoryGuard] until forwarded
+    pub held: Option<HeldBytes>,
+}
+
+impl From<PacketBatch> for ReceivedBatch {
+    fn from(packets: PacketBatch) -> Self {
+        Self {
+            packets,
+            rx_timestamps: None,
+            held: None,
+        }
+    }
+}
+
+/// What a receive thread does when its forwarder's channel is full
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DropPolicy {
+    /// Drop the oldest queued batch to make room, since stale shreds are worthless
+    #[default]
+    DropOldest,
+    /// Drop the batch just received
+    DropNewest,
+    /// Wait for the forwarder to catch up, leaving the kernel to drop packets once the socket buffer fills
+    Block,
+}
+
+/// Sending half of a bounded channel from a receive thread to a forwarder thread.
+/// Applies the [DropPolicy] when full, counting drops in [ShredMetrics]. Cloned for each receive thread sharing the channel
+#[derive(Clone)]
+pub struct PacketBatchSender {
+    sender: Sender<ReceivedBatch>,
+    /// Only kept to pop the oldest batch with [DropPolicy::DropOldest]
+    receiver: Option<Receiver<ReceivedBatch>>,
+    drop_policy: DropPolicy,
+    metrics: Arc<ShredMetrics>,
+}
+
+pub fn bounded(
+    capacity: usize,
+    drop_policy: DropPolicy,
+    metrics: Arc<ShredMetrics>,
+) -> (PacketBatchSender, Receiver<ReceivedBatch>) {
+    let (sender, receiver) = crossbeam_channel::bounded(capacity);
+    let packet_sender = PacketBatchSender {
+        sender,
+        receiver: (drop_policy == DropPolicy::DropOldest).then(|| receiver.clone()),
+        drop_policy,
+        metrics,
+    };
+    (packet_sender, receiver)
+}
+
+impl PacketBatchSender {
+    /// Returns an error once the forwarder thread has exited.
+    /// With [DropPolicy::DropOldest], the sender keeps the channel open, so it never errors
+    pub fn send(&self, packet_batch: ReceivedBatch) -> Result<(), SendError<ReceivedBatch>> {
+        if self.drop_policy == DropPolicy::Block {
+            return self.sender.send(packet_batch);
+        }
+        let mut packet_batch = packet_batch;
+        loop {
+            match self.sender.try_send(packet_batch) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Disconnected(packet_batch)) => {
+                    return Err(SendError(packet_batch))
+                }
+                Err(TrySendError::Full(full_batch)) => match &self.receiver {
+                    Some(receiver) => {
+                        // the forwarder may have drained the channel in the meantime, in which case nothing is dropped
+                        if let Ok(oldest) = receiver.try_recv() {
+                            self.record_drop(&oldest);
+                        }
+                        packet_batch = full_batch;
+                    }
+                    None => {
+                        self.record_drop(&full_batch);
+                        return Ok(());
+                    }
+                },
+            }
+        }
+    }
+
+    /// Batches currently queued
+    pub fn num_queued(&self) -> usize {
+        self.sender.len()
+    }
+
+    fn record_drop(&self, packet_batch: &ReceivedBatch) {
+        self.metrics
+            .channel_dropped_batches
+            .fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .channel_dropped_packets
+            .fetch_add(packet_batch.packets.len() as u64, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::Ordering, Arc};
+
+    use solana_perf::packet::{Packet, PacketBatch};
+
+    use crate::{
+        forwarder::ShredMetrics,
+        packet_channel::{bounded, DropPolicy, ReceivedBatch},
+    };
+
+    fn batch(num_packets: usize) -> ReceivedBatch {
+        PacketBatch::new(vec![Packet::default(); num_packets]).into()
+    }
+
+    #[test]
+    fn test_drop_policies() {
+        for (drop_policy, expected_queued) in [
+            (DropPolicy::DropOldest, vec![2, 3]),
+            (DropPolicy::DropNewest, vec![1, 2]),
+        ] {
+            let metrics = Arc::new(ShredMetrics::new());
+            let (sender, receiver) = bounded(2, drop_policy, metrics.clone());
+            for num_packets in 1..=3 {
+                sender.send(batch(num_packets)).unwrap();
+            }
+            assert_eq!(sender.num_queued(), 2);
+            let queued = receiver
+                .try_iter()
+                .map(|packet_batch| packet_batch.packets.len())
+                .collect::<Vec<_>>();
+            assert_eq!(queued, expected_queued, "{drop_policy:?}");
+            assert_eq!(metrics.channel_dropped_batches.load(Ordering::Relaxed), 1);
+            assert_eq!(
+                metrics.channel_dropped_packets.load(Ordering::Relaxed),
+                if drop_policy == DropPolicy::DropOldest {
+                    1
+                } else {
+                    3
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_block_policy() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let (sender, receiver) = bounded(1, DropPolicy::Block, metrics.clone());
+        sender.send(batch(1)).unwrap();
+        // blocks until the forwarder makes room
+        let send_thread = std::thread::spawn(move || {
+            sender.send(batch(2)).unwrap();
+            sender
+        });
+        assert_eq!(receiver.recv().unwrap().packets.len(), 1);
+        let sender = send_thread.join().unwrap();
+        assert_eq!(receiver.recv().unwrap().packets.len(), 2);
+        assert_eq!(metrics.channel_dropped_batches.load(Ordering::Relaxed), 0);
+
+        // errors once the forwarder exits
+        drop(receiver);
+        assert!(sender.send(batch(1)).is_err());
+    }
+}
@@ -0,0 +1,716 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{RecvTimeoutError, Sender, TrySendError};
+use log::{error, info, warn};
+use solana_perf::packet::PacketBatch;
+
+use crate::{
+    forwarder::ShredMetrics,
+    memory_guard::{BufferKind, HeldBytes},
+    metrics_backend::datapoint_warn,
+};
+
+/// Pcap magic for nanosecond timestamps
+pub const PCAP_MAGIC_NANOS: u32 = 0xa1b2_3c4d;
+/// Pcap magic for microsecond timestamps, the tcpdump default
+pub const PCAP_MAGIC_MICROS: u32 = 0xa1b2_c3d4;
+/// Section header block type, the first bytes of a pcapng file
+const PCAPNG_MAGIC: u32 = 0x0a0d_0d0a;
+pub const LINKTYPE_ETHERNET: u32 = 1;
+/// Raw IP packets, IPv4 or IPv6 by the version nibble
+pub const LINKTYPE_RAW: u32 = 101;
+/// Linux cooked capture, used by `tcpdump -i any`
+pub const LINKTYPE_LINUX_SLL: u32 = 113;
+pub const LINKTYPE_IPV4: u32 = 228;
+pub const LINKTYPE_IPV6: u32 = 229;
+pub const PCAP_FILE_HEADER_LEN: usize = 24;
+pub const PCAP_RECORD_HEADER_LEN: usize = 16;
+const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
+const UDP_HEADER_LEN: usize = 8;
+const ETHERNET_HEADER_LEN: usize = 14;
+const LINUX_SLL_HEADER_LEN: usize = 16;
+const VLAN_TAG_LEN: usize = 4;
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IP_PROTOCOL_UDP: u8 = 17;
+const SNAPLEN: u32 = 65_535;
+/// Largest record accepted when reading, tcpdump's max snaplen
+const MAX_RECORD_LEN: u32 = 262_144;
+
+/// Packets queued for the writer before new ones are dropped
+const PCAP_CHANNEL_CAPACITY: usize = 65_536;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Packet received by the proxy, recorded as a UDP datagram from `src` to `dst`
+pub struct RecordedPacket {
+    pub received_at: SystemTime,
+    pub src: SocketAddr,
+    pub dst: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PcapRotation {
+    /// Start a new file once the current one reaches this size, if set
+    pub max_file_bytes: Option<u64>,
+    /// Start a new file once the current one is this old, if set
+    pub max_file_age: Option<Duration>,
+    /// Delete the oldest files beyond this many, if set
+    pub max_files: Option<usize>,
+}
+
+/// Queues packets for the pcap writer thread, dropping them when the writer can't keep up.
+/// One per listen socket, since packets don't carry the port they were received on.
+#[derive(Clone)]
+pub struct PcapTap {
+    packet_sender: Sender<(RecordedPacket, HeldBytes)>,
+    listen_addr: SocketAddr,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl PcapTap {
+    pub fn with_listen_addr(&self, listen_addr: SocketAddr) -> Self {
+        Self {
+            listen_addr,
+            ..self.clone()
+        }
+    }
+
+    /// Records packets that weren't discarded
+    pub fn record(&self, packet_batches: &[PacketBatch], received_at: SystemTime) {
+        packet_batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .filter_map(|packet| Some((packet.meta().socket_addr(), packet.data(..)?)))
+            .for_each(|(src, payload)| {
+                let packet = RecordedPacket {
+                    received_at,
+                    src,
+                    dst: self.listen_addr,
+                    payload: payload.to_vec(),
+                };
+                let held = self
+                    .metrics
+                    .memory_guard
+                    .hold(BufferKind::Pcap, packet.payload.len());
+                match self.packet_sender.try_send((packet, held)) {
+                    Ok(()) => {
+                        self.metrics.pcap_recorded.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                        self.metrics.pcap_dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+    }
+}
+
+/// Writes packets sent through the returned tap to `path`, readable by Wireshark.
+/// With rotation, files are named `path` with the time they were opened and a sequence number appended to the stem.
+pub fn start_pcap_writer_thread(
+    path: PathBuf,
+    rotation: PcapRotation,
+    metrics: Arc<ShredMetrics>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(PcapTap, JoinHandle<()>)> {
+    let (packet_sender, packet_receiver) = crossbeam_channel::bounded(PCAP_CHANNEL_CAPACITY);
+    let mut writer = PcapWriter::new(path, rotation)?;
+    info!("Recording received packets to {:?}", writer.file_paths[0]);
+
+    let hdl = Builder::new()
+        .name("ssPxyPcap".to_string())
+        .spawn(move || {
+            let mut last_flush = Instant::now();
+            // drain queued packets before exiting
+            while !exit.load(Ordering::Relaxed) || !packet_receiver.is_empty() {
+                match packet_receiver.recv_timeout(FLUSH_INTERVAL) {
+                    Ok((packet, _held)) => {
+                        if let Err(e) = writer.write(&packet) {
+                            error!("Failed to write pcap, stopping recording. Error: {e}");
+                            datapoint_warn!(
+                                "shredstream_proxy-pcap_error",
+                                ("errors", 1, i64),
+                                ("error_str", e.to_string(), String),
+                            );
+                            // tap counts packets as dropped once disconnected
+                            return;
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                if last_flush.elapsed() >= FLUSH_INTERVAL {
+                    if let Err(e) = writer.flush() {
+                        warn!("Failed to flush pcap. Error: {e}");
+                    }
+                    last_flush = Instant::now();
+                }
+            }
+            if let Err(e) = writer.flush() {
+                warn!("Failed to flush pcap. Error: {e}");
+            }
+        })?;
+
+    Ok((
+        PcapTap {
+            packet_sender,
+            listen_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+            metrics,
+        },
+        hdl,
+    ))
+}
+
+pub struct PcapWriter {
+    path: PathBuf,
+    rotation: PcapRotation,
+    file: BufWriter<File>,
+    file_bytes: u64,
+    file_opened: Instant,
+    /// Files written so far, oldest first, for pruning. Last is the current file
+    file_paths: Vec<PathBuf>,
+    num_files: u64,
+}
+
+impl PcapWriter {
+    pub fn new(path: PathBuf, rotation: PcapRotation) -> io::Result<Self> {
+        let file_path = rotated_file_path(&path, &rotation, SystemTime::now(), 0);
+        let (file, file_bytes) = open_pcap_file(&file_path)?;
+        Ok(Self {
+            path,
+            rotation,
+            file_paths: vec![file_path],
+            file,
+            file_bytes,
+            file_opened: Instant::now(),
+            num_files: 1,
+        })
+    }
+
+    pub fn write(&mut self, packet: &RecordedPacket) -> io::Result<()> {
+        let needs_rotation = self
+            .rotation
+            .max_file_bytes
+            .is_some_and(|max_file_bytes| self.file_bytes >= max_file_bytes)
+            || self
+                .rotation
+                .max_file_age
+                .is_some_and(|max_file_age| self.file_opened.elapsed() >= max_file_age);
+        if needs_rotation {
+            self.rotate()?;
+        }
+
+        let record = encode_record(packet);
+        self.file.write_all(&record)?;
+        self.file_bytes += record.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let file_path = rotated_file_path(
+            &self.path,
+            &self.rotation,
+            SystemTime::now(),
+            self.num_files,
+        );
+        self.num_files += 1;
+        (self.file, self.file_bytes) = open_pcap_file(&file_path)?;
+        self.file_opened = Instant::now();
+        self.file_paths.push(file_path);
+
+        if let Some(max_files) = self.rotation.max_files {
+            while self.file_paths.len() > max_files {
+                let oldest = self.file_paths.remove(0);
+                if let Err(e) = fs::remove_file(&oldest) {
+                    warn!("Failed to remove old pcap {oldest:?}. Error: {e}");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// `path` as is without rotation, otherwise with the open time in unix millis and file sequence number appended to the stem.
+/// The sequence number avoids overwriting when rotating more than once per millisecond.
+fn rotated_file_path(
+    path: &Path,
+    rotation: &PcapRotation,
+    now: SystemTime,
+    sequence: u64,
+) -> PathBuf {
+    if rotation.max_file_bytes.is_none() && rotation.max_file_age.is_none() {
+        return path.to_path_buf();
+    }
+    let millis = now
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "pcap".to_string());
+    path.with_file_name(format!("{stem}-{millis}-{sequence}.{extension}"))
+}
+
+fn open_pcap_file(path: &Path) -> io::Result<(BufWriter<File>, u64)> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut header = Vec::with_capacity(PCAP_FILE_HEADER_LEN);
+    header.extend_from_slice(&PCAP_MAGIC_NANOS.to_le_bytes());
+    header.extend_from_slice(&2u16.to_le_bytes()); // version major
+    header.extend_from_slice(&4u16.to_le_bytes()); // version minor
+    header.extend_from_slice(&0i32.to_le_bytes()); // timezone offset
+    header.extend_from_slice(&0u32.to_le_bytes()); // timestamp accuracy
+    header.extend_from_slice(&SNAPLEN.to_le_bytes());
+    header.extend_from_slice(&LINKTYPE_RAW.to_le_bytes());
+    file.write_all(&header)?;
+    Ok((file, header.len() as u64))
+}
+
+/// Record header followed by the payload wrapped in IP and UDP headers
+fn encode_record(packet: &RecordedPacket) -> Vec<u8> {
+    let datagram = encode_ip_udp(packet.src, packet.dst, &packet.payload);
+    let timestamp = packet
+        .received_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut record = Vec::with_capacity(PCAP_RECORD_HEADER_LEN + datagram.len());
+    record.extend_from_slice(&(timestamp.as_secs() as u32).to_le_bytes());
+    record.extend_from_slice(&timestamp.subsec_nanos().to_le_bytes());
+    record.extend_from_slice(&(datagram.len() as u32).to_le_bytes()); // captured length
+    record.extend_from_slice(&(datagram.len() as u32).to_le_bytes()); // original length
+    record.extend_from_slice(&datagram);
+    record
+}
+
+/// Wraps the payload in an IPv4 or IPv6 header, by source address, and a UDP header without checksum
+fn encode_ip_udp(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
+    let udp_len = UDP_HEADER_LEN + payload.len();
+    let mut datagram = Vec::with_capacity(IPV6_HEADER_LEN + udp_len);
+    match src.ip() {
+        IpAddr::V4(src_ip) => {
+            let dst_ip = match dst.ip() {
+                IpAddr::V4(dst_ip) => dst_ip,
+                IpAddr::V6(dst_ip) => dst_ip.to_ipv4_mapped().unwrap_or(Ipv4Addr::UNSPECIFIED),
+            };
+            let mut header = [0u8; IPV4_HEADER_LEN];
+            header[0] = 0x45; // version 4, 5 word header
+            header[2..4].copy_from_slice(&((IPV4_HEADER_LEN + udp_len) as u16).to_be_bytes());
+            header[6] = 0x40; // don't fragment
+            header[8] = 64; // ttl
+            header[9] = 17; // udp
+            header[12..16].copy_from_slice(&src_ip.octets());
+            header[16..20].copy_from_slice(&dst_ip.octets());
+            let checksum = ipv4_checksum(&header);
+            header[10..12].copy_from_slice(&checksum.to_be_bytes());
+            datagram.extend_from_slice(&header);
+        }
+        IpAddr::V6(src_ip) => {
+            let dst_ip = match dst.ip() {
+                IpAddr::V4(dst_ip) if dst_ip.is_unspecified() => Ipv6Addr::UNSPECIFIED,
+                IpAddr::V4(dst_ip) => dst_ip.to_ipv6_mapped(),
+                IpAddr::V6(dst_ip) => dst_ip,
+            };
+            let mut header = [0u8; IPV6_HEADER_LEN];
+            header[0] = 0x60; // version 6
+            header[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+            header[6] = 17; // udp
+            header[7] = 64; // hop limit
+            header[8..24].copy_from_slice(&src_ip.octets());
+            header[24..40].copy_from_slice(&dst_ip.octets());
+            datagram.extend_from_slice(&header);
+        }
+    }
+    datagram.extend_from_slice(&src.port().to_be_bytes());
+    datagram.extend_from_slice(&dst.port().to_be_bytes());
+    datagram.extend_from_slice(&(udp_len as u16).to_be_bytes());
+    datagram.extend_from_slice(&0u16.to_be_bytes()); // checksum, optional over ipv4
+    datagram.extend_from_slice(payload);
+    datagram
+}
+
+fn ipv4_checksum(header: &[u8]) -> u16 {
+    let sum = header
+        .chunks(2)
+        .map(|word| u32::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u32>();
+    let sum = (sum & 0xffff) + (sum >> 16);
+    !((sum & 0xffff) + (sum >> 16)) as u16
+}
+
+/// Reads UDP packets from a classic pcap file, as written by `--record-pcap` or `tcpdump -w`.
+/// Records that aren't UDP over IPv4 or IPv6 are skipped.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    nanos: bool,
+    linktype: u32,
+}
+
+impl<R: Read> PcapReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut header = [0u8; PCAP_FILE_HEADER_LEN];
+        reader.read_exact(&mut header)?;
+        let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+        let (big_endian, nanos) = match magic {
+            PCAP_MAGIC_MICROS => (false, false),
+            PCAP_MAGIC_NANOS => (false, true),
+            _ if magic.swap_bytes() == PCAP_MAGIC_MICROS => (true, false),
+            _ if magic.swap_bytes() == PCAP_MAGIC_NANOS => (true, true),
+            PCAPNG_MAGIC => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "pcapng is not supported, convert with `editcap -F pcap`",
+                ))
+            }
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Not a pcap file, unexpected magic {magic:#010x}"),
+                ))
+            }
+        };
+        let mut pcap_reader = Self {
+            reader,
+            big_endian,
+            nanos,
+            linktype: 0,
+        };
+        pcap_reader.linktype = pcap_reader.u32_at(&header, 20);
+        if ![
+            LINKTYPE_ETHERNET,
+            LINKTYPE_RAW,
+            LINKTYPE_LINUX_SLL,
+            LINKTYPE_IPV4,
+            LINKTYPE_IPV6,
+        ]
+        .contains(&pcap_reader.linktype)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported pcap link type {}", pcap_reader.linktype),
+            ));
+        }
+        Ok(pcap_reader)
+    }
+
+    /// Returns the next UDP packet, or `None` at the end of the capture.
+    /// A truncated final record, from a capture that was still being written, is treated as the end.
+    pub fn next_packet(&mut self) -> io::Result<Option<RecordedPacket>> {
+        loop {
+            let mut header = [0u8; PCAP_RECORD_HEADER_LEN];
+            if !self.read_or_eof(&mut header)? {
+                return Ok(None);
+            }
+            let secs = self.u32_at(&header, 0);
+            let subsec = self.u32_at(&header, 4);
+            let captured_len = self.u32_at(&header, 8);
+            if captured_len > MAX_RECORD_LEN {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Pcap record length {captured_len} too large, file may be corrupt"),
+                ));
+            }
+            let mut data = vec![0u8; captured_len as usize];
+            if !self.read_or_eof(&mut data)? {
+                return Ok(None);
+            }
+
+            let subsec_nanos = if self.nanos { subsec } else { subsec * 1_000 };
+            let received_at = UNIX_EPOCH + Duration::new(u64::from(secs), subsec_nanos);
+            if let Some((src, dst, payload)) = decode_link(self.linktype, &data) {
+                return Ok(Some(RecordedPacket {
+                    received_at,
+                    src,
+                    dst,
+                    payload: payload.to_vec(),
+                }));
+            }
+        }
+    }
+
+    /// Fills `buf`, returning false if the reader ends before it's full
+    fn read_or_eof(&mut self, buf: &mut [u8]) -> io::Result<bool> {
+        match self.reader.read_exact(buf) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn u32_at(&self, bytes: &[u8], i: usize) -> u32 {
+        let bytes = bytes[i..i + 4].try_into().unwrap();
+        if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        }
+    }
+}
+
+/// Strips the link layer header, returning the UDP source, destination, and payload
+fn decode_link(linktype: u32, data: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let ip = match linktype {
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => data,
+        LINKTYPE_ETHERNET => {
+            let mut offset = ETHERNET_HEADER_LEN;
+            let mut ethertype = u16::from_be_bytes(data.get(12..14)?.try_into().unwrap());
+            if ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes(data.get(16..18)?.try_into().unwrap());
+                offset += VLAN_TAG_LEN;
+            }
+            if ethertype != ETHERTYPE_IPV4 && ethertype != ETHERTYPE_IPV6 {
+                return None;
+            }
+            data.get(offset..)?
+        }
+        LINKTYPE_LINUX_SLL => data.get(LINUX_SLL_HEADER_LEN..)?,
+        _ => return None,
+    };
+    decode_ip_udp(ip)
+}
+
+/// Inverse of [encode_ip_udp]. Returns `None` for non-UDP packets, IPv4 fragments, and IPv6 extension headers
+fn decode_ip_udp(ip: &[u8]) -> Option<(SocketAddr, SocketAddr, &[u8])> {
+    let (src_ip, dst_ip, udp): (IpAddr, IpAddr, _) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = usize::from(ip[0] & 0x0f) * 4;
+            let fragment_offset = u16::from_be_bytes(ip.get(6..8)?.try_into().unwrap()) & 0x1fff;
+            let more_fragments = ip[6] & 0x20 != 0;
+            if *ip.get(9)? != IP_PROTOCOL_UDP || fragment_offset != 0 || more_fragments {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().unwrap();
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().unwrap();
+            (src.into(), dst.into(), ip.get(header_len..)?)
+        }
+        6 => {
+            if *ip.get(6)? != IP_PROTOCOL_UDP {
+                return None;
+            }
+            let src: [u8; 16] = ip.get(8..24)?.try_into().unwrap();
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().unwrap();
+            (src.into(), dst.into(), ip.get(IPV6_HEADER_LEN..)?)
+        }
+        _ => return None,
+    };
+    let src_port = u16::from_be_bytes(udp.get(0..2)?.try_into().unwrap());
+    let dst_port = u16::from_be_bytes(udp.get(2..4)?.try_into().unwrap());
+    let udp_len = usize::from(u16::from_be_bytes(udp.get(4..6)?.try_into().unwrap()));
+    // captures may be truncated by the snaplen
+    let payload = udp.get(UDP_HEADER_LEN..udp_len.min(udp.len()))?;
+    Some((
+        SocketAddr::new(src_ip, src_port),
+        SocketAddr::new(dst_ip, dst_port),
+        payload,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::Cursor,
+        net::SocketAddr,
+        str::FromStr,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::pcap::{
+        encode_ip_udp, encode_record, ipv4_checksum, PcapReader, PcapRotation, PcapWriter,
+        RecordedPacket, LINKTYPE_ETHERNET, LINKTYPE_RAW, PCAP_FILE_HEADER_LEN, PCAP_MAGIC_MICROS,
+        PCAP_MAGIC_NANOS, PCAP_RECORD_HEADER_LEN,
+    };
+
+    fn new_packet(payload: &[u8]) -> RecordedPacket {
+        RecordedPacket {
+            received_at: UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789),
+            src: SocketAddr::from_str("10.0.0.1:8001").unwrap(),
+            dst: SocketAddr::from_str("10.0.0.2:20000").unwrap(),
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_encode_record() {
+        let record = encode_record(&new_packet(b"shred"));
+        let u32_at = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().unwrap());
+
+        assert_eq!(u32_at(0), 1_700_000_000);
+        assert_eq!(u32_at(4), 123_456_789);
+        assert_eq!(u32_at(8) as usize, record.len() - PCAP_RECORD_HEADER_LEN);
+        let ip = &record[PCAP_RECORD_HEADER_LEN..];
+        assert_eq!(ip.len(), 20 + 8 + 5);
+        assert_eq!(&ip[12..16], &[10, 0, 0, 1]);
+        assert_eq!(&ip[16..20], &[10, 0, 0, 2]);
+        // valid header sums to zero
+        assert_eq!(ipv4_checksum(&ip[..20]), 0);
+        let udp = &ip[20..];
+        assert_eq!(u16::from_be_bytes([udp[0], udp[1]]), 8001);
+        assert_eq!(u16::from_be_bytes([udp[2], udp[3]]), 20000);
+        assert_eq!(&udp[8..], b"shred");
+    }
+
+    #[test]
+    fn test_pcap_writer_rotation() {
+        let dir = std::env::temp_dir().join(format!(
+            "shredstream_proxy_pcap_{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let record_len = encode_record(&new_packet(b"shred")).len() as u64;
+        let mut writer = PcapWriter::new(
+            dir.join("capture.pcap"),
+            PcapRotation {
+                // two records per file
+                max_file_bytes: Some(PCAP_FILE_HEADER_LEN as u64 + 2 * record_len),
+                max_file_age: None,
+                max_files: Some(2),
+            },
+        )
+        .unwrap();
+
+        for _ in 0..5 {
+            writer.write(&new_packet(b"shred")).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        files.sort();
+        // 3 files written, oldest pruned
+        assert_eq!(files.len(), 2);
+        let contents = files
+            .iter()
+            .map(|file| fs::read(file).unwrap())
+            .collect::<Vec<_>>();
+        for contents in contents.iter() {
+            assert_eq!(
+                u32::from_le_bytes(contents[0..4].try_into().unwrap()),
+                PCAP_MAGIC_NANOS
+            );
+            assert_eq!(
+                u32::from_le_bytes(contents[20..24].try_into().unwrap()),
+                LINKTYPE_RAW
+            );
+        }
+        let num_records = contents
+            .iter()
+            .map(|contents| (contents.len() - PCAP_FILE_HEADER_LEN) as u64 / record_len)
+            .sum::<u64>();
+        assert_eq!(num_records, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pcap_reader_roundtrip() {
+        let path = std::env::temp_dir().join(format!(
+            "shredstream_proxy_pcap_reader_{}.pcap",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let v6_packet = RecordedPacket {
+            src: SocketAddr::from_str("[2001:db8::1]:8001").unwrap(),
+            dst: SocketAddr::from_str("[2001:db8::2]:20000").unwrap(),
+            ..new_packet(b"shred6")
+        };
+        let mut writer = PcapWriter::new(
+            path.clone(),
+            PcapRotation {
+                max_file_bytes: None,
+                max_file_age: None,
+                max_files: None,
+            },
+        )
+        .unwrap();
+        writer.write(&new_packet(b"shred")).unwrap();
+        writer.write(&v6_packet).unwrap();
+        writer.flush().unwrap();
+
+        let mut contents = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // partially written record at the end is ignored
+        contents.extend_from_slice(&[0u8; 10]);
+        let mut reader = PcapReader::new(Cursor::new(contents)).unwrap();
+        for expected in [new_packet(b"shred"), v6_packet] {
+            let packet = reader.next_packet().unwrap().unwrap();
+            assert_eq!(packet.received_at, expected.received_at);
+            assert_eq!(packet.src, expected.src);
+            assert_eq!(packet.dst, expected.dst);
+            assert_eq!(packet.payload, expected.payload);
+        }
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pcap_reader_ethernet() {
+        let packet = new_packet(b"shred");
+        let mut contents = Vec::new();
+        contents.extend_from_slice(&PCAP_MAGIC_MICROS.to_be_bytes());
+        contents.extend_from_slice(&2u16.to_be_bytes());
+        contents.extend_from_slice(&4u16.to_be_bytes());
+        contents.extend_from_slice(&[0u8; 8]);
+        contents.extend_from_slice(&65_535u32.to_be_bytes());
+        contents.extend_from_slice(&LINKTYPE_ETHERNET.to_be_bytes());
+        let mut add_frame = |ethertype: u16, ip: &[u8]| {
+            let mut frame = vec![0u8; 12]; // mac addresses
+            frame.extend_from_slice(&ethertype.to_be_bytes());
+            frame.extend_from_slice(ip);
+            contents.extend_from_slice(&1_700_000_000u32.to_be_bytes());
+            contents.extend_from_slice(&123_456u32.to_be_bytes());
+            contents.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            contents.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+            contents.extend_from_slice(&frame);
+        };
+        // arp is skipped
+        add_frame(0x0806, &[0u8; 28]);
+        add_frame(
+            0x0800,
+            &encode_ip_udp(packet.src, packet.dst, &packet.payload),
+        );
+
+        let mut reader = PcapReader::new(Cursor::new(contents)).unwrap();
+        let read = reader.next_packet().unwrap().unwrap();
+        assert_eq!(
+            read.received_at,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_000)
+        );
+        assert_eq!(read.src, packet.src);
+        assert_eq!(read.dst, packet.dst);
+        assert_eq!(read.payload, packet.payload);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        // pcapng is rejected rather than misread
+        let pcapng = [0x0a, 0x0d, 0x0d, 0x0a].repeat(6);
+        assert!(PcapReader::new(Cursor::new(pcapng)).is_err());
+    }
+}
@@ -0,0 +1,114 @@
+use std::{fmt, str::FromStr};
+
+use crossbeam_channel::Receiver;
+
+/// Backlog, as a fraction of a queue's capacity, from which sends to `normal` priority destinations are dropped
+const NORMAL_SHED_BACKLOG: f64 = 0.75;
+/// Backlog from which sends to `low` priority destinations are dropped, before any other destination's
+const LOW_SHED_BACKLOG: f64 = 0.5;
+
+/// Order destinations are sent to in, and which are dropped first once the forwarder falls behind, from a destination's `priority` option
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DestPriority {
+    /// Sent first, and only dropped once its queue is full
+    High,
+    #[default]
+    Normal,
+    /// Sent last, and dropped first
+    Low,
+}
+
+impl DestPriority {
+    pub const ALL: [DestPriority; 3] =
+        [DestPriority::High, DestPriority::Normal, DestPriority::Low];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DestPriority::High => "high",
+            DestPriority::Normal => "normal",
+            DestPriority::Low => "low",
+        }
+    }
+
+    /// Whether sends at this priority are dropped with `backlog` queued, a fraction of the queue's capacity
+    pub fn is_shed(self, backlog: f64) -> bool {
+        match self {
+            DestPriority::High => false,
+            DestPriority::Normal => backlog >= NORMAL_SHED_BACKLOG,
+            DestPriority::Low => backlog >= LOW_SHED_BACKLOG,
+        }
+    }
+}
+
+impl FromStr for DestPriority {
+    type Err = String;
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority {
+            "high" => Ok(DestPriority::High),
+            "normal" => Ok(DestPriority::Normal),
+            "low" => Ok(DestPriority::Low),
+            _ => Err(format!(
+                "Invalid priority {priority:?}, expected `high`, `normal`, or `low`."
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DestPriority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Fraction of `receiver`'s capacity that's queued, 0 for unbounded channels
+pub fn backlog<T>(receiver: &Receiver<T>) -> f64 {
+    match receiver.capacity() {
+        Some(capacity) if capacity > 0 => receiver.len() as f64 / capacity as f64,
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::priority::{backlog, DestPriority};
+
+    #[test]
+    fn test_dest_priority() {
+        assert_eq!("high".parse(), Ok(DestPriority::High));
+        assert_eq!("low".parse(), Ok(DestPriority::Low));
+        assert!("urgent".parse::<DestPriority>().is_err());
+
+        let mut priorities = vec![DestPriority::Low, DestPriority::Normal, DestPriority::High];
+        priorities.sort();
+        assert_eq!(priorities, DestPriority::ALL);
+
+        // low priority is shed first, high priority never
+        for (backlog, shed) in [
+            (0.0, [false, false, false]),
+            (0.5, [false, false, true]),
+            (0.75, [false, true, true]),
+            (1.0, [false, true, true]),
+        ] {
+            assert_eq!(
+                DestPriority::ALL.map(|priority| priority.is_shed(backlog)),
+                shed,
+                "{backlog}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_backlog() {
+        let (sender, receiver) = crossbeam_channel::bounded(4);
+        assert_eq!(backlog(&receiver), 0.0);
+        sender.send(()).unwrap();
+        sender.send(()).unwrap();
+        assert_eq!(backlog(&receiver), 0.5);
+
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        sender.send(()).unwrap();
+        assert_eq!(backlog(&receiver), 0.0);
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use log::info;
+
+/// Upper bound on the buffer for a passwd or group entry, which grows from `INITIAL_ENTRY_BUFFER_BYTES` on `ERANGE`
+#[cfg(unix)]
+const MAX_ENTRY_BUFFER_BYTES: usize = 1 << 20;
+#[cfg(unix)]
+const INITIAL_ENTRY_BUFFER_BYTES: usize = 1024;
+
+/// User and group ids to switch to, None leaves the current one
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Ids {
+    uid: Option<libc::uid_t>,
+    gid: Option<libc::gid_t>,
+}
+
+/// Optionally chroots into `chroot`, then switches to `group` and `user`, each a name or numeric id.
+/// `group` defaults to the primary group of `user`. Names are looked up before chrooting, since `/etc` may not exist inside.
+/// Fails if any step fails, or if root can be regained afterwards
+#[cfg(unix)]
+pub fn drop_privileges(
+    user: Option<&str>,
+    group: Option<&str>,
+    chroot: Option<&Path>,
+) -> io::Result<()> {
+    let ids = resolve_ids(user, group)?;
+
+    if let Some(dir) = chroot {
+        let dir_cstr = cstring(&dir.to_string_lossy())?;
+        // SAFETY: dir_cstr is a valid nul terminated string
+        if unsafe { libc::chroot(dir_cstr.as_ptr()) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(io::Error::new(
+                e.kind(),
+                format!("Failed to chroot to {dir:?}: {e}"),
+            ));
+        }
+        std::env::set_current_dir("/")?;
+    }
+
+    // glibc and musl apply set*id calls to every thread, not just the caller
+    if let Some(gid) = ids.gid {
+        // SAFETY: setgroups reads exactly one gid from a valid pointer
+        if unsafe { libc::setgroups(1, &gid) } != 0 {
+            return Err(os_error(format!(
+                "Failed to set supplementary groups to {gid}"
+            )));
+        }
+        // SAFETY: setgid takes no pointers
+        if unsafe { libc::setgid(gid) } != 0 {
+            return Err(os_error(format!("Failed to set gid to {gid}")));
+        }
+    }
+    if let Some(uid) = ids.uid {
+        // SAFETY: setuid takes no pointers
+        if unsafe { libc::setuid(uid) } != 0 {
+            return Err(os_error(format!("Failed to set uid to {uid}")));
+        }
+    }
+
+    // SAFETY: the get*id calls take no pointers and always succeed
+    let (real_uid, effective_uid, real_gid, effective_gid) = unsafe {
+        (
+            libc::getuid(),
+            libc::geteuid(),
+            libc::getgid(),
+            libc::getegid(),
+        )
+    };
+    if ids
+        .uid
+        .is_some_and(|uid| real_uid != uid || effective_uid != uid)
+        || ids
+            .gid
+            .is_some_and(|gid| real_gid != gid || effective_gid != gid)
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Privileges weren't dropped, running as uid {effective_uid} gid {effective_gid}"
+            ),
+        ));
+    }
+    // SAFETY: setuid takes no pointers
+    if effective_uid != 0 && unsafe { libc::setuid(0) } == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "Privileges weren't dropped, root can be regained",
+        ));
+    }
+    match chroot {
+        Some(dir) => info!(
+            "Dropped privileges to uid {effective_uid} gid {effective_gid}, chrooted to {dir:?}."
+        ),
+        None => info!("Dropped privileges to uid {effective_uid} gid {effective_gid}."),
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn drop_privileges(
+    _user: Option<&str>,
+    _group: Option<&str>,
+    _chroot: Option<&Path>,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Dropping privileges is only supported on Unix",
+    ))
+}
+
+/// Checks the files opened after chrooting resolve inside `chroot`, since the threads opening them start before it.
+/// `paths` are (option setting it, path), each of which must be absolute and exist inside `chroot`, or have its parent
+/// exist there if it's created later. With `resolves_hostnames`, `chroot` also needs its own `/etc/resolv.conf`
+pub fn validate_chroot_paths(
+    chroot: &Path,
+    paths: &[(&str, PathBuf)],
+    resolves_hostnames: bool,
+) -> Result<(), String> {
+    for (option, path) in paths {
+        let Ok(relative) = path.strip_prefix("/") else {
+            return Err(format!("Invalid arguments provided, {option} {path:?} must be an absolute path with --chroot, since it's opened after chrooting."));
+        };
+        let inside = chroot.join(relative);
+        if !inside.exists() && !inside.parent().is_some_and(Path::is_dir) {
+            return Err(format!("Invalid arguments provided, {option} {path:?} is opened after chrooting to {chroot:?}, but {inside:?} doesn't exist."));
+        }
+    }
+    let resolv_conf = chroot.join("etc/resolv.conf");
+    if resolves_hostnames && !resolv_conf.is_file() {
+        return Err(format!("Invalid arguments provided, hostnames are resolved after chrooting to {chroot:?}, but {resolv_conf:?} doesn't exist."));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn resolve_ids(user: Option<&str>, group: Option<&str>) -> io::Result<Ids> {
+    let group_gid = group
+        .map(|group| {
+            lookup_group(group)?
+                .or_else(|| group.parse().ok())
+                .ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::NotFound, format!("Unknown group {group:?}"))
+                })
+        })
+        .transpose()?;
+    let Some(user) = user else {
+        return Ok(Ids {
+            uid: None,
+            gid: group_gid,
+        });
+    };
+    let (uid, primary_gid) = match lookup_user(user)? {
+        Some((uid, gid)) => (uid, Some(gid)),
+        // numeric ids without a passwd entry work too, as long as the group is given
+        None => (
+            user.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, format!("Unknown user {user:?}"))
+            })?,
+            None,
+        ),
+    };
+    let gid = group_gid.or(primary_gid).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("User {user:?} has no passwd entry, so --run-as-group is required"),
+        )
+    })?;
+    Ok(Ids {
+        uid: Some(uid),
+        gid: Some(gid),
+    })
+}
+
+/// Returns the uid and primary gid of a user name or numeric uid, or None if there's no passwd entry
+#[cfg(unix)]
+fn lookup_user(user: &str) -> io::Result<Option<(libc::uid_t, libc::gid_t)>> {
+    let name = cstring(user)?;
+    let uid = user.parse::<libc::uid_t>().ok();
+    with_entry_buffer(|buf| {
+        // SAFETY: passwd is plain data filled in by getpw*_r, which only writes within buf
+        unsafe {
+            let mut passwd = std::mem::zeroed::<libc::passwd>();
+            let mut result = std::ptr::null_mut();
+            let err = match uid {
+                Some(uid) => {
+                    libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result)
+                }
+                None => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            };
+            (
+                err,
+                (!result.is_null()).then_some((passwd.pw_uid, passwd.pw_gid)),
+            )
+        }
+    })
+}
+
+/// Returns the gid of a group name or numeric gid, or None if there's no group entry
+#[cfg(unix)]
+fn lookup_group(group: &str) -> io::Result<Option<libc::gid_t>> {
+    let name = cstring(group)?;
+    let gid = group.parse::<libc::gid_t>().ok();
+    with_entry_buffer(|buf| {
+        // SAFETY: group is plain data filled in by getgr*_r, which only writes within buf
+        unsafe {
+            let mut group = std::mem::zeroed::<libc::group>();
+            let mut result = std::ptr::null_mut();
+            let err = match gid {
+                Some(gid) => {
+                    libc::getgrgid_r(gid, &mut group, buf.as_mut_ptr(), buf.len(), &mut result)
+                }
+                None => libc::getgrnam_r(
+                    name.as_ptr(),
+                    &mut group,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            };
+            (err, (!result.is_null()).then_some(group.gr_gid))
+        }
+    })
+}
+
+/// Calls `lookup` with a growing buffer until the entry fits, `lookup` returns the error number and entry
+#[cfg(unix)]
+fn with_entry_buffer<T>(
+    mut lookup: impl FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<T>),
+) -> io::Result<Option<T>> {
+    let mut buf = vec![0; INITIAL_ENTRY_BUFFER_BYTES];
+    loop {
+        match lookup(&mut buf) {
+            (libc::ERANGE, _) if buf.len() < MAX_ENTRY_BUFFER_BYTES => buf.resize(buf.len() * 2, 0),
+            (0, entry) => return Ok(entry),
+            (err, _) => return Err(io::Error::from_raw_os_error(err)),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn cstring(s: &str) -> io::Result<std::ffi::CString> {
+    std::ffi::CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+#[cfg(unix)]
+fn os_error(context: String) -> io::Error {
+    let e = io::Error::last_os_error();
+    io::Error::new(e.kind(), format!("{context}: {e}"))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use crate::privileges::{drop_privileges, resolve_ids, validate_chroot_paths, Ids};
+
+    #[test]
+    fn test_resolve_ids() {
+        // root is uid 0 with primary group 0 everywhere
+        let root = Ids {
+            uid: Some(0),
+            gid: Some(0),
+        };
+        assert_eq!(resolve_ids(Some("root"), None).unwrap(), root);
+        assert_eq!(resolve_ids(Some("0"), Some("0")).unwrap(), root);
+        assert_eq!(
+            resolve_ids(None, Some("root")).unwrap(),
+            Ids {
+                uid: None,
+                gid: Some(0),
+            }
+        );
+
+        // numeric ids without an entry need the group given
+        assert!(resolve_ids(Some("4000000000"), None).is_err());
+        assert_eq!(
+            resolve_ids(Some("4000000000"), Some("4000000001")).unwrap(),
+            Ids {
+                uid: Some(4_000_000_000),
+                gid: Some(4_000_000_001),
+            }
+        );
+
+        assert!(resolve_ids(Some("no-such-user-ssPxy"), None).is_err());
+        assert!(resolve_ids(Some("root"), Some("no-such-group-ssPxy")).is_err());
+        assert!(resolve_ids(Some("ro\0ot"), None).is_err());
+
+        // nothing to drop is a no-op
+        drop_privileges(None, None, None).unwrap();
+    }
+
+    #[test]
+    fn test_validate_chroot_paths() {
+        let chroot =
+            std::env::temp_dir().join(format!("test_validate_chroot_paths_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&chroot);
+        fs::create_dir_all(chroot.join("etc")).unwrap();
+        fs::create_dir_all(chroot.join("var/spill")).unwrap();
+
+        let spill = ("--spill-dir", PathBuf::from("/var/spill"));
+        // created after chrooting, so only the parent needs to exist
+        let pcap = ("--record-pcap", PathBuf::from("/var/capture.pcap"));
+        validate_chroot_paths(&chroot, &[spill.clone(), pcap.clone()], false).unwrap();
+
+        let err = validate_chroot_paths(
+            &chroot,
+            &[("--record-pcap", PathBuf::from("/srv/pcap/capture.pcap"))],
+            false,
+        )
+        .unwrap_err();
+        assert!(err.contains("--record-pcap"), "{err}");
+        let err = validate_chroot_paths(
+            &chroot,
+            &[("config file", PathBuf::from("config.toml"))],
+            false,
+        )
+        .unwrap_err();
+        assert!(err.contains("must be an absolute path"), "{err}");
+
+        let err = validate_chroot_paths(&chroot, &[spill.clone()], true).unwrap_err();
+        assert!(err.contains("resolv.conf"), "{err}");
+        fs::write(chroot.join("etc/resolv.conf"), "nameserver 127.0.0.1\n").unwrap();
+        validate_chroot_paths(&chroot, &[spill, pcap], true).unwrap();
+
+        fs::remove_dir_all(&chroot).unwrap();
+    }
+}
@@ -0,0 +1,330 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use serde::Serialize;
+use tiny_http::{Header, Response};
+
+use crate::{
+    forwarder::ShredMetrics,
+    systemd::{ThreadLiveness, MIN_FORWARDER_STALL_TIMEOUT},
+};
+
+/// Outcome of one check, listed in the probe's body so a failing probe shows which check failed and why
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    ok: bool,
+    detail: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ProbeResponse {
+    /// True if every check passed, also reflected in the status code: 200 or 503
+    ok: bool,
+    checks: Vec<Check>,
+}
+
+/// Thresholds that only apply in shredstream mode, when heartbeating to a block engine
+pub struct HeartbeatProbeConfig {
+    /// Max age of the last successful heartbeat before `/readyz` fails
+    pub max_age: Duration,
+    /// How long no packets may arrive while heartbeats succeed before `/healthz` fails, the stall watchdog's threshold.
+    /// None when the watchdog is disabled
+    pub stall_timeout: Option<Duration>,
+}
+
+/// Tracks when packets last arrived, and how many heartbeats had succeeded then
+struct StreamProgress {
+    last_received: u64,
+    successful_heartbeats: u64,
+    since: Instant,
+}
+
+impl StreamProgress {
+    fn new(now: Instant) -> Self {
+        Self {
+            last_received: 0,
+            successful_heartbeats: 0,
+            since: now,
+        }
+    }
+
+    /// Returns how long no packets arrived while heartbeats succeeded, None if packets arrived or heartbeats haven't succeeded since.
+    /// Sampled per probe, so the stall is measured from the first probe that saw no new packets
+    fn stalled_for(
+        &mut self,
+        received: u64,
+        successful_heartbeats: u64,
+        heartbeats_paused: bool,
+        now: Instant,
+    ) -> Option<Duration> {
+        // nothing is expected before the first heartbeat succeeds, nor while paused
+        if received != self.last_received || self.successful_heartbeats == 0 || heartbeats_paused {
+            self.last_received = received;
+            self.successful_heartbeats = successful_heartbeats;
+            self.since = now;
+            return None;
+        }
+        (successful_heartbeats > self.successful_heartbeats).then(|| now.duration_since(self.since))
+    }
+}
+
+/// Serves `GET /readyz` and `GET /healthz` for Kubernetes probes, from the prometheus and admin listeners.
+/// `/readyz` requires listening, at least one destination, and in shredstream mode a recent successful heartbeat.
+/// `/healthz` fails once forwarder threads exit or get stuck, or no packets arrive while heartbeats succeed for longer than the stall watchdog's threshold
+pub struct Probes {
+    src_bind_addr: IpAddr,
+    /// Empty when replaying a capture, which binds nothing
+    listen_ports: Vec<u16>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    forwarder_liveness: Arc<ThreadLiveness>,
+    heartbeat: Option<HeartbeatProbeConfig>,
+    metrics: Arc<ShredMetrics>,
+    stream_progress: Mutex<StreamProgress>,
+}
+
+impl Probes {
+    /// Created once the forwarder threads have bound the listen sockets
+    pub fn new(
+        src_bind_addr: IpAddr,
+        listen_ports: Vec<u16>,
+        unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+        forwarder_liveness: Arc<ThreadLiveness>,
+        heartbeat: Option<HeartbeatProbeConfig>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
+        Self {
+            src_bind_addr,
+            listen_ports,
+            unioned_dest_sockets,
+            forwarder_liveness,
+            heartbeat,
+            metrics,
+            stream_progress: Mutex::new(StreamProgress::new(Instant::now())),
+        }
+    }
+
+    /// Returns the response to `url` if it's a probe
+    pub fn respond(&self, url: &str) -> Option<Response<io::Cursor<Vec<u8>>>> {
+        let checks = match url {
+            "/readyz" => self.readiness(Instant::now()),
+            "/healthz" => self.liveness(Instant::now()),
+            _ => return None,
+        };
+        let response = ProbeResponse {
+            ok: checks.iter().all(|check| check.ok),
+            checks,
+        };
+        Some(
+            Response::from_data(serde_json::to_vec(&response).unwrap())
+                .with_status_code(if response.ok { 200 } else { 503 })
+                .with_header(Header::from_bytes("Content-Type", "application/json").unwrap()),
+        )
+    }
+
+    fn readiness(&self, now: Instant) -> Vec<Check> {
+        let mut checks = vec![
+            Check {
+                name: "listen_socket",
+                ok: true,
+                detail: match self.listen_ports.is_empty() {
+                    true => "replaying a capture, nothing to bind".to_string(),
+                    false => format!(
+                        "listening on {} ports {:?}",
+                        self.src_bind_addr, self.listen_ports
+                    ),
+                },
+            },
+            {
+                let num_dests = self.unioned_dest_sockets.load().len();
+                Check {
+                    name: "destinations",
+                    ok: num_dests > 0,
+                    detail: format!("{num_dests} destinations configured"),
+                }
+            },
+        ];
+        if let Some(heartbeat) = &self.heartbeat {
+            let last_success = (self
+                .metrics
+                .successful_heartbeat_cumulative
+                .load(Ordering::Relaxed)
+                > 0)
+            .then(|| {
+                self.metrics
+                    .heartbeat_regions
+                    .iter()
+                    .filter(|stats| stats.ttl_ms.is_some())
+                    .map(|stats| stats.last_success)
+                    .max()
+            })
+            .flatten();
+            checks.push(match last_success {
+                Some(last_success) => {
+                    let age = now.saturating_duration_since(last_success);
+                    Check {
+                        name: "heartbeat",
+                        ok: age <= heartbeat.max_age,
+                        detail: format!(
+                            "last heartbeat succeeded {:.1?} ago, max {:?}",
+                            age, heartbeat.max_age
+                        ),
+                    }
+                }
+                None => Check {
+                    name: "heartbeat",
+                    ok: false,
+                    detail: "no heartbeat has succeeded yet".to_string(),
+                },
+            });
+        }
+        checks
+    }
+
+    fn liveness(&self, now: Instant) -> Vec<Check> {
+        let num_threads = self.forwarder_liveness.num_threads();
+        let num_stalled = self
+            .forwarder_liveness
+            .num_stalled(MIN_FORWARDER_STALL_TIMEOUT, now);
+        let mut checks = vec![Check {
+            name: "forwarder_threads",
+            ok: num_stalled == 0,
+            detail: format!(
+                "{num_stalled} of {num_threads} forwarder threads exited or made no progress for {MIN_FORWARDER_STALL_TIMEOUT:?}"
+            ),
+        }];
+        if let Some(stall_timeout) = self.heartbeat.as_ref().and_then(|h| h.stall_timeout) {
+            let stalled_for = self.stream_progress.lock().unwrap().stalled_for(
+                self.metrics.agg_received_cumulative.load(Ordering::Relaxed),
+                self.metrics
+                    .successful_heartbeat_cumulative
+                    .load(Ordering::Relaxed),
+                self.metrics.heartbeat_paused.load(Ordering::Relaxed),
+                now,
+            );
+            checks.push(match stalled_for {
+                Some(stalled_for) => Check {
+                    name: "stream",
+                    ok: stalled_for <= stall_timeout,
+                    detail: format!(
+                        "no packets for {stalled_for:.1?} while heartbeats succeeded, max {stall_timeout:?}"
+                    ),
+                },
+                None => Check {
+                    name: "stream",
+                    ok: true,
+                    detail: "receiving packets, or not expecting any".to_string(),
+                },
+            });
+        }
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant},
+    };
+
+    use arc_swap::ArcSwap;
+
+    use crate::{
+        forwarder::ShredMetrics,
+        heartbeat::RegionHeartbeatStats,
+        probes::{Check, HeartbeatProbeConfig, Probes, StreamProgress},
+        systemd::ThreadLiveness,
+    };
+
+    fn failing(checks: &[Check]) -> Vec<&'static str> {
+        checks
+            .iter()
+            .filter(|check| !check.ok)
+            .map(|check| check.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_stream_progress() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut progress = StreamProgress::new(start);
+
+        // nothing expected until a heartbeat succeeds
+        assert_eq!(progress.stalled_for(0, 0, false, secs(100)), None);
+        assert_eq!(progress.stalled_for(0, 1, false, secs(110)), None);
+        assert_eq!(
+            progress.stalled_for(0, 3, false, secs(150)),
+            Some(Duration::from_secs(40))
+        );
+        // packets arriving restart the window
+        assert_eq!(progress.stalled_for(5, 4, false, secs(160)), None);
+        // not while heartbeats are failing
+        assert_eq!(progress.stalled_for(5, 4, false, secs(300)), None);
+        assert_eq!(
+            progress.stalled_for(5, 5, false, secs(320)),
+            Some(Duration::from_secs(160))
+        );
+        // nor while paused
+        assert_eq!(progress.stalled_for(5, 6, true, secs(400)), None);
+        assert_eq!(progress.stalled_for(5, 6, false, secs(500)), None);
+    }
+
+    #[test]
+    fn test_probes() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let unioned_dest_sockets = Arc::new(ArcSwap::from_pointee(vec![]));
+        let forwarder_liveness = Arc::new(ThreadLiveness::default());
+        let _beat = forwarder_liveness.register();
+        let probes = Probes::new(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            vec![20_000],
+            unioned_dest_sockets.clone(),
+            forwarder_liveness,
+            Some(HeartbeatProbeConfig {
+                max_age: Duration::from_secs(30),
+                stall_timeout: Some(Duration::from_secs(120)),
+            }),
+            metrics.clone(),
+        );
+        let now = Instant::now();
+
+        assert_eq!(
+            failing(&probes.readiness(now)),
+            vec!["destinations", "heartbeat"]
+        );
+        unioned_dest_sockets.store(Arc::new(vec![SocketAddr::from(([127, 0, 0, 1], 8001))]));
+        metrics
+            .successful_heartbeat_cumulative
+            .store(1, Ordering::Relaxed);
+        let mut stats = RegionHeartbeatStats::new(now);
+        stats.ttl_ms = Some(600);
+        metrics.heartbeat_regions.insert("ny".to_string(), stats);
+        assert!(failing(&probes.readiness(now + Duration::from_secs(30))).is_empty());
+        assert_eq!(
+            failing(&probes.readiness(now + Duration::from_secs(31))),
+            vec!["heartbeat"]
+        );
+
+        // heartbeats keep succeeding without packets for longer than the stall timeout
+        assert!(failing(&probes.liveness(now + Duration::from_secs(1))).is_empty());
+        metrics
+            .successful_heartbeat_cumulative
+            .store(50, Ordering::Relaxed);
+        assert_eq!(
+            failing(&probes.liveness(now + Duration::from_secs(122))),
+            vec!["forwarder_threads", "stream"]
+        );
+
+        let response = probes.respond("/readyz").unwrap();
+        assert_eq!(response.status_code().0, 200);
+        assert!(probes.respond("/metrics").is_none());
+    }
+}
@@ -0,0 +1,1114 @@
+use std::{
+    fmt::{Display, Write},
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use log::{info, warn};
+use solana_streamer::streamer::StreamerReceiveStats;
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::{
+    build_info::{self, BuildInfo},
+    forwarder::ShredMetrics,
+    memory_guard::BufferKind,
+    metrics_backend::datapoint_info,
+    priority::DestPriority,
+    probes::Probes,
+};
+
+/// Cumulative copy of [StreamerReceiveStats], which resets on every report
+#[derive(Default)]
+pub struct ReceiveStatsTotals {
+    pub packets_count: AtomicU64,
+    pub packet_batches_count: AtomicU64,
+    pub full_packet_batches_count: AtomicU64,
+}
+
+impl ReceiveStatsTotals {
+    /// Sends `stats` to influx like [StreamerReceiveStats::report], adding the reset values to the totals
+    pub fn report(&self, stats: &StreamerReceiveStats) {
+        let packets_count = stats.packets_count.swap(0, Ordering::Relaxed) as u64;
+        let packet_batches_count = stats.packet_batches_count.swap(0, Ordering::Relaxed) as u64;
+        let full_packet_batches_count =
+            stats.full_packet_batches_count.swap(0, Ordering::Relaxed) as u64;
+        self.packets_count
+            .fetch_add(packets_count, Ordering::Relaxed);
+        self.packet_batches_count
+            .fetch_add(packet_batches_count, Ordering::Relaxed);
+        self.full_packet_batches_count
+            .fetch_add(full_packet_batches_count, Ordering::Relaxed);
+
+        datapoint_info!(
+            stats.name,
+            ("packets_count", packets_count, i64),
+            ("packet_batches_count", packet_batches_count, i64),
+            ("full_packet_batches_count", full_packet_batches_count, i64),
+            (
+                "channel_len",
+                stats.max_channel_len.swap(0, Ordering::Relaxed),
+                i64
+            ),
+        );
+    }
+}
+
+/// Serves `GET /metrics` in Prometheus text exposition format, and the `GET /readyz` and `GET /healthz` [Probes].
+/// Only reads cumulative counters, which the forwarder hot path never locks. Values lag by up to `metrics-report-interval-ms`.
+pub fn start_prometheus_thread(
+    bind_addr: SocketAddr,
+    metrics: Arc<ShredMetrics>,
+    receive_totals: Arc<ReceiveStatsTotals>,
+    probes: Arc<Probes>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let server = Server::http(bind_addr).map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+    info!("Serving prometheus metrics on http://{bind_addr}/metrics");
+
+    Builder::new()
+        .name("ssPxyPrometheus".to_string())
+        .spawn(move || {
+            let content_type =
+                Header::from_bytes("Content-Type", "text/plain; version=0.0.4").unwrap();
+            while !exit.load(Ordering::Relaxed) {
+                // use timeout so we periodically check for exit
+                let request = match server.recv_timeout(Duration::from_millis(500)) {
+                    Ok(Some(request)) => request,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        warn!("Failed to receive prometheus request. Error: {e}");
+                        continue;
+                    }
+                };
+
+                let response = match (request.method(), request.url()) {
+                    (Method::Get, "/metrics") => {
+                        Response::from_string(render_metrics(&metrics, &receive_totals))
+                            .with_header(content_type.clone())
+                    }
+                    (Method::Get, url) => probes.respond(url).unwrap_or_else(|| {
+                        Response::from_string("Not Found").with_status_code(404)
+                    }),
+                    _ => Response::from_string("Not Found").with_status_code(404),
+                };
+                if let Err(e) = request.respond(response) {
+                    warn!("Failed to respond to prometheus request. Error: {e}");
+                }
+            }
+            info!("Exiting prometheus thread.");
+        })
+}
+
+fn render_metrics(metrics: &ShredMetrics, receive_totals: &ReceiveStatsTotals) -> String {
+    let mut out = String::new();
+    write_build_info(&mut out, &build_info::build_info());
+    write_counter(
+        &mut out,
+        "shredstream_proxy_received_total",
+        "Shreds received, including duplicates.",
+        metrics.agg_received_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_forward_success_total",
+        "Shreds successfully forwarded, accounting for all destinations.",
+        metrics
+            .agg_success_forward_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_forward_fail_total",
+        "Shreds failed to forward, accounting for all destinations.",
+        metrics.agg_fail_forward_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_udp_forward_fail_total",
+        "Shreds failed to forward to UDP destinations.",
+        metrics.udp_fail_forward_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_quic_forward_fail_total",
+        "Shreds failed to forward to QUIC destinations.",
+        metrics.quic_fail_forward_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_tunnel_forward_fail_total",
+        "Shreds failed to forward to TCP and TLS destinations.",
+        metrics
+            .tunnel_fail_forward_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_unix_forward_fail_total",
+        "Shreds failed to forward to unix socket destinations.",
+        metrics.unix_fail_forward_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_duplicate_total",
+        "Duplicate shreds received.",
+        metrics.duplicate_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_stale_slot_dropped_total",
+        "Shreds dropped for being more than max slot age behind the highest slot seen.",
+        metrics
+            .stale_slot_dropped_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_received_invalid_total",
+        "Packets failing the shred sanity check, whether dropped or forwarded.",
+        metrics.received_invalid_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_non_shred_dropped_total",
+        "Packets dropped for not parsing as a shred.",
+        metrics.non_shred_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_conflicting_shreds_total",
+        "Shreds with the same slot, index, and type as an earlier shred but a different payload.",
+        metrics
+            .conflicting_shreds_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_source_not_allowed_total",
+        "Packets dropped for coming from a source not in the allowed source IPs.",
+        metrics
+            .source_not_allowed_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_labeled_counter(
+        &mut out,
+        "shredstream_proxy_shred_type_filtered_total",
+        "Shreds dropped by the forward shred types filter.",
+        "type",
+        [
+            ("data", &metrics.data_shred_filtered_cumulative),
+            ("code", &metrics.code_shred_filtered_cumulative),
+        ]
+        .into_iter()
+        .map(|(shred_type, count)| (shred_type, count.load(Ordering::Relaxed))),
+    );
+
+    write_counter(
+        &mut out,
+        "shredstream_proxy_recv_socket_dropped_total",
+        "Packets dropped by the kernel before reaching the listen sockets, usually from a full receive buffer.",
+        metrics
+            .recv_socket_dropped_cumulative
+            .load(Ordering::Relaxed),
+    );
+
+    write_counter(
+        &mut out,
+        "shredstream_proxy_channel_dropped_batches_total",
+        "Batches dropped because a forwarder's channel was full.",
+        metrics
+            .channel_dropped_batches_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_channel_dropped_packets_total",
+        "Packets dropped because a forwarder's channel was full.",
+        metrics
+            .channel_dropped_packets_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_memory_guard_dropped_total",
+        "Packets dropped at ingress while buffered bytes were over --max-buffered-bytes.",
+        metrics
+            .memory_guard_dropped_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_keepalive_sent_total",
+        "Keepalives sent to --upstream-keepalive upstreams.",
+        metrics.keepalive_sent_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_keepalive_send_failed_total",
+        "Keepalives to upstreams that failed to send.",
+        metrics
+            .keepalive_send_failed_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_keepalive_received_total",
+        "Keepalives received from downstream proxies, dropped before dedup.",
+        metrics
+            .keepalive_received_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_encrypt_dropped_total",
+        "Packets for enc:// destinations dropped because their key file couldn't be read.",
+        metrics.encrypt_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_decrypt_failed_total",
+        "Packets that failed authentication with --decrypt-key-file, dropped before dedup.",
+        metrics.decrypt_failed_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_events_sent_total",
+        "Slot events sent to --events-dest.",
+        metrics.events_sent_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_events_dropped_total",
+        "Slot events dropped under pressure, over --events-max-per-sec, or failing to send.",
+        metrics.events_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_labeled_counter(
+        &mut out,
+        "shredstream_proxy_priority_dropped_total",
+        "Packets dropped for destinations of each priority because the forwarder fell behind or their isolated send queue was full.",
+        "priority",
+        DestPriority::ALL.iter().map(|priority| {
+            (
+                priority.as_str(),
+                metrics.priority_dropped(*priority).1.load(Ordering::Relaxed),
+            )
+        }),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_spill_spilled_total",
+        "Packets written to disk for spill destinations while they were unhealthy or paused.",
+        metrics.spill_spilled_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_spill_replayed_total",
+        "Spilled packets sent once their destination was back.",
+        metrics.spill_replayed_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_spill_expired_total",
+        "Spilled or queued packets skipped on replay for being older than --spill-max-age-ms.",
+        metrics.spill_expired_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_spill_dropped_total",
+        "Spilled packets overwritten or discarded, and live packets dropped behind a full replay queue.",
+        metrics.spill_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_mirror_sent_total",
+        "Sampled packets sent to --mirror-dest mirrors.",
+        metrics.mirror_sent_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_mirror_failed_total",
+        "Sampled packets that failed to send to --mirror-dest mirrors.",
+        metrics.mirror_failed_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_mirror_shed_total",
+        "Sampled packets not mirrored because the forwarder fell behind.",
+        metrics.mirror_shed_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_chaos_dropped_total",
+        "Packets dropped by --chaos-drop-rate fault injection.",
+        metrics.chaos_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_chaos_duplicated_total",
+        "Packets sent twice by --chaos-duplicate-rate fault injection.",
+        metrics.chaos_duplicated_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_chaos_reordered_total",
+        "Packets sent ahead of older ones by --chaos-reorder-window fault injection.",
+        metrics.chaos_reordered_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_chaos_delayed_total",
+        "Packets held back by --chaos-delay-ms fault injection.",
+        metrics.chaos_delayed_cumulative.load(Ordering::Relaxed),
+    );
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_buffered_bytes",
+        "Bytes buffered in flight per buffer.",
+        "buffer",
+        BufferKind::ALL
+            .iter()
+            .map(|kind| (kind.as_str(), metrics.memory_guard.held(*kind))),
+    );
+
+    write_counter(
+        &mut out,
+        "shredstream_proxy_pcap_recorded_total",
+        "Packets queued for recording to pcap.",
+        metrics.pcap_recorded_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_pcap_dropped_total",
+        "Packets not recorded to pcap because the writer fell behind.",
+        metrics.pcap_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_kafka_published_total",
+        "Shreds acknowledged by the Kafka brokers.",
+        metrics.kafka_published_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_kafka_dropped_total",
+        "Shreds not published to Kafka because the publisher fell behind.",
+        metrics.kafka_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_kafka_delivery_failed_total",
+        "Shreds the Kafka producer failed to deliver.",
+        metrics
+            .kafka_delivery_failed_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_archive_inserted_total",
+        "Shreds written to the archive.",
+        metrics.archive_inserted_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_archive_dropped_total",
+        "Shreds not archived because the archiver fell behind.",
+        metrics.archive_dropped_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_archive_insert_failed_total",
+        "Shreds that failed to parse or to write to the archive.",
+        metrics
+            .archive_insert_failed_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_subscriber_delivered_total",
+        "Shreds handed to in-process subscribers.",
+        metrics
+            .subscriber_delivered_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_subscriber_dropped_total",
+        "Shreds not handed to an in-process subscriber because it fell behind.",
+        metrics
+            .subscriber_dropped_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_healthy_destinations",
+        "Destinations passing health checks, when enabled.",
+        metrics.healthy_destinations.load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_unhealthy_destinations",
+        "Destinations failing health checks and not forwarded to, when enabled.",
+        metrics.unhealthy_destinations.load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_blocked_destinations",
+        "Destinations blocked by the destination blocklist or as the proxy's own address.",
+        metrics.blocked_destinations.load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_dest_cap_exceeded",
+        "1 while there are more destinations than the max destinations and some are dropped.",
+        metrics.dest_cap_exceeded.load(Ordering::Relaxed) as u64,
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_validator_identities_unresolved",
+        "Validator identities missing from the cluster for longer than the alert threshold.",
+        metrics
+            .validator_identities_unresolved
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_slots_finalized_total",
+        "Slots counted for coverage once far enough behind the highest slot.",
+        metrics.slots_finalized_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_slots_last_shred_seen_total",
+        "Finalized slots whose last data shred was received.",
+        metrics
+            .slots_last_shred_seen_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_slots_observed",
+        "Distinct slots shreds were received for in the last metrics interval.",
+        metrics.last_interval_slots_observed.load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_slot_data_shreds_min",
+        "Fewest data shreds received for a slot finalized in the last metrics interval.",
+        metrics
+            .last_interval_slot_data_shreds_min
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_slot_data_shreds_median",
+        "Median data shreds received per slot finalized in the last metrics interval.",
+        metrics
+            .last_interval_slot_data_shreds_median
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_slot_data_shreds_max",
+        "Most data shreds received for a slot finalized in the last metrics interval.",
+        metrics
+            .last_interval_slot_data_shreds_max
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_destination_unhealthy_total",
+        "Times a destination was marked unhealthy by health checks.",
+        metrics
+            .dest_became_unhealthy_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_destination_recovered_total",
+        "Times an unhealthy destination recovered.",
+        metrics
+            .dest_became_healthy_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_thread_restarts_total",
+        "Times a thread was restarted after panicking.",
+        metrics.thread_restarts_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_socket_rebinds_total",
+        "Times a forwarding or listen socket was rebound after persistent errors.",
+        metrics.socket_rebinds_cumulative.load(Ordering::Relaxed),
+    );
+    write_gauge_f64(
+        &mut out,
+        "shredstream_proxy_duplicate_ratio",
+        "Fraction of packets received in the last metrics interval that were duplicates.",
+        metrics.last_interval_duplicate_ppm.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+    );
+    write_gauge_f64(
+        &mut out,
+        "shredstream_proxy_deduper_saturation",
+        "Estimated fraction of the deduper's bits set, as of the last metrics interval.",
+        metrics.deduper_saturation_ppm.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+    );
+    write_gauge_f64(
+        &mut out,
+        "shredstream_proxy_deduper_age_seconds",
+        "Time since the deduper was last reset, as of the last metrics interval.",
+        metrics.deduper_age_ms.load(Ordering::Relaxed) as f64 / 1_000.0,
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_deduper_early_resets_total",
+        "Deduper resets because it saturated before the scheduled reset interval.",
+        metrics
+            .deduper_early_resets_cumulative
+            .load(Ordering::Relaxed),
+    );
+
+    let mut dest_forwarded = metrics
+        .dest_forwarded_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_forwarded.sort_unstable();
+    write_labeled_counter(
+        &mut out,
+        "shredstream_proxy_destination_forward_success_total",
+        "Shreds successfully forwarded per destination.",
+        "addr",
+        dest_forwarded
+            .iter()
+            .map(|(addr, (success, _fail))| (addr, *success)),
+    );
+    write_labeled_counter(
+        &mut out,
+        "shredstream_proxy_destination_forward_fail_total",
+        "Shreds failed to forward per destination.",
+        "addr",
+        dest_forwarded
+            .iter()
+            .map(|(addr, (_success, fail))| (addr, *fail)),
+    );
+    // only populated for destinations with a `rate` option
+    let mut dest_throttled = metrics
+        .dest_throttled_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_throttled.sort_unstable();
+    if !dest_throttled.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_destination_throttled_total",
+            "Shreds dropped by a destination's rate limit.",
+            "addr",
+            dest_throttled.into_iter(),
+        );
+    }
+    // only populated with `--isolated-send-threads`
+    let mut dest_send_queue_dropped = metrics
+        .dest_send_queue_dropped_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_send_queue_dropped.sort_unstable();
+    if !dest_send_queue_dropped.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_destination_send_queue_dropped_total",
+            "Shreds dropped because a destination's send queue was full.",
+            "addr",
+            dest_send_queue_dropped.into_iter(),
+        );
+    }
+    let mut dest_send_queue_depth = metrics
+        .dest_send_queue_depth
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value() as u64))
+        .collect::<Vec<_>>();
+    dest_send_queue_depth.sort_unstable();
+    if !dest_send_queue_depth.is_empty() {
+        write_labeled_gauge(
+            &mut out,
+            "shredstream_proxy_destination_send_queue_depth",
+            "Batches queued for a destination's send thread.",
+            "addr",
+            dest_send_queue_depth.into_iter(),
+        );
+    }
+    // only populated with `--measure-send-latency`
+    let mut dest_slow_sends = metrics
+        .dest_slow_sends_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_slow_sends.sort_unstable();
+    if !dest_slow_sends.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_destination_slow_sends_total",
+            "Sends to a destination slower than the slow send threshold.",
+            "addr",
+            dest_slow_sends.into_iter(),
+        );
+    }
+    let mut dest_emsgsize = metrics
+        .dest_emsgsize_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    dest_emsgsize.sort_unstable();
+    if !dest_emsgsize.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_destination_emsgsize_total",
+            "Sends to a destination that failed with EMSGSIZE, too large for its path MTU.",
+            "addr",
+            dest_emsgsize.into_iter(),
+        );
+    }
+    // only populated for members of a `dest-shard-group`
+    let mut shard_assigned = metrics
+        .shard_assigned_cumulative
+        .iter()
+        .map(|kv| (*kv.key(), *kv.value()))
+        .collect::<Vec<_>>();
+    shard_assigned.sort_unstable();
+    if !shard_assigned.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_shard_member_assigned_total",
+            "Shreds sent to each member of a shard group.",
+            "addr",
+            shard_assigned.into_iter(),
+        );
+    }
+
+    // only populated when listening on a port per region
+    let mut region_received = metrics
+        .region_received_cumulative
+        .iter()
+        .map(|kv| (kv.key().clone(), *kv.value()))
+        .collect::<Vec<_>>();
+    region_received.sort_unstable();
+    if !region_received.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_region_received_total",
+            "Shreds received per region, including duplicates.",
+            "region",
+            region_received
+                .iter()
+                .map(|(region, (received, _duplicate))| (region, *received)),
+        );
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_region_duplicate_total",
+            "Duplicate shreds received per region, already received from any region.",
+            "region",
+            region_received
+                .iter()
+                .map(|(region, (_received, duplicate))| (region, *duplicate)),
+        );
+    }
+
+    // only populated with --listener
+    let mut listener_received = metrics
+        .listener_received_cumulative
+        .iter()
+        .map(|kv| (kv.key().clone(), *kv.value()))
+        .collect::<Vec<_>>();
+    listener_received.sort_unstable();
+    if !listener_received.is_empty() {
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_listener_received_total",
+            "Shreds received per --listener, including duplicates.",
+            "listener",
+            listener_received
+                .iter()
+                .map(|(listener, (received, _duplicate))| (listener, *received)),
+        );
+        write_labeled_counter(
+            &mut out,
+            "shredstream_proxy_listener_duplicate_total",
+            "Duplicate shreds received per --listener, deduped against that listener only.",
+            "listener",
+            listener_received
+                .iter()
+                .map(|(listener, (_received, duplicate))| (listener, *duplicate)),
+        );
+    }
+
+    write_counter(
+        &mut out,
+        "shredstream_proxy_heartbeat_success_total",
+        "Heartbeats successfully sent to the block engine.",
+        metrics
+            .successful_heartbeat_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_heartbeat_fail_total",
+        "Heartbeats failed to send to the block engine.",
+        metrics.failed_heartbeat_cumulative.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_block_engine_failover_total",
+        "Times the heartbeat failed over to the next block engine.",
+        metrics
+            .block_engine_failovers_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_stall_reregistration_total",
+        "Times the heartbeat re-registered after receiving no shreds while heartbeats succeeded.",
+        metrics
+            .stall_reregistrations_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_admin_destination_expired_total",
+        "Destinations added via the admin API removed once their TTL expired.",
+        metrics
+            .admin_dest_expired_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_public_ip_changes_total",
+        "Times the rechecked public IP changed, re-registering the new address with the block engine.",
+        metrics.public_ip_changes_cumulative.load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_paused",
+        "1 while heartbeats are paused for maintenance, forwarding continues.",
+        metrics.heartbeat_paused.load(Ordering::Relaxed) as u64,
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_token_refresh_total",
+        "Block engine token refreshes attempted before expiry, including full re-auths.",
+        metrics
+            .token_refresh_attempts_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_token_refresh_fail_total",
+        "Block engine token refreshes that failed and were retried.",
+        metrics
+            .token_refresh_failures_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_access_token_ttl_seconds",
+        "Seconds until the block engine access token expires.",
+        metrics.access_token_ttl_secs.load(Ordering::Relaxed),
+    );
+    write_labeled_counter(
+        &mut out,
+        "shredstream_proxy_discovery_failures_total",
+        "Failed endpoint discovery requests, `auth` when rejected with 401 or 403.",
+        "reason",
+        [
+            (
+                "auth",
+                metrics
+                    .discovery_auth_failures_cumulative
+                    .load(Ordering::Relaxed),
+            ),
+            (
+                "other",
+                metrics
+                    .discovery_failures_cumulative
+                    .load(Ordering::Relaxed),
+            ),
+        ]
+        .into_iter(),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_discovery_not_modified_total",
+        "Endpoint discovery requests answered with 304 Not Modified.",
+        metrics
+            .discovery_not_modified_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_discovery_mass_removals_held_total",
+        "Endpoint discovery responses held back for removing too many endpoints at once.",
+        metrics
+            .discovery_mass_removals_held_cumulative
+            .load(Ordering::Relaxed),
+    );
+    write_gauge(
+        &mut out,
+        "shredstream_proxy_discovery_using_saved_state",
+        "1 while forwarding to destinations loaded from the discovery state file because discovery hasn't succeeded since startup.",
+        metrics.discovery_using_saved_state.load(Ordering::Relaxed) as u64,
+    );
+    if let Some(discovery_age) = metrics.discovery_age(SystemTime::now()) {
+        write_gauge(
+            &mut out,
+            "shredstream_proxy_discovery_last_success_age_seconds",
+            "Seconds since endpoint discovery last succeeded, including unchanged responses.",
+            discovery_age.as_secs(),
+        );
+    }
+    if let Some(active_block_engine_url) = metrics.active_block_engine_url.load_full() {
+        write_labeled_gauge(
+            &mut out,
+            "shredstream_proxy_active_block_engine",
+            "Block engine currently heartbeating to, always 1.",
+            "url",
+            [(active_block_engine_url, 1)].into_iter(),
+        );
+    }
+    let now = Instant::now();
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_state",
+        "Heartbeat state per region: 0 disconnected, 1 authenticating, 2 active, 3 paused.",
+        "region",
+        metrics
+            .heartbeat_regions
+            .iter()
+            .map(|kv| (kv.key().clone(), kv.state as u64)),
+    );
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_consecutive_failures",
+        "Heartbeats failed in a row per region.",
+        "region",
+        metrics
+            .heartbeat_regions
+            .iter()
+            .map(|kv| (kv.key().clone(), kv.consecutive_failures)),
+    );
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_last_success_age_seconds",
+        "Seconds since a heartbeat last succeeded per region, or since the region was first tracked.",
+        "region",
+        metrics.heartbeat_regions.iter().map(|kv| {
+            (
+                kv.key().clone(),
+                now.saturating_duration_since(kv.last_success).as_secs(),
+            )
+        }),
+    );
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_rtt_microseconds",
+        "Round trip time of the last heartbeat per region.",
+        "region",
+        metrics
+            .heartbeat_regions
+            .iter()
+            .filter_map(|kv| Some((kv.key().clone(), kv.last_rtt?.as_micros() as u64))),
+    );
+    write_labeled_gauge(
+        &mut out,
+        "shredstream_proxy_heartbeat_ttl_milliseconds",
+        "Heartbeat TTL requested by the block engine per region, heartbeats are sent every third of it.",
+        "region",
+        metrics
+            .heartbeat_regions
+            .iter()
+            .filter_map(|kv| Some((kv.key().clone(), kv.ttl_ms? as u64))),
+    );
+
+    write_counter(
+        &mut out,
+        "shredstream_proxy_listen_packets_total",
+        "Packets read from the listen sockets.",
+        receive_totals.packets_count.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_listen_packet_batches_total",
+        "Packet batches read from the listen sockets.",
+        receive_totals.packet_batches_count.load(Ordering::Relaxed),
+    );
+    write_counter(
+        &mut out,
+        "shredstream_proxy_listen_full_packet_batches_total",
+        "Packet batches read from the listen sockets that hit the max batch size.",
+        receive_totals
+            .full_packet_batches_count
+            .load(Ordering::Relaxed),
+    );
+    out
+}
+
+/// Info metric, always 1, with the build details as labels
+fn write_build_info(out: &mut String, info: &BuildInfo) {
+    let name = "shredstream_proxy_build_info";
+    let _ = writeln!(
+        out,
+        "# HELP {name} Build of the running proxy, always 1.\n# TYPE {name} gauge\n\
+         {name}{{version=\"{}\",git_hash=\"{}\",build_timestamp=\"{}\",rustc_version=\"{}\",features=\"{}\"}} 1",
+        info.version,
+        info.git_hash,
+        info.build_timestamp,
+        info.rustc_version,
+        info.features.join(","),
+    );
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+    );
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+    );
+}
+
+fn write_gauge_f64(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(
+        out,
+        "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}"
+    );
+}
+
+fn write_labeled_gauge<L: Display>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: impl Iterator<Item = (L, u64)>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} gauge");
+    values.for_each(|(label_value, value)| {
+        let _ = writeln!(out, "{name}{{{label}=\"{label_value}\"}} {value}");
+    });
+}
+
+fn write_labeled_counter<L: Display>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    label: &str,
+    values: impl Iterator<Item = (L, u64)>,
+) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+    values.for_each(|(label_value, value)| {
+        let _ = writeln!(out, "{name}{{{label}=\"{label_value}\"}} {value}");
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        str::FromStr,
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant},
+    };
+
+    use histogram::Histogram;
+
+    use crate::{
+        build_info,
+        forwarder::ShredMetrics,
+        heartbeat::{HeartbeatState, RegionHeartbeatStats},
+        prometheus::{render_metrics, ReceiveStatsTotals},
+    };
+
+    #[test]
+    fn test_render_metrics() {
+        let metrics = ShredMetrics::new();
+        metrics.agg_received_cumulative.store(5, Ordering::Relaxed);
+        metrics
+            .dest_forwarded_cumulative
+            .insert(SocketAddr::from_str("127.0.0.1:8001").unwrap(), (4, 1));
+        metrics
+            .region_received_cumulative
+            .insert("ny".to_string(), (6, 2));
+        metrics
+            .listener_received_cumulative
+            .insert("fleet-b".to_string(), (9, 3));
+        metrics
+            .active_block_engine_url
+            .store(Some(Arc::new("https://ny.block-engine".to_string())));
+        metrics
+            .discovery_auth_failures_cumulative
+            .store(3, Ordering::Relaxed);
+        metrics.heartbeat_regions.insert(
+            "ny".to_string(),
+            RegionHeartbeatStats {
+                state: HeartbeatState::Active,
+                rtt_us: Histogram::new(),
+                last_rtt: Some(Duration::from_micros(1_500)),
+                consecutive_failures: 0,
+                last_success: Instant::now(),
+                ttl_ms: Some(600),
+            },
+        );
+        let receive_totals = ReceiveStatsTotals::default();
+        receive_totals.packets_count.store(7, Ordering::Relaxed);
+
+        let rendered = render_metrics(&metrics, &receive_totals);
+
+        assert!(rendered.contains(&format!(
+            "\nshredstream_proxy_build_info{{version=\"{}\",git_hash=\"{}\",",
+            build_info::VERSION,
+            build_info::GIT_HASH
+        )));
+        assert!(rendered.contains("# TYPE shredstream_proxy_received_total counter\n"));
+        assert!(rendered.contains("\nshredstream_proxy_received_total 5\n"));
+        assert!(rendered.contains(
+            "\nshredstream_proxy_destination_forward_success_total{addr=\"127.0.0.1:8001\"} 4\n"
+        ));
+        assert!(rendered.contains(
+            "\nshredstream_proxy_destination_forward_fail_total{addr=\"127.0.0.1:8001\"} 1\n"
+        ));
+        assert!(rendered.contains("\nshredstream_proxy_region_received_total{region=\"ny\"} 6\n"));
+        assert!(rendered.contains("\nshredstream_proxy_region_duplicate_total{region=\"ny\"} 2\n"));
+        assert!(rendered
+            .contains("\nshredstream_proxy_listener_received_total{listener=\"fleet-b\"} 9\n"));
+        assert!(rendered
+            .contains("\nshredstream_proxy_listener_duplicate_total{listener=\"fleet-b\"} 3\n"));
+        assert!(rendered.contains("\nshredstream_proxy_listen_packets_total 7\n"));
+        assert!(rendered.contains(
+            "\nshredstream_proxy_active_block_engine{url=\"https://ny.block-engine\"} 1\n"
+        ));
+        assert!(
+            rendered.contains("\nshredstream_proxy_discovery_failures_total{reason=\"auth\"} 3\n")
+        );
+        assert!(
+            rendered.contains("\nshredstream_proxy_discovery_failures_total{reason=\"other\"} 0\n")
+        );
+        assert!(rendered.contains("\nshredstream_proxy_heartbeat_state{region=\"ny\"} 2\n"));
+        assert!(rendered
+            .contains("\nshredstream_proxy_heartbeat_rtt_microseconds{region=\"ny\"} 1500\n"));
+        assert!(rendered
+            .contains("\nshredstream_proxy_heartbeat_ttl_milliseconds{region=\"ny\"} 600\n"));
+        assert!(rendered.contains("\nshredstream_proxy_heartbeat_paused 0\n"));
+        assert!(rendered.contains("\nshredstream_proxy_discovery_using_saved_state 0\n"));
+    }
+}
@@ -0,0 +1,461 @@
+use std::{
+    collections::HashSet,
+    io::{self, ErrorKind},
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, OnceLock,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use log::{error, info, warn};
+use quinn::{
+    crypto::rustls::{QuicClientConfig, QuicServerConfig},
+    ClientConfig, Connection, Endpoint, EndpointConfig, IdleTimeout, Incoming, ServerConfig,
+    TransportConfig,
+};
+use solana_quic_client::nonblocking::quic_client::SkipServerVerification;
+use solana_sdk::{packet::PACKET_DATA_SIZE, signature::Keypair};
+use solana_streamer::{
+    sendmmsg::{batch_send, SendPktsError},
+    tls_certificates::new_dummy_x509_certificate,
+};
+use thiserror::Error;
+use tokio::{runtime::Runtime, sync::mpsc};
+
+use crate::{
+    forwarder::{ShredMetrics, ShredSink, Transport},
+    socket,
+};
+
+/// Prefix marking a destination in `dest-ip-ports` as QUIC, eg. `quic://10.0.0.1:20001`
+pub const QUIC_SCHEME: &str = "quic://";
+
+const ALPN_SHREDSTREAM_PROTOCOL_ID: &[u8] = b"shredstream-proxy";
+/// Server name sent during the handshake. Certificates are self-signed and not verified
+const SERVER_NAME: &str = "shredstream-proxy";
+
+/// Each packet is framed as a little-endian u16 length followed by the packet data
+pub(crate) const FRAME_HEADER_LEN: usize = 2;
+/// Max packets written to a single stream, bounds how much the receiver buffers per stream
+const MAX_PACKETS_PER_STREAM: usize = 1024;
+const MAX_STREAM_LEN: usize = MAX_PACKETS_PER_STREAM * (FRAME_HEADER_LEN + PACKET_DATA_SIZE);
+
+/// Batches queued per destination while connecting or sending. New batches are dropped when full
+const SEND_QUEUE_CAPACITY: usize = 1024;
+/// Min time between connection attempts to a destination, batches are dropped in the meantime
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+const QUIC_KEEP_ALIVE: Duration = Duration::from_secs(1);
+const QUIC_MAX_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+enum QuicSendError {
+    #[error("ConnectError {0}")]
+    ConnectError(#[from] quinn::ConnectError),
+    #[error("ConnectionError {0}")]
+    ConnectionError(#[from] quinn::ConnectionError),
+    #[error("WriteError {0}")]
+    WriteError(#[from] quinn::WriteError),
+    #[error("ClosedStream {0}")]
+    ClosedStream(#[from] quinn::ClosedStream),
+}
+
+/// Forwards packets to QUIC destinations, sharing one connection per destination across forwarder threads.
+/// Sends are queued and written by a background task per destination, which reconnects after failures.
+pub struct QuicSink {
+    /// Created on first send, since most deployments have no QUIC destinations
+    client: OnceLock<Result<QuicClient, String>>,
+    /// Send queue of (frames, number of packets) per destination
+    connections: DashMap<SocketAddr, mpsc::Sender<(Vec<u8>, usize)>>,
+    metrics: Arc<ShredMetrics>,
+}
+
+struct QuicClient {
+    runtime: Runtime,
+    endpoint: Endpoint,
+}
+
+impl QuicSink {
+    pub fn new(metrics: Arc<ShredMetrics>) -> Self {
+        Self {
+            client: OnceLock::new(),
+            connections: DashMap::new(),
+            metrics,
+        }
+    }
+
+    /// Closes connections to destinations no longer forwarded to over QUIC
+    pub fn retain_destinations(&self, quic_dest_sockets: &HashSet<SocketAddr>) {
+        self.connections
+            .retain(|addr, _| quic_dest_sockets.contains(addr));
+    }
+
+    fn client(&self) -> Option<&QuicClient> {
+        self.client
+            .get_or_init(|| {
+                new_quic_client().map_err(|e| {
+                    error!("Failed to create QUIC client, QUIC destinations will fail. Error: {e}");
+                    e.to_string()
+                })
+            })
+            .as_ref()
+            .ok()
+    }
+}
+
+impl ShredSink for QuicSink {
+    fn send(&self, dest: SocketAddr, packets: &[&[u8]]) {
+        if packets.is_empty() {
+            return;
+        }
+        let Some(client) = self.client() else {
+            self.metrics
+                .record_forward(dest, Transport::Quic, 0, packets.len() as u64);
+            return;
+        };
+        // clone so the map isn't locked while queueing
+        let sender = self
+            .connections
+            .entry(dest)
+            .or_insert_with(|| {
+                let (sender, receiver) = mpsc::channel(SEND_QUEUE_CAPACITY);
+                client.runtime.spawn(run_connection(
+                    client.endpoint.clone(),
+                    dest,
+                    receiver,
+                    self.metrics.clone(),
+                ));
+                sender
+            })
+            .clone();
+
+        packets.chunks(MAX_PACKETS_PER_STREAM).for_each(|chunk| {
+            // queue is full when the destination can't keep up or is unreachable
+            if sender
+                .try_send((encode_frames(chunk), chunk.len()))
+                .is_err()
+            {
+                self.metrics
+                    .record_forward(dest, Transport::Quic, 0, chunk.len() as u64);
+            }
+        });
+    }
+}
+
+/// Writes queued batches to `dest`, one stream per batch, until the sender is dropped
+async fn run_connection(
+    endpoint: Endpoint,
+    dest: SocketAddr,
+    mut receiver: mpsc::Receiver<(Vec<u8>, usize)>,
+    metrics: Arc<ShredMetrics>,
+) {
+    let mut connection: Option<Connection> = None;
+    let mut next_connect_attempt = Instant::now();
+    while let Some((frames, num_packets)) = receiver.recv().await {
+        let conn = match connection.take().filter(|c| c.close_reason().is_none()) {
+            Some(conn) => conn,
+            None if Instant::now() < next_connect_attempt => {
+                metrics.record_forward(dest, Transport::Quic, 0, num_packets as u64);
+                continue;
+            }
+            None => match connect(&endpoint, dest).await {
+                Ok(conn) => {
+                    info!("Connected to QUIC destination {dest}.");
+                    conn
+                }
+                Err(e) => {
+                    warn!("Failed to connect to QUIC destination {dest}, retrying in {RECONNECT_BACKOFF:?}. Error: {e}");
+                    next_connect_attempt = Instant::now() + RECONNECT_BACKOFF;
+                    metrics.record_forward(dest, Transport::Quic, 0, num_packets as u64);
+                    continue;
+                }
+            },
+        };
+
+        match write_stream(&conn, &frames).await {
+            Ok(()) => {
+                metrics.record_forward(dest, Transport::Quic, num_packets as u64, 0);
+                connection = Some(conn);
+            }
+            Err(e) => {
+                warn!("Failed to send batch of size {num_packets} to QUIC destination {dest}, reconnecting. Error: {e}");
+                metrics.record_forward(dest, Transport::Quic, 0, num_packets as u64);
+            }
+        }
+    }
+
+    if let Some(conn) = connection {
+        conn.close(0u32.into(), b"destination removed");
+    }
+    info!("Closed QUIC destination {dest}.");
+}
+
+async fn connect(endpoint: &Endpoint, dest: SocketAddr) -> Result<Connection, QuicSendError> {
+    Ok(endpoint.connect(dest, SERVER_NAME)?.await?)
+}
+
+async fn write_stream(connection: &Connection, frames: &[u8]) -> Result<(), QuicSendError> {
+    let mut stream = connection.open_uni().await?;
+    stream.write_all(frames).await?;
+    stream.finish()?;
+    Ok(())
+}
+
+fn new_quic_client() -> io::Result<QuicClient> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .thread_name("ssPxyQuicTx")
+        .enable_all()
+        .build()?;
+
+    let mut crypto = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .map_err(|e| io::Error::new(ErrorKind::Other, e))?
+    .dangerous()
+    .with_custom_certificate_verifier(SkipServerVerification::new())
+    .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN_SHREDSTREAM_PROTOCOL_ID.to_vec()];
+    let mut client_config = ClientConfig::new(Arc::new(
+        QuicClientConfig::try_from(crypto).map_err(|e| io::Error::new(ErrorKind::Other, e))?,
+    ));
+    client_config.transport_config(Arc::new(transport_config()));
+
+    // endpoint needs a runtime context to spawn its driver.
+    // quinn maps IPv4 destinations itself when the socket is dual-stack
+    let mut endpoint = {
+        let _guard = runtime.enter();
+        Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            socket::bind_send_socket()?,
+            quinn::default_runtime()
+                .ok_or_else(|| io::Error::new(ErrorKind::Other, "No async runtime found"))?,
+        )?
+    };
+    endpoint.set_default_client_config(client_config);
+
+    Ok(QuicClient { runtime, endpoint })
+}
+
+fn new_server_config() -> io::Result<ServerConfig> {
+    let (cert, key) = new_dummy_x509_certificate(&Keypair::new());
+    let mut crypto = rustls::ServerConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_protocol_versions(&[&rustls::version::TLS13])
+    .map_err(|e| io::Error::new(ErrorKind::Other, e))?
+    .with_no_client_auth()
+    .with_single_cert(vec![cert], key)
+    .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+    crypto.alpn_protocols = vec![ALPN_SHREDSTREAM_PROTOCOL_ID.to_vec()];
+    let mut server_config = ServerConfig::with_crypto(Arc::new(
+        QuicServerConfig::try_from(crypto).map_err(|e| io::Error::new(ErrorKind::Other, e))?,
+    ));
+    server_config.transport_config(Arc::new(transport_config()));
+    Ok(server_config)
+}
+
+fn transport_config() -> TransportConfig {
+    let mut config = TransportConfig::default();
+    config.max_idle_timeout(Some(IdleTimeout::try_from(QUIC_MAX_IDLE_TIMEOUT).unwrap()));
+    config.keep_alive_interval(Some(QUIC_KEEP_ALIVE));
+    config
+}
+
+/// Accepts connections from proxies forwarding to `quic://` destinations, re-emitting packets over UDP to `dest_sockets`.
+/// Connections are not authenticated, restrict access to `bind_addr` with a firewall.
+/// Returns the address bound, which differs from `bind_addr` when its port is 0.
+pub fn start_quic_receiver_thread(
+    bind_addr: SocketAddr,
+    dest_sockets: Vec<SocketAddr>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(JoinHandle<()>, SocketAddr)> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("ssPxyQuicRx")
+        .enable_all()
+        .build()?;
+    let endpoint = {
+        let _guard = runtime.enter();
+        Endpoint::server(new_server_config()?, bind_addr)?
+    };
+    let local_addr = endpoint.local_addr()?;
+    let send_socket = socket::bind_send_socket()?;
+    info!("Receiving QUIC on {local_addr}, forwarding to {dest_sockets:?}.");
+    let ipv6_socket = send_socket.local_addr()?.is_ipv6();
+    let dest_sockets = Arc::new(
+        dest_sockets
+            .into_iter()
+            .map(|dest| socket::send_addr(ipv6_socket, dest))
+            .collect::<Vec<_>>(),
+    );
+    let send_socket = Arc::new(send_socket);
+
+    let hdl = Builder::new()
+        .name("ssPxyQuicRecv".to_string())
+        .spawn(move || {
+            runtime.block_on(async {
+                while !exit.load(Ordering::Relaxed) {
+                    // use timeout so we periodically check for exit
+                    let Ok(incoming) =
+                        tokio::time::timeout(Duration::from_millis(500), endpoint.accept()).await
+                    else {
+                        continue;
+                    };
+                    let Some(incoming) = incoming else {
+                        break;
+                    };
+                    tokio::spawn(handle_connection(
+                        incoming,
+                        send_socket.clone(),
+                        dest_sockets.clone(),
+                    ));
+                }
+                endpoint.close(0u32.into(), b"exit");
+            });
+            info!("Exiting QUIC receiver thread.");
+        })?;
+    Ok((hdl, local_addr))
+}
+
+async fn handle_connection(
+    incoming: Incoming,
+    send_socket: Arc<UdpSocket>,
+    dest_sockets: Arc<Vec<SocketAddr>>,
+) {
+    let remote_addr = incoming.remote_address();
+    let connection = match incoming.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!("Failed to accept QUIC connection from {remote_addr}. Error: {e}");
+            return;
+        }
+    };
+    info!("Accepted QUIC connection from {remote_addr}.");
+
+    loop {
+        let mut stream = match connection.accept_uni().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                info!("QUIC connection from {remote_addr} closed. Reason: {e}");
+                return;
+            }
+        };
+        let send_socket = send_socket.clone();
+        let dest_sockets = dest_sockets.clone();
+        tokio::spawn(async move {
+            let frames = match stream.read_to_end(MAX_STREAM_LEN).await {
+                Ok(frames) => frames,
+                Err(e) => {
+                    warn!("Failed to read QUIC stream from {remote_addr}. Error: {e}");
+                    return;
+                }
+            };
+            let Some(packets) = decode_frames(&frames) else {
+                warn!("Dropping malformed QUIC stream from {remote_addr}.");
+                return;
+            };
+            dest_sockets.iter().for_each(|outgoing_socketaddr| {
+                let packets_with_dest = packets
+                    .iter()
+                    .map(|data| (*data, outgoing_socketaddr))
+                    .collect::<Vec<(&[u8], &SocketAddr)>>();
+                if let Err(SendPktsError::IoError(err, num_failed)) =
+                    batch_send(&send_socket, &packets_with_dest)
+                {
+                    error!("Failed to send batch of size {} to {outgoing_socketaddr:?}. {num_failed} packets failed. Error: {err}", packets_with_dest.len());
+                }
+            });
+        });
+    }
+}
+
+/// Encodes packets as length prefixed frames
+pub(crate) fn encode_frames(packets: &[&[u8]]) -> Vec<u8> {
+    let mut frames = Vec::with_capacity(
+        packets
+            .iter()
+            .map(|packet| FRAME_HEADER_LEN + packet.len())
+            .sum(),
+    );
+    packets.iter().for_each(|packet| {
+        frames.extend_from_slice(&(packet.len() as u16).to_le_bytes());
+        frames.extend_from_slice(packet);
+    });
+    frames
+}
+
+/// Decodes frames written by [encode_frames]. Returns None if the frames are truncated
+fn decode_frames(mut frames: &[u8]) -> Option<Vec<&[u8]>> {
+    let mut packets = Vec::new();
+    while !frames.is_empty() {
+        let header = frames.get(..FRAME_HEADER_LEN)?;
+        let len = u16::from_le_bytes(header.try_into().ok()?) as usize;
+        packets.push(frames.get(FRAME_HEADER_LEN..FRAME_HEADER_LEN + len)?);
+        frames = &frames[FRAME_HEADER_LEN + len..];
+    }
+    Some(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{SocketAddr, UdpSocket},
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use crate::{
+        forwarder::{ShredMetrics, ShredSink},
+        quic::{decode_frames, encode_frames, start_quic_receiver_thread, QuicSink},
+    };
+
+    #[test]
+    fn test_frames_roundtrip() {
+        let packets: Vec<&[u8]> = vec![&[1; 1228], &[], &[2; 3]];
+        let frames = encode_frames(&packets);
+        assert_eq!(decode_frames(&frames).unwrap(), packets);
+
+        assert!(decode_frames(&frames[..frames.len() - 1]).is_none());
+        assert!(decode_frames(&[5]).is_none());
+        assert_eq!(decode_frames(&[]).unwrap(), Vec::<&[u8]>::new());
+    }
+
+    #[test]
+    fn test_quic_sink_to_receiver() {
+        let udp_listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        udp_listener
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        let exit = Arc::new(AtomicBool::new(false));
+        let (receiver_hdl, quic_addr) = start_quic_receiver_thread(
+            SocketAddr::from_str("127.0.0.1:0").unwrap(),
+            vec![udp_listener.local_addr().unwrap()],
+            exit.clone(),
+        )
+        .unwrap();
+
+        let metrics = Arc::new(ShredMetrics::new());
+        let quic_sink = QuicSink::new(metrics.clone());
+        quic_sink.send(quic_addr, &[&[1; 1228], &[2; 1228]]);
+
+        let mut buf = [0u8; 2048];
+        let len = udp_listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], [1; 1228]);
+        let len = udp_listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], [2; 1228]);
+        assert_eq!(*metrics.dest_forwarded.get(&quic_addr).unwrap(), (2, 0));
+        assert_eq!(metrics.quic_fail_forward.load(Ordering::Relaxed), 0);
+
+        exit.store(true, Ordering::Relaxed);
+        receiver_hdl.join().unwrap();
+    }
+}
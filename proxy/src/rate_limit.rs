@@ -0,0 +1,205 @@
+use std::{net::IpAddr, path::PathBuf, sync::Mutex, time::Instant};
+
+use crate::{priority::DestPriority, socket::Egress};
+
+/// Separates per-destination options from a `dest-ip-ports` entry, eg. `1.2.3.4:8001?rate=5000pps`
+pub fn split_dest_options(hostname_port: &str) -> (&str, Option<&str>) {
+    match hostname_port.split_once('?') {
+        Some((hostname_port, options)) => (hostname_port, Some(options)),
+        None => (hostname_port, None),
+    }
+}
+
+/// Options of a `dest-ip-ports` entry
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DestOptions {
+    /// `rate=<n>pps`
+    pub rate_pps: Option<u64>,
+    /// `iface=<name>` and `src=<ip>`, overriding `--egress-interface` and `--egress-source-ip`
+    pub egress: Egress,
+    /// `key-file=<path>`, the key an `enc://` destination is sealed with
+    pub key_file: Option<PathBuf>,
+    /// `priority=high|normal|low`
+    pub priority: DestPriority,
+    /// `spill=true`, spill shreds to `spill-dir` while the destination is unhealthy or paused, and replay them once it's back
+    pub spill: bool,
+}
+
+/// Parses the options of a `dest-ip-ports` entry, erroring on unknown or malformed options
+pub fn parse_dest_options(hostname_port: &str) -> Result<DestOptions, String> {
+    let Some(options) = split_dest_options(hostname_port).1 else {
+        return Ok(DestOptions::default());
+    };
+    let mut dest_options = DestOptions::default();
+    for option in options.split('&') {
+        match option.split_once('=') {
+            Some(("rate", rate)) => {
+                let rate = rate
+                    .strip_suffix("pps")
+                    .and_then(|rate| rate.parse::<u64>().ok())
+                    .filter(|rate| *rate > 0)
+                    .ok_or_else(|| {
+                        format!(
+                            "Invalid rate {rate:?}, expected packets per second such as `5000pps`."
+                        )
+                    })?;
+                dest_options.rate_pps = Some(rate);
+            }
+            Some(("iface", interface)) if !interface.is_empty() => {
+                dest_options.egress.interface = Some(interface.to_string());
+            }
+            Some(("src", source_ip)) => {
+                let source_ip = source_ip.parse::<IpAddr>().map_err(|_| {
+                    format!(
+                        "Invalid source address {source_ip:?}, expected an IP such as `10.0.0.5`."
+                    )
+                })?;
+                dest_options.egress.source_ip = Some(source_ip);
+            }
+            Some(("key-file", key_file)) if !key_file.is_empty() => {
+                dest_options.key_file = Some(PathBuf::from(key_file));
+            }
+            Some(("priority", priority)) => {
+                dest_options.priority = priority.parse()?;
+            }
+            Some(("spill", spill)) => {
+                dest_options.spill = spill
+                    .parse()
+                    .map_err(|_| format!("Invalid spill {spill:?}, expected `true` or `false`."))?;
+            }
+            _ => return Err(format!("Unknown destination option {option:?}.")),
+        }
+    }
+    Ok(dest_options)
+}
+
+/// Returns the packets per second limit set by a `rate=<n>pps` option, if any
+pub fn parse_dest_rate_limit(hostname_port: &str) -> Result<Option<u64>, String> {
+    parse_dest_options(hostname_port).map(|dest_options| dest_options.rate_pps)
+}
+
+/// Token bucket holding up to a second of packets. Shared by forwarder threads sending to the same destination
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate_pps: u64,
+    /// (tokens, when last refilled)
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    pub fn new(rate_pps: u64, now: Instant) -> Self {
+        Self {
+            rate_pps,
+            bucket: Mutex::new((rate_pps as f64, now)),
+        }
+    }
+
+    pub fn rate_pps(&self) -> u64 {
+        self.rate_pps
+    }
+
+    /// Takes a token for up to `num_packets` packets, returning how many can be sent. The rest should be dropped
+    pub fn acquire(&self, num_packets: usize, now: Instant) -> usize {
+        let mut bucket = self.bucket.lock().unwrap();
+        let (tokens, last_refill) = &mut *bucket;
+        let elapsed = now.saturating_duration_since(*last_refill);
+        *tokens =
+            (*tokens + elapsed.as_secs_f64() * self.rate_pps as f64).min(self.rate_pps as f64);
+        *last_refill = now.max(*last_refill);
+        let allowed = (*tokens as usize).min(num_packets);
+        *tokens -= allowed as f64;
+        allowed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{
+        priority::DestPriority,
+        rate_limit::{
+            parse_dest_options, parse_dest_rate_limit, split_dest_options, DestOptions, RateLimiter,
+        },
+        socket::Egress,
+    };
+
+    #[test]
+    fn test_parse_dest_rate_limit() {
+        assert_eq!(
+            split_dest_options("1.2.3.4:8001?rate=5000pps"),
+            ("1.2.3.4:8001", Some("rate=5000pps"))
+        );
+        assert_eq!(parse_dest_rate_limit("1.2.3.4:8001"), Ok(None));
+        assert_eq!(
+            parse_dest_rate_limit("quic://1.2.3.4:8001?rate=5000pps"),
+            Ok(Some(5000))
+        );
+        assert!(parse_dest_rate_limit("1.2.3.4:8001?rate=5000").is_err());
+        assert!(parse_dest_rate_limit("1.2.3.4:8001?rate=0pps").is_err());
+        assert!(parse_dest_rate_limit("1.2.3.4:8001?burst=10").is_err());
+    }
+
+    #[test]
+    fn test_parse_dest_egress() {
+        assert_eq!(
+            parse_dest_options("1.2.3.4:8001?rate=100pps&iface=eth1&src=10.0.0.5"),
+            Ok(DestOptions {
+                rate_pps: Some(100),
+                egress: Egress {
+                    interface: Some("eth1".to_string()),
+                    source_ip: Some("10.0.0.5".parse().unwrap()),
+                },
+                key_file: None,
+                priority: DestPriority::Normal,
+                spill: false,
+            })
+        );
+        assert_eq!(
+            parse_dest_options("[::1]:8001?src=::1").unwrap().egress,
+            Egress {
+                interface: None,
+                source_ip: Some("::1".parse().unwrap()),
+            }
+        );
+        // egress options don't affect the rate
+        assert_eq!(parse_dest_rate_limit("1.2.3.4:8001?iface=eth1"), Ok(None));
+        assert!(parse_dest_options("1.2.3.4:8001?iface=").is_err());
+        assert!(parse_dest_options("1.2.3.4:8001?src=eth1").is_err());
+        assert_eq!(
+            parse_dest_options("enc://1.2.3.4:8001?key-file=/etc/peer.key")
+                .unwrap()
+                .key_file,
+            Some("/etc/peer.key".into())
+        );
+        assert!(parse_dest_options("enc://1.2.3.4:8001?key-file=").is_err());
+        assert_eq!(
+            parse_dest_options("1.2.3.4:8001?rate=100pps&priority=high")
+                .unwrap()
+                .priority,
+            DestPriority::High
+        );
+        assert!(parse_dest_options("1.2.3.4:8001?priority=urgent").is_err());
+        assert!(parse_dest_options("1.2.3.4:8001?spill=true").unwrap().spill);
+        assert!(parse_dest_options("1.2.3.4:8001?spill=yes").is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let start = Instant::now();
+        let rate_limiter = RateLimiter::new(100, start);
+
+        // starts with a full second of tokens
+        assert_eq!(rate_limiter.acquire(64, start), 64);
+        assert_eq!(rate_limiter.acquire(64, start), 36);
+        assert_eq!(rate_limiter.acquire(64, start), 0);
+
+        // refills at the rate
+        let later = start + Duration::from_millis(100);
+        assert_eq!(rate_limiter.acquire(64, later), 10);
+
+        // never holds more than a second of tokens
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(rate_limiter.acquire(1_000, much_later), 100);
+    }
+}
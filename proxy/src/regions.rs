@@ -0,0 +1,169 @@
+use std::{
+    io::{self, ErrorKind},
+    sync::{atomic::AtomicBool, Arc},
+    time::Duration,
+};
+
+use jito_protos::{
+    auth::{auth_service_client::AuthServiceClient, Role},
+    searcher::{searcher_service_client::SearcherServiceClient, GetRegionsRequest},
+};
+use log::{info, warn};
+use solana_sdk::signature::Keypair;
+use tokio::runtime::Runtime;
+
+use crate::{
+    forwarder::ShredMetrics,
+    grpc_channel_config, read_auth_keypair,
+    token_authenticator::{create_grpc_channel, ClientInterceptor, GrpcChannelConfig, TokenCache},
+    ShredstreamArgs, ShredstreamProxyError,
+};
+
+/// Bounds startup on a block engine that accepts connections but never answers
+const REGIONS_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Returns the regions the primary `block-engine-url` accepts heartbeats for, as printed by `--list-regions`
+pub fn list_available_regions(
+    args: &ShredstreamArgs,
+) -> Result<Vec<String>, ShredstreamProxyError> {
+    let auth_keypair =
+        Arc::new(read_auth_keypair(args).map_err(ShredstreamProxyError::InvalidArguments)?);
+    let grpc_channel_config =
+        grpc_channel_config(args).map_err(ShredstreamProxyError::InvalidArguments)?;
+    fetch_available_regions(
+        &Runtime::new()?,
+        args,
+        auth_keypair,
+        &grpc_channel_config,
+        Arc::new(ShredMetrics::new()),
+    )
+}
+
+/// Checks `desired-regions` against the regions the primary block engine offers, returning them as the block engine spells them.
+/// Proceeds with `desired-regions` unchanged if the block engine can't list its regions, so startup never depends on it
+pub(crate) fn check_desired_regions(
+    runtime: &Runtime,
+    args: &ShredstreamArgs,
+    auth_keypair: Arc<Keypair>,
+    grpc_channel_config: &GrpcChannelConfig,
+    metrics: Arc<ShredMetrics>,
+) -> Result<Vec<String>, ShredstreamProxyError> {
+    let available_regions =
+        match fetch_available_regions(runtime, args, auth_keypair, grpc_channel_config, metrics) {
+            Ok(available_regions) if !available_regions.is_empty() => available_regions,
+            Ok(_) => {
+                warn!("Block engine listed no regions, not checking --desired-regions.");
+                return Ok(args.desired_regions.clone());
+            }
+            Err(e) => {
+                warn!(
+                "Failed to list block engine regions, not checking --desired-regions. Error: {e}"
+            );
+                return Ok(args.desired_regions.clone());
+            }
+        };
+    let desired_regions = resolve_desired_regions(&args.desired_regions, &available_regions)
+        .map_err(ShredstreamProxyError::InvalidArguments)?;
+    info!("Requesting shreds from regions {desired_regions:?}.");
+    Ok(desired_regions)
+}
+
+fn fetch_available_regions(
+    runtime: &Runtime,
+    args: &ShredstreamArgs,
+    auth_keypair: Arc<Keypair>,
+    grpc_channel_config: &GrpcChannelConfig,
+    metrics: Arc<ShredMetrics>,
+) -> Result<Vec<String>, ShredstreamProxyError> {
+    let block_engine_url = args.block_engine_url[0].clone();
+    let auth_url = args
+        .auth_url
+        .clone()
+        .unwrap_or_else(|| block_engine_url.clone());
+    runtime.block_on(async {
+        let request = async {
+            let auth_channel = create_grpc_channel(auth_url.clone(), grpc_channel_config).await?;
+            let searcher_channel =
+                create_grpc_channel(block_engine_url, grpc_channel_config).await?;
+            let (client_interceptor, refresh_hdl) = ClientInterceptor::new(
+                AuthServiceClient::new(auth_channel),
+                auth_url,
+                TokenCache::default(),
+                auth_keypair,
+                Role::ShredstreamSubscriber,
+                "shredstream_proxy".to_string(),
+                metrics,
+                Arc::new(AtomicBool::new(false)),
+            )
+            .await?;
+            // only needed for this one request
+            refresh_hdl.abort();
+            let regions =
+                SearcherServiceClient::with_interceptor(searcher_channel, client_interceptor)
+                    .get_regions(GetRegionsRequest {})
+                    .await?
+                    .into_inner();
+            Ok::<_, ShredstreamProxyError>(regions.available_regions)
+        };
+        tokio::time::timeout(REGIONS_REQUEST_TIMEOUT, request)
+            .await
+            .map_err(|_| {
+                io::Error::new(
+                    ErrorKind::TimedOut,
+                    format!("No response listing regions within {REGIONS_REQUEST_TIMEOUT:?}"),
+                )
+            })?
+    })
+}
+
+/// Matches each desired region to an available one, ignoring case and surrounding whitespace.
+/// Errors list every unknown region along with the valid options
+fn resolve_desired_regions(
+    desired_regions: &[String],
+    available_regions: &[String],
+) -> Result<Vec<String>, String> {
+    let (resolved, unknown): (Vec<_>, Vec<_>) = desired_regions
+        .iter()
+        .map(|desired| {
+            available_regions
+                .iter()
+                .find(|available| available.trim().eq_ignore_ascii_case(desired.trim()))
+                .ok_or(desired)
+        })
+        .partition(Result::is_ok);
+    if !unknown.is_empty() {
+        let unknown = unknown
+            .into_iter()
+            .map(Result::unwrap_err)
+            .collect::<Vec<_>>();
+        return Err(format!(
+            "Invalid arguments provided, --desired-regions {unknown:?} not offered by the block engine. Valid regions: {}.",
+            available_regions.join(", ")
+        ));
+    }
+    Ok(resolved
+        .into_iter()
+        .map(|available| available.unwrap().trim().to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regions::resolve_desired_regions;
+
+    #[test]
+    fn test_resolve_desired_regions() {
+        let available = ["amsterdam", "frankfurt", "ny", "tokyo"].map(String::from);
+        let desired = |regions: &[&str]| regions.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(
+            resolve_desired_regions(&desired(&["ny", " Amsterdam"]), &available).unwrap(),
+            vec!["ny", "amsterdam"]
+        );
+        let err = resolve_desired_regions(&desired(&["ams", "ny", "slc"]), &available).unwrap_err();
+        assert_eq!(
+            err,
+            "Invalid arguments provided, --desired-regions [\"ams\", \"slc\"] not offered by the block engine. Valid regions: amsterdam, frankfurt, ny, tokyo."
+        );
+    }
+}
@@ -0,0 +1,752 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+};
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+
+use crate::{
+    archive_config, chaos_config, conflict_detector_config, endpoint_discovery, events_config,
+    forwarder::{
+        DestinationSources, EndpointDiscovery, PacketFilter, SourceAllowlist, TraceShredSampler,
+    },
+    kafka_config, load_proxy_config,
+    metrics_backend::datapoint_warn,
+    rpc_discovery_config, slow_send_threshold, trace_shred_sample_rate, tunnel_config,
+    validate_common_args, validate_has_destinations, validator_resolver_config, CommonArgs,
+    ConfigFormat, ProxyConfig, ShredstreamArgs,
+};
+
+/// Runtime state updated in place when the config file is reloaded
+pub struct ReloadableState {
+    pub dest_sources: Arc<Mutex<DestinationSources>>,
+    pub unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    pub endpoint_discovery: Arc<ArcSwapOption<EndpointDiscovery>>,
+    pub metrics_report_interval_ms: Arc<AtomicU64>,
+    pub trace_shred_sampler: Arc<TraceShredSampler>,
+    pub packet_filter: Arc<PacketFilter>,
+    pub source_allowlist: Arc<SourceAllowlist>,
+}
+
+/// Re-reads the config file whenever `reload_receiver` is notified, such as on `SIGHUP`, and applies reloadable fields without restarting.
+/// An invalid config is logged and ignored, keeping the current config.
+pub fn start_config_reload_thread(
+    config_path: PathBuf,
+    config_format: Option<ConfigFormat>,
+    mut current_args: ProxyConfig,
+    state: ReloadableState,
+    reload_receiver: Receiver<()>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    Builder::new()
+        .name("ssPxyReload".to_string())
+        .spawn(move || {
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(reload_receiver) -> _ => {
+                        info!("Reloading config from {config_path:?}.");
+                        let new_args = load_proxy_config(&config_path, config_format)
+                            .and_then(|new_args| {
+                                validate_has_destinations(new_args.common_args())
+                                    .and_then(|()| validate_common_args(new_args.common_args()))
+                                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                                Ok(new_args)
+                            });
+                        match new_args {
+                            Ok(new_args) => {
+                                apply_reload(&current_args, &new_args, &state);
+                                current_args = new_args;
+                            }
+                            Err(e) => {
+                                warn!("Failed to reload config, keeping current config. Error: {e}");
+                                datapoint_warn!(
+                                    "shredstream_proxy-config_reload_error",
+                                    ("errors", 1, i64),
+                                    ("error_str", e.to_string(), String),
+                                );
+                            }
+                        }
+                        // rotated keys are picked up whether or not the config changed or is valid
+                        let encryption_keys =
+                            state.dest_sources.lock().unwrap().encryption_keys.clone();
+                        encryption_keys.reload();
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+        })
+        .unwrap()
+}
+
+/// Applies reloadable fields that changed, warning about the rest
+fn apply_reload(old: &ProxyConfig, new: &ProxyConfig, state: &ReloadableState) {
+    let restart_required = restart_required_fields(old, new);
+    if !restart_required.is_empty() {
+        warn!("Config fields {restart_required:?} changed but require a restart to take effect.");
+    }
+
+    let (old_common, new_common) = (old.common_args(), new.common_args());
+    let hostnames = |args: &CommonArgs| -> Vec<String> {
+        args.dest_ip_ports.iter().map(|x| x.1.clone()).collect()
+    };
+    if hostnames(old_common) != hostnames(new_common) {
+        info!("Reloading dest_ip_ports: {:?}", hostnames(new_common));
+        let mut dest_sources = state.dest_sources.lock().unwrap();
+        dest_sources
+            .static_dest_sockets
+            .clone_from(&new_common.dest_ip_ports);
+        dest_sources.store_union(&state.unioned_dest_sockets);
+    }
+
+    if old_common.dest_shard_groups != new_common.dest_shard_groups {
+        info!(
+            "Reloading dest_shard_groups: {:?}",
+            new_common.dest_shard_groups
+        );
+        let mut dest_sources = state.dest_sources.lock().unwrap();
+        dest_sources
+            .shard_group_specs
+            .clone_from(&new_common.dest_shard_groups);
+        dest_sources.store_union(&state.unioned_dest_sockets);
+    }
+
+    let (old_discovery, new_discovery) = (
+        endpoint_discovery(old_common),
+        endpoint_discovery(new_common),
+    );
+    if old_discovery != new_discovery {
+        // headers aren't logged since they may hold credentials
+        info!(
+            "Reloading endpoint discovery: {:?}, takes effect on next refresh.",
+            new_discovery.as_ref().map(|discovery| (
+                &discovery.url,
+                discovery.port,
+                discovery.interval
+            ))
+        );
+        state.endpoint_discovery.store(new_discovery.map(Arc::new));
+    }
+
+    if old_common.metrics_report_interval_ms != new_common.metrics_report_interval_ms {
+        info!(
+            "Reloading metrics_report_interval_ms: {}",
+            new_common.metrics_report_interval_ms
+        );
+        state
+            .metrics_report_interval_ms
+            .store(new_common.metrics_report_interval_ms, Ordering::Relaxed);
+    }
+
+    let new_trace_shred_sample_rate = trace_shred_sample_rate(new_common);
+    if trace_shred_sample_rate(old_common) != new_trace_shred_sample_rate {
+        info!("Reloading trace_shred_sample_rate: {new_trace_shred_sample_rate}");
+        state
+            .trace_shred_sampler
+            .set_sample_rate(new_trace_shred_sample_rate);
+    }
+
+    if (
+        old_common.max_slot_age,
+        old_common.drop_unknown_packets(),
+        old_common.forward_shred_types,
+    ) != (
+        new_common.max_slot_age,
+        new_common.drop_unknown_packets(),
+        new_common.forward_shred_types,
+    ) {
+        info!(
+            "Reloading max_slot_age: {:?}, drop unknown packets: {}, forward_shred_types: {:?}",
+            new_common.max_slot_age,
+            new_common.drop_unknown_packets(),
+            new_common.forward_shred_types
+        );
+        state.packet_filter.set(
+            new_common.max_slot_age,
+            new_common.drop_unknown_packets(),
+            new_common.forward_shred_types,
+        );
+    }
+
+    if (
+        &old_common.allowed_source_ips,
+        &old_common.allowed_source_ips_file,
+    ) != (
+        &new_common.allowed_source_ips,
+        &new_common.allowed_source_ips_file,
+    ) {
+        info!(
+            "Reloading allowed_source_ips: {:?}, allowed_source_ips_file: {:?}",
+            new_common.allowed_source_ips, new_common.allowed_source_ips_file
+        );
+        if let Err(e) = state.source_allowlist.set(
+            new_common.allowed_source_ips.clone(),
+            new_common.allowed_source_ips_file.clone(),
+        ) {
+            warn!("Failed to reload allowed source IPs, keeping current allowlist. Error: {e}");
+        }
+    }
+}
+
+/// Returns names of changed fields that can't be applied while running
+fn restart_required_fields(old: &ProxyConfig, new: &ProxyConfig) -> Vec<&'static str> {
+    let mut fields = match (old, new) {
+        (ProxyConfig::Shredstream(old), ProxyConfig::Shredstream(new)) => {
+            shredstream_restart_required_fields(old, new)
+        }
+        (ProxyConfig::ForwardOnly(_), ProxyConfig::ForwardOnly(_)) => vec![],
+        _ => vec!["mode"],
+    };
+    fields.extend(common_restart_required_fields(
+        old.common_args(),
+        new.common_args(),
+    ));
+    fields
+}
+
+fn shredstream_restart_required_fields(
+    old: &ShredstreamArgs,
+    new: &ShredstreamArgs,
+) -> Vec<&'static str> {
+    [
+        (
+            "block_engine_url",
+            old.block_engine_url != new.block_engine_url,
+        ),
+        (
+            "block_engine_failover_threshold",
+            old.block_engine_failover_threshold != new.block_engine_failover_threshold,
+        ),
+        (
+            "block_engine_primary_retry_secs",
+            old.block_engine_primary_retry_secs != new.block_engine_primary_retry_secs,
+        ),
+        ("auth_url", old.auth_url != new.auth_url),
+        ("auth_keypair", old.auth_keypair != new.auth_keypair),
+        (
+            "auth_keypair_env",
+            old.auth_keypair_base58 != new.auth_keypair_base58,
+        ),
+        (
+            "desired_regions",
+            old.desired_regions != new.desired_regions,
+        ),
+        ("region_ports", old.region_ports != new.region_ports),
+        (
+            "stall_timeout_secs",
+            old.stall_timeout_secs != new.stall_timeout_secs,
+        ),
+        (
+            "ready_heartbeat_max_age_secs",
+            old.ready_heartbeat_max_age_secs != new.ready_heartbeat_max_age_secs,
+        ),
+        (
+            "token_refresh_margin_secs",
+            old.token_refresh_margin_secs != new.token_refresh_margin_secs,
+        ),
+        (
+            "token_cache_file",
+            old.token_cache_file != new.token_cache_file,
+        ),
+        (
+            "initial_auth_jitter_ms",
+            old.initial_auth_jitter_ms != new.initial_auth_jitter_ms,
+        ),
+        (
+            "grpc_connect_timeout_ms",
+            old.grpc_connect_timeout_ms != new.grpc_connect_timeout_ms,
+        ),
+        (
+            "grpc_keepalive_interval_ms",
+            old.grpc_keepalive_interval_ms != new.grpc_keepalive_interval_ms,
+        ),
+        (
+            "grpc_keepalive_timeout_ms",
+            old.grpc_keepalive_timeout_ms != new.grpc_keepalive_timeout_ms,
+        ),
+        (
+            "grpc_tls_ca_cert",
+            old.grpc_tls_ca_cert != new.grpc_tls_ca_cert,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+fn common_restart_required_fields(
+    old_common: &CommonArgs,
+    new_common: &CommonArgs,
+) -> Vec<&'static str> {
+    [
+        (
+            "src_bind_addr",
+            old_common.src_bind_addr != new_common.src_bind_addr,
+        ),
+        (
+            "src_bind_port",
+            old_common.src_bind_port != new_common.src_bind_port,
+        ),
+        (
+            "dest_resolve_interval_secs",
+            old_common.dest_resolve_interval_secs != new_common.dest_resolve_interval_secs,
+        ),
+        (
+            "dest_address_family",
+            old_common.dest_address_family != new_common.dest_address_family,
+        ),
+        (
+            "mirror_dests",
+            old_common.mirror_dests != new_common.mirror_dests,
+        ),
+        (
+            "prometheus_bind_addr",
+            old_common.prometheus_bind_addr != new_common.prometheus_bind_addr,
+        ),
+        (
+            "admin_bind_addr",
+            old_common.admin_bind_addr != new_common.admin_bind_addr,
+        ),
+        (
+            "admin_grpc_bind_addr",
+            old_common.admin_grpc_bind_addr != new_common.admin_grpc_bind_addr,
+        ),
+        (
+            "admin_grpc_tls_cert_file",
+            old_common.admin_grpc_tls_cert_file != new_common.admin_grpc_tls_cert_file,
+        ),
+        (
+            "admin_grpc_tls_key_file",
+            old_common.admin_grpc_tls_key_file != new_common.admin_grpc_tls_key_file,
+        ),
+        (
+            "grpc_service_bind_addr",
+            old_common.grpc_service_bind_addr != new_common.grpc_service_bind_addr,
+        ),
+        (
+            "deshred_fec_set_timeout_ms",
+            old_common.deshred_fec_set_timeout_ms != new_common.deshred_fec_set_timeout_ms,
+        ),
+        (
+            "ws_bind_addr",
+            old_common.ws_bind_addr != new_common.ws_bind_addr,
+        ),
+        (
+            "ws_max_clients",
+            old_common.ws_max_clients != new_common.ws_max_clients,
+        ),
+        (
+            "alert_webhook_url",
+            old_common.alert_webhook_url != new_common.alert_webhook_url,
+        ),
+        (
+            "alert_webhook_format",
+            old_common.alert_webhook_format != new_common.alert_webhook_format,
+        ),
+        (
+            "alert_host_id",
+            old_common.alert_host_id != new_common.alert_host_id,
+        ),
+        (
+            "alert_no_shreds_secs",
+            old_common.alert_no_shreds_secs != new_common.alert_no_shreds_secs,
+        ),
+        (
+            "alert_forward_failure_percent",
+            old_common.alert_forward_failure_percent != new_common.alert_forward_failure_percent,
+        ),
+        (
+            "alert_heartbeat_down_secs",
+            old_common.alert_heartbeat_down_secs != new_common.alert_heartbeat_down_secs,
+        ),
+        (
+            "alert_deduper_saturation_percent",
+            old_common.alert_deduper_saturation_percent
+                != new_common.alert_deduper_saturation_percent,
+        ),
+        (
+            "alert_cooldown_secs",
+            old_common.alert_cooldown_secs != new_common.alert_cooldown_secs,
+        ),
+        (
+            "slot_latency_rpc_url",
+            old_common.slot_latency_rpc_url != new_common.slot_latency_rpc_url,
+        ),
+        (
+            "otlp_endpoint",
+            old_common.otlp_endpoint != new_common.otlp_endpoint,
+        ),
+        ("public_ip", old_common.public_ip != new_common.public_ip),
+        (
+            "public_ip_family",
+            old_common.public_ip_family != new_common.public_ip_family,
+        ),
+        (
+            "public_ip_recheck_interval_secs",
+            old_common.public_ip_recheck_interval_secs
+                != new_common.public_ip_recheck_interval_secs,
+        ),
+        (
+            "advertise_addr",
+            old_common.advertise_addr != new_common.advertise_addr,
+        ),
+        (
+            "num_threads",
+            old_common.num_threads != new_common.num_threads,
+        ),
+        (
+            "num_recv_threads",
+            old_common.num_recv_threads != new_common.num_recv_threads,
+        ),
+        (
+            "num_send_threads",
+            old_common.num_send_threads != new_common.num_send_threads,
+        ),
+        (
+            "core_affinity",
+            old_common.core_affinity != new_common.core_affinity,
+        ),
+        (
+            "send_batch_size",
+            old_common.send_batch_size != new_common.send_batch_size,
+        ),
+        (
+            "send_batch_linger_us",
+            old_common.send_batch_linger_us != new_common.send_batch_linger_us,
+        ),
+        (
+            "isolated_send_threads",
+            old_common.isolated_send_threads != new_common.isolated_send_threads,
+        ),
+        (
+            "isolated_send_queue_capacity",
+            old_common.isolated_send_queue_capacity != new_common.isolated_send_queue_capacity,
+        ),
+        (
+            "forwarder_channel_capacity",
+            old_common.forwarder_channel_capacity != new_common.forwarder_channel_capacity,
+        ),
+        (
+            "forwarder_drop_policy",
+            old_common.forwarder_drop_policy != new_common.forwarder_drop_policy,
+        ),
+        (
+            "upstream_keepalive",
+            old_common.upstream_keepalives != new_common.upstream_keepalives,
+        ),
+        ("listener", old_common.listeners != new_common.listeners),
+        (
+            "decrypt_key_file",
+            old_common.decrypt_key_file != new_common.decrypt_key_file,
+        ),
+        (
+            "max_buffered_bytes",
+            old_common.max_buffered_bytes != new_common.max_buffered_bytes,
+        ),
+        (
+            "recv_mmsg_batch_size",
+            old_common.recv_mmsg_batch_size != new_common.recv_mmsg_batch_size,
+        ),
+        (
+            "recv_poll_timeout_ms",
+            old_common.recv_poll_timeout_ms != new_common.recv_poll_timeout_ms,
+        ),
+        (
+            "measure_internal_latency",
+            old_common.measure_internal_latency != new_common.measure_internal_latency,
+        ),
+        (
+            "send_latency",
+            slow_send_threshold(old_common) != slow_send_threshold(new_common),
+        ),
+        (
+            "rx_timestamp_source",
+            old_common.rx_timestamp_source != new_common.rx_timestamp_source,
+        ),
+        (
+            "deduper_num_bits",
+            old_common.deduper_num_bits != new_common.deduper_num_bits,
+        ),
+        (
+            "deduper_false_positive_rate",
+            old_common.deduper_false_positive_rate != new_common.deduper_false_positive_rate,
+        ),
+        ("dedup_mode", old_common.dedup_mode != new_common.dedup_mode),
+        (
+            "record_pcap",
+            old_common.record_pcap != new_common.record_pcap,
+        ),
+        (
+            "record_pcap_rotate_bytes",
+            old_common.record_pcap_rotate_bytes != new_common.record_pcap_rotate_bytes,
+        ),
+        (
+            "record_pcap_rotate_secs",
+            old_common.record_pcap_rotate_secs != new_common.record_pcap_rotate_secs,
+        ),
+        (
+            "record_pcap_max_files",
+            old_common.record_pcap_max_files != new_common.record_pcap_max_files,
+        ),
+        (
+            "tunnel",
+            tunnel_config(old_common) != tunnel_config(new_common),
+        ),
+        (
+            "dest_validator",
+            validator_resolver_config(old_common) != validator_resolver_config(new_common),
+        ),
+        (
+            "rpc_discovery",
+            rpc_discovery_config(old_common) != rpc_discovery_config(new_common),
+        ),
+        (
+            "chaos",
+            chaos_config(old_common) != chaos_config(new_common),
+        ),
+        (
+            "dest_blocklist",
+            old_common.dest_blocklist != new_common.dest_blocklist,
+        ),
+        (
+            "allow_self_forward",
+            old_common.allow_self_forward != new_common.allow_self_forward,
+        ),
+        (
+            "max_destinations",
+            old_common.max_destinations != new_common.max_destinations,
+        ),
+        (
+            "kafka",
+            kafka_config(old_common) != kafka_config(new_common),
+        ),
+        (
+            "archive",
+            archive_config(old_common) != archive_config(new_common),
+        ),
+        (
+            "conflicting_shreds",
+            conflict_detector_config(old_common) != conflict_detector_config(new_common),
+        ),
+        (
+            "events",
+            events_config(old_common) != events_config(new_common),
+        ),
+        (
+            "health_check_mode",
+            old_common.health_check_mode != new_common.health_check_mode,
+        ),
+        (
+            "health_check_port",
+            old_common.health_check_port != new_common.health_check_port,
+        ),
+        (
+            "health_check_http_path",
+            old_common.health_check_http_path != new_common.health_check_http_path,
+        ),
+        (
+            "health_check_interval_ms",
+            old_common.health_check_interval_ms != new_common.health_check_interval_ms,
+        ),
+        (
+            "health_check_timeout_ms",
+            old_common.health_check_timeout_ms != new_common.health_check_timeout_ms,
+        ),
+        (
+            "health_check_failure_threshold",
+            old_common.health_check_failure_threshold != new_common.health_check_failure_threshold,
+        ),
+        ("spill_dir", old_common.spill_dir != new_common.spill_dir),
+        (
+            "spill_max_bytes",
+            old_common.spill_max_bytes != new_common.spill_max_bytes,
+        ),
+        (
+            "spill_replay_rate_pps",
+            old_common.spill_replay_rate_pps != new_common.spill_replay_rate_pps,
+        ),
+        (
+            "spill_max_age_ms",
+            old_common.spill_max_age_ms != new_common.spill_max_age_ms,
+        ),
+        (
+            "spill_live_queue_packets",
+            old_common.spill_live_queue_packets != new_common.spill_live_queue_packets,
+        ),
+        (
+            "recv_socket_buffer_bytes",
+            old_common.recv_socket_buffer_bytes != new_common.recv_socket_buffer_bytes,
+        ),
+        (
+            "send_socket_buffer_bytes",
+            old_common.send_socket_buffer_bytes != new_common.send_socket_buffer_bytes,
+        ),
+        ("dscp", old_common.dscp != new_common.dscp),
+        (
+            "listen_dscp",
+            old_common.listen_dscp != new_common.listen_dscp,
+        ),
+        (
+            "metrics_backend",
+            old_common.metrics_backend != new_common.metrics_backend,
+        ),
+        (
+            "statsd_addr",
+            old_common.statsd_addr != new_common.statsd_addr,
+        ),
+        (
+            "statsd_dialect",
+            old_common.statsd_dialect != new_common.statsd_dialect,
+        ),
+        (
+            "egress_interface",
+            old_common.egress_interface != new_common.egress_interface,
+        ),
+        (
+            "egress_source_ip",
+            old_common.egress_source_ip != new_common.egress_source_ip,
+        ),
+        (
+            "socket_rebind_error_threshold",
+            old_common.socket_rebind_error_threshold != new_common.socket_rebind_error_threshold,
+        ),
+        (
+            "socket_max_rebinds_per_minute",
+            old_common.socket_max_rebinds_per_minute != new_common.socket_max_rebinds_per_minute,
+        ),
+        (
+            "deduper_reset_interval_ms",
+            old_common.deduper_reset_interval_ms != new_common.deduper_reset_interval_ms,
+        ),
+        (
+            "thread_max_restarts",
+            old_common.thread_max_restarts != new_common.thread_max_restarts,
+        ),
+        (
+            "thread_restart_window_secs",
+            old_common.thread_restart_window_secs != new_common.thread_restart_window_secs,
+        ),
+        (
+            "crash_report_file",
+            old_common.crash_report_file != new_common.crash_report_file,
+        ),
+        (
+            "shutdown_grace_period_ms",
+            old_common.shutdown_grace_period_ms != new_common.shutdown_grace_period_ms,
+        ),
+        (
+            "run_as",
+            old_common.run_as_user != new_common.run_as_user
+                || old_common.run_as_group != new_common.run_as_group
+                || old_common.chroot != new_common.chroot,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(field, changed)| changed.then_some(field))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use arc_swap::{ArcSwap, ArcSwapOption};
+
+    use crate::{
+        forwarder::{DestinationSources, PacketFilter, SourceAllowlist, TraceShredSampler},
+        parse_shredstream_config,
+        reload::{apply_reload, restart_required_fields, ReloadableState},
+        ConfigFormat, ProxyConfig,
+    };
+
+    fn parse_args(common: &str) -> ProxyConfig {
+        let contents = format!(
+            "block_engine_url = \"https://mainnet.block-engine.jito.wtf\"\n\
+             auth_keypair = \"keypair.json\"\n\
+             desired_regions = [\"ny\"]\n\
+             [common]\n{common}"
+        );
+        parse_shredstream_config(&contents, ConfigFormat::Toml)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_apply_reload() {
+        let old = parse_args("dest_ip_ports = [\"127.0.0.1:8001\"]\n");
+        let new = parse_args(
+            "dest_ip_ports = [\"127.0.0.1:8002\"]\n\
+             src_bind_port = 20001\n\
+             metrics_report_interval_ms = 5000\n\
+             debug_trace_shred = true\n\
+             allowed_source_ips = [\"10.0.0.0/8\"]\n",
+        );
+        let state = ReloadableState {
+            dest_sources: Arc::new(Mutex::new(DestinationSources {
+                static_dest_sockets: old.common_args().dest_ip_ports.clone(),
+                ..Default::default()
+            })),
+            unioned_dest_sockets: Arc::new(ArcSwap::from_pointee(vec![])),
+            endpoint_discovery: Arc::new(ArcSwapOption::empty()),
+            metrics_report_interval_ms: Arc::new(AtomicU64::new(15_000)),
+            trace_shred_sampler: Arc::new(TraceShredSampler::default()),
+            packet_filter: Arc::new(PacketFilter::default()),
+            source_allowlist: Arc::new(SourceAllowlist::default()),
+        };
+
+        assert_eq!(restart_required_fields(&old, &new), vec!["src_bind_port"]);
+        apply_reload(&old, &new, &state);
+
+        assert_eq!(
+            **state.unioned_dest_sockets.load(),
+            vec![SocketAddr::from_str("127.0.0.1:8002").unwrap()]
+        );
+        assert_eq!(
+            state.metrics_report_interval_ms.load(Ordering::Relaxed),
+            5000
+        );
+        assert_eq!(state.trace_shred_sampler.sample_rate(), 1.0);
+        assert!(state.endpoint_discovery.load().is_none());
+        assert!(state.source_allowlist.is_allowed([10, 0, 0, 1].into()));
+        assert!(!state.source_allowlist.is_allowed([192, 168, 0, 1].into()));
+    }
+
+    #[test]
+    fn test_restart_required_fields_mode() {
+        let shredstream = parse_args("dest_ip_ports = [\"127.0.0.1:8001\"]\n");
+        let forward_only: ProxyConfig = parse_shredstream_config(
+            "mode = \"forward-only\"\n\
+             [common]\n\
+             dest_ip_ports = [\"127.0.0.1:8001\"]\n\
+             src_bind_port = 20001\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap()
+        .try_into()
+        .unwrap();
+
+        assert_eq!(
+            restart_required_fields(&shredstream, &forward_only),
+            vec!["mode", "src_bind_port"]
+        );
+        assert!(restart_required_fields(&forward_only, &forward_only).is_empty());
+    }
+}
@@ -0,0 +1,297 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use log::{error, info, warn};
+use solana_perf::packet::{Packet, PacketBatch, PACKET_DATA_SIZE};
+use solana_sdk::packet::Meta;
+
+use crate::{
+    forwarder::ShredDeduper,
+    packet_channel::ReceivedBatch,
+    pcap::{PcapReader, RecordedPacket},
+};
+
+/// Max packets sent to a forwarder thread at once
+const REPLAY_BATCH_SIZE: usize = 64;
+/// Batches queued per forwarder thread before replay waits for it to catch up
+pub const REPLAY_CHANNEL_CAPACITY: usize = 1024;
+/// How often to check whether forwarders have drained queued packets before exiting
+const DRAIN_CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Debug)]
+pub struct ReplayConfig {
+    pub input: PathBuf,
+    /// Multiplier applied to the captured timing. 0 replays as fast as possible
+    pub speed: f64,
+    /// Start over from the beginning once the capture is exhausted, until shutdown
+    pub loop_replay: bool,
+    /// Size of the fresh deduper swapped in on each loop, so repeated packets aren't dropped as duplicates
+    pub deduper_num_bits: u64,
+}
+
+/// Reads packets from a capture and sends them to forwarder threads round robin, keeping the captured inter-packet timing.
+/// Once the capture is exhausted and forwarders have drained, stops the proxy via `shutdown_sender`.
+pub fn start_replay_thread(
+    config: ReplayConfig,
+    packet_senders: Vec<Sender<ReceivedBatch>>,
+    deduper: Arc<ArcSwap<ShredDeduper>>,
+    shutdown_sender: Sender<()>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    // fail fast on a missing or unreadable capture
+    PcapReader::new(BufReader::new(File::open(&config.input)?))?;
+
+    Builder::new()
+        .name("ssPxyReplay".to_string())
+        .spawn(move || {
+            info!(
+                "Replaying {:?} at {}x speed{}.",
+                config.input,
+                config.speed,
+                if config.loop_replay { ", looping" } else { "" }
+            );
+            let start = Instant::now();
+            let mut replayer = Replayer {
+                packet_senders,
+                next_sender: 0,
+                num_replayed: 0,
+                num_skipped: 0,
+            };
+            let mut num_passes = 0u64;
+            loop {
+                if num_passes > 0 {
+                    let mode = deduper.load().mode();
+                    deduper.store(Arc::new(ShredDeduper::new(
+                        &mut rand::thread_rng(),
+                        config.deduper_num_bits,
+                        mode,
+                    )));
+                }
+                num_passes += 1;
+                match replayer.replay_file(&config, &shutdown_receiver, &exit) {
+                    Ok(true) if config.loop_replay => continue,
+                    Ok(_) => {}
+                    Err(e) => error!("Failed to read capture {:?}. Error: {e}", config.input),
+                }
+                break;
+            }
+
+            // let forwarders send everything queued before shutting them down
+            while !exit.load(Ordering::Relaxed)
+                && replayer
+                    .packet_senders
+                    .iter()
+                    .any(|sender| !sender.is_empty())
+            {
+                let _ = shutdown_receiver.recv_timeout(DRAIN_CHECK_INTERVAL);
+            }
+            let elapsed = start.elapsed();
+            info!(
+                "Replayed {} packets over {num_passes} passes in {elapsed:?}, {:.0} packets/sec.",
+                replayer.num_replayed,
+                replayer.num_replayed as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            );
+            if replayer.num_skipped > 0 {
+                warn!(
+                    "Skipped {} packets larger than {PACKET_DATA_SIZE} bytes.",
+                    replayer.num_skipped
+                );
+            }
+
+            exit.store(true, Ordering::SeqCst);
+            crate::broadcast_shutdown(&shutdown_sender);
+        })
+}
+
+struct Replayer {
+    /// One per forwarder thread
+    packet_senders: Vec<Sender<ReceivedBatch>>,
+    next_sender: usize,
+    num_replayed: u64,
+    num_skipped: u64,
+}
+
+impl Replayer {
+    /// Replays the capture once. Returns false if stopped early by shutdown
+    fn replay_file(
+        &mut self,
+        config: &ReplayConfig,
+        shutdown_receiver: &Receiver<()>,
+        exit: &AtomicBool,
+    ) -> io::Result<bool> {
+        let mut reader = PcapReader::new(BufReader::new(File::open(&config.input)?))?;
+        let pass_start = Instant::now();
+        let mut first_received_at = None;
+        let mut batch = PacketBatch::with_capacity(REPLAY_BATCH_SIZE);
+        while let Some(packet) = reader.next_packet()? {
+            if exit.load(Ordering::Relaxed) {
+                return Ok(false);
+            }
+            if config.speed > 0.0 {
+                let first_received_at = *first_received_at.get_or_insert(packet.received_at);
+                let offset = packet
+                    .received_at
+                    .duration_since(first_received_at)
+                    .unwrap_or_default();
+                let due = pass_start + offset.div_f64(config.speed);
+                let now = Instant::now();
+                if due > now {
+                    // send what's due before waiting
+                    if !self.send(&mut batch) {
+                        return Ok(false);
+                    }
+                    match shutdown_receiver.recv_timeout(due - now) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => return Ok(false),
+                    }
+                }
+            }
+
+            match to_packet(&packet) {
+                Some(packet) => batch.push(packet),
+                None => self.num_skipped += 1,
+            }
+            if batch.len() >= REPLAY_BATCH_SIZE && !self.send(&mut batch) {
+                return Ok(false);
+            }
+        }
+        Ok(self.send(&mut batch))
+    }
+
+    /// Sends and clears the batch, returning false if forwarders have exited
+    fn send(&mut self, batch: &mut PacketBatch) -> bool {
+        if batch.is_empty() {
+            return true;
+        }
+        let num_packets = batch.len() as u64;
+        let batch = std::mem::replace(batch, PacketBatch::with_capacity(REPLAY_BATCH_SIZE));
+        let sender = &self.packet_senders[self.next_sender];
+        self.next_sender = (self.next_sender + 1) % self.packet_senders.len();
+        // blocks when forwarders fall behind, so replaying as fast as possible doesn't buffer the whole capture
+        if sender.send(batch.into()).is_err() {
+            return false;
+        }
+        self.num_replayed += num_packets;
+        true
+    }
+}
+
+/// Returns `None` if the payload doesn't fit in a packet
+fn to_packet(recorded: &RecordedPacket) -> Option<Packet> {
+    let size = recorded.payload.len();
+    if size > PACKET_DATA_SIZE {
+        return None;
+    }
+    let mut data = [0u8; PACKET_DATA_SIZE];
+    data[..size].copy_from_slice(&recorded.payload);
+    let mut meta = Meta {
+        size,
+        ..Meta::default()
+    };
+    meta.set_socket_addr(&recorded.src);
+    Some(Packet::new(data, meta))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::SocketAddr,
+        str::FromStr,
+        sync::atomic::AtomicBool,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use crate::{
+        pcap::{PcapRotation, PcapWriter, RecordedPacket},
+        replay::{ReplayConfig, Replayer},
+    };
+
+    #[test]
+    fn test_replay_file() {
+        let input = std::env::temp_dir().join(format!(
+            "shredstream_proxy_replay_{}.pcap",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos()
+        ));
+        let mut writer = PcapWriter::new(
+            input.clone(),
+            PcapRotation {
+                max_file_bytes: None,
+                max_file_age: None,
+                max_files: None,
+            },
+        )
+        .unwrap();
+        let src = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        // 3 packets spread over 200ms, one too large to replay
+        for (millis, payload_len) in [(0, 10), (100, 2_000), (200, 20)] {
+            writer
+                .write(&RecordedPacket {
+                    received_at: UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + millis),
+                    src,
+                    dst: SocketAddr::from_str("10.0.0.2:20000").unwrap(),
+                    payload: vec![1; payload_len],
+                })
+                .unwrap();
+        }
+        writer.flush().unwrap();
+
+        let (packet_senders, packet_receivers): (Vec<_>, Vec<_>) =
+            (0..2).map(|_| crossbeam_channel::unbounded()).unzip();
+        let mut replayer = Replayer {
+            packet_senders,
+            next_sender: 0,
+            num_replayed: 0,
+            num_skipped: 0,
+        };
+        let (_shutdown_sender, shutdown_receiver) = crossbeam_channel::unbounded();
+        let config = ReplayConfig {
+            input: input.clone(),
+            speed: 2.0,
+            loop_replay: false,
+            deduper_num_bits: 1024,
+        };
+
+        let start = Instant::now();
+        assert!(replayer
+            .replay_file(&config, &shutdown_receiver, &AtomicBool::new(false))
+            .unwrap());
+        // captured 200ms at 2x speed
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        assert_eq!(replayer.num_replayed, 2);
+        assert_eq!(replayer.num_skipped, 1);
+        // batches alternate between forwarders
+        let batches = packet_receivers
+            .iter()
+            .map(|receiver| receiver.try_iter().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(batches[0].len(), 1);
+        assert_eq!(batches[1].len(), 1);
+        assert_eq!(batches[0][0].packets[0].meta().size, 10);
+        assert_eq!(batches[0][0].packets[0].meta().socket_addr(), src);
+        assert_eq!(batches[1][0].packets[0].meta().size, 20);
+
+        // stopped early by shutdown
+        assert!(!replayer
+            .replay_file(&config, &shutdown_receiver, &AtomicBool::new(true))
+            .unwrap());
+        assert_eq!(replayer.num_replayed, 2);
+        fs::remove_file(&input).unwrap();
+    }
+}
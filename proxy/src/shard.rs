@@ -0,0 +1,262 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::{self, Error, ErrorKind},
+    net::SocketAddr,
+};
+
+use crate::{
+    quic::QUIC_SCHEME,
+    resolve_hostname_port_with_family, shred,
+    tunnel::{TCP_SCHEME, TLS_SCHEME},
+    AddressFamily,
+};
+
+const SHARD_GROUP_PREFIX: &str = "shard(";
+/// Member list of a group made up of the discovery service's endpoints
+const DISCOVERED_MEMBERS: &str = "discovered";
+
+/// How packets are spread across the members of a shard group
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShardBy {
+    /// Each shred goes to the member chosen by its (slot, index)
+    #[default]
+    Shred,
+    /// Every shred of a slot goes to the same member
+    Slot,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ShardMembers {
+    /// Members listed in the group, with the original hostname
+    Static(Vec<(SocketAddr, String)>),
+    /// Endpoints fetched from `endpoint-discovery-url`, updated on each refresh
+    Discovered,
+}
+
+/// A `dest-shard-group` entry, eg. `shard(10.0.0.1:9000,10.0.0.2:9000)?shard-by=slot`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardGroupSpec {
+    pub members: ShardMembers,
+    pub shard_by: ShardBy,
+}
+
+/// Parses a `dest-shard-group` entry, used before the preferred family is known
+pub fn parse_shard_group(spec: &str) -> io::Result<ShardGroupSpec> {
+    parse_shard_group_with_family(spec, AddressFamily::Any)
+}
+
+/// Parses a `dest-shard-group` entry, resolving static members to the first address of `family`
+pub fn parse_shard_group_with_family(
+    spec: &str,
+    family: AddressFamily,
+) -> io::Result<ShardGroupSpec> {
+    let invalid = |reason: String| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid shard group {spec:?}, {reason}."),
+        )
+    };
+    let (members, options) = spec
+        .trim()
+        .strip_prefix(SHARD_GROUP_PREFIX)
+        .and_then(|rest| rest.split_once(')'))
+        .ok_or_else(|| invalid("expected `shard(<host:port>,...)`".to_string()))?;
+
+    let shard_by = match options {
+        "" => ShardBy::default(),
+        "?shard-by=shred" => ShardBy::Shred,
+        "?shard-by=slot" => ShardBy::Slot,
+        _ => {
+            return Err(invalid(format!(
+                "unknown option {options:?}, expected `?shard-by=slot` or `?shard-by=shred`"
+            )))
+        }
+    };
+
+    let members = members
+        .split(',')
+        .map(str::trim)
+        .filter(|member| !member.is_empty())
+        .collect::<Vec<_>>();
+    let members = match members[..] {
+        [] => return Err(invalid("no members".to_string())),
+        [DISCOVERED_MEMBERS] => ShardMembers::Discovered,
+        _ => {
+            let mut resolved = Vec::with_capacity(members.len());
+            for member in members {
+                // members are picked per packet, so per-destination transport and options don't apply
+                if [QUIC_SCHEME, TCP_SCHEME, TLS_SCHEME]
+                    .iter()
+                    .any(|scheme| member.starts_with(scheme))
+                    || member.contains('?')
+                {
+                    return Err(invalid(format!(
+                        "member {member:?} must be a plain UDP host:port"
+                    )));
+                }
+                let (socketaddr, hostname_port) =
+                    resolve_hostname_port_with_family(member, family)?;
+                if !resolved.iter().any(|(addr, _)| *addr == socketaddr) {
+                    resolved.push((socketaddr, hostname_port));
+                }
+            }
+            ShardMembers::Static(resolved)
+        }
+    };
+
+    Ok(ShardGroupSpec { members, shard_by })
+}
+
+/// Members of a shard group currently forwarded to. Each packet goes to exactly one member,
+/// chosen by rendezvous hashing so a member joining or leaving only moves the keys it owns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShardGroup {
+    pub members: Vec<SocketAddr>,
+    pub shard_by: ShardBy,
+}
+
+impl ShardGroup {
+    /// Returns the member `packet` is sent to, or None if the group has no members
+    pub fn member_for(&self, packet: &[u8]) -> Option<SocketAddr> {
+        let key = shard_key(packet, self.shard_by);
+        self.members
+            .iter()
+            .max_by_key(|member| rendezvous_weight(key, member))
+            .copied()
+    }
+
+    /// Splits `packets` by the member each is sent to, preserving order within a member
+    pub fn assign<'a>(&self, packets: &[&'a [u8]]) -> HashMap<SocketAddr, Vec<&'a [u8]>> {
+        let mut assigned = HashMap::<SocketAddr, Vec<&[u8]>>::new();
+        for packet in packets {
+            if let Some(member) = self.member_for(packet) {
+                assigned.entry(member).or_default().push(packet);
+            }
+        }
+        assigned
+    }
+}
+
+/// Hashes the fields packets are sharded by. Packets that don't parse as shreds are hashed whole
+fn shard_key(packet: &[u8], shard_by: ShardBy) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    match (shred::get_slot(packet), shard_by) {
+        (Some(slot), ShardBy::Slot) => slot.hash(&mut hasher),
+        (Some(slot), ShardBy::Shred) => (slot, shred::get_index(packet)).hash(&mut hasher),
+        (None, _) => packet.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+/// `DefaultHasher::new` uses fixed keys, so every forwarder thread and restart agrees on the member
+fn rendezvous_weight(key: u64, member: &SocketAddr) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    member.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use crate::{
+        shard::{parse_shard_group, ShardBy, ShardGroup, ShardGroupSpec, ShardMembers},
+        shred::tests::new_data_shred,
+    };
+
+    #[test]
+    fn test_parse_shard_group() {
+        assert_eq!(
+            parse_shard_group("shard(127.0.0.1:9000, 127.0.0.1:9001,127.0.0.1:9000)").unwrap(),
+            ShardGroupSpec {
+                members: ShardMembers::Static(vec![
+                    (
+                        SocketAddr::from_str("127.0.0.1:9000").unwrap(),
+                        "127.0.0.1:9000".to_string()
+                    ),
+                    (
+                        SocketAddr::from_str("127.0.0.1:9001").unwrap(),
+                        "127.0.0.1:9001".to_string()
+                    ),
+                ]),
+                shard_by: ShardBy::Shred,
+            }
+        );
+        assert_eq!(
+            parse_shard_group("shard(discovered)?shard-by=slot").unwrap(),
+            ShardGroupSpec {
+                members: ShardMembers::Discovered,
+                shard_by: ShardBy::Slot,
+            }
+        );
+
+        for invalid in [
+            "127.0.0.1:9000",
+            "shard()",
+            "shard(127.0.0.1:9000",
+            "shard(127.0.0.1:9000)?shard-by=fec",
+            "shard(quic://127.0.0.1:9000)",
+            "shard(tcp://127.0.0.1:9000)",
+            "shard(127.0.0.1:9000?rate=10pps)",
+        ] {
+            assert!(parse_shard_group(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_shard_group_consistent_hashing() {
+        let members = (9000..9004)
+            .map(|port| SocketAddr::from(([127, 0, 0, 1], port)))
+            .collect::<Vec<_>>();
+        let group = ShardGroup {
+            members: members.clone(),
+            shard_by: ShardBy::Shred,
+        };
+        let shreds = (0..400)
+            .map(|i| new_data_shred(100 + i / 100, i as u32 % 100, 0, false, &[]))
+            .collect::<Vec<_>>();
+        let packets = shreds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        // every shred goes to exactly one member, and every member gets some
+        let assigned = group.assign(&packets);
+        assert_eq!(assigned.len(), members.len());
+        assert_eq!(
+            assigned.values().map(Vec::len).sum::<usize>(),
+            packets.len()
+        );
+
+        // removing a member only moves the shreds it owned
+        let smaller_group = ShardGroup {
+            members: members[1..].to_vec(),
+            shard_by: ShardBy::Shred,
+        };
+        for packet in &packets {
+            let before = group.member_for(packet).unwrap();
+            let after = smaller_group.member_for(packet).unwrap();
+            assert!(before == members[0] || before == after);
+        }
+
+        // sharding by slot keeps a slot together
+        let slot_group = ShardGroup {
+            members,
+            shard_by: ShardBy::Slot,
+        };
+        for slot_packets in packets.chunks(100) {
+            let member = slot_group.member_for(slot_packets[0]);
+            assert!(slot_packets
+                .iter()
+                .all(|packet| slot_group.member_for(packet) == member));
+        }
+
+        assert_eq!(
+            ShardGroup {
+                members: vec![],
+                shard_by: ShardBy::Shred,
+            }
+            .member_for(packets[0]),
+            None
+        );
+    }
+}
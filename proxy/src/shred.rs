@@ -0,0 +1,332 @@
+use solana_sdk::{clock::Slot, packet::PACKET_DATA_SIZE};
+
+// offsets into the shred common header, read directly to avoid deserializing the whole shred
+// see https://github.com/anza-xyz/agave/blob/master/ledger/src/shred.rs
+const SIGNATURE_LEN: usize = 64;
+const OFFSET_OF_SHRED_VARIANT: usize = SIGNATURE_LEN;
+const OFFSET_OF_SHRED_SLOT: usize = OFFSET_OF_SHRED_VARIANT + 1;
+const OFFSET_OF_SHRED_INDEX: usize = OFFSET_OF_SHRED_SLOT + 8;
+// skips the u16 shred version
+const OFFSET_OF_FEC_SET_INDEX: usize = OFFSET_OF_SHRED_INDEX + 4 + 2;
+const SIZE_OF_COMMON_SHRED_HEADER: usize = 83;
+// data shred header follows the common header, starting with the u16 parent offset
+const OFFSET_OF_DATA_FLAGS: usize = SIZE_OF_COMMON_SHRED_HEADER + 2;
+const OFFSET_OF_DATA_SIZE: usize = OFFSET_OF_DATA_FLAGS + 1;
+pub const SIZE_OF_DATA_SHRED_HEADERS: usize = OFFSET_OF_DATA_SIZE + 2;
+
+/// Set on the last data shred of a serialized entry batch
+const DATA_COMPLETE_SHRED: u8 = 0b0100_0000;
+/// Set along with `DATA_COMPLETE_SHRED` on the last data shred of a slot
+const LAST_SHRED_IN_SLOT: u8 = 0b1100_0000;
+/// Data shreds in a legacy FEC set
+const DATA_SHREDS_PER_FEC_BLOCK: u32 = 32;
+
+const LEGACY_CODE_VARIANT: u8 = 0x5a;
+const LEGACY_DATA_VARIANT: u8 = 0xa5;
+
+/// Returns true if the shred variant byte is one a validator would produce
+fn is_valid_variant(variant: u8) -> bool {
+    match variant {
+        LEGACY_CODE_VARIANT | LEGACY_DATA_VARIANT => true,
+        // merkle code, chained, and chained + resigned
+        // merkle data, chained, and chained + resigned
+        _ => matches!(variant & 0xf0, 0x40 | 0x60 | 0x70 | 0x80 | 0x90 | 0xb0),
+    }
+}
+
+/// Returns true if the shred variant byte is a data shred: legacy, merkle, chained, or chained + resigned
+fn is_data_variant(variant: u8) -> bool {
+    variant == LEGACY_DATA_VARIANT || matches!(variant & 0xf0, 0x80 | 0x90 | 0xb0)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShredType {
+    Data,
+    Code,
+}
+
+/// Returns the shred type from the length and variant byte alone, or None if the packet isn't a shred
+pub fn get_shred_type(shred: &[u8]) -> Option<ShredType> {
+    if !(SIZE_OF_COMMON_SHRED_HEADER..=PACKET_DATA_SIZE).contains(&shred.len()) {
+        return None;
+    }
+    let variant = shred[OFFSET_OF_SHRED_VARIANT];
+    if !is_valid_variant(variant) {
+        None
+    } else if is_data_variant(variant) {
+        Some(ShredType::Data)
+    } else {
+        Some(ShredType::Code)
+    }
+}
+
+/// Returns the header bytes identifying the shred: variant (which carries the shred type), slot, index, and version.
+/// Copies of the same shred share these, so deduping on them skips hashing the payload. None if the packet isn't a shred
+pub fn get_shred_id(shred: &[u8]) -> Option<&[u8]> {
+    get_shred_type(shred)?;
+    shred.get(OFFSET_OF_SHRED_VARIANT..OFFSET_OF_FEC_SET_INDEX)
+}
+
+/// Fields of a data shred needed to reassemble entries
+#[derive(Debug, PartialEq, Eq)]
+pub struct DataShred<'a> {
+    pub slot: Slot,
+    pub index: u32,
+    pub fec_set_index: u32,
+    /// Last shred of an entry batch
+    pub data_complete: bool,
+    /// Serialized entry bytes carried by this shred
+    pub data: &'a [u8],
+}
+
+/// Returns the slot of the shred, or None if the packet isn't a shred
+pub fn get_slot(shred: &[u8]) -> Option<Slot> {
+    if shred.len() < SIZE_OF_COMMON_SHRED_HEADER
+        || !is_valid_variant(shred[OFFSET_OF_SHRED_VARIANT])
+    {
+        return None;
+    }
+    let bytes = shred.get(OFFSET_OF_SHRED_SLOT..OFFSET_OF_SHRED_SLOT + 8)?;
+    Some(Slot::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Returns the index of the shred within its slot, or None if the packet isn't a shred
+pub fn get_index(shred: &[u8]) -> Option<u32> {
+    if shred.len() < SIZE_OF_COMMON_SHRED_HEADER
+        || !is_valid_variant(shred[OFFSET_OF_SHRED_VARIANT])
+    {
+        return None;
+    }
+    let bytes = shred.get(OFFSET_OF_SHRED_INDEX..OFFSET_OF_SHRED_INDEX + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Returns the index of the first data shred of the shred's FEC set, or None if the packet isn't a shred.
+/// Data and coding shreds of a set share it, so it groups the shreds needed to recover each other
+pub fn get_fec_set_index(shred: &[u8]) -> Option<u32> {
+    if shred.len() < SIZE_OF_COMMON_SHRED_HEADER
+        || !is_valid_variant(shred[OFFSET_OF_SHRED_VARIANT])
+    {
+        return None;
+    }
+    let bytes = shred.get(OFFSET_OF_FEC_SET_INDEX..OFFSET_OF_FEC_SET_INDEX + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Returns true if the packet is the last data shred of its slot
+pub fn is_last_in_slot(shred: &[u8]) -> bool {
+    get_shred_type(shred) == Some(ShredType::Data)
+        && shred
+            .get(OFFSET_OF_DATA_FLAGS)
+            .is_some_and(|flags| flags & LAST_SHRED_IN_SLOT == LAST_SHRED_IN_SLOT)
+}
+
+/// Returns the data shred fields, or None if the packet isn't a data shred
+pub fn get_data_shred(shred: &[u8]) -> Option<DataShred<'_>> {
+    if shred.len() < SIZE_OF_DATA_SHRED_HEADERS || !is_data_variant(shred[OFFSET_OF_SHRED_VARIANT])
+    {
+        return None;
+    }
+    let read_u32 =
+        |offset: usize| u32::from_le_bytes(shred[offset..offset + 4].try_into().unwrap());
+    // size includes the headers, so merkle proofs and padding after the data are excluded
+    let size = u16::from_le_bytes(
+        shred[OFFSET_OF_DATA_SIZE..SIZE_OF_DATA_SHRED_HEADERS]
+            .try_into()
+            .unwrap(),
+    ) as usize;
+    Some(DataShred {
+        slot: get_slot(shred)?,
+        index: read_u32(OFFSET_OF_SHRED_INDEX),
+        fec_set_index: read_u32(OFFSET_OF_FEC_SET_INDEX),
+        data_complete: shred[OFFSET_OF_DATA_FLAGS] & DATA_COMPLETE_SHRED != 0,
+        data: shred.get(SIZE_OF_DATA_SHRED_HEADERS..size)?,
+    })
+}
+
+/// Overwrites the headers of `packet` with a legacy data shred of `slot` and `index` whose data fills the rest of the packet.
+/// Used to generate synthetic shreds, `packet` must be at least `SIZE_OF_DATA_SHRED_HEADERS` and at most `u16::MAX` long
+pub fn write_data_shred_headers(packet: &mut [u8], slot: Slot, index: u32) {
+    let fec_set_index = index - index % DATA_SHREDS_PER_FEC_BLOCK;
+    packet[OFFSET_OF_SHRED_VARIANT] = LEGACY_DATA_VARIANT;
+    packet[OFFSET_OF_SHRED_SLOT..OFFSET_OF_SHRED_INDEX].copy_from_slice(&slot.to_le_bytes());
+    packet[OFFSET_OF_SHRED_INDEX..OFFSET_OF_SHRED_INDEX + 4].copy_from_slice(&index.to_le_bytes());
+    packet[OFFSET_OF_FEC_SET_INDEX..OFFSET_OF_FEC_SET_INDEX + 4]
+        .copy_from_slice(&fec_set_index.to_le_bytes());
+    // parent offset of 1
+    packet[SIZE_OF_COMMON_SHRED_HEADER..OFFSET_OF_DATA_FLAGS].copy_from_slice(&1u16.to_le_bytes());
+    packet[OFFSET_OF_DATA_FLAGS] = 0;
+    let size = packet.len() as u16;
+    packet[OFFSET_OF_DATA_SIZE..SIZE_OF_DATA_SHRED_HEADERS].copy_from_slice(&size.to_le_bytes());
+}
+
+#[cfg(test)]
+pub mod tests {
+    use solana_sdk::{clock::Slot, packet::PACKET_DATA_SIZE};
+
+    use crate::shred::{
+        get_data_shred, get_fec_set_index, get_index, get_shred_id, get_shred_type, get_slot,
+        is_last_in_slot, write_data_shred_headers, DataShred, ShredType, DATA_COMPLETE_SHRED,
+        LAST_SHRED_IN_SLOT, OFFSET_OF_DATA_FLAGS, OFFSET_OF_DATA_SIZE, OFFSET_OF_FEC_SET_INDEX,
+        OFFSET_OF_SHRED_INDEX, OFFSET_OF_SHRED_SLOT, OFFSET_OF_SHRED_VARIANT,
+        SIZE_OF_DATA_SHRED_HEADERS,
+    };
+
+    fn new_shred(variant: u8, slot: u64) -> Vec<u8> {
+        let mut shred = vec![0u8; 1203];
+        shred[OFFSET_OF_SHRED_VARIANT] = variant;
+        shred[OFFSET_OF_SHRED_SLOT..OFFSET_OF_SHRED_SLOT + 8].copy_from_slice(&slot.to_le_bytes());
+        shred
+    }
+
+    /// Returns a legacy data shred carrying `data`, shared with deshred tests
+    pub fn new_data_shred(
+        slot: Slot,
+        index: u32,
+        fec_set_index: u32,
+        data_complete: bool,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let mut shred = new_shred(0xa5, slot);
+        shred[OFFSET_OF_SHRED_INDEX..OFFSET_OF_SHRED_INDEX + 4]
+            .copy_from_slice(&index.to_le_bytes());
+        shred[OFFSET_OF_FEC_SET_INDEX..OFFSET_OF_FEC_SET_INDEX + 4]
+            .copy_from_slice(&fec_set_index.to_le_bytes());
+        if data_complete {
+            shred[OFFSET_OF_DATA_FLAGS] = DATA_COMPLETE_SHRED;
+        }
+        let size = (SIZE_OF_DATA_SHRED_HEADERS + data.len()) as u16;
+        shred[OFFSET_OF_DATA_SIZE..SIZE_OF_DATA_SHRED_HEADERS].copy_from_slice(&size.to_le_bytes());
+        shred[SIZE_OF_DATA_SHRED_HEADERS..size as usize].copy_from_slice(data);
+        shred
+    }
+
+    /// Marks a data shred from [new_data_shred] as the last of its slot
+    pub fn set_last_in_slot(shred: &mut [u8]) {
+        shred[OFFSET_OF_DATA_FLAGS] |= LAST_SHRED_IN_SLOT;
+    }
+
+    #[test]
+    fn test_get_slot() {
+        assert_eq!(get_slot(&new_shred(0xa5, 42)), Some(42));
+        assert_eq!(get_slot(&new_shred(0x5a, 42)), Some(42));
+        // merkle data and code with proof size in the low nibble
+        assert_eq!(get_slot(&new_shred(0x86, 300_000_000)), Some(300_000_000));
+        assert_eq!(get_slot(&new_shred(0x46, 7)), Some(7));
+
+        assert_eq!(get_slot(&new_shred(0x00, 42)), None);
+        assert_eq!(get_slot(&[0xa5; 10]), None);
+        assert_eq!(get_slot(&[]), None);
+    }
+
+    #[test]
+    fn test_get_index() {
+        assert_eq!(get_index(&new_data_shred(42, 7, 5, false, &[])), Some(7));
+        assert_eq!(get_index(&new_shred(0x00, 42)), None);
+        assert_eq!(get_index(&[0xa5; 10]), None);
+        assert_eq!(
+            get_fec_set_index(&new_data_shred(42, 7, 5, false, &[])),
+            Some(5)
+        );
+        assert_eq!(get_fec_set_index(&new_shred(0x00, 42)), None);
+    }
+
+    #[test]
+    fn test_get_shred_id() {
+        let shred = new_data_shred(42, 7, 0, false, &[1, 2, 3]);
+        let id = get_shred_id(&shred).unwrap();
+        assert_eq!(id.len(), 15);
+        // the same shred with a different payload has the same id
+        assert_eq!(
+            get_shred_id(&new_data_shred(42, 7, 0, true, &[4, 5])),
+            Some(id)
+        );
+        assert_ne!(
+            get_shred_id(&new_data_shred(42, 8, 0, false, &[])),
+            Some(id)
+        );
+        assert_ne!(
+            get_shred_id(&new_data_shred(43, 7, 0, false, &[])),
+            Some(id)
+        );
+        let mut code = shred.clone();
+        code[OFFSET_OF_SHRED_VARIANT] = 0x5a;
+        assert_ne!(get_shred_id(&code), Some(id));
+        assert_eq!(get_shred_id(&new_shred(0x00, 42)), None);
+        assert_eq!(get_shred_id(&[0xa5; 10]), None);
+    }
+
+    #[test]
+    fn test_get_shred_type() {
+        for (variant, expected) in [
+            (0xa5, Some(ShredType::Data)),
+            (0x86, Some(ShredType::Data)),
+            (0x96, Some(ShredType::Data)),
+            (0xb6, Some(ShredType::Data)),
+            (0x5a, Some(ShredType::Code)),
+            (0x46, Some(ShredType::Code)),
+            (0x66, Some(ShredType::Code)),
+            (0x76, Some(ShredType::Code)),
+            (0x00, None),
+            (0xff, None),
+        ] {
+            assert_eq!(
+                get_shred_type(&new_shred(variant, 42)),
+                expected,
+                "{variant:#x}"
+            );
+        }
+        assert_eq!(get_shred_type(&[0xa5; 10]), None);
+        let mut oversized = new_shred(0xa5, 42);
+        oversized.resize(PACKET_DATA_SIZE + 1, 0);
+        assert_eq!(get_shred_type(&oversized), None);
+    }
+
+    #[test]
+    fn test_is_last_in_slot() {
+        let mut shred = new_data_shred(42, 7, 0, true, &[]);
+        assert!(!is_last_in_slot(&shred));
+        set_last_in_slot(&mut shred);
+        assert!(is_last_in_slot(&shred));
+        assert!(get_data_shred(&shred).unwrap().data_complete);
+
+        // code shreds have no data flags
+        let mut code = new_shred(0x5a, 42);
+        code[OFFSET_OF_DATA_FLAGS] = LAST_SHRED_IN_SLOT;
+        assert!(!is_last_in_slot(&code));
+        assert!(!is_last_in_slot(&[0xa5; 10]));
+    }
+
+    #[test]
+    fn test_get_data_shred() {
+        let shred = new_data_shred(42, 7, 5, true, &[1, 2, 3]);
+        assert_eq!(
+            get_data_shred(&shred),
+            Some(DataShred {
+                slot: 42,
+                index: 7,
+                fec_set_index: 5,
+                data_complete: true,
+                data: &[1, 2, 3],
+            })
+        );
+
+        // code shreds and sizes past the end of the packet are rejected
+        assert_eq!(get_data_shred(&new_shred(0x5a, 42)), None);
+        let mut shred = new_data_shred(42, 7, 5, false, &[]);
+        shred[OFFSET_OF_DATA_SIZE..SIZE_OF_DATA_SHRED_HEADERS]
+            .copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(get_data_shred(&shred), None);
+    }
+
+    #[test]
+    fn test_write_data_shred_headers() {
+        let mut packet = vec![0xffu8; 1228];
+        write_data_shred_headers(&mut packet, 42, 37);
+        let shred = get_data_shred(&packet).unwrap();
+        assert_eq!((shred.slot, shred.index, shred.fec_set_index), (42, 37, 32));
+        assert!(!shred.data_complete);
+        // the data fills the rest of the packet
+        assert_eq!(shred.data.len(), 1228 - SIZE_OF_DATA_SHRED_HEADERS);
+    }
+}
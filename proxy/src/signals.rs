@@ -0,0 +1,137 @@
+//! Signals the proxy binary responds to. On unix `SIGINT` and `SIGTERM` shut down, `SIGHUP` reloads,
+//! and `SIGUSR1`/`SIGUSR2` pause and resume heartbeats. On Windows only Ctrl-C, Ctrl-Break, and closing the console
+//! shut down, pausing heartbeats is left to the admin API.
+
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crossbeam_channel::{Receiver, Sender};
+#[cfg(windows)]
+use log::warn;
+
+use crate::{broadcast_shutdown, forwarder::ShredMetrics, set_heartbeats_paused};
+
+/// Creates a channel that gets a message every time `SIGINT` or `SIGTERM` is signalled, or Ctrl-C on Windows
+pub fn shutdown_notifier(exit: Arc<AtomicBool>) -> io::Result<(Sender<()>, Receiver<()>)> {
+    let (s, r) = crossbeam_channel::bounded(256);
+    platform::on_shutdown_signal({
+        let s = s.clone();
+        move || signal_shutdown(&exit, &s)
+    })?;
+    Ok((s, r))
+}
+
+/// Creates a channel that gets a message every time `SIGHUP` is signalled
+#[cfg(unix)]
+pub fn reload_notifier() -> io::Result<Receiver<()>> {
+    let (s, r) = crossbeam_channel::bounded(1);
+    platform::on_reload_signal(move || {
+        // coalesce repeated signals while a reload is pending
+        let _ = s.try_send(());
+    })?;
+    Ok(r)
+}
+
+/// There's no `SIGHUP` on Windows, so the channel never gets a message
+#[cfg(windows)]
+pub fn reload_notifier() -> io::Result<Receiver<()>> {
+    warn!("Reloading on SIGHUP isn't supported on Windows, restart the proxy to apply changes.");
+    Ok(crossbeam_channel::never())
+}
+
+/// Pauses heartbeats on `SIGUSR1` and resumes them on `SIGUSR2`, for block engine maintenance. Does nothing on Windows
+pub fn heartbeat_pause_notifier(metrics: Arc<ShredMetrics>) -> io::Result<()> {
+    platform::on_heartbeat_pause_signal(move |paused| {
+        set_heartbeats_paused(&metrics, paused, "signal");
+    })
+}
+
+fn signal_shutdown(exit: &AtomicBool, shutdown_sender: &Sender<()>) {
+    exit.store(true, Ordering::SeqCst);
+    broadcast_shutdown(shutdown_sender);
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::{io, thread};
+
+    use signal_hook::{
+        consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2},
+        iterator::Signals,
+    };
+
+    /// Calls `handler` from a dedicated thread for each signal in `signals`
+    fn on_signals(
+        name: &str,
+        signals: &[i32],
+        mut handler: impl FnMut(i32) + Send + 'static,
+    ) -> io::Result<()> {
+        let mut signals = Signals::new(signals)?;
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    handler(signal);
+                }
+            })?;
+        Ok(())
+    }
+
+    pub fn on_shutdown_signal(mut handler: impl FnMut() + Send + 'static) -> io::Result<()> {
+        on_signals("ssPxySignals", &[SIGINT, SIGTERM], move |_| handler())
+    }
+
+    pub fn on_reload_signal(mut handler: impl FnMut() + Send + 'static) -> io::Result<()> {
+        on_signals("ssPxySighup", &[SIGHUP], move |_| handler())
+    }
+
+    /// `handler` is passed true to pause heartbeats, false to resume them
+    pub fn on_heartbeat_pause_signal(
+        mut handler: impl FnMut(bool) + Send + 'static,
+    ) -> io::Result<()> {
+        on_signals("ssPxySigusr", &[SIGUSR1, SIGUSR2], move |signal| {
+            handler(signal == SIGUSR1)
+        })
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::io;
+
+    /// Ctrl-C, Ctrl-Break, and closing the console, which `ctrlc` calls `handler` for from its own thread
+    pub fn on_shutdown_signal(handler: impl FnMut() + Send + 'static) -> io::Result<()> {
+        ctrlc::set_handler(handler).map_err(io::Error::other)
+    }
+
+    pub fn on_heartbeat_pause_signal(
+        _handler: impl FnMut(bool) + Send + 'static,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use crate::signals::signal_shutdown;
+
+    #[test]
+    fn test_signal_shutdown() {
+        let exit = AtomicBool::new(false);
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(256);
+
+        signal_shutdown(&exit, &shutdown_sender);
+        assert!(exit.load(Ordering::SeqCst));
+        assert_eq!(shutdown_receiver.len(), 256);
+        // signalled again while shutting down
+        signal_shutdown(&exit, &shutdown_sender);
+        assert_eq!(shutdown_receiver.len(), 256);
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, MutexGuard},
+    time::Instant,
+};
+
+use crossbeam_channel::{Receiver, Sender};
+use solana_sdk::clock::Slot;
+
+use crate::{
+    forwarder::HighestSlot,
+    shred::{self, ShredType},
+};
+
+/// Slots this far behind the highest slot seen are finalized and counted, shreds for them arriving later are ignored
+pub const SLOT_FINALIZE_LAG: Slot = 32;
+/// Slots tracked at once, the lowest are finalized early if shreds span more than this
+const MAX_TRACKED_SLOTS: usize = 256;
+/// Finalized slots whose data shred count is kept for the percentiles, per interval
+const MAX_FINALIZED_SAMPLES: usize = 4_096;
+/// Batches of forwarded shreds queued for the accessory thread to count
+const SLOT_COVERAGE_QUEUE_CAPACITY: usize = 4_096;
+
+/// What slot coverage counts of a forwarded shred
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CoverageShred {
+    slot: Slot,
+    data: bool,
+    last_in_slot: bool,
+}
+
+impl CoverageShred {
+    /// Returns None for packets that aren't shreds
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        let data = shred::get_shred_type(packet)? == ShredType::Data;
+        Some(Self {
+            slot: shred::get_slot(packet)?,
+            data,
+            last_in_slot: data && shred::is_last_in_slot(packet),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct SlotShreds {
+    data_shreds: u32,
+    last_shred_seen: bool,
+    /// Shreds were received for the slot this interval
+    observed: bool,
+}
+
+/// Slot coverage over a metrics interval
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SlotCoverageStats {
+    /// Distinct slots shreds were received for
+    pub slots_observed: u64,
+    /// Slots that fell `SLOT_FINALIZE_LAG` behind the highest slot
+    pub slots_finalized: u64,
+    /// Finalized slots whose last data shred was received
+    pub slots_last_shred_seen: u64,
+    /// (min, median, max) data shreds received per finalized slot, None if no slot was finalized
+    pub data_shreds_per_slot: Option<(u32, u32, u32)>,
+}
+
+/// Counts data shreds per recent slot, to tell whether whole slots are being received
+#[derive(Default)]
+pub struct SlotCoverage {
+    slots: BTreeMap<Slot, SlotShreds>,
+    /// Bounded like the packet filter's, so a spoofed far future slot can't finalize every slot early
+    highest_slot: HighestSlot,
+    /// Highest slot when slots were last finalized
+    finalized_at_slot: Slot,
+    slots_observed: u64,
+    slots_finalized: u64,
+    slots_last_shred_seen: u64,
+    finalized_data_shreds: Vec<u32>,
+}
+
+impl SlotCoverage {
+    /// Records forwarded shreds received at `now`
+    pub fn record(&mut self, shreds: &[CoverageShred], now: Instant) {
+        for shred in shreds {
+            let slot = shred.slot;
+            // shreds sampled to seed the highest slot aren't counted, their slots may be outliers
+            let Some(highest_slot) = self.highest_slot.observe(slot, now) else {
+                continue;
+            };
+            // outliers too far ahead to advance the highest slot aren't counted either
+            if slot > highest_slot || slot.saturating_add(SLOT_FINALIZE_LAG) < highest_slot {
+                continue;
+            }
+            if slot == highest_slot && slot != self.finalized_at_slot {
+                // reseeded lower, slots above came with the bad seed
+                if slot < self.finalized_at_slot {
+                    self.slots.split_off(&slot);
+                }
+                self.finalized_at_slot = slot;
+                let unfinalized = self
+                    .slots
+                    .split_off(&slot.saturating_sub(SLOT_FINALIZE_LAG));
+                let finalized = std::mem::replace(&mut self.slots, unfinalized);
+                finalized.into_values().for_each(|slot| self.finalize(slot));
+            }
+
+            let slot_shreds = self.slots.entry(slot).or_default();
+            if !slot_shreds.observed {
+                slot_shreds.observed = true;
+                self.slots_observed += 1;
+            }
+            if shred.data {
+                slot_shreds.data_shreds += 1;
+                slot_shreds.last_shred_seen |= shred.last_in_slot;
+            }
+
+            while self.slots.len() > MAX_TRACKED_SLOTS {
+                let (_, lowest) = self.slots.pop_first().unwrap();
+                self.finalize(lowest);
+            }
+        }
+    }
+
+    fn finalize(&mut self, slot: SlotShreds) {
+        self.slots_finalized += 1;
+        self.slots_last_shred_seen += slot.last_shred_seen as u64;
+        if self.finalized_data_shreds.len() < MAX_FINALIZED_SAMPLES {
+            self.finalized_data_shreds.push(slot.data_shreds);
+        }
+    }
+
+    /// Returns the stats since the last [Self::reset]
+    pub fn stats(&self) -> SlotCoverageStats {
+        let mut data_shreds = self.finalized_data_shreds.clone();
+        data_shreds.sort_unstable();
+        SlotCoverageStats {
+            slots_observed: self.slots_observed,
+            slots_finalized: self.slots_finalized,
+            slots_last_shred_seen: self.slots_last_shred_seen,
+            data_shreds_per_slot: (!data_shreds.is_empty()).then(|| {
+                (
+                    data_shreds[0],
+                    data_shreds[data_shreds.len() / 2],
+                    data_shreds[data_shreds.len() - 1],
+                )
+            }),
+        }
+    }
+
+    /// Starts a new interval, keeping the slots still being received
+    pub fn reset(&mut self) {
+        self.slots_observed = 0;
+        self.slots_finalized = 0;
+        self.slots_last_shred_seen = 0;
+        self.finalized_data_shreds.clear();
+        self.slots
+            .values_mut()
+            .for_each(|slot| slot.observed = false);
+    }
+}
+
+/// Slot coverage of forwarded shreds. Forwarder threads queue what they forward with [Self::send],
+/// which the accessory thread counts, so the forwarding path never locks the coverage
+pub struct SlotCoverageTap {
+    shred_sender: Sender<Vec<CoverageShred>>,
+    shred_receiver: Receiver<Vec<CoverageShred>>,
+    coverage: Mutex<SlotCoverage>,
+}
+
+impl Default for SlotCoverageTap {
+    fn default() -> Self {
+        let (shred_sender, shred_receiver) =
+            crossbeam_channel::bounded(SLOT_COVERAGE_QUEUE_CAPACITY);
+        Self {
+            shred_sender,
+            shred_receiver,
+            coverage: Mutex::new(SlotCoverage::default()),
+        }
+    }
+}
+
+impl SlotCoverageTap {
+    pub fn send(&self, packets: &[&[u8]]) {
+        let shreds = packets
+            .iter()
+            .filter_map(|packet| CoverageShred::parse(packet))
+            .collect::<Vec<_>>();
+        if shreds.is_empty() {
+            return;
+        }
+        // drop instead of blocking forwarding when the accessory thread falls behind
+        let _ = self.shred_sender.try_send(shreds);
+    }
+
+    /// Batches queued by [Self::send], for the accessory thread to pass to [Self::record]
+    pub fn receiver(&self) -> &Receiver<Vec<CoverageShred>> {
+        &self.shred_receiver
+    }
+
+    /// Records shreds taken from [Self::receiver] at `now`
+    pub fn record(&self, shreds: &[CoverageShred], now: Instant) {
+        self.coverage.lock().unwrap().record(shreds, now);
+    }
+
+    /// Returns the stats since the last [Self::take_stats], counting batches still queued first
+    pub fn stats(&self) -> SlotCoverageStats {
+        self.record_queued().stats()
+    }
+
+    /// Returns the stats and starts a new interval
+    pub fn take_stats(&self) -> SlotCoverageStats {
+        let mut coverage = self.record_queued();
+        let stats = coverage.stats();
+        coverage.reset();
+        stats
+    }
+
+    fn record_queued(&self) -> MutexGuard<'_, SlotCoverage> {
+        let mut coverage = self.coverage.lock().unwrap();
+        let now = Instant::now();
+        self.shred_receiver
+            .try_iter()
+            .for_each(|shreds| coverage.record(&shreds, now));
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use solana_sdk::clock::{Slot, DEFAULT_MS_PER_SLOT};
+
+    use crate::{
+        forwarder::HIGHEST_SLOT_SEED_SAMPLES,
+        shred::tests::{new_data_shred, set_last_in_slot},
+        slot_coverage::{CoverageShred, SlotCoverage, SlotCoverageStats, SLOT_FINALIZE_LAG},
+    };
+
+    fn coverage_shreds(shreds: &[Vec<u8>]) -> Vec<CoverageShred> {
+        shreds
+            .iter()
+            .map(|shred| CoverageShred::parse(shred).unwrap())
+            .collect()
+    }
+
+    /// Seeds the highest slot seen with `slot`, the shreds sampled to seed it aren't counted
+    fn seed(coverage: &mut SlotCoverage, slot: Slot, now: Instant) {
+        let shreds = vec![new_data_shred(slot, 0, 0, false, &[]); HIGHEST_SLOT_SEED_SAMPLES];
+        coverage.record(&coverage_shreds(&shreds), now);
+        assert_eq!(coverage.stats(), SlotCoverageStats::default());
+    }
+
+    #[test]
+    fn test_slot_coverage() {
+        let mut coverage = SlotCoverage::default();
+        let start = Instant::now();
+        // received as the slot clock reaches each slot
+        let at_slot =
+            |slot: Slot| start + Duration::from_millis((slot - 100) * DEFAULT_MS_PER_SLOT);
+        let mut shreds = (0..10)
+            .map(|index| new_data_shred(100, index, 0, false, &[]))
+            .chain((0..4).map(|index| new_data_shred(101, index, 0, false, &[])))
+            .collect::<Vec<_>>();
+        set_last_in_slot(&mut shreds[9]);
+        seed(&mut coverage, 100, start);
+        coverage.record(&coverage_shreds(&shreds), start);
+        assert_eq!(CoverageShred::parse(b"not a shred"), None);
+        assert_eq!(
+            coverage.stats(),
+            SlotCoverageStats {
+                slots_observed: 2,
+                ..Default::default()
+            }
+        );
+
+        // slots are finalized once far enough behind, late shreds for them are ignored
+        coverage.reset();
+        let slot = 101 + SLOT_FINALIZE_LAG + 1;
+        coverage.record(
+            &coverage_shreds(&[new_data_shred(slot, 0, 0, false, &[])]),
+            at_slot(slot),
+        );
+        coverage.record(
+            &coverage_shreds(&[new_data_shred(100, 10, 0, false, &[])]),
+            at_slot(slot),
+        );
+        assert_eq!(
+            coverage.stats(),
+            SlotCoverageStats {
+                slots_observed: 1,
+                slots_finalized: 2,
+                slots_last_shred_seen: 1,
+                data_shreds_per_slot: Some((4, 10, 10)),
+            }
+        );
+
+        // the slot still being received carries over to the next interval
+        coverage.reset();
+        let next_slot = slot + SLOT_FINALIZE_LAG + 1;
+        coverage.record(
+            &coverage_shreds(&[new_data_shred(next_slot, 0, 0, false, &[])]),
+            at_slot(next_slot),
+        );
+        assert_eq!(
+            coverage.stats(),
+            SlotCoverageStats {
+                slots_observed: 1,
+                slots_finalized: 1,
+                slots_last_shred_seen: 0,
+                data_shreds_per_slot: Some((1, 1, 1)),
+            }
+        );
+    }
+
+    #[test]
+    fn test_slot_coverage_ignores_outlier_slot() {
+        let mut coverage = SlotCoverage::default();
+        let mut slots = vec![100; HIGHEST_SLOT_SEED_SAMPLES];
+        slots[0] = u64::MAX - 1;
+        slots.extend([100, u64::MAX - 1, 101]);
+        let shreds = slots
+            .into_iter()
+            .map(|slot| new_data_shred(slot, 0, 0, false, &[]))
+            .collect::<Vec<_>>();
+        coverage.record(&coverage_shreds(&shreds), Instant::now());
+        // the spoofed slot neither seeds, counts, nor finalizes the slots being received
+        assert_eq!(
+            coverage.stats(),
+            SlotCoverageStats {
+                slots_observed: 2,
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{clock::Slot, commitment_config::CommitmentConfig};
+
+use crate::{
+    metrics_backend::{datapoint_info, datapoint_warn},
+    {shred, supervisor::Supervisor},
+};
+
+/// Slots whose first shred time is kept, a few minutes of slots
+const MAX_TRACKED_SLOTS: usize = 512;
+/// Recent latencies the rolling percentiles are computed over
+const ROLLING_WINDOW_SLOTS: usize = 1_000;
+/// How often the RPC is asked for its slot. Bounds how precisely the RPC's side is timed
+const RPC_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RPC_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Records when the first shred of each slot was received, for comparing against the reference RPC
+pub struct SlotLatencyTap {
+    /// (slot, first shred time), indexed by slot modulo `MAX_TRACKED_SLOTS` so older slots are overwritten
+    first_shreds: Mutex<Vec<Option<(Slot, SystemTime)>>>,
+}
+
+impl Default for SlotLatencyTap {
+    fn default() -> Self {
+        Self {
+            first_shreds: Mutex::new(vec![None; MAX_TRACKED_SLOTS]),
+        }
+    }
+}
+
+impl SlotLatencyTap {
+    pub fn record(&self, packets: &[&[u8]], received_time: SystemTime) {
+        let mut first_shreds = None;
+        let mut last_slot = None;
+        for slot in packets.iter().filter_map(|packet| shred::get_slot(packet)) {
+            // shreds of a slot arrive together, so skip most lookups
+            if last_slot == Some(slot) {
+                continue;
+            }
+            last_slot = Some(slot);
+            let first_shreds =
+                first_shreds.get_or_insert_with(|| self.first_shreds.lock().unwrap());
+            let first_shred = &mut first_shreds[slot as usize % MAX_TRACKED_SLOTS];
+            if first_shred.map_or(true, |(tracked_slot, _)| tracked_slot < slot) {
+                *first_shred = Some((slot, received_time));
+            }
+        }
+    }
+
+    /// Returns when the first shred of `slot` was received, or None if none was or the slot is too old
+    pub fn first_shred_time(&self, slot: Slot) -> Option<SystemTime> {
+        match self.first_shreds.lock().unwrap()[slot as usize % MAX_TRACKED_SLOTS] {
+            Some((tracked_slot, first_shred_time)) if tracked_slot == slot => {
+                Some(first_shred_time)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Latencies of the most recent slots
+#[derive(Default)]
+struct LatencyWindow {
+    latencies_us: VecDeque<i64>,
+}
+
+impl LatencyWindow {
+    fn push(&mut self, latency_us: i64) {
+        if self.latencies_us.len() == ROLLING_WINDOW_SLOTS {
+            self.latencies_us.pop_front();
+        }
+        self.latencies_us.push_back(latency_us);
+    }
+
+    /// Returns the nearest-rank percentiles for each of `percentiles`, or None when empty
+    fn percentiles<const N: usize>(&self, percentiles: [f64; N]) -> Option<[i64; N]> {
+        if self.latencies_us.is_empty() {
+            return None;
+        }
+        let mut sorted = self.latencies_us.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        Some(percentiles.map(|percentile| {
+            let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+            sorted[rank.clamp(1, sorted.len()) - 1]
+        }))
+    }
+}
+
+/// Polls `rpc_url` for its processed slot and reports how long after the slot's first shred the RPC reached it.
+/// Positive latencies mean the proxy saw the slot first. Slots the RPC skips past between polls aren't reported
+pub fn start_slot_latency_thread(
+    rpc_url: String,
+    slot_latency_tap: Arc<SlotLatencyTap>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    // the url may carry an API key, so it's never logged
+    info!("Measuring slot latency against the reference RPC.");
+    supervisor.spawn_restartable("ssPxySlotLatency", move || {
+        let rpc_client = RpcClient::new_with_timeout_and_commitment(
+            rpc_url.clone(),
+            RPC_TIMEOUT,
+            CommitmentConfig::processed(),
+        );
+        let mut latency_window = LatencyWindow::default();
+        let mut last_rpc_slot = None;
+        let mut rpc_failing = false;
+        while !exit.load(Ordering::Relaxed) {
+            match rpc_client.get_slot() {
+                Ok(slot) => {
+                    if rpc_failing {
+                        info!("Reference RPC recovered, resuming slot latency.");
+                        rpc_failing = false;
+                    }
+                    if last_rpc_slot.map_or(true, |last_rpc_slot| slot > last_rpc_slot) {
+                        let rpc_time = SystemTime::now();
+                        last_rpc_slot = Some(slot);
+                        if let Some(first_shred_time) = slot_latency_tap.first_shred_time(slot) {
+                            let latency_us = match rpc_time.duration_since(first_shred_time) {
+                                Ok(latency) => latency.as_micros() as i64,
+                                Err(e) => -(e.duration().as_micros() as i64),
+                            };
+                            latency_window.push(latency_us);
+                            let [p50_us, p99_us] =
+                                latency_window.percentiles([50.0, 99.0]).unwrap_or_default();
+                            datapoint_info!(
+                                "shredstream_proxy-slot_latency",
+                                ("slot", slot, i64),
+                                ("latency_us", latency_us, i64),
+                                ("rolling_p50_us", p50_us, i64),
+                                ("rolling_p99_us", p99_us, i64),
+                            );
+                        }
+                    }
+                }
+                // only logged once per outage since the RPC is polled many times a second
+                Err(e) if !rpc_failing => {
+                    rpc_failing = true;
+                    let e = redact_url(e, &rpc_url, "<slot-latency-rpc-url>");
+                    warn!(
+                        "Failed to get slot from reference RPC, pausing slot latency. Error: {e}"
+                    );
+                    datapoint_warn!(
+                        "shredstream_proxy-slot_latency_rpc_error",
+                        ("errors", 1, i64),
+                        ("error_str", e, String),
+                    );
+                }
+                Err(_) => {}
+            }
+            // wakes immediately on shutdown (avoid using sleep since it will hang under SIGINT)
+            let _ = shutdown_receiver.recv_timeout(RPC_POLL_INTERVAL);
+        }
+    })
+}
+
+/// RPC client errors include the request url, which may carry an API key. The url may be normalized, eg. with a trailing slash
+pub(crate) fn redact_url(e: impl Display, rpc_url: &str, placeholder: &str) -> String {
+    let mut redacted = e.to_string();
+    if let Ok(normalized_url) = reqwest::Url::parse(rpc_url) {
+        redacted = redacted.replace(normalized_url.as_str(), placeholder);
+    }
+    redacted.replace(rpc_url, placeholder)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        shred::tests::new_data_shred,
+        slot_latency::{redact_url, LatencyWindow, SlotLatencyTap, MAX_TRACKED_SLOTS},
+    };
+
+    #[test]
+    fn test_slot_latency_tap() {
+        let tap = SlotLatencyTap::default();
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let later = start + Duration::from_millis(400);
+        let shreds = [(100, 0), (100, 1), (101, 0)]
+            .map(|(slot, index)| new_data_shred(slot, index, 0, false, &[]));
+        let packets = shreds.iter().map(Vec::as_slice).collect::<Vec<_>>();
+
+        tap.record(&packets[..1], start);
+        tap.record(&packets, later);
+        // first shred wins, non shreds are ignored
+        tap.record(&[&[1, 2, 3]], later);
+        assert_eq!(tap.first_shred_time(100), Some(start));
+        assert_eq!(tap.first_shred_time(101), Some(later));
+        assert_eq!(tap.first_shred_time(102), None);
+
+        // newer slots replace older ones, late shreds of old slots don't replace newer ones
+        let newer_slot = 100 + MAX_TRACKED_SLOTS as u64;
+        let newer_shred = new_data_shred(newer_slot, 0, 0, false, &[]);
+        tap.record(&[&newer_shred], later);
+        tap.record(&packets[..1], later);
+        assert_eq!(tap.first_shred_time(100), None);
+        assert_eq!(tap.first_shred_time(newer_slot), Some(later));
+    }
+
+    #[test]
+    fn test_latency_window() {
+        let mut latency_window = LatencyWindow::default();
+        assert_eq!(latency_window.percentiles([50.0, 99.0]), None);
+
+        (1..=100).for_each(|latency_us| latency_window.push(latency_us));
+        assert_eq!(latency_window.percentiles([50.0, 99.0]), Some([50, 99]));
+
+        // only the most recent slots count
+        (0..1_000).for_each(|_| latency_window.push(-5));
+        assert_eq!(latency_window.percentiles([50.0, 99.0]), Some([-5, -5]));
+    }
+
+    #[test]
+    fn test_redact_url() {
+        let rpc_url = "https://rpc.example.com/?api-key=secret";
+        assert_eq!(
+            redact_url(
+                format!("error sending request for url ({rpc_url})"),
+                rpc_url,
+                "<slot-latency-rpc-url>"
+            ),
+            "error sending request for url (<slot-latency-rpc-url>)"
+        );
+        assert_eq!(
+            redact_url(
+                "error sending request for url (https://rpc.example.com/)",
+                "https://rpc.example.com",
+                "<slot-latency-rpc-url>"
+            ),
+            "error sending request for url (<slot-latency-rpc-url>)"
+        );
+    }
+}
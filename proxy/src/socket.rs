@@ -0,0 +1,1049 @@
+use std::{
+    cell::Cell,
+    collections::{HashSet, VecDeque},
+    fmt, fs, io, iter, mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+use log::{info, warn};
+use socket2::{Domain, Protocol, SockAddr, SockRef, Socket, Type};
+use solana_perf::packet::Packet;
+
+use crate::metrics_backend::datapoint_warn;
+
+/// Linux doubles the requested buffer size to account for bookkeeping overhead
+const KERNEL_BUFFER_OVERHEAD_FACTOR: usize = if cfg!(target_os = "linux") { 2 } else { 1 };
+
+#[derive(Clone, Copy, Debug)]
+pub enum SocketBuffer {
+    Recv,
+    Send,
+}
+
+impl SocketBuffer {
+    fn name(&self) -> &'static str {
+        match self {
+            SocketBuffer::Recv => "SO_RCVBUF",
+            SocketBuffer::Send => "SO_SNDBUF",
+        }
+    }
+
+    /// Sysctl the kernel clamps requested sizes to
+    fn max_sysctl(&self) -> &'static str {
+        match self {
+            SocketBuffer::Recv => "net.core.rmem_max",
+            SocketBuffer::Send => "net.core.wmem_max",
+        }
+    }
+}
+
+/// Requests a socket buffer size, returning the size the kernel granted.
+/// Warns if the kernel clamped the request.
+pub fn set_socket_buffer_size(
+    socket: &UdpSocket,
+    buffer: SocketBuffer,
+    requested_bytes: usize,
+) -> io::Result<usize> {
+    let socket = SockRef::from(socket);
+    let granted_bytes = match buffer {
+        SocketBuffer::Recv => {
+            socket.set_recv_buffer_size(requested_bytes)?;
+            socket.recv_buffer_size()?
+        }
+        SocketBuffer::Send => {
+            socket.set_send_buffer_size(requested_bytes)?;
+            socket.send_buffer_size()?
+        }
+    };
+
+    if granted_bytes < requested_bytes.saturating_mul(KERNEL_BUFFER_OVERHEAD_FACTOR) {
+        warn!(
+            "Requested {requested_bytes} byte {} but kernel granted {granted_bytes} bytes. Raise {} to at least {requested_bytes}.",
+            buffer.name(),
+            buffer.max_sysctl()
+        );
+        datapoint_warn!(
+            "shredstream_proxy-socket_buffer_clamped",
+            ("buffer", buffer.name(), String),
+            ("requested_bytes", requested_bytes, i64),
+            ("granted_bytes", granted_bytes, i64),
+        );
+    } else {
+        info!(
+            "Requested {requested_bytes} byte {}, kernel granted {granted_bytes} bytes.",
+            buffer.name()
+        );
+    }
+    Ok(granted_bytes)
+}
+
+/// Largest DSCP value, which is 6 bits
+pub const MAX_DSCP: u8 = 63;
+/// Lower 2 bits of the TOS byte, which the kernel may set itself
+const ECN_MASK: u32 = 0b11;
+
+/// Marks packets sent from `socket` with a DSCP class, eg. 46 for expedited forwarding.
+/// Dual-stack sockets are marked for both IPv4 (`IP_TOS`) and IPv6 (`IPV6_TCLASS`) destinations.
+/// Errors if the platform rejects the value, or accepts it without applying it
+pub fn set_dscp(socket: &UdpSocket, dscp: u8) -> io::Result<()> {
+    if dscp > MAX_DSCP {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("DSCP {dscp} is greater than {MAX_DSCP}"),
+        ));
+    }
+    // DSCP is the upper 6 bits of the TOS byte
+    let tos = u32::from(dscp) << 2;
+    let sock = SockRef::from(socket);
+    let ipv6_socket = socket.local_addr()?.is_ipv6();
+    // IPv4-mapped destinations take their marking from IP_TOS, even on an IPv6 socket
+    let marks_ipv4 = !ipv6_socket || !sock.only_v6()?;
+    if ipv6_socket {
+        set_ipv6_tclass(socket, tos)?;
+    }
+    if marks_ipv4 {
+        sock.set_tos(tos)?;
+    }
+
+    let applied = (!ipv6_socket || ipv6_tclass(socket)? & !ECN_MASK == tos)
+        && (!marks_ipv4 || sock.tos()? & !ECN_MASK == tos);
+    if !applied {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("DSCP {dscp} was accepted but not applied"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_ipv6_tclass(socket: &UdpSocket, tclass: u32) -> io::Result<()> {
+    let tclass = tclass as libc::c_int;
+    // SAFETY: tclass outlives the call and its size is passed along
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &tclass as *const _ as *const libc::c_void,
+            mem::size_of_val(&tclass) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn ipv6_tclass(socket: &UdpSocket) -> io::Result<u32> {
+    let mut tclass: libc::c_int = 0;
+    let mut len = mem::size_of_val(&tclass) as libc::socklen_t;
+    // SAFETY: tclass and len outlive the call, len holds tclass' size
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_TCLASS,
+            &mut tclass as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(tclass as u32)
+}
+
+/// `IPV6_TCLASS` is only set on unix, so [set_dscp] errors for IPv6 sockets elsewhere
+#[cfg(not(unix))]
+fn set_ipv6_tclass(_socket: &UdpSocket, _tclass: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "marking IPv6 packets is only supported on unix",
+    ))
+}
+
+#[cfg(not(unix))]
+fn ipv6_tclass(_socket: &UdpSocket) -> io::Result<u32> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Binds `num` sockets to `addr` with `SO_REUSEPORT`, so the kernel load balances packets between them.
+/// With port `0`, all sockets share the ephemeral port picked for the first one.
+/// An unspecified IPv6 address also receives IPv4, regardless of `net.ipv6.bindv6only`
+#[cfg(target_os = "linux")]
+pub fn bind_reuseport(addr: SocketAddr, num: usize) -> io::Result<Vec<UdpSocket>> {
+    let bind = |addr: SocketAddr| {
+        let socket = new_udp_socket(addr)?;
+        socket.set_reuse_port(true)?;
+        socket.bind(&addr.into())?;
+        Ok(UdpSocket::from(socket))
+    };
+    let first = bind(addr)?;
+    let addr = first.local_addr()?;
+    iter::once(Ok(first))
+        .chain((1..num).map(|_| bind(addr)))
+        .collect()
+}
+
+/// Elsewhere `SO_REUSEPORT` doesn't load balance unicast packets, or doesn't exist, so this falls back to [bind_shared]
+#[cfg(not(target_os = "linux"))]
+pub fn bind_reuseport(addr: SocketAddr, num: usize) -> io::Result<Vec<UdpSocket>> {
+    bind_shared(addr, num)
+}
+
+/// Binds one socket to `addr`, returning `num` handles to it. Threads reading from the handles take turns with its packets,
+/// so unlike [bind_reuseport] on Linux, reads don't scale across threads
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+fn bind_shared(addr: SocketAddr, num: usize) -> io::Result<Vec<UdpSocket>> {
+    let socket = bind_udp(addr)?;
+    (1..num)
+        .map(|_| socket.try_clone())
+        .chain(iter::once(Ok(socket)))
+        .collect()
+}
+
+/// Binds an ephemeral port able to send to IPv4 and IPv6 destinations, falling back to IPv4 only when IPv6 is unavailable.
+/// Pass destinations through [send_addr] before sending from it
+pub fn bind_send_socket() -> io::Result<UdpSocket> {
+    bind_udp(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))).or_else(|e| {
+        warn!("Failed to bind dual-stack socket, only sending to IPv4 destinations. Error: {e}");
+        UdpSocket::bind(SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)))
+    })
+}
+
+/// Maps IPv4 destinations to IPv4-mapped IPv6 addresses when sending from an IPv6 socket
+pub fn send_addr(ipv6_socket: bool, dest: SocketAddr) -> SocketAddr {
+    match dest {
+        SocketAddr::V4(dest) if ipv6_socket => {
+            SocketAddr::from((dest.ip().to_ipv6_mapped(), dest.port()))
+        }
+        dest => dest,
+    }
+}
+
+/// Interface and source address forwarding sockets send from, instead of what the kernel's routing table picks
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Egress {
+    /// Set with `SO_BINDTODEVICE`, which needs `CAP_NET_RAW`
+    pub interface: Option<String>,
+    pub source_ip: Option<IpAddr>,
+}
+
+impl Egress {
+    pub fn is_default(&self) -> bool {
+        self.interface.is_none() && self.source_ip.is_none()
+    }
+
+    /// Fills in whatever this doesn't set from `fallback`
+    pub fn or(&self, fallback: &Egress) -> Egress {
+        Egress {
+            interface: self
+                .interface
+                .clone()
+                .or_else(|| fallback.interface.clone()),
+            source_ip: self.source_ip.or(fallback.source_ip),
+        }
+    }
+}
+
+impl fmt::Display for Egress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.interface, self.source_ip) {
+            (None, None) => write!(f, "default route"),
+            (Some(interface), None) => write!(f, "interface {interface}"),
+            (None, Some(source_ip)) => write!(f, "source {source_ip}"),
+            (Some(interface), Some(source_ip)) => {
+                write!(f, "interface {interface} from source {source_ip}")
+            }
+        }
+    }
+}
+
+/// Like [bind_send_socket], but sends out of `egress`. Binding to a source address only sends to destinations of its family
+pub fn bind_egress_send_socket(egress: &Egress) -> io::Result<UdpSocket> {
+    let socket = match egress.source_ip {
+        Some(source_ip) => bind_udp(SocketAddr::new(source_ip, 0)).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("Failed to bind source address {source_ip}, check it's assigned to this host: {e}"),
+            )
+        })?,
+        None => bind_send_socket()?,
+    };
+    if let Some(interface) = &egress.interface {
+        bind_device(&socket, interface)?;
+    }
+    Ok(socket)
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    SockRef::from(socket)
+        .bind_device(Some(interface.as_bytes()))
+        .map_err(|e| {
+            let hint = match e.raw_os_error() {
+                Some(libc::EPERM) => ", SO_BINDTODEVICE requires CAP_NET_RAW or running as root",
+                Some(libc::ENODEV) => ", no such interface",
+                _ => "",
+            };
+            io::Error::new(
+                e.kind(),
+                format!("Failed to bind to interface {interface}{hint}: {e}"),
+            )
+        })
+}
+
+/// `IP_BOUND_IF`/`IPV6_BOUND_IF`, macOS' equivalent of `SO_BINDTODEVICE`
+#[cfg(target_vendor = "apple")]
+fn bind_device(socket: &UdpSocket, interface: &str) -> io::Result<()> {
+    let name = std::ffi::CString::new(interface)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    // SAFETY: name is a valid C string that outlives the call
+    let index = std::num::NonZeroU32::new(unsafe { libc::if_nametoindex(name.as_ptr()) })
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Failed to bind to interface {interface}, no such interface"),
+            )
+        })?;
+    let socket = SockRef::from(socket);
+    match socket.local_addr()?.is_ipv6() {
+        true => socket.bind_device_by_index_v6(Some(index)),
+        false => socket.bind_device_by_index_v4(Some(index)),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_vendor = "apple")))]
+fn bind_device(_socket: &UdpSocket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "binding to an interface is only supported on linux and macos",
+    ))
+}
+
+/// Payload a destination's path must carry unfragmented, the IPv6 minimum MTU, which leaves headroom over the largest shred
+pub const MIN_PATH_MTU_PAYLOAD: usize = 1280;
+
+/// IP and UDP header bytes added to each packet sent to `dest`
+pub fn udp_header_bytes(dest: &SocketAddr) -> usize {
+    match dest {
+        SocketAddr::V4(_) => 20 + 8,
+        SocketAddr::V6(_) => 40 + 8,
+    }
+}
+
+/// Path MTU to `dest` as the kernel knows it, read with `IP_MTU` from a socket connected to it.
+/// Before any traffic to `dest` this is the MTU of the route to it, so smaller hops further along are only seen once discovered
+#[cfg(target_os = "linux")]
+pub fn path_mtu(dest: SocketAddr) -> io::Result<usize> {
+    let (bind_addr, level, name) = match dest {
+        SocketAddr::V4(_) => (
+            SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            libc::IPPROTO_IP,
+            libc::IP_MTU,
+        ),
+        SocketAddr::V6(_) => (
+            SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU,
+        ),
+    };
+    let socket = UdpSocket::bind(bind_addr)?;
+    socket.connect(dest)?;
+    let mut mtu: libc::c_int = 0;
+    let mut len = mem::size_of_val(&mtu) as libc::socklen_t;
+    // SAFETY: mtu and len outlive the call, len holds mtu's size
+    let ret = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &mut mtu as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(mtu as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn path_mtu(_dest: SocketAddr) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading the path MTU is only supported on linux",
+    ))
+}
+
+/// Warns about each destination whose path MTU can't carry [MIN_PATH_MTU_PAYLOAD] plus headers, where shreds
+/// may be fragmented or fail with `EMSGSIZE`. Best effort, destinations whose MTU can't be read are skipped.
+/// Returns the destinations warned about, with their path MTU
+pub fn check_path_mtus(dests: &[SocketAddr]) -> Vec<(SocketAddr, usize)> {
+    dests
+        .iter()
+        .filter_map(|dest| {
+            let mtu = path_mtu(*dest).ok()?;
+            let required = MIN_PATH_MTU_PAYLOAD + udp_header_bytes(dest);
+            if mtu >= required {
+                return None;
+            }
+            warn!("PATH MTU TOO SMALL: the path MTU to {dest} is {mtu} bytes, under the {required} bytes a full size shred needs. Shreds sent to it may be truncated, fragmented, or fail with EMSGSIZE. Check the MTU of any tunnel on the way.");
+            datapoint_warn!(
+                "shredstream_proxy-path_mtu_too_small",
+                "addr" => dest.to_string(),
+                ("path_mtu", mtu, i64),
+                ("required", required, i64),
+            );
+            Some((*dest, mtu))
+        })
+        .collect()
+}
+
+/// Window over which [RebindPolicy] counts rebinds
+const REBIND_WINDOW: Duration = Duration::from_secs(60);
+
+/// Returns true for errors that mean the socket itself is broken, such as its interface or address going away,
+/// rather than a single destination being unreachable
+#[cfg(unix)]
+pub fn is_persistent_socket_error(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(
+            libc::EBADF
+                | libc::ENOTSOCK
+                | libc::EINVAL
+                | libc::ENETDOWN
+                | libc::ENODEV
+                | libc::EADDRNOTAVAIL
+        )
+    )
+}
+
+/// Returns true for errors that mean the socket itself is broken, such as its interface or address going away,
+/// rather than a single destination being unreachable. Winsock errors aren't errno values, so they're matched by
+/// their WSAE* codes: WSAEBADF, WSAEINVAL, WSAENOTSOCK, WSAEADDRNOTAVAIL and WSAENETDOWN
+#[cfg(windows)]
+pub fn is_persistent_socket_error(err: &io::Error) -> bool {
+    matches!(
+        err.raw_os_error(),
+        Some(10009 | 10022 | 10038 | 10049 | 10050)
+    )
+}
+
+/// Returns true if a send failed because the datagram was larger than the socket or path allows
+#[cfg(unix)]
+pub fn is_message_too_large(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+/// Returns true if a send failed because the datagram was larger than the socket or path allows, WSAEMSGSIZE
+#[cfg(windows)]
+pub fn is_message_too_large(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(10040)
+}
+
+/// Decides when sockets are rebound after persistent errors. Shared by every forwarding and listen socket,
+/// so a rebind storm across sockets shuts down the proxy instead of rebinding forever
+#[derive(Debug)]
+pub struct RebindPolicy {
+    /// Consecutive persistent errors on a socket before rebinding it, 0 never rebinds
+    error_threshold: usize,
+    max_rebinds_per_minute: usize,
+    rebinds: Mutex<VecDeque<Instant>>,
+}
+
+impl RebindPolicy {
+    pub fn new(error_threshold: usize, max_rebinds_per_minute: usize) -> Self {
+        Self {
+            error_threshold,
+            max_rebinds_per_minute,
+            rebinds: Mutex::default(),
+        }
+    }
+
+    pub fn max_rebinds_per_minute(&self) -> usize {
+        self.max_rebinds_per_minute
+    }
+
+    /// Tracks errors for a single socket
+    pub fn error_tracker(&self) -> SocketErrorTracker {
+        SocketErrorTracker {
+            error_threshold: self.error_threshold,
+            consecutive_errors: Cell::new(0),
+        }
+    }
+
+    /// Records a rebind at `now`, forgetting rebinds older than a minute.
+    /// Returns false once more than `max_rebinds_per_minute` rebinds happened within the last minute
+    pub fn allow_rebind(&self, now: Instant) -> bool {
+        let mut rebinds = self.rebinds.lock().unwrap();
+        while rebinds
+            .front()
+            .is_some_and(|rebind| now.duration_since(*rebind) >= REBIND_WINDOW)
+        {
+            rebinds.pop_front();
+        }
+        if rebinds.len() >= self.max_rebinds_per_minute {
+            return false;
+        }
+        rebinds.push_back(now);
+        true
+    }
+}
+
+/// Counts consecutive persistent errors on a socket owned by a single thread
+#[derive(Debug)]
+pub struct SocketErrorTracker {
+    error_threshold: usize,
+    consecutive_errors: Cell<usize>,
+}
+
+impl SocketErrorTracker {
+    pub fn record_success(&self) {
+        self.consecutive_errors.set(0);
+    }
+
+    /// Returns true once the socket should be rebound, starting the count over.
+    /// Errors that don't indicate a broken socket, such as read timeouts, are ignored
+    pub fn record_error(&self, err: &io::Error) -> bool {
+        if self.error_threshold == 0 || !is_persistent_socket_error(err) {
+            return false;
+        }
+        let consecutive_errors = self.consecutive_errors.get() + 1;
+        if consecutive_errors < self.error_threshold {
+            self.consecutive_errors.set(consecutive_errors);
+            return false;
+        }
+        self.consecutive_errors.set(0);
+        true
+    }
+}
+
+/// Unbound UDP socket for `addr`, dual-stack if it's the unspecified IPv6 address
+fn new_udp_socket(addr: SocketAddr) -> io::Result<Socket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        socket.set_only_v6(false)?;
+    }
+    Ok(socket)
+}
+
+fn bind_udp(addr: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = new_udp_socket(addr)?;
+    socket.bind(&addr.into())?;
+    Ok(socket.into())
+}
+
+/// Max packets read per `recvmmsg` call, the kernel's `UIO_MAXIOV`
+pub const MAX_RECV_MMSG_BATCH_SIZE: usize = 1_024;
+/// `CMSG_SPACE` of the three timespecs in an `SCM_TIMESTAMPING` control message, as u64s for alignment
+#[cfg(target_os = "linux")]
+const CMSG_BUFFER_WORDS: usize = 8;
+
+/// Asks the kernel to timestamp packets as they're received, read with [RecvMmsg].
+/// Hardware timestamps also need timestamping enabled on the NIC, eg. with `hwstamp_ctl`
+#[cfg(target_os = "linux")]
+pub fn enable_rx_timestamps(socket: &UdpSocket, hardware: bool) -> io::Result<()> {
+    let mut flags = libc::SOF_TIMESTAMPING_RX_SOFTWARE | libc::SOF_TIMESTAMPING_SOFTWARE;
+    if hardware {
+        flags |= libc::SOF_TIMESTAMPING_RX_HARDWARE | libc::SOF_TIMESTAMPING_RAW_HARDWARE;
+    }
+    // SAFETY: flags outlives the call and its size is passed along
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_TIMESTAMPING,
+            &flags as *const _ as *const libc::c_void,
+            mem::size_of_val(&flags) as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reads batches of packets with one `recvmmsg` call each, reusing the message headers it allocates once
+#[cfg(target_os = "linux")]
+pub struct RecvMmsg {
+    iovs: Vec<libc::iovec>,
+    addrs: Vec<libc::sockaddr_storage>,
+    hdrs: Vec<libc::mmsghdr>,
+    cmsgs: Vec<[u64; CMSG_BUFFER_WORDS]>,
+}
+
+#[cfg(target_os = "linux")]
+impl RecvMmsg {
+    /// Reads up to `batch_size` packets per call, capped at [MAX_RECV_MMSG_BATCH_SIZE]
+    pub fn new(batch_size: usize) -> Self {
+        let batch_size = batch_size.clamp(1, MAX_RECV_MMSG_BATCH_SIZE);
+        // SAFETY: all zeroes is valid for these plain C structs
+        let (iov, addr, hdr) = unsafe { (mem::zeroed(), mem::zeroed(), mem::zeroed()) };
+        Self {
+            iovs: vec![iov; batch_size],
+            addrs: vec![addr; batch_size],
+            hdrs: vec![hdr; batch_size],
+            cmsgs: vec![[0; CMSG_BUFFER_WORDS]; batch_size],
+        }
+    }
+
+    /// Reads whatever packets are ready, up to the batch size, blocking until one arrives or the socket's read timeout elapses.
+    /// Sets each packet's size and source. With `timestamps`, also reads each packet's kernel receive timestamp, preferring the hardware one.
+    /// Timestamps are `None` if the kernel didn't attach one, such as before [enable_rx_timestamps]
+    pub fn recv(
+        &mut self,
+        socket: &UdpSocket,
+        packets: &mut [Packet],
+        mut timestamps: Option<&mut [Option<SystemTime>]>,
+    ) -> io::Result<usize> {
+        let count = packets.len().min(self.hdrs.len()).min(
+            timestamps
+                .as_ref()
+                .map_or(usize::MAX, |timestamps| timestamps.len()),
+        );
+        for (i, packet) in packets.iter_mut().take(count).enumerate() {
+            let buffer = packet.buffer_mut();
+            self.iovs[i] = libc::iovec {
+                iov_base: buffer.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buffer.len(),
+            };
+            let msg_hdr = &mut self.hdrs[i].msg_hdr;
+            msg_hdr.msg_name = &mut self.addrs[i] as *mut _ as *mut libc::c_void;
+            msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+            msg_hdr.msg_iov = &mut self.iovs[i];
+            msg_hdr.msg_iovlen = 1;
+            // control messages are only read when asked for, the kernel skips them otherwise
+            (msg_hdr.msg_control, msg_hdr.msg_controllen) = match timestamps {
+                Some(_) => (
+                    self.cmsgs[i].as_mut_ptr() as *mut libc::c_void,
+                    mem::size_of_val(&self.cmsgs[i]) as _,
+                ),
+                None => (std::ptr::null_mut(), 0),
+            };
+        }
+
+        // SAFETY: each header points at buffers that outlive the call, sized as declared
+        #[allow(clippy::useless_conversion)]
+        let num_received = unsafe {
+            libc::recvmmsg(
+                socket.as_raw_fd(),
+                self.hdrs.as_mut_ptr(),
+                count as u32,
+                libc::MSG_WAITFORONE.try_into().unwrap(),
+                std::ptr::null_mut(),
+            )
+        };
+        if num_received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let num_received = num_received as usize;
+        for (i, packet) in packets.iter_mut().take(num_received).enumerate() {
+            let hdr = &self.hdrs[i];
+            packet.meta_mut().size = hdr.msg_len as usize;
+            // SAFETY: the kernel wrote a socket address of msg_namelen bytes
+            let src = unsafe { SockAddr::new(self.addrs[i], hdr.msg_hdr.msg_namelen) };
+            if let Some(src) = src.as_socket() {
+                packet.meta_mut().set_socket_addr(&src);
+            }
+            if let Some(timestamps) = timestamps.as_deref_mut() {
+                timestamps[i] = rx_timestamp(&hdr.msg_hdr);
+            }
+        }
+        Ok(num_received)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_rx_timestamps(_socket: &UdpSocket, _hardware: bool) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// `recvmmsg` is Linux only, other platforms read with `solana_streamer`
+#[cfg(not(target_os = "linux"))]
+pub struct RecvMmsg;
+
+#[cfg(not(target_os = "linux"))]
+impl RecvMmsg {
+    pub fn new(_batch_size: usize) -> Self {
+        Self
+    }
+
+    pub fn recv(
+        &mut self,
+        _socket: &UdpSocket,
+        _packets: &mut [Packet],
+        _timestamps: Option<&mut [Option<SystemTime>]>,
+    ) -> io::Result<usize> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+/// Reads the `SCM_TIMESTAMPING` control message: software, deprecated, then raw hardware timestamps
+#[cfg(target_os = "linux")]
+fn rx_timestamp(msg_hdr: &libc::msghdr) -> Option<SystemTime> {
+    // SAFETY: msg_control and msg_controllen were set by the kernel for this message
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg_hdr) };
+    while !cmsg.is_null() {
+        // SAFETY: non-null headers returned by CMSG_FIRSTHDR/CMSG_NXTHDR are within the buffer
+        let header = unsafe { &*cmsg };
+        if header.cmsg_level == libc::SOL_SOCKET && header.cmsg_type == libc::SCM_TIMESTAMPING {
+            // SAFETY: SCM_TIMESTAMPING data is three timespecs, possibly unaligned
+            let timespecs = unsafe {
+                std::ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const [libc::timespec; 3])
+            };
+            return [timespecs[2], timespecs[0]]
+                .into_iter()
+                .find(|timespec| timespec.tv_sec != 0 || timespec.tv_nsec != 0)
+                .map(|timespec| {
+                    UNIX_EPOCH + Duration::new(timespec.tv_sec as u64, timespec.tv_nsec as u32)
+                });
+        }
+        // SAFETY: same as CMSG_FIRSTHDR
+        cmsg = unsafe { libc::CMSG_NXTHDR(msg_hdr, cmsg) };
+    }
+    None
+}
+
+/// Reads kernel receive drops for a set of UDP sockets from `/proc/net/udp` and `/proc/net/udp6`.
+/// Drops here happen before the proxy sees the packet, usually from a full receive buffer.
+pub struct SocketDropCounter {
+    inodes: HashSet<u64>,
+    last_drops: u64,
+}
+
+impl SocketDropCounter {
+    /// Returns `None` if socket inodes can't be resolved, such as outside Linux
+    pub fn new<'a>(sockets: impl IntoIterator<Item = &'a UdpSocket>) -> Option<Self> {
+        let inodes = sockets
+            .into_iter()
+            .map(|socket| socket_inode(socket).ok())
+            .collect::<Option<HashSet<_>>>()?;
+        Some(Self {
+            inodes,
+            last_drops: 0,
+        })
+    }
+
+    /// Returns drops since the last call
+    pub fn drops_since_last(&mut self) -> io::Result<u64> {
+        let drops = ["/proc/net/udp", "/proc/net/udp6"]
+            .into_iter()
+            .map(|path| match fs::read_to_string(path) {
+                Ok(contents) => Ok(parse_udp_drops(&contents, &self.inodes)),
+                // udp6 is missing when ipv6 is disabled
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+                Err(e) => Err(e),
+            })
+            .sum::<io::Result<u64>>()?;
+        // closed sockets disappear from the table, so the total can go down
+        let new_drops = drops.saturating_sub(self.last_drops);
+        self.last_drops = drops;
+        Ok(new_drops)
+    }
+
+    /// Counts `other`'s sockets too, such as those of another pipeline
+    pub fn merge(&mut self, other: SocketDropCounter) {
+        self.inodes.extend(other.inodes);
+        self.last_drops += other.last_drops;
+    }
+}
+
+/// Inode from the socket's `/proc/self/fd` link, formatted as `socket:[inode]`
+#[cfg(target_os = "linux")]
+fn socket_inode(socket: &UdpSocket) -> io::Result<u64> {
+    let link = fs::read_link(format!("/proc/self/fd/{}", socket.as_raw_fd()))?;
+    link.to_str()
+        .and_then(|link| link.strip_prefix("socket:["))
+        .and_then(|link| link.strip_suffix(']'))
+        .and_then(|inode| inode.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unexpected socket link {link:?}"),
+            )
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn socket_inode(_socket: &UdpSocket) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Sums the drops column for rows matching `inodes`. Columns are
+/// `sl local_address rem_address st tx_queue:rx_queue tr:tm->when retrnsmt uid timeout inode ref pointer drops`
+fn parse_udp_drops(contents: &str, inodes: &HashSet<u64>) -> u64 {
+    contents
+        .lines()
+        .skip(1) // header
+        .filter_map(|line| {
+            let columns = line.split_whitespace().collect::<Vec<_>>();
+            let inode = columns.get(9)?.parse::<u64>().ok()?;
+            let drops = columns.last()?.parse::<u64>().ok()?;
+            inodes.contains(&inode).then_some(drops)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        io,
+        net::{SocketAddr, UdpSocket},
+        str::FromStr,
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use solana_perf::packet::Packet;
+
+    use crate::socket::{
+        bind_reuseport, bind_send_socket, bind_shared, check_path_mtus, ipv6_tclass,
+        parse_udp_drops, send_addr, set_dscp, set_socket_buffer_size, RebindPolicy, SocketBuffer,
+        SocketDropCounter,
+    };
+    #[cfg(target_os = "linux")]
+    use crate::socket::{enable_rx_timestamps, path_mtu, RecvMmsg, MIN_PATH_MTU_PAYLOAD};
+
+    #[test]
+    fn test_parse_udp_drops() {
+        let contents = "   sl  local_address rem_address   st tx_queue rx_queue tr tm->when retrnsmt   uid  timeout inode ref pointer drops\n\
+            \x20 1: 00000000:4E20 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 1001 2 0000000000000000 5\n\
+            \x20 2: 00000000:4E20 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 1002 2 0000000000000000 7\n\
+            \x20 3: 00000000:0035 00000000:0000 07 00000000:00000000 00:00000000 00000000     0        0 2001 2 0000000000000000 100\n";
+
+        assert_eq!(parse_udp_drops(contents, &HashSet::from([1001, 1002])), 12);
+        assert_eq!(parse_udp_drops(contents, &HashSet::from([3001])), 0);
+    }
+
+    #[test]
+    fn test_path_mtu() {
+        // loopback's MTU is far larger than a shred
+        let dest = SocketAddr::from_str("127.0.0.1:9").unwrap();
+        #[cfg(target_os = "linux")]
+        assert!(path_mtu(dest).unwrap() > MIN_PATH_MTU_PAYLOAD);
+        assert!(check_path_mtus(&[dest]).is_empty());
+    }
+
+    #[test]
+    fn test_socket_buffers_and_drops() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        // small enough not to be clamped
+        assert!(set_socket_buffer_size(&socket, SocketBuffer::Recv, 4096).unwrap() >= 4096);
+        assert!(set_socket_buffer_size(&socket, SocketBuffer::Send, 4096).unwrap() >= 4096);
+
+        if cfg!(target_os = "linux") {
+            let mut counter = SocketDropCounter::new([&socket]).unwrap();
+            assert_eq!(counter.drops_since_last().unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_set_dscp() {
+        // expedited forwarding
+        let ef_tos = 46 << 2;
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        set_dscp(&socket, 46).unwrap();
+        assert_eq!(socket2::SockRef::from(&socket).tos().unwrap(), ef_tos);
+
+        let send_socket = bind_send_socket().unwrap();
+        let ipv6_socket = send_socket.local_addr().unwrap().is_ipv6();
+        // IPv6 packets are only marked on unix
+        if cfg!(unix) || !ipv6_socket {
+            set_dscp(&send_socket, 46).unwrap();
+            assert_eq!(socket2::SockRef::from(&send_socket).tos().unwrap(), ef_tos);
+        }
+        if cfg!(unix) && ipv6_socket {
+            assert_eq!(ipv6_tclass(&send_socket).unwrap(), ef_tos);
+        }
+
+        assert!(set_dscp(&socket, 64).is_err());
+    }
+
+    #[test]
+    fn test_dual_stack_listen() {
+        let listen_sockets = bind_reuseport(SocketAddr::from_str("[::]:0").unwrap(), 2).unwrap();
+        let port = listen_sockets[0].local_addr().unwrap().port();
+        assert_eq!(listen_sockets[1].local_addr().unwrap().port(), port);
+        listen_sockets.iter().for_each(|socket| {
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap()
+        });
+
+        let send_socket = bind_send_socket().unwrap();
+        let ipv6_socket = send_socket.local_addr().unwrap().is_ipv6();
+        for dest in [
+            SocketAddr::from(([127, 0, 0, 1], port)),
+            SocketAddr::from_str(&format!("[::1]:{port}")).unwrap(),
+        ] {
+            send_socket
+                .send_to(b"shred", send_addr(ipv6_socket, dest))
+                .unwrap();
+            // the kernel hashes each source to one of the sockets
+            let mut buf = [0; 8];
+            let received = listen_sockets
+                .iter()
+                .find_map(|socket| socket.recv(&mut buf).ok());
+            assert_eq!(received, Some(5), "{dest}");
+        }
+    }
+
+    #[test]
+    fn test_bind_shared() {
+        let sockets = bind_shared(SocketAddr::from_str("127.0.0.1:0").unwrap(), 3).unwrap();
+        assert_eq!(sockets.len(), 3);
+        let addr = sockets[0].local_addr().unwrap();
+        assert!(sockets
+            .iter()
+            .all(|socket| socket.local_addr().unwrap() == addr));
+
+        // every handle reads from the same socket
+        let send_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for socket in &sockets {
+            send_socket.send_to(b"shred", addr).unwrap();
+            socket
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let mut buf = [0; 8];
+            assert_eq!(socket.recv(&mut buf).unwrap(), 5);
+        }
+    }
+
+    #[test]
+    fn test_send_addr() {
+        let v4 = SocketAddr::from_str("10.0.0.1:8001").unwrap();
+        let v6 = SocketAddr::from_str("[2001:db8::1]:8001").unwrap();
+        assert_eq!(send_addr(false, v4), v4);
+        assert_eq!(send_addr(true, v6), v6);
+        assert_eq!(
+            send_addr(true, v4),
+            SocketAddr::from_str("[::ffff:10.0.0.1]:8001").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_rebind_policy() {
+        let policy = RebindPolicy::new(3, 2);
+        let errors = policy.error_tracker();
+        #[cfg(unix)]
+        let persistent = io::Error::from_raw_os_error(libc::ENETDOWN);
+        // WSAENETDOWN
+        #[cfg(windows)]
+        let persistent = io::Error::from_raw_os_error(10050);
+        let timeout = io::Error::from(io::ErrorKind::WouldBlock);
+
+        // only consecutive persistent errors count
+        assert!(!errors.record_error(&persistent));
+        assert!(!errors.record_error(&timeout));
+        assert!(!errors.record_error(&persistent));
+        errors.record_success();
+        assert!(!errors.record_error(&persistent));
+        assert!(!errors.record_error(&persistent));
+        assert!(errors.record_error(&persistent));
+        // count starts over after a rebind
+        assert!(!errors.record_error(&persistent));
+
+        let now = Instant::now();
+        assert!(policy.allow_rebind(now));
+        assert!(policy.allow_rebind(now + Duration::from_secs(30)));
+        assert!(!policy.allow_rebind(now + Duration::from_secs(59)));
+        // the first rebind falls out of the window
+        assert!(policy.allow_rebind(now + Duration::from_secs(60)));
+
+        // 0 never rebinds
+        let errors = RebindPolicy::new(0, 2).error_tracker();
+        assert!((0..100).all(|_| !errors.record_error(&persistent)));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_recv_mmsg() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for payload in [&b"shred0"[..], b"shred1", b"shred2"] {
+            sender
+                .send_to(payload, socket.local_addr().unwrap())
+                .unwrap();
+        }
+
+        // reads up to the batch size per call, however many packets are passed in
+        let mut recv_mmsg = RecvMmsg::new(2);
+        let mut packets = vec![Packet::default(); 4];
+        assert_eq!(recv_mmsg.recv(&socket, &mut packets, None).unwrap(), 2);
+        assert_eq!(packets[0].data(..), Some(&b"shred0"[..]));
+        assert_eq!(packets[1].data(..), Some(&b"shred1"[..]));
+        assert_eq!(
+            packets[1].meta().socket_addr(),
+            sender.local_addr().unwrap()
+        );
+        assert_eq!(packets[2].meta().size, 0);
+        assert_eq!(recv_mmsg.recv(&socket, &mut packets, None).unwrap(), 1);
+        assert_eq!(packets[0].data(..), Some(&b"shred2"[..]));
+
+        // times out once nothing is left
+        socket
+            .set_read_timeout(Some(Duration::from_millis(10)))
+            .unwrap();
+        assert_eq!(
+            recv_mmsg
+                .recv(&socket, &mut packets, None)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_recv_mmsg_with_timestamps() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        enable_rx_timestamps(&socket, false).unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let mut recv_mmsg = RecvMmsg::new(2);
+        let mut packets = [Packet::default(), Packet::default()];
+        let mut timestamps = [None; 2];
+        // the kernel turns on timestamping asynchronously, so the first packets may not be stamped
+        for _ in 0..100 {
+            let before_send = SystemTime::now();
+            sender
+                .send_to(b"shred0", socket.local_addr().unwrap())
+                .unwrap();
+            assert_eq!(
+                recv_mmsg
+                    .recv(&socket, &mut packets, Some(&mut timestamps))
+                    .unwrap(),
+                1
+            );
+            assert_eq!(packets[0].data(..), Some(&b"shred0"[..]));
+            assert_eq!(
+                packets[0].meta().socket_addr(),
+                sender.local_addr().unwrap()
+            );
+            assert_eq!(timestamps[1], None);
+            if let Some(timestamp) = timestamps[0] {
+                // allow for coarse clock granularity
+                assert!(timestamp + Duration::from_millis(10) >= before_send);
+                assert!(timestamp <= SystemTime::now());
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        panic!("no kernel receive timestamp");
+    }
+}
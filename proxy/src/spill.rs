@@ -0,0 +1,845 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use dashmap::DashMap;
+use log::{error, info, warn};
+
+use crate::{
+    forwarder::{SendSocketOptions, ShredMetrics, ShredSink, UdpSink},
+    memory_guard::{BufferKind, HeldBytes},
+    rate_limit::RateLimiter,
+};
+
+/// Default bytes spilled to disk per destination before its oldest segment is overwritten
+pub const DEFAULT_SPILL_MAX_BYTES: u64 = 1 << 30;
+/// Segment files each destination's spill is split into, the oldest is deleted once they'd exceed `spill-max-bytes`
+const SPILL_SEGMENTS: u64 = 8;
+const SEGMENT_EXTENSION: &str = "spill";
+/// Spill time in unix microseconds, then the packet length
+const RECORD_HEADER_LEN: usize = 8 + 2;
+/// How often the replay thread sends, paced by `spill-replay-rate-pps`
+const REPLAY_INTERVAL: Duration = Duration::from_millis(10);
+/// Most packets replayed to a destination per interval
+const MAX_REPLAY_BATCH: usize = 1_024;
+/// Batches queued for the spill writer thread, beyond which spilled packets are dropped
+const SPILL_CHANNEL_CAPACITY: usize = 8_192;
+/// How often an idle spill writer thread checks for exit
+const WRITER_EXIT_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub struct SpillConfig {
+    /// Each destination spills to its own subdirectory
+    pub dir: PathBuf,
+    /// Bytes on disk per destination, the oldest packets are overwritten beyond it
+    pub max_bytes: u64,
+    pub replay_rate_pps: u64,
+    /// Spilled packets older than this are skipped when replaying
+    pub max_age: Duration,
+    /// Live packets queued per destination behind its replay, the rest are dropped
+    pub live_queue_packets: usize,
+}
+
+struct Segment {
+    path: PathBuf,
+    bytes: u64,
+    packets: u64,
+}
+
+/// Size capped ring of segment files, appended to at the back and read from the front
+struct SegmentRing {
+    dir: PathBuf,
+    segment_bytes: u64,
+    next_segment: u64,
+    segments: VecDeque<Segment>,
+    /// Appends to the back segment
+    writer: Option<BufWriter<File>>,
+    /// Reads the front segment
+    reader: Option<BufReader<File>>,
+    /// Packets already read from the front segment
+    read_packets: u64,
+}
+
+impl SegmentRing {
+    fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self {
+            dir,
+            segment_bytes: (max_bytes / SPILL_SEGMENTS).max(1),
+            next_segment: 0,
+            segments: VecDeque::new(),
+            writer: None,
+            reader: None,
+            read_packets: 0,
+        }
+    }
+
+    fn unread(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.packets)
+            .sum::<u64>()
+            - self.read_packets
+    }
+
+    /// Appends `packet`, returning how many unread packets were overwritten to make room
+    fn append(&mut self, spilled_at_micros: u64, packet: &[u8]) -> io::Result<u64> {
+        let record_len = (RECORD_HEADER_LEN + packet.len()) as u64;
+        let mut overwritten = 0;
+        let rotate = match self.segments.back() {
+            Some(segment) => segment.bytes > 0 && segment.bytes + record_len > self.segment_bytes,
+            None => true,
+        };
+        if rotate {
+            if let Some(mut writer) = self.writer.take() {
+                writer.flush()?;
+            }
+            if self.segments.is_empty() {
+                // leftovers of an earlier run aren't replayed, the ring only tracks what it wrote
+                remove_segments(&self.dir)?;
+                fs::create_dir_all(&self.dir)?;
+            }
+            let path = self
+                .dir
+                .join(format!("{:08}.{SEGMENT_EXTENSION}", self.next_segment));
+            self.next_segment += 1;
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(&path)?;
+            self.writer = Some(BufWriter::new(file));
+            self.segments.push_back(Segment {
+                path,
+                bytes: 0,
+                packets: 0,
+            });
+            if self.segments.len() as u64 > SPILL_SEGMENTS {
+                overwritten = self.pop_front()?;
+            }
+        }
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_all(&spilled_at_micros.to_le_bytes())?;
+        writer.write_all(&(packet.len() as u16).to_le_bytes())?;
+        writer.write_all(packet)?;
+        let segment = self.segments.back_mut().unwrap();
+        segment.bytes += record_len;
+        segment.packets += 1;
+        Ok(overwritten)
+    }
+
+    /// Reads the oldest unread packet with when it was spilled, deleting segments once they're read
+    fn read(&mut self) -> io::Result<Option<(u64, Vec<u8>)>> {
+        loop {
+            let Some(front) = self.segments.front() else {
+                return Ok(None);
+            };
+            if self.read_packets == front.packets {
+                if self.segments.len() == 1 {
+                    self.clear()?;
+                    return Ok(None);
+                }
+                self.pop_front()?;
+                continue;
+            }
+            if self.segments.len() == 1 {
+                if let Some(writer) = self.writer.as_mut() {
+                    writer.flush()?;
+                }
+            }
+            let reader = match &mut self.reader {
+                Some(reader) => reader,
+                None => self.reader.insert(BufReader::new(File::open(&front.path)?)),
+            };
+            let mut header = [0u8; RECORD_HEADER_LEN];
+            reader.read_exact(&mut header)?;
+            let spilled_at_micros = u64::from_le_bytes(header[..8].try_into().unwrap());
+            let mut packet =
+                vec![0u8; u16::from_le_bytes(header[8..].try_into().unwrap()) as usize];
+            reader.read_exact(&mut packet)?;
+            self.read_packets += 1;
+            return Ok(Some((spilled_at_micros, packet)));
+        }
+    }
+
+    /// Deletes the front segment, returning how many of its packets were unread
+    fn pop_front(&mut self) -> io::Result<u64> {
+        let Some(segment) = self.segments.pop_front() else {
+            return Ok(0);
+        };
+        let unread = segment.packets - self.read_packets;
+        self.reader = None;
+        self.read_packets = 0;
+        if self.segments.is_empty() {
+            self.writer = None;
+        }
+        fs::remove_file(&segment.path)?;
+        Ok(unread)
+    }
+
+    /// Deletes every segment, returning how many packets were unread
+    fn clear(&mut self) -> io::Result<u64> {
+        let unread = self.unread();
+        self.writer = None;
+        self.reader = None;
+        self.read_packets = 0;
+        for segment in self.segments.drain(..) {
+            fs::remove_file(&segment.path)?;
+        }
+        Ok(unread)
+    }
+}
+
+/// Removes segment files left in `dir`, if it exists
+fn remove_segments(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+/// A destination's spilled packets, and the live packets queued behind their replay.
+/// Forwarder threads only lock `queue`, so they never wait on the disk
+struct SpillDest {
+    /// Appended to by the spill writer thread and read by the replay thread
+    ring: Mutex<SegmentRing>,
+    /// Unread packets in `ring`, updated whenever it's changed
+    ring_unread: AtomicU64,
+    queue: Mutex<SpillQueue>,
+    /// Set once a disk error is logged, so a full disk is reported once per outage rather than per batch
+    error_logged: AtomicBool,
+}
+
+struct SpillQueue {
+    /// (when queued in unix microseconds, packet), counted as [BufferKind::Spill]
+    live_queue: VecDeque<(u64, Vec<u8>)>,
+    /// Packets queued for the spill writer and not yet in the ring, which live packets must wait behind
+    pending_writes: usize,
+    /// Set once the destination is no longer spilled to, so writes still queued for it are dropped
+    discarded: bool,
+}
+
+impl SpillQueue {
+    fn is_empty(&self, spill_dest: &SpillDest) -> bool {
+        self.live_queue.is_empty()
+            && self.pending_writes == 0
+            && spill_dest.ring_unread.load(Ordering::Relaxed) == 0
+    }
+}
+
+impl SpillDest {
+    fn is_empty(&self) -> bool {
+        self.queue.lock().unwrap().is_empty(self)
+    }
+
+    fn spill(
+        &self,
+        ring: &mut SegmentRing,
+        dest: SocketAddr,
+        spilled_at_micros: u64,
+        packet: &[u8],
+        metrics: &ShredMetrics,
+    ) {
+        match ring.append(spilled_at_micros, packet) {
+            Ok(overwritten) => {
+                metrics.spill_spilled.fetch_add(1, Ordering::Relaxed);
+                metrics
+                    .spill_dropped
+                    .fetch_add(overwritten, Ordering::Relaxed);
+            }
+            Err(e) => {
+                metrics.spill_dropped.fetch_add(1, Ordering::Relaxed);
+                if !self.error_logged.swap(true, Ordering::Relaxed) {
+                    error!(
+                        "Failed to spill shreds for {dest} to {:?}, dropping them. Error: {e}",
+                        ring.dir
+                    );
+                }
+            }
+        }
+        self.ring_unread.store(ring.unread(), Ordering::Relaxed);
+    }
+}
+
+/// Packets for the spill writer thread to append to a destination's ring
+struct SpillWrite {
+    dest: SocketAddr,
+    spill_dest: Arc<SpillDest>,
+    /// (when spilled in unix microseconds, packet)
+    packets: Vec<(u64, Vec<u8>)>,
+    _held: HeldBytes,
+}
+
+/// Spills shreds for destinations with a `spill` option to disk while they're unhealthy or paused,
+/// and replays them once they're back, ahead of live packets.
+/// Forwarder threads queue packets for the spill writer thread, dropping them when it can't keep up, so a slow disk never stalls forwarding
+pub struct SpillStore {
+    config: SpillConfig,
+    /// Shared with [crate::forwarder::DestinationSources], destinations with a `spill` option, true while they're spilled to
+    dest_spill: Arc<ArcSwap<HashMap<SocketAddr, bool>>>,
+    /// Destinations spilled to or replaying, removed once their replay drains
+    dests: DashMap<SocketAddr, Arc<SpillDest>>,
+    write_sender: Sender<SpillWrite>,
+    write_receiver: Receiver<SpillWrite>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl SpillStore {
+    pub fn new(
+        config: SpillConfig,
+        dest_spill: Arc<ArcSwap<HashMap<SocketAddr, bool>>>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
+        let (write_sender, write_receiver) = crossbeam_channel::bounded(SPILL_CHANNEL_CAPACITY);
+        Self {
+            config,
+            dest_spill,
+            dests: DashMap::new(),
+            write_sender,
+            write_receiver,
+            metrics,
+        }
+    }
+
+    /// Spills `packets` for `dest` while `spilling`, otherwise queues them behind its replay if one is in progress.
+    /// Returns false if they should be sent as usual
+    pub fn divert(&self, dest: SocketAddr, spilling: bool, packets: &[&[u8]]) -> bool {
+        if packets.is_empty() {
+            return false;
+        }
+        let now_micros = unix_micros(SystemTime::now());
+        let memory_guard = &self.metrics.memory_guard;
+        if spilling {
+            // the shard lock is held while queueing, so the replay thread can't remove the entry in between
+            let spill_dest = self.dests.entry(dest).or_insert_with(|| {
+                info!(
+                    "Spilling shreds for {dest} to {:?} until it's back.",
+                    self.config.dir
+                );
+                Arc::new(SpillDest {
+                    ring: Mutex::new(SegmentRing::new(
+                        self.config.dir.join(dest_dir_name(dest)),
+                        self.config.max_bytes,
+                    )),
+                    ring_unread: AtomicU64::new(0),
+                    queue: Mutex::new(SpillQueue {
+                        live_queue: VecDeque::new(),
+                        pending_writes: 0,
+                        discarded: false,
+                    }),
+                    error_logged: AtomicBool::new(false),
+                })
+            });
+            let mut queue = spill_dest.queue.lock().unwrap();
+            // live packets queued behind an interrupted replay go ahead of the new ones
+            let mut spilled = queue.live_queue.drain(..).collect::<Vec<_>>();
+            memory_guard.release(BufferKind::Spill, packets_len(&spilled));
+            spilled.extend(packets.iter().map(|packet| (now_micros, packet.to_vec())));
+            let num_spilled = spilled.len();
+            let held = memory_guard.hold(BufferKind::Spill, packets_len(&spilled));
+            let write = SpillWrite {
+                dest,
+                spill_dest: Arc::clone(spill_dest.value()),
+                packets: spilled,
+                _held: held,
+            };
+            // queued with the lock held, so writes for a destination are in the order they were spilled
+            match self.write_sender.try_send(write) {
+                Ok(()) => queue.pending_writes += num_spilled,
+                Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                    self.metrics
+                        .spill_dropped
+                        .fetch_add(num_spilled as u64, Ordering::Relaxed);
+                }
+            }
+            return true;
+        }
+        // the shard lock is held while queueing, so the replay thread can't remove the entry in between
+        let Some(spill_dest) = self.dests.get(&dest) else {
+            return false;
+        };
+        let mut queue = spill_dest.queue.lock().unwrap();
+        if queue.discarded || queue.is_empty(&spill_dest) {
+            return false;
+        }
+        let room = self
+            .config
+            .live_queue_packets
+            .saturating_sub(queue.live_queue.len());
+        let queued = &packets[..room.min(packets.len())];
+        memory_guard.add(
+            BufferKind::Spill,
+            queued.iter().map(|packet| packet.len()).sum(),
+        );
+        queue
+            .live_queue
+            .extend(queued.iter().map(|packet| (now_micros, packet.to_vec())));
+        self.metrics
+            .spill_dropped
+            .fetch_add((packets.len() - queued.len()) as u64, Ordering::Relaxed);
+        true
+    }
+
+    /// Appends packets queued by [Self::divert] to their destination's ring, on the spill writer thread
+    fn write(&self, write: SpillWrite) {
+        let spill_dest = &write.spill_dest;
+        let mut ring = spill_dest.ring.lock().unwrap();
+        // checked with the ring locked, so a discarded destination's ring is already cleared or cleared after this
+        if spill_dest.queue.lock().unwrap().discarded {
+            self.metrics
+                .spill_dropped
+                .fetch_add(write.packets.len() as u64, Ordering::Relaxed);
+        } else {
+            for (spilled_at_micros, packet) in &write.packets {
+                spill_dest.spill(
+                    &mut ring,
+                    write.dest,
+                    *spilled_at_micros,
+                    packet,
+                    &self.metrics,
+                );
+            }
+        }
+        drop(ring);
+        // only once they're in the ring, so the destination isn't removed as drained in between
+        spill_dest.queue.lock().unwrap().pending_writes -= write.packets.len();
+    }
+
+    /// Sends spilled packets, then the live packets queued behind them, to destinations no longer spilled to,
+    /// at most as many as `rate_limiter` allows. Stale packets are skipped
+    fn replay(&self, sink: &dyn ShredSink, rate_limiter: &RateLimiter, now: Instant) {
+        let dest_spill = self.dest_spill.load();
+        let dests = self
+            .dests
+            .iter()
+            .map(|kv| (*kv.key(), kv.value().clone()))
+            .collect::<Vec<_>>();
+        let stale_before_micros =
+            unix_micros(SystemTime::now()).saturating_sub(self.config.max_age.as_micros() as u64);
+        let memory_guard = &self.metrics.memory_guard;
+        for (dest, spill_dest) in dests {
+            match dest_spill.get(&dest) {
+                Some(true) => continue,
+                Some(false) => {}
+                None => {
+                    let mut queue = spill_dest.queue.lock().unwrap();
+                    queue.discarded = true;
+                    let live_queue = mem::take(&mut queue.live_queue);
+                    drop(queue);
+                    memory_guard.release(BufferKind::Spill, packets_len(&live_queue));
+                    let mut ring = spill_dest.ring.lock().unwrap();
+                    let discarded = live_queue.len() as u64
+                        + ring.clear().unwrap_or_else(|e| {
+                            warn!("Failed to delete spilled shreds for {dest}. Error: {e}");
+                            0
+                        });
+                    spill_dest.ring_unread.store(0, Ordering::Relaxed);
+                    drop(ring);
+                    self.metrics
+                        .spill_dropped
+                        .fetch_add(discarded, Ordering::Relaxed);
+                    self.dests.remove(&dest);
+                    info!("Discarded {discarded} spilled shreds for {dest}, it's no longer spilled to.");
+                    continue;
+                }
+            }
+            let allowed = rate_limiter.acquire(MAX_REPLAY_BATCH, now);
+            let mut packets = Vec::with_capacity(allowed);
+            let (mut replayed, mut expired) = (0, 0);
+            {
+                let mut ring = spill_dest.ring.lock().unwrap();
+                while packets.len() < allowed {
+                    match ring.read() {
+                        Ok(Some((spilled_at_micros, _)))
+                            if spilled_at_micros < stale_before_micros =>
+                        {
+                            expired += 1
+                        }
+                        Ok(Some((_, packet))) => {
+                            packets.push(packet);
+                            replayed += 1;
+                        }
+                        Ok(None) => break,
+                        Err(e) => {
+                            let discarded = ring.clear().unwrap_or_default();
+                            self.metrics
+                                .spill_dropped
+                                .fetch_add(discarded, Ordering::Relaxed);
+                            error!("Failed to read spilled shreds for {dest}, discarding {discarded} of them. Error: {e}");
+                            break;
+                        }
+                    }
+                }
+                spill_dest
+                    .ring_unread
+                    .store(ring.unread(), Ordering::Relaxed);
+            }
+            {
+                let mut queue = spill_dest.queue.lock().unwrap();
+                // live packets wait until every spilled packet is in the ring and replayed
+                let ring_drained = queue.pending_writes == 0
+                    && spill_dest.ring_unread.load(Ordering::Relaxed) == 0;
+                while ring_drained && packets.len() < allowed {
+                    match queue.live_queue.pop_front() {
+                        Some((queued_at_micros, packet)) => {
+                            memory_guard.release(BufferKind::Spill, packet.len());
+                            if queued_at_micros < stale_before_micros {
+                                expired += 1;
+                            } else {
+                                packets.push(packet);
+                            }
+                        }
+                        None => break,
+                    }
+                }
+            }
+            self.metrics
+                .spill_replayed
+                .fetch_add(replayed, Ordering::Relaxed);
+            self.metrics
+                .spill_expired
+                .fetch_add(expired, Ordering::Relaxed);
+            if !packets.is_empty() {
+                sink.send(dest, &packets.iter().map(Vec::as_slice).collect::<Vec<_>>());
+            }
+            if self
+                .dests
+                .remove_if(&dest, |_, spill_dest| spill_dest.is_empty())
+                .is_some()
+            {
+                info!("Finished replaying spilled shreds to {dest}, resuming live forwarding.");
+            }
+        }
+    }
+}
+
+/// Bytes of the packets in `packets`
+fn packets_len<'a>(packets: impl IntoIterator<Item = &'a (u64, Vec<u8>)>) -> usize {
+    packets.into_iter().map(|(_, packet)| packet.len()).sum()
+}
+
+fn unix_micros(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Subdirectory of a destination's segments, eg. `10.0.0.1_8001`
+fn dest_dir_name(dest: SocketAddr) -> String {
+    format!("{}_{}", dest.ip(), dest.port()).replace(':', "-")
+}
+
+/// Returns an error if `dir` can't be created or written to
+pub fn validate_spill_dir(dir: &Path) -> Result<(), String> {
+    let probe = dir.join(".spill-check");
+    fs::create_dir_all(dir)
+        .and_then(|()| File::create(&probe))
+        .and_then(|_| fs::remove_file(&probe))
+        .map_err(|e| format!("Invalid arguments provided, --spill-dir {dir:?} isn't writable: {e}"))
+}
+
+/// Appends packets spilled by forwarder threads to disk, so they never wait on it
+pub(crate) fn start_spill_writer_thread(
+    spill_store: Arc<SpillStore>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    Builder::new()
+        .name("ssPxySpillWr".to_string())
+        .spawn(move || {
+            // drain queued packets before exiting
+            while !exit.load(Ordering::Relaxed) || !spill_store.write_receiver.is_empty() {
+                match spill_store
+                    .write_receiver
+                    .recv_timeout(WRITER_EXIT_CHECK_INTERVAL)
+                {
+                    Ok(write) => spill_store.write(write),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            info!("Exiting spill writer thread.");
+        })
+}
+
+/// Replays spilled shreds to destinations once they're back, from its own socket
+pub(crate) fn start_spill_replay_thread(
+    spill_store: Arc<SpillStore>,
+    send_socket_options: SendSocketOptions,
+    send_batch_size: usize,
+    exit: Arc<AtomicBool>,
+) -> io::Result<JoinHandle<()>> {
+    let socket = send_socket_options.bind()?;
+    Builder::new()
+        .name("ssPxySpillRpl".to_string())
+        .spawn(move || {
+            let udp_sink = UdpSink::new(socket, send_batch_size, None, spill_store.metrics.clone());
+            let rate_limiter = RateLimiter::new(spill_store.config.replay_rate_pps, Instant::now());
+            let tick = crossbeam_channel::tick(REPLAY_INTERVAL);
+            while !exit.load(Ordering::Relaxed) {
+                if tick.recv().is_err() {
+                    break;
+                }
+                spill_store.replay(&udp_sink, &rate_limiter, Instant::now());
+            }
+            info!("Exiting spill replay thread.");
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        fs,
+        net::SocketAddr,
+        sync::{atomic::Ordering, Arc},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use arc_swap::ArcSwap;
+
+    use crate::{
+        forwarder::{ShredMetrics, ShredSink},
+        memory_guard::BufferKind,
+        rate_limit::RateLimiter,
+        spill::{
+            unix_micros, SegmentRing, SpillConfig, SpillStore, RECORD_HEADER_LEN,
+            SPILL_CHANNEL_CAPACITY,
+        },
+    };
+
+    #[derive(Default)]
+    struct RecordingSink(RefCell<Vec<(SocketAddr, Vec<u8>)>>);
+
+    impl ShredSink for RecordingSink {
+        fn send(&self, dest: SocketAddr, packets: &[&[u8]]) {
+            self.0
+                .borrow_mut()
+                .extend(packets.iter().map(|packet| (dest, packet.to_vec())));
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("test_spill_{name}_{}", std::process::id()))
+    }
+
+    /// Writes what the spill writer thread would
+    fn write_queued(spill_store: &SpillStore) {
+        while let Ok(write) = spill_store.write_receiver.try_recv() {
+            spill_store.write(write);
+        }
+    }
+
+    #[test]
+    fn test_segment_ring() {
+        let dir = temp_dir("ring");
+        // 8 segments of two 6 byte packets each
+        let mut ring = SegmentRing::new(dir.clone(), 8 * 2 * (RECORD_HEADER_LEN as u64 + 6));
+        for i in 0..16u8 {
+            assert_eq!(ring.append(i as u64, &[i; 6]).unwrap(), 0);
+        }
+        assert_eq!(ring.segments.len(), 8);
+        assert_eq!(ring.unread(), 16);
+        assert_eq!(ring.read().unwrap(), Some((0, vec![0; 6])));
+
+        // a ninth segment overwrites the oldest, including its unread packet
+        assert_eq!(ring.append(16, &[16; 6]).unwrap(), 1);
+        assert_eq!(ring.segments.len(), 8);
+        let mut read = vec![];
+        while let Some((spilled_at, _)) = ring.read().unwrap() {
+            read.push(spilled_at);
+        }
+        assert_eq!(read, (2..=16).collect::<Vec<_>>());
+
+        // segments are deleted once read
+        assert!(ring.segments.is_empty());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        assert_eq!(ring.append(17, b"again").unwrap(), 0);
+        assert_eq!(ring.read().unwrap(), Some((17, b"again".to_vec())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_spill_and_replay() {
+        let dir = temp_dir("replay");
+        let dest = SocketAddr::from(([127, 0, 0, 1], 8001));
+        let dest_spill = Arc::new(ArcSwap::from_pointee(HashMap::from([(dest, true)])));
+        let metrics = Arc::new(ShredMetrics::new());
+        let spill_store = SpillStore::new(
+            SpillConfig {
+                dir: dir.clone(),
+                max_bytes: 1 << 20,
+                replay_rate_pps: 2,
+                max_age: Duration::from_secs(60),
+                live_queue_packets: 1,
+            },
+            dest_spill.clone(),
+            metrics.clone(),
+        );
+        let sink = RecordingSink::default();
+        let start = Instant::now();
+        let rate_limiter = RateLimiter::new(2, start);
+
+        // spilled while the destination is down, nothing is replayed yet
+        assert!(spill_store.divert(dest, true, &[b"one", b"two", b"three"]));
+        write_queued(&spill_store);
+        spill_store.replay(&sink, &rate_limiter, start);
+        assert!(sink.0.borrow().is_empty());
+        assert_eq!(metrics.spill_spilled.load(Ordering::Relaxed), 3);
+
+        // once back, live packets queue behind the replay, up to the limit
+        dest_spill.store(Arc::new(HashMap::from([(dest, false)])));
+        assert!(spill_store.divert(dest, false, &[b"live", b"dropped"]));
+        assert_eq!(metrics.spill_dropped.load(Ordering::Relaxed), 1);
+        spill_store.replay(&sink, &rate_limiter, start);
+        assert_eq!(sink.0.borrow().len(), 2);
+        spill_store.replay(&sink, &rate_limiter, start + Duration::from_secs(1));
+        assert_eq!(
+            sink.0
+                .borrow()
+                .iter()
+                .map(|(_, packet)| packet.as_slice())
+                .collect::<Vec<_>>(),
+            vec![&b"one"[..], b"two", b"three", b"live"]
+        );
+        assert_eq!(metrics.spill_replayed.load(Ordering::Relaxed), 3);
+
+        // drained, so live packets are sent as usual
+        assert!(spill_store.dests.is_empty());
+        assert!(!spill_store.divert(dest, false, &[b"next"]));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_replay_skips_stale_packets() {
+        let dir = temp_dir("stale");
+        let dest = SocketAddr::from(([127, 0, 0, 1], 8001));
+        let dest_spill = Arc::new(ArcSwap::from_pointee(HashMap::from([(dest, false)])));
+        let metrics = Arc::new(ShredMetrics::new());
+        let spill_store = SpillStore::new(
+            SpillConfig {
+                dir: dir.clone(),
+                max_bytes: 1 << 20,
+                replay_rate_pps: 100,
+                max_age: Duration::from_secs(60),
+                live_queue_packets: 16,
+            },
+            dest_spill.clone(),
+            metrics.clone(),
+        );
+        assert!(spill_store.divert(dest, true, &[b"fresh"]));
+        write_queued(&spill_store);
+        {
+            let spill_dest = spill_store.dests.get(&dest).unwrap();
+            let mut ring = spill_dest.ring.lock().unwrap();
+            let stale = unix_micros(SystemTime::now() - Duration::from_secs(120));
+            spill_dest.spill(&mut ring, dest, stale, b"stale", &metrics);
+        }
+        let sink = RecordingSink::default();
+        spill_store.replay(
+            &sink,
+            &RateLimiter::new(100, Instant::now()),
+            Instant::now(),
+        );
+        assert_eq!(*sink.0.borrow(), vec![(dest, b"fresh".to_vec())]);
+        assert_eq!(metrics.spill_expired.load(Ordering::Relaxed), 1);
+
+        // destinations no longer spilled to have their spill discarded
+        assert!(spill_store.divert(dest, true, &[b"discarded"]));
+        write_queued(&spill_store);
+        dest_spill.store(Arc::default());
+        spill_store.replay(
+            &sink,
+            &RateLimiter::new(100, Instant::now()),
+            Instant::now(),
+        );
+        assert!(spill_store.dests.is_empty());
+        assert_eq!(metrics.spill_dropped.load(Ordering::Relaxed), 1);
+
+        // including packets still queued for the writer, which never reach the disk
+        assert!(spill_store.divert(dest, true, &[b"queued"]));
+        spill_store.replay(
+            &sink,
+            &RateLimiter::new(100, Instant::now()),
+            Instant::now(),
+        );
+        write_queued(&spill_store);
+        assert!(spill_store.dests.is_empty());
+        assert_eq!(metrics.spill_dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.spill_spilled.load(Ordering::Relaxed), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_live_packets_wait_for_queued_spill() {
+        let dir = temp_dir("queued");
+        let dest = SocketAddr::from(([127, 0, 0, 1], 8001));
+        let dest_spill = Arc::new(ArcSwap::from_pointee(HashMap::from([(dest, true)])));
+        let metrics = Arc::new(ShredMetrics::new());
+        let spill_store = SpillStore::new(
+            SpillConfig {
+                dir: dir.clone(),
+                max_bytes: 1 << 20,
+                replay_rate_pps: 100,
+                max_age: Duration::from_secs(60),
+                live_queue_packets: 16,
+            },
+            dest_spill.clone(),
+            metrics.clone(),
+        );
+        assert!(spill_store.divert(dest, true, &[b"spilled"]));
+
+        // back before the writer caught up, live packets still queue behind the spilled ones
+        dest_spill.store(Arc::new(HashMap::from([(dest, false)])));
+        assert!(spill_store.divert(dest, false, &[b"live"]));
+        assert_eq!(metrics.memory_guard.held(BufferKind::Spill), 11);
+        let sink = RecordingSink::default();
+        let start = Instant::now();
+        let rate_limiter = RateLimiter::new(100, start);
+        spill_store.replay(&sink, &rate_limiter, start);
+        assert!(sink.0.borrow().is_empty());
+
+        write_queued(&spill_store);
+        spill_store.replay(&sink, &rate_limiter, start + Duration::from_secs(1));
+        assert_eq!(
+            *sink.0.borrow(),
+            vec![(dest, b"spilled".to_vec()), (dest, b"live".to_vec())]
+        );
+        assert_eq!(metrics.memory_guard.held(BufferKind::Spill), 0);
+        assert!(spill_store.dests.is_empty());
+
+        // dropped rather than blocking once the writer falls behind
+        for _ in 0..SPILL_CHANNEL_CAPACITY {
+            assert!(spill_store.divert(dest, true, &[b"x"]));
+        }
+        assert!(spill_store.divert(dest, true, &[b"dropped"]));
+        assert_eq!(metrics.spill_dropped.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            metrics.memory_guard.held(BufferKind::Spill),
+            SPILL_CHANNEL_CAPACITY as u64
+        );
+        write_queued(&spill_store);
+        assert_eq!(metrics.memory_guard.held(BufferKind::Spill), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
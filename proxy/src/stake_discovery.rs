@@ -0,0 +1,554 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::Receiver;
+use ipnet::IpNet;
+use log::{info, warn};
+use solana_client::{
+    rpc_client::RpcClient,
+    rpc_response::{RpcContactInfo, RpcVoteAccountStatus},
+};
+use solana_sdk::{commitment_config::CommitmentConfig, native_token::LAMPORTS_PER_SOL};
+
+use crate::{
+    forwarder::DestinationSources,
+    metrics_backend::{datapoint_info, datapoint_warn},
+    slot_latency::redact_url,
+    supervisor::Supervisor,
+};
+
+/// `getClusterNodes` and `getVoteAccounts` return every node and vote account, so allow for a large response
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+const RPC_URL_PLACEHOLDER: &str = "<rpc-discovery-url>";
+pub const DEFAULT_RPC_DISCOVERY_MAX_NODES: usize = 25;
+
+/// Least activated stake a node needs to be forwarded to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MinStake {
+    /// Share of the total activated stake, eg. `0.1%`
+    Percent(f64),
+    /// Absolute stake, eg. `50000` SOL
+    Sol(f64),
+}
+
+impl MinStake {
+    fn lamports(&self, total_stake: u64) -> u64 {
+        match self {
+            MinStake::Percent(percent) => (total_stake as f64 * percent / 100.0) as u64,
+            MinStake::Sol(sol) => (sol * LAMPORTS_PER_SOL as f64) as u64,
+        }
+    }
+}
+
+/// Which staked nodes `rpc-discovery-url` forwards to, eg. `min_stake=0.1%,max_nodes=25,country=DE|NL`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcDiscoveryFilter {
+    pub min_stake: Option<MinStake>,
+    /// Highest staked nodes kept after filtering
+    pub max_nodes: usize,
+    /// ISO country codes, looked up in `rpc-discovery-geo-file`. Empty allows any
+    pub countries: Vec<String>,
+    /// Autonomous system numbers, looked up in `rpc-discovery-geo-file`. Empty allows any
+    pub asns: Vec<u32>,
+}
+
+impl Default for RpcDiscoveryFilter {
+    fn default() -> Self {
+        Self {
+            min_stake: None,
+            max_nodes: DEFAULT_RPC_DISCOVERY_MAX_NODES,
+            countries: vec![],
+            asns: vec![],
+        }
+    }
+}
+
+impl RpcDiscoveryFilter {
+    fn needs_geo(&self) -> bool {
+        !self.countries.is_empty() || !self.asns.is_empty()
+    }
+}
+
+/// Parses an `rpc-discovery-filter`, comma separated `key=value` pairs with `|` separated lists
+pub fn parse_rpc_discovery_filter(spec: &str) -> Result<RpcDiscoveryFilter, String> {
+    let mut filter = RpcDiscoveryFilter::default();
+    for option in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (key, value) = option
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .ok_or_else(|| {
+                format!("Invalid RPC discovery filter {option:?}, expected `key=value`.")
+            })?;
+        let invalid = || format!("Invalid RPC discovery filter value for {key}: {value:?}.");
+        match key {
+            "min_stake" => {
+                let (amount, max) = match value.strip_suffix('%') {
+                    Some(percent) => (percent.trim(), 100.0),
+                    None => (value, f64::MAX),
+                };
+                let amount = amount.parse::<f64>().map_err(|_| invalid())?;
+                if !amount.is_finite() || !(0.0..=max).contains(&amount) {
+                    return Err(invalid());
+                }
+                filter.min_stake = Some(if value.ends_with('%') {
+                    MinStake::Percent(amount)
+                } else {
+                    MinStake::Sol(amount)
+                });
+            }
+            "max_nodes" => {
+                filter.max_nodes = value.parse().map_err(|_| invalid())?;
+                if filter.max_nodes == 0 {
+                    return Err(invalid());
+                }
+            }
+            "country" => {
+                filter.countries = value
+                    .split('|')
+                    .map(|country| {
+                        let country = country.trim();
+                        (country.len() == 2 && country.chars().all(|c| c.is_ascii_alphabetic()))
+                            .then(|| country.to_ascii_uppercase())
+                            .ok_or_else(invalid)
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "asn" => {
+                filter.asns = value
+                    .split('|')
+                    .map(|asn| parse_asn(asn).ok_or_else(invalid))
+                    .collect::<Result<_, _>>()?;
+            }
+            _ => {
+                return Err(format!(
+                    "Invalid RPC discovery filter key {key:?}, expected min_stake, max_nodes, country, or asn."
+                ))
+            }
+        }
+    }
+    Ok(filter)
+}
+
+/// Parses `13335` or `AS13335`
+fn parse_asn(asn: &str) -> Option<u32> {
+    let asn = asn.trim();
+    asn.strip_prefix("AS")
+        .or_else(|| asn.strip_prefix("as"))
+        .unwrap_or(asn)
+        .parse()
+        .ok()
+}
+
+/// Country and ASN of IP ranges, from a `cidr,country,asn` CSV such as an export of a GeoIP database
+#[derive(Debug, Default)]
+pub struct GeoTable {
+    entries: Vec<(IpNet, Option<String>, Option<u32>)>,
+}
+
+impl GeoTable {
+    /// Reads `path`, skipping blank lines and `#` comments. Country or ASN may be left empty
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read geo file {}: {e}", path.display()))?;
+        Self::parse(&contents).map_err(|e| format!("Invalid geo file {}, {e}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self, String> {
+        let mut entries = vec![];
+        for (line_no, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                format!(
+                    "line {} isn't formatted as `cidr,country,asn`.",
+                    line_no + 1
+                )
+            };
+            let mut fields = line.split(',').map(str::trim);
+            let (Some(net), Some(country), Some(asn), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            let net = IpNet::from_str(net)
+                .or_else(|_| IpAddr::from_str(net).map(IpNet::from))
+                .map_err(|_| invalid())?;
+            let asn = match asn {
+                "" => None,
+                asn => Some(parse_asn(asn).ok_or_else(invalid)?),
+            };
+            let country = (!country.is_empty()).then(|| country.to_ascii_uppercase());
+            entries.push((net, country, asn));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Country and ASN of the most specific range containing `ip`
+    fn lookup(&self, ip: IpAddr) -> Option<(Option<&str>, Option<u32>)> {
+        let ip = ip.to_canonical();
+        self.entries
+            .iter()
+            .filter(|(net, _, _)| net.contains(&ip))
+            .max_by_key(|(net, _, _)| net.prefix_len())
+            .map(|(_, country, asn)| (country.as_deref(), *asn))
+    }
+}
+
+/// Where, how often, and which staked nodes to discover with `getClusterNodes` and `getVoteAccounts`
+#[derive(Clone, Debug, PartialEq)]
+pub struct RpcDiscoveryConfig {
+    pub rpc_url: String,
+    pub filter: RpcDiscoveryFilter,
+    pub refresh_interval: Duration,
+    /// Re-read on every refresh, so it can be updated without a restart
+    pub geo_file: Option<PathBuf>,
+}
+
+/// TVU addresses of the highest staked nodes passing `filter`, highest stake first.
+/// Delinquent vote accounts count towards the total stake but their nodes are never selected
+fn select_nodes(
+    cluster_nodes: &[RpcContactInfo],
+    vote_accounts: &RpcVoteAccountStatus,
+    filter: &RpcDiscoveryFilter,
+    geo: Option<&GeoTable>,
+) -> Vec<SocketAddr> {
+    let total_stake = vote_accounts
+        .current
+        .iter()
+        .chain(&vote_accounts.delinquent)
+        .map(|account| account.activated_stake)
+        .sum::<u64>();
+    let min_stake = filter
+        .min_stake
+        .map_or(0, |min_stake| min_stake.lamports(total_stake));
+    // a node may have several vote accounts
+    let mut node_stakes = HashMap::<&str, u64>::new();
+    for account in &vote_accounts.current {
+        *node_stakes.entry(&account.node_pubkey).or_default() += account.activated_stake;
+    }
+    let geo_allowed = |tvu: &SocketAddr| {
+        if !filter.needs_geo() {
+            return true;
+        }
+        let Some((country, asn)) = geo.and_then(|geo| geo.lookup(tvu.ip())) else {
+            return false;
+        };
+        (filter.countries.is_empty()
+            || country.is_some_and(|country| filter.countries.iter().any(|c| c == country)))
+            && (filter.asns.is_empty() || asn.is_some_and(|asn| filter.asns.contains(&asn)))
+    };
+    let mut selected = cluster_nodes
+        .iter()
+        .filter_map(|node| {
+            let stake = *node_stakes.get(node.pubkey.as_str())?;
+            let tvu = node.tvu?;
+            (stake > 0 && stake >= min_stake && geo_allowed(&tvu)).then_some((
+                stake,
+                &node.pubkey,
+                tvu,
+            ))
+        })
+        .collect::<Vec<_>>();
+    // ties broken by identity so the set doesn't flap between refreshes
+    selected.sort_unstable_by(|(a_stake, a_pubkey, _), (b_stake, b_pubkey, _)| {
+        b_stake.cmp(a_stake).then(a_pubkey.cmp(b_pubkey))
+    });
+    let mut seen = HashSet::new();
+    selected
+        .into_iter()
+        .map(|(_, _, tvu)| tvu)
+        .filter(|tvu| seen.insert(*tvu))
+        .take(filter.max_nodes)
+        .collect()
+}
+
+/// Returns (added, removed) going from `old` to `new`
+fn diff(old: &[SocketAddr], new: &[SocketAddr]) -> (Vec<SocketAddr>, Vec<SocketAddr>) {
+    let added = new
+        .iter()
+        .filter(|addr| !old.contains(addr))
+        .copied()
+        .collect();
+    let removed = old
+        .iter()
+        .filter(|addr| !new.contains(addr))
+        .copied()
+        .collect();
+    (added, removed)
+}
+
+/// Periodically forwards to the TVU address of the highest staked nodes passing `rpc-discovery-filter`,
+/// alongside the other destinations. A failed refresh keeps the last discovered set
+pub fn start_rpc_discovery_thread(
+    config: RpcDiscoveryConfig,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    // the url may carry an API key, so it's never logged
+    info!(
+        "Discovering destinations by stake every {:?} with {:?}.",
+        config.refresh_interval, config.filter
+    );
+    supervisor.spawn_restartable("ssPxyStakeDisc", move || {
+        let rpc_client = RpcClient::new_with_timeout_and_commitment(
+            config.rpc_url.clone(),
+            RPC_TIMEOUT,
+            CommitmentConfig::processed(),
+        );
+        let refresh_tick = crossbeam_channel::tick(config.refresh_interval);
+        // discover right away instead of waiting for the first tick
+        let mut refresh_now = true;
+        while !exit.load(Ordering::Relaxed) {
+            if !refresh_now {
+                crossbeam_channel::select! {
+                    recv(refresh_tick) -> _ => {}
+                    recv(shutdown_receiver) -> _ => break,
+                }
+            }
+            refresh_now = false;
+
+            let geo = match config.geo_file.as_deref().map(GeoTable::load).transpose() {
+                Ok(geo) => geo,
+                Err(e) => {
+                    warn!("Failed to load RPC discovery geo file, keeping discovered destinations. Error: {e}");
+                    continue;
+                }
+            };
+            let fetched = rpc_client
+                .get_cluster_nodes()
+                .and_then(|cluster_nodes| Ok((cluster_nodes, rpc_client.get_vote_accounts()?)));
+            let (cluster_nodes, vote_accounts) = match fetched {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    let e = redact_url(e, &config.rpc_url, RPC_URL_PLACEHOLDER);
+                    warn!("Failed to get cluster nodes and vote accounts, keeping discovered destinations. Error: {e}");
+                    datapoint_warn!(
+                        "shredstream_proxy-rpc_discovery_error",
+                        ("errors", 1, i64),
+                        ("error_str", e, String),
+                    );
+                    continue;
+                }
+            };
+            let selected =
+                select_nodes(&cluster_nodes, &vote_accounts, &config.filter, geo.as_ref());
+            let mut dest_sources = dest_sources.lock().unwrap();
+            let (added, removed) = diff(&dest_sources.stake_dest_sockets, &selected);
+            if !added.is_empty() || !removed.is_empty() {
+                info!(
+                    "Stake discovery selected {} destinations, added {added:?}, removed {removed:?}.",
+                    selected.len()
+                );
+                dest_sources.stake_dest_sockets = selected;
+                dest_sources.store_union(&unioned_dest_sockets);
+            }
+            datapoint_info!(
+                "shredstream_proxy-rpc_discovery",
+                ("cluster_nodes", cluster_nodes.len(), i64),
+                ("selected", dest_sources.stake_dest_sockets.len(), i64),
+                ("added", added.len(), i64),
+                ("removed", removed.len(), i64),
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use solana_client::rpc_response::{RpcContactInfo, RpcVoteAccountInfo, RpcVoteAccountStatus};
+    use solana_sdk::{native_token::LAMPORTS_PER_SOL, pubkey::Pubkey};
+
+    use crate::stake_discovery::{
+        diff, parse_rpc_discovery_filter, select_nodes, GeoTable, MinStake, RpcDiscoveryFilter,
+    };
+
+    fn contact_info(identity: &Pubkey, tvu: Option<&str>) -> RpcContactInfo {
+        RpcContactInfo {
+            pubkey: identity.to_string(),
+            gossip: None,
+            tvu: tvu.map(|tvu| SocketAddr::from_str(tvu).unwrap()),
+            tpu: None,
+            tpu_quic: None,
+            tpu_forwards: None,
+            tpu_forwards_quic: None,
+            tpu_vote: None,
+            serve_repair: None,
+            rpc: None,
+            pubsub: None,
+            version: None,
+            feature_set: None,
+            shred_version: None,
+        }
+    }
+
+    fn vote_account(identity: &Pubkey, sol: u64) -> RpcVoteAccountInfo {
+        RpcVoteAccountInfo {
+            vote_pubkey: Pubkey::new_unique().to_string(),
+            node_pubkey: identity.to_string(),
+            activated_stake: sol * LAMPORTS_PER_SOL,
+            commission: 0,
+            epoch_vote_account: true,
+            epoch_credits: vec![],
+            last_vote: 0,
+            root_slot: 0,
+        }
+    }
+
+    fn addr(addr: &str) -> SocketAddr {
+        SocketAddr::from_str(addr).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rpc_discovery_filter() {
+        assert_eq!(
+            parse_rpc_discovery_filter("").unwrap(),
+            RpcDiscoveryFilter::default()
+        );
+        assert_eq!(
+            parse_rpc_discovery_filter(
+                "min_stake=0.1%, max_nodes=10,country=de|NL,asn=AS13335|16509"
+            )
+            .unwrap(),
+            RpcDiscoveryFilter {
+                min_stake: Some(MinStake::Percent(0.1)),
+                max_nodes: 10,
+                countries: vec!["DE".to_string(), "NL".to_string()],
+                asns: vec![13335, 16509],
+            }
+        );
+        assert_eq!(
+            parse_rpc_discovery_filter("min_stake=50000")
+                .unwrap()
+                .min_stake,
+            Some(MinStake::Sol(50_000.0))
+        );
+        for invalid in [
+            "min_stake",
+            "min_stake=-1",
+            "min_stake=101%",
+            "min_stake=NaN",
+            "max_nodes=0",
+            "country=DEU",
+            "asn=ASX",
+            "region=eu",
+        ] {
+            assert!(parse_rpc_discovery_filter(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_geo_table() {
+        let geo = GeoTable::parse(
+            "# cidr,country,asn\n10.0.0.0/8,us,AS100\n\n10.1.0.0/16,DE,\n192.168.0.1,,200\n",
+        )
+        .unwrap();
+        assert_eq!(
+            geo.lookup("10.2.0.1".parse().unwrap()),
+            Some((Some("US"), Some(100)))
+        );
+        // most specific range wins
+        assert_eq!(
+            geo.lookup("10.1.0.1".parse().unwrap()),
+            Some((Some("DE"), None))
+        );
+        assert_eq!(
+            geo.lookup("::ffff:192.168.0.1".parse().unwrap()),
+            Some((None, Some(200)))
+        );
+        assert_eq!(geo.lookup("172.16.0.1".parse().unwrap()), None);
+
+        assert!(GeoTable::parse("10.0.0.0/8,US").is_err());
+        assert!(GeoTable::parse("10.0.0.0/33,US,100").is_err());
+    }
+
+    #[test]
+    fn test_select_nodes() {
+        let [big, medium, small, delinquent, unstaked, no_tvu] =
+            [(); 6].map(|_| Pubkey::new_unique());
+        let cluster_nodes = [
+            contact_info(&big, Some("10.0.0.1:8001")),
+            contact_info(&medium, Some("10.1.0.2:8001")),
+            contact_info(&small, Some("10.0.0.3:8001")),
+            contact_info(&delinquent, Some("10.0.0.4:8001")),
+            contact_info(&unstaked, Some("10.0.0.5:8001")),
+            contact_info(&no_tvu, None),
+        ];
+        let vote_accounts = RpcVoteAccountStatus {
+            // stake of several vote accounts adds up
+            current: vec![
+                vote_account(&big, 500),
+                vote_account(&medium, 200),
+                vote_account(&medium, 100),
+                vote_account(&small, 10),
+                vote_account(&no_tvu, 1_000),
+            ],
+            delinquent: vec![vote_account(&delinquent, 190)],
+        };
+
+        let filter = RpcDiscoveryFilter::default();
+        assert_eq!(
+            select_nodes(&cluster_nodes, &vote_accounts, &filter, None),
+            vec![
+                addr("10.0.0.1:8001"),
+                addr("10.1.0.2:8001"),
+                addr("10.0.0.3:8001")
+            ]
+        );
+
+        // 1% of the 2000 SOL total, including delinquent stake
+        let filter = parse_rpc_discovery_filter("min_stake=1%").unwrap();
+        assert_eq!(
+            select_nodes(&cluster_nodes, &vote_accounts, &filter, None),
+            vec![addr("10.0.0.1:8001"), addr("10.1.0.2:8001")]
+        );
+        let filter = parse_rpc_discovery_filter("min_stake=10,max_nodes=1").unwrap();
+        assert_eq!(
+            select_nodes(&cluster_nodes, &vote_accounts, &filter, None),
+            vec![addr("10.0.0.1:8001")]
+        );
+
+        // nodes missing from the geo file are left out
+        let geo = GeoTable::parse("10.0.0.0/8,US,100\n10.1.0.0/16,DE,200\n").unwrap();
+        let filter = parse_rpc_discovery_filter("country=DE").unwrap();
+        assert_eq!(
+            select_nodes(&cluster_nodes, &vote_accounts, &filter, Some(&geo)),
+            vec![addr("10.1.0.2:8001")]
+        );
+        let filter = parse_rpc_discovery_filter("asn=100").unwrap();
+        assert_eq!(
+            select_nodes(&cluster_nodes, &vote_accounts, &filter, Some(&geo)),
+            vec![addr("10.0.0.1:8001"), addr("10.0.0.3:8001")]
+        );
+        assert!(select_nodes(&cluster_nodes, &vote_accounts, &filter, None).is_empty());
+    }
+
+    #[test]
+    fn test_diff() {
+        let (a, b, c) = (
+            addr("10.0.0.1:8001"),
+            addr("10.0.0.2:8001"),
+            addr("10.0.0.3:8001"),
+        );
+        assert_eq!(diff(&[a, b], &[b, c]), (vec![c], vec![a]));
+        assert_eq!(diff(&[a, b], &[b, a]), (vec![], vec![]));
+    }
+}
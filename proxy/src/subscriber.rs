@@ -0,0 +1,202 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::Duration,
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use log::info;
+use solana_perf::packet::PacketBatch;
+
+use crate::forwarder::ShredMetrics;
+
+/// Batches queued for the dispatch thread before new ones are dropped
+const SUBSCRIBER_CHANNEL_CAPACITY: usize = 1_024;
+const DISPATCH_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub type ShredCallback = Box<dyn Fn(&[u8], SocketAddr) + Send + Sync>;
+
+/// Consumes deduplicated shreds in process, called from a dispatch thread off the forwarding path.
+/// Shreds a subscriber can't keep up with are dropped and counted in [ShredMetrics]
+pub enum ShredSubscriber {
+    /// Called with each shred and the address it was received from
+    Callback(ShredCallback),
+    /// Sent each shred, dropping it if the channel is full. Removed once the receiver is dropped
+    Channel(Sender<Vec<u8>>),
+}
+
+impl ShredSubscriber {
+    /// Returns false once the subscriber is gone
+    fn dispatch(&self, shred: &[u8], src: SocketAddr, metrics: &ShredMetrics) -> bool {
+        match self {
+            ShredSubscriber::Callback(callback) => callback(shred, src),
+            ShredSubscriber::Channel(sender) => match sender.try_send(shred.to_vec()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    metrics.subscriber_dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            },
+        }
+        metrics.subscriber_delivered.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+}
+
+/// Copies deduped shreds from forwarders to the subscriber dispatch thread, dropping them when it falls behind
+#[derive(Clone)]
+pub struct SubscriberTap {
+    shred_sender: Sender<Vec<(SocketAddr, Vec<u8>)>>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl SubscriberTap {
+    pub fn send(&self, packet_batches: &[PacketBatch]) {
+        // discarded (duplicate or filtered) packets return None from `data()` and are skipped
+        let shreds = packet_batches
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .filter_map(|packet| Some((packet.meta().socket_addr(), packet.data(..)?.to_vec())))
+            .collect::<Vec<_>>();
+        if shreds.is_empty() {
+            return;
+        }
+        let num_shreds = shreds.len() as u64;
+        if self.shred_sender.try_send(shreds).is_err() {
+            self.metrics
+                .subscriber_dropped
+                .fetch_add(num_shreds, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Hands shreds sent through the returned tap to each subscriber in registration order
+pub fn start_subscriber_dispatch_thread(
+    subscribers: Vec<ShredSubscriber>,
+    metrics: Arc<ShredMetrics>,
+    exit: Arc<AtomicBool>,
+) -> (SubscriberTap, JoinHandle<()>) {
+    let (shred_sender, shred_receiver) = crossbeam_channel::bounded(SUBSCRIBER_CHANNEL_CAPACITY);
+    let tap = SubscriberTap {
+        shred_sender,
+        metrics: metrics.clone(),
+    };
+    let hdl = Builder::new()
+        .name("ssPxySubscribe".to_string())
+        .spawn(move || dispatch_loop(subscribers, shred_receiver, &metrics, &exit))
+        .unwrap();
+    (tap, hdl)
+}
+
+fn dispatch_loop(
+    mut subscribers: Vec<ShredSubscriber>,
+    shred_receiver: Receiver<Vec<(SocketAddr, Vec<u8>)>>,
+    metrics: &ShredMetrics,
+    exit: &AtomicBool,
+) {
+    while !exit.load(Ordering::Relaxed) {
+        let shreds = match shred_receiver.recv_timeout(DISPATCH_TIMEOUT) {
+            Ok(shreds) => shreds,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        for (src, shred) in &shreds {
+            subscribers.retain(|subscriber| {
+                let retain = subscriber.dispatch(shred, *src, metrics);
+                if !retain {
+                    info!("Shred subscriber channel closed, removing subscriber.");
+                }
+                retain
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use solana_perf::packet::{Meta, Packet, PacketBatch, PACKET_DATA_SIZE};
+
+    use crate::{
+        forwarder::ShredMetrics,
+        subscriber::{dispatch_loop, ShredSubscriber, SubscriberTap},
+    };
+
+    fn packet_batch(payloads: &[&[u8]], src: SocketAddr) -> PacketBatch {
+        PacketBatch::new(
+            payloads
+                .iter()
+                .map(|payload| {
+                    let mut data = [0; PACKET_DATA_SIZE];
+                    data[..payload.len()].copy_from_slice(payload);
+                    let mut packet = Packet::new(data, Meta::default());
+                    packet.meta_mut().size = payload.len();
+                    packet.meta_mut().set_socket_addr(&src);
+                    packet
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_dispatch_to_subscribers() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let src = SocketAddr::from(([10, 0, 0, 1], 8001));
+        let (shred_sender, shred_receiver) = crossbeam_channel::bounded(1);
+        let tap = SubscriberTap {
+            shred_sender,
+            metrics: metrics.clone(),
+        };
+        let mut batch = packet_batch(&[b"shred0", b"duplicate", b"shred1"], src);
+        batch[1].meta_mut().set_discard(true);
+        tap.send(&[batch.clone()]);
+        // queue is full, so the whole batch is dropped
+        tap.send(&[batch]);
+        assert_eq!(metrics.subscriber_dropped.swap(0, Ordering::Relaxed), 2);
+        drop(tap);
+
+        let received = Arc::new(Mutex::new(vec![]));
+        let callback = {
+            let received = received.clone();
+            ShredSubscriber::Callback(Box::new(move |shred, src| {
+                received.lock().unwrap().push((shred.to_vec(), src))
+            }))
+        };
+        // a slow subscriber has its shreds dropped without affecting others
+        let (slow_sender, slow_receiver) = crossbeam_channel::bounded(1);
+        let (closed_sender, _) = crossbeam_channel::bounded(1);
+        dispatch_loop(
+            vec![
+                callback,
+                ShredSubscriber::Channel(slow_sender),
+                ShredSubscriber::Channel(closed_sender),
+            ],
+            shred_receiver,
+            &metrics,
+            &AtomicBool::new(false),
+        );
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(b"shred0".to_vec(), src), (b"shred1".to_vec(), src)]
+        );
+        assert_eq!(
+            slow_receiver.try_iter().collect::<Vec<_>>(),
+            vec![b"shred0"]
+        );
+        assert_eq!(metrics.subscriber_delivered.load(Ordering::Relaxed), 3);
+        assert_eq!(metrics.subscriber_dropped.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    any::Any,
+    cell::Cell,
+    collections::VecDeque,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
+use log::{error, warn};
+
+use crate::{
+    forwarder::ShredMetrics,
+    metrics_backend::{datapoint_error, datapoint_warn},
+};
+
+/// Delay before the first restart, doubled for each further restart within the window
+const RESTART_INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const RESTART_MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+thread_local! {
+    /// Set on threads whose panics are handled by a [Supervisor] instead of the panic hook
+    static SUPERVISED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Returns true if the current thread was spawned by a [Supervisor]
+pub fn is_supervised_thread() -> bool {
+    SUPERVISED.with(Cell::get)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RestartPolicy {
+    /// Restarts allowed within `window` before shutting down the proxy
+    pub max_restarts: usize,
+    pub window: Duration,
+}
+
+/// Respawns restartable threads when they panic, such as destination refresh and heartbeat.
+/// Threads not spawned through the supervisor, such as forwarders, are critical: a panic in them shuts down the proxy via the panic hook.
+#[derive(Clone)]
+pub struct Supervisor {
+    policy: RestartPolicy,
+    metrics: Arc<ShredMetrics>,
+    shutdown_sender: Sender<()>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+}
+
+impl Supervisor {
+    pub fn new(
+        policy: RestartPolicy,
+        metrics: Arc<ShredMetrics>,
+        shutdown_sender: Sender<()>,
+        shutdown_receiver: Receiver<()>,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        Self {
+            policy,
+            metrics,
+            shutdown_sender,
+            shutdown_receiver,
+            exit,
+        }
+    }
+
+    /// Spawns a thread running `work`, running it again with backoff if it panics.
+    /// Shuts down the proxy once it panics more than `max_restarts` times within `window`.
+    pub fn spawn_restartable<F>(&self, name: &str, mut work: F) -> JoinHandle<()>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let thread_name = name.to_string();
+        Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                SUPERVISED.with(|supervised| supervised.set(true));
+                let mut restarts = VecDeque::new();
+                while let Err(panic_payload) = panic::catch_unwind(AssertUnwindSafe(&mut work)) {
+                    if supervisor.exit.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let Some(backoff) =
+                        record_restart(&mut restarts, Instant::now(), &supervisor.policy)
+                    else {
+                        error!(
+                            "Thread {thread_name} panicked more than {} times within {:?}, shutting down.",
+                            supervisor.policy.max_restarts, supervisor.policy.window
+                        );
+                        datapoint_error!(
+                            "shredstream_proxy-thread_restart_limit",
+                            "thread" => thread_name,
+                            ("max_restarts", supervisor.policy.max_restarts, i64),
+                        );
+                        supervisor.exit.store(true, Ordering::SeqCst);
+                        crate::broadcast_shutdown(&supervisor.shutdown_sender);
+                        return;
+                    };
+
+                    let error_str = panic_message(panic_payload.as_ref());
+                    warn!("Thread {thread_name} panicked, restarting in {backoff:?}. Error: {error_str}");
+                    supervisor
+                        .metrics
+                        .thread_restarts
+                        .fetch_add(1, Ordering::Relaxed);
+                    datapoint_warn!(
+                        "shredstream_proxy-thread_restart",
+                        "thread" => thread_name,
+                        ("restarts_in_window", restarts.len(), i64),
+                        ("error_str", error_str, String),
+                    );
+                    // handle shutdown during backoff (avoid using sleep since it will hang under SIGINT)
+                    match supervisor.shutdown_receiver.recv_timeout(backoff) {
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            })
+            .unwrap()
+    }
+}
+
+/// Records a restart at `now`, forgetting restarts older than the window.
+/// Returns the backoff before restarting, or `None` if the restart limit is exceeded
+fn record_restart(
+    restarts: &mut VecDeque<Instant>,
+    now: Instant,
+    policy: &RestartPolicy,
+) -> Option<Duration> {
+    while restarts
+        .front()
+        .is_some_and(|restart| now.duration_since(*restart) >= policy.window)
+    {
+        restarts.pop_front();
+    }
+    if restarts.len() >= policy.max_restarts {
+        return None;
+    }
+    restarts.push_back(now);
+    let doublings = (restarts.len() - 1).min(u32::BITS as usize - 1) as u32;
+    Some(
+        RESTART_INITIAL_BACKOFF
+            .saturating_mul(1 << doublings)
+            .min(RESTART_MAX_BACKOFF),
+    )
+}
+
+pub(crate) fn panic_message(panic_payload: &(dyn Any + Send)) -> String {
+    panic_payload
+        .downcast_ref::<&str>()
+        .map(|message| message.to_string())
+        .or_else(|| panic_payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        forwarder::ShredMetrics,
+        supervisor::{record_restart, RestartPolicy, Supervisor},
+    };
+
+    #[test]
+    fn test_record_restart() {
+        let policy = RestartPolicy {
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+        };
+        let start = Instant::now();
+        let mut restarts = VecDeque::new();
+
+        // backoff doubles within the window
+        let backoffs = (0..3)
+            .map(|i| record_restart(&mut restarts, start + Duration::from_secs(i), &policy))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            backoffs,
+            vec![
+                Some(Duration::from_secs(1)),
+                Some(Duration::from_secs(2)),
+                Some(Duration::from_secs(4)),
+            ]
+        );
+        // limit exceeded
+        assert_eq!(
+            record_restart(&mut restarts, start + Duration::from_secs(3), &policy),
+            None
+        );
+        // restarts outside the window are forgotten, leaving the one at 2s
+        assert_eq!(
+            record_restart(&mut restarts, start + Duration::from_secs(61), &policy),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            record_restart(&mut restarts, start + Duration::from_secs(200), &policy),
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn test_spawn_restartable() {
+        let metrics = Arc::new(ShredMetrics::new());
+        let exit = Arc::new(AtomicBool::new(false));
+        let (shutdown_sender, shutdown_receiver) = crossbeam_channel::bounded(256);
+        let supervisor = Supervisor::new(
+            RestartPolicy {
+                max_restarts: 1,
+                window: Duration::from_secs(60),
+            },
+            metrics.clone(),
+            shutdown_sender,
+            shutdown_receiver.clone(),
+            exit.clone(),
+        );
+
+        // recovers from a single panic
+        let runs = Arc::new(AtomicUsize::new(0));
+        let hdl = {
+            let runs = runs.clone();
+            supervisor.spawn_restartable("ssTestRestart", move || {
+                if runs.fetch_add(1, Ordering::Relaxed) == 0 {
+                    panic!("first run");
+                }
+            })
+        };
+        hdl.join().unwrap();
+        assert_eq!(runs.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.thread_restarts.load(Ordering::Relaxed), 1);
+        assert!(!exit.load(Ordering::Relaxed));
+
+        // shuts down once the limit is exceeded
+        supervisor
+            .spawn_restartable("ssTestRestart", || panic!("every run"))
+            .join()
+            .unwrap();
+        assert!(exit.load(Ordering::Relaxed));
+        assert!(shutdown_receiver.try_recv().is_ok());
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+use sd_notify::NotifyState;
+
+use crate::{forwarder::ShredMetrics, ShredstreamProxyError};
+
+/// How often readiness and forwarder progress are checked
+const NOTIFY_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+/// Forwarder threads wake at least every `RUNTIME_DEST_REFRESH_INTERVAL` while idle, so this leaves plenty of room
+pub(crate) const MIN_FORWARDER_STALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Returns true when running under systemd with a notify socket, eg. `Type=notify`
+pub fn notify_enabled() -> bool {
+    std::env::var_os("NOTIFY_SOCKET").is_some()
+}
+
+/// When each registered thread last made progress, so a thread stuck mid-send is told apart from one idling
+pub struct ThreadLiveness {
+    started_at: Instant,
+    /// Milliseconds after `started_at` of each thread's last beat
+    last_beats_ms: Mutex<Vec<Arc<AtomicU64>>>,
+}
+
+impl Default for ThreadLiveness {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            last_beats_ms: Mutex::new(vec![]),
+        }
+    }
+}
+
+impl ThreadLiveness {
+    /// Registers a thread, counted as making progress as of now
+    pub fn register(&self) -> LivenessBeat {
+        let beat = LivenessBeat {
+            started_at: self.started_at,
+            last_beat_ms: Arc::new(AtomicU64::new(0)),
+        };
+        beat.beat_at(Instant::now());
+        self.last_beats_ms
+            .lock()
+            .unwrap()
+            .push(beat.last_beat_ms.clone());
+        beat
+    }
+
+    pub(crate) fn num_threads(&self) -> usize {
+        self.last_beats_ms.lock().unwrap().len()
+    }
+
+    /// Returns the number of threads that haven't beat for `stall_timeout`, including threads that exited
+    pub(crate) fn num_stalled(&self, stall_timeout: Duration, now: Instant) -> usize {
+        let now_ms = now.duration_since(self.started_at).as_millis() as u64;
+        self.last_beats_ms
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|last_beat_ms| {
+                now_ms.saturating_sub(last_beat_ms.load(Ordering::Relaxed))
+                    >= stall_timeout.as_millis() as u64
+            })
+            .count()
+    }
+}
+
+/// Handed to a registered thread, which calls [Self::beat] on every loop iteration
+pub struct LivenessBeat {
+    started_at: Instant,
+    last_beat_ms: Arc<AtomicU64>,
+}
+
+impl LivenessBeat {
+    pub fn beat(&self) {
+        self.beat_at(Instant::now());
+    }
+
+    fn beat_at(&self, now: Instant) {
+        self.last_beat_ms.store(
+            now.duration_since(self.started_at).as_millis() as u64,
+            Ordering::Relaxed,
+        );
+    }
+}
+
+/// Notifies systemd of readiness once listening, and after the first successful heartbeat if `wait_for_heartbeat`.
+/// With `WatchdogSec` set, pings the watchdog only while every forwarder thread is making progress, so systemd restarts
+/// the proxy when one gets stuck. Sends `STOPPING=1` once shutdown begins
+pub fn start_systemd_notify_thread(
+    wait_for_heartbeat: bool,
+    forwarder_liveness: Arc<ThreadLiveness>,
+    metrics: Arc<ShredMetrics>,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> Result<JoinHandle<()>, ShredstreamProxyError> {
+    let mut watchdog_usec = 0;
+    let watchdog_timeout = sd_notify::watchdog_enabled(false, &mut watchdog_usec)
+        .then(|| Duration::from_micros(watchdog_usec));
+    let hdl = Builder::new()
+        .name("ssPxySystemd".to_string())
+        .spawn(move || {
+            let notify = |state: NotifyState| {
+                if let Err(e) = sd_notify::notify(false, &[state]) {
+                    warn!("Failed to notify systemd. Error: {e}");
+                }
+            };
+            // pinging at half the timeout is what systemd recommends
+            let check_interval = watchdog_timeout
+                .map_or(NOTIFY_CHECK_INTERVAL, |timeout| {
+                    NOTIFY_CHECK_INTERVAL.min(timeout / 2)
+                });
+            let stall_timeout = watchdog_timeout
+                .map_or(MIN_FORWARDER_STALL_TIMEOUT, |timeout| {
+                    timeout.max(MIN_FORWARDER_STALL_TIMEOUT)
+                });
+            if let Some(watchdog_timeout) = watchdog_timeout {
+                info!("Pinging systemd watchdog, which times out after {watchdog_timeout:?}.");
+            }
+            let check_tick = crossbeam_channel::tick(check_interval);
+            let mut ready = false;
+            let mut last_ping = None::<Instant>;
+            let mut stalled = false;
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(check_tick) -> _ => {
+                        if !ready
+                            && (!wait_for_heartbeat
+                                || metrics.successful_heartbeat_cumulative.load(Ordering::Relaxed) > 0)
+                        {
+                            ready = true;
+                            info!("Notifying systemd the proxy is ready.");
+                            notify(NotifyState::Ready);
+                        }
+                        let Some(watchdog_timeout) = watchdog_timeout else {
+                            continue;
+                        };
+                        let num_stalled = forwarder_liveness.num_stalled(stall_timeout, Instant::now());
+                        match (num_stalled > 0, stalled) {
+                            (true, false) => warn!(
+                                "{num_stalled} forwarder threads made no progress for {stall_timeout:?}, withholding systemd watchdog pings."
+                            ),
+                            (false, true) => info!("Forwarder threads resumed, resuming systemd watchdog pings."),
+                            _ => {}
+                        }
+                        stalled = num_stalled > 0;
+                        if !stalled
+                            && last_ping.map_or(true, |last_ping| last_ping.elapsed() >= watchdog_timeout / 2)
+                        {
+                            notify(NotifyState::Watchdog);
+                            last_ping = Some(Instant::now());
+                        }
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            notify(NotifyState::Stopping);
+            info!("Exiting systemd notify thread.");
+        })?;
+    Ok(hdl)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::systemd::ThreadLiveness;
+
+    #[test]
+    fn test_thread_liveness() {
+        let liveness = ThreadLiveness::default();
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let stall_timeout = Duration::from_secs(5);
+        let first = liveness.register();
+        let second = liveness.register();
+        assert_eq!(liveness.num_stalled(stall_timeout, secs(1)), 0);
+
+        // a thread that stops beating stalls, the other keeps going
+        first.beat_at(secs(4));
+        assert_eq!(liveness.num_stalled(stall_timeout, secs(6)), 1);
+        second.beat_at(secs(6));
+        assert_eq!(liveness.num_stalled(stall_timeout, secs(8)), 0);
+        assert_eq!(liveness.num_stalled(stall_timeout, secs(11)), 2);
+    }
+}
@@ -1,9 +1,11 @@
 use std::{
+    collections::HashMap,
+    path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
-    time::{Duration, Instant, SystemTime},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use arc_swap::{ArcSwap, ArcSwapAny};
@@ -11,33 +13,323 @@ use jito_protos::auth::{
     auth_service_client::AuthServiceClient, GenerateAuthChallengeRequest,
     GenerateAuthTokensRequest, RefreshAccessTokenRequest, Role, Token,
 };
-use prost_types::Timestamp;
-use solana_metrics::datapoint_info;
-use solana_sdk::signature::{Keypair, Signer};
+use log::{error, info, warn};
+use rand::Rng;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
 use thiserror::Error;
 use tokio::{task::JoinHandle, time::sleep};
 use tonic::{
-    metadata::errors::InvalidMetadataValue,
+    metadata::{errors::InvalidMetadataValue, MetadataMap},
     service::Interceptor,
-    transport::{Channel, Endpoint},
-    Request, Status,
+    transport::{Certificate, Channel, ClientTlsConfig, Endpoint},
+    Code, Request, Response, Status,
+};
+
+use crate::{
+    forwarder::ShredMetrics,
+    metrics_backend::datapoint_info,
+    otel::{control_span, traced_async},
+    token_cache_file::{self, SavedToken, SavedTokens},
 };
 
 /// Adds the token to each requests' authorization header.
+/// Auth failures are split by their usual cause, so the message tells operators what to fix
 #[derive(Debug, Error)]
 pub enum BlockEngineConnectionError {
-    #[error("transport error: {0}")]
+    #[error("transport error, check the block engine url and network access to it: {0}")]
     Transport(#[from] tonic::transport::Error),
 
+    /// Connection dropped or timed out mid-request
+    #[error("block engine unavailable, check network access to it: {0}")]
+    Unavailable(Status),
+
+    /// Auth service rejected the keypair's signed challenge, or the keypair isn't allowlisted
+    #[error("auth rejected, check the auth keypair is the one approved for shredstream: {0}")]
+    AuthRejected(Status),
+
+    /// Local clock disagrees with the auth service's by more than [CLOCK_SKEW_THRESHOLD]
+    #[error("clock skew detected: local={local}, server={server}, fix NTP. {reason}")]
+    ClockSkew {
+        local: String,
+        server: String,
+        reason: String,
+    },
+
     #[error("client error: {0}")]
-    Client(#[from] Status),
+    Client(Status),
 
     #[error("deserializing error")]
     Deserialization,
 }
 
+impl From<Status> for BlockEngineConnectionError {
+    fn from(status: Status) -> Self {
+        match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded => Self::Unavailable(status),
+            _ => Self::Client(status),
+        }
+    }
+}
+
 pub type BlockEngineConnectionResult<T> = Result<T, BlockEngineConnectionError>;
 
+/// Default time before expiry to refresh tokens, see `--token-refresh-margin-secs`
+pub const DEFAULT_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+/// Wait between failed refresh attempts
+const TOKEN_REFRESH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Max time the refresh task sleeps before checking for exit
+const TOKEN_REFRESH_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Difference from the auth service's clock that's reported as clock skew.
+/// Well above the `date` header's one second precision and request latency
+pub const CLOCK_SKEW_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Access and refresh tokens from the last auth, per auth service URL, reused when reconnecting.
+/// Keyed per URL since tokens issued by one block engine aren't valid for another.
+#[derive(Clone)]
+pub struct TokenCache {
+    tokens: Arc<Mutex<HashMap<String, (Token, Token)>>>,
+    /// Tokens expiring within this long are refreshed, and not reused when reconnecting
+    refresh_margin: Duration,
+    /// Where `tokens` are saved for the next run, and the keypair pubkey they were issued to
+    file: Option<(PathBuf, String)>,
+    /// Max random delay before the first full auth, taken once so only startup is delayed
+    initial_auth_jitter: Arc<Mutex<Duration>>,
+}
+
+impl Default for TokenCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_REFRESH_MARGIN)
+    }
+}
+
+impl TokenCache {
+    pub fn new(refresh_margin: Duration) -> Self {
+        Self {
+            tokens: Default::default(),
+            refresh_margin,
+            file: None,
+            initial_auth_jitter: Default::default(),
+        }
+    }
+
+    /// Loads tokens saved at `path` for `pubkey` by a previous run, and saves them there after every auth and refresh.
+    /// An unreadable file is logged and overwritten, falling back to a full auth
+    pub fn with_file(mut self, path: PathBuf, pubkey: &Pubkey) -> Self {
+        let pubkey = pubkey.to_string();
+        match token_cache_file::load(&path) {
+            Ok(saved) => {
+                let mut tokens = self.tokens.lock().unwrap();
+                for saved_tokens in saved.into_iter().filter(|saved| saved.pubkey == pubkey) {
+                    tokens.insert(
+                        saved_tokens.auth_url,
+                        (
+                            saved_tokens.access_token.to_token(),
+                            saved_tokens.refresh_token.to_token(),
+                        ),
+                    );
+                }
+                if !tokens.is_empty() {
+                    info!(
+                        "Loaded cached tokens for {} auth urls from {path:?}.",
+                        tokens.len()
+                    );
+                }
+            }
+            Err(e) => warn!("Ignoring token cache file, authenticating instead. {e}"),
+        }
+        self.file = Some((path, pubkey));
+        self
+    }
+
+    /// Waits a random time up to `max_jitter` before the first full auth, so proxies restarted together don't all authenticate at once
+    pub fn with_initial_auth_jitter(self, max_jitter: Duration) -> Self {
+        *self.initial_auth_jitter.lock().unwrap() = max_jitter;
+        self
+    }
+
+    /// Random delay before the first full auth, zero afterwards
+    fn take_initial_auth_jitter(&self) -> Duration {
+        let max_jitter = std::mem::take(&mut *self.initial_auth_jitter.lock().unwrap());
+        if max_jitter.is_zero() {
+            return Duration::ZERO;
+        }
+        rand::thread_rng().gen_range(Duration::ZERO..=max_jitter)
+    }
+
+    /// Returns (access token, refresh token) for `auth_url` if neither expires soon
+    fn get(&self, auth_url: &str, now: SystemTime) -> Option<(Token, Token)> {
+        let tokens = self.tokens.lock().unwrap();
+        let (access_token, refresh_token) = tokens.get(auth_url)?;
+        [access_token, refresh_token]
+            .iter()
+            .all(|token| token_ttl(token, now) >= self.refresh_margin)
+            .then(|| (access_token.clone(), refresh_token.clone()))
+    }
+
+    /// Forgets tokens for `auth_url`, so the next connection authenticates from scratch
+    pub fn remove(&self, auth_url: &str) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if tokens.remove(auth_url).is_some() {
+            self.save(&tokens);
+        }
+    }
+
+    fn insert(&self, auth_url: &str, access_token: Token, refresh_token: Token) {
+        let mut tokens = self.tokens.lock().unwrap();
+        tokens.insert(auth_url.to_string(), (access_token, refresh_token));
+        self.save(&tokens);
+    }
+
+    fn update_access_token(&self, auth_url: &str, access_token: Token) {
+        let mut tokens = self.tokens.lock().unwrap();
+        if let Some((cached_access_token, _refresh_token)) = tokens.get_mut(auth_url) {
+            *cached_access_token = access_token;
+            self.save(&tokens);
+        }
+    }
+
+    /// Writes `tokens` to the cache file, if any. Called with the lock held so writes never interleave
+    fn save(&self, tokens: &HashMap<String, (Token, Token)>) {
+        let Some((path, pubkey)) = &self.file else {
+            return;
+        };
+        let saved = tokens
+            .iter()
+            .filter_map(|(auth_url, (access_token, refresh_token))| {
+                Some(SavedTokens {
+                    auth_url: auth_url.clone(),
+                    pubkey: pubkey.clone(),
+                    access_token: SavedToken::from_token(access_token)?,
+                    refresh_token: SavedToken::from_token(refresh_token)?,
+                })
+            })
+            .collect();
+        if let Err(e) = token_cache_file::save(path, pubkey, saved) {
+            warn!(
+                "Failed to save tokens to {path:?}, the next restart will authenticate again. Error: {e}"
+            );
+        }
+    }
+}
+
+/// Time until the token expires, zero if expired or missing an expiration
+fn token_ttl(token: &Token, now: SystemTime) -> Duration {
+    token
+        .expires_at_utc
+        .clone()
+        .and_then(|expires_at| SystemTime::try_from(expires_at).ok())
+        .and_then(|expires_at| expires_at.duration_since(now).ok())
+        .unwrap_or_default()
+}
+
+/// Errors on tokens without an expiration, which could never be refreshed on time
+fn require_expiration(token: Option<Token>) -> BlockEngineConnectionResult<Token> {
+    token
+        .filter(|token| token.expires_at_utc.is_some())
+        .ok_or(BlockEngineConnectionError::Deserialization)
+}
+
+/// Errors on tokens that expired before they arrived, which only happens when the local clock is ahead of the auth service's
+fn require_unexpired(token: Token, now: SystemTime) -> BlockEngineConnectionResult<Token> {
+    if !token_ttl(&token, now).is_zero() {
+        return Ok(token);
+    }
+    let expires_at = token
+        .expires_at_utc
+        .clone()
+        .and_then(|expires_at| SystemTime::try_from(expires_at).ok())
+        .unwrap_or(UNIX_EPOCH);
+    let err = BlockEngineConnectionError::ClockSkew {
+        local: httpdate::fmt_http_date(now),
+        server: format!("before {}", httpdate::fmt_http_date(expires_at)),
+        reason: "Tokens were already expired when issued.".to_string(),
+    };
+    error!("{err}");
+    Err(err)
+}
+
+/// Time the auth service sent its response, from the `date` header, to the second
+fn server_time(metadata: &MetadataMap) -> Option<SystemTime> {
+    httpdate::parse_http_date(metadata.get("date")?.to_str().ok()?).ok()
+}
+
+fn exceeds_clock_skew(local: SystemTime, server: SystemTime) -> bool {
+    let skew = local
+        .duration_since(server)
+        .or_else(|_| server.duration_since(local))
+        .unwrap_or_default();
+    skew > CLOCK_SKEW_THRESHOLD
+}
+
+/// Classifies a failed challenge or token request, where rejections mean the keypair isn't accepted
+fn auth_error(status: Status) -> BlockEngineConnectionError {
+    match status.code() {
+        Code::PermissionDenied | Code::Unauthenticated => {
+            BlockEngineConnectionError::AuthRejected(status)
+        }
+        _ => status.into(),
+    }
+}
+
+/// Unwraps an auth service response, logging clock skew against its `date` header.
+/// Failures are reported as clock skew when skewed, since tokens are then rejected regardless of the keypair, otherwise by `classify`
+fn auth_response<T>(
+    result: Result<Response<T>, Status>,
+    classify: fn(Status) -> BlockEngineConnectionError,
+) -> BlockEngineConnectionResult<T> {
+    let local = SystemTime::now();
+    let metadata = match &result {
+        Ok(response) => response.metadata(),
+        Err(status) => status.metadata(),
+    };
+    let skewed_server_time =
+        server_time(metadata).filter(|server| exceeds_clock_skew(local, *server));
+    if let Some(server) = skewed_server_time {
+        error!(
+            "Clock skew detected: local={}, server={}, fix NTP.",
+            httpdate::fmt_http_date(local),
+            httpdate::fmt_http_date(server)
+        );
+    }
+    match (result, skewed_server_time) {
+        (Ok(response), _) => Ok(response.into_inner()),
+        (Err(status), Some(server)) => Err(BlockEngineConnectionError::ClockSkew {
+            local: httpdate::fmt_http_date(local),
+            server: httpdate::fmt_http_date(server),
+            reason: format!("Auth failed: {status}"),
+        }),
+        (Err(status), None) => Err(classify(status)),
+    }
+}
+
+/// Next step for the token refresh task
+#[derive(Debug, PartialEq, Eq)]
+enum RefreshAction {
+    /// Re-run the whole auth workflow, since the refresh token expires soon
+    FullAuth,
+    /// Exchange the refresh token for a new access token
+    RefreshAccessToken,
+    /// Both tokens are good for this long before they need refreshing
+    Wait(Duration),
+}
+
+fn next_refresh_action(
+    access_token_ttl: Duration,
+    refresh_token_ttl: Duration,
+    refresh_margin: Duration,
+) -> RefreshAction {
+    if refresh_token_ttl < refresh_margin {
+        RefreshAction::FullAuth
+    } else if access_token_ttl < refresh_margin {
+        RefreshAction::RefreshAccessToken
+    } else {
+        RefreshAction::Wait(access_token_ttl.min(refresh_token_ttl) - refresh_margin)
+    }
+}
+
 /// Manages refreshing the token in a separate thread.
 #[derive(Clone)]
 pub struct ClientInterceptor {
@@ -46,30 +338,53 @@ pub struct ClientInterceptor {
 }
 
 impl ClientInterceptor {
+    /// Reuses tokens cached for `auth_url` if still valid, otherwise authenticates
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         mut auth_service_client: AuthServiceClient<Channel>,
+        auth_url: String,
+        token_cache: TokenCache,
         keypair: Arc<Keypair>,
         role: Role,
         service_name: String,
+        metrics: Arc<ShredMetrics>,
         exit: Arc<AtomicBool>,
     ) -> BlockEngineConnectionResult<(Self, JoinHandle<()>)> {
-        let (
-            Token {
-                value: access_token,
-                expires_at_utc: access_token_expiration,
-            },
-            refresh_token,
-        ) = Self::auth(&mut auth_service_client, &keypair, role).await?;
-        let bearer_token = Arc::new(ArcSwap::from_pointee(access_token));
+        // taken even when cached tokens are reused, so only a startup without them is delayed
+        let jitter = token_cache.take_initial_auth_jitter();
+        let (access_token, refresh_token) = match token_cache.get(&auth_url, SystemTime::now()) {
+            Some(tokens) => tokens,
+            None => {
+                if !jitter.is_zero() {
+                    info!("No cached tokens for {auth_url}, authenticating in {jitter:?}.");
+                    sleep(jitter).await;
+                }
+                let (access_token, refresh_token) = traced_async(
+                    control_span!(
+                        "auth",
+                        auth_url = auth_url.as_str(),
+                        auth_type = "full_auth"
+                    ),
+                    Self::auth(&mut auth_service_client, &keypair, role),
+                )
+                .await?;
+                token_cache.insert(&auth_url, access_token.clone(), refresh_token.clone());
+                (access_token, refresh_token)
+            }
+        };
+        let bearer_token = Arc::new(ArcSwap::from_pointee(access_token.value.clone()));
 
         let refresh_thread_handle = Self::spawn_token_refresh_thread(
             auth_service_client,
+            auth_url,
+            token_cache,
             bearer_token.clone(),
+            access_token,
             refresh_token,
-            access_token_expiration.ok_or(BlockEngineConnectionError::Deserialization)?,
             keypair,
             role,
             service_name,
+            metrics,
             exit,
         );
 
@@ -83,119 +398,164 @@ impl ClientInterceptor {
         role: Role,
     ) -> BlockEngineConnectionResult<(Token, Token)> {
         let pubkey_vec = keypair.pubkey().as_ref().to_vec();
-        let challenge_resp = auth_service_client
-            .generate_auth_challenge(GenerateAuthChallengeRequest {
-                role: role as i32,
-                pubkey: pubkey_vec.clone(),
-            })
-            .await?
-            .into_inner();
+        let challenge_resp = auth_response(
+            auth_service_client
+                .generate_auth_challenge(GenerateAuthChallengeRequest {
+                    role: role as i32,
+                    pubkey: pubkey_vec.clone(),
+                })
+                .await,
+            auth_error,
+        )?;
         let challenge = format!("{}-{}", keypair.pubkey(), challenge_resp.challenge);
         let signed_challenge = keypair.sign_message(challenge.as_bytes()).as_ref().to_vec();
 
-        let tokens = auth_service_client
-            .generate_auth_tokens(GenerateAuthTokensRequest {
-                challenge,
-                client_pubkey: pubkey_vec,
-                signed_challenge,
-            })
-            .await?
-            .into_inner();
+        let tokens = auth_response(
+            auth_service_client
+                .generate_auth_tokens(GenerateAuthTokensRequest {
+                    challenge,
+                    client_pubkey: pubkey_vec,
+                    signed_challenge,
+                })
+                .await,
+            auth_error,
+        )?;
 
+        let now = SystemTime::now();
         Ok((
-            tokens
-                .access_token
-                .ok_or(BlockEngineConnectionError::Deserialization)?,
-            tokens
-                .refresh_token
-                .ok_or(BlockEngineConnectionError::Deserialization)?,
+            require_unexpired(require_expiration(tokens.access_token)?, now)?,
+            require_unexpired(require_expiration(tokens.refresh_token)?, now)?,
         ))
     }
 
-    /// Periodically updates `bearer_token`
+    /// Returns a new access token
+    async fn refresh_access_token(
+        auth_service_client: &mut AuthServiceClient<Channel>,
+        refresh_token: &Token,
+    ) -> BlockEngineConnectionResult<Token> {
+        let refresh_resp = auth_response(
+            auth_service_client
+                .refresh_access_token(RefreshAccessTokenRequest {
+                    refresh_token: refresh_token.value.clone(),
+                })
+                .await,
+            BlockEngineConnectionError::from,
+        )?;
+        require_unexpired(
+            require_expiration(refresh_resp.access_token)?,
+            SystemTime::now(),
+        )
+    }
+
+    /// Refreshes tokens `refresh_margin` before they expire, swapping the new access token into `bearer_token`
+    /// so requests in flight keep using a valid token. Failed refreshes are retried, falling back to a full auth.
     #[allow(clippy::too_many_arguments)]
     fn spawn_token_refresh_thread(
         mut auth_service_client: AuthServiceClient<Channel>,
+        auth_url: String,
+        token_cache: TokenCache,
         bearer_token: Arc<ArcSwap<String>>,
+        initial_access_token: Token,
         initial_refresh_token: Token,
-        initial_access_token_expiration: Timestamp,
         keypair: Arc<Keypair>,
         role: Role,
         service_name: String,
+        metrics: Arc<ShredMetrics>,
         exit: Arc<AtomicBool>,
     ) -> JoinHandle<()> {
         tokio::spawn(async move {
             // refresh token gets us an access token. access token is short-lived
+            let mut access_token = initial_access_token;
             let mut refresh_token = initial_refresh_token;
-            let mut access_token_expiration = initial_access_token_expiration;
+            // the refresh token may have been revoked, so fall back to a full auth after a failed refresh
+            let mut force_full_auth = false;
 
             while !exit.load(Ordering::Relaxed) {
                 let now = SystemTime::now();
+                let access_token_ttl = token_ttl(&access_token, now);
+                metrics
+                    .access_token_ttl_secs
+                    .store(access_token_ttl.as_secs(), Ordering::Relaxed);
+                let action = match force_full_auth {
+                    true => RefreshAction::FullAuth,
+                    false => next_refresh_action(
+                        access_token_ttl,
+                        token_ttl(&refresh_token, now),
+                        token_cache.refresh_margin,
+                    ),
+                };
 
-                let refresh_token_ttl =
-                    SystemTime::try_from(refresh_token.expires_at_utc.as_ref().unwrap().clone())
-                        .unwrap()
-                        .duration_since(now)
-                        .unwrap_or_default();
-                // re-run entire auth workflow if refresh token expiring soon
-                if refresh_token_ttl < Duration::from_secs(5 * 60) {
-                    let start = Instant::now();
-                    let is_error = {
-                        if let Ok((new_access_token, new_refresh_token)) =
-                            Self::auth(&mut auth_service_client, &keypair, role).await
-                        {
-                            bearer_token.store(Arc::new(new_access_token.value));
-                            access_token_expiration = new_access_token.expires_at_utc.unwrap();
+                let start = Instant::now();
+                let (auth_type, result) = match action {
+                    RefreshAction::Wait(wait) => {
+                        sleep(wait.min(TOKEN_REFRESH_POLL_INTERVAL)).await;
+                        continue;
+                    }
+                    RefreshAction::FullAuth => (
+                        "full_auth",
+                        traced_async(
+                            control_span!(
+                                "auth",
+                                auth_url = auth_url.as_str(),
+                                auth_type = "full_auth"
+                            ),
+                            Self::auth(&mut auth_service_client, &keypair, role),
+                        )
+                        .await
+                        .map(|(new_access_token, new_refresh_token)| {
+                            token_cache.insert(
+                                &auth_url,
+                                new_access_token.clone(),
+                                new_refresh_token.clone(),
+                            );
                             refresh_token = new_refresh_token;
-                            false
-                        } else {
-                            true
-                        }
-                    };
-                    datapoint_info!(
-                        "token_auth",
-                        ("auth_type", "full_auth", String),
-                        ("service", service_name, String),
-                        ("is_error", is_error, bool),
-                        ("latency_us", start.elapsed().as_micros(), i64),
-                    );
-                    continue;
+                            new_access_token
+                        }),
+                    ),
+                    RefreshAction::RefreshAccessToken => (
+                        "access_token",
+                        traced_async(
+                            control_span!(
+                                "auth",
+                                auth_url = auth_url.as_str(),
+                                auth_type = "access_token"
+                            ),
+                            Self::refresh_access_token(&mut auth_service_client, &refresh_token),
+                        )
+                        .await
+                        .inspect(|new_access_token| {
+                            token_cache.update_access_token(&auth_url, new_access_token.clone())
+                        }),
+                    ),
+                };
+                metrics
+                    .token_refresh_attempts_cumulative
+                    .fetch_add(1, Ordering::Relaxed);
+                datapoint_info!(
+                    "token_auth",
+                    ("auth_type", auth_type, String),
+                    ("service", service_name, String),
+                    ("is_error", result.is_err(), bool),
+                    ("latency_us", start.elapsed().as_micros(), i64),
+                    ("access_token_ttl_secs", access_token_ttl.as_secs(), i64),
+                );
+                match result {
+                    Ok(new_access_token) => {
+                        bearer_token.store(Arc::new(new_access_token.value.clone()));
+                        access_token = new_access_token;
+                        force_full_auth = false;
+                    }
+                    Err(e) => {
+                        metrics
+                            .token_refresh_failures_cumulative
+                            .fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "Failed to refresh {auth_type} token for {auth_url}, retrying in {TOKEN_REFRESH_RETRY_INTERVAL:?}. Access token expires in {access_token_ttl:?}. Error: {e}"
+                        );
+                        force_full_auth = true;
+                        sleep(TOKEN_REFRESH_RETRY_INTERVAL).await;
+                    }
                 }
-
-                let access_token_ttl = SystemTime::try_from(access_token_expiration.clone())
-                    .unwrap()
-                    .duration_since(now)
-                    .unwrap_or_default();
-                // re-up the access token if it expires soon
-                if access_token_ttl < Duration::from_secs(5 * 60) {
-                    let start = Instant::now();
-                    let is_error = {
-                        if let Ok(refresh_resp) = auth_service_client
-                            .refresh_access_token(RefreshAccessTokenRequest {
-                                refresh_token: refresh_token.value.clone(),
-                            })
-                            .await
-                        {
-                            let access_token = refresh_resp.into_inner().access_token.unwrap();
-                            bearer_token.store(Arc::new(access_token.value.clone()));
-                            access_token_expiration = access_token.expires_at_utc.unwrap();
-                            false
-                        } else {
-                            true
-                        }
-                    };
-
-                    datapoint_info!(
-                        "token_auth",
-                        ("auth_type", "access_token", String),
-                        ("service", service_name, String),
-                        ("is_error", is_error, bool),
-                        ("latency_us", start.elapsed().as_micros(), i64),
-                    );
-                    continue;
-                }
-
-                sleep(Duration::from_secs(5)).await;
             }
         })
     }
@@ -218,12 +578,422 @@ impl Interceptor for ClientInterceptor {
     }
 }
 
-pub async fn create_grpc_channel(url: String) -> BlockEngineConnectionResult<Channel> {
-    let endpoint = match url.starts_with("https") {
-        true => Endpoint::from_shared(url)
-            .map_err(BlockEngineConnectionError::Transport)?
-            .tls_config(tonic::transport::ClientTlsConfig::new())?,
-        false => Endpoint::from_shared(url).map_err(BlockEngineConnectionError::Transport)?,
-    };
+/// How to connect to the block engine and auth service. The default matches tonic's: no connect timeout, no keepalive pings, and the system's CA certificates
+#[derive(Clone, Debug, Default)]
+pub struct GrpcChannelConfig {
+    pub connect_timeout: Option<Duration>,
+    /// Pings are sent even while idle, so a connection dropped silently, eg. by a firewall, fails instead of hanging
+    pub keepalive_interval: Option<Duration>,
+    /// How long to wait for a ping to be acknowledged, tonic's 20s if unset
+    pub keepalive_timeout: Option<Duration>,
+    /// Trusted for `https` urls in addition to the system's CA certificates
+    pub tls_ca_cert: Option<Certificate>,
+}
+
+pub async fn create_grpc_channel(
+    url: String,
+    config: &GrpcChannelConfig,
+) -> BlockEngineConnectionResult<Channel> {
+    let mut endpoint = Endpoint::from_shared(url).map_err(BlockEngineConnectionError::Transport)?;
+    if endpoint.uri().scheme_str() == Some("https") {
+        let mut tls_config = ClientTlsConfig::new();
+        if let Some(ca_cert) = &config.tls_ca_cert {
+            tls_config = tls_config.ca_certificate(ca_cert.clone());
+        }
+        endpoint = endpoint.tls_config(tls_config)?;
+    }
+    if let Some(connect_timeout) = config.connect_timeout {
+        endpoint = endpoint.connect_timeout(connect_timeout);
+    }
+    if let Some(keepalive_interval) = config.keepalive_interval {
+        endpoint = endpoint
+            .http2_keep_alive_interval(keepalive_interval)
+            .keep_alive_while_idle(true);
+    }
+    if let Some(keepalive_timeout) = config.keepalive_timeout {
+        endpoint = endpoint.keep_alive_timeout(keepalive_timeout);
+    }
     Ok(endpoint.connect().await?)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        sync::Arc,
+        time::{Duration, SystemTime},
+    };
+
+    use jito_protos::auth::{
+        auth_service_client::AuthServiceClient,
+        auth_service_server::{AuthService, AuthServiceServer},
+        GenerateAuthChallengeRequest, GenerateAuthChallengeResponse, GenerateAuthTokensRequest,
+        GenerateAuthTokensResponse, RefreshAccessTokenRequest, RefreshAccessTokenResponse, Role,
+        Token,
+    };
+    use solana_sdk::{pubkey::Pubkey, signature::Keypair};
+    use tokio::{net::TcpListener, runtime::Runtime};
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::{
+        metadata::{MetadataMap, MetadataValue},
+        transport::{Certificate, Identity, Server, ServerTlsConfig},
+        Code, Request, Response, Status,
+    };
+
+    use crate::{
+        token_authenticator::{
+            create_grpc_channel, exceeds_clock_skew, next_refresh_action, server_time,
+            BlockEngineConnectionError, BlockEngineConnectionResult, ClientInterceptor,
+            GrpcChannelConfig, RefreshAction, TokenCache,
+        },
+        tunnel::tests::{TEST_CERT, TEST_KEY},
+    };
+
+    fn new_token(value: &str, expires_at: SystemTime) -> Token {
+        Token {
+            value: value.to_string(),
+            expires_at_utc: Some(expires_at.into()),
+        }
+    }
+
+    /// Auth service that fails token requests with `failure`, or issues tokens expiring at `expires_at`.
+    /// Responses carry a `date` header of `server_time`, when set
+    struct MockAuthService {
+        failure: Option<Code>,
+        server_time: Option<SystemTime>,
+        expires_at: SystemTime,
+    }
+
+    impl MockAuthService {
+        fn metadata(&self) -> MetadataMap {
+            let mut metadata = MetadataMap::new();
+            if let Some(server_time) = self.server_time {
+                let date = httpdate::fmt_http_date(server_time);
+                metadata.insert("date", MetadataValue::try_from(date).unwrap());
+            }
+            metadata
+        }
+
+        fn respond<T>(&self, message: T) -> Result<Response<T>, Status> {
+            if let Some(code) = self.failure {
+                return Err(Status::with_metadata(code, "mock failure", self.metadata()));
+            }
+            let mut response = Response::new(message);
+            *response.metadata_mut() = self.metadata();
+            Ok(response)
+        }
+    }
+
+    #[tonic::async_trait]
+    impl AuthService for MockAuthService {
+        async fn generate_auth_challenge(
+            &self,
+            _request: Request<GenerateAuthChallengeRequest>,
+        ) -> Result<Response<GenerateAuthChallengeResponse>, Status> {
+            Ok(Response::new(GenerateAuthChallengeResponse {
+                challenge: "challenge".to_string(),
+            }))
+        }
+
+        async fn generate_auth_tokens(
+            &self,
+            _request: Request<GenerateAuthTokensRequest>,
+        ) -> Result<Response<GenerateAuthTokensResponse>, Status> {
+            self.respond(GenerateAuthTokensResponse {
+                access_token: Some(new_token("access", self.expires_at)),
+                refresh_token: Some(new_token("refresh", self.expires_at)),
+            })
+        }
+
+        async fn refresh_access_token(
+            &self,
+            _request: Request<RefreshAccessTokenRequest>,
+        ) -> Result<Response<RefreshAccessTokenResponse>, Status> {
+            self.respond(RefreshAccessTokenResponse {
+                access_token: Some(new_token("access", self.expires_at)),
+            })
+        }
+    }
+
+    /// Runs the auth workflow, then a token refresh, against `auth_service`
+    fn authenticate(
+        auth_service: MockAuthService,
+    ) -> (
+        BlockEngineConnectionResult<(Token, Token)>,
+        BlockEngineConnectionResult<Token>,
+    ) {
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            tokio::spawn(
+                Server::builder()
+                    .add_service(AuthServiceServer::new(auth_service))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+            let mut client = AuthServiceClient::new(
+                create_grpc_channel(url, &GrpcChannelConfig::default())
+                    .await
+                    .unwrap(),
+            );
+            let keypair = Arc::new(Keypair::new());
+            let tokens =
+                ClientInterceptor::auth(&mut client, &keypair, Role::ShredstreamSubscriber).await;
+            let refreshed = ClientInterceptor::refresh_access_token(
+                &mut client,
+                &new_token("refresh", SystemTime::now()),
+            )
+            .await;
+            (tokens, refreshed)
+        })
+    }
+
+    #[test]
+    fn test_auth_errors() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+
+        let (tokens, refreshed) = authenticate(MockAuthService {
+            failure: None,
+            server_time: Some(now),
+            expires_at: now + hour,
+        });
+        assert_eq!(tokens.unwrap().0.value, "access");
+        assert_eq!(refreshed.unwrap().value, "access");
+
+        // wrong keypair or not allowlisted
+        for code in [Code::PermissionDenied, Code::Unauthenticated] {
+            let (tokens, refreshed) = authenticate(MockAuthService {
+                failure: Some(code),
+                server_time: Some(now),
+                expires_at: now + hour,
+            });
+            assert!(matches!(
+                tokens,
+                Err(BlockEngineConnectionError::AuthRejected(_))
+            ));
+            // refresh tokens can expire without anything being wrong with the keypair
+            assert!(matches!(
+                refreshed,
+                Err(BlockEngineConnectionError::Client(_))
+            ));
+        }
+
+        let (tokens, _) = authenticate(MockAuthService {
+            failure: Some(Code::Unavailable),
+            server_time: None,
+            expires_at: now + hour,
+        });
+        assert!(matches!(
+            tokens,
+            Err(BlockEngineConnectionError::Unavailable(_))
+        ));
+
+        // rejections are blamed on the clock when it's off from the auth service's
+        let (tokens, refreshed) = authenticate(MockAuthService {
+            failure: Some(Code::PermissionDenied),
+            server_time: Some(now - hour),
+            expires_at: now + hour,
+        });
+        for err in [tokens.unwrap_err(), refreshed.unwrap_err()] {
+            assert!(matches!(err, BlockEngineConnectionError::ClockSkew { .. }));
+            assert!(err.to_string().contains("fix NTP"), "{err}");
+        }
+
+        // tokens already expired when issued
+        let (tokens, refreshed) = authenticate(MockAuthService {
+            failure: None,
+            server_time: None,
+            expires_at: now - hour,
+        });
+        assert!(matches!(
+            tokens,
+            Err(BlockEngineConnectionError::ClockSkew { .. })
+        ));
+        assert!(matches!(
+            refreshed,
+            Err(BlockEngineConnectionError::ClockSkew { .. })
+        ));
+    }
+
+    #[test]
+    fn test_transport_error() {
+        let runtime = Runtime::new().unwrap();
+        let addr = runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        });
+        assert!(matches!(
+            runtime.block_on(create_grpc_channel(
+                format!("http://{addr}"),
+                &GrpcChannelConfig::default()
+            )),
+            Err(BlockEngineConnectionError::Transport(_))
+        ));
+    }
+
+    #[test]
+    fn test_tls_ca_cert() {
+        let runtime = Runtime::new().unwrap();
+        let url = runtime.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!(
+                "https://localhost:{}",
+                listener.local_addr().unwrap().port()
+            );
+            tokio::spawn(
+                Server::builder()
+                    .tls_config(
+                        ServerTlsConfig::new().identity(Identity::from_pem(TEST_CERT, TEST_KEY)),
+                    )
+                    .unwrap()
+                    .add_service(AuthServiceServer::new(MockAuthService {
+                        failure: None,
+                        server_time: None,
+                        expires_at: SystemTime::now() + Duration::from_secs(600),
+                    }))
+                    .serve_with_incoming(TcpListenerStream::new(listener)),
+            );
+            url
+        });
+
+        // self-signed, so only trusted once configured
+        assert!(matches!(
+            runtime.block_on(create_grpc_channel(
+                url.clone(),
+                &GrpcChannelConfig::default()
+            )),
+            Err(BlockEngineConnectionError::Transport(_))
+        ));
+        let config = GrpcChannelConfig {
+            tls_ca_cert: Some(Certificate::from_pem(TEST_CERT)),
+            ..GrpcChannelConfig::default()
+        };
+        runtime.block_on(async {
+            let mut client =
+                AuthServiceClient::new(create_grpc_channel(url, &config).await.unwrap());
+            ClientInterceptor::auth(
+                &mut client,
+                &Arc::new(Keypair::new()),
+                Role::ShredstreamSubscriber,
+            )
+            .await
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_clock_skew() {
+        let now = SystemTime::now();
+        let minute = Duration::from_secs(60);
+        assert!(!exceeds_clock_skew(now, now + minute / 2));
+        assert!(exceeds_clock_skew(now, now + minute));
+        assert!(exceeds_clock_skew(now + minute, now));
+
+        let mut metadata = MetadataMap::new();
+        assert_eq!(server_time(&metadata), None);
+        metadata.insert(
+            "date",
+            MetadataValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+        assert_eq!(
+            server_time(&metadata),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(784_111_777))
+        );
+    }
+
+    #[test]
+    fn test_token_cache() {
+        let now = SystemTime::now();
+        let cache = TokenCache::default();
+        let hour = Duration::from_secs(60 * 60);
+        cache.insert(
+            "https://primary",
+            new_token("access", now + hour),
+            new_token("refresh", now + 24 * hour),
+        );
+
+        let (access_token, refresh_token) = cache.get("https://primary", now).unwrap();
+        assert_eq!(access_token.value, "access");
+        assert_eq!(refresh_token.value, "refresh");
+        // not reused for a different block engine
+        assert!(cache.get("https://secondary", now).is_none());
+        // not reused once expiring soon
+        assert!(cache.get("https://primary", now + hour).is_none());
+
+        cache.update_access_token("https://primary", new_token("access2", now + 2 * hour));
+        assert_eq!(
+            cache.get("https://primary", now + hour).unwrap().0.value,
+            "access2"
+        );
+
+        cache.remove("https://primary");
+        assert!(cache.get("https://primary", now).is_none());
+    }
+
+    #[test]
+    fn test_token_cache_file() {
+        let path = std::env::temp_dir().join(format!(
+            "test_token_authenticator_cache_{}.json",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let pubkey = Pubkey::new_unique();
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(60 * 60);
+
+        let cache = TokenCache::default().with_file(path.clone(), &pubkey);
+        cache.insert(
+            "https://primary",
+            new_token("access", now + hour),
+            new_token("refresh", now + 24 * hour),
+        );
+        cache.insert(
+            "https://secondary",
+            new_token("expiring", now + Duration::from_secs(60)),
+            new_token("refresh", now + 24 * hour),
+        );
+
+        // a restart reuses unexpired tokens
+        let restarted = TokenCache::default().with_file(path.clone(), &pubkey);
+        let (access_token, refresh_token) = restarted.get("https://primary", now).unwrap();
+        assert_eq!(access_token.value, "access");
+        assert_eq!(refresh_token.value, "refresh");
+        assert!(restarted.get("https://secondary", now).is_none());
+        // but not another keypair's
+        let other = TokenCache::default().with_file(path.clone(), &Pubkey::new_unique());
+        assert!(other.get("https://primary", now).is_none());
+
+        restarted.remove("https://primary");
+        let restarted = TokenCache::default().with_file(path.clone(), &pubkey);
+        assert!(restarted.get("https://primary", now).is_none());
+        fs::remove_file(&path).unwrap();
+
+        let cache = TokenCache::default().with_initial_auth_jitter(Duration::from_millis(100));
+        assert!(cache.take_initial_auth_jitter() <= Duration::from_millis(100));
+        assert_eq!(cache.take_initial_auth_jitter(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_next_refresh_action() {
+        let minute = Duration::from_secs(60);
+        let margin = 5 * minute;
+        assert_eq!(
+            next_refresh_action(30 * minute, 24 * 60 * minute, margin),
+            RefreshAction::Wait(25 * minute)
+        );
+        assert_eq!(
+            next_refresh_action(4 * minute, 24 * 60 * minute, margin),
+            RefreshAction::RefreshAccessToken
+        );
+        // a new refresh token also gets a new access token
+        assert_eq!(
+            next_refresh_action(4 * minute, 4 * minute, margin),
+            RefreshAction::FullAuth
+        );
+        assert_eq!(
+            next_refresh_action(Duration::ZERO, Duration::ZERO, margin),
+            RefreshAction::FullAuth
+        );
+    }
+}
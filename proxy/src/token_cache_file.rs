@@ -0,0 +1,160 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use jito_protos::auth::Token;
+use serde::{Deserialize, Serialize};
+
+/// Tokens from one auth, saved so a restart reuses them instead of authenticating again.
+/// Keyed by auth url and keypair pubkey, since tokens are only valid for the block engine and keypair that got them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedTokens {
+    pub auth_url: String,
+    pub pubkey: String,
+    pub access_token: SavedToken,
+    pub refresh_token: SavedToken,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedToken {
+    pub value: String,
+    pub expires_at_unix_secs: u64,
+}
+
+impl SavedToken {
+    /// None for tokens without an expiration, which are never cached
+    pub fn from_token(token: &Token) -> Option<Self> {
+        let expires_at = SystemTime::try_from(token.expires_at_utc.clone()?).ok()?;
+        Some(Self {
+            value: token.value.clone(),
+            expires_at_unix_secs: expires_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+
+    pub fn to_token(&self) -> Token {
+        Token {
+            value: self.value.clone(),
+            expires_at_utc: Some(
+                (UNIX_EPOCH + Duration::from_secs(self.expires_at_unix_secs)).into(),
+            ),
+        }
+    }
+}
+
+/// Returns every entry saved at `path`, empty if nothing was saved. Errors if the file is unreadable or corrupt
+pub fn load(path: &Path) -> Result<Vec<SavedTokens>, String> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {path:?}: {e}")),
+    };
+    serde_json::from_slice(&bytes).map_err(|e| format!("Corrupt {path:?}: {e}"))
+}
+
+/// Replaces the entries for `pubkey` with `saved`, keeping other keypairs' entries so proxies can share a file.
+/// Written to a temp file readable only by the owner, then renamed over `path`
+pub fn save(path: &Path, pubkey: &str, saved: Vec<SavedTokens>) -> io::Result<()> {
+    // a corrupt file is overwritten, since it can't be reused anyway
+    let mut entries = load(path).unwrap_or_default();
+    entries.retain(|entry| entry.pubkey != pubkey);
+    entries.extend(saved);
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let json = serde_json::to_vec(&entries).map_err(io::Error::other)?;
+    // the mode only applies when creating, so never reuse a leftover temp file
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(&tmp_path)?;
+    file.write_all(&json)?;
+    file.sync_all()?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use jito_protos::auth::Token;
+
+    use crate::token_cache_file::{load, save, SavedToken, SavedTokens};
+
+    fn saved_tokens(auth_url: &str, pubkey: &str, value: &str) -> SavedTokens {
+        SavedTokens {
+            auth_url: auth_url.to_string(),
+            pubkey: pubkey.to_string(),
+            access_token: SavedToken {
+                value: format!("{value}-access"),
+                expires_at_unix_secs: 1_700_000_000,
+            },
+            refresh_token: SavedToken {
+                value: format!("{value}-refresh"),
+                expires_at_unix_secs: 1_700_086_400,
+            },
+        }
+    }
+
+    #[test]
+    fn test_saved_token() {
+        let expires_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let token = Token {
+            value: "access".to_string(),
+            expires_at_utc: Some(expires_at.into()),
+        };
+        let saved = SavedToken::from_token(&token).unwrap();
+        assert_eq!(saved.expires_at_unix_secs, 1_700_000_000);
+        assert_eq!(saved.to_token(), token);
+        assert_eq!(
+            SavedToken::from_token(&Token {
+                value: "access".to_string(),
+                expires_at_utc: None,
+            }),
+            None
+        );
+        assert!(SystemTime::try_from(saved.to_token().expires_at_utc.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path =
+            std::env::temp_dir().join(format!("test_token_cache_{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(load(&path), Ok(Vec::new()));
+
+        let first = saved_tokens("https://primary", "pubkey1", "first");
+        let other = saved_tokens("https://primary", "pubkey2", "other");
+        save(&path, "pubkey1", vec![first.clone()]).unwrap();
+        save(&path, "pubkey2", vec![other.clone()]).unwrap();
+        assert_eq!(load(&path), Ok(vec![first, other.clone()]));
+
+        // replaces only this keypair's entries
+        let second = saved_tokens("https://secondary", "pubkey1", "second");
+        save(&path, "pubkey1", vec![second.clone()]).unwrap();
+        assert_eq!(load(&path), Ok(vec![other, second.clone()]));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(&path, "[{\"auth_url\":").unwrap();
+        assert!(load(&path).unwrap_err().starts_with("Corrupt"));
+        // overwrites the corrupt file
+        save(&path, "pubkey1", vec![second.clone()]).unwrap();
+        assert_eq!(load(&path), Ok(vec![second]));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, ErrorKind},
+    net::{Ipv6Addr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use log::{info, warn};
+
+use crate::{
+    forwarder::{splitmix64_bytes, ShredMetrics, ShredSink, Transport},
+    rate_limit::split_dest_options,
+};
+
+/// Prefix marking a destination in `dest-ip-ports` as a unix datagram socket, eg. `unix:///run/consumer.sock`,
+/// or `unix://@consumer` for the abstract namespace
+pub const UNIX_SCHEME: &str = "unix://";
+
+const MIN_RECONNECT_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(10);
+
+/// Where a `unix://` destination's socket is bound
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum UnixDest {
+    Path(PathBuf),
+    /// Linux abstract namespace, which isn't tied to a file so needs no cleanup when the consumer exits
+    Abstract(String),
+}
+
+impl fmt::Display for UnixDest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnixDest::Path(path) => write!(f, "{UNIX_SCHEME}{}", path.display()),
+            UnixDest::Abstract(name) => write!(f, "{UNIX_SCHEME}@{name}"),
+        }
+    }
+}
+
+/// Returns the socket a `dest-ip-ports` entry is sent to, or None if it isn't a unix destination or names no socket
+pub fn parse_unix_dest(hostname_port: &str) -> Option<UnixDest> {
+    let (address, _options) = split_dest_options(hostname_port);
+    let address = address.strip_prefix(UNIX_SCHEME)?;
+    match address.strip_prefix('@') {
+        Some(name) if !name.is_empty() => Some(UnixDest::Abstract(name.to_string())),
+        Some(_) => None,
+        None if address.is_empty() => None,
+        None => Some(UnixDest::Path(PathBuf::from(address))),
+    }
+}
+
+/// Stands in for a unix destination wherever destinations are keyed by address, eg. in metrics and the union.
+/// Derived from the socket so it's stable across restarts, within the discard-only `100::/64` prefix so it's never a real peer.
+/// Hashed with splitmix64 rather than std's hasher, whose output may change between releases
+pub fn unix_dest_addr(unix_dest: &UnixDest) -> SocketAddr {
+    // the low bit of the seed keeps a path and an abstract name with the same bytes apart
+    let (bytes, kind) = match unix_dest {
+        UnixDest::Path(path) => (path.as_os_str().as_encoded_bytes(), 0),
+        UnixDest::Abstract(name) => (name.as_bytes(), 1),
+    };
+    let hash = splitmix64_bytes(bytes, ((bytes.len() as u64) << 1) | kind);
+    let ip = Ipv6Addr::from((0x0100_u128 << 112) | hash as u128);
+    SocketAddr::new(ip.into(), 0)
+}
+
+/// Unix datagram sockets only exist on unix, elsewhere `unix://` destinations are rejected when parsing `dest-ip-ports`
+/// and never connect
+#[cfg(not(unix))]
+enum UnixDatagram {}
+
+#[cfg(not(unix))]
+impl UnixDatagram {
+    fn send(&self, _buf: &[u8]) -> io::Result<usize> {
+        match *self {}
+    }
+}
+
+/// Connected socket for a destination, reconnected with backoff once the consumer goes away
+struct UnixConnection {
+    socket: Option<Arc<UnixDatagram>>,
+    next_connect: Instant,
+    backoff: Duration,
+}
+
+/// Forwards packets to `unix://` destinations, skipping the loopback UDP stack for consumers on the same host.
+/// Sockets are shared across forwarder threads and non-blocking, so a consumer that falls behind drops packets instead of stalling forwarding
+pub struct UnixSink {
+    /// Shared with [crate::forwarder::DestinationSources], which updates it with the union
+    unix_dests: Arc<ArcSwap<HashMap<SocketAddr, UnixDest>>>,
+    connections: DashMap<SocketAddr, Arc<Mutex<UnixConnection>>>,
+    metrics: Arc<ShredMetrics>,
+}
+
+impl UnixSink {
+    pub fn new(
+        unix_dests: Arc<ArcSwap<HashMap<SocketAddr, UnixDest>>>,
+        metrics: Arc<ShredMetrics>,
+    ) -> Self {
+        Self {
+            unix_dests,
+            connections: DashMap::new(),
+            metrics,
+        }
+    }
+
+    /// Closes sockets of destinations no longer forwarded to
+    pub fn retain_destinations(&self, unix_dests: &HashMap<SocketAddr, UnixDest>) {
+        self.connections
+            .retain(|addr, _| unix_dests.contains_key(addr));
+    }
+
+    /// Returns the connected socket for `dest`, connecting if due. None while the consumer is unreachable
+    fn socket(&self, dest: SocketAddr, unix_dest: &UnixDest) -> Option<Arc<UnixDatagram>> {
+        // clone so the map isn't locked while sending
+        let connection = self
+            .connections
+            .entry(dest)
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(UnixConnection {
+                    socket: None,
+                    next_connect: Instant::now(),
+                    backoff: MIN_RECONNECT_BACKOFF,
+                }))
+            })
+            .clone();
+        let mut connection = connection.lock().unwrap();
+        if connection.socket.is_none() && Instant::now() >= connection.next_connect {
+            match connect(unix_dest) {
+                Ok(socket) => {
+                    info!("Connected to {unix_dest}.");
+                    connection.socket = Some(Arc::new(socket));
+                    connection.backoff = MIN_RECONNECT_BACKOFF;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to connect to {unix_dest}, retrying in {:?}. Error: {e}",
+                        connection.backoff
+                    );
+                    connection.next_connect = Instant::now() + connection.backoff;
+                    connection.backoff = (connection.backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                }
+            }
+        }
+        connection.socket.clone()
+    }
+
+    /// Drops `socket` so the next send reconnects, unless another thread already replaced it
+    fn disconnect(&self, dest: SocketAddr, socket: &Arc<UnixDatagram>) {
+        if let Some(connection) = self.connections.get(&dest) {
+            let mut connection = connection.lock().unwrap();
+            if connection
+                .socket
+                .as_ref()
+                .is_some_and(|current| Arc::ptr_eq(current, socket))
+            {
+                connection.socket = None;
+            }
+        }
+    }
+}
+
+impl ShredSink for UnixSink {
+    fn send(&self, dest: SocketAddr, packets: &[&[u8]]) {
+        if packets.is_empty() {
+            return;
+        }
+        let Some(unix_dest) = self.unix_dests.load().get(&dest).cloned() else {
+            self.metrics
+                .record_forward(dest, Transport::Unix, 0, packets.len() as u64);
+            return;
+        };
+        let Some(socket) = self.socket(dest, &unix_dest) else {
+            self.metrics
+                .record_forward(dest, Transport::Unix, 0, packets.len() as u64);
+            return;
+        };
+        let mut num_sent = 0;
+        for packet in packets {
+            match socket.send(packet) {
+                Ok(_) => num_sent += 1,
+                // the consumer's receive buffer is full, drop the rest rather than stall other destinations
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // the consumer exited or restarted, recreating its socket
+                Err(e) => {
+                    warn!("Failed to send to {unix_dest}, reconnecting. Error: {e}");
+                    self.disconnect(dest, &socket);
+                    break;
+                }
+            }
+        }
+        self.metrics.record_forward(
+            dest,
+            Transport::Unix,
+            num_sent as u64,
+            (packets.len() - num_sent) as u64,
+        );
+    }
+}
+
+#[cfg(unix)]
+fn connect(unix_dest: &UnixDest) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.set_nonblocking(true)?;
+    match unix_dest {
+        UnixDest::Path(path) => socket.connect(path)?,
+        #[cfg(target_os = "linux")]
+        UnixDest::Abstract(name) => {
+            use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+            socket.connect_addr(&SocketAddr::from_abstract_name(name)?)?
+        }
+        #[cfg(not(target_os = "linux"))]
+        UnixDest::Abstract(_) => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                "abstract unix sockets are only supported on linux",
+            ))
+        }
+    }
+    Ok(socket)
+}
+
+#[cfg(not(unix))]
+fn connect(_unix_dest: &UnixDest) -> io::Result<UnixDatagram> {
+    Err(io::Error::new(
+        ErrorKind::Unsupported,
+        "unix sockets are only supported on unix",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::os::unix::net::UnixDatagram;
+    use std::{collections::HashMap, net::SocketAddr, path::PathBuf, str::FromStr, sync::Arc};
+
+    use arc_swap::ArcSwap;
+
+    use crate::{
+        forwarder::{ShredMetrics, ShredSink},
+        unix::{parse_unix_dest, unix_dest_addr, UnixDest, UnixSink},
+    };
+
+    #[test]
+    fn test_parse_unix_dest() {
+        assert_eq!(
+            parse_unix_dest("unix:///run/consumer.sock?rate=100pps"),
+            Some(UnixDest::Path(PathBuf::from("/run/consumer.sock")))
+        );
+        assert_eq!(
+            parse_unix_dest("unix://@consumer"),
+            Some(UnixDest::Abstract("consumer".to_string()))
+        );
+        assert_eq!(parse_unix_dest("unix://"), None);
+        assert_eq!(parse_unix_dest("unix://@"), None);
+        assert_eq!(parse_unix_dest("127.0.0.1:8001"), None);
+
+        let unix_dest = UnixDest::Path(PathBuf::from("/run/consumer.sock"));
+        assert_eq!(unix_dest.to_string(), "unix:///run/consumer.sock");
+        // pinned, so metrics and destinations keyed by it carry over restarts and upgrades
+        assert_eq!(
+            unix_dest_addr(&unix_dest),
+            SocketAddr::from_str("[100::d55d:6efa:9951:c699]:0").unwrap()
+        );
+        assert_ne!(
+            unix_dest_addr(&unix_dest),
+            unix_dest_addr(&UnixDest::Abstract("/run/consumer.sock".to_string()))
+        );
+        assert_eq!(
+            unix_dest_addr(&unix_dest)
+                .ip()
+                .to_string()
+                .split(':')
+                .next(),
+            Some("100")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_sink_reconnects() {
+        let path = std::env::temp_dir().join(format!("test_unix_sink_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let unix_dest = UnixDest::Path(path.clone());
+        let dest = unix_dest_addr(&unix_dest);
+        let metrics = Arc::new(ShredMetrics::new());
+        let sink = UnixSink::new(
+            Arc::new(ArcSwap::from_pointee(HashMap::from([(dest, unix_dest)]))),
+            metrics.clone(),
+        );
+
+        // nothing listening yet
+        sink.send(dest, &[b"lost"]);
+        assert_eq!(*metrics.dest_forwarded.get(&dest).unwrap(), (0, 1));
+
+        let consumer = UnixDatagram::bind(&path).unwrap();
+        // waits out the reconnect backoff
+        std::thread::sleep(super::MIN_RECONNECT_BACKOFF);
+        sink.send(dest, &[b"one", b"two"]);
+        let mut buf = [0u8; 16];
+        for expected in [&b"one"[..], b"two"] {
+            let len = consumer.recv(&mut buf).unwrap();
+            assert_eq!(&buf[..len], expected);
+        }
+
+        // consumer restarts, recreating its socket
+        drop(consumer);
+        std::fs::remove_file(&path).unwrap();
+        let consumer = UnixDatagram::bind(&path).unwrap();
+        sink.send(dest, &[b"lost"]);
+        sink.send(dest, &[b"three"]);
+        let len = consumer.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"three");
+        assert_eq!(*metrics.dest_forwarded.get(&dest).unwrap(), (3, 2));
+
+        sink.retain_destinations(&HashMap::new());
+        assert!(sink.connections.is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,274 @@
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::Receiver;
+use log::{info, warn};
+use solana_client::{rpc_client::RpcClient, rpc_response::RpcContactInfo};
+use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey};
+
+use crate::{
+    forwarder::{DestinationSources, ShredMetrics},
+    metrics_backend::{datapoint_info, datapoint_warn},
+    slot_latency::redact_url,
+    supervisor::Supervisor,
+};
+
+/// `getClusterNodes` returns every node in gossip, so allow for a large response
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+const RPC_URL_PLACEHOLDER: &str = "<dest-validator-rpc-url>";
+
+/// Where and how often to resolve `dest-validator-identities`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ValidatorResolverConfig {
+    pub identities: Vec<Pubkey>,
+    pub rpc_url: String,
+    pub refresh_interval: Duration,
+    /// Identities unresolved for longer are counted in `validator_identities_unresolved`
+    pub unresolved_alert: Duration,
+}
+
+/// Tracks the TVU address of each identity, keeping the last known address of identities missing from a refresh
+struct ValidatorResolver {
+    identities: Vec<Pubkey>,
+    /// Last known TVU address, and when it was last seen
+    resolved: HashMap<Pubkey, (SocketAddr, Instant)>,
+    started_at: Instant,
+}
+
+impl ValidatorResolver {
+    fn new(identities: Vec<Pubkey>, now: Instant) -> Self {
+        Self {
+            identities,
+            resolved: HashMap::new(),
+            started_at: now,
+        }
+    }
+
+    /// Updates addresses from `getClusterNodes`, returning (identity, old address, new address) for each that moved or appeared
+    fn update(
+        &mut self,
+        cluster_nodes: &[RpcContactInfo],
+        now: Instant,
+    ) -> Vec<(Pubkey, Option<SocketAddr>, SocketAddr)> {
+        let tvus = cluster_nodes
+            .iter()
+            .filter_map(|node| Some((Pubkey::from_str(&node.pubkey).ok()?, node.tvu?)))
+            .collect::<HashMap<_, _>>();
+        let mut changed = vec![];
+        for identity in &self.identities {
+            let Some(tvu) = tvus.get(identity) else {
+                continue;
+            };
+            let old = self
+                .resolved
+                .insert(*identity, (*tvu, now))
+                .map(|(old, _)| old);
+            if old != Some(*tvu) {
+                changed.push((*identity, old, *tvu));
+            }
+        }
+        changed
+    }
+
+    /// Last known addresses, in the order identities were given
+    fn addresses(&self) -> Vec<SocketAddr> {
+        self.identities
+            .iter()
+            .filter_map(|identity| self.resolved.get(identity).map(|(addr, _)| *addr))
+            .collect()
+    }
+
+    /// Identities not seen for `unresolved_alert`, counting from startup for those never resolved
+    fn unresolved(&self, unresolved_alert: Duration, now: Instant) -> Vec<Pubkey> {
+        self.identities
+            .iter()
+            .filter(|identity| {
+                let last_seen = self
+                    .resolved
+                    .get(identity)
+                    .map_or(self.started_at, |(_, last_seen)| *last_seen);
+                now.duration_since(last_seen) > unresolved_alert
+            })
+            .copied()
+            .collect()
+    }
+}
+
+/// Periodically resolves validator identities to their TVU address via `getClusterNodes`, forwarding to them
+/// alongside the other destinations. A failed refresh or an identity missing from gossip keeps its last known address
+pub fn start_validator_resolver_thread(
+    config: ValidatorResolverConfig,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    metrics: Arc<ShredMetrics>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    // the url may carry an API key, so it's never logged
+    info!(
+        "Resolving {} validator identities to destinations every {:?}.",
+        config.identities.len(),
+        config.refresh_interval
+    );
+    supervisor.spawn_restartable("ssPxyValidators", move || {
+        let rpc_client = RpcClient::new_with_timeout_and_commitment(
+            config.rpc_url.clone(),
+            RPC_TIMEOUT,
+            CommitmentConfig::processed(),
+        );
+        let mut resolver = ValidatorResolver::new(config.identities.clone(), Instant::now());
+        // identities already counted as unresolved, so each is only logged once per outage
+        let mut alerted = HashSet::new();
+        let refresh_tick = crossbeam_channel::tick(config.refresh_interval);
+        // resolve right away instead of waiting for the first tick
+        let mut refresh_now = true;
+        while !exit.load(Ordering::Relaxed) {
+            if !refresh_now {
+                crossbeam_channel::select! {
+                    recv(refresh_tick) -> _ => {}
+                    recv(shutdown_receiver) -> _ => break,
+                }
+            }
+            refresh_now = false;
+
+            match rpc_client.get_cluster_nodes() {
+                Ok(cluster_nodes) => {
+                    let changed = resolver.update(&cluster_nodes, Instant::now());
+                    for (identity, old, new) in &changed {
+                        match old {
+                            Some(old) => info!(
+                                "Validator {identity} moved from {old} to {new}, updating destination."
+                            ),
+                            None => info!("Resolved validator {identity} to {new}."),
+                        }
+                    }
+                    if !changed.is_empty() {
+                        let mut dest_sources = dest_sources.lock().unwrap();
+                        dest_sources.validator_dest_sockets = resolver.addresses();
+                        dest_sources.store_union(&unioned_dest_sockets);
+                    }
+                }
+                Err(e) => {
+                    let e = redact_url(e, &config.rpc_url, RPC_URL_PLACEHOLDER);
+                    warn!("Failed to get cluster nodes, keeping last known validator addresses. Error: {e}");
+                    datapoint_warn!(
+                        "shredstream_proxy-validator_resolve_error",
+                        ("errors", 1, i64),
+                        ("error_str", e, String),
+                    );
+                }
+            }
+
+            let unresolved = resolver.unresolved(config.unresolved_alert, Instant::now());
+            for identity in unresolved.iter().filter(|identity| !alerted.contains(*identity)) {
+                warn!(
+                    "Validator {identity} unresolved for over {:?}, forwarding to its last known address if any.",
+                    config.unresolved_alert
+                );
+            }
+            alerted = unresolved.iter().copied().collect();
+            metrics
+                .validator_identities_unresolved
+                .store(unresolved.len() as u64, Ordering::Relaxed);
+            datapoint_info!(
+                "shredstream_proxy-validator_resolution",
+                ("identities", config.identities.len(), i64),
+                ("resolved", resolver.addresses().len(), i64),
+                ("unresolved", unresolved.len(), i64),
+            );
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        str::FromStr,
+        time::{Duration, Instant},
+    };
+
+    use solana_client::rpc_response::RpcContactInfo;
+    use solana_sdk::pubkey::Pubkey;
+
+    use crate::validators::ValidatorResolver;
+
+    fn contact_info(identity: &Pubkey, tvu: Option<&str>) -> RpcContactInfo {
+        RpcContactInfo {
+            pubkey: identity.to_string(),
+            gossip: None,
+            tvu: tvu.map(|tvu| SocketAddr::from_str(tvu).unwrap()),
+            tpu: None,
+            tpu_quic: None,
+            tpu_forwards: None,
+            tpu_forwards_quic: None,
+            tpu_vote: None,
+            serve_repair: None,
+            rpc: None,
+            pubsub: None,
+            version: None,
+            feature_set: None,
+            shred_version: None,
+        }
+    }
+
+    #[test]
+    fn test_validator_resolver() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let alert = Duration::from_secs(600);
+        let [first, second, other] = [(); 3].map(|_| Pubkey::new_unique());
+        let mut resolver = ValidatorResolver::new(vec![first, second], start);
+        let addr = |addr| SocketAddr::from_str(addr).unwrap();
+
+        // other nodes are ignored, identities without a TVU stay unresolved
+        let changed = resolver.update(
+            &[
+                contact_info(&first, Some("10.0.0.1:8001")),
+                contact_info(&second, None),
+                contact_info(&other, Some("10.0.0.3:8001")),
+            ],
+            secs(1),
+        );
+        assert_eq!(changed, vec![(first, None, addr("10.0.0.1:8001"))]);
+        assert_eq!(resolver.addresses(), vec![addr("10.0.0.1:8001")]);
+        assert!(resolver.unresolved(alert, secs(600)).is_empty());
+        assert_eq!(resolver.unresolved(alert, secs(601)), vec![second]);
+
+        // moving hosts updates the address
+        let changed = resolver.update(
+            &[
+                contact_info(&first, Some("10.0.0.2:8001")),
+                contact_info(&second, Some("10.0.0.4:8001")),
+            ],
+            secs(700),
+        );
+        assert_eq!(
+            changed,
+            vec![
+                (first, Some(addr("10.0.0.1:8001")), addr("10.0.0.2:8001")),
+                (second, None, addr("10.0.0.4:8001")),
+            ]
+        );
+
+        // missing identities keep their last known address until alerting
+        assert!(resolver.update(&[], secs(800)).is_empty());
+        assert_eq!(
+            resolver.addresses(),
+            vec![addr("10.0.0.2:8001"), addr("10.0.0.4:8001")]
+        );
+        assert!(resolver.unresolved(alert, secs(1_300)).is_empty());
+        assert_eq!(resolver.unresolved(alert, secs(1_301)), vec![first, second]);
+    }
+}
@@ -0,0 +1,256 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use crossbeam_channel::{Receiver, Sender};
+use log::{info, warn};
+
+use crate::{
+    forwarder::{DestinationSources, ShredMetrics},
+    get_public_ip,
+    metrics_backend::datapoint_warn,
+    supervisor::Supervisor,
+    AddressFamily,
+};
+
+const WATCHDOG_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Detects a stream that stalled while heartbeats keep succeeding, such as when the block engine lost our registration.
+/// Stays disarmed until the first successful heartbeat, since nothing arrives before registering
+struct StallDetector {
+    stall_timeout: Duration,
+    /// Counts when packets last arrived, or when the detector last fired
+    last_received: u64,
+    last_successful_heartbeats: u64,
+    since: Instant,
+}
+
+impl StallDetector {
+    fn new(stall_timeout: Duration, now: Instant) -> Self {
+        Self {
+            stall_timeout,
+            last_received: 0,
+            last_successful_heartbeats: 0,
+            since: now,
+        }
+    }
+
+    /// Disarms until heartbeats succeed again, such as while they're paused and nothing is expected
+    fn disarm(&mut self) {
+        self.last_successful_heartbeats = 0;
+    }
+
+    /// Returns true if no packets arrived for `stall_timeout` while heartbeats succeeded.
+    /// Waits another `stall_timeout` before firing again
+    fn check(&mut self, received: u64, successful_heartbeats: u64, now: Instant) -> bool {
+        // the window starts once packets arrive or the first heartbeat succeeds
+        if received != self.last_received || self.last_successful_heartbeats == 0 {
+            self.last_received = received;
+            self.last_successful_heartbeats = successful_heartbeats;
+            self.since = now;
+            return false;
+        }
+        if now.duration_since(self.since) < self.stall_timeout {
+            return false;
+        }
+        // failing heartbeats are handled by the heartbeat loop's reconnects and failover
+        let heartbeats_succeeded = successful_heartbeats > self.last_successful_heartbeats;
+        self.last_successful_heartbeats = successful_heartbeats;
+        self.since = now;
+        heartbeats_succeeded
+    }
+}
+
+/// Starts a thread that signals `reregister_sender` when no packets arrive for `stall_timeout` while heartbeats succeed.
+/// The heartbeat loop then reconnects and authenticates from scratch
+pub fn start_stall_watchdog_thread(
+    stall_timeout: Duration,
+    metrics: Arc<ShredMetrics>,
+    reregister_sender: Sender<()>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    supervisor.spawn_restartable("ssPxyWatchdog", move || {
+        let mut stall_detector = StallDetector::new(stall_timeout, Instant::now());
+        let check_tick = crossbeam_channel::tick(WATCHDOG_CHECK_INTERVAL);
+        while !exit.load(Ordering::Relaxed) {
+            crossbeam_channel::select! {
+                recv(check_tick) -> _ => {
+                    if metrics.heartbeat_paused.load(Ordering::Relaxed) {
+                        stall_detector.disarm();
+                        continue;
+                    }
+                    let received = metrics.agg_received_cumulative.load(Ordering::Relaxed);
+                    let successful_heartbeats = metrics.successful_heartbeat_cumulative.load(Ordering::Relaxed);
+                    if !stall_detector.check(received, successful_heartbeats, Instant::now()) {
+                        continue;
+                    }
+                    warn!(
+                        event = "stream_stalled",
+                        stall_timeout_secs = stall_timeout.as_secs();
+                        "No shreds received for {stall_timeout:?} while heartbeats succeeded, re-registering with the block engine."
+                    );
+                    datapoint_warn!(
+                        "shredstream_proxy-stream_stalled",
+                        ("stall_timeout_secs", stall_timeout.as_secs(), i64),
+                        ("reregistrations", 1, i64),
+                    );
+                    metrics
+                        .stall_reregistrations_cumulative
+                        .fetch_add(1, Ordering::Relaxed);
+                    // a pending signal already covers this stall
+                    let _ = reregister_sender.try_send(());
+                }
+                recv(shutdown_receiver) -> _ => {
+                    break;
+                }
+            }
+        }
+        info!("Exiting stall watchdog thread.");
+    })
+}
+
+/// Points `advertise_addr` at `public_ip`, keeping its port. Returns the previous IP if it changed
+fn update_advertised_ip(advertise_addr: &ArcSwap<SocketAddr>, public_ip: IpAddr) -> Option<IpAddr> {
+    let previous = **advertise_addr.load();
+    if previous.ip() == public_ip {
+        return None;
+    }
+    advertise_addr.store(Arc::new(SocketAddr::new(public_ip, previous.port())));
+    Some(previous.ip())
+}
+
+/// Starts a thread that re-fetches the public IP every `recheck_interval`. When it changed since registering,
+/// updates `advertise_addr` and signals `reregister_sender` so the heartbeat loop registers the new address
+#[allow(clippy::too_many_arguments)]
+pub fn start_public_ip_recheck_thread(
+    recheck_interval: Duration,
+    family: AddressFamily,
+    advertise_addr: Arc<ArcSwap<SocketAddr>>,
+    dest_sources: Arc<Mutex<DestinationSources>>,
+    unioned_dest_sockets: Arc<ArcSwap<Vec<SocketAddr>>>,
+    metrics: Arc<ShredMetrics>,
+    reregister_sender: Sender<()>,
+    supervisor: &Supervisor,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> JoinHandle<()> {
+    supervisor.spawn_restartable("ssPxyPublicIp", move || {
+        let recheck_tick = crossbeam_channel::tick(recheck_interval);
+        while !exit.load(Ordering::Relaxed) {
+            crossbeam_channel::select! {
+                recv(recheck_tick) -> _ => {
+                    let public_ip = match get_public_ip(family) {
+                        Ok(public_ip) => public_ip,
+                        Err(e) => {
+                            warn!("Failed to recheck public ip, keeping {}. Error: {e}", advertise_addr.load().ip());
+                            continue;
+                        }
+                    };
+                    let Some(previous_ip) = update_advertised_ip(&advertise_addr, public_ip) else {
+                        continue;
+                    };
+                    warn!(
+                        event = "public_ip_changed",
+                        previous_ip:% = previous_ip,
+                        public_ip:% = public_ip;
+                        "Public ip changed from {previous_ip} to {public_ip}, re-registering with the block engine."
+                    );
+                    datapoint_warn!(
+                        "shredstream_proxy-public_ip_changed",
+                        ("previous_ip", previous_ip.to_string(), String),
+                        ("public_ip", public_ip.to_string(), String),
+                        ("reregistrations", 1, i64),
+                    );
+                    metrics
+                        .public_ip_changes_cumulative
+                        .fetch_add(1, Ordering::Relaxed);
+                    {
+                        let mut dest_sources = dest_sources.lock().unwrap();
+                        if let Some(dest_blocklist) = &mut dest_sources.dest_blocklist {
+                            // the previous IP may now belong to someone else, so it's no longer blocked
+                            dest_blocklist.remove_self_ip(previous_ip);
+                            dest_blocklist.add_self_ip(public_ip);
+                        }
+                        dest_sources.store_union(&unioned_dest_sockets);
+                    }
+                    // a pending signal re-registers with the address already updated
+                    let _ = reregister_sender.try_send(());
+                }
+                recv(shutdown_receiver) -> _ => {
+                    break;
+                }
+            }
+        }
+        info!("Exiting public ip recheck thread.");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, SocketAddr},
+        str::FromStr,
+        time::{Duration, Instant},
+    };
+
+    use arc_swap::ArcSwap;
+
+    use crate::watchdog::{update_advertised_ip, StallDetector};
+
+    #[test]
+    fn test_update_advertised_ip() {
+        let advertise_addr = ArcSwap::from_pointee(SocketAddr::from_str("1.1.1.1:20000").unwrap());
+        assert_eq!(
+            update_advertised_ip(&advertise_addr, IpAddr::from_str("1.1.1.1").unwrap()),
+            None
+        );
+        assert_eq!(
+            update_advertised_ip(&advertise_addr, IpAddr::from_str("2.2.2.2").unwrap()),
+            Some(IpAddr::from_str("1.1.1.1").unwrap())
+        );
+        // keeps the bound port
+        assert_eq!(
+            **advertise_addr.load(),
+            SocketAddr::from_str("2.2.2.2:20000").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_stall_detector() {
+        let start = Instant::now();
+        let secs = |secs| start + Duration::from_secs(secs);
+        let mut detector = StallDetector::new(Duration::from_secs(60), start);
+
+        // disarmed until the first successful heartbeat
+        assert!(!detector.check(0, 0, secs(120)));
+        assert!(!detector.check(0, 1, secs(150)));
+        assert!(!detector.check(0, 2, secs(209)));
+        assert!(detector.check(0, 3, secs(210)));
+        // waits another timeout before firing again
+        assert!(!detector.check(0, 4, secs(269)));
+        assert!(detector.check(0, 5, secs(270)));
+
+        // packets arriving restart the window
+        assert!(!detector.check(10, 6, secs(300)));
+        assert!(!detector.check(10, 7, secs(359)));
+        assert!(detector.check(10, 8, secs(360)));
+
+        // not while heartbeats are failing
+        assert!(!detector.check(10, 8, secs(420)));
+
+        // rearms once heartbeats succeed after a pause, rather than counting the pause as a stall
+        detector.disarm();
+        assert!(!detector.check(10, 8, secs(600)));
+        assert!(!detector.check(10, 9, secs(659)));
+        assert!(detector.check(10, 10, secs(660)));
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::{Builder, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info, warn};
+use serde::Serialize;
+use solana_sdk::clock::Slot;
+use tokio::{net::TcpStream, sync::mpsc};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::StatusCode,
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use crate::{
+    metrics_backend::datapoint_info,
+    shred::{self, ShredType},
+};
+
+/// Shred batches queued from forwarders. Batches are dropped when full, forwarding is never blocked
+const SHRED_CHANNEL_CAPACITY: usize = 1_024;
+/// Messages queued per client. Clients are disconnected when full
+const CLIENT_QUEUE_CAPACITY: usize = 1_024;
+/// Clients not reading a message within this long are disconnected
+const CLIENT_SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How each shred is sent to a client, picked with the `format` query parameter
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum WsFormat {
+    /// Raw shred payload as a binary message
+    #[default]
+    Binary,
+    /// [ShredSummary] as a text message
+    Json,
+}
+
+impl WsFormat {
+    /// Parses the request's query string, eg. `format=json`
+    fn from_query(query: Option<&str>) -> Result<Self, String> {
+        let format = query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|param| param.split_once('='))
+            .find_map(|(key, value)| (key == "format").then_some(value));
+        match format {
+            None | Some("binary") => Ok(Self::Binary),
+            Some("json") => Ok(Self::Json),
+            Some(format) => Err(format!("unknown format {format}, expected binary or json")),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct ShredSummary {
+    slot: Slot,
+    index: u32,
+    #[serde(rename = "type")]
+    shred_type: &'static str,
+    size: usize,
+    /// When the proxy received the shred, in microseconds since the unix epoch
+    recv_timestamp_us: u64,
+}
+
+impl ShredSummary {
+    /// Returns None if `shred` isn't a shred
+    fn new(shred: &[u8], received_time: SystemTime) -> Option<Self> {
+        let shred_type = match shred::get_shred_type(shred)? {
+            ShredType::Data => "data",
+            ShredType::Code => "code",
+        };
+        Some(Self {
+            slot: shred::get_slot(shred)?,
+            index: shred::get_index(shred)?,
+            shred_type,
+            size: shred.len(),
+            recv_timestamp_us: received_time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_micros() as u64,
+        })
+    }
+}
+
+/// Copies deduped shreds from forwarders to the WebSocket dispatch thread while any client is connected
+pub struct WsTap {
+    shred_sender: Sender<(SystemTime, Vec<Vec<u8>>)>,
+    num_clients: Arc<AtomicUsize>,
+    batches_dropped: Arc<AtomicU64>,
+}
+
+impl WsTap {
+    pub fn send(&self, packets: &[&[u8]], received_time: SystemTime) {
+        if packets.is_empty() || self.num_clients.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        // drop instead of blocking forwarding when dispatching falls behind
+        if let Err(TrySendError::Full(_)) = self.shred_sender.try_send((
+            received_time,
+            packets.iter().map(|packet| packet.to_vec()).collect(),
+        )) {
+            self.batches_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct WsClient {
+    remote_addr: SocketAddr,
+    format: WsFormat,
+    message_sender: mpsc::Sender<Message>,
+}
+
+/// Counts a client from accepting its connection until it's closed
+struct ClientGuard(Arc<AtomicUsize>);
+
+impl ClientGuard {
+    /// Returns None if `max_clients` are already connected
+    fn try_new(num_clients: &Arc<AtomicUsize>, max_clients: usize) -> Option<Self> {
+        num_clients
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                (n < max_clients).then_some(n + 1)
+            })
+            .ok()?;
+        Some(Self(num_clients.clone()))
+    }
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Queues `shreds` for each client, disconnecting clients whose queue is full.
+/// Returns the number of messages queued and clients disconnected for falling behind
+fn dispatch(
+    clients: &mut Vec<WsClient>,
+    shreds: &[Vec<u8>],
+    received_time: SystemTime,
+) -> (u64, u64) {
+    let mut json = None;
+    let mut num_queued = 0u64;
+    let mut num_slow = 0u64;
+    clients.retain(|client| {
+        let messages: Vec<Message> = match client.format {
+            WsFormat::Binary => shreds.iter().cloned().map(Message::Binary).collect(),
+            WsFormat::Json => json
+                .get_or_insert_with(|| {
+                    shreds
+                        .iter()
+                        .filter_map(|shred| ShredSummary::new(shred, received_time))
+                        .map(|summary| serde_json::to_string(&summary).unwrap())
+                        .collect::<Vec<_>>()
+                })
+                .iter()
+                .cloned()
+                .map(Message::Text)
+                .collect(),
+        };
+        for message in messages {
+            match client.message_sender.try_send(message) {
+                Ok(()) => num_queued += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!(
+                        "Disconnecting WebSocket client {} for falling behind.",
+                        client.remote_addr
+                    );
+                    num_slow += 1;
+                    return false;
+                }
+                // client already disconnected
+                Err(mpsc::error::TrySendError::Closed(_)) => return false,
+            }
+        }
+        true
+    });
+    (num_queued, num_slow)
+}
+
+/// Serves a WebSocket stream of deduped shreds on `bind_addr`, one message per shred. Clients get raw shreds as binary
+/// messages, or with `?format=json` a JSON summary of each. Shreds are only copied while a client is connected.
+/// Clients over `max_clients` are rejected, and clients that can't keep up are disconnected.
+pub fn start_ws_threads(
+    bind_addr: SocketAddr,
+    max_clients: usize,
+    shutdown_receiver: Receiver<()>,
+    exit: Arc<AtomicBool>,
+) -> io::Result<(WsTap, Vec<JoinHandle<()>>)> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .thread_name("ssPxyWsRt")
+        .enable_all()
+        .build()?;
+    let listener = runtime.block_on(tokio::net::TcpListener::bind(bind_addr))?;
+    info!("Serving WebSocket shreds on {bind_addr}");
+
+    let (shred_sender, shred_receiver) =
+        crossbeam_channel::bounded::<(SystemTime, Vec<Vec<u8>>)>(SHRED_CHANNEL_CAPACITY);
+    let num_clients = Arc::new(AtomicUsize::new(0));
+    let batches_dropped = Arc::new(AtomicU64::new(0));
+    let clients = Arc::new(Mutex::new(Vec::<WsClient>::new()));
+
+    let server_exit = exit.clone();
+    let server_clients = clients.clone();
+    let server_num_clients = num_clients.clone();
+    let server_hdl = Builder::new().name("ssPxyWs".to_string()).spawn(move || {
+        runtime.block_on(async move {
+            while !server_exit.load(Ordering::Relaxed) {
+                // periodically check for exit
+                let Ok(accepted) =
+                    tokio::time::timeout(Duration::from_millis(500), listener.accept()).await
+                else {
+                    continue;
+                };
+                match accepted {
+                    Ok((stream, remote_addr)) => {
+                        tokio::spawn(serve_client(
+                            stream,
+                            remote_addr,
+                            ClientGuard::try_new(&server_num_clients, max_clients),
+                            server_clients.clone(),
+                        ));
+                    }
+                    Err(e) => warn!("Failed to accept WebSocket connection. Error: {e}"),
+                }
+            }
+        });
+        info!("Exiting WebSocket thread.");
+    })?;
+
+    let dispatch_num_clients = num_clients.clone();
+    let dispatch_batches_dropped = batches_dropped.clone();
+    let dispatch_hdl = Builder::new()
+        .name("ssPxyWsDispatch".to_string())
+        .spawn(move || {
+            let metrics_tick = crossbeam_channel::tick(Duration::from_secs(30));
+            let mut num_messages_sent = 0u64;
+            let mut num_slow_clients = 0u64;
+            while !exit.load(Ordering::Relaxed) {
+                crossbeam_channel::select! {
+                    recv(shred_receiver) -> maybe_shreds => {
+                        let Ok((received_time, shreds)) = maybe_shreds else {
+                            break;
+                        };
+                        let (num_queued, num_slow) =
+                            dispatch(&mut clients.lock().unwrap(), &shreds, received_time);
+                        num_messages_sent += num_queued;
+                        num_slow_clients += num_slow;
+                    }
+                    recv(metrics_tick) -> _ => {
+                        datapoint_info!("shredstream_proxy-ws_stats",
+                            ("clients", dispatch_num_clients.load(Ordering::Relaxed), i64),
+                            ("messages_sent", std::mem::take(&mut num_messages_sent), i64),
+                            ("slow_clients_disconnected", std::mem::take(&mut num_slow_clients), i64),
+                            ("batches_dropped", dispatch_batches_dropped.swap(0, Ordering::Relaxed), i64),
+                        );
+                    }
+                    recv(shutdown_receiver) -> _ => {
+                        break;
+                    }
+                }
+            }
+            // closes every client's connection
+            clients.lock().unwrap().clear();
+            info!("Exiting WebSocket dispatch thread.");
+        })?;
+
+    Ok((
+        WsTap {
+            shred_sender,
+            num_clients,
+            batches_dropped,
+        },
+        vec![server_hdl, dispatch_hdl],
+    ))
+}
+
+fn error_response(status: StatusCode, reason: String) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason));
+    *response.status_mut() = status;
+    response
+}
+
+/// Upgrades `stream` to a WebSocket and forwards messages queued by the dispatch thread until either side closes.
+/// `guard` is None when too many clients are connected, rejecting the upgrade
+async fn serve_client(
+    stream: TcpStream,
+    remote_addr: SocketAddr,
+    guard: Option<ClientGuard>,
+    clients: Arc<Mutex<Vec<WsClient>>>,
+) {
+    let mut format = WsFormat::default();
+    let callback = |request: &Request, response: Response| {
+        if guard.is_none() {
+            return Err(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "too many clients".to_string(),
+            ));
+        }
+        format = WsFormat::from_query(request.uri().query())
+            .map_err(|e| error_response(StatusCode::BAD_REQUEST, e))?;
+        Ok(response)
+    };
+    let ws_stream = match tokio_tungstenite::accept_hdr_async(stream, callback).await {
+        Ok(ws_stream) => ws_stream,
+        Err(e) => {
+            debug!("Rejected WebSocket connection from {remote_addr}. Error: {e}");
+            return;
+        }
+    };
+    info!("WebSocket client connected from {remote_addr} as {format:?}.");
+
+    let (message_sender, mut message_receiver) = mpsc::channel(CLIENT_QUEUE_CAPACITY);
+    clients.lock().unwrap().push(WsClient {
+        remote_addr,
+        format,
+        message_sender,
+    });
+    let (mut sink, mut incoming) = ws_stream.split();
+    loop {
+        tokio::select! {
+            maybe_message = message_receiver.recv() => {
+                let Some(message) = maybe_message else {
+                    // dropped by the dispatch thread for falling behind, or exiting
+                    let _ = sink
+                        .send(Message::Close(Some(CloseFrame {
+                            code: CloseCode::Policy,
+                            reason: "falling behind or shutting down".into(),
+                        })))
+                        .await;
+                    break;
+                };
+                match tokio::time::timeout(CLIENT_SEND_TIMEOUT, sink.send(message)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => {
+                        debug!("Failed to send to WebSocket client {remote_addr}. Error: {e}");
+                        break;
+                    }
+                    Err(_) => {
+                        warn!("Disconnecting WebSocket client {remote_addr} for not reading.");
+                        break;
+                    }
+                }
+            }
+            maybe_incoming = incoming.next() => {
+                match maybe_incoming {
+                    // pings are answered when the next message is sent, anything else from clients is ignored
+                    Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                }
+            }
+        }
+    }
+    info!("WebSocket client {remote_addr} disconnected.");
+    drop(guard);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::{atomic::AtomicUsize, Arc},
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use tokio::sync::mpsc;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use crate::{
+        shred::tests::new_data_shred,
+        ws::{dispatch, ClientGuard, ShredSummary, WsClient, WsFormat},
+    };
+
+    #[test]
+    fn test_ws_format_from_query() {
+        assert_eq!(WsFormat::from_query(None), Ok(WsFormat::Binary));
+        assert_eq!(WsFormat::from_query(Some("")), Ok(WsFormat::Binary));
+        assert_eq!(
+            WsFormat::from_query(Some("format=binary")),
+            Ok(WsFormat::Binary)
+        );
+        assert_eq!(
+            WsFormat::from_query(Some("token=abc&format=json")),
+            Ok(WsFormat::Json)
+        );
+        assert!(WsFormat::from_query(Some("format=xml")).is_err());
+    }
+
+    #[test]
+    fn test_shred_summary() {
+        let shred = new_data_shred(42, 7, 0, false, &[1, 2, 3]);
+        let received_time = UNIX_EPOCH + Duration::from_micros(1_234);
+        let summary = ShredSummary::new(&shred, received_time).unwrap();
+        assert_eq!(
+            serde_json::to_string(&summary).unwrap(),
+            format!(
+                r#"{{"slot":42,"index":7,"type":"data","size":{},"recv_timestamp_us":1234}}"#,
+                shred.len()
+            )
+        );
+        assert_eq!(ShredSummary::new(&[0u8; 10], received_time), None);
+    }
+
+    #[test]
+    fn test_dispatch_disconnects_slow_clients() {
+        let new_client = |format, capacity| {
+            let (message_sender, message_receiver) = mpsc::channel(capacity);
+            let client = WsClient {
+                remote_addr: SocketAddr::from(([127, 0, 0, 1], 0)),
+                format,
+                message_sender,
+            };
+            (client, message_receiver)
+        };
+        let (binary_client, mut binary_receiver) = new_client(WsFormat::Binary, 4);
+        let (json_client, mut json_receiver) = new_client(WsFormat::Json, 4);
+        let (slow_client, _slow_receiver) = new_client(WsFormat::Binary, 1);
+        let (closed_client, _) = new_client(WsFormat::Binary, 4);
+        let mut clients = vec![binary_client, json_client, slow_client, closed_client];
+
+        let shreds = vec![new_data_shred(42, 0, 0, false, &[1]), vec![0u8; 10]];
+        let (num_queued, num_slow) = dispatch(&mut clients, &shreds, UNIX_EPOCH);
+        // packets that aren't shreds are only sent as binary
+        assert_eq!((num_queued, num_slow), (4, 1));
+        assert_eq!(clients.len(), 2);
+        assert_eq!(
+            binary_receiver.try_recv().unwrap(),
+            Message::Binary(shreds[0].clone())
+        );
+        assert_eq!(
+            binary_receiver.try_recv().unwrap(),
+            Message::Binary(shreds[1].clone())
+        );
+        assert!(matches!(
+            json_receiver.try_recv().unwrap(),
+            Message::Text(text) if text.contains(r#""slot":42"#)
+        ));
+        assert!(json_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_client_guard() {
+        let num_clients = Arc::new(AtomicUsize::new(0));
+        let first = ClientGuard::try_new(&num_clients, 2).unwrap();
+        let second = ClientGuard::try_new(&num_clients, 2).unwrap();
+        assert!(ClientGuard::try_new(&num_clients, 2).is_none());
+        drop(first);
+        assert!(ClientGuard::try_new(&num_clients, 2).is_some());
+        drop(second);
+        assert_eq!(num_clients.load(std::sync::atomic::Ordering::Relaxed), 0);
+    }
+}